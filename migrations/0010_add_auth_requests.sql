@@ -0,0 +1,21 @@
+-- Login with device: pending/answered auth requests.
+CREATE TABLE IF NOT EXISTS auth_requests (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    request_device_identifier TEXT NOT NULL,
+    device_type INTEGER NOT NULL,
+    request_ip TEXT NOT NULL,
+    response_device_id TEXT,
+    access_code TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    enc_key TEXT, -- User key encrypted to public_key (set on approval)
+    master_password_hash TEXT,
+    approved INTEGER, -- NULL = pending, 1 = approved, 0 = denied
+    creation_date TEXT NOT NULL,
+    response_date TEXT,
+    authentication_date TEXT, -- Set once the request has been used to log in
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_auth_requests_user_id ON auth_requests(user_id);
+CREATE INDEX IF NOT EXISTS idx_auth_requests_creation_date ON auth_requests(creation_date);
@@ -0,0 +1,16 @@
+-- Devices that have logged in, keyed by the client-generated device identifier.
+-- The encrypted_* columns hold trusted device encryption (TDE) keys.
+CREATE TABLE IF NOT EXISTS devices (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    identifier TEXT NOT NULL,
+    name TEXT NOT NULL,
+    atype INTEGER NOT NULL,
+    encrypted_user_key TEXT, -- User key encrypted with the device public key
+    encrypted_public_key TEXT, -- Device public key encrypted with the user key
+    encrypted_private_key TEXT, -- Device private key encrypted with the device key
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(user_id, identifier)
+);
@@ -0,0 +1,4 @@
+-- Per-device sessions: the id of the currently valid refresh token and when it was last used.
+-- Revoking a device's session clears refresh_token_id so its refresh token stops working.
+ALTER TABLE devices ADD COLUMN refresh_token_id TEXT;
+ALTER TABLE devices ADD COLUMN last_active_at TEXT;
@@ -0,0 +1,3 @@
+-- Push relay registration for mobile devices.
+ALTER TABLE devices ADD COLUMN push_uuid TEXT; -- Id the device is registered under at the push relay
+ALTER TABLE devices ADD COLUMN push_token TEXT; -- APNs/FCM token reported by the client
@@ -0,0 +1,4 @@
+-- Web Push subscription of browser clients (RFC 8030).
+ALTER TABLE devices ADD COLUMN web_push_endpoint TEXT; -- Push service URL of the subscription
+ALTER TABLE devices ADD COLUMN web_push_p256dh TEXT; -- Client ECDH public key (base64url)
+ALTER TABLE devices ADD COLUMN web_push_auth TEXT; -- Client auth secret (base64url)
@@ -0,0 +1,24 @@
+-- Bitwarden Send: text and file shares readable by anyone holding the link.
+CREATE TABLE IF NOT EXISTS sends (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    atype INTEGER NOT NULL, -- 0 = text, 1 = file
+    name TEXT NOT NULL,
+    notes TEXT,
+    data TEXT NOT NULL, -- JSON: {text, hidden} for text sends, {id, fileName, size, sizeName} for files
+    akey TEXT NOT NULL, -- Send key encrypted with the user key
+    password_hash TEXT, -- Server-side hash of the client password hash (NULL = no password)
+    password_salt TEXT,
+    password_iter INTEGER,
+    max_access_count INTEGER,
+    access_count INTEGER NOT NULL DEFAULT 0,
+    creation_date TEXT NOT NULL,
+    revision_date TEXT NOT NULL,
+    expiration_date TEXT,
+    deletion_date TEXT NOT NULL,
+    disabled INTEGER NOT NULL DEFAULT 0,
+    hide_email INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_sends_user_id ON sends(user_id);
+CREATE INDEX IF NOT EXISTS idx_sends_deletion_date ON sends(deletion_date);
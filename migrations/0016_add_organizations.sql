@@ -0,0 +1,42 @@
+-- Organizations: shared vaults with their own symmetric key.
+CREATE TABLE IF NOT EXISTS organizations (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    billing_email TEXT NOT NULL,
+    public_key TEXT, -- Organization public key
+    private_key TEXT, -- Organization private key encrypted with the organization key
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+-- Organization memberships. user_id is NULL while an invitation hasn't been accepted yet.
+-- Types: 0=Owner, 1=Admin, 2=User, 3=Manager, 4=Custom
+-- Status: -1=Revoked, 0=Invited, 1=Accepted, 2=Confirmed
+CREATE TABLE IF NOT EXISTS organization_users (
+    id TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL,
+    user_id TEXT,
+    email TEXT NOT NULL, -- Invited email (lowercase)
+    akey TEXT, -- Organization key encrypted with the member's public key (set on confirm)
+    status INTEGER NOT NULL,
+    atype INTEGER NOT NULL,
+    access_all INTEGER NOT NULL DEFAULT 0, -- Member can see every collection
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(organization_id, email)
+);
+CREATE INDEX IF NOT EXISTS idx_organization_users_user_id ON organization_users(user_id);
+
+-- Collections group organization ciphers for access control.
+CREATE TABLE IF NOT EXISTS collections (
+    id TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL,
+    name TEXT NOT NULL, -- Encrypted with the organization key
+    external_id TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_collections_organization_id ON collections(organization_id);
@@ -0,0 +1,12 @@
+-- Per-member collection assignments, for members without access to every collection.
+CREATE TABLE IF NOT EXISTS collections_users (
+    collection_id TEXT NOT NULL,
+    membership_id TEXT NOT NULL, -- organization_users.id
+    read_only INTEGER NOT NULL DEFAULT 0,
+    hide_passwords INTEGER NOT NULL DEFAULT 0,
+    manage INTEGER NOT NULL DEFAULT 0, -- Member can edit, assign and delete the collection
+    PRIMARY KEY (collection_id, membership_id),
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+    FOREIGN KEY (membership_id) REFERENCES organization_users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_collections_users_membership_id ON collections_users(membership_id);
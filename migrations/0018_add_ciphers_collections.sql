@@ -0,0 +1,9 @@
+-- Collections an organization cipher is assigned to.
+CREATE TABLE IF NOT EXISTS ciphers_collections (
+    cipher_id TEXT NOT NULL,
+    collection_id TEXT NOT NULL,
+    PRIMARY KEY (cipher_id, collection_id),
+    FOREIGN KEY (cipher_id) REFERENCES ciphers(id) ON DELETE CASCADE,
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_ciphers_collections_collection_id ON ciphers_collections(collection_id);
@@ -0,0 +1,13 @@
+-- Organization policies, at most one per type.
+-- Types follow Bitwarden's PolicyType: 0=TwoFactorAuthentication, 1=MasterPassword, ...
+CREATE TABLE IF NOT EXISTS organization_policies (
+    id TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL,
+    atype INTEGER NOT NULL,
+    enabled INTEGER NOT NULL DEFAULT 0,
+    data TEXT, -- Policy options as JSON
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    UNIQUE(organization_id, atype)
+);
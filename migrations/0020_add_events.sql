@@ -0,0 +1,18 @@
+-- Organization audit trail. Types follow Bitwarden's EventType (1000=User_LoggedIn,
+-- 1100=Cipher_Created, ...). No foreign keys: events outlive what they refer to.
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT PRIMARY KEY NOT NULL,
+    atype INTEGER NOT NULL,
+    organization_id TEXT,
+    user_id TEXT, -- User the event is about
+    cipher_id TEXT,
+    collection_id TEXT,
+    policy_id TEXT,
+    member_id TEXT, -- organization_users.id the event is about
+    acting_user_id TEXT,
+    device_type INTEGER,
+    ip_address TEXT,
+    date TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_events_organization_id_date ON events(organization_id, date);
+CREATE INDEX IF NOT EXISTS idx_events_acting_user_id_date ON events(acting_user_id, date);
@@ -0,0 +1,34 @@
+-- Organization groups: members gain collection access through the groups they belong to.
+CREATE TABLE IF NOT EXISTS groups (
+    id TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    external_id TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_groups_organization_id ON groups(organization_id);
+
+CREATE TABLE IF NOT EXISTS groups_users (
+    group_id TEXT NOT NULL,
+    membership_id TEXT NOT NULL, -- organization_users.id
+    PRIMARY KEY (group_id, membership_id),
+    FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
+    FOREIGN KEY (membership_id) REFERENCES organization_users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_groups_users_membership_id ON groups_users(membership_id);
+
+CREATE TABLE IF NOT EXISTS collections_groups (
+    collection_id TEXT NOT NULL,
+    group_id TEXT NOT NULL,
+    read_only INTEGER NOT NULL DEFAULT 0,
+    hide_passwords INTEGER NOT NULL DEFAULT 0,
+    manage INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (collection_id, group_id),
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+    FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_collections_groups_group_id ON collections_groups(group_id);
+
+ALTER TABLE events ADD COLUMN group_id TEXT;
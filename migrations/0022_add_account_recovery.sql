@@ -0,0 +1,9 @@
+-- Account recovery: members may enroll their user key, encrypted with the organization's public
+-- key, so owners and admins can reset their master password. A reset forces the member to choose
+-- a new password on next login.
+--
+-- Note: This migration is applied via GitHub Actions which handles
+-- the "duplicate column" error gracefully for existing databases.
+
+ALTER TABLE organization_users ADD COLUMN reset_password_key TEXT;
+ALTER TABLE users ADD COLUMN force_password_reset INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,19 @@
+-- Emergency access: a grantor designates trusted contacts (grantees) who can request access to
+-- their vault. An invitation is accepted by the grantee and then confirmed by the grantor, who
+-- hands over their user key encrypted with the grantee's public key.
+CREATE TABLE IF NOT EXISTS emergency_access (
+    id TEXT PRIMARY KEY NOT NULL,
+    grantor_id TEXT NOT NULL,
+    grantee_id TEXT, -- NULL until the invitation is accepted
+    email TEXT NOT NULL, -- invited address, lowercase
+    key_encrypted TEXT, -- grantor's user key encrypted with the grantee's public key
+    atype INTEGER NOT NULL, -- 0 View, 1 Takeover
+    status INTEGER NOT NULL, -- 0 Invited, 1 Accepted, 2 Confirmed
+    wait_time_days INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (grantor_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (grantee_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_emergency_access_grantor_email ON emergency_access(grantor_id, email);
+CREATE INDEX IF NOT EXISTS idx_emergency_access_grantee_id ON emergency_access(grantee_id);
@@ -0,0 +1,7 @@
+-- Emergency access recovery: a confirmed grantee may request access, which the grantor approves
+-- or rejects. Requests the grantor leaves unanswered are approved once the wait time has passed.
+--
+-- Note: This migration is applied via GitHub Actions which handles
+-- the "duplicate column" error gracefully for existing databases.
+
+ALTER TABLE emergency_access ADD COLUMN recovery_initiated_at TEXT;
@@ -0,0 +1,43 @@
+-- Chunked imports: an import session stages folders and ciphers uploaded over several requests
+-- and moves them into the vault on commit. Sessions expire an hour after they're started.
+CREATE TABLE IF NOT EXISTS import_sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    expected_folders INTEGER, -- totals announced at start, checked on commit
+    expected_ciphers INTEGER,
+    committed_at TEXT, -- NULL while chunks are still being uploaded
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_import_sessions_user_id ON import_sessions(user_id);
+CREATE INDEX IF NOT EXISTS idx_import_sessions_expires_at ON import_sessions(expires_at);
+
+-- Staged items are keyed by their index in the whole export, so a retried chunk overwrites
+-- rather than duplicates
+CREATE TABLE IF NOT EXISTS import_session_folders (
+    session_id TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    folder_id TEXT NOT NULL, -- id the folder is created with, or the user's existing folder
+    name TEXT NOT NULL,
+    PRIMARY KEY (session_id, idx),
+    FOREIGN KEY (session_id) REFERENCES import_sessions(id) ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS import_session_ciphers (
+    session_id TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    cipher_id TEXT NOT NULL,
+    type INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    favorite BOOLEAN NOT NULL DEFAULT 0,
+    PRIMARY KEY (session_id, idx),
+    FOREIGN KEY (session_id) REFERENCES import_sessions(id) ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS import_session_relationships (
+    session_id TEXT NOT NULL,
+    cipher_idx INTEGER NOT NULL,
+    folder_idx INTEGER NOT NULL,
+    PRIMARY KEY (session_id, cipher_idx),
+    FOREIGN KEY (session_id) REFERENCES import_sessions(id) ON DELETE CASCADE
+);
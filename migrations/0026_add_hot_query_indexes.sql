@@ -0,0 +1,18 @@
+-- Indexes for the cipher queries behind sync and listing.
+-- (user_id, deleted_at) serves both the whole personal vault (/api/sync) and the non-deleted
+-- ciphers (/api/ciphers), and replaces the plain user_id index.
+DROP INDEX IF EXISTS idx_ciphers_user_id;
+CREATE INDEX IF NOT EXISTS idx_ciphers_user_id_deleted_at ON ciphers(user_id, deleted_at);
+
+-- Organization vaults and the organization part of sync.
+CREATE INDEX IF NOT EXISTS idx_ciphers_organization_id ON ciphers(organization_id);
+
+-- Deleting a folder clears folder_id on its ciphers (ON DELETE SET NULL).
+CREATE INDEX IF NOT EXISTS idx_ciphers_folder_id ON ciphers(folder_id);
+
+-- Emails are stored lowercased; this keeps addresses that differ only in case from being
+-- registered twice.
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email_lower ON users(lower(email));
+
+-- The join tables (collections_users, ciphers_collections, groups_users, collections_groups)
+-- are already indexed both ways by their primary key and a reverse index.
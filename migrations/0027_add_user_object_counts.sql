@@ -0,0 +1,10 @@
+-- Per-user counts of ciphers, folders and sends, kept up to date by the writes that change them,
+-- so quota checks (MAX_CIPHERS_PER_USER, MAX_FOLDERS_PER_USER, MAX_SENDS_PER_USER) don't count rows.
+ALTER TABLE users ADD COLUMN cipher_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN folder_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN send_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE users SET
+    cipher_count = (SELECT COUNT(*) FROM ciphers WHERE user_id = users.id),
+    folder_count = (SELECT COUNT(*) FROM folders WHERE user_id = users.id),
+    send_count = (SELECT COUNT(*) FROM sends WHERE user_id = users.id);
@@ -0,0 +1,10 @@
+-- Users' timestamps used to be written by chrono's to_rfc3339 (`2024-05-01T12:34:56.789012345+00:00`)
+-- while everything else uses `2024-05-01T12:34:56.789Z`. Rewrite them in the common format so
+-- they compare as text with the rest; values SQLite can't read are left alone.
+UPDATE users SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', created_at)
+WHERE strftime('%Y-%m-%dT%H:%M:%fZ', created_at) IS NOT NULL
+  AND created_at <> strftime('%Y-%m-%dT%H:%M:%fZ', created_at);
+
+UPDATE users SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', updated_at)
+WHERE strftime('%Y-%m-%dT%H:%M:%fZ', updated_at) IS NOT NULL
+  AND updated_at <> strftime('%Y-%m-%dT%H:%M:%fZ', updated_at);
@@ -0,0 +1,7 @@
+-- Registration invitations sent by the operator (POST /admin/invite). An invited address may
+-- register without matching ALLOWED_EMAILS until the invitation expires; registering removes it.
+CREATE TABLE IF NOT EXISTS invitations (
+    email TEXT PRIMARY KEY NOT NULL, -- lowercased
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Whether members of the organization see the TOTP codes of its items (organizationUseTotp)
+ALTER TABLE organizations ADD COLUMN use_totp INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,9 @@
+-- One row per user while an import or a key rotation rewrites their vault, so that a second
+-- request for the same user is turned away instead of interleaving its writes. A lock past
+-- expires_at was left by a request that died, and the next one takes it over.
+CREATE TABLE IF NOT EXISTS import_locks (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    token TEXT NOT NULL, -- Identifies the holder, which alone releases it
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
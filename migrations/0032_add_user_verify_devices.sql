@@ -0,0 +1,2 @@
+-- Whether logins from unknown devices need an emailed code (NEW_DEVICE_VERIFICATION)
+ALTER TABLE users ADD COLUMN verify_devices INTEGER NOT NULL DEFAULT 1;
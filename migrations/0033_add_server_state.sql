@@ -0,0 +1,7 @@
+-- Values the Worker records about itself between runs, keyed by name, such as the last scheduled
+-- backup (last_backup). Not part of backups.
+CREATE TABLE IF NOT EXISTS server_state (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL, -- JSON
+    updated_at TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Whether the organization grants collection access through groups (useGroups). Organizations
+-- that already have groups keep using them.
+ALTER TABLE organizations ADD COLUMN use_groups INTEGER NOT NULL DEFAULT 0;
+
+UPDATE organizations SET use_groups = 1
+WHERE id IN (SELECT organization_id FROM groups);
@@ -0,0 +1,280 @@
+#!/usr/bin/env python3
+"""
+Generate the test account served by `POST /dev/seed` (sql/dev_seed.json).
+
+Why:
+- Logging a client into a fresh `wrangler dev` instance needs an account whose hashes and keys
+  were derived by a client. This derives them the way the Bitwarden clients do, so the seeded
+  account works with `bw login` and every official client.
+
+What it writes:
+- The account's master password hash (client side; the Worker hashes it again when seeding),
+  its encrypted user key and RSA key pair.
+- Folders and a few items of each type, encrypted with the user key, including a trashed one.
+
+The keys are random, so each run produces different ciphertexts; commit the output once and
+only regenerate it when the seed content changes.
+
+Usage:
+  python3 scripts/generate-dev-seed.py --output sql/dev_seed.json
+
+Requires the `cryptography` package.
+"""
+
+from __future__ import annotations
+
+import argparse
+import base64
+import hashlib
+import hmac
+import json
+import os
+from pathlib import Path
+
+from cryptography.hazmat.primitives import padding, serialization
+from cryptography.hazmat.primitives.asymmetric import ed25519, rsa
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+
+EMAIL = "dev@warden.test"
+PASSWORD = "warden-dev-password"
+KDF_ITERATIONS = 600_000
+
+USER_ID = "5eed0000-0000-4000-8000-000000000000"
+
+
+def folder_id(n: int) -> str:
+    return f"5eed0000-0000-4000-8001-{n:012d}"
+
+
+def cipher_id(n: int) -> str:
+    return f"5eed0000-0000-4000-8002-{n:012d}"
+
+
+def b64(data: bytes) -> str:
+    return base64.b64encode(data).decode()
+
+
+def hkdf_expand(prk: bytes, info: bytes) -> bytes:
+    """HKDF-Expand to 32 bytes, as the clients stretch the master key."""
+    return hmac.new(prk, info + b"\x01", hashlib.sha256).digest()
+
+
+class Key:
+    """An AES-256-CBC + HMAC-SHA256 key pair, producing type 2 EncStrings."""
+
+    def __init__(self, enc: bytes, mac: bytes) -> None:
+        self.enc = enc
+        self.mac = mac
+
+    def encrypt(self, plain: bytes | str) -> str:
+        if isinstance(plain, str):
+            plain = plain.encode()
+        iv = os.urandom(16)
+        padder = padding.PKCS7(128).padder()
+        padded = padder.update(plain) + padder.finalize()
+        encryptor = Cipher(algorithms.AES(self.enc), modes.CBC(iv)).encryptor()
+        data = encryptor.update(padded) + encryptor.finalize()
+        mac = hmac.new(self.mac, iv + data, hashlib.sha256).digest()
+        return f"2.{b64(iv)}|{b64(data)}|{b64(mac)}"
+
+
+def account() -> tuple[dict, Key]:
+    master_key = hashlib.pbkdf2_hmac(
+        "sha256", PASSWORD.encode(), EMAIL.lower().encode(), KDF_ITERATIONS, 32
+    )
+    master_password_hash = hashlib.pbkdf2_hmac("sha256", master_key, PASSWORD.encode(), 1, 32)
+    stretched = Key(hkdf_expand(master_key, b"enc"), hkdf_expand(master_key, b"mac"))
+
+    user_key_bytes = os.urandom(64)
+    user_key = Key(user_key_bytes[:32], user_key_bytes[32:])
+
+    private_key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+    private_der = private_key.private_bytes(
+        serialization.Encoding.DER,
+        serialization.PrivateFormat.PKCS8,
+        serialization.NoEncryption(),
+    )
+    public_der = private_key.public_key().public_bytes(
+        serialization.Encoding.DER,
+        serialization.PublicFormat.SubjectPublicKeyInfo,
+    )
+
+    return (
+        {
+            "id": USER_ID,
+            "name": "Dev User",
+            "email": EMAIL,
+            "password": PASSWORD,
+            "kdfIterations": KDF_ITERATIONS,
+            "masterPasswordHash": b64(master_password_hash),
+            "key": stretched.encrypt(user_key_bytes),
+            "publicKey": b64(public_der),
+            "privateKey": user_key.encrypt(private_der),
+        },
+        user_key,
+    )
+
+
+def ssh_key(key: Key) -> dict:
+    private_key = ed25519.Ed25519PrivateKey.generate()
+    private_openssh = private_key.private_bytes(
+        serialization.Encoding.PEM,
+        serialization.PrivateFormat.OpenSSH,
+        serialization.NoEncryption(),
+    ).decode()
+    public_openssh = (
+        private_key.public_key()
+        .public_bytes(serialization.Encoding.OpenSSH, serialization.PublicFormat.OpenSSH)
+        .decode()
+    )
+    blob = base64.b64decode(public_openssh.split()[1])
+    fingerprint = "SHA256:" + base64.b64encode(hashlib.sha256(blob).digest()).decode().rstrip("=")
+    return {
+        "privateKey": key.encrypt(private_openssh),
+        "publicKey": key.encrypt(public_openssh),
+        "keyFingerprint": key.encrypt(fingerprint),
+    }
+
+
+def items(key: Key) -> tuple[list[dict], list[dict]]:
+    folders = [
+        {"id": folder_id(1), "name": key.encrypt("Work")},
+        {"id": folder_id(2), "name": key.encrypt("Personal")},
+    ]
+
+    def login(username: str, password: str, uri: str, totp: str | None = None) -> dict:
+        return {
+            "username": key.encrypt(username),
+            "password": key.encrypt(password),
+            "uris": [{"uri": key.encrypt(uri), "match": None}],
+            "totp": key.encrypt(totp) if totp else None,
+            "passwordRevisionDate": None,
+        }
+
+    ciphers = [
+        {
+            "id": cipher_id(1),
+            "type": 1,
+            "folderId": folder_id(1),
+            "favorite": True,
+            "data": {
+                "name": key.encrypt("Example Login"),
+                "notes": key.encrypt("Seeded for local development."),
+                "login": login("dev", "correct horse battery staple", "https://example.com"),
+                "fields": [
+                    {
+                        "name": key.encrypt("PIN"),
+                        "value": key.encrypt("1234"),
+                        "type": 1,
+                        "linkedId": None,
+                    }
+                ],
+            },
+        },
+        {
+            "id": cipher_id(2),
+            "type": 1,
+            "folderId": folder_id(1),
+            "data": {
+                "name": key.encrypt("Login with TOTP"),
+                "login": login(
+                    "dev@warden.test",
+                    "hunter2",
+                    "https://accounts.example.org/login",
+                    "otpauth://totp/Example:dev?secret=JBSWY3DPEHPK3PXP&issuer=Example",
+                ),
+            },
+        },
+        {
+            "id": cipher_id(3),
+            "type": 1,
+            "folderId": folder_id(2),
+            "data": {
+                "name": key.encrypt("Forum Account"),
+                "login": login("devuser", "p@ssw0rd!", "https://forum.example.net"),
+            },
+        },
+        {
+            "id": cipher_id(4),
+            "type": 2,
+            "folderId": None,
+            "data": {
+                "name": key.encrypt("Wi-Fi Notes"),
+                "notes": key.encrypt("SSID: warden-dev\nPassphrase: not-a-real-network"),
+                "secureNote": {"type": 0},
+            },
+        },
+        {
+            "id": cipher_id(5),
+            "type": 3,
+            "folderId": folder_id(2),
+            "data": {
+                "name": key.encrypt("Test Visa"),
+                "card": {
+                    "cardholderName": key.encrypt("Dev User"),
+                    "brand": key.encrypt("Visa"),
+                    "number": key.encrypt("4111111111111111"),
+                    "expMonth": key.encrypt("12"),
+                    "expYear": key.encrypt("2030"),
+                    "code": key.encrypt("123"),
+                },
+            },
+        },
+        {
+            "id": cipher_id(6),
+            "type": 4,
+            "folderId": folder_id(2),
+            "data": {
+                "name": key.encrypt("Dev Identity"),
+                "identity": {
+                    "title": key.encrypt("Mx"),
+                    "firstName": key.encrypt("Dev"),
+                    "lastName": key.encrypt("User"),
+                    "email": key.encrypt("dev@warden.test"),
+                    "phone": key.encrypt("+1 555 0100"),
+                    "address1": key.encrypt("1 Example Street"),
+                    "city": key.encrypt("Springfield"),
+                    "postalCode": key.encrypt("12345"),
+                    "country": key.encrypt("US"),
+                },
+            },
+        },
+        {
+            "id": cipher_id(7),
+            "type": 5,
+            "folderId": folder_id(1),
+            "data": {
+                "name": key.encrypt("Deploy Key"),
+                "sshKey": ssh_key(key),
+            },
+        },
+        {
+            "id": cipher_id(8),
+            "type": 1,
+            "folderId": None,
+            "deleted": True,
+            "data": {
+                "name": key.encrypt("Old Account (trashed)"),
+                "login": login("olddev", "retired-password", "https://old.example.com"),
+            },
+        },
+    ]
+    for cipher in ciphers:
+        cipher["data"].setdefault("reprompt", 0)
+    return folders, ciphers
+
+
+def main() -> None:
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[1])
+    parser.add_argument("--output", default="sql/dev_seed.json", help="file to write")
+    args = parser.parse_args()
+
+    user, key = account()
+    folders, ciphers = items(key)
+    seed = {"user": user, "folders": folders, "ciphers": ciphers}
+    Path(args.output).write_text(json.dumps(seed, indent=2) + "\n")
+    print(f"Wrote {args.output}: {len(folders)} folders, {len(ciphers)} ciphers")
+
+
+if __name__ == "__main__":
+    main()
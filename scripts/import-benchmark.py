@@ -0,0 +1,132 @@
+#!/usr/bin/env python3
+"""
+Benchmark POST /api/ciphers/import with a generated payload.
+
+Why:
+- Imports are the heaviest request the Worker serves; this measures how long a vault of a given
+  size takes, e.g. against `wrangler dev`, to check it stays within the CPU budget.
+
+The payload mimics a real export: type 2 encrypted strings of realistic length, a mix of
+logins, secure notes and cards, and folders with relationships. Nothing in it is decryptable, so
+use a throwaway account.
+
+Usage:
+  # Write a payload only
+  python3 scripts/import-benchmark.py --items 5000 --output /tmp/import.json
+
+  # Import it into a local dev server (token from /identity/connect/token)
+  python3 scripts/import-benchmark.py --items 5000 \
+    --url http://localhost:8787 --token "$ACCESS_TOKEN"
+"""
+
+from __future__ import annotations
+
+import argparse
+import base64
+import json
+import os
+import sys
+import time
+import urllib.error
+import urllib.request
+from pathlib import Path
+
+
+def enc(plain_len: int) -> str:
+    """An EncString (AES-CBC + HMAC) of roughly the size `plain_len` bytes encrypt to."""
+    iv = base64.b64encode(os.urandom(16)).decode()
+    data = base64.b64encode(os.urandom((plain_len // 16 + 1) * 16)).decode()
+    mac = base64.b64encode(os.urandom(32)).decode()
+    return f"2.{iv}|{data}|{mac}"
+
+
+def cipher(index: int, notes_len: int) -> dict:
+    kind = (1, 1, 1, 2, 3)[index % 5]
+    item: dict = {
+        "type": kind,
+        "name": enc(24),
+        "notes": enc(notes_len) if index % 4 == 0 else None,
+        "favorite": index % 50 == 0,
+        "reprompt": 0,
+    }
+    if kind == 1:
+        item["login"] = {
+            "username": enc(20),
+            "password": enc(24),
+            "uris": [{"uri": enc(40), "match": None}],
+            "totp": None,
+        }
+    elif kind == 2:
+        item["secureNote"] = {"type": 0}
+    else:
+        item["card"] = {
+            "cardholderName": enc(20),
+            "number": enc(16),
+            "expMonth": enc(2),
+            "expYear": enc(4),
+            "code": enc(3),
+            "brand": enc(8),
+        }
+    return item
+
+
+def payload(items: int, folders: int, notes_len: int) -> dict:
+    return {
+        "folders": [{"name": enc(16)} for _ in range(folders)],
+        "ciphers": [cipher(index, notes_len) for index in range(items)],
+        "folderRelationships": [
+            {"key": index, "value": index % folders}
+            for index in range(items)
+            if folders and index % 3 == 0
+        ],
+    }
+
+
+def main() -> int:
+    parser = argparse.ArgumentParser(description=__doc__.split("\n\n")[0])
+    parser.add_argument("--items", type=int, default=5000, help="Number of ciphers")
+    parser.add_argument("--folders", type=int, default=50, help="Number of folders")
+    parser.add_argument(
+        "--notes-length", type=int, default=200, help="Plaintext length of notes (every 4th item)"
+    )
+    parser.add_argument("--output", type=Path, help="Write the payload to this file")
+    parser.add_argument("--url", help="Base URL of the server, e.g. http://localhost:8787")
+    parser.add_argument("--token", help="Access token of the account to import into")
+    args = parser.parse_args()
+
+    if not args.output and not args.url:
+        parser.error("give --output, --url or both")
+    if args.url and not args.token:
+        parser.error("--url needs --token")
+
+    body = json.dumps(payload(args.items, args.folders, args.notes_length)).encode()
+    print(f"Payload: {args.items} ciphers, {args.folders} folders, {len(body) / 1024:.0f} KiB")
+    if args.output:
+        args.output.write_bytes(body)
+        print(f"Wrote {args.output}")
+    if not args.url:
+        return 0
+
+    request = urllib.request.Request(
+        f"{args.url.rstrip('/')}/api/ciphers/import",
+        data=body,
+        method="POST",
+        headers={"Authorization": f"Bearer {args.token}", "Content-Type": "application/json"},
+    )
+    started = time.monotonic()
+    try:
+        with urllib.request.urlopen(request) as response:
+            answer = response.read().decode()
+            status = response.status
+    except urllib.error.HTTPError as err:
+        answer = err.read().decode()
+        status = err.code
+    elapsed = time.monotonic() - started
+
+    print(f"HTTP {status} after {elapsed:.2f}s ({args.items / elapsed:.0f} items/s)")
+    print(answer)
+    return 0 if status == 200 else 1
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -20,9 +20,15 @@ CREATE TABLE IF NOT EXISTS users (
     equivalent_domains TEXT NOT NULL DEFAULT '[]', -- JSON: Vec<Vec<String>>
     excluded_globals TEXT NOT NULL DEFAULT '[]', -- JSON: Vec<i32> (reserved for future global groups)
     totp_recover TEXT, -- Recovery code for 2FA
+    force_password_reset INTEGER NOT NULL DEFAULT 0, -- Set by an admin password reset until the user picks a new one
+    verify_devices INTEGER NOT NULL DEFAULT 1, -- Logins from unknown devices need an emailed code (NEW_DEVICE_VERIFICATION)
+    cipher_count INTEGER NOT NULL DEFAULT 0, -- Rows in ciphers, folders and sends with this user_id, for the quotas
+    folder_count INTEGER NOT NULL DEFAULT 0,
+    send_count INTEGER NOT NULL DEFAULT 0,
     created_at TEXT NOT NULL,
     updated_at TEXT NOT NULL
 );
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email_lower ON users(lower(email));
 
 -- Ciphers table for storing encrypted vault items
 CREATE TABLE IF NOT EXISTS ciphers (
@@ -40,8 +46,11 @@ CREATE TABLE IF NOT EXISTS ciphers (
     FOREIGN KEY (folder_id) REFERENCES folders(id) ON DELETE SET NULL
 );
 
--- Index to speed up common per-user cipher queries (sync/list/attachments joins)
-CREATE INDEX IF NOT EXISTS idx_ciphers_user_id ON ciphers(user_id);
+-- Indexes for per-user cipher queries (sync/list/attachments joins), organization vaults and
+-- folder deletes
+CREATE INDEX IF NOT EXISTS idx_ciphers_user_id_deleted_at ON ciphers(user_id, deleted_at);
+CREATE INDEX IF NOT EXISTS idx_ciphers_organization_id ON ciphers(organization_id);
+CREATE INDEX IF NOT EXISTS idx_ciphers_folder_id ON ciphers(folder_id);
 
 -- Attachments table for cipher file metadata
 CREATE TABLE IF NOT EXISTS attachments (
@@ -97,7 +106,299 @@ CREATE TABLE IF NOT EXISTS folders (
 
 CREATE INDEX IF NOT EXISTS idx_folders_user_id ON folders(user_id);
 
--- Global equivalent domains dataset (seeded separately, not bundled into the Worker)
+-- Login with device: pending/answered auth requests
+CREATE TABLE IF NOT EXISTS auth_requests (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    request_device_identifier TEXT NOT NULL,
+    device_type INTEGER NOT NULL,
+    request_ip TEXT NOT NULL,
+    response_device_id TEXT,
+    access_code TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    enc_key TEXT, -- User key encrypted to public_key (set on approval)
+    master_password_hash TEXT,
+    approved INTEGER, -- NULL = pending, 1 = approved, 0 = denied
+    creation_date TEXT NOT NULL,
+    response_date TEXT,
+    authentication_date TEXT, -- Set once the request has been used to log in
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_auth_requests_user_id ON auth_requests(user_id);
+CREATE INDEX IF NOT EXISTS idx_auth_requests_creation_date ON auth_requests(creation_date);
+
+-- Devices that have logged in, keyed by the client-generated device identifier.
+-- The encrypted_* columns hold trusted device encryption (TDE) keys.
+CREATE TABLE IF NOT EXISTS devices (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    identifier TEXT NOT NULL,
+    name TEXT NOT NULL,
+    atype INTEGER NOT NULL,
+    encrypted_user_key TEXT, -- User key encrypted with the device public key
+    encrypted_public_key TEXT, -- Device public key encrypted with the user key
+    encrypted_private_key TEXT, -- Device private key encrypted with the device key
+    refresh_token_id TEXT, -- Id of the currently valid refresh token (NULL = no active session)
+    last_active_at TEXT, -- Last login or token refresh
+    push_uuid TEXT, -- Id the device is registered under at the push relay
+    push_token TEXT, -- APNs/FCM token reported by the client
+    web_push_endpoint TEXT, -- Web Push subscription endpoint (browser clients)
+    web_push_p256dh TEXT, -- Web Push client ECDH public key (base64url)
+    web_push_auth TEXT, -- Web Push client auth secret (base64url)
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(user_id, identifier)
+);
+
+-- Bitwarden Send: text and file shares readable by anyone holding the link.
+CREATE TABLE IF NOT EXISTS sends (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    atype INTEGER NOT NULL, -- 0 = text, 1 = file
+    name TEXT NOT NULL,
+    notes TEXT,
+    data TEXT NOT NULL, -- JSON: {text, hidden} for text sends, {id, fileName, size, sizeName} for files
+    akey TEXT NOT NULL, -- Send key encrypted with the user key
+    password_hash TEXT, -- Server-side hash of the client password hash (NULL = no password)
+    password_salt TEXT,
+    password_iter INTEGER,
+    max_access_count INTEGER,
+    access_count INTEGER NOT NULL DEFAULT 0,
+    creation_date TEXT NOT NULL,
+    revision_date TEXT NOT NULL,
+    expiration_date TEXT,
+    deletion_date TEXT NOT NULL,
+    disabled INTEGER NOT NULL DEFAULT 0,
+    hide_email INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_sends_user_id ON sends(user_id);
+CREATE INDEX IF NOT EXISTS idx_sends_deletion_date ON sends(deletion_date);
+
+-- Organizations: shared vaults with their own symmetric key.
+CREATE TABLE IF NOT EXISTS organizations (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    billing_email TEXT NOT NULL,
+    public_key TEXT, -- Organization public key
+    private_key TEXT, -- Organization private key encrypted with the organization key
+    use_totp INTEGER NOT NULL DEFAULT 1, -- Members see the TOTP codes of the organization's items
+    use_groups INTEGER NOT NULL DEFAULT 0, -- Collection access is also granted through groups
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+-- Organization memberships. user_id is NULL while an invitation hasn't been accepted yet.
+-- Types: 0=Owner, 1=Admin, 2=User, 3=Manager, 4=Custom
+-- Status: -1=Revoked, 0=Invited, 1=Accepted, 2=Confirmed
+CREATE TABLE IF NOT EXISTS organization_users (
+    id TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL,
+    user_id TEXT,
+    email TEXT NOT NULL, -- Invited email (lowercase)
+    akey TEXT, -- Organization key encrypted with the member's public key (set on confirm)
+    status INTEGER NOT NULL,
+    atype INTEGER NOT NULL,
+    access_all INTEGER NOT NULL DEFAULT 0, -- Member can see every collection
+    reset_password_key TEXT, -- User key encrypted with the organization's public key (account recovery enrollment)
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(organization_id, email)
+);
+CREATE INDEX IF NOT EXISTS idx_organization_users_user_id ON organization_users(user_id);
+
+-- Collections group organization ciphers for access control.
+CREATE TABLE IF NOT EXISTS collections (
+    id TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL,
+    name TEXT NOT NULL, -- Encrypted with the organization key
+    external_id TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_collections_organization_id ON collections(organization_id);
+
+-- Per-member collection assignments, for members without access to every collection.
+CREATE TABLE IF NOT EXISTS collections_users (
+    collection_id TEXT NOT NULL,
+    membership_id TEXT NOT NULL, -- organization_users.id
+    read_only INTEGER NOT NULL DEFAULT 0,
+    hide_passwords INTEGER NOT NULL DEFAULT 0,
+    manage INTEGER NOT NULL DEFAULT 0, -- Member can edit, assign and delete the collection
+    PRIMARY KEY (collection_id, membership_id),
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+    FOREIGN KEY (membership_id) REFERENCES organization_users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_collections_users_membership_id ON collections_users(membership_id);
+
+-- Collections an organization cipher is assigned to.
+CREATE TABLE IF NOT EXISTS ciphers_collections (
+    cipher_id TEXT NOT NULL,
+    collection_id TEXT NOT NULL,
+    PRIMARY KEY (cipher_id, collection_id),
+    FOREIGN KEY (cipher_id) REFERENCES ciphers(id) ON DELETE CASCADE,
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_ciphers_collections_collection_id ON ciphers_collections(collection_id);
+
+-- Organization policies, at most one per type.
+-- Types follow Bitwarden's PolicyType: 0=TwoFactorAuthentication, 1=MasterPassword, ...
+CREATE TABLE IF NOT EXISTS organization_policies (
+    id TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL,
+    atype INTEGER NOT NULL,
+    enabled INTEGER NOT NULL DEFAULT 0,
+    data TEXT, -- Policy options as JSON
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    UNIQUE(organization_id, atype)
+);
+
+-- Organization audit trail. Types follow Bitwarden's EventType (1000=User_LoggedIn,
+-- 1100=Cipher_Created, ...). No foreign keys: events outlive what they refer to.
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT PRIMARY KEY NOT NULL,
+    atype INTEGER NOT NULL,
+    organization_id TEXT,
+    user_id TEXT, -- User the event is about
+    cipher_id TEXT,
+    collection_id TEXT,
+    group_id TEXT,
+    policy_id TEXT,
+    member_id TEXT, -- organization_users.id the event is about
+    acting_user_id TEXT,
+    device_type INTEGER,
+    ip_address TEXT,
+    date TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_events_organization_id_date ON events(organization_id, date);
+CREATE INDEX IF NOT EXISTS idx_events_acting_user_id_date ON events(acting_user_id, date);
+
+-- Organization groups: members gain collection access through the groups they belong to.
+CREATE TABLE IF NOT EXISTS groups (
+    id TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    external_id TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_groups_organization_id ON groups(organization_id);
+
+CREATE TABLE IF NOT EXISTS groups_users (
+    group_id TEXT NOT NULL,
+    membership_id TEXT NOT NULL, -- organization_users.id
+    PRIMARY KEY (group_id, membership_id),
+    FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
+    FOREIGN KEY (membership_id) REFERENCES organization_users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_groups_users_membership_id ON groups_users(membership_id);
+
+CREATE TABLE IF NOT EXISTS collections_groups (
+    collection_id TEXT NOT NULL,
+    group_id TEXT NOT NULL,
+    read_only INTEGER NOT NULL DEFAULT 0,
+    hide_passwords INTEGER NOT NULL DEFAULT 0,
+    manage INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (collection_id, group_id),
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+    FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_collections_groups_group_id ON collections_groups(group_id);
+
+-- Emergency access contacts
+CREATE TABLE IF NOT EXISTS emergency_access (
+    id TEXT PRIMARY KEY NOT NULL,
+    grantor_id TEXT NOT NULL,
+    grantee_id TEXT, -- NULL until the invitation is accepted
+    email TEXT NOT NULL, -- invited address, lowercase
+    key_encrypted TEXT, -- grantor's user key encrypted with the grantee's public key
+    atype INTEGER NOT NULL, -- 0 View, 1 Takeover
+    status INTEGER NOT NULL, -- 0 Invited, 1 Accepted, 2 Confirmed, 3 RecoveryInitiated, 4 RecoveryApproved
+    wait_time_days INTEGER NOT NULL,
+    recovery_initiated_at TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (grantor_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (grantee_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_emergency_access_grantor_email ON emergency_access(grantor_id, email);
+CREATE INDEX IF NOT EXISTS idx_emergency_access_grantee_id ON emergency_access(grantee_id);
+
+-- Chunked import sessions and their staged items
+CREATE TABLE IF NOT EXISTS import_sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    expected_folders INTEGER, -- totals announced at start, checked on commit
+    expected_ciphers INTEGER,
+    committed_at TEXT, -- NULL while chunks are still being uploaded
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_import_sessions_user_id ON import_sessions(user_id);
+CREATE INDEX IF NOT EXISTS idx_import_sessions_expires_at ON import_sessions(expires_at);
+
+-- Staged items are keyed by their index in the whole export, so a retried chunk overwrites
+-- rather than duplicates
+CREATE TABLE IF NOT EXISTS import_session_folders (
+    session_id TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    folder_id TEXT NOT NULL, -- id the folder is created with, or the user's existing folder
+    name TEXT NOT NULL,
+    PRIMARY KEY (session_id, idx),
+    FOREIGN KEY (session_id) REFERENCES import_sessions(id) ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS import_session_ciphers (
+    session_id TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    cipher_id TEXT NOT NULL,
+    type INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    favorite BOOLEAN NOT NULL DEFAULT 0,
+    PRIMARY KEY (session_id, idx),
+    FOREIGN KEY (session_id) REFERENCES import_sessions(id) ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS import_session_relationships (
+    session_id TEXT NOT NULL,
+    cipher_idx INTEGER NOT NULL,
+    folder_idx INTEGER NOT NULL,
+    PRIMARY KEY (session_id, cipher_idx),
+    FOREIGN KEY (session_id) REFERENCES import_sessions(id) ON DELETE CASCADE
+);
+
+-- Held while an import or a key rotation rewrites a user's vault; past expires_at, a lock left
+-- by a request that died is taken over
+CREATE TABLE IF NOT EXISTS import_locks (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    token TEXT NOT NULL, -- Identifies the holder, which alone releases it
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Registration invitations sent by the operator (POST /admin/invite). An invited address may
+-- register without matching ALLOWED_EMAILS until the invitation expires; registering removes it.
+CREATE TABLE IF NOT EXISTS invitations (
+    email TEXT PRIMARY KEY NOT NULL, -- lowercased
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
+-- Values the Worker records about itself between runs, such as the last scheduled backup
+CREATE TABLE IF NOT EXISTS server_state (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL, -- JSON
+    updated_at TEXT NOT NULL
+);
+
+-- Global equivalent domains dataset (optional, seeded separately; overrides the Worker's built-in list)
 CREATE TABLE IF NOT EXISTS global_equivalent_domains (
     type INTEGER PRIMARY KEY NOT NULL,
     sort_order INTEGER NOT NULL,
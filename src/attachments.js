@@ -4,15 +4,49 @@
  * This module implements:
  * - Attachment upload logic (zero-copy streaming to R2)
  * - Attachment download logic (zero-copy streaming from R2)
- * - JWT validation for attachment tokens (HMAC-SHA256) using Web Crypto API
+ * - File Send download logic (zero-copy streaming from R2)
+ * - JWT validation for attachment tokens (HMAC-SHA256, with the keys of JWT_SECRET and
+ *   JWT_PREVIOUS_SECRETS) using Web Crypto API
  *
  * Route matching and URL parsing should be handled by `src/entry.js`.
  */
 
 const JWT_EXPECTED_ALG = "HS256";
 const JWT_VALIDATION_LEEWAY_SECS = 60;
-// JWT validation using Web Crypto API (no external dependencies)
-async function verifyJwt(token, secret) {
+// Length (in hex chars) of the fingerprint used as `kid`, as in src/auth/keys.rs
+const KID_LEN = 16;
+
+// `kid` of a secret: the first KID_LEN hex chars of its SHA-256 digest
+async function keyId(secret) {
+  const digest = await crypto.subtle.digest("SHA-256", new TextEncoder().encode(secret));
+  return Array.from(new Uint8Array(digest), (byte) => byte.toString(16).padStart(2, "0"))
+    .join("")
+    .slice(0, KID_LEN);
+}
+
+// The keys `KeyRing::access` verifies with: JWT_SECRET, then the JWT_PREVIOUS_SECRETS still
+// accepted after a rotation (separated by commas and/or whitespace).
+async function accessKeys(env) {
+  const primary = getEnvVar(env, "JWT_SECRET");
+  if (!primary) {
+    throw new Error("JWT_SECRET not configured");
+  }
+  const previous = (getEnvVar(env, "JWT_PREVIOUS_SECRETS") || "")
+    .split(/[\s,]+/)
+    .filter((secret) => secret);
+  const keys = [];
+  for (const secret of [primary, ...previous]) {
+    const kid = await keyId(secret);
+    if (!keys.some((key) => key.kid === kid)) {
+      keys.push({ kid, secret });
+    }
+  }
+  return keys;
+}
+
+// JWT validation using Web Crypto API (no external dependencies). The key is picked by the
+// token's `kid`; tokens without one (issued before key ids) are checked against every key.
+async function verifyJwt(token, keys) {
   const encoder = new TextEncoder();
   const parts = token.split(".");
   if (parts.length !== 3) {
@@ -32,21 +66,31 @@ async function verifyJwt(token, secret) {
     throw new Error("Invalid token algorithm");
   }
 
-  // Import the secret key for HMAC-SHA256
-  const key = await crypto.subtle.importKey(
-    "raw",
-    encoder.encode(secret),
-    { name: "HMAC", hash: "SHA-256" },
-    false,
-    ["verify"]
-  );
+  const candidates = header.kid == null ? keys : keys.filter((key) => key.kid === header.kid);
+  if (candidates.length === 0) {
+    throw new Error("Unknown token key");
+  }
 
   // Decode the signature (base64url to Uint8Array)
   const signature = base64UrlDecode(signatureB64);
 
   // Verify the signature
   const data = encoder.encode(`${headerB64}.${payloadB64}`);
-  const valid = await crypto.subtle.verify("HMAC", key, signature, data);
+  let valid = false;
+  for (const { secret } of candidates) {
+    // Import the secret key for HMAC-SHA256
+    const key = await crypto.subtle.importKey(
+      "raw",
+      encoder.encode(secret),
+      { name: "HMAC", hash: "SHA-256" },
+      false,
+      ["verify"]
+    );
+    if (await crypto.subtle.verify("HMAC", key, signature, data)) {
+      valid = true;
+      break;
+    }
+  }
 
   if (!valid) {
     throw new Error("Invalid token signature");
@@ -158,6 +202,10 @@ async function getUserAttachmentUsage(db, userId, excludeAttachmentId) {
          FROM attachments_pending p
          JOIN ciphers c2 ON c2.id = p.cipher_id
          WHERE c2.user_id = ?1 AND p.id != ?2
+         UNION ALL
+         SELECT CAST(json_extract(s.data, '$.size') AS INTEGER) as file_size
+         FROM sends s
+         WHERE s.user_id = ?1 AND s.atype = 1 AND json_extract(s.data, '$.id') != ?2
        ) AS files`
     : `SELECT COALESCE(SUM(file_size), 0) as total FROM (
          SELECT a.file_size as file_size
@@ -169,6 +217,10 @@ async function getUserAttachmentUsage(db, userId, excludeAttachmentId) {
          FROM attachments_pending p
          JOIN ciphers c2 ON c2.id = p.cipher_id
          WHERE c2.user_id = ?1
+         UNION ALL
+         SELECT CAST(json_extract(s.data, '$.size') AS INTEGER) as file_size
+         FROM sends s
+         WHERE s.user_id = ?1 AND s.atype = 1
        ) AS files`;
 
   const bindings = excludeAttachmentId ? [userId, excludeAttachmentId] : [userId];
@@ -227,11 +279,7 @@ export async function handleAzureUpload(request, env, cipherId, attachmentId, to
   // Validate JWT token
   let claims;
   try {
-    const secret = env.JWT_SECRET?.toString?.() || env.JWT_SECRET;
-    if (!secret) {
-      throw new Error("JWT_SECRET not configured");
-    }
-    claims = await verifyJwt(token, secret);
+    claims = await verifyJwt(token, await accessKeys(env));
   } catch (err) {
     return new Response(JSON.stringify({ error: `Invalid token: ${err.message}` }), {
       status: 401,
@@ -430,11 +478,7 @@ export async function handleDownload(request, env, cipherId, attachmentId, token
   // Validate JWT token
   let claims;
   try {
-    const secret = env.JWT_SECRET?.toString?.() || env.JWT_SECRET;
-    if (!secret) {
-      throw new Error("JWT_SECRET not configured");
-    }
-    claims = await verifyJwt(token, secret);
+    claims = await verifyJwt(token, await accessKeys(env));
   } catch (err) {
     return new Response(JSON.stringify({ error: `Invalid token: ${err.message}` }), {
       status: 401,
@@ -526,3 +570,139 @@ export async function handleDownload(request, env, cipherId, attachmentId, token
     return new Response(r2Object.body, { status: 200, headers });
   }
 }
+
+// `purpose` claim of send download tokens (see `SendDownloadClaims` in src/handlers/sends.rs)
+const SEND_DOWNLOAD_PURPOSE = "send_download";
+
+// Unavailable sends all look like missing ones, as on the Rust side
+function sendNotFound() {
+  return new Response(JSON.stringify({ error: "Send not found" }), {
+    status: 404,
+    headers: { "Content-Type": "application/json" },
+  });
+}
+
+// Counts one download of a send, unless it was disabled or ran out of accesses in the meantime,
+// and lets the owner's clients pick up the new count on their next sync. The increment is
+// conditional on the limit so concurrent downloads can't push the count past max_access_count.
+async function recordSendAccess(db, send) {
+  const result = await db
+    .prepare(
+      `UPDATE sends SET access_count = access_count + 1
+       WHERE id = ?1 AND disabled = 0
+         AND (max_access_count IS NULL OR access_count < max_access_count)`
+    )
+    .bind(send.id)
+    .run();
+  if (!result.meta?.changes) {
+    return false;
+  }
+  await db
+    .prepare("UPDATE users SET updated_at = ?1 WHERE id = ?2")
+    .bind(nowString(), send.user_id)
+    .run();
+  return true;
+}
+
+// Handle file Send download with zero-copy streaming (R2) or stream (KV).
+// The token comes from POST /api/sends/{id}/access/file/{file_id}, which already checked the
+// password; here we make sure the send is still available and count the download.
+export async function handleSendDownload(request, env, sendId, fileId, token) {
+  const backend = getStorageBackend(env);
+  if (!backend) {
+    return new Response(JSON.stringify({ error: "File sends are not enabled" }), {
+      status: 400,
+      headers: { "Content-Type": "application/json" },
+    });
+  }
+
+  const db = env.vault1;
+  if (!db) {
+    return new Response(JSON.stringify({ error: "Database not available" }), {
+      status: 500,
+      headers: { "Content-Type": "application/json" },
+    });
+  }
+
+  // Validate JWT token
+  let claims;
+  try {
+    claims = await verifyJwt(token, await accessKeys(env));
+  } catch (err) {
+    return new Response(JSON.stringify({ error: `Invalid token: ${err.message}` }), {
+      status: 401,
+      headers: { "Content-Type": "application/json" },
+    });
+  }
+
+  // Validate token claims match the request
+  if (
+    claims.purpose !== SEND_DOWNLOAD_PURPOSE ||
+    claims.send_id !== sendId ||
+    claims.file_id !== fileId
+  ) {
+    return new Response(JSON.stringify({ error: "Invalid download token" }), {
+      status: 401,
+      headers: { "Content-Type": "application/json" },
+    });
+  }
+
+  // The token outlives neither the send nor its availability
+  const send = await db.prepare("SELECT * FROM sends WHERE id = ?1").bind(sendId).first();
+  const now = new Date().toISOString();
+  let data = null;
+  try {
+    data = send ? JSON.parse(send.data) : null;
+  } catch {
+    data = null;
+  }
+  if (
+    !send ||
+    !data ||
+    data.id !== fileId ||
+    send.disabled ||
+    send.deletion_date <= now ||
+    (send.expiration_date && send.expiration_date <= now) ||
+    (send.max_access_count !== null && send.access_count >= send.max_access_count)
+  ) {
+    return sendNotFound();
+  }
+
+  const storageKey = `sends/${sendId}/${fileId}`;
+
+  // Contents are encrypted client-side, so the stored content type is meaningless to recipients
+  const headers = new Headers();
+  headers.set("Content-Type", "application/octet-stream");
+
+  if (backend === "kv") {
+    const stream = await env.ATTACHMENTS_KV.get(storageKey, { type: "stream" });
+    if (!stream) {
+      return new Response(JSON.stringify({ error: "Send file not found in storage" }), {
+        status: 404,
+        headers: { "Content-Type": "application/json" },
+      });
+    }
+    if (!(await recordSendAccess(db, send))) {
+      await stream.cancel();
+      return sendNotFound();
+    }
+    headers.set("Content-Length", String(data.size));
+    return new Response(stream, { status: 200, headers });
+  }
+
+  const r2Object = await env.ATTACHMENTS_BUCKET.get(storageKey);
+  if (!r2Object) {
+    return new Response(JSON.stringify({ error: "Send file not found in storage" }), {
+      status: 404,
+      headers: { "Content-Type": "application/json" },
+    });
+  }
+  if (!(await recordSendAccess(db, send))) {
+    await r2Object.body.cancel();
+    return sendNotFound();
+  }
+  headers.set("Content-Length", r2Object.size.toString());
+
+  // Return response with R2 object body directly - zero-copy streaming
+  return new Response(r2Object.body, { status: 200, headers });
+}
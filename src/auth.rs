@@ -4,17 +4,27 @@ use axum::{
 };
 use chrono::Duration;
 use constant_time_eq::constant_time_eq;
-use jwt_compact::AlgorithmExt;
-use jwt_compact::{alg::Hs256Key, TimeOptions, UntrustedToken};
+use jwt_compact::{Claims as JwtClaims, TimeOptions, ValidationError};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use worker::Env;
 
-use crate::db;
-use crate::error::AppError;
+use crate::config::Settings;
+use crate::db::{self, Database};
+use crate::error::{db_error, AppError};
+use crate::logging::RequestContext;
+use crate::Env;
 
-pub(crate) const JWT_VALIDATION_LEEWAY_SECS: u64 = 60;
+pub mod device_verification;
+pub mod keys;
+pub mod revocation;
 
+use keys::KeyRing;
+
+/// Access token claims.
+///
+/// Official clients decode the access token and read these fields directly (e.g. `name` for the
+/// account switcher), so the names must match what the official server issues. Fields added after
+/// the initial release are `#[serde(default)]` so tokens issued by older versions still decode.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,    // User ID
@@ -24,14 +34,62 @@ pub struct Claims {
     pub name: String,
     pub email: String,
     pub email_verified: bool,
+    /// Authentication methods: `Application`, plus `mfa` when two-factor was used at login.
+    #[serde(default)]
     pub amr: Vec<String>,
+    /// Device identifier the token was issued to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Issuer, see [`token_issuer`]. Empty for tokens issued before it was introduced.
+    #[serde(default)]
+    pub iss: String,
+    /// Audience, see [`token_audience`].
+    #[serde(default)]
+    pub aud: String,
 }
 
-pub(crate) fn jwt_time_options() -> TimeOptions {
-    let leeway = Duration::seconds(JWT_VALIDATION_LEEWAY_SECS as i64);
+/// Leeway applied to `exp`/`nbf` (JWT_LEEWAY_SECS).
+pub(crate) fn jwt_time_options(settings: &Settings) -> TimeOptions {
+    let leeway = Duration::seconds(settings.jwt_leeway_secs as i64);
     TimeOptions::from_leeway(leeway)
 }
 
+/// Stands in for DOMAIN in issuers and audiences when it isn't set.
+const DEFAULT_TOKEN_DOMAIN: &str = "warden-worker";
+
+/// DOMAIN and BASE_PATH, which issuers and audiences start with. Never the URL of the request:
+/// its host is whatever the client sent.
+fn token_base(settings: &Settings) -> String {
+    let domain = settings.domain.as_deref().unwrap_or(DEFAULT_TOKEN_DOMAIN);
+    format!("{domain}{}", settings.base_path)
+}
+
+/// `iss` of tokens issued by this server's identity endpoint.
+pub(crate) fn token_issuer(settings: &Settings) -> String {
+    format!("{}/identity", token_base(settings))
+}
+
+/// `aud` of access tokens: this server's API.
+pub(crate) fn token_audience(settings: &Settings) -> String {
+    format!("{}/api", token_base(settings))
+}
+
+/// Checks `exp` and `nbf`, telling expired tokens apart from otherwise invalid ones so client
+/// logs show why a token was refused.
+pub(crate) fn validate_token_times<T>(
+    claims: &JwtClaims<T>,
+    time_options: &TimeOptions,
+) -> Result<(), AppError> {
+    let reject = |e: ValidationError| match e {
+        ValidationError::Expired => AppError::Unauthorized("Token has expired".to_string()),
+        ValidationError::NotMature => AppError::Unauthorized("Token is not yet valid".to_string()),
+        _ => AppError::Unauthorized("Invalid token".to_string()),
+    };
+    claims.validate_expiration(time_options).map_err(reject)?;
+    claims.validate_maturity(time_options).map_err(reject)?;
+    Ok(())
+}
+
 /// AuthUser extractor - provides (user_id, email) tuple
 pub struct AuthUser(
     pub String, // user_id
@@ -57,42 +115,85 @@ impl FromRequestParts<Arc<Env>> for Claims {
                     .map(|value| value.to_owned())
             })
             .ok_or_else(|| AppError::Unauthorized("Missing or invalid token".to_string()))?;
+        verify_access_token(parts, state, &token).await
+    }
+}
 
-        let secret = state.secret("JWT_SECRET")?;
-
-        // Decode and validate the token
-        let key = Hs256Key::new(secret.to_string().as_bytes());
-        let token = UntrustedToken::new(&token)
-            .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))?;
-        let token = jwt_compact::alg::Hs256
-            .validator::<Claims>(&key)
-            .validate(&token)
-            .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))?;
-        let time_options = jwt_time_options();
-        token
-            .claims()
-            .validate_expiration(&time_options)
-            .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))?;
-        token
-            .claims()
-            .validate_maturity(&time_options)
-            .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))?;
-        let claims = token.into_parts().1.custom;
-
-        let db = db::get_db(state)?;
-        let current_sstamp = db
-            .prepare("SELECT security_stamp FROM users WHERE id = ?1")
-            .bind(&[claims.sub.clone().into()])?
-            .first::<String>(Some("security_stamp"))
-            .await
-            .map_err(|_| AppError::Database)?
-            .ok_or_else(|| AppError::Unauthorized("Invalid token".to_string()))?;
-
-        if !constant_time_eq(claims.sstamp.as_bytes(), current_sstamp.as_bytes()) {
+/// Checks an access token the way every authenticated request is checked, and returns its
+/// claims.
+async fn verify_access_token(
+    parts: &Parts,
+    state: &Arc<Env>,
+    token: &str,
+) -> Result<Claims, AppError> {
+    // Decode and validate the token with the key selected by its `kid`
+    let token = KeyRing::access(state)?
+        .verify::<Claims>(token)
+        .ok_or_else(|| AppError::Unauthorized("Invalid token".to_string()))?;
+    let settings = Settings::get(state);
+    validate_token_times(token.claims(), &jwt_time_options(&settings))?;
+
+    // Only accept tokens this deployment issued for its own API, even if another service
+    // shares (or leaked) the signing secret. Tokens without an issuer, or issued before it came
+    // from DOMAIN (when it named the origin of the login request), fail here; clients then
+    // refresh them, or log in again if their refresh token fails the same check.
+    let custom = &token.claims().custom;
+    if !constant_time_eq(custom.iss.as_bytes(), token_issuer(&settings).as_bytes())
+        || !constant_time_eq(custom.aud.as_bytes(), token_audience(&settings).as_bytes())
+    {
+        return Err(AppError::Unauthorized(
+            "Invalid token issuer or audience".to_string(),
+        ));
+    }
+
+    let issued_at = token.claims().issued_at.map(|iat| iat.timestamp());
+    let claims = token.into_parts().1.custom;
+
+    if let (Some(device), Some(issued_at)) = (claims.device.as_deref(), issued_at) {
+        if revocation::is_device_token_revoked(state, &claims.sub, device, issued_at).await {
             return Err(AppError::Unauthorized("Invalid token".to_string()));
         }
+    }
+
+    let db = db::get_db(state)?;
+    let current_sstamp = db
+        .first_column::<String>(
+            "SELECT security_stamp FROM users WHERE id = ?1",
+            &[claims.sub.clone().into()],
+            "security_stamp",
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::Unauthorized("Invalid token".to_string()))?;
+
+    if !constant_time_eq(claims.sstamp.as_bytes(), current_sstamp.as_bytes()) {
+        return Err(AppError::Unauthorized("Invalid token".to_string()));
+    }
+
+    if let Some(context) = parts.extensions.get::<RequestContext>() {
+        context.set_user_id(&claims.sub);
+    }
+    Ok(claims)
+}
+
+/// Claims of an access token passed in the `access_token` query parameter, for WebSocket
+/// connections: browsers can't set headers on them.
+pub struct QueryTokenClaims(pub Claims);
 
-        Ok(claims)
+impl FromRequestParts<Arc<Env>> for QueryTokenClaims {
+    type Rejection = AppError;
+    #[worker::send]
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<Env>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = form_urlencoded::parse(parts.uri.query().unwrap_or_default().as_bytes())
+            .find(|(name, _)| name == "access_token")
+            .map(|(_, token)| token.into_owned())
+            .ok_or_else(|| AppError::Unauthorized("Missing or invalid token".to_string()))?;
+        Ok(QueryTokenClaims(
+            verify_access_token(parts, state, &token).await?,
+        ))
     }
 }
 
@@ -107,3 +208,283 @@ impl FromRequestParts<Arc<Env>> for AuthUser {
         Ok(AuthUser(claims.sub, claims.email))
     }
 }
+
+/// Guards the `/admin` endpoints with the `ADMIN_TOKEN` secret, passed as a bearer token.
+///
+/// When `ADMIN_TOKEN` is not configured the admin endpoints don't exist.
+pub struct AdminAuth;
+
+impl FromRequestParts<Arc<Env>> for AdminAuth {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<Env>,
+    ) -> Result<Self, Self::Rejection> {
+        let admin_token = Settings::get(state)
+            .admin_token
+            .clone()
+            .ok_or_else(|| AppError::NotFound("Not found".to_string()))?;
+
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|auth_header| auth_header.to_str().ok())
+            .and_then(|auth_value| auth_value.strip_prefix("Bearer "))
+            .ok_or_else(|| AppError::Unauthorized("Missing or invalid token".to_string()))?;
+
+        if !constant_time_eq(token.as_bytes(), admin_token.as_bytes()) {
+            return Err(AppError::Unauthorized("Invalid admin token".to_string()));
+        }
+
+        Ok(AdminAuth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::{testing, Env};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+
+    const EMAIL: &str = "alice@example.com";
+
+    /// A migrated env with `vars` set, where alice is a user.
+    fn env(vars: &[(&str, &str)]) -> Env {
+        let mut env = testing::env();
+        for (name, value) in vars {
+            env = env.with_var(name, value);
+        }
+        testing::user(&env, "alice");
+        env
+    }
+
+    /// An access token for alice naming `iss` as its issuer.
+    fn token_issued_by(env: &Env, iss: &str) -> String {
+        let settings = Settings::get(env);
+        let claims = JwtClaims::new(Claims {
+            sub: "alice".to_string(),
+            sstamp: "stamp".to_string(),
+            premium: true,
+            name: "Alice".to_string(),
+            email: EMAIL.to_string(),
+            email_verified: true,
+            amr: vec!["Application".to_string()],
+            device: None,
+            iss: iss.to_string(),
+            aud: token_audience(&settings),
+        })
+        .set_duration_and_issuance(&TimeOptions::default(), Duration::hours(1))
+        .set_not_before(chrono::Utc::now());
+        KeyRing::access(env).unwrap().sign(&claims).unwrap()
+    }
+
+    /// Status of an authenticated request sent to `origin`.
+    fn status(env: &Env, origin: &str, token: &str) -> StatusCode {
+        let req = Request::builder()
+            .uri(format!("{origin}/api/accounts/revision-date"))
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        testing::fetch(env, req).status()
+    }
+
+    #[test]
+    fn issuer_and_audience_come_from_domain_and_base_path() {
+        let env = env(&[
+            ("DOMAIN", "https://Vault.example.com/"),
+            ("BASE_PATH", "/vault"),
+        ]);
+        let settings = Settings::get(&env);
+
+        assert_eq!(
+            token_issuer(&settings),
+            "https://vault.example.com/vault/identity"
+        );
+        assert_eq!(
+            token_audience(&settings),
+            "https://vault.example.com/vault/api"
+        );
+    }
+
+    #[test]
+    fn issuer_has_a_default_without_domain() {
+        let env = env(&[]);
+
+        assert_eq!(token_issuer(&Settings::get(&env)), "warden-worker/identity");
+    }
+
+    #[test]
+    fn tokens_are_accepted_whatever_host_the_request_names() {
+        let env = env(&[("DOMAIN", "https://vault.example.com")]);
+        let token = testing::token(&env, EMAIL);
+
+        assert_eq!(
+            status(&env, "https://vault.example.com", &token),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&env, "https://other.example.net", &token),
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn tokens_from_another_issuer_are_refused() {
+        let env = env(&[("DOMAIN", "https://vault.example.com")]);
+        let ours = token_issued_by(&env, "https://vault.example.com/identity");
+        // A token minted for another host, as a client naming it in its request once got
+        let theirs = token_issued_by(&env, "https://other.example.net/identity");
+        // Tokens issued before `iss` existed
+        let none = token_issued_by(&env, "");
+
+        assert_eq!(
+            status(&env, "https://vault.example.com", &ours),
+            StatusCode::OK
+        );
+        for token in [theirs, none] {
+            assert_eq!(
+                status(&env, "https://other.example.net", &token),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+    }
+
+    /// A token that expires `exp` seconds from now and is valid from `nbf` seconds from now.
+    fn times(exp: i64, nbf: i64) -> JwtClaims<()> {
+        JwtClaims::new(())
+            .set_duration_and_issuance(&TimeOptions::default(), Duration::seconds(exp))
+            .set_not_before(chrono::Utc::now() + Duration::seconds(nbf))
+    }
+
+    /// What [`validate_token_times`] says of `claims` under the leeway of `env`.
+    fn validate(env: &Env, claims: &JwtClaims<()>) -> Result<(), String> {
+        validate_token_times(claims, &jwt_time_options(&Settings::get(env))).map_err(|e| match e {
+            AppError::Unauthorized(message) => message,
+            other => panic!("{other:?}"),
+        })
+    }
+
+    #[test]
+    fn expiry_and_maturity_are_checked_against_the_leeway() {
+        let env = env(&[("JWT_LEEWAY_SECS", "30")]);
+
+        assert_eq!(validate(&env, &times(-25, -60)), Ok(()));
+        assert_eq!(
+            validate(&env, &times(-35, -60)),
+            Err("Token has expired".to_string())
+        );
+        assert_eq!(validate(&env, &times(3600, 25)), Ok(()));
+        assert_eq!(
+            validate(&env, &times(3600, 35)),
+            Err("Token is not yet valid".to_string())
+        );
+    }
+
+    #[test]
+    fn leeway_defaults_to_a_minute_when_unset_or_invalid() {
+        for env in [env(&[]), env(&[("JWT_LEEWAY_SECS", "soon")])] {
+            assert_eq!(validate(&env, &times(-55, -60)), Ok(()));
+            assert_eq!(
+                validate(&env, &times(-65, -60)),
+                Err("Token has expired".to_string())
+            );
+            assert_eq!(validate(&env, &times(3600, 55)), Ok(()));
+            assert_eq!(
+                validate(&env, &times(3600, 65)),
+                Err("Token is not yet valid".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_claims_alongside_ones_it_doesnt_use() {
+        // Hand-written, with registered and OAuth claims this server doesn't read next to its own
+        let payload = r#"{
+            "nbf": 1714566896, "exp": 1714570496, "iss": "http://localhost|login",
+            "client_id": "web", "sub": "2b3a9c1e-5f4d-4c7a-9e21-6d0b8f3a1c55",
+            "auth_time": 1714566896, "idp": "bitwarden", "premium": false,
+            "email": "alice@example.com", "email_verified": true, "name": "Alice",
+            "sstamp": "0c8d5f5e-1a6b-4f0e-9d5c-2e7b3f9a4d81",
+            "device": "6f1c9e2a-3b7d-4a58-8c0e-91d2f4b6a7e3",
+            "scope": ["api", "offline_access"], "amr": ["Application"]
+        }"#;
+
+        let claims: Claims = serde_json::from_str(payload).unwrap();
+
+        assert_eq!(claims.sub, "2b3a9c1e-5f4d-4c7a-9e21-6d0b8f3a1c55");
+        assert_eq!(claims.sstamp, "0c8d5f5e-1a6b-4f0e-9d5c-2e7b3f9a4d81");
+        assert!(!claims.premium);
+        assert_eq!(claims.name, "Alice");
+        assert_eq!(claims.email, EMAIL);
+        assert!(claims.email_verified);
+        assert_eq!(claims.amr, ["Application"]);
+        assert_eq!(
+            claims.device.as_deref(),
+            Some("6f1c9e2a-3b7d-4a58-8c0e-91d2f4b6a7e3")
+        );
+    }
+
+    #[test]
+    fn decodes_tokens_issued_before_the_newer_claims() {
+        let payload = r#"{
+            "sub": "alice", "sstamp": "stamp", "premium": true, "name": "Alice",
+            "email": "alice@example.com", "email_verified": true
+        }"#;
+
+        let claims: Claims = serde_json::from_str(payload).unwrap();
+
+        assert!(claims.amr.is_empty());
+        assert_eq!(claims.device, None);
+        assert_eq!(claims.iss, "");
+        assert_eq!(claims.aud, "");
+    }
+
+    #[test]
+    fn issued_claims_use_the_names_clients_read() {
+        let claims = Claims {
+            sub: "alice".to_string(),
+            sstamp: "stamp".to_string(),
+            premium: true,
+            name: "Alice".to_string(),
+            email: EMAIL.to_string(),
+            email_verified: true,
+            amr: vec!["Application".to_string(), "mfa".to_string()],
+            device: Some("device".to_string()),
+            iss: "warden-worker/identity".to_string(),
+            aud: "warden-worker/api".to_string(),
+        };
+
+        let json = serde_json::to_value(&claims).unwrap();
+        let mut names: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "amr",
+                "aud",
+                "device",
+                "email",
+                "email_verified",
+                "iss",
+                "name",
+                "premium",
+                "sstamp",
+                "sub"
+            ]
+        );
+
+        let without_device = Claims {
+            device: None,
+            ..claims
+        };
+        let json = serde_json::to_value(&without_device).unwrap();
+        assert!(json.get("device").is_none());
+    }
+}
@@ -0,0 +1,144 @@
+//! New device verification (NEW_DEVICE_VERIFICATION).
+//!
+//! A password login from a device the user hasn't logged in from before is refused with the
+//! `new device verification required` error the clients recognize, and a 6-digit code is emailed
+//! to the user. The client then asks for the code and repeats the login with it as
+//! `newDeviceOtp`; once it matches, the login goes on and registers the device, which logs in
+//! without a code from then on.
+//!
+//! The code lives in a KV entry (`DEVICE_VERIFICATION_KV`) keyed by user, which expires after 10
+//! minutes and is dropped after 5 wrong guesses. Every login without a code replaces it. KV is
+//! eventually consistent, so the attempt count is a limit for a single client guessing in
+//! sequence rather than an exact one; the login rate limiter covers the rest.
+//!
+//! Users with two-step login enabled, logins with an approved auth request and a user's very
+//! first device are exempt, as is every login while mail or the KV namespace isn't configured.
+
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use worker::KvStore;
+
+use crate::Env;
+use crate::{
+    config::Settings,
+    crypto::{ct_eq, random_bytes},
+    error::{internal_error, AppError},
+    mail::{self, templates},
+    models::user::User,
+};
+
+const DEVICE_VERIFICATION_KV: &str = "DEVICE_VERIFICATION_KV";
+
+const CODE_TTL_MINUTES: i64 = 10;
+/// Wrong codes accepted before the code is dropped.
+const MAX_ATTEMPTS: u32 = 5;
+/// The shortest TTL KV accepts.
+const MIN_KV_TTL_SECS: i64 = 60;
+
+const VERIFICATION_REQUIRED: &str = "new device verification required";
+const INVALID_CODE: &str = "invalid new device otp";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingCode {
+    code: String,
+    /// Unix seconds.
+    expires_at: i64,
+    attempts: u32,
+}
+
+fn code_key(user_id: &str) -> String {
+    format!("new-device-otp:{}", user_id)
+}
+
+/// The KV namespace, when new device verification can be enforced at all.
+pub fn store(env: &Env, settings: &Settings) -> Option<KvStore> {
+    if !settings.new_device_verification || !matches!(settings.mail, Ok(Some(_))) {
+        return None;
+    }
+    match env.kv(DEVICE_VERIFICATION_KV) {
+        Ok(kv) => Some(kv),
+        Err(_) => {
+            log::warn!(
+                "NEW_DEVICE_VERIFICATION is on but {} is not bound; logins from new devices are not verified",
+                DEVICE_VERIFICATION_KV
+            );
+            None
+        }
+    }
+}
+
+/// Lets a password login from an unknown device through when `otp` is the code emailed to the
+/// user. Without a code, emails a new one and asks the client for it.
+pub async fn verify(
+    env: &Env,
+    kv: &KvStore,
+    user: &User,
+    otp: Option<&str>,
+) -> Result<(), AppError> {
+    let Some(otp) = otp.map(str::trim).filter(|otp| !otp.is_empty()) else {
+        send_code(env, kv, user).await?;
+        return Err(AppError::OAuth(
+            "invalid_grant",
+            VERIFICATION_REQUIRED.to_string(),
+        ));
+    };
+
+    let key = code_key(&user.id);
+    let pending: Option<PendingCode> = kv
+        .get(&key)
+        .json()
+        .await
+        .map_err(internal_error!("read new device code"))?;
+    let now = Utc::now().timestamp();
+    let Some(mut pending) = pending.filter(|pending| pending.expires_at > now) else {
+        return Err(AppError::OAuth("invalid_grant", INVALID_CODE.to_string()));
+    };
+
+    if ct_eq(&pending.code, otp) {
+        kv.delete(&key)
+            .await
+            .map_err(internal_error!("delete new device code"))?;
+        return Ok(());
+    }
+
+    pending.attempts += 1;
+    let result = if pending.attempts >= MAX_ATTEMPTS {
+        kv.delete(&key).await
+    } else {
+        let ttl = (pending.expires_at - now).max(MIN_KV_TTL_SECS);
+        let value = serde_json::to_string(&pending).map_err(internal_error!())?;
+        match kv.put(&key, value) {
+            Ok(put) => put.expiration_ttl(ttl as u64).execute().await,
+            Err(e) => Err(e),
+        }
+    };
+    if let Err(e) = result {
+        log::error!("Failed to count a wrong new device code: {:?}", e);
+    }
+    Err(AppError::OAuth("invalid_grant", INVALID_CODE.to_string()))
+}
+
+/// Stores a fresh code for the user, replacing any earlier one, and emails it.
+async fn send_code(env: &Env, kv: &KvStore, user: &User) -> Result<(), AppError> {
+    let random = random_bytes(4)?;
+    let number = u32::from_le_bytes([random[0], random[1], random[2], random[3]]);
+    let code = format!("{:06}", number % 1_000_000);
+
+    let valid_for = Duration::minutes(CODE_TTL_MINUTES);
+    let pending = PendingCode {
+        code,
+        expires_at: (Utc::now() + valid_for).timestamp(),
+        attempts: 0,
+    };
+    let value = serde_json::to_string(&pending).map_err(internal_error!())?;
+    kv.put(&code_key(&user.id), value)
+        .map_err(internal_error!("store new device code"))?
+        .expiration_ttl(valid_for.num_seconds() as u64)
+        .execute()
+        .await
+        .map_err(internal_error!("store new device code"))?;
+
+    let message = templates::new_device_verification(&pending.code, valid_for).to(&user.email);
+    mail::send(env, &message).await?;
+    Ok(())
+}
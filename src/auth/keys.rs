@@ -0,0 +1,235 @@
+//! JWT signing keyring with `kid`-based key selection.
+//!
+//! Each token family (access / refresh) is backed by a primary secret that signs new tokens plus
+//! an optional list of previous secrets that are still accepted for verification. This lets an
+//! operator rotate a secret without logging out every device:
+//!
+//! 1. Move the current value of `JWT_SECRET` into `JWT_PREVIOUS_SECRETS`.
+//! 2. Set `JWT_SECRET` to the new value.
+//! 3. Once the longest-lived token signed by the old secret has expired, drop it from the list.
+//!
+//! Env format (all values are read via `env.secret`):
+//! - `JWT_SECRET` / `JWT_REFRESH_SECRET`: primary secret (required).
+//! - `JWT_PREVIOUS_SECRETS` / `JWT_REFRESH_PREVIOUS_SECRETS`: optional, previous secrets separated
+//!   by commas and/or whitespace, e.g. `old-secret-1,old-secret-2`.
+//!
+//! The `kid` of a secret is derived from it (first 16 hex chars of its SHA-256 digest), so no
+//! separate key id has to be configured and the same secret always maps to the same `kid`.
+//! Tokens carrying an unknown `kid` are rejected. Tokens without a `kid` (issued before key ids
+//! were introduced) are checked against every configured key.
+
+use jwt_compact::{
+    alg::{Hs256, Hs256Key},
+    AlgorithmExt, Claims as JwtClaims, Header, Token, UntrustedToken,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::AppError;
+use crate::Env;
+
+const ACCESS_SECRET: &str = "JWT_SECRET";
+const ACCESS_PREVIOUS_SECRETS: &str = "JWT_PREVIOUS_SECRETS";
+const REFRESH_SECRET: &str = "JWT_REFRESH_SECRET";
+const REFRESH_PREVIOUS_SECRETS: &str = "JWT_REFRESH_PREVIOUS_SECRETS";
+
+/// Length (in hex chars) of the fingerprint used as `kid`.
+const KID_LEN: usize = 16;
+
+struct SigningKey {
+    kid: String,
+    key: Hs256Key,
+}
+
+impl SigningKey {
+    fn new(secret: &str) -> Self {
+        let digest = Sha256::digest(secret.as_bytes());
+        let mut kid = hex::encode(digest);
+        kid.truncate(KID_LEN);
+        Self {
+            kid,
+            key: Hs256Key::new(secret.as_bytes()),
+        }
+    }
+}
+
+/// Primary signing key plus previous keys still accepted for verification.
+pub struct KeyRing {
+    primary: SigningKey,
+    previous: Vec<SigningKey>,
+}
+
+impl KeyRing {
+    /// Keyring for access tokens (and other tokens signed with `JWT_SECRET`).
+    pub fn access(env: &Env) -> Result<Self, AppError> {
+        Self::from_env(env, ACCESS_SECRET, ACCESS_PREVIOUS_SECRETS)
+    }
+
+    /// Keyring for refresh tokens.
+    pub fn refresh(env: &Env) -> Result<Self, AppError> {
+        Self::from_env(env, REFRESH_SECRET, REFRESH_PREVIOUS_SECRETS)
+    }
+
+    fn from_env(env: &Env, primary_name: &str, previous_name: &str) -> Result<Self, AppError> {
+        let primary = env.secret(primary_name)?.to_string();
+        let previous = env
+            .secret(previous_name)
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        Ok(Self::from_secrets(&primary, &previous))
+    }
+
+    fn from_secrets(primary: &str, previous: &str) -> Self {
+        let primary = SigningKey::new(primary);
+        let previous = previous
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(SigningKey::new)
+            .filter(|k| k.kid != primary.kid)
+            .collect();
+        Self { primary, previous }
+    }
+
+    /// Signs `claims` with the primary key, stamping its `kid` into the header.
+    pub fn sign<T: Serialize>(&self, claims: &JwtClaims<T>) -> Result<String, AppError> {
+        let header = Header::empty().with_key_id(self.primary.kid.clone());
+        Hs256
+            .token(&header, claims, &self.primary.key)
+            .map_err(|_| AppError::Crypto("Failed to sign token".to_string()))
+    }
+
+    /// Verifies the token signature with the key selected by its `kid`.
+    ///
+    /// Only the signature is checked here; callers still validate expiration/maturity.
+    /// Returns `None` for malformed tokens, unknown `kid`s and bad signatures.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Option<Token<T>> {
+        let token = UntrustedToken::new(token).ok()?;
+        match token.header().key_id.as_deref() {
+            Some(kid) => {
+                let Some(key) = self.find(kid) else {
+                    log::warn!("Rejecting JWT with unknown kid '{}'", kid);
+                    return None;
+                };
+                Hs256.validator::<T>(&key.key).validate(&token).ok()
+            }
+            None => self
+                .keys()
+                .find_map(|key| Hs256.validator::<T>(&key.key).validate(&token).ok()),
+        }
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &SigningKey> {
+        std::iter::once(&self.primary).chain(self.previous.iter())
+    }
+
+    fn find(&self, kid: &str) -> Option<&SigningKey> {
+        self.keys().find(|k| k.kid == kid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use jwt_compact::TimeOptions;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct TestClaims {
+        sub: String,
+    }
+
+    fn claims() -> JwtClaims<TestClaims> {
+        JwtClaims::new(TestClaims {
+            sub: "alice".to_string(),
+        })
+        .set_duration_and_issuance(&TimeOptions::default(), Duration::hours(1))
+    }
+
+    /// A token signed with `secret` and no `kid`, as issued before key ids existed.
+    fn token_without_kid(secret: &str) -> String {
+        Hs256
+            .token(
+                &Header::empty(),
+                &claims(),
+                &Hs256Key::new(secret.as_bytes()),
+            )
+            .unwrap()
+    }
+
+    fn subject(ring: &KeyRing, token: &str) -> Option<String> {
+        ring.verify::<TestClaims>(token)
+            .map(|token| token.claims().custom.sub.clone())
+    }
+
+    #[test]
+    fn verifies_its_own_tokens() {
+        let ring = KeyRing::from_secrets("current", "");
+        let token = ring.sign(&claims()).unwrap();
+
+        assert_eq!(subject(&ring, &token).as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn tokens_signed_with_a_previous_key_still_verify() {
+        let old = KeyRing::from_secrets("old", "");
+        let token = old.sign(&claims()).unwrap();
+        let rotated = KeyRing::from_secrets("new", "older, old");
+
+        assert_eq!(subject(&rotated, &token).as_deref(), Some("alice"));
+        // Once dropped from the previous secrets, the old key's tokens are refused
+        assert_eq!(
+            subject(&KeyRing::from_secrets("new", "older"), &token),
+            None
+        );
+    }
+
+    #[test]
+    fn tokens_with_an_unknown_kid_are_rejected() {
+        let token = KeyRing::from_secrets("elsewhere", "")
+            .sign(&claims())
+            .unwrap();
+
+        assert_eq!(
+            subject(&KeyRing::from_secrets("current", "old"), &token),
+            None
+        );
+    }
+
+    #[test]
+    fn a_known_kid_with_another_key_is_rejected() {
+        let ring = KeyRing::from_secrets("current", "");
+        let header = Header::empty().with_key_id(ring.primary.kid.clone());
+        let forged = Hs256
+            .token(&header, &claims(), &Hs256Key::new(b"forger"))
+            .unwrap();
+
+        assert_eq!(subject(&ring, &forged), None);
+    }
+
+    #[test]
+    fn tokens_without_a_kid_fall_back_to_the_configured_keys() {
+        let ring = KeyRing::from_secrets("current", "old");
+
+        assert_eq!(
+            subject(&ring, &token_without_kid("current")).as_deref(),
+            Some("alice")
+        );
+        assert_eq!(
+            subject(&ring, &token_without_kid("old")).as_deref(),
+            Some("alice")
+        );
+        assert_eq!(subject(&ring, &token_without_kid("elsewhere")), None);
+    }
+
+    #[test]
+    fn kids_are_derived_from_the_secret() {
+        let ring = KeyRing::from_secrets("current", "current,  old,,");
+
+        assert_eq!(ring.primary.kid.len(), KID_LEN);
+        assert_eq!(ring.primary.kid, SigningKey::new("current").kid);
+        // The primary secret isn't repeated among the previous ones
+        assert_eq!(ring.previous.len(), 1);
+        assert_eq!(ring.previous[0].kid, SigningKey::new("old").kid);
+    }
+}
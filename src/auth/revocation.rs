@@ -0,0 +1,69 @@
+//! Per-device access token revocation.
+//!
+//! Access tokens are stateless, so revoking a device only kills its refresh token by itself; the
+//! access token it already holds would stay valid for up to an hour. To close that window the
+//! revocation time is written to a short-lived KV entry (`REVOCATION_KV`) keyed by user and device,
+//! and the `Claims` extractor rejects tokens for that device issued at or before that time.
+//!
+//! The entry only needs to outlive the longest access token, so it expires on its own. When the
+//! KV namespace is not bound, revocation still takes effect at the next refresh.
+
+use crate::config::MAX_JWT_LEEWAY_SECS;
+use crate::Env;
+
+const REVOCATION_KV: &str = "REVOCATION_KV";
+
+/// Access token lifetime plus the largest allowed validation leeway.
+const DENYLIST_TTL_SECS: u64 = 3600 + MAX_JWT_LEEWAY_SECS;
+
+fn denylist_key(user_id: &str, device_identifier: &str) -> String {
+    format!("revoked-device:{}:{}", user_id, device_identifier)
+}
+
+/// Denies access tokens issued to `device_identifier` up to now.
+pub async fn revoke_device_tokens(env: &Env, user_id: &str, device_identifier: &str) {
+    let Ok(kv) = env.kv(REVOCATION_KV) else {
+        log::warn!(
+            "{} is not bound; access tokens stay valid until expiry",
+            REVOCATION_KV
+        );
+        return;
+    };
+
+    let revoked_at = chrono::Utc::now().timestamp().to_string();
+    let result = match kv.put(&denylist_key(user_id, device_identifier), revoked_at) {
+        Ok(put) => put.expiration_ttl(DENYLIST_TTL_SECS).execute().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        log::error!("Failed to write device revocation: {:?}", e);
+    }
+}
+
+/// Whether a token for `device_identifier` issued at `issued_at` (unix seconds) was revoked.
+pub async fn is_device_token_revoked(
+    env: &Env,
+    user_id: &str,
+    device_identifier: &str,
+    issued_at: i64,
+) -> bool {
+    let Ok(kv) = env.kv(REVOCATION_KV) else {
+        return false;
+    };
+
+    match kv
+        .get(&denylist_key(user_id, device_identifier))
+        .text()
+        .await
+    {
+        Ok(Some(revoked_at)) => revoked_at
+            .parse::<i64>()
+            .map(|revoked_at| issued_at <= revoked_at)
+            .unwrap_or(true),
+        Ok(None) => false,
+        Err(e) => {
+            log::error!("Failed to read device revocation: {:?}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,855 @@
+//! Typed settings read from the Worker's vars and secrets.
+//!
+//! Every knob is parsed here, once per isolate (vars and secrets only change with a new
+//! deployment, which starts new isolates). Handlers get the [`Settings`] as an extension instead
+//! of reading `Env`. Values that can't be used are kept as an [`InvalidSetting`] and reported
+//! when the feature relying on them is used, so one typo doesn't take the whole server down.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+use log::LevelFilter;
+
+use crate::crypto::MIN_SERVER_PBKDF2_ITERATIONS;
+use crate::db::BatchLimits;
+use crate::error::AppError;
+use crate::Env;
+
+const DEFAULT_IMPORT_BATCH_SIZE: usize = 30;
+const DEFAULT_IMPORT_MAX_ITEMS: usize = 5000;
+const DEFAULT_IMPORT_MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+const DEFAULT_MAX_BODY_BYTES: usize = 5 * 1024 * 1024;
+const DEFAULT_AUTH_MAX_BODY_BYTES: usize = 64 * 1024;
+const DEFAULT_JWT_LEEWAY_SECS: u64 = 60;
+/// Upper bound for `JWT_LEEWAY_SECS`, so a typo cannot extend token lifetimes indefinitely.
+pub(crate) const MAX_JWT_LEEWAY_SECS: u64 = 300;
+const DEFAULT_DB_RETRY_ATTEMPTS: u32 = 3;
+/// Upper bound for `DB_RETRY_ATTEMPTS`, so a typo cannot hold requests for minutes.
+const MAX_DB_RETRY_ATTEMPTS: u32 = 10;
+const DEFAULT_DB_RETRY_DELAY_MS: u64 = 100;
+const DEFAULT_ATTACHMENT_TTL_SECS: i64 = 300; // 5 minutes
+const DEFAULT_SEND_FILE_MAX_BYTES: i64 = 100 * 1024 * 1024; // 100 MiB
+const DEFAULT_TRASH_AUTO_DELETE_DAYS: i64 = 30;
+const DEFAULT_EVENTS_RETENTION_DAYS: i64 = 90;
+const DEFAULT_BACKUP_RETENTION_DAYS: i64 = 30;
+const DEFAULT_ICON_CACHE_TTL_SECS: usize = 30 * 24 * 60 * 60;
+const DEFAULT_ICON_CACHE_NEGTTL_SECS: usize = 3 * 24 * 60 * 60;
+const DEFAULT_SERVER_NAME: &str = "Vaultwarden";
+const DEFAULT_SERVER_URL: &str = "https://github.com/dani-garcia/vaultwarden";
+const DEFAULT_PUSH_RELAY_URI: &str = "https://push.bitwarden.com";
+const DEFAULT_PUSH_IDENTITY_URI: &str = "https://identity.bitwarden.com";
+
+/// A configured value that can't be used, reported as a 500 by the features that need it.
+#[derive(Debug, Clone)]
+pub struct InvalidSetting {
+    pub name: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {}: {}", self.name, self.reason)
+    }
+}
+
+impl From<InvalidSetting> for AppError {
+    fn from(err: InvalidSetting) -> Self {
+        AppError::Internal(Some(err.to_string()))
+    }
+}
+
+pub type Setting<T> = Result<T, InvalidSetting>;
+
+/// Push relay credentials (PUSH_INSTALLATION_ID/KEY) and endpoints.
+#[derive(Debug, Clone)]
+pub struct PushRelaySettings {
+    pub installation_id: String,
+    pub installation_key: String,
+    pub relay_uri: String,
+    pub identity_uri: String,
+}
+
+/// Raw VAPID key pair for Web Push; `push::web_push` validates the keys.
+#[derive(Debug, Clone)]
+pub struct WebPushSettings {
+    pub public_key: String,
+    pub private_key: String,
+    pub subject: Option<String>,
+}
+
+/// Outbound mail service selected by MAIL_PROVIDER.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailProvider {
+    MailChannels,
+    Resend,
+    SendGrid,
+    /// Keeps messages for the tests to look at ([`crate::mail::MockMailer`]).
+    #[cfg(test)]
+    Mock,
+}
+
+impl MailProvider {
+    pub fn name(self) -> &'static str {
+        match self {
+            MailProvider::MailChannels => "mailchannels",
+            MailProvider::Resend => "resend",
+            MailProvider::SendGrid => "sendgrid",
+            #[cfg(test)]
+            MailProvider::Mock => "mock",
+        }
+    }
+}
+
+/// Mail provider credentials (MAIL_API_KEY) and sender (MAIL_FROM, MAIL_FROM_NAME); see
+/// [`crate::mail`].
+#[derive(Debug, Clone)]
+pub struct MailSettings {
+    pub provider: MailProvider,
+    pub api_key: String,
+    pub from: String,
+    pub from_name: Option<String>,
+}
+
+/// Per-user object limits (MAX_CIPHERS_PER_USER, MAX_FOLDERS_PER_USER, MAX_SENDS_PER_USER);
+/// `None` when unset or `0`. Enforced by [`crate::quota`].
+#[derive(Debug, Clone, Default)]
+pub struct ObjectQuotas {
+    pub ciphers: Option<u64>,
+    pub folders: Option<u64>,
+    pub sends: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    // Accounts
+    /// ALLOWED_EMAILS: glob patterns of addresses allowed to register.
+    pub allowed_emails: Setting<Vec<String>>,
+    /// DISABLE_USER_REGISTRATION, advertised to clients; only "false" enables the signup UI.
+    pub disable_user_registration: bool,
+    /// PASSWORD_ITERATIONS, never below [`MIN_SERVER_PBKDF2_ITERATIONS`].
+    pub password_iterations: u32,
+    /// JWT_LEEWAY_SECS, at most [`MAX_JWT_LEEWAY_SECS`].
+    pub jwt_leeway_secs: u64,
+    /// Whether TOTP codes of the neighbouring time steps are accepted
+    /// (AUTHENTICATOR_DISABLE_TIME_DRIFT turns it off).
+    pub allow_totp_drift: bool,
+    /// NEW_DEVICE_VERIFICATION: password logins from unknown devices need a code emailed to the
+    /// user. See [`crate::auth::device_verification`].
+    pub new_device_verification: bool,
+    pub admin_token: Option<String>,
+    pub hibp_api_key: Option<String>,
+
+    // Organizations
+    pub org_groups_enabled: bool,
+    pub org_invite_links: bool,
+
+    // Sync and import
+    pub ciphers_default_row_query: bool,
+    pub sync_response_prealloc_bytes: Option<usize>,
+    /// IMPORT_BATCH_SIZE: statements per batch for deletes; `0` disables batching.
+    pub batch_size: usize,
+    pub import_batch_limits: BatchLimits,
+    /// IMPORT_MAX_ITEMS; `0` lifts the limit.
+    pub import_max_items: usize,
+    /// IMPORT_MAX_BODY_BYTES; `usize::MAX` when set to `0`.
+    pub import_max_body_bytes: usize,
+
+    pub object_quotas: ObjectQuotas,
+
+    // Database
+    /// DB_RETRY_ATTEMPTS: attempts of a retried D1 call, the first one included; `1` disables
+    /// retries.
+    pub db_retry_attempts: u32,
+    /// DB_RETRY_DELAY_MS: base wait before the first retry, doubled for each further one.
+    pub db_retry_delay_ms: u64,
+
+    // Attachments and sends
+    pub attachment_ttl_secs: Setting<i64>,
+    pub attachment_max_bytes: Setting<Option<u64>>,
+    /// ATTACHMENT_TOTAL_LIMIT_KB, in bytes.
+    pub attachment_total_limit_bytes: Setting<Option<u64>>,
+    pub send_file_max_bytes: Setting<i64>,
+
+    // Maintenance
+    /// AUTO_MIGRATE: apply pending schema migrations on the first request of each isolate.
+    pub auto_migrate: bool,
+    pub trash_auto_delete_days: i64,
+    /// EVENTS_RETENTION_DAYS; `0` or less keeps events forever.
+    pub events_retention_days: i64,
+    /// BACKUP_RETENTION_DAYS: age past which scheduled backups are deleted from BACKUP_BUCKET;
+    /// `0` or less keeps them forever.
+    pub backup_retention_days: i64,
+
+    // Icons
+    /// The service icons are delegated to, with `{}` standing for the domain. `None` when icons
+    /// are fetched by the Worker itself.
+    pub icon_service: Option<String>,
+    pub icon_cache_ttl: usize,
+    pub icon_cache_negttl: usize,
+
+    // Server
+    pub server_name: String,
+    pub server_url: String,
+    pub notifications_url: String,
+    /// DOMAIN clients reach the server at, like `https://vault.example.com`, without BASE_PATH.
+    pub domain: Option<String>,
+    /// BASE_PATH every route is mounted under, like `/vault`; empty at the domain root.
+    pub base_path: String,
+    /// FEATURE_FLAGS overrides, in order.
+    pub feature_flags: Vec<(String, bool)>,
+    pub web_vault_enabled: bool,
+    pub api_docs_ui: bool,
+    /// DEV_SEED: routes `POST /dev/seed`, which creates a test account. Local development only.
+    pub dev_seed: bool,
+    /// ALLOWED_ORIGINS, lowercased without trailing slashes.
+    pub allowed_origins: Vec<String>,
+    pub log_level: LevelFilter,
+    /// MAX_BODY_BYTES: largest request body of the routes without a limit of their own;
+    /// `usize::MAX` when set to `0`.
+    pub max_body_bytes: usize,
+    /// AUTH_MAX_BODY_BYTES: largest request body of the unauthenticated identity endpoints.
+    pub auth_max_body_bytes: usize,
+
+    // Push
+    pub push_relay: Option<PushRelaySettings>,
+    pub web_push: Option<WebPushSettings>,
+
+    // Mail
+    /// `None` when MAIL_PROVIDER is unset: mail is logged instead of sent.
+    pub mail: Setting<Option<MailSettings>>,
+}
+
+thread_local! {
+    static SETTINGS: RefCell<Option<Arc<Settings>>> = const { RefCell::new(None) };
+}
+
+impl Settings {
+    /// The deployment's settings, read from `env` on first use in this isolate.
+    pub fn get(env: &Env) -> Arc<Settings> {
+        SETTINGS.with(|cached| {
+            cached
+                .borrow_mut()
+                .get_or_insert_with(|| Arc::new(Settings::from_env(env)))
+                .clone()
+        })
+    }
+
+    fn from_env(env: &Env) -> Settings {
+        let import_batch_size = usize_var(env, "IMPORT_BATCH_SIZE");
+        let import_max_body_bytes =
+            body_limit(env, "IMPORT_MAX_BODY_BYTES").unwrap_or(DEFAULT_IMPORT_MAX_BODY_BYTES);
+
+        Settings {
+            allowed_emails: secret(env, "ALLOWED_EMAILS")
+                .map(|raw| {
+                    raw.split(',')
+                        .map(|pattern| pattern.trim().to_string())
+                        .collect()
+                })
+                .ok_or_else(|| InvalidSetting {
+                    name: "ALLOWED_EMAILS",
+                    reason: "the secret is not set".to_string(),
+                }),
+            disable_user_registration: var(env, "DISABLE_USER_REGISTRATION")
+                .is_none_or(|value| value.to_lowercase() != "false"),
+            password_iterations: password_iterations(env),
+            jwt_leeway_secs: var(env, "JWT_LEEWAY_SECS")
+                .and_then(|value| value.parse::<u64>().ok())
+                .map(|secs| secs.min(MAX_JWT_LEEWAY_SECS))
+                .unwrap_or(DEFAULT_JWT_LEEWAY_SECS),
+            allow_totp_drift: !flag(env, "AUTHENTICATOR_DISABLE_TIME_DRIFT", false),
+            new_device_verification: flag(env, "NEW_DEVICE_VERIFICATION", false),
+            admin_token: secret(env, "ADMIN_TOKEN"),
+            hibp_api_key: secret(env, "HIBP_API_KEY"),
+
+            org_groups_enabled: flag(env, "ORG_GROUPS_ENABLED", false),
+            org_invite_links: flag(env, "ORG_INVITE_LINKS", false),
+
+            ciphers_default_row_query: flag(env, "CIPHERS_DEFAULT_ROW_QUERY", false),
+            sync_response_prealloc_bytes: var(env, "SYNC_RESPONSE_PREALLOC_BYTES").and_then(
+                |raw| match raw.parse::<usize>() {
+                    Ok(value) => Some(value),
+                    Err(err) => {
+                        log::warn!(
+                            "Invalid SYNC_RESPONSE_PREALLOC_BYTES='{}' ({}); ignoring",
+                            raw,
+                            err
+                        );
+                        None
+                    }
+                },
+            ),
+            batch_size: import_batch_size.unwrap_or(DEFAULT_IMPORT_BATCH_SIZE),
+            // A fixed IMPORT_BATCH_SIZE keeps import batches at that many statements; otherwise
+            // they are sized by their parameters, so many small items share a batch while large
+            // notes don't push one past D1's limits.
+            import_batch_limits: match import_batch_size {
+                Some(max_statements) => BatchLimits {
+                    max_statements,
+                    max_bytes: 0,
+                },
+                None => BatchLimits {
+                    max_statements: 100,
+                    max_bytes: 512 * 1024,
+                },
+            },
+            import_max_items: usize_var(env, "IMPORT_MAX_ITEMS")
+                .unwrap_or(DEFAULT_IMPORT_MAX_ITEMS),
+            import_max_body_bytes,
+            object_quotas: ObjectQuotas {
+                ciphers: quota(env, "MAX_CIPHERS_PER_USER"),
+                folders: quota(env, "MAX_FOLDERS_PER_USER"),
+                sends: quota(env, "MAX_SENDS_PER_USER"),
+            },
+
+            db_retry_attempts: var(env, "DB_RETRY_ATTEMPTS")
+                .and_then(|value| value.parse::<u32>().ok())
+                .map(|attempts| attempts.clamp(1, MAX_DB_RETRY_ATTEMPTS))
+                .unwrap_or(DEFAULT_DB_RETRY_ATTEMPTS),
+            db_retry_delay_ms: var(env, "DB_RETRY_DELAY_MS")
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_DB_RETRY_DELAY_MS),
+
+            attachment_ttl_secs: parsed(env, "ATTACHMENT_TTL_SECS", |raw| {
+                match raw.parse::<i64>() {
+                    Ok(ttl) if ttl > 0 => Ok(ttl),
+                    Ok(_) => Err(format!("'{raw}' must be positive")),
+                    Err(err) => Err(format!("'{raw}': {err}")),
+                }
+            })
+            .map(|ttl| ttl.unwrap_or(DEFAULT_ATTACHMENT_TTL_SECS)),
+            attachment_max_bytes: parsed(env, "ATTACHMENT_MAX_BYTES", |raw| {
+                raw.parse::<u64>().map_err(|err| format!("'{raw}': {err}"))
+            }),
+            attachment_total_limit_bytes: parsed(env, "ATTACHMENT_TOTAL_LIMIT_KB", |raw| {
+                let kb = raw
+                    .parse::<u64>()
+                    .map_err(|err| format!("'{raw}': {err}"))?;
+                kb.checked_mul(1024)
+                    .ok_or_else(|| format!("'{raw}' overflowed when converting to bytes"))
+            }),
+            send_file_max_bytes: parsed(env, "SEND_FILE_MAX_BYTES", |raw| {
+                raw.parse::<i64>().map_err(|err| format!("'{raw}': {err}"))
+            })
+            .map(|max| max.unwrap_or(DEFAULT_SEND_FILE_MAX_BYTES)),
+
+            auto_migrate: flag(env, "AUTO_MIGRATE", false),
+            trash_auto_delete_days: var(env, "TRASH_AUTO_DELETE_DAYS")
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(DEFAULT_TRASH_AUTO_DELETE_DAYS),
+            events_retention_days: var(env, "EVENTS_RETENTION_DAYS")
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(DEFAULT_EVENTS_RETENTION_DAYS),
+            backup_retention_days: var(env, "BACKUP_RETENTION_DAYS")
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(DEFAULT_BACKUP_RETENTION_DAYS),
+
+            icon_service: var(env, "ICON_SERVICE").and_then(|service| icon_service(&service)),
+            icon_cache_ttl: usize_var(env, "ICON_CACHE_TTL").unwrap_or(DEFAULT_ICON_CACHE_TTL_SECS),
+            icon_cache_negttl: usize_var(env, "ICON_CACHE_NEGTTL")
+                .unwrap_or(DEFAULT_ICON_CACHE_NEGTTL_SECS),
+
+            server_name: var(env, "SERVER_NAME").unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string()),
+            server_url: var(env, "SERVER_URL").unwrap_or_else(|| DEFAULT_SERVER_URL.to_string()),
+            notifications_url: var(env, "NOTIFICATIONS_URL").unwrap_or_default(),
+            domain: var(env, "DOMAIN")
+                .map(|domain| domain.trim().trim_end_matches('/').to_ascii_lowercase())
+                .filter(|domain| !domain.is_empty()),
+            base_path: var(env, "BASE_PATH")
+                .map(|path| base_path(&path))
+                .unwrap_or_default(),
+            feature_flags: var(env, "FEATURE_FLAGS")
+                .map(|raw| parse_feature_flags(&raw))
+                .unwrap_or_default(),
+            web_vault_enabled: flag(env, "WEB_VAULT_ENABLED", true),
+            api_docs_ui: flag(env, "API_DOCS_UI", false),
+            dev_seed: flag(env, "DEV_SEED", false),
+            allowed_origins: var(env, "ALLOWED_ORIGINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_ascii_lowercase())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+            log_level: var(env, "LOG_LEVEL")
+                .and_then(|value| value.parse::<LevelFilter>().ok())
+                .unwrap_or(LevelFilter::Info),
+            max_body_bytes: body_limit(env, "MAX_BODY_BYTES").unwrap_or(DEFAULT_MAX_BODY_BYTES),
+            auth_max_body_bytes: body_limit(env, "AUTH_MAX_BODY_BYTES")
+                .unwrap_or(DEFAULT_AUTH_MAX_BODY_BYTES),
+
+            push_relay: push_relay(env),
+            web_push: web_push(env),
+
+            mail: mail(env),
+        }
+    }
+}
+
+/// A var, trimmed; empty counts as unset.
+fn var(env: &Env, name: &str) -> Option<String> {
+    env.var(name)
+        .ok()
+        .map(|value| value.to_string().trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// A secret, trimmed; empty counts as unset.
+fn secret(env: &Env, name: &str) -> Option<String> {
+    env.secret(name)
+        .ok()
+        .map(|value| value.to_string().trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn usize_var(env: &Env, name: &str) -> Option<usize> {
+    var(env, name).and_then(|value| value.parse::<usize>().ok())
+}
+
+/// A request body size in bytes; `0` lifts the limit.
+fn body_limit(env: &Env, name: &str) -> Option<usize> {
+    usize_var(env, name).map(|max_bytes| match max_bytes {
+        0 => usize::MAX,
+        max_bytes => max_bytes,
+    })
+}
+
+/// A per-user object limit; `0` lifts it.
+fn quota(env: &Env, name: &str) -> Option<u64> {
+    var(env, name)
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|&max| max > 0)
+}
+
+/// `1`/`true`/`yes`/`on` (any case) enable a flag; any other value disables it.
+fn flag(env: &Env, name: &str, default: bool) -> bool {
+    var(env, name)
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(default)
+}
+
+/// An optional var that must parse when set.
+fn parsed<T>(
+    env: &Env,
+    name: &'static str,
+    parse: impl FnOnce(&str) -> Result<T, String>,
+) -> Setting<Option<T>> {
+    var(env, name)
+        .map(|raw| parse(&raw).map_err(|reason| InvalidSetting { name, reason }))
+        .transpose()
+}
+
+/// Per-user server-side PBKDF2 iterations (PASSWORD_ITERATIONS).
+///
+/// - Defaults to `MIN_SERVER_PBKDF2_ITERATIONS` (600k).
+/// - Can be increased via env var, but will never be allowed below the minimum.
+fn password_iterations(env: &Env) -> u32 {
+    let min = MIN_SERVER_PBKDF2_ITERATIONS;
+    let Some(raw) = var(env, "PASSWORD_ITERATIONS") else {
+        return min;
+    };
+    match raw.parse::<u32>() {
+        Ok(iter) if iter >= min => iter,
+        Ok(iter) => {
+            log::warn!(
+                "PASSWORD_ITERATIONS={} is below the minimum {}; clamping to {}",
+                iter,
+                min,
+                min
+            );
+            min
+        }
+        Err(err) => {
+            log::warn!(
+                "Invalid PASSWORD_ITERATIONS='{}' ({}); using minimum {}",
+                raw,
+                err,
+                min
+            );
+            min
+        }
+    }
+}
+
+/// `vault`, `/vault/` and `/vault` all mount the routes under `/vault`; `/` is the domain root.
+fn base_path(raw: &str) -> String {
+    match raw.trim_matches('/') {
+        "" => String::new(),
+        path => format!("/{path}"),
+    }
+}
+
+fn icon_service(service: &str) -> Option<String> {
+    match service {
+        "internal" => None,
+        "bitwarden" => Some("https://icons.bitwarden.net/{}/icon.png".to_string()),
+        "duckduckgo" => Some("https://icons.duckduckgo.com/ip3/{}.ico".to_string()),
+        "google" => Some("https://www.google.com/s2/favicons?domain={}&sz=32".to_string()),
+        template if template.contains("{}") => Some(template.to_string()),
+        template => {
+            log::warn!(
+                "ICON_SERVICE={template} has no {{}} placeholder; fetching icons internally"
+            );
+            None
+        }
+    }
+}
+
+/// Parses FEATURE_FLAGS (`flag=true,other-flag=false`). Malformed entries are skipped with a
+/// warning rather than failing the whole config.
+fn parse_feature_flags(raw: &str) -> Vec<(String, bool)> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(key, value)| {
+                let key = key.trim();
+                let value = match value.trim().to_ascii_lowercase().as_str() {
+                    "1" | "true" | "yes" | "on" => true,
+                    "0" | "false" | "no" | "off" => false,
+                    _ => return None,
+                };
+                (!key.is_empty()).then(|| (key.to_string(), value))
+            });
+            if parsed.is_none() {
+                log::warn!("Ignoring malformed FEATURE_FLAGS entry: {entry}");
+            }
+            parsed
+        })
+        .collect()
+}
+
+fn push_relay(env: &Env) -> Option<PushRelaySettings> {
+    let uri = |name: &str, default: &str| {
+        var(env, name)
+            .map(|value| value.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| default.to_string())
+    };
+    Some(PushRelaySettings {
+        installation_id: secret(env, "PUSH_INSTALLATION_ID")?,
+        installation_key: secret(env, "PUSH_INSTALLATION_KEY")?,
+        relay_uri: uri("PUSH_RELAY_URI", DEFAULT_PUSH_RELAY_URI),
+        identity_uri: uri("PUSH_IDENTITY_URI", DEFAULT_PUSH_IDENTITY_URI),
+    })
+}
+
+fn web_push(env: &Env) -> Option<WebPushSettings> {
+    Some(WebPushSettings {
+        public_key: var(env, "WEB_PUSH_VAPID_PUBLIC_KEY")?,
+        private_key: secret(env, "WEB_PUSH_VAPID_PRIVATE_KEY")?,
+        subject: var(env, "WEB_PUSH_SUBJECT"),
+    })
+}
+
+fn mail(env: &Env) -> Setting<Option<MailSettings>> {
+    let Some(provider) = var(env, "MAIL_PROVIDER") else {
+        return Ok(None);
+    };
+    let provider = match provider.to_ascii_lowercase().as_str() {
+        "mailchannels" => MailProvider::MailChannels,
+        "resend" => MailProvider::Resend,
+        "sendgrid" => MailProvider::SendGrid,
+        #[cfg(test)]
+        "mock" => MailProvider::Mock,
+        _ => {
+            return Err(InvalidSetting {
+                name: "MAIL_PROVIDER",
+                reason: format!("'{provider}' is not mailchannels, resend or sendgrid"),
+            })
+        }
+    };
+    let api_key = secret(env, "MAIL_API_KEY").ok_or_else(|| InvalidSetting {
+        name: "MAIL_API_KEY",
+        reason: "the secret is not set".to_string(),
+    })?;
+    let from = var(env, "MAIL_FROM")
+        .filter(|from| from.contains('@'))
+        .ok_or_else(|| InvalidSetting {
+            name: "MAIL_FROM",
+            reason: "an email address is required when MAIL_PROVIDER is set".to_string(),
+        })?;
+    Ok(Some(MailSettings {
+        provider,
+        api_key,
+        from,
+        from_name: var(env, "MAIL_FROM_NAME"),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing;
+
+    fn flags(pairs: &[(&str, bool)]) -> Vec<(String, bool)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn feature_flags_parse_key_bool_pairs() {
+        assert_eq!(
+            parse_feature_flags("email-verification=false, new-ui = TRUE ,x=1,y=off,z=Yes"),
+            flags(&[
+                ("email-verification", false),
+                ("new-ui", true),
+                ("x", true),
+                ("y", false),
+                ("z", true),
+            ])
+        );
+        assert_eq!(parse_feature_flags(""), flags(&[]));
+        assert_eq!(parse_feature_flags(" , ,"), flags(&[]));
+    }
+
+    #[test]
+    fn malformed_feature_flags_are_skipped() {
+        assert_eq!(
+            parse_feature_flags("no-value,=true,bad=maybe,empty=,ok=true,a=b=c"),
+            flags(&[("ok", true)])
+        );
+    }
+
+    /// Settings of an environment with `vars` and `secrets`.
+    fn load(vars: &[(&str, &str)], secrets: &[(&str, &str)]) -> Settings {
+        let mut env = testing::bare();
+        for (name, value) in vars {
+            env = env.with_var(name, value);
+        }
+        for (name, value) in secrets {
+            env = env.with_secret(name, value);
+        }
+        Settings::from_env(&env)
+    }
+
+    #[track_caller]
+    fn invalid<T: fmt::Debug>(setting: Setting<T>) -> String {
+        setting.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn defaults_apply_when_nothing_is_set() {
+        let settings = load(&[], &[]);
+        assert!(settings.disable_user_registration);
+        assert_eq!(settings.password_iterations, MIN_SERVER_PBKDF2_ITERATIONS);
+        assert_eq!(settings.jwt_leeway_secs, DEFAULT_JWT_LEEWAY_SECS);
+        assert!(settings.allow_totp_drift);
+        assert_eq!(settings.batch_size, DEFAULT_IMPORT_BATCH_SIZE);
+        assert_eq!(settings.import_max_items, DEFAULT_IMPORT_MAX_ITEMS);
+        assert_eq!(
+            settings.import_max_body_bytes,
+            DEFAULT_IMPORT_MAX_BODY_BYTES
+        );
+        assert_eq!(settings.max_body_bytes, DEFAULT_MAX_BODY_BYTES);
+        assert_eq!(settings.db_retry_attempts, DEFAULT_DB_RETRY_ATTEMPTS);
+        assert_eq!(
+            settings.attachment_ttl_secs.unwrap(),
+            DEFAULT_ATTACHMENT_TTL_SECS
+        );
+        assert_eq!(settings.attachment_max_bytes.unwrap(), None);
+        assert_eq!(settings.object_quotas.ciphers, None);
+        assert_eq!(settings.server_name, DEFAULT_SERVER_NAME);
+        assert_eq!(settings.base_path, "");
+        assert_eq!(settings.domain, None);
+        assert!(settings.web_vault_enabled);
+        assert_eq!(settings.log_level, LevelFilter::Info);
+        assert!(settings.mail.unwrap().is_none());
+        assert!(settings.push_relay.is_none());
+        assert_eq!(
+            invalid(settings.allowed_emails),
+            "Invalid ALLOWED_EMAILS: the secret is not set"
+        );
+    }
+
+    #[test]
+    fn values_are_trimmed_and_empty_ones_unset() {
+        let settings = load(
+            &[("SERVER_NAME", "  Example  "), ("DOMAIN", "  ")],
+            &[("ALLOWED_EMAILS", " *@example.com , admin@example.org ")],
+        );
+        assert_eq!(settings.server_name, "Example");
+        assert_eq!(settings.domain, None);
+        assert_eq!(
+            settings.allowed_emails.unwrap(),
+            ["*@example.com", "admin@example.org"]
+        );
+    }
+
+    #[test]
+    fn flags_read_the_usual_spellings() {
+        for (value, enabled) in [
+            ("1", true),
+            ("TRUE", true),
+            ("yes", true),
+            ("On", true),
+            ("0", false),
+            ("false", false),
+            ("nope", false),
+        ] {
+            let settings = load(&[("ORG_GROUPS_ENABLED", value)], &[]);
+            assert_eq!(settings.org_groups_enabled, enabled, "{value}");
+        }
+        let settings = load(&[("AUTHENTICATOR_DISABLE_TIME_DRIFT", "true")], &[]);
+        assert!(!settings.allow_totp_drift);
+        // Only an explicit "false" opens registration
+        let settings = load(&[("DISABLE_USER_REGISTRATION", "False")], &[]);
+        assert!(!settings.disable_user_registration);
+    }
+
+    #[test]
+    fn numbers_are_clamped_to_safe_ranges() {
+        let settings = load(
+            &[
+                ("PASSWORD_ITERATIONS", "1000"),
+                ("JWT_LEEWAY_SECS", "86400"),
+                ("DB_RETRY_ATTEMPTS", "0"),
+            ],
+            &[],
+        );
+        assert_eq!(settings.password_iterations, MIN_SERVER_PBKDF2_ITERATIONS);
+        assert_eq!(settings.jwt_leeway_secs, MAX_JWT_LEEWAY_SECS);
+        assert_eq!(settings.db_retry_attempts, 1);
+
+        let settings = load(
+            &[
+                ("PASSWORD_ITERATIONS", "800000"),
+                ("DB_RETRY_ATTEMPTS", "50"),
+                ("JWT_LEEWAY_SECS", "soon"),
+            ],
+            &[],
+        );
+        assert_eq!(settings.password_iterations, 800_000);
+        assert_eq!(settings.db_retry_attempts, MAX_DB_RETRY_ATTEMPTS);
+        assert_eq!(settings.jwt_leeway_secs, DEFAULT_JWT_LEEWAY_SECS);
+    }
+
+    #[test]
+    fn zero_lifts_limits() {
+        let settings = load(
+            &[
+                ("MAX_BODY_BYTES", "0"),
+                ("IMPORT_MAX_BODY_BYTES", "0"),
+                ("MAX_CIPHERS_PER_USER", "0"),
+                ("MAX_FOLDERS_PER_USER", "20"),
+            ],
+            &[],
+        );
+        assert_eq!(settings.max_body_bytes, usize::MAX);
+        assert_eq!(settings.import_max_body_bytes, usize::MAX);
+        assert_eq!(settings.object_quotas.ciphers, None);
+        assert_eq!(settings.object_quotas.folders, Some(20));
+    }
+
+    #[test]
+    fn unusable_values_are_reported_by_name() {
+        let settings = load(
+            &[
+                ("ATTACHMENT_TTL_SECS", "-5"),
+                ("ATTACHMENT_MAX_BYTES", "lots"),
+                ("ATTACHMENT_TOTAL_LIMIT_KB", "18446744073709551615"),
+                ("SEND_FILE_MAX_BYTES", "1e6"),
+            ],
+            &[],
+        );
+        assert_eq!(
+            invalid(settings.attachment_ttl_secs),
+            "Invalid ATTACHMENT_TTL_SECS: '-5' must be positive"
+        );
+        assert!(invalid(settings.attachment_max_bytes)
+            .starts_with("Invalid ATTACHMENT_MAX_BYTES: 'lots'"));
+        assert_eq!(
+            invalid(settings.attachment_total_limit_bytes),
+            "Invalid ATTACHMENT_TOTAL_LIMIT_KB: '18446744073709551615' overflowed when converting to bytes"
+        );
+        assert!(invalid(settings.send_file_max_bytes).starts_with("Invalid SEND_FILE_MAX_BYTES"));
+
+        let settings = load(&[("ATTACHMENT_TOTAL_LIMIT_KB", "2048")], &[]);
+        assert_eq!(
+            settings.attachment_total_limit_bytes.unwrap(),
+            Some(2 * 1024 * 1024)
+        );
+    }
+
+    #[test]
+    fn mail_needs_a_known_provider_a_key_and_a_sender() {
+        let mail = |vars: &[(&str, &str)], secrets: &[(&str, &str)]| load(vars, secrets).mail;
+        assert_eq!(
+            invalid(mail(&[("MAIL_PROVIDER", "smtp")], &[])),
+            "Invalid MAIL_PROVIDER: 'smtp' is not mailchannels, resend or sendgrid"
+        );
+        assert_eq!(
+            invalid(mail(&[("MAIL_PROVIDER", "resend")], &[])),
+            "Invalid MAIL_API_KEY: the secret is not set"
+        );
+        assert_eq!(
+            invalid(mail(
+                &[("MAIL_PROVIDER", "resend"), ("MAIL_FROM", "vault")],
+                &[("MAIL_API_KEY", "key")]
+            )),
+            "Invalid MAIL_FROM: an email address is required when MAIL_PROVIDER is set"
+        );
+        let mail = mail(
+            &[
+                ("MAIL_PROVIDER", "SendGrid"),
+                ("MAIL_FROM", "vault@example.com"),
+            ],
+            &[("MAIL_API_KEY", "key")],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(mail.provider, MailProvider::SendGrid);
+        assert_eq!(mail.from, "vault@example.com");
+        assert_eq!(mail.from_name, None);
+    }
+
+    #[test]
+    fn paths_and_services_are_normalized() {
+        assert_eq!(base_path("vault"), "/vault");
+        assert_eq!(base_path("/vault/"), "/vault");
+        assert_eq!(base_path("/"), "");
+        assert_eq!(icon_service("internal"), None);
+        assert_eq!(
+            icon_service("duckduckgo").as_deref(),
+            Some("https://icons.duckduckgo.com/ip3/{}.ico")
+        );
+        assert_eq!(
+            icon_service("https://icons.example.com/{}.png").as_deref(),
+            Some("https://icons.example.com/{}.png")
+        );
+        assert_eq!(icon_service("https://icons.example.com/"), None);
+
+        let settings = load(
+            &[
+                ("DOMAIN", "https://Vault.Example.com/"),
+                (
+                    "ALLOWED_ORIGINS",
+                    "https://Web.Example.org/, ,http://localhost:8080",
+                ),
+            ],
+            &[],
+        );
+        assert_eq!(
+            settings.domain.as_deref(),
+            Some("https://vault.example.com")
+        );
+        assert_eq!(
+            settings.allowed_origins,
+            ["https://web.example.org", "http://localhost:8080"]
+        );
+    }
+
+    #[test]
+    fn import_batches_are_sized_by_bytes_unless_import_batch_size_is_set() {
+        let env = testing::bare;
+        let adaptive = Settings::from_env(&env()).import_batch_limits;
+        assert_eq!(
+            (adaptive.max_statements, adaptive.max_bytes),
+            (100, 512 * 1024)
+        );
+        let fixed =
+            Settings::from_env(&env().with_var("IMPORT_BATCH_SIZE", "30")).import_batch_limits;
+        assert_eq!((fixed.max_statements, fixed.max_bytes), (30, 0));
+    }
+}
@@ -36,7 +36,7 @@ fn get_crypto() -> Result<Crypto, AppError> {
 }
 
 /// Gets the SubtleCrypto interface from the global scope.
-fn subtle_crypto() -> Result<SubtleCrypto, AppError> {
+pub(crate) fn subtle_crypto() -> Result<SubtleCrypto, AppError> {
     Ok(get_crypto()?.subtle())
 }
 
@@ -62,13 +62,18 @@ pub fn pbkdf2_sha256(
 
 /// Generates a cryptographically secure random salt.
 pub fn generate_salt() -> Result<String, AppError> {
-    let crypto = get_crypto()?;
-    let salt = Uint8Array::new_with_length(PASSWORD_SALT_LENGTH as u32);
-    crypto
-        .get_random_values_with_array_buffer_view(&salt)
-        .map_err(|e| AppError::Crypto(format!("Failed to generate random salt: {:?}", e)))?;
+    Ok(BASE64.encode(random_bytes(PASSWORD_SALT_LENGTH as u32)?))
+}
+
+/// Fills a buffer of `len` cryptographically secure random bytes.
+///
+/// Through `getrandom`, which is `crypto.getRandomValues` on Workers and the OS natively.
+pub(crate) fn random_bytes(len: u32) -> Result<Vec<u8>, AppError> {
+    let mut bytes = vec![0u8; len as usize];
+    getrandom::fill(&mut bytes)
+        .map_err(|e| AppError::Crypto(format!("Failed to generate random bytes: {:?}", e)))?;
 
-    Ok(BASE64.encode(salt.to_vec()))
+    Ok(bytes)
 }
 
 /// Hashes the client-provided master password hash with server-side PBKDF2.
@@ -127,13 +132,7 @@ pub fn base32_encode(data: &[u8]) -> String {
 /// Generates a random TOTP secret (20 bytes = 160 bits).
 /// Returns the Base32 encoded secret.
 pub fn generate_totp_secret() -> Result<String, AppError> {
-    let crypto = get_crypto()?;
-    let secret = Uint8Array::new_with_length(20);
-    crypto
-        .get_random_values_with_array_buffer_view(&secret)
-        .map_err(|e| AppError::Crypto(format!("Failed to generate TOTP secret: {:?}", e)))?;
-
-    Ok(base32_encode(&secret.to_vec()))
+    Ok(base32_encode(&random_bytes(20)?))
 }
 
 /// Computes HMAC-SHA1 using Web Crypto API.
@@ -257,13 +256,7 @@ pub async fn validate_totp(
 
 /// Generates a recovery code (20 characters, Base32 encoded).
 pub fn generate_recovery_code() -> Result<String, AppError> {
-    let crypto = get_crypto()?;
-    let bytes = Uint8Array::new_with_length(20);
-    crypto
-        .get_random_values_with_array_buffer_view(&bytes)
-        .map_err(|e| AppError::Crypto(format!("Failed to generate recovery code: {:?}", e)))?;
-
-    Ok(base32_encode(&bytes.to_vec()))
+    Ok(base32_encode(&random_bytes(20)?))
 }
 
 /// Constant-time string comparison wrapper.
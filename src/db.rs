@@ -1,12 +1,152 @@
-use crate::error::AppError;
-use chrono::Utc;
+use crate::config::Settings;
+use crate::error::{db_error, AppError};
+use crate::logging::RequestContext;
+use crate::time;
+use crate::Env;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::future::Future;
+use std::ops::Range;
 use std::sync::Arc;
-use worker::{query, D1Database, D1PreparedStatement, Env, Error};
+use std::time::Duration;
+use worker::{wasm_bindgen::JsValue, D1Database, D1PreparedStatement, Error};
 
-pub fn get_db(env: &Arc<Env>) -> Result<D1Database, AppError> {
+#[cfg(feature = "native-db")]
+pub mod native;
+#[cfg(all(test, feature = "native-db"))]
+mod parity;
+pub mod scoped;
+
+/// The database handlers run against: D1, or SQLite when running natively.
+#[cfg(not(feature = "native-db"))]
+pub type Db = D1Database;
+#[cfg(feature = "native-db")]
+pub type Db = native::SqliteDatabase;
+
+/// A statement of [`Db`], prepared for a batch.
+pub type Statement = <Db as Database>::Statement;
+
+pub fn get_db(env: &Arc<Env>) -> Result<Db, AppError> {
     env.d1("vault1").map_err(AppError::Worker)
 }
 
+/// A bound parameter: strings, numbers, booleans and `null` bind as themselves; arrays and
+/// objects bind as their JSON text, for `json_each`.
+pub type Param = Value;
+
+/// The statements handlers run, independent of the database behind them.
+///
+/// SQL is SQLite's, with `?1`-style parameters, and rows deserialize by column name. Errors stay
+/// `worker::Error`, so [`classify_error`] and `db_error!` apply unchanged. Handlers only reach the
+/// database through this trait, on a [`Db`], so they don't depend on the backend.
+///
+/// Implemented for D1 and, behind the `native-db` feature, for SQLite (`db::native`); the checks in
+/// `db::parity` pin down the behaviour both have to share.
+///
+/// `D1Database` has inherent `prepare` and `batch` methods, which take precedence over the
+/// trait's: call those as `Database::prepare(&db, ..)` and `Database::batch(&db, ..)`.
+#[allow(async_fn_in_trait)]
+pub trait Database {
+    type Statement;
+
+    fn prepare(&self, sql: &str, params: &[Param]) -> Result<Self::Statement, Error>;
+
+    async fn first<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: &[Param],
+    ) -> Result<Option<T>, Error>;
+
+    async fn all<T: DeserializeOwned>(&self, sql: &str, params: &[Param]) -> Result<Vec<T>, Error>;
+
+    /// Runs a statement and returns how many rows it changed.
+    async fn run(&self, sql: &str, params: &[Param]) -> Result<usize, Error>;
+
+    /// Runs statements in one transaction and returns how many rows each changed.
+    async fn batch(&self, statements: Vec<Self::Statement>) -> Result<Vec<usize>, Error>;
+
+    /// The first column of every row, as text: for queries that build their JSON in SQL and
+    /// hand it out as is, without deserializing each row.
+    async fn texts(&self, sql: &str, params: &[Param]) -> Result<Vec<String>, Error>;
+
+    /// `column` of the first row, as D1's `first(Some(column))`: no row and NULL are `None`.
+    async fn first_column<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: &[Param],
+        column: &str,
+    ) -> Result<Option<T>, Error> {
+        let row: Option<serde_json::Map<String, Value>> = self.first(sql, params).await?;
+        match row.and_then(|mut row| row.remove(column)) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+        }
+    }
+}
+
+fn js_param(param: &Param) -> JsValue {
+    match param {
+        Value::Null => JsValue::NULL,
+        Value::Bool(value) => JsValue::from_bool(*value),
+        Value::Number(value) => JsValue::from_f64(value.as_f64().unwrap_or_default()),
+        Value::String(value) => JsValue::from_str(value),
+        Value::Array(_) | Value::Object(_) => JsValue::from_str(&param.to_string()),
+    }
+}
+
+impl Database for D1Database {
+    type Statement = D1PreparedStatement;
+
+    fn prepare(&self, sql: &str, params: &[Param]) -> Result<D1PreparedStatement, Error> {
+        let params: Vec<JsValue> = params.iter().map(js_param).collect();
+        D1Database::prepare(self, sql).bind(&params)
+    }
+
+    async fn first<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: &[Param],
+    ) -> Result<Option<T>, Error> {
+        Database::prepare(self, sql, params)?.first(None).await
+    }
+
+    async fn all<T: DeserializeOwned>(&self, sql: &str, params: &[Param]) -> Result<Vec<T>, Error> {
+        Database::prepare(self, sql, params)?.all().await?.results()
+    }
+
+    async fn run(&self, sql: &str, params: &[Param]) -> Result<usize, Error> {
+        let result = Database::prepare(self, sql, params)?.run().await?;
+        Ok(result.meta()?.and_then(|meta| meta.changes).unwrap_or(0))
+    }
+
+    async fn batch(&self, statements: Vec<D1PreparedStatement>) -> Result<Vec<usize>, Error> {
+        D1Database::batch(self, statements)
+            .await?
+            .iter()
+            .map(|result| Ok(result.meta()?.and_then(|meta| meta.changes).unwrap_or(0)))
+            .collect()
+    }
+
+    async fn texts(&self, sql: &str, params: &[Param]) -> Result<Vec<String>, Error> {
+        use worker::js_sys::Array;
+        use worker::wasm_bindgen::JsCast;
+
+        // Each raw row is a JS array of its columns
+        Database::prepare(self, sql, params)?
+            .raw_js_value()
+            .await?
+            .iter()
+            .map(|row| {
+                row.dyn_ref::<Array>()
+                    .and_then(|columns| columns.get(0).as_string())
+                    .ok_or_else(|| Error::RustError("first column is not text".to_string()))
+            })
+            .collect()
+    }
+}
+
 /// Map D1 JSON parsing errors to 400 while leaving other errors untouched.
 pub fn map_d1_json_error(err: Error) -> AppError {
     let msg = err.to_string();
@@ -17,28 +157,130 @@ pub fn map_d1_json_error(err: Error) -> AppError {
     }
 }
 
+/// A constraint a failed statement broke, as named in the SQLite error D1 passes on
+/// (`UNIQUE constraint failed: users.email: SQLITE_CONSTRAINT`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintViolation {
+    /// A `UNIQUE` or `PRIMARY KEY` constraint: the row already exists.
+    Unique,
+    /// A `FOREIGN KEY` constraint: the row refers to one that doesn't exist.
+    ForeignKey,
+}
+
+impl ConstraintViolation {
+    pub fn of(err: &Error) -> Option<Self> {
+        let msg = err.to_string();
+        if msg.contains("UNIQUE constraint failed") || msg.contains("PRIMARY KEY constraint failed")
+        {
+            Some(ConstraintViolation::Unique)
+        } else if msg.contains("FOREIGN KEY constraint failed") {
+            Some(ConstraintViolation::ForeignKey)
+        } else {
+            None
+        }
+    }
+}
+
+/// The error for a failed write of `entity` ("The folder"): a duplicate is a 409, a reference
+/// to a missing row a 400, and anything else stays a 500.
+pub fn classify_error(err: Error, entity: &str) -> AppError {
+    match ConstraintViolation::of(&err) {
+        Some(ConstraintViolation::Unique) => AppError::Conflict(format!("{entity} already exists")),
+        Some(ConstraintViolation::ForeignKey) => {
+            AppError::BadRequest(format!("{entity} refers to an item that doesn't exist"))
+        }
+        None => AppError::Worker(err),
+    }
+}
+
 /// Update the user's `updated_at` field to the current timestamp.
 /// This should be called after any operation that modifies user data (ciphers, folders, etc.)
-pub async fn touch_user_updated_at(db: &D1Database, user_id: &str) -> Result<(), AppError> {
-    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-    query!(
-        db,
+pub async fn touch_user_updated_at(db: &impl Database, user_id: &str) -> Result<(), AppError> {
+    let now = time::now_bw();
+    db.run(
         "UPDATE users SET updated_at = ?1 WHERE id = ?2",
-        now,
-        user_id
+        &[now.into(), user_id.into()],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await?;
+    .await
+    .map_err(db_error!())?;
     Ok(())
 }
 
+/// [`touch_user_updated_at`] as a statement, to go in the batch of the write it records.
+pub fn touch_user_updated_at_statement<D: Database>(
+    db: &D,
+    user_id: &str,
+) -> Result<D::Statement, AppError> {
+    db.prepare(
+        "UPDATE users SET updated_at = ?1 WHERE id = ?2",
+        &[time::now_bw().into(), user_id.into()],
+    )
+    .map_err(db_error!())
+}
+
+/// The statements of one logical write, run as a single batch so that they all apply or none
+/// does. Each is labelled with the table it writes: when D1 names the table in its error
+/// (`UNIQUE constraint failed: ciphers.id`), the log tells which statement failed.
+pub struct Batch<S> {
+    statements: Vec<(&'static str, S)>,
+}
+
+impl<S> Default for Batch<S> {
+    fn default() -> Self {
+        Batch {
+            statements: Vec::new(),
+        }
+    }
+}
+
+impl<S> Batch<S> {
+    pub fn push(&mut self, table: &'static str, statement: S) {
+        self.statements.push((table, statement));
+    }
+
+    pub fn extend(&mut self, table: &'static str, statements: impl IntoIterator<Item = S>) {
+        self.statements
+            .extend(statements.into_iter().map(|statement| (table, statement)));
+    }
+
+    /// Runs the batch. Errors are classified as by [`classify_error`], for `entity`.
+    pub async fn run<D: Database<Statement = S>>(
+        self,
+        db: &D,
+        entity: &str,
+    ) -> Result<Vec<usize>, AppError> {
+        let (tables, statements): (Vec<_>, Vec<_>) = self.statements.into_iter().unzip();
+        db.batch(statements).await.map_err(|err| {
+            let msg = err.to_string();
+            match failed_statement(&tables, &msg) {
+                Some(index) => log::error!(
+                    "Statement {index} ({}) of a batch of {} failed: {msg}",
+                    tables[index],
+                    tables.len()
+                ),
+                None => log::error!("A batch of {} statements failed: {msg}", tables.len()),
+            }
+            classify_error(err, entity)
+        })
+    }
+}
+
+/// Index of the first statement labelled with the table a D1 error names, if it names one.
+fn failed_statement(tables: &[&str], msg: &str) -> Option<usize> {
+    tables
+        .iter()
+        .position(|table| msg.contains(&format!(": {table}.")))
+}
+
 /// Execute D1 statements in batches, allowing batch_size 0 to run everything at once.
-pub async fn execute_in_batches(
-    db: &D1Database,
-    statements: Vec<D1PreparedStatement>,
+pub async fn execute_in_batches<D: Database>(
+    db: &D,
+    statements: Vec<D::Statement>,
     batch_size: usize,
-) -> Result<(), AppError> {
+) -> Result<(), AppError>
+where
+    D::Statement: Clone,
+{
     if statements.is_empty() {
         return Ok(());
     }
@@ -53,3 +295,288 @@ pub async fn execute_in_batches(
 
     Ok(())
 }
+
+/// How [`execute_in_batches_retrying`] groups statements: at most `max_statements` per batch,
+/// and at most `max_bytes` of estimated bound parameters (a larger statement gets a batch of its
+/// own). `0` lifts a limit.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchLimits {
+    pub max_statements: usize,
+    pub max_bytes: usize,
+}
+
+impl BatchLimits {
+    /// Splits statements of the given estimated sizes into consecutive batches.
+    pub fn plan(&self, sizes: &[usize]) -> Vec<Range<usize>> {
+        let max_statements = match self.max_statements {
+            0 => usize::MAX,
+            max => max,
+        };
+        let max_bytes = match self.max_bytes {
+            0 => usize::MAX,
+            max => max,
+        };
+        let mut batches = Vec::new();
+        let mut start = 0;
+        let mut bytes = 0usize;
+        for (index, size) in sizes.iter().enumerate() {
+            let full = index - start >= max_statements || bytes.saturating_add(*size) > max_bytes;
+            if index > start && full {
+                batches.push(start..index);
+                start = index;
+                bytes = 0;
+            }
+            bytes = bytes.saturating_add(*size);
+        }
+        if start < sizes.len() {
+            batches.push(start..sizes.len());
+        }
+        batches
+    }
+}
+
+/// Attempts per batch in [`execute_in_batches_retrying`], the first one included.
+const BATCH_ATTEMPTS: u32 = 3;
+/// Wait before the first retry; doubled for each further one.
+const BATCH_RETRY_DELAY_MS: u64 = 250;
+
+/// A batch that kept failing, after `changes` (one entry per statement of the batches that
+/// committed before it).
+pub struct BatchFailure {
+    pub changes: Vec<usize>,
+    pub error: AppError,
+}
+
+/// Like `execute_in_batches`, but returns how many rows each statement changed, in order, so
+/// callers can tell which `INSERT OR IGNORE`s were skipped.
+///
+/// A D1 batch is a transaction, so a failed one wrote nothing and is retried with backoff, unless
+/// it broke a constraint. When it keeps failing, the earlier batches stay committed and are reported in the [`BatchFailure`].
+///
+/// `sizes` holds the estimated size of each statement's parameters, for `limits`. `follow_up`,
+/// when given, ends every batch (and isn't reported in the changes), to keep derived data such
+/// as the quota counters in step with each committed batch.
+pub async fn execute_in_batches_retrying<D: Database>(
+    db: &D,
+    statements: Vec<D::Statement>,
+    sizes: &[usize],
+    limits: BatchLimits,
+    follow_up: Option<&D::Statement>,
+) -> Result<Vec<usize>, BatchFailure>
+where
+    D::Statement: Clone,
+{
+    let mut changes = Vec::with_capacity(statements.len());
+    if statements.is_empty() {
+        return Ok(changes);
+    }
+
+    for batch in limits.plan(sizes) {
+        let mut attempt = 1;
+        let results = loop {
+            let mut batch_statements = statements[batch.clone()].to_vec();
+            batch_statements.extend(follow_up.cloned());
+            match db.batch(batch_statements).await {
+                Ok(results) => break results,
+                // Broken constraints fail the same way every time
+                Err(err) if attempt < BATCH_ATTEMPTS && ConstraintViolation::of(&err).is_none() => {
+                    let delay = BATCH_RETRY_DELAY_MS << (attempt - 1);
+                    log::warn!("Batch failed (attempt {attempt}), retrying in {delay} ms: {err}");
+                    pause(Duration::from_millis(delay)).await;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    return Err(BatchFailure {
+                        changes,
+                        error: classify_error(err, "An item of the batch"),
+                    })
+                }
+            }
+        };
+        changes.extend(results.into_iter().take(batch.len()));
+    }
+
+    Ok(changes)
+}
+
+/// Waits out a retry's backoff: on a Workers timer, or by sleeping when running natively.
+async fn pause(delay: Duration) {
+    #[cfg(not(feature = "native-db"))]
+    worker::Delay::from(delay).await;
+    #[cfg(feature = "native-db")]
+    std::thread::sleep(delay);
+}
+
+/// Messages of D1 failures that say nothing about the statement and usually pass on their own:
+/// the database object restarting, being overloaded, or the connection to it dropping.
+const TRANSIENT_ERRORS: &[&str] = &[
+    "storage caused object to be reset",
+    "object to be reset",
+    "overloaded",
+    "Network connection lost",
+    "transient issue",
+    "internal error; reference",
+];
+
+/// Whether `err` is a D1 failure worth retrying as is.
+pub fn is_transient(err: &AppError) -> bool {
+    let msg = match err {
+        AppError::Worker(err) => err.to_string(),
+        AppError::Database(Some(msg)) => msg.clone(),
+        _ => return false,
+    };
+    TRANSIENT_ERRORS.iter().any(|pattern| msg.contains(pattern))
+}
+
+/// Retries D1 calls that failed with a [transient](is_transient) error, with jittered
+/// exponential backoff (DB_RETRY_ATTEMPTS, DB_RETRY_DELAY_MS).
+///
+/// Only wrap reads and writes that can safely run twice (`UPDATE`s setting fixed values,
+/// `INSERT OR IGNORE`, upserts): a plain `INSERT` whose response was lost may have committed,
+/// and running it again would duplicate the row or fail on its key.
+#[derive(Debug, Clone)]
+pub struct Retry {
+    attempts: u32,
+    delay_ms: u64,
+    request_id: Option<String>,
+}
+
+impl Retry {
+    pub fn new(settings: &Settings, request_id: Option<String>) -> Self {
+        Retry {
+            attempts: settings.db_retry_attempts.max(1),
+            delay_ms: settings.db_retry_delay_ms,
+            request_id,
+        }
+    }
+
+    /// Runs `operation` until it succeeds, fails with a non-transient error, or runs out of
+    /// attempts. `name` identifies it in the logs.
+    pub async fn run<T, F, Fut>(&self, name: &str, mut operation: F) -> Result<T, AppError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(err) if self.should_retry(name, attempt, &err).await => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// For callers that can't wrap the operation in a closure: whether attempt number `attempt`
+    /// of `name`, which failed with `err`, should be retried, after waiting for the backoff.
+    pub async fn should_retry(&self, name: &str, attempt: u32, err: &AppError) -> bool {
+        if attempt >= self.attempts || !is_transient(err) {
+            return false;
+        }
+        // Full jitter over the doubled base delay, so isolates hitting the same hiccup don't
+        // retry in lockstep.
+        let ceiling = self.delay_ms.saturating_mul(1 << (attempt - 1).min(16));
+        let delay = (js_sys::Math::random() * ceiling as f64) as u64;
+        log::warn!(
+            "{}",
+            json!({
+                "requestId": self.request_id,
+                "operation": name,
+                "attempt": attempt,
+                "delayMs": delay,
+                "error": format!("{err:?}"),
+            })
+        );
+        pause(Duration::from_millis(delay)).await;
+        true
+    }
+}
+
+impl FromRequestParts<Arc<Env>> for Retry {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<Env>,
+    ) -> Result<Self, Self::Rejection> {
+        let request_id = parts
+            .extensions
+            .get::<RequestContext>()
+            .map(|context| context.id().to_string());
+        Ok(Retry::new(&Settings::get(state), request_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KB: usize = 1024;
+
+    fn limits(max_statements: usize, max_bytes: usize) -> BatchLimits {
+        BatchLimits {
+            max_statements,
+            max_bytes,
+        }
+    }
+
+    #[test]
+    fn small_items_share_batches_up_to_the_statement_cap() {
+        let sizes = vec![300; 250];
+        assert_eq!(
+            limits(100, 512 * KB).plan(&sizes),
+            vec![0..100, 100..200, 200..250]
+        );
+    }
+
+    #[test]
+    fn large_items_split_batches_by_bytes() {
+        // Notes of 200 KiB: two fit under 512 KiB, a third doesn't
+        let sizes = vec![200 * KB; 5];
+        assert_eq!(limits(100, 512 * KB).plan(&sizes), vec![0..2, 2..4, 4..5]);
+
+        let mixed = [300, 300, 520 * KB, 300, 100 * KB, 300];
+        assert_eq!(limits(100, 512 * KB).plan(&mixed), vec![0..2, 2..3, 3..6]);
+    }
+
+    #[test]
+    fn an_item_over_the_byte_cap_gets_a_batch_of_its_own() {
+        let sizes = [300, 2048 * KB, 300];
+        assert_eq!(limits(100, 512 * KB).plan(&sizes), vec![0..1, 1..2, 2..3]);
+    }
+
+    #[test]
+    fn zero_lifts_a_limit() {
+        let sizes = vec![200 * KB; 4];
+        assert_eq!(limits(0, 0).plan(&sizes), vec![0..4]);
+        assert_eq!(limits(3, 0).plan(&sizes), vec![0..3, 3..4]);
+        assert_eq!(limits(0, 400 * KB).plan(&sizes), vec![0..2, 2..4]);
+    }
+
+    #[test]
+    fn nothing_to_plan() {
+        assert!(limits(100, 512 * KB).plan(&[]).is_empty());
+    }
+
+    #[test]
+    fn the_table_a_d1_error_names_points_at_its_statement() {
+        let tables = ["ciphers", "users", "ciphers_collections", "events"];
+        assert_eq!(
+            failed_statement(
+                &tables,
+                "D1_ERROR: UNIQUE constraint failed: ciphers_collections.cipher_id, ciphers_collections.collection_id: SQLITE_CONSTRAINT"
+            ),
+            Some(2)
+        );
+        assert_eq!(
+            failed_statement(&tables, "D1_ERROR: UNIQUE constraint failed: ciphers.id"),
+            Some(0)
+        );
+        assert_eq!(
+            failed_statement(
+                &tables,
+                "D1_ERROR: FOREIGN KEY constraint failed: SQLITE_CONSTRAINT"
+            ),
+            None
+        );
+    }
+}
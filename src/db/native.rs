@@ -0,0 +1,257 @@
+//! [`Database`] on SQLite through rusqlite, so handlers run natively (`cargo test`, local tools)
+//! with the same SQL they run on D1.
+//!
+//! Parameters bind the way D1 binds them (booleans as `0`/`1`, arrays and objects as JSON text),
+//! and rows come back as the JSON objects D1 hands out, so models deserialize unchanged.
+//! Foreign keys are enforced, as on D1, and errors carry SQLite's message, so
+//! [`ConstraintViolation`](super::ConstraintViolation) classifies them the same way.
+
+#[cfg(test)]
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value};
+use worker::Error;
+
+use super::{Database, Param};
+
+/// A SQLite connection, shared by its clones like a D1 binding.
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    conn: Arc<Mutex<Connection>>,
+    #[cfg(test)]
+    faults: Arc<Mutex<BatchFaults>>,
+}
+
+/// Batch calls to fail, set by [`SqliteDatabase::fail_batches`].
+#[cfg(test)]
+#[derive(Default)]
+struct BatchFaults {
+    calls: usize,
+    failing: Range<usize>,
+    message: String,
+}
+
+/// A statement with its parameters, run when its batch is.
+#[derive(Debug, Clone)]
+pub struct SqliteStatement {
+    sql: String,
+    params: Vec<Param>,
+}
+
+impl SqliteDatabase {
+    /// A fresh database that lives as long as its handle.
+    pub fn in_memory() -> Result<Self, Error> {
+        Self::new(Connection::open_in_memory().map_err(to_error)?)
+    }
+
+    fn new(conn: Connection) -> Result<Self, Error> {
+        conn.pragma_update(None, "foreign_keys", true)
+            .map_err(to_error)?;
+        Ok(SqliteDatabase {
+            conn: Arc::new(Mutex::new(conn)),
+            #[cfg(test)]
+            faults: Arc::default(),
+        })
+    }
+
+    /// Makes the batch calls numbered `calls`, counting from 0 at this call, fail with `message`
+    /// without running, so tests can see how callers deal with D1 failing part way. A retried
+    /// batch is a new call.
+    #[cfg(test)]
+    pub fn fail_batches(&self, calls: Range<usize>, message: &str) {
+        *self.faults.lock().unwrap() = BatchFaults {
+            calls: 0,
+            failing: calls,
+            message: message.to_owned(),
+        };
+    }
+
+    /// Runs `sql`, any number of statements without parameters, like `D1Database::exec`.
+    pub async fn exec(&self, sql: &str) -> Result<(), Error> {
+        self.lock().execute_batch(sql).map_err(to_error)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        // A panicking test leaves the connection usable; SQLite rolled back what didn't commit.
+        self.conn
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn to_error(err: rusqlite::Error) -> Error {
+    Error::RustError(err.to_string())
+}
+
+fn sql_param(param: &Param) -> SqlValue {
+    match param {
+        Value::Null => SqlValue::Null,
+        Value::Bool(value) => SqlValue::Integer(i64::from(*value)),
+        Value::Number(value) => match value.as_i64() {
+            Some(value) => SqlValue::Integer(value),
+            None => SqlValue::Real(value.as_f64().unwrap_or_default()),
+        },
+        Value::String(value) => SqlValue::Text(value.clone()),
+        Value::Array(_) | Value::Object(_) => SqlValue::Text(param.to_string()),
+    }
+}
+
+fn json_value(value: ValueRef<'_>) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(value) => value.into(),
+        ValueRef::Real(value) => Number::from_f64(value).map_or(Value::Null, Value::Number),
+        ValueRef::Text(value) => String::from_utf8_lossy(value).into_owned().into(),
+        ValueRef::Blob(value) => value.to_vec().into(),
+    }
+}
+
+/// Runs one statement, reading its rows (if any) as JSON objects keyed by column name.
+fn query(conn: &Connection, sql: &str, params: &[Param]) -> Result<Vec<Value>, Error> {
+    let mut statement = conn.prepare(sql).map_err(to_error)?;
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+    let mut rows = statement
+        .query(params_from_iter(params.iter().map(sql_param)))
+        .map_err(to_error)?;
+    let mut results = Vec::new();
+    while let Some(row) = rows.next().map_err(to_error)? {
+        let mut object = Map::with_capacity(columns.len());
+        for (index, column) in columns.iter().enumerate() {
+            object.insert(
+                column.clone(),
+                json_value(row.get_ref(index).map_err(to_error)?),
+            );
+        }
+        results.push(Value::Object(object));
+    }
+    Ok(results)
+}
+
+/// Runs one statement and returns how many rows it changed (none, for a `SELECT`).
+fn execute(conn: &Connection, sql: &str, params: &[Param]) -> Result<usize, Error> {
+    let before = conn.total_changes();
+    query(conn, sql, params)?;
+    Ok((conn.total_changes() - before) as usize)
+}
+
+fn deserialize<T: DeserializeOwned>(row: Value) -> Result<T, Error> {
+    serde_json::from_value(row).map_err(|err| Error::RustError(err.to_string()))
+}
+
+impl Database for SqliteDatabase {
+    type Statement = SqliteStatement;
+
+    fn prepare(&self, sql: &str, params: &[Param]) -> Result<SqliteStatement, Error> {
+        Ok(SqliteStatement {
+            sql: sql.to_owned(),
+            params: params.to_vec(),
+        })
+    }
+
+    async fn first<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: &[Param],
+    ) -> Result<Option<T>, Error> {
+        query(&self.lock(), sql, params)?
+            .into_iter()
+            .next()
+            .map(deserialize)
+            .transpose()
+    }
+
+    async fn all<T: DeserializeOwned>(&self, sql: &str, params: &[Param]) -> Result<Vec<T>, Error> {
+        query(&self.lock(), sql, params)?
+            .into_iter()
+            .map(deserialize)
+            .collect()
+    }
+
+    async fn run(&self, sql: &str, params: &[Param]) -> Result<usize, Error> {
+        execute(&self.lock(), sql, params)
+    }
+
+    async fn batch(&self, statements: Vec<SqliteStatement>) -> Result<Vec<usize>, Error> {
+        #[cfg(test)]
+        {
+            let mut faults = self.faults.lock().unwrap();
+            let call = faults.calls;
+            faults.calls += 1;
+            if faults.failing.contains(&call) {
+                return Err(Error::RustError(faults.message.clone()));
+            }
+        }
+        let mut conn = self.lock();
+        let transaction = conn.transaction().map_err(to_error)?;
+        let changes = statements
+            .iter()
+            .map(|statement| execute(&transaction, &statement.sql, &statement.params))
+            .collect::<Result<Vec<_>, _>>()?;
+        transaction.commit().map_err(to_error)?;
+        Ok(changes)
+    }
+
+    async fn texts(&self, sql: &str, params: &[Param]) -> Result<Vec<String>, Error> {
+        query(&self.lock(), sql, params)?
+            .into_iter()
+            .map(|row| {
+                row.as_object()
+                    .and_then(|columns| columns.values().next())
+                    .and_then(Value::as_str)
+                    .map(str::to_owned)
+                    .ok_or_else(|| Error::RustError("first column is not text".to_string()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::parity;
+    use crate::native::block_on;
+
+    fn database() -> SqliteDatabase {
+        let db = SqliteDatabase::in_memory().unwrap();
+        block_on(db.exec(parity::SCHEMA)).unwrap();
+        db
+    }
+
+    #[test]
+    fn binds_parameters_like_d1() {
+        block_on(parity::binds_parameters_like_d1(&database()));
+    }
+
+    #[test]
+    fn reads_rows_by_column_name() {
+        block_on(parity::reads_rows_by_column_name(&database()));
+    }
+
+    #[test]
+    fn counts_changed_rows() {
+        block_on(parity::counts_changed_rows(&database()));
+    }
+
+    #[test]
+    fn reads_single_columns() {
+        block_on(parity::reads_single_columns(&database()));
+    }
+
+    #[test]
+    fn rolls_back_a_failed_batch() {
+        block_on(parity::rolls_back_a_failed_batch(&database()));
+    }
+
+    #[test]
+    fn reports_constraint_violations() {
+        block_on(parity::reports_constraint_violations(&database()));
+    }
+}
@@ -0,0 +1,247 @@
+//! What handlers rely on from a [`Database`], as checks any backend has to pass.
+//!
+//! Each check runs against a database with [`SCHEMA`] applied. The native backend runs them
+//! under `cargo test`; D1 only runs in a Worker, so these also document what its impl has to keep
+//! doing (`js_param` binds values as [`native`](super::native) does).
+
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{classify_error, ConstraintViolation, Database};
+use crate::error::AppError;
+use crate::models::serde_d1::{deserialize_bool, deserialize_option_string};
+
+pub const SCHEMA: &str = "
+    CREATE TABLE owners (id TEXT PRIMARY KEY NOT NULL, email TEXT NOT NULL UNIQUE);
+    CREATE TABLE items (
+        id TEXT PRIMARY KEY NOT NULL,
+        owner_id TEXT NOT NULL REFERENCES owners(id),
+        name TEXT,
+        favorite INTEGER NOT NULL DEFAULT 0,
+        size INTEGER NOT NULL DEFAULT 0
+    );
+";
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Item {
+    id: String,
+    #[serde(deserialize_with = "deserialize_option_string")]
+    name: Option<String>,
+    #[serde(deserialize_with = "deserialize_bool")]
+    favorite: bool,
+    size: i64,
+}
+
+async fn seed<D: Database>(db: &D) {
+    db.run(
+        "INSERT INTO owners (id, email) VALUES (?1, ?2), (?3, ?4)",
+        &[
+            "o1".into(),
+            "a@example.com".into(),
+            "o2".into(),
+            "b@example.com".into(),
+        ],
+    )
+    .await
+    .unwrap();
+    for (id, owner, name, size) in [
+        ("i1", "o1", "one", 1),
+        ("i2", "o1", "two", 2),
+        ("i3", "o2", "three", 3),
+    ] {
+        db.run(
+            "INSERT INTO items (id, owner_id, name, size) VALUES (?1, ?2, ?3, ?4)",
+            &[id.into(), owner.into(), name.into(), size.into()],
+        )
+        .await
+        .unwrap();
+    }
+}
+
+/// Booleans bind as `0`/`1`, NULL as NULL, and arrays as JSON text `json_each` can read.
+pub async fn binds_parameters_like_d1<D: Database>(db: &D) {
+    seed(db).await;
+    db.run(
+        "UPDATE items SET favorite = ?1, name = ?2 WHERE id = ?3",
+        &[true.into(), json!(null), "i1".into()],
+    )
+    .await
+    .unwrap();
+    let favorite: Option<serde_json::Value> = db
+        .first(
+            "SELECT favorite, typeof(favorite) AS kind FROM items WHERE id = ?1",
+            &["i1".into()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(favorite, Some(json!({ "favorite": 1, "kind": "integer" })));
+
+    let ids: Vec<serde_json::Value> = db
+        .all(
+            "SELECT id FROM items WHERE id IN (SELECT value FROM json_each(?1)) ORDER BY id",
+            &[json!(["i1", "i3", "missing"])],
+        )
+        .await
+        .unwrap();
+    assert_eq!(ids, vec![json!({ "id": "i1" }), json!({ "id": "i3" })]);
+}
+
+/// Rows deserialize by column name, in the query's order; no row reads as `None`.
+pub async fn reads_rows_by_column_name<D: Database>(db: &D) {
+    seed(db).await;
+    db.run("UPDATE items SET name = NULL WHERE id = 'i2'", &[])
+        .await
+        .unwrap();
+
+    let items: Vec<Item> = db
+        .all(
+            "SELECT * FROM items WHERE owner_id = ?1 ORDER BY size DESC",
+            &["o1".into()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        items,
+        vec![
+            Item {
+                id: "i2".into(),
+                name: None,
+                favorite: false,
+                size: 2
+            },
+            Item {
+                id: "i1".into(),
+                name: Some("one".into()),
+                favorite: false,
+                size: 1
+            },
+        ]
+    );
+
+    let missing: Option<Item> = db
+        .first("SELECT * FROM items WHERE id = ?1", &["nope".into()])
+        .await
+        .unwrap();
+    assert_eq!(missing, None);
+}
+
+/// `first_column` reads one column of the first row, and `texts` the first column of each row.
+pub async fn reads_single_columns<D: Database>(db: &D) {
+    seed(db).await;
+    db.run("UPDATE items SET name = NULL WHERE id = 'i2'", &[])
+        .await
+        .unwrap();
+
+    let size: Option<i64> = db
+        .first_column("SELECT * FROM items WHERE id = ?1", &["i3".into()], "size")
+        .await
+        .unwrap();
+    assert_eq!(size, Some(3));
+    let name: Option<String> = db
+        .first_column("SELECT * FROM items WHERE id = ?1", &["i2".into()], "name")
+        .await
+        .unwrap();
+    assert_eq!(name, None);
+
+    let texts = db
+        .texts(
+            "SELECT json_object('id', id, 'size', size) AS item_json FROM items ORDER BY id",
+            &[],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        texts,
+        vec![
+            r#"{"id":"i1","size":1}"#,
+            r#"{"id":"i2","size":2}"#,
+            r#"{"id":"i3","size":3}"#
+        ]
+    );
+}
+
+/// `run` and `batch` count the rows each statement changed; a `SELECT` changes none.
+pub async fn counts_changed_rows<D: Database>(db: &D) {
+    seed(db).await;
+    let changed = db
+        .run(
+            "UPDATE items SET size = size + 1 WHERE owner_id = ?1",
+            &["o1".into()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(changed, 2);
+    let changed = db
+        .run(
+            "UPDATE items SET size = 0 WHERE owner_id = ?1",
+            &["nobody".into()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(changed, 0);
+
+    let statements = vec![
+        db.prepare("DELETE FROM items WHERE id = ?1", &["i3".into()])
+            .unwrap(),
+        db.prepare("SELECT * FROM items", &[]).unwrap(),
+        db.prepare(
+            "INSERT OR IGNORE INTO owners (id, email) VALUES ('o1', 'x')",
+            &[],
+        )
+        .unwrap(),
+    ];
+    assert_eq!(db.batch(statements).await.unwrap(), vec![1, 0, 0]);
+}
+
+/// A batch is one transaction: when a statement fails, none of them applies.
+pub async fn rolls_back_a_failed_batch<D: Database>(db: &D) {
+    seed(db).await;
+    let statements = vec![
+        db.prepare("DELETE FROM items WHERE id = ?1", &["i1".into()])
+            .unwrap(),
+        db.prepare(
+            "INSERT INTO items (id, owner_id) VALUES (?1, ?2)",
+            &["i2".into(), "o1".into()],
+        )
+        .unwrap(),
+    ];
+    assert!(db.batch(statements).await.is_err());
+
+    let item: Option<Item> = db
+        .first("SELECT * FROM items WHERE id = ?1", &["i1".into()])
+        .await
+        .unwrap();
+    assert!(item.is_some());
+}
+
+/// Errors name the constraint they broke; foreign keys are enforced.
+pub async fn reports_constraint_violations<D: Database>(db: &D) {
+    seed(db).await;
+    let duplicate = db
+        .run(
+            "INSERT INTO owners (id, email) VALUES (?1, ?2)",
+            &["o3".into(), "a@example.com".into()],
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        ConstraintViolation::of(&duplicate),
+        Some(ConstraintViolation::Unique)
+    );
+    assert!(matches!(
+        classify_error(duplicate, "The owner"),
+        AppError::Conflict(_)
+    ));
+
+    let dangling = db
+        .run(
+            "INSERT INTO items (id, owner_id) VALUES (?1, ?2)",
+            &["i9".into(), "nobody".into()],
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        ConstraintViolation::of(&dangling),
+        Some(ConstraintViolation::ForeignKey)
+    );
+}
@@ -0,0 +1,432 @@
+//! Queries of a user's own data that always bind the user's id.
+//!
+//! A row matched by its id alone may be anyone's, and a single forgotten `AND user_id = ?` hands
+//! another user's encrypted data out, or lets them change it. Handlers read and change folders
+//! and ciphers by id through these helpers instead; a row of another user reads as missing, and
+//! the handler answers 404.
+//!
+//! Organization ciphers belong to whoever the organization gives access, not to their creator;
+//! [`org_scoped`] has the variants that join the user's memberships. Lists of ciphers, such as
+//! the sync, go through [`user_ciphers`], which applies the same visibility.
+
+pub mod org_scoped;
+
+use serde::de::DeserializeOwned;
+use worker::Error;
+
+use super::{Database, Param};
+use crate::models::cipher::CipherDBModel;
+use crate::models::folder::Folder;
+
+/// The user's folder `id`.
+pub async fn folder_by_id<D: Database>(
+    db: &D,
+    user_id: &str,
+    id: &str,
+) -> Result<Option<Folder>, Error> {
+    db.first(
+        "SELECT * FROM folders WHERE id = ?1 AND user_id = ?2",
+        &[id.into(), user_id.into()],
+    )
+    .await
+}
+
+/// Every folder of the user.
+pub async fn user_folders<D: Database>(db: &D, user_id: &str) -> Result<Vec<Folder>, Error> {
+    db.all(
+        "SELECT * FROM folders WHERE user_id = ?1",
+        &[user_id.into()],
+    )
+    .await
+}
+
+/// Renames the user's folder `id`; returns how many rows changed.
+pub async fn rename_folder<D: Database>(
+    db: &D,
+    user_id: &str,
+    id: &str,
+    name: &str,
+    updated_at: &str,
+) -> Result<usize, Error> {
+    db.run(
+        "UPDATE folders SET name = ?1, updated_at = ?2 WHERE id = ?3 AND user_id = ?4",
+        &[name.into(), updated_at.into(), id.into(), user_id.into()],
+    )
+    .await
+}
+
+/// Statement deleting the user's folder `id`, for a batch.
+pub fn delete_folder_statement<D: Database>(
+    db: &D,
+    user_id: &str,
+    id: &str,
+) -> Result<D::Statement, Error> {
+    db.prepare(
+        "DELETE FROM folders WHERE id = ?1 AND user_id = ?2",
+        &[id.into(), user_id.into()],
+    )
+}
+
+/// The user's personal cipher `id`. Organization ciphers, even those the user created, are only
+/// found through [`org_scoped::cipher_by_id`].
+pub async fn cipher_by_id<D: Database>(
+    db: &D,
+    user_id: &str,
+    id: &str,
+) -> Result<Option<CipherDBModel>, Error> {
+    db.first(
+        "SELECT * FROM ciphers WHERE id = ?1 AND user_id = ?2 AND organization_id IS NULL",
+        &[id.into(), user_id.into()],
+    )
+    .await
+}
+
+/// Which of the ciphers a user can see [`user_ciphers`] reads; each field that is set narrows
+/// them down. The default is every cipher the user can see, trashed ones included.
+#[derive(Debug, Default, Clone)]
+pub struct CipherFilter {
+    /// `Some(None)` for the ciphers in no folder.
+    pub folder_id: Option<Option<String>>,
+    pub collection_id: Option<String>,
+    /// `Some(None)` for personal ciphers only.
+    pub organization_id: Option<Option<String>>,
+    pub r#type: Option<i32>,
+    /// `Some(true)` for the trash only, `Some(false)` for the ciphers outside of it.
+    pub deleted: Option<bool>,
+    /// A JSON document whose `ids` array lists the ciphers, as the bulk endpoints receive it.
+    pub ids_json: Option<String>,
+    /// Only the ciphers after this revision date and id, in the order they are read.
+    pub after: Option<(String, String)>,
+    /// Only the ciphers up to this revision date and id, included.
+    pub through: Option<(String, String)>,
+    pub limit: Option<usize>,
+}
+
+/// Reads `columns` of the ciphers the user can see (see [`org_scoped::cipher_visible_sql`])
+/// that match `filter`, most recently changed first.
+///
+/// `columns` is a select list over the matching ciphers, aliased `c`, and may refer to the user
+/// as `?1`; an aggregate such as `json_group_array` reads them all into one row, in order.
+pub async fn user_ciphers<D: Database, T: DeserializeOwned>(
+    db: &D,
+    user_id: &str,
+    filter: &CipherFilter,
+    columns: &str,
+) -> Result<Vec<T>, Error> {
+    let (sql, params) = user_ciphers_query(user_id, filter, columns);
+    db.all(&sql, &params).await
+}
+
+/// [`user_ciphers`] for a single text column, such as JSON built in SQL, read without
+/// deserializing each row.
+pub async fn user_cipher_texts<D: Database>(
+    db: &D,
+    user_id: &str,
+    filter: &CipherFilter,
+    column: &str,
+) -> Result<Vec<String>, Error> {
+    let (sql, params) = user_ciphers_query(user_id, filter, column);
+    db.texts(&sql, &params).await
+}
+
+fn user_ciphers_query(user_id: &str, filter: &CipherFilter, columns: &str) -> (String, Vec<Param>) {
+    let mut conditions = vec![org_scoped::cipher_visible_sql("?1")];
+    let mut params: Vec<Param> = vec![user_id.into()];
+    let mut bind = |value: Param| {
+        params.push(value);
+        format!("?{}", params.len())
+    };
+
+    match &filter.folder_id {
+        Some(Some(folder_id)) => {
+            conditions.push(format!("c.folder_id = {}", bind(folder_id.as_str().into())))
+        }
+        Some(None) => conditions.push("c.folder_id IS NULL".to_string()),
+        None => {}
+    }
+    if let Some(collection_id) = &filter.collection_id {
+        conditions.push(format!(
+            "EXISTS (SELECT 1 FROM ciphers_collections cc WHERE cc.cipher_id = c.id AND cc.collection_id = {})",
+            bind(collection_id.as_str().into())
+        ));
+    }
+    match &filter.organization_id {
+        Some(Some(organization_id)) => conditions.push(format!(
+            "c.organization_id = {}",
+            bind(organization_id.as_str().into())
+        )),
+        Some(None) => conditions.push("c.organization_id IS NULL".to_string()),
+        None => {}
+    }
+    if let Some(cipher_type) = filter.r#type {
+        conditions.push(format!("c.type = {}", bind(cipher_type.into())));
+    }
+    match filter.deleted {
+        Some(true) => conditions.push("c.deleted_at IS NOT NULL".to_string()),
+        Some(false) => conditions.push("c.deleted_at IS NULL".to_string()),
+        None => {}
+    }
+    if let Some(ids_json) = &filter.ids_json {
+        conditions.push(format!(
+            "c.id IN (SELECT value FROM json_each({}, '$.ids'))",
+            bind(ids_json.as_str().into())
+        ));
+    }
+    if let Some((updated_at, id)) = &filter.after {
+        let (updated_at, id) = (bind(updated_at.as_str().into()), bind(id.as_str().into()));
+        conditions.push(format!(
+            "(c.updated_at < {updated_at} OR (c.updated_at = {updated_at} AND c.id < {id}))"
+        ));
+    }
+    if let Some((updated_at, id)) = &filter.through {
+        let (updated_at, id) = (bind(updated_at.as_str().into()), bind(id.as_str().into()));
+        conditions.push(format!(
+            "(c.updated_at > {updated_at} OR (c.updated_at = {updated_at} AND c.id >= {id}))"
+        ));
+    }
+    let limit = filter
+        .limit
+        .map(|limit| format!(" LIMIT {limit}"))
+        .unwrap_or_default();
+
+    // The ciphers are ordered in a subquery, which an aggregate in `columns` keeps the order of
+    let sql = format!(
+        "SELECT {columns} FROM (
+            SELECT * FROM ciphers c WHERE {}
+            ORDER BY c.updated_at DESC, c.id DESC{limit}
+        ) c",
+        conditions.join(" AND "),
+    );
+    (sql, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::native::block_on;
+
+    /// Alice and Bob, each with a folder and a cipher in it; Alice also has a cipher in no folder
+    /// and one in the trash.
+    fn database() -> Db {
+        let db = Db::in_memory().unwrap();
+        block_on(crate::migrations::run(&db)).unwrap();
+        block_on(db.exec(
+            "INSERT INTO users (id, email, master_password_hash, key, private_key, public_key, created_at, updated_at) VALUES
+                 ('alice', 'alice@example.com', 'h', 'k', 'p', 'p', '2025-01-01T00:00:00.000Z', '2025-01-01T00:00:00.000Z'),
+                 ('bob', 'bob@example.com', 'h', 'k', 'p', 'p', '2025-01-01T00:00:00.000Z', '2025-01-01T00:00:00.000Z');
+             INSERT INTO folders (id, user_id, name, created_at, updated_at) VALUES
+                 ('alice-folder', 'alice', 'f', '2025-01-01T00:00:00.000Z', '2025-01-01T00:00:00.000Z'),
+                 ('bob-folder', 'bob', 'f', '2025-01-01T00:00:00.000Z', '2025-01-01T00:00:00.000Z');
+             INSERT INTO ciphers (id, user_id, type, data, folder_id, deleted_at, created_at, updated_at) VALUES
+                 ('alice-filed', 'alice', 1, '{}', 'alice-folder', NULL, '2025-01-01T00:00:00.000Z', '2025-01-03T00:00:00.000Z'),
+                 ('alice-loose', 'alice', 2, '{}', NULL, NULL, '2025-01-01T00:00:00.000Z', '2025-01-02T00:00:00.000Z'),
+                 ('alice-trashed', 'alice', 1, '{}', NULL, '2025-01-04T00:00:00.000Z', '2025-01-01T00:00:00.000Z', '2025-01-04T00:00:00.000Z'),
+                 ('bob-filed', 'bob', 1, '{}', 'bob-folder', NULL, '2025-01-01T00:00:00.000Z', '2025-01-01T00:00:00.000Z');",
+        ))
+        .unwrap();
+        db
+    }
+
+    fn ids(db: &Db, user_id: &str, filter: &CipherFilter) -> Vec<String> {
+        block_on(user_cipher_texts(db, user_id, filter, "c.id")).unwrap()
+    }
+
+    #[test]
+    fn another_users_folder_reads_as_missing() {
+        let db = database();
+        assert!(block_on(folder_by_id(&db, "alice", "alice-folder"))
+            .unwrap()
+            .is_some());
+        assert!(block_on(folder_by_id(&db, "bob", "alice-folder"))
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            block_on(rename_folder(&db, "bob", "alice-folder", "x", "now")).unwrap(),
+            0
+        );
+        let folders = block_on(user_folders(&db, "bob")).unwrap();
+        assert_eq!(
+            folders.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(),
+            vec!["bob-folder"]
+        );
+    }
+
+    #[test]
+    fn another_users_folder_is_not_deleted() {
+        let db = database();
+        let statement = delete_folder_statement(&db, "bob", "alice-folder").unwrap();
+        assert_eq!(block_on(db.batch(vec![statement])).unwrap(), vec![0]);
+        assert!(block_on(folder_by_id(&db, "alice", "alice-folder"))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn another_users_cipher_reads_as_missing() {
+        let db = database();
+        assert!(block_on(cipher_by_id(&db, "alice", "alice-filed"))
+            .unwrap()
+            .is_some());
+        assert!(block_on(cipher_by_id(&db, "bob", "alice-filed"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn lists_only_the_users_own_ciphers() {
+        let db = database();
+        assert_eq!(
+            ids(&db, "alice", &CipherFilter::default()),
+            vec!["alice-trashed", "alice-filed", "alice-loose"]
+        );
+        assert_eq!(ids(&db, "bob", &CipherFilter::default()), vec!["bob-filed"]);
+        assert!(ids(&db, "mallory", &CipherFilter::default()).is_empty());
+    }
+
+    #[test]
+    fn filters_never_reach_another_users_ciphers() {
+        let db = database();
+        let by_folder = CipherFilter {
+            folder_id: Some(Some("alice-folder".to_string())),
+            ..CipherFilter::default()
+        };
+        let by_ids = CipherFilter {
+            ids_json: Some(r#"{"ids": ["alice-filed", "bob-filed"]}"#.to_string()),
+            ..CipherFilter::default()
+        };
+        assert_eq!(ids(&db, "alice", &by_folder), vec!["alice-filed"]);
+        assert!(ids(&db, "bob", &by_folder).is_empty());
+        assert_eq!(ids(&db, "bob", &by_ids), vec!["bob-filed"]);
+    }
+
+    #[test]
+    fn narrows_the_list_by_each_filter() {
+        let db = database();
+        let list = |filter: CipherFilter| ids(&db, "alice", &filter);
+
+        assert_eq!(
+            list(CipherFilter {
+                folder_id: Some(None),
+                ..CipherFilter::default()
+            }),
+            vec!["alice-trashed", "alice-loose"]
+        );
+        assert_eq!(
+            list(CipherFilter {
+                deleted: Some(false),
+                r#type: Some(1),
+                ..CipherFilter::default()
+            }),
+            vec!["alice-filed"]
+        );
+        assert_eq!(
+            list(CipherFilter {
+                deleted: Some(true),
+                ..CipherFilter::default()
+            }),
+            vec!["alice-trashed"]
+        );
+        assert_eq!(
+            list(CipherFilter {
+                after: Some((
+                    "2025-01-03T00:00:00.000Z".to_string(),
+                    "alice-filed".to_string()
+                )),
+                ..CipherFilter::default()
+            }),
+            vec!["alice-loose"]
+        );
+        assert_eq!(
+            list(CipherFilter {
+                through: Some((
+                    "2025-01-03T00:00:00.000Z".to_string(),
+                    "alice-filed".to_string()
+                )),
+                limit: Some(1),
+                ..CipherFilter::default()
+            }),
+            vec!["alice-trashed"]
+        );
+    }
+
+    #[test]
+    fn aggregates_keep_the_order() {
+        let db = database();
+        let arrays = block_on(user_cipher_texts(
+            &db,
+            "alice",
+            &CipherFilter::default(),
+            "json_group_array(c.id)",
+        ))
+        .unwrap();
+        assert_eq!(
+            arrays,
+            vec![r#"["alice-trashed","alice-filed","alice-loose"]"#]
+        );
+    }
+
+    /// The steps of SQLite's plan for `sql`.
+    fn plan(db: &Db, sql: &str, params: &[Param]) -> Vec<String> {
+        let steps: Vec<serde_json::Value> =
+            block_on(db.all(&format!("EXPLAIN QUERY PLAN {sql}"), params)).unwrap();
+        steps
+            .iter()
+            .map(|step| step["detail"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[track_caller]
+    fn assert_searches(steps: &[String], search: &str) {
+        assert!(
+            steps.iter().any(|step| step == search),
+            "{search}: {steps:#?}"
+        );
+        assert!(
+            !steps.iter().any(|step| step.starts_with("SCAN c")),
+            "{steps:#?}"
+        );
+    }
+
+    #[test]
+    fn lists_search_the_indexes_instead_of_scanning() {
+        let db = database();
+        let ciphers = |filter: CipherFilter| {
+            let (sql, params) = user_ciphers_query("alice", &filter, "c.id");
+            plan(&db, &sql, &params)
+        };
+
+        // The sync reads the whole vault, /api/ciphers the ciphers outside of the trash
+        let sync = ciphers(CipherFilter::default());
+        assert_searches(
+            &sync,
+            "SEARCH c USING INDEX idx_ciphers_user_id_deleted_at (user_id=?)",
+        );
+        assert_searches(
+            &sync,
+            "SEARCH c USING INDEX idx_ciphers_organization_id (organization_id=?)",
+        );
+        assert_searches(
+            &ciphers(CipherFilter {
+                deleted: Some(false),
+                ..Default::default()
+            }),
+            "SEARCH c USING INDEX idx_ciphers_user_id_deleted_at (user_id=? AND deleted_at=?)",
+        );
+        assert_searches(
+            &ciphers(CipherFilter {
+                organization_id: Some(Some("org".to_string())),
+                ..Default::default()
+            }),
+            "SEARCH c USING INDEX idx_ciphers_organization_id (organization_id=?)",
+        );
+        assert_searches(
+            &plan(
+                &db,
+                "SELECT * FROM folders WHERE user_id = ?1",
+                &["alice".into()],
+            ),
+            "SEARCH folders USING INDEX idx_folders_user_id (user_id=?)",
+        );
+    }
+}
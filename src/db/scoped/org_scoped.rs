@@ -0,0 +1,199 @@
+//! Organization data is the user's through their memberships: these queries join the user's
+//! confirmed memberships (and, for organization ciphers, the collections assigned to them
+//! directly or through groups) instead of matching an owner column.
+//!
+//! The `*_sql` builders are the same conditions as SQL fragments, for queries that filter many
+//! ciphers at once, such as the sync and the bulk endpoints; they expect the cipher aliased `c`.
+
+use worker::Error;
+
+use crate::db::Database;
+use crate::models::cipher::CipherDBModel;
+use crate::models::organization::{MembershipStatus, MembershipType};
+use crate::models::policy::PolicyType;
+use crate::models::twofactor::TwoFactorType;
+
+/// SQL subquery of every collection assignment as `(collection_id, membership_id, read_only,
+/// hide_passwords, manage)`: members' own assignments plus those they get through their groups,
+/// in organizations that use groups. A member can appear several times for one collection; their
+/// access is the union.
+pub const COLLECTION_ASSIGNMENTS_SQL: &str =
+    "SELECT collection_id, membership_id, read_only, hide_passwords, manage FROM collections_users
+     UNION ALL
+     SELECT cg.collection_id, gu.membership_id, cg.read_only, cg.hide_passwords, cg.manage
+     FROM collections_groups cg
+     JOIN groups_users gu ON gu.group_id = cg.group_id
+     JOIN groups g ON g.id = cg.group_id
+     JOIN organizations o ON o.id = g.organization_id
+     WHERE o.use_groups = 1";
+
+/// SQL condition on a membership (aliased `ou`) that holds while the organization's two-step
+/// login policy doesn't lock the member out. Owners and admins are exempt; everyone else needs an
+/// authenticator app set up before organization ciphers reach their clients.
+fn two_step_login_satisfied_sql() -> String {
+    format!(
+        "(ou.atype IN ({owner}, {admin})
+          OR NOT EXISTS (
+              SELECT 1 FROM organization_policies p
+              WHERE p.organization_id = ou.organization_id AND p.atype = {policy} AND p.enabled = 1
+          )
+          OR EXISTS (
+              SELECT 1 FROM twofactor t
+              WHERE t.user_uuid = ou.user_id AND t.enabled = 1 AND t.atype = {authenticator}
+          ))",
+        owner = MembershipType::Owner as i32,
+        admin = MembershipType::Admin as i32,
+        policy = PolicyType::TwoFactorAuthentication as i32,
+        authenticator = TwoFactorType::Authenticator as i32,
+    )
+}
+
+/// SQL condition matching organization ciphers in organizations where the user bound to
+/// `user_param` can see every collection (owners, admins and members with access to all).
+fn org_full_access_sql(user_param: &str) -> String {
+    format!(
+        "c.organization_id IN (
+            SELECT ou.organization_id FROM organization_users ou
+            WHERE ou.user_id = {user_param} AND ou.status = {confirmed}
+              AND (ou.atype IN ({owner}, {admin}) OR ou.access_all = 1)
+              AND {two_step}
+        )",
+        confirmed = MembershipStatus::Confirmed as i32,
+        two_step = two_step_login_satisfied_sql(),
+        owner = MembershipType::Owner as i32,
+        admin = MembershipType::Admin as i32,
+    )
+}
+
+/// SQL condition matching organization ciphers in a collection assigned to the user bound to
+/// `user_param`, directly or through a group, with an extra condition on the assignment.
+///
+/// The subquery doesn't refer to `c`, so each term of a visibility condition can use an index on
+/// `ciphers` and a user's list doesn't scan the whole table.
+fn org_collection_access_sql(user_param: &str, assignment_condition: &str) -> String {
+    format!(
+        "c.id IN (
+            SELECT cc.cipher_id FROM ciphers_collections cc
+            JOIN ({assignments}) cu ON cu.collection_id = cc.collection_id
+            JOIN organization_users ou ON ou.id = cu.membership_id
+            WHERE ou.user_id = {user_param} AND ou.status = {confirmed}
+              AND {two_step} {assignment_condition}
+        )",
+        assignments = COLLECTION_ASSIGNMENTS_SQL,
+        confirmed = MembershipStatus::Confirmed as i32,
+        two_step = two_step_login_satisfied_sql(),
+    )
+}
+
+/// SQL condition matching ciphers (aliased `c`) the user bound to `user_param` can see: their
+/// personal ciphers and organization ciphers they have access to.
+pub fn cipher_visible_sql(user_param: &str) -> String {
+    format!(
+        "((c.organization_id IS NULL AND c.user_id = {user_param}) OR {} OR {})",
+        org_full_access_sql(user_param),
+        org_collection_access_sql(user_param, ""),
+    )
+}
+
+/// SQL condition matching ciphers the user bound to `user_param` can edit and delete. Organization
+/// ciphers need a collection assignment that isn't read-only.
+pub fn cipher_writable_sql(user_param: &str) -> String {
+    format!(
+        "((c.organization_id IS NULL AND c.user_id = {user_param}) OR {} OR {})",
+        org_full_access_sql(user_param),
+        org_collection_access_sql(user_param, "AND cu.read_only = 0"),
+    )
+}
+
+/// SQL condition matching visible ciphers whose passwords the user bound to `user_param` may see.
+pub fn cipher_view_password_sql(user_param: &str) -> String {
+    format!(
+        "(c.organization_id IS NULL OR {} OR {})",
+        org_full_access_sql(user_param),
+        org_collection_access_sql(user_param, "AND cu.hide_passwords = 0"),
+    )
+}
+
+/// SQL condition matching ciphers of an organization whose members see TOTP codes. Personal
+/// ciphers never match: their TOTP codes depend on the user's own premium status.
+pub fn organization_use_totp_sql() -> &'static str {
+    "EXISTS (SELECT 1 FROM organizations o WHERE o.id = c.organization_id AND o.use_totp = 1)"
+}
+
+/// Cipher `id` if the user can see it: their personal cipher, or an organization cipher they
+/// have access to.
+pub async fn cipher_by_id<D: Database>(
+    db: &D,
+    user_id: &str,
+    id: &str,
+) -> Result<Option<CipherDBModel>, Error> {
+    db.first(
+        &format!(
+            "SELECT c.* FROM ciphers c WHERE c.id = ?2 AND {}",
+            cipher_visible_sql("?1")
+        ),
+        &[user_id.into(), id.into()],
+    )
+    .await
+}
+
+/// Organization cipher `id` if the user is a confirmed member of its organization, whatever
+/// their collection access; for the organization's admin routes, which check the member's role
+/// next.
+pub async fn organization_cipher_by_id<D: Database>(
+    db: &D,
+    user_id: &str,
+    id: &str,
+) -> Result<Option<CipherDBModel>, Error> {
+    db.first(
+        "SELECT c.* FROM ciphers c
+         JOIN organization_users ou
+           ON ou.organization_id = c.organization_id AND ou.user_id = ?1 AND ou.status = ?3
+         WHERE c.id = ?2",
+        &[
+            user_id.into(),
+            id.into(),
+            (MembershipStatus::Confirmed as i32).into(),
+        ],
+    )
+    .await
+}
+
+/// Moves cipher `id` to the trash (`deleted_at` set) or out of it (`None`), if the user can edit
+/// it; returns how many rows changed.
+pub async fn set_cipher_deleted_at<D: Database>(
+    db: &D,
+    user_id: &str,
+    id: &str,
+    deleted_at: Option<&str>,
+    updated_at: &str,
+) -> Result<usize, Error> {
+    db.run(
+        &format!(
+            "UPDATE ciphers AS c SET deleted_at = ?3, updated_at = ?4 WHERE c.id = ?2 AND {}",
+            cipher_writable_sql("?1")
+        ),
+        &[
+            user_id.into(),
+            id.into(),
+            deleted_at.into(),
+            updated_at.into(),
+        ],
+    )
+    .await
+}
+
+/// Statement deleting cipher `id` if the user can edit it, for a batch.
+pub fn delete_cipher_statement<D: Database>(
+    db: &D,
+    user_id: &str,
+    id: &str,
+) -> Result<D::Statement, Error> {
+    db.prepare(
+        &format!(
+            "DELETE FROM ciphers AS c WHERE c.id = ?2 AND {}",
+            cipher_writable_sql("?1")
+        ),
+        &[user_id.into(), id.into()],
+    )
+}
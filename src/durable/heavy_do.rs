@@ -1,9 +1,8 @@
-use axum::{extract::DefaultBodyLimit, Extension};
-use tower_http::cors::{Any, CorsLayer};
+use axum::Extension;
 use tower_service::Service;
 use worker::{durable_object, DurableObject, Env, HttpRequest, Request, Response, Result, State};
 
-use crate::{router, BaseUrl};
+use crate::{config::Settings, logging, migrations, router, BaseUrl};
 
 /// Durable Object used to run CPU-heavy API flows with a higher CPU budget.
 ///
@@ -22,37 +21,26 @@ impl DurableObject for HeavyDo {
 
     async fn fetch(&self, req: Request) -> Result<Response> {
         // Set up logging/panic hook (idempotent).
-        console_error_panic_hook::set_once();
+        logging::set_panic_hook();
         let _ = console_log::init_with_level(log::Level::Debug);
+        logging::apply_log_level(&self.env);
 
         // Keep fields used to avoid "unused" warnings even if we don't currently rely on them.
         let _ = &self.state;
 
+        migrations::run_on_first_request(&self.env).await;
+
         // Convert worker::Request -> worker::HttpRequest so we can reuse axum Router.
         let http_req: HttpRequest = req.try_into()?;
 
         // Extract base URL for /api/config endpoint (matches src/lib.rs behavior).
-        let uri = http_req.uri().clone();
-        let base_url = format!(
-            "{}://{}",
-            uri.scheme_str().unwrap_or("https"),
-            uri.authority().map(|a| a.as_str()).unwrap_or("localhost")
-        );
-
-        // Allow all origins for CORS, matching the main worker.
-        let cors = CorsLayer::new()
-            .allow_methods(Any)
-            .allow_headers(Any)
-            .allow_origin(Any);
+        let base_url = BaseUrl::new(http_req.uri(), &Settings::get(&self.env));
 
-        // Match the main worker's default body limit (5MB) for regular API requests.
-        const BODY_LIMIT: usize = 5 * 1024 * 1024;
-
-        // Reuse the existing router stack.
-        let mut app = router::api_router(self.env.clone())
-            .layer(Extension(BaseUrl(base_url)))
-            .layer(cors)
-            .layer(DefaultBodyLimit::max(BODY_LIMIT));
+        // Reuse the existing router stack, with the same CORS policy as the main worker.
+        let mut app = router::with_cors(
+            router::api_router(self.env.clone()).layer(Extension(base_url)),
+            &self.env,
+        );
 
         let http_resp = app.call(http_req).await?;
 
@@ -1 +1,13 @@
+//! Durable Objects. They are handed the runtime's `worker::Env`, so they aren't built natively
+//! (`native-db`).
+
+#[cfg(not(feature = "native-db"))]
 pub mod heavy_do;
+#[cfg(not(feature = "native-db"))]
+pub mod notifications_hub;
+
+/// Path the Worker posts notifications to the `NotificationsHub`, see [`crate::notify`].
+pub const SEND_PATH: &str = "/send";
+/// Path the Worker forwards WebSocket upgrades to the `NotificationsHub`, with the client's device
+/// identifier in a `device` query parameter when its token names one.
+pub const CONNECT_PATH: &str = "/hub";
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::{
+    durable_object, DurableObject, Env, Method, Request, Response, Result, State, WebSocket,
+    WebSocketIncomingMessage, WebSocketPair,
+};
+
+use super::SEND_PATH;
+use crate::logging;
+use crate::push::signalr::{self, Frame, Protocol};
+
+/// What a connection remembers while the object hibernates.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Connection {
+    /// Set by the handshake, before which nothing else is read or sent.
+    protocol: Option<Protocol>,
+    /// Identifier of the client's device, which isn't told about its own changes.
+    device: Option<String>,
+}
+
+/// Durable Object holding the notifications hub connections of one user, named by their id.
+///
+/// The WebSockets are accepted with the hibernation API, so an idle hub is evicted from memory
+/// while its connections stay open, and wakes up for a client's ping or a notification to send.
+/// The Worker authenticates the user before forwarding the upgrade here.
+#[durable_object]
+pub struct NotificationsHub {
+    state: State,
+    env: Env,
+}
+
+impl NotificationsHub {
+    fn init_logging(&self) {
+        logging::set_panic_hook();
+        let _ = console_log::init_with_level(log::Level::Debug);
+        logging::apply_log_level(&self.env);
+    }
+
+    /// Sends a `ReceiveMessage` with the notification to every connection past its handshake,
+    /// except the one of the device that caused it.
+    fn broadcast(&self, notification: &Value) {
+        let acting_device = notification["ContextId"].as_str();
+        for ws in self.state.get_websockets() {
+            let Ok(Some(Connection {
+                protocol: Some(protocol),
+                device,
+            })) = ws.deserialize_attachment::<Connection>()
+            else {
+                continue;
+            };
+            if device.is_some() && device.as_deref() == acting_device {
+                continue;
+            }
+            let frame = signalr::invocation(protocol, signalr::RECEIVE_MESSAGE, notification);
+            if let Err(err) = send(&ws, frame) {
+                log::warn!("Failed to send a hub notification: {err:?}");
+            }
+        }
+    }
+}
+
+fn send(ws: &WebSocket, frame: Frame) -> Result<()> {
+    match frame {
+        Frame::Text(text) => ws.send_with_str(text),
+        Frame::Binary(bytes) => ws.send_with_bytes(bytes),
+    }
+}
+
+impl DurableObject for NotificationsHub {
+    fn new(state: State, env: Env) -> Self {
+        Self { state, env }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        self.init_logging();
+
+        if req.method() == Method::Post && req.path() == SEND_PATH {
+            let notification: Value = req.json().await?;
+            self.broadcast(&notification);
+            return Response::empty();
+        }
+
+        let upgrade = req.headers().get("Upgrade")?;
+        if !upgrade.is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")) {
+            return Response::error("Expected a WebSocket upgrade", 426);
+        }
+        let device = req
+            .url()?
+            .query_pairs()
+            .find(|(name, _)| name == "device")
+            .map(|(_, device)| device.into_owned());
+        let pair = WebSocketPair::new()?;
+        self.state.accept_web_socket(&pair.server);
+        pair.server.serialize_attachment(Connection {
+            protocol: None,
+            device,
+        })?;
+        Response::from_websocket(pair.client)
+    }
+
+    async fn websocket_message(
+        &self,
+        ws: WebSocket,
+        message: WebSocketIncomingMessage,
+    ) -> Result<()> {
+        self.init_logging();
+
+        let connection: Connection = ws.deserialize_attachment()?.unwrap_or_default();
+        let Some(protocol) = connection.protocol else {
+            let WebSocketIncomingMessage::String(handshake) = message else {
+                return ws.close(Some(1002), Some("Expected a handshake"));
+            };
+            return match signalr::parse_handshake(&handshake) {
+                Ok(protocol) => {
+                    ws.serialize_attachment(Connection {
+                        protocol: Some(protocol),
+                        ..connection
+                    })?;
+                    ws.send_with_str(signalr::handshake_response(None))
+                }
+                Err(error) => {
+                    ws.send_with_str(signalr::handshake_response(Some(&error)))?;
+                    ws.close(Some(1002), Some(error))
+                }
+            };
+        };
+
+        let frame = match &message {
+            WebSocketIncomingMessage::String(text) => text.as_bytes(),
+            WebSocketIncomingMessage::Binary(bytes) => bytes.as_slice(),
+        };
+        for message_type in signalr::message_types(protocol, frame) {
+            match message_type {
+                // Answering the client's keep-alive keeps its server timeout from expiring
+                signalr::PING => send(&ws, signalr::ping(protocol))?,
+                signalr::CLOSE => return ws.close(Some(1000), Some("Closed by the client")),
+                // The clients don't invoke anything on the hub
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn websocket_close(
+        &self,
+        ws: WebSocket,
+        code: usize,
+        _reason: String,
+        _was_clean: bool,
+    ) -> Result<()> {
+        // Finish the closing handshake; the socket then leaves `get_websockets`. 1005 and 1006
+        // only describe a close and can't be sent.
+        let code = match code {
+            1005 | 1006 => 1000,
+            code => code as u16,
+        };
+        ws.close(Some(code), Some("Closed"))
+    }
+
+    async fn websocket_error(&self, ws: WebSocket, error: worker::Error) -> Result<()> {
+        self.init_logging();
+        log::warn!("Notifications hub connection failed: {error:?}");
+        ws.close(Some(1011), Some("Connection error"))
+    }
+}
@@ -1,7 +1,7 @@
 /**
  * JS Wrapper Entry Point for Warden Worker
  *
- * This wrapper intercepts attachment upload and download requests for zero-copy streaming
+ * This wrapper intercepts attachment upload and download requests (and file Send downloads) for zero-copy streaming
  * to/from R2. Workers R2 binding can accept request.body directly for uploads,
  * and r2Object.body can be passed directly to Response for downloads.
  * See: https://blog.cloudflare.com/zh-cn/r2-ga/
@@ -18,7 +18,12 @@
  */
 
 import RustWorker from "../build/index.js";
-import { base64UrlDecode, handleAzureUpload, handleDownload } from "./attachments.js";
+import {
+  base64UrlDecode,
+  handleAzureUpload,
+  handleDownload,
+  handleSendDownload,
+} from "./attachments.js";
 
 function getBearerToken(request) {
   const auth = request.headers.get("Authorization") || request.headers.get("authorization");
@@ -54,13 +59,27 @@ function normalizePathname(pathname) {
   return pathname.replace(/\/+$/, "");
 }
 
-async function getHeavyDoShardKey(request, url) {
-  const pathname = url.pathname;
+// BASE_PATH as the Rust side reads it: "/vault" for "vault", "/vault/" or "/vault"; "" at the root.
+function normalizeBasePath(basePath) {
+  if (typeof basePath !== "string") return "";
+  const v = basePath.trim().replace(/^\/+|\/+$/g, "");
+  return v ? `/${v}` : "";
+}
+
+// The path the routes below are written for, with BASE_PATH removed; null outside of it.
+function routePathname(pathname, basePath) {
+  if (!basePath) return pathname;
+  if (pathname === basePath) return "/";
+  return pathname.startsWith(`${basePath}/`) ? pathname.slice(basePath.length) : null;
+}
+
+async function getHeavyDoShardKey(request, pathname) {
 
   // Registration endpoints and 2FA recovery are not JWT-authenticated; request body uses `email` as username.
   if (
     pathname === "/identity/accounts/register" ||
     pathname === "/identity/accounts/register/finish" ||
+    pathname === "/api/accounts/register" ||
     pathname === "/api/two-factor/recover"
   ) {
     try {
@@ -85,10 +104,12 @@ async function getHeavyDoShardKey(request, url) {
 const HEAVY_DO_ROUTE_METHODS = new Map([
   // Import
   ["/api/ciphers/import", new Set(["POST"])],
+  ["/api/ciphers/import-organization", new Set(["POST"])],
 
   // Identity/Auth (password hashing / verification)
   ["/identity/accounts/register", new Set(["POST"])],
   ["/identity/accounts/register/finish", new Set(["POST"])],
+  ["/api/accounts/register", new Set(["POST"])],
 
   // Password/KDF changes
   ["/api/accounts/password", new Set(["POST"])],
@@ -110,8 +131,8 @@ const HEAVY_DO_ROUTE_METHODS = new Map([
   ["/api/two-factor/recover", new Set(["POST"])],
 ]);
 
-function shouldOffloadToHeavyDo(request, url) {
-  const methods = HEAVY_DO_ROUTE_METHODS.get(url.pathname);
+function shouldOffloadToHeavyDo(request, pathname) {
+  const methods = HEAVY_DO_ROUTE_METHODS.get(pathname);
   if (!methods) return false;
   const method = (request.method || "GET").toUpperCase();
   return methods.has(method);
@@ -149,83 +170,152 @@ function parseDownloadPath(path) {
   return null;
 }
 
-// Main fetch handler
-export default {
-  async fetch(request, env, ctx) {
-    // Normalize pathname to avoid trailing slashes
-    const url = new URL(request.url);
-    url.pathname = normalizePathname(url.pathname);
-    request = new Request(url.toString(), request);
-    const method = (request.method || "GET").toUpperCase();
-
-    // Optional: route selected CPU-heavy endpoints to Durable Objects.
-    // This keeps the main Worker on a low-CPU path while allowing heavy work to complete.
-    if (env.HEAVY_DO) {
-      // Token endpoint:
-      // - password grant is CPU-heavy (password verification) => offload
-      // - refresh_token grant is lightweight (JWT HS256 verify) => keep in Worker/WASM
-      if (url.pathname === "/identity/connect/token" && method === "POST") {
-        const body = await request.clone().text();
-        const params = new URLSearchParams(body);
-        const grantType = params.get("grant_type");
-        if (grantType !== "refresh_token") {
-          const shardKey = normalizeUsername(params.get("username"));
-          const name = shardKey ? `user:${shardKey}` : "user:default";
-          const id = env.HEAVY_DO.idFromName(name);
-          const stub = env.HEAVY_DO.get(id);
-          return stub.fetch(request, { body });
-        }
-      } else if (shouldOffloadToHeavyDo(request, url)) {
-        const shardKey = await getHeavyDoShardKey(request, url);
+// Parse send download route: /api/sends/{send_id}/{file_id}
+function parseSendDownloadPath(path) {
+  const parts = path.replace(/^\//, "").split("/");
+  // Expected: ["api", "sends", "{send_id}", "{file_id}"]
+  if (parts.length === 4 && parts[0] === "api" && parts[1] === "sends") {
+    return { sendId: parts[2], fileId: parts[3] };
+  }
+  return null;
+}
+
+// Routes a request to the JS fast paths, the Durable Object or the Rust WASM module.
+async function handleRequest(request, env, ctx) {
+  // Normalize pathname to avoid trailing slashes
+  const url = new URL(request.url);
+  url.pathname = normalizePathname(url.pathname);
+  request = new Request(url.toString(), request);
+  const method = (request.method || "GET").toUpperCase();
+  const pathname = routePathname(url.pathname, normalizeBasePath(env.BASE_PATH));
+  if (pathname === null) {
+    // Outside of BASE_PATH: let the Rust router answer with its 404
+    const worker = new RustWorker(ctx, env);
+    return worker.fetch(request);
+  }
+
+  // Optional: route selected CPU-heavy endpoints to Durable Objects.
+  // This keeps the main Worker on a low-CPU path while allowing heavy work to complete.
+  if (env.HEAVY_DO) {
+    // Token endpoint:
+    // - password grant is CPU-heavy (password verification) => offload
+    // - refresh_token grant is lightweight (JWT HS256 verify) => keep in Worker/WASM
+    if (pathname === "/identity/connect/token" && method === "POST") {
+      const body = await request.clone().text();
+      const params = new URLSearchParams(body);
+      const grantType = params.get("grant_type");
+      if (grantType !== "refresh_token") {
+        const shardKey = normalizeUsername(params.get("username"));
         const name = shardKey ? `user:${shardKey}` : "user:default";
         const id = env.HEAVY_DO.idFromName(name);
         const stub = env.HEAVY_DO.get(id);
-        return stub.fetch(request);
+        return stub.fetch(request, { body });
       }
+    } else if (shouldOffloadToHeavyDo(request, pathname)) {
+      const shardKey = await getHeavyDoShardKey(request, pathname);
+      const name = shardKey ? `user:${shardKey}` : "user:default";
+      const id = env.HEAVY_DO.idFromName(name);
+      const stub = env.HEAVY_DO.get(id);
+      return stub.fetch(request);
     }
+  }
 
-    // Attachment upload/download fast-path (R2 zero-copy streaming + JWT validation)
-    if (method === "PUT") {
-      const parsed = parseAzureUploadPath(url.pathname);
-      if (parsed) {
-        const token = url.searchParams.get("token");
-        if (!token) {
-          return new Response(
-            JSON.stringify({ error: "Missing upload token" }), 
-            { status: 401, headers: { "Content-Type": "application/json" } }
-          );
-        }
-        return handleAzureUpload(
-          request,
-          env,
-          parsed.cipherId,
-          parsed.attachmentId,
-          token
+  // Attachment upload/download fast-path (R2 zero-copy streaming + JWT validation)
+  if (method === "PUT") {
+    const parsed = parseAzureUploadPath(pathname);
+    if (parsed) {
+      const token = url.searchParams.get("token");
+      if (!token) {
+        return new Response(
+          JSON.stringify({ error: "Missing upload token" }), 
+          { status: 401, headers: { "Content-Type": "application/json" } }
         );
       }
-    } else if (method === "GET") {
-      const parsed = parseDownloadPath(url.pathname);
-      if (parsed) {
-        const token = url.searchParams.get("token");
-        if (!token) {
-          return new Response(
-            JSON.stringify({ error: "Missing download token" }),
-            { status: 401, headers: { "Content-Type": "application/json" } }
-          );
-        }
-        return handleDownload(
-          request,
-          env,
-          parsed.cipherId,
-          parsed.attachmentId,
-          token
+      return handleAzureUpload(
+        request,
+        env,
+        parsed.cipherId,
+        parsed.attachmentId,
+        token
+      );
+    }
+  } else if (method === "GET") {
+    const parsed = parseDownloadPath(pathname);
+    if (parsed) {
+      const token = url.searchParams.get("token");
+      if (!token) {
+        return new Response(
+          JSON.stringify({ error: "Missing download token" }),
+          { status: 401, headers: { "Content-Type": "application/json" } }
         );
       }
+      return handleDownload(
+        request,
+        env,
+        parsed.cipherId,
+        parsed.attachmentId,
+        token
+      );
     }
 
-    // Pass all other requests to Rust WASM
-    const worker = new RustWorker(ctx, env);
-    return worker.fetch(request);
+    const sendParsed = parseSendDownloadPath(pathname);
+    if (sendParsed) {
+      const token = url.searchParams.get("token");
+      if (!token) {
+        return new Response(
+          JSON.stringify({ error: "Missing download token" }),
+          { status: 401, headers: { "Content-Type": "application/json" } }
+        );
+      }
+      return handleSendDownload(
+        request,
+        env,
+        sendParsed.sendId,
+        sendParsed.fileId,
+        token
+      );
+    }
+  }
+
+  // Pass all other requests to Rust WASM
+  const worker = new RustWorker(ctx, env);
+  return worker.fetch(request);
+}
+
+// The Bitwarden `ErrorResponseModel`, as the Rust side returns it (see `error_model` in
+// src/error.rs), for requests that failed before it could answer: a panic aborts the WASM call.
+function internalErrorResponse(request) {
+  const message = "Internal server error";
+  const headers = { "Content-Type": "application/json" };
+  const requestId = request.headers.get("cf-ray");
+  if (requestId) headers["x-request-id"] = requestId;
+  return new Response(
+    JSON.stringify({
+      error: message,
+      message,
+      validationErrors: { "": [message] },
+      errorModel: { message, object: "error" },
+      exceptionMessage: null,
+      exceptionStackTrace: null,
+      innerExceptionMessage: null,
+      object: "error",
+    }),
+    { status: 500, headers }
+  );
+}
+
+// Main fetch handler
+export default {
+  async fetch(request, env, ctx) {
+    try {
+      return await handleRequest(request, env, ctx);
+    } catch (err) {
+      // Panics were already logged with their request id by the Rust panic hook
+      console.error(
+        JSON.stringify({ requestId: request.headers.get("cf-ray"), error: String(err) })
+      );
+      return internalErrorResponse(request);
+    }
   },
 
   async scheduled(event, env, ctx) {
@@ -235,6 +325,7 @@ export default {
   },
 };
 
-// Re-export Rust Durable Object class implemented in WASM.
-// wrangler.toml binds HEAVY_DO -> class_name = "HeavyDo".
-export { HeavyDo } from "../build/index.js";
+// Re-export the Rust Durable Object classes implemented in WASM.
+// wrangler.toml binds HEAVY_DO -> class_name = "HeavyDo" and
+// NOTIFICATIONS_HUB -> class_name = "NotificationsHub".
+export { HeavyDo, NotificationsHub } from "../build/index.js";
@@ -1,16 +1,21 @@
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use thiserror::Error;
 
+use crate::logging::ErrorDetail;
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Worker error: {0}")]
     Worker(#[from] worker::Error),
 
+    /// A failed query; the source is logged, never sent to the client.
     #[error("Database query failed")]
-    Database,
+    Database(Option<String>),
 
     #[error("Not found: {0}")]
     NotFound(String),
@@ -21,17 +26,154 @@ pub enum AppError {
     #[error("Unauthorized: {0}")]
     Unauthorized(String),
 
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    /// The request would duplicate an existing row.
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
     #[error("Too many requests: {0}")]
     TooManyRequests(String),
 
+    /// The path exists, but not for this method.
+    #[error("Method not allowed")]
+    MethodNotAllowed,
+
+    /// The request body is over the route's limit.
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
     #[error("Cryptography error: {0}")]
     Crypto(String),
 
+    /// An unexpected failure; the source is logged, never sent to the client.
     #[error("Internal server error")]
-    Internal,
+    Internal(Option<String>),
 
     #[error("Two factor authentication required")]
     TwoFactorRequired(Value),
+
+    /// An import that failed part way, with the status of what stopped it; the body says how
+    /// far it got.
+    #[error("Import incomplete: {1}")]
+    ImportIncomplete(StatusCode, Value),
+
+    /// Invalid request fields, keyed by their path in the payload (`ciphers[3].notes`).
+    #[error("Validation failed: {0:?}")]
+    Validation(BTreeMap<String, Vec<String>>),
+
+    /// OAuth2 token endpoint error (RFC 6749 section 5.2), e.g. `invalid_scope`.
+    #[error("OAuth error {0}: {1}")]
+    OAuth(&'static str, String),
+}
+
+/// `map_err` closure turning any error into [`AppError::Database`], tagged with the call site
+/// and an optional description of the operation: `.map_err(db_error!("load user"))`.
+macro_rules! db_error {
+    () => {
+        |err| $crate::error::AppError::database(concat!(file!(), ":", line!()), err)
+    };
+    ($context:expr) => {
+        |err| {
+            $crate::error::AppError::database(
+                format!("{} ({}:{})", $context, file!(), line!()),
+                err,
+            )
+        }
+    };
+}
+
+/// Like [`db_error!`], for [`AppError::Internal`].
+macro_rules! internal_error {
+    () => {
+        |err| $crate::error::AppError::internal(concat!(file!(), ":", line!()), err)
+    };
+    ($context:expr) => {
+        |err| {
+            $crate::error::AppError::internal(
+                format!("{} ({}:{})", $context, file!(), line!()),
+                err,
+            )
+        }
+    };
+}
+
+pub(crate) use {db_error, internal_error};
+
+impl AppError {
+    /// A database failure while doing `context`, keeping `source` for the logs.
+    pub fn database(context: impl std::fmt::Display, source: impl std::fmt::Display) -> Self {
+        AppError::Database(Some(format!("{context}: {source}")))
+    }
+
+    /// An internal failure while doing `context`, keeping `source` for the logs.
+    pub fn internal(context: impl std::fmt::Display, source: impl std::fmt::Display) -> Self {
+        AppError::Internal(Some(format!("{context}: {source}")))
+    }
+
+    /// A validation error for a single request field.
+    pub fn validation(field: impl Into<String>, message: impl Into<String>) -> Self {
+        AppError::Validation(BTreeMap::from([(field.into(), vec![message.into()])]))
+    }
+
+    /// Maps errors raised while issuing tokens onto the OAuth2 shape the identity clients parse.
+    pub fn into_oauth(self) -> Self {
+        match self {
+            AppError::BadRequest(msg) => AppError::OAuth("invalid_request", msg),
+            AppError::Unauthorized(msg) | AppError::Forbidden(msg) | AppError::NotFound(msg) => {
+                AppError::OAuth("invalid_grant", msg)
+            }
+            AppError::Validation(errors) => AppError::OAuth(
+                "invalid_request",
+                errors.into_values().flatten().next().unwrap_or_default(),
+            ),
+            other => other,
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            // A body without a length that outgrew the route's limit while being read
+            JsonRejection::BytesRejection(_)
+                if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE =>
+            {
+                AppError::PayloadTooLarge("The request body is too large".to_string())
+            }
+            // Reading the body failed; there is no field to blame
+            JsonRejection::BytesRejection(_) => AppError::BadRequest(rejection.body_text()),
+            _ => AppError::validation("", rejection.body_text()),
+        }
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::validation("", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::validation("", rejection.body_text())
+    }
+}
+
+/// The `ErrorResponseModel` the Bitwarden clients parse; they show `message`, or the
+/// `validationErrors` when there are any.
+fn error_model(message: &str, validation_errors: Value) -> Value {
+    json!({
+        "error": message,
+        "message": message,
+        "validationErrors": validation_errors,
+        "errorModel": { "message": message, "object": "error" },
+        "exceptionMessage": null,
+        "exceptionStackTrace": null,
+        "innerExceptionMessage": null,
+        "object": "error",
+    })
 }
 
 impl IntoResponse for AppError {
@@ -41,34 +183,271 @@ impl IntoResponse for AppError {
                 // Return 400 Bad Request with the 2FA required JSON response as expected by clients
                 (StatusCode::BAD_REQUEST, Json(json_body)).into_response()
             }
+            AppError::ImportIncomplete(status, details) => {
+                let message = details["message"].as_str().unwrap_or("Import incomplete");
+                let mut body = error_model(message, Value::Null);
+                if let (Some(body), Value::Object(details)) = (body.as_object_mut(), details) {
+                    body.extend(details);
+                }
+                (status, Json(body)).into_response()
+            }
+            AppError::Validation(errors) => (
+                StatusCode::BAD_REQUEST,
+                Json(error_model("The model state is invalid.", json!(errors))),
+            )
+                .into_response(),
+            AppError::OAuth(error, description) => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": error,
+                    "error_description": description,
+                    "ErrorModel": { "Message": description, "Object": "error" },
+                })),
+            )
+                .into_response(),
             other => {
-                let (status, error_message) = match other {
+                let internal = StatusCode::INTERNAL_SERVER_ERROR;
+                let (status, error_message, detail) = match other {
                     AppError::Worker(e) => (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        format!("Worker error: {}", e),
+                        internal,
+                        "Internal server error".to_string(),
+                        Some(format!("Worker error: {e}")),
                     ),
-                    AppError::Database => (
-                        StatusCode::INTERNAL_SERVER_ERROR,
+                    AppError::Database(source) => (
+                        internal,
                         "Database error".to_string(),
+                        Some(format!(
+                            "Database error: {}",
+                            source.as_deref().unwrap_or("no details")
+                        )),
                     ),
-                    AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
-                    AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-                    AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
-                    AppError::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+                    AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg, None),
+                    AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg, None),
+                    AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg, None),
+                    AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg, None),
+                    AppError::Conflict(msg) => (StatusCode::CONFLICT, msg, None),
+                    AppError::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg, None),
+                    AppError::MethodNotAllowed => (
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "Method not allowed".to_string(),
+                        None,
+                    ),
+                    AppError::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg, None),
                     AppError::Crypto(msg) => (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        format!("Crypto error: {}", msg),
+                        internal,
+                        "Internal server error".to_string(),
+                        Some(format!("Crypto error: {msg}")),
                     ),
-                    AppError::Internal => (
-                        StatusCode::INTERNAL_SERVER_ERROR,
+                    AppError::Internal(source) => (
+                        internal,
                         "Internal server error".to_string(),
+                        Some(format!(
+                            "Internal error: {}",
+                            source.as_deref().unwrap_or("no details")
+                        )),
                     ),
-                    AppError::TwoFactorRequired(_) => unreachable!(),
+                    AppError::TwoFactorRequired(_)
+                    | AppError::ImportIncomplete(..)
+                    | AppError::Validation(_)
+                    | AppError::OAuth(..) => unreachable!(),
                 };
 
-                let body = Json(json!({ "error": error_message }));
-                (status, body).into_response()
+                // Clients without field errors still look for a message under the empty key.
+                let body = error_model(&error_message, json!({ "": [&error_message] }));
+                let mut response = (status, Json(body)).into_response();
+                // Logged with the request id by `logging::request_log`
+                if let Some(detail) = detail {
+                    response.extensions_mut().insert(ErrorDetail(detail));
+                }
+                response
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::block_on;
+    use http_body_util::BodyExt;
+
+    fn render(error: AppError) -> (StatusCode, Value, Option<String>) {
+        let response = error.into_response();
+        let status = response.status();
+        let detail = response
+            .extensions()
+            .get::<ErrorDetail>()
+            .map(|detail| detail.0.clone());
+        let bytes = block_on(response.into_body().collect()).unwrap().to_bytes();
+        (status, serde_json::from_slice(&bytes).unwrap(), detail)
+    }
+
+    #[test]
+    fn messages_render_as_the_error_model() {
+        let (status, body, detail) = render(AppError::NotFound("Cipher not found".to_string()));
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            json!({
+                "error": "Cipher not found",
+                "message": "Cipher not found",
+                "validationErrors": { "": ["Cipher not found"] },
+                "errorModel": { "message": "Cipher not found", "object": "error" },
+                "exceptionMessage": null,
+                "exceptionStackTrace": null,
+                "innerExceptionMessage": null,
+                "object": "error",
+            })
+        );
+        assert_eq!(detail, None);
+    }
+
+    #[test]
+    fn each_variant_has_its_status() {
+        let message = || "message".to_string();
+        for (error, status) in [
+            (AppError::BadRequest(message()), StatusCode::BAD_REQUEST),
+            (AppError::Unauthorized(message()), StatusCode::UNAUTHORIZED),
+            (AppError::Forbidden(message()), StatusCode::FORBIDDEN),
+            (AppError::Conflict(message()), StatusCode::CONFLICT),
+            (
+                AppError::TooManyRequests(message()),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                AppError::PayloadTooLarge(message()),
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+        ] {
+            let (rendered, body, _) = render(error);
+            assert_eq!(rendered, status);
+            assert_eq!(body["message"], "message");
+            assert_eq!(body["errorModel"]["message"], "message");
+            assert_eq!(body["object"], "error");
+        }
+        let (status, body, _) = render(AppError::MethodNotAllowed);
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body["message"], "Method not allowed");
+    }
+
+    #[test]
+    fn failures_keep_their_details_out_of_the_body() {
+        for (error, message, detail) in [
+            (
+                AppError::Database(Some("users.rs:10: no such table".to_string())),
+                "Database error",
+                "Database error: users.rs:10: no such table",
+            ),
+            (
+                AppError::Internal(None),
+                "Internal server error",
+                "Internal error: no details",
+            ),
+            (
+                AppError::Crypto("bad key".to_string()),
+                "Internal server error",
+                "Crypto error: bad key",
+            ),
+        ] {
+            let (status, body, logged) = render(error);
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(body["message"], message);
+            assert!(!body.to_string().contains(&detail[detail.len() - 5..]));
+            assert_eq!(logged.as_deref(), Some(detail));
+        }
+
+        let worker = worker::Error::RustError("binding missing".to_string());
+        let detail = format!("Worker error: {worker}");
+        let (status, body, logged) = render(AppError::Worker(worker));
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["message"], "Internal server error");
+        assert_eq!(logged, Some(detail));
+    }
+
+    #[test]
+    fn validation_errors_are_listed_by_field() {
+        let errors = BTreeMap::from([
+            ("ciphers[0].name".to_string(), vec!["Too long.".to_string()]),
+            (
+                "folders[1].name".to_string(),
+                vec!["Too long.".to_string(), "Not encrypted.".to_string()],
+            ),
+        ]);
+        let (status, body, _) = render(AppError::Validation(errors));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], "The model state is invalid.");
+        assert_eq!(
+            body["validationErrors"],
+            json!({
+                "ciphers[0].name": ["Too long."],
+                "folders[1].name": ["Too long.", "Not encrypted."],
+            })
+        );
+        assert_eq!(
+            body["errorModel"],
+            json!({ "message": "The model state is invalid.", "object": "error" })
+        );
+    }
+
+    #[test]
+    fn oauth_errors_keep_their_shape() {
+        let (status, body, _) = render(AppError::OAuth(
+            "invalid_grant",
+            "Username or password is incorrect. Try again".to_string(),
+        ));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({
+                "error": "invalid_grant",
+                "error_description": "Username or password is incorrect. Try again",
+                "ErrorModel": {
+                    "Message": "Username or password is incorrect. Try again",
+                    "Object": "error",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn token_errors_map_onto_oauth_codes() {
+        let code = |error: AppError| match error.into_oauth() {
+            AppError::OAuth(code, _) => Some(code),
+            _ => None,
+        };
+        let message = || "message".to_string();
+        assert_eq!(
+            code(AppError::BadRequest(message())),
+            Some("invalid_request")
+        );
+        assert_eq!(
+            code(AppError::Unauthorized(message())),
+            Some("invalid_grant")
+        );
+        assert_eq!(code(AppError::Forbidden(message())), Some("invalid_grant"));
+        assert_eq!(code(AppError::NotFound(message())), Some("invalid_grant"));
+        assert_eq!(
+            code(AppError::validation("scope", message())),
+            Some("invalid_request")
+        );
+        assert_eq!(code(AppError::TooManyRequests(message())), None);
+        assert_eq!(code(AppError::Internal(None)), None);
+    }
+
+    #[test]
+    fn two_factor_and_import_bodies_pass_through() {
+        let challenge = json!({ "error": "invalid_grant", "TwoFactorProviders": ["0"] });
+        let (status, body, _) = render(AppError::TwoFactorRequired(challenge.clone()));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, challenge);
+
+        let (status, body, _) = render(AppError::ImportIncomplete(
+            StatusCode::CONFLICT,
+            json!({ "message": "Stopped at 3", "resumeOffset": 3 }),
+        ));
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["message"], "Stopped at 3");
+        assert_eq!(body["errorModel"]["message"], "Stopped at 3");
+        assert_eq!(body["resumeOffset"], 3);
+    }
+}
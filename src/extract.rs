@@ -0,0 +1,136 @@
+//! `Json`, `Path` and `Query` extractors whose rejections are [`AppError`]s, so a malformed
+//! request gets a 400 in the error model the clients parse instead of axum's plain-text reply.
+
+use axum::extract::{FromRequest, FromRequestParts};
+
+use crate::error::AppError;
+
+/// `axum::Json` for request bodies; deserialization failures become validation errors.
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct AppJson<T>(pub T);
+
+/// `axum::extract::Path` with its rejection reported as a validation error.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct AppPath<T>(pub T);
+
+/// `axum::extract::Query` with its rejection reported as a validation error.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct AppQuery<T>(pub T);
+
+#[cfg(test)]
+mod tests {
+    use crate::native::testing::{self, ORIGIN};
+    use crate::native::Env;
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, StatusCode};
+    use serde_json::{json, Value};
+
+    fn env() -> (Env, String) {
+        let env = testing::env();
+        let token = testing::user(&env, "alice");
+        (env, token)
+    }
+
+    fn send(
+        env: &Env,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        content_type: &str,
+        body: &str,
+    ) -> (StatusCode, Value) {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("{ORIGIN}{path}"))
+            .header(header::CONTENT_TYPE, content_type);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let req = req.body(Body::from(body.to_string())).unwrap();
+        testing::json_response(testing::fetch(env, req))
+    }
+
+    /// The single validation message of an error model body.
+    #[track_caller]
+    fn validation_message(body: &Value) -> &str {
+        assert_eq!(body["object"], "error", "{body}");
+        assert_eq!(body["message"], "The model state is invalid.", "{body}");
+        body["validationErrors"][""][0].as_str().unwrap()
+    }
+
+    #[test]
+    fn truncated_json_is_a_validation_error() {
+        let (env, token) = env();
+        let (status, body) = send(
+            &env,
+            Method::POST,
+            "/api/ciphers",
+            Some(&token),
+            "application/json",
+            r#"{"type": 1, "name": "2.bmFtZQ==|aXY=|bWFj", "login": {"#,
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            validation_message(&body).contains("EOF while parsing"),
+            "{body}"
+        );
+    }
+
+    #[test]
+    fn wrong_typed_fields_name_the_problem() {
+        let (env, _) = env();
+        let register = json!({
+            "email": "bob@example.com",
+            "masterPasswordHash": "hash",
+            "userSymmetricKey": "2.a2V5|aXY=|bWFj",
+            "userAsymmetricKeys": { "publicKey": "public", "encryptedPrivateKey": "private" },
+            "kdf": "pbkdf2",
+            "kdfIterations": 600000,
+        });
+        let (status, body) = send(
+            &env,
+            Method::POST,
+            "/identity/accounts/register",
+            None,
+            "application/json",
+            &register.to_string(),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let message = validation_message(&body);
+        assert!(message.contains("kdf"), "{message}");
+        assert!(message.contains("invalid type"), "{message}");
+    }
+
+    #[test]
+    fn bodies_that_arent_json_are_validation_errors() {
+        let (env, token) = env();
+        let (status, body) = send(
+            &env,
+            Method::POST,
+            "/api/folders",
+            Some(&token),
+            "text/plain",
+            r#"{"name": "2.Zm9sZGVy|aXY=|bWFj"}"#,
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(validation_message(&body).contains("Content-Type"), "{body}");
+    }
+
+    #[test]
+    fn malformed_queries_are_validation_errors() {
+        let (env, token) = env();
+        let (status, body) = send(
+            &env,
+            Method::GET,
+            "/api/ciphers?type=login",
+            Some(&token),
+            "application/json",
+            "",
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(validation_message(&body).contains("type"), "{body}");
+    }
+}
@@ -0,0 +1,782 @@
+//! Bitwarden's global equivalent domains: groups of domains owned by the same service (e.g.
+//! `google.com`, `youtube.com` and `gmail.com`), which clients treat as one site for URI matching.
+//!
+//! Users can exclude groups in their domain settings. Type ids follow Bitwarden's
+//! `GlobalEquivalentDomainsType` and are stored in `users.excluded_globals`, so they must never be
+//! renumbered; retired ids are simply left out.
+
+use serde_json::{json, Value};
+
+/// One group of equivalent domains.
+#[derive(Debug)]
+pub struct GlobalDomains {
+    pub atype: i32,
+    pub domains: &'static [&'static str],
+}
+
+impl GlobalDomains {
+    /// The group as listed in `globalEquivalentDomains`.
+    pub fn to_json(&self, excluded: bool) -> Value {
+        json!({
+            "type": self.atype,
+            "domains": self.domains,
+            "excluded": excluded,
+        })
+    }
+}
+
+/// Every group, ordered by type id.
+pub static GLOBAL_DOMAINS: &[GlobalDomains] = &[
+    GlobalDomains {
+        atype: 0,
+        domains: &["google.com", "youtube.com", "gmail.com"],
+    },
+    GlobalDomains {
+        atype: 1,
+        domains: &["apple.com", "icloud.com"],
+    },
+    GlobalDomains {
+        atype: 2,
+        domains: &["tdameritrade.com", "ameritrade.com"],
+    },
+    GlobalDomains {
+        atype: 3,
+        domains: &["bankofamerica.com", "bofa.com", "mbna.com", "usecfo.com"],
+    },
+    GlobalDomains {
+        atype: 4,
+        domains: &["sprint.com", "sprintpcs.com", "nextel.com"],
+    },
+    GlobalDomains {
+        atype: 5,
+        domains: &["wellsfargo.com", "wf.com", "wellsfargoadvisors.com"],
+    },
+    GlobalDomains {
+        atype: 6,
+        domains: &["mymerrill.com", "ml.com", "merrilledge.com"],
+    },
+    GlobalDomains {
+        atype: 7,
+        domains: &[
+            "accountonline.com",
+            "citi.com",
+            "citibank.com",
+            "citicards.com",
+            "citibankonline.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 8,
+        domains: &[
+            "cnet.com",
+            "cnettv.com",
+            "com.com",
+            "download.com",
+            "news.com",
+            "search.com",
+            "upload.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 9,
+        domains: &[
+            "bananarepublic.com",
+            "gap.com",
+            "oldnavy.com",
+            "piperlime.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 10,
+        domains: &[
+            "bing.com",
+            "hotmail.com",
+            "live.com",
+            "microsoft.com",
+            "msn.com",
+            "passport.net",
+            "windows.com",
+            "microsoftonline.com",
+            "office.com",
+            "office365.com",
+            "microsoftstore.com",
+            "xbox.com",
+            "azure.com",
+            "windowsazure.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 11,
+        domains: &["ua2go.com", "ual.com", "united.com", "unitedwifi.com"],
+    },
+    GlobalDomains {
+        atype: 12,
+        domains: &["overture.com", "yahoo.com"],
+    },
+    GlobalDomains {
+        atype: 13,
+        domains: &["zonealarm.com", "zonelabs.com"],
+    },
+    GlobalDomains {
+        atype: 14,
+        domains: &["paypal.com", "paypal-search.com"],
+    },
+    GlobalDomains {
+        atype: 15,
+        domains: &["avon.com", "youravon.com"],
+    },
+    GlobalDomains {
+        atype: 16,
+        domains: &[
+            "diapers.com",
+            "soap.com",
+            "wag.com",
+            "yoyo.com",
+            "beautybar.com",
+            "casa.com",
+            "afterschool.com",
+            "vine.com",
+            "bookworm.com",
+            "look.com",
+            "vinemarket.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 17,
+        domains: &["1800contacts.com", "800contacts.com"],
+    },
+    GlobalDomains {
+        atype: 18,
+        domains: &[
+            "amazon.com",
+            "amazon.com.be",
+            "amazon.ae",
+            "amazon.ca",
+            "amazon.co.uk",
+            "amazon.com.au",
+            "amazon.com.br",
+            "amazon.com.mx",
+            "amazon.com.tr",
+            "amazon.de",
+            "amazon.es",
+            "amazon.fr",
+            "amazon.in",
+            "amazon.it",
+            "amazon.nl",
+            "amazon.pl",
+            "amazon.sa",
+            "amazon.se",
+            "amazon.sg",
+        ],
+    },
+    GlobalDomains {
+        atype: 19,
+        domains: &["cox.com", "cox.net", "coxbusiness.com"],
+    },
+    GlobalDomains {
+        atype: 20,
+        domains: &["mynortonaccount.com", "norton.com"],
+    },
+    GlobalDomains {
+        atype: 21,
+        domains: &["verizon.com", "verizon.net"],
+    },
+    GlobalDomains {
+        atype: 22,
+        domains: &["rakuten.com", "buy.com"],
+    },
+    GlobalDomains {
+        atype: 23,
+        domains: &["siriusxm.com", "sirius.com"],
+    },
+    GlobalDomains {
+        atype: 24,
+        domains: &[
+            "ea.com",
+            "origin.com",
+            "play4free.com",
+            "tiberiumalliance.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 25,
+        domains: &[
+            "37signals.com",
+            "basecamp.com",
+            "basecamphq.com",
+            "highrisehq.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 26,
+        domains: &["steampowered.com", "steamcommunity.com", "steamgames.com"],
+    },
+    GlobalDomains {
+        atype: 27,
+        domains: &["chart.io", "chartio.com"],
+    },
+    GlobalDomains {
+        atype: 28,
+        domains: &["gotomeeting.com", "citrixonline.com"],
+    },
+    GlobalDomains {
+        atype: 29,
+        domains: &["gogoair.com", "gogoinflight.com"],
+    },
+    GlobalDomains {
+        atype: 30,
+        domains: &["mysql.com", "oracle.com"],
+    },
+    GlobalDomains {
+        atype: 31,
+        domains: &["discover.com", "discovercard.com"],
+    },
+    GlobalDomains {
+        atype: 32,
+        domains: &["dcu.org", "dcu-online.org"],
+    },
+    GlobalDomains {
+        atype: 33,
+        domains: &["healthcare.gov", "cms.gov"],
+    },
+    GlobalDomains {
+        atype: 34,
+        domains: &["pepco.com", "pepcoholdings.com"],
+    },
+    GlobalDomains {
+        atype: 35,
+        domains: &["century21.com", "21online.com"],
+    },
+    GlobalDomains {
+        atype: 36,
+        domains: &["comcast.com", "comcast.net", "xfinity.com"],
+    },
+    GlobalDomains {
+        atype: 37,
+        domains: &["cricketwireless.com", "aiowireless.com"],
+    },
+    GlobalDomains {
+        atype: 38,
+        domains: &["mandtbank.com", "mtb.com"],
+    },
+    GlobalDomains {
+        atype: 39,
+        domains: &["dropbox.com", "getdropbox.com"],
+    },
+    GlobalDomains {
+        atype: 40,
+        domains: &["snapfish.com", "snapfish.ca"],
+    },
+    GlobalDomains {
+        atype: 41,
+        domains: &["alibaba.com", "aliexpress.com", "aliyun.com", "net.cn"],
+    },
+    GlobalDomains {
+        atype: 42,
+        domains: &["playstation.com", "sonyentertainmentnetwork.com"],
+    },
+    GlobalDomains {
+        atype: 43,
+        domains: &[
+            "mercadolivre.com",
+            "mercadolivre.com.br",
+            "mercadolibre.com",
+            "mercadolibre.com.ar",
+            "mercadolibre.com.mx",
+        ],
+    },
+    GlobalDomains {
+        atype: 44,
+        domains: &["zendesk.com", "zopim.com"],
+    },
+    GlobalDomains {
+        atype: 45,
+        domains: &["autodesk.com", "tinkercad.com"],
+    },
+    GlobalDomains {
+        atype: 46,
+        domains: &[
+            "railnation.ru",
+            "railnation.de",
+            "rail-nation.com",
+            "railnation.gr",
+            "railnation.us",
+            "trucknation.de",
+            "traviangames.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 47,
+        domains: &["wpcu.coop", "wpcuonline.com"],
+    },
+    GlobalDomains {
+        atype: 48,
+        domains: &["mathletics.com", "mathletics.com.au", "mathletics.co.uk"],
+    },
+    GlobalDomains {
+        atype: 49,
+        domains: &["discountbank.co.il", "telebank.co.il"],
+    },
+    GlobalDomains {
+        atype: 50,
+        domains: &["mi.com", "xiaomi.com"],
+    },
+    GlobalDomains {
+        atype: 51,
+        domains: &["facebook.com", "messenger.com"],
+    },
+    GlobalDomains {
+        atype: 52,
+        domains: &["postepay.it", "poste.it"],
+    },
+    GlobalDomains {
+        atype: 53,
+        domains: &["skysports.com", "skybet.com", "skyvegas.com"],
+    },
+    GlobalDomains {
+        atype: 54,
+        domains: &[
+            "disneymoviesanywhere.com",
+            "go.com",
+            "disney.com",
+            "dadt.com",
+            "disneyplus.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 55,
+        domains: &["pokemon-gl.com", "pokemon.com"],
+    },
+    GlobalDomains {
+        atype: 56,
+        domains: &["myuv.com", "uvvu.com"],
+    },
+    GlobalDomains {
+        atype: 57,
+        domains: &["bank-yahav.co.il", "bankhapoalim.co.il"],
+    },
+    GlobalDomains {
+        atype: 58,
+        domains: &["mdsol.com", "imedidata.com"],
+    },
+    GlobalDomains {
+        atype: 59,
+        domains: &["sears.com", "shld.net"],
+    },
+    GlobalDomains {
+        atype: 60,
+        domains: &["xiami.com", "alipay.com"],
+    },
+    GlobalDomains {
+        atype: 61,
+        domains: &["belkin.com", "seedonk.com"],
+    },
+    GlobalDomains {
+        atype: 62,
+        domains: &["turbotax.com", "intuit.com"],
+    },
+    GlobalDomains {
+        atype: 63,
+        domains: &["shopify.com", "myshopify.com"],
+    },
+    GlobalDomains {
+        atype: 64,
+        domains: &[
+            "ebay.com",
+            "ebay.at",
+            "ebay.be",
+            "ebay.ca",
+            "ebay.ch",
+            "ebay.cn",
+            "ebay.co.jp",
+            "ebay.co.th",
+            "ebay.co.uk",
+            "ebay.com.au",
+            "ebay.com.hk",
+            "ebay.com.my",
+            "ebay.com.sg",
+            "ebay.com.tw",
+            "ebay.de",
+            "ebay.es",
+            "ebay.fr",
+            "ebay.ie",
+            "ebay.in",
+            "ebay.it",
+            "ebay.nl",
+            "ebay.ph",
+            "ebay.pl",
+        ],
+    },
+    GlobalDomains {
+        atype: 65,
+        domains: &["techdata.com", "techdata.ch"],
+    },
+    GlobalDomains {
+        atype: 66,
+        domains: &["schwab.com", "schwabplan.com"],
+    },
+    GlobalDomains {
+        atype: 68,
+        domains: &["tesla.com", "teslamotors.com"],
+    },
+    GlobalDomains {
+        atype: 69,
+        domains: &[
+            "morganstanley.com",
+            "morganstanleyclientserv.com",
+            "stockplanconnect.com",
+            "ms.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 70,
+        domains: &["taxact.com", "taxactonline.com"],
+    },
+    GlobalDomains {
+        atype: 71,
+        domains: &[
+            "mediawiki.org",
+            "wikibooks.org",
+            "wikidata.org",
+            "wikimedia.org",
+            "wikinews.org",
+            "wikipedia.org",
+            "wikiquote.org",
+            "wikisource.org",
+            "wikiversity.org",
+            "wikivoyage.org",
+            "wiktionary.org",
+        ],
+    },
+    GlobalDomains {
+        atype: 72,
+        domains: &[
+            "airbnb.at",
+            "airbnb.be",
+            "airbnb.ca",
+            "airbnb.ch",
+            "airbnb.cl",
+            "airbnb.co.cr",
+            "airbnb.co.id",
+            "airbnb.co.in",
+            "airbnb.co.kr",
+            "airbnb.co.nz",
+            "airbnb.co.uk",
+            "airbnb.co.ve",
+            "airbnb.com",
+            "airbnb.com.ar",
+            "airbnb.com.au",
+            "airbnb.com.bo",
+            "airbnb.com.br",
+            "airbnb.com.bz",
+            "airbnb.com.co",
+            "airbnb.com.ec",
+            "airbnb.com.gt",
+            "airbnb.com.hk",
+            "airbnb.com.hn",
+            "airbnb.com.mt",
+            "airbnb.com.my",
+            "airbnb.com.ni",
+            "airbnb.com.pa",
+            "airbnb.com.pe",
+            "airbnb.com.py",
+            "airbnb.com.sg",
+            "airbnb.com.sv",
+            "airbnb.com.tr",
+            "airbnb.com.tw",
+            "airbnb.cz",
+            "airbnb.de",
+            "airbnb.dk",
+            "airbnb.es",
+            "airbnb.fi",
+            "airbnb.fr",
+            "airbnb.gr",
+            "airbnb.gy",
+            "airbnb.hu",
+            "airbnb.ie",
+            "airbnb.is",
+            "airbnb.it",
+            "airbnb.jp",
+            "airbnb.mx",
+            "airbnb.nl",
+            "airbnb.no",
+            "airbnb.pl",
+            "airbnb.pt",
+            "airbnb.ru",
+            "airbnb.se",
+        ],
+    },
+    GlobalDomains {
+        atype: 73,
+        domains: &[
+            "eventbrite.at",
+            "eventbrite.be",
+            "eventbrite.ca",
+            "eventbrite.ch",
+            "eventbrite.cl",
+            "eventbrite.co",
+            "eventbrite.co.nz",
+            "eventbrite.co.uk",
+            "eventbrite.com",
+            "eventbrite.com.ar",
+            "eventbrite.com.au",
+            "eventbrite.com.br",
+            "eventbrite.com.mx",
+            "eventbrite.com.pe",
+            "eventbrite.de",
+            "eventbrite.dk",
+            "eventbrite.es",
+            "eventbrite.fi",
+            "eventbrite.fr",
+            "eventbrite.hk",
+            "eventbrite.ie",
+            "eventbrite.it",
+            "eventbrite.nl",
+            "eventbrite.pt",
+            "eventbrite.se",
+            "eventbrite.sg",
+        ],
+    },
+    GlobalDomains {
+        atype: 74,
+        domains: &[
+            "stackexchange.com",
+            "superuser.com",
+            "stackoverflow.com",
+            "serverfault.com",
+            "mathoverflow.net",
+            "askubuntu.com",
+            "stackapps.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 75,
+        domains: &["docusign.com", "docusign.net"],
+    },
+    GlobalDomains {
+        atype: 76,
+        domains: &[
+            "envato.com",
+            "themeforest.net",
+            "codecanyon.net",
+            "videohive.net",
+            "audiojungle.net",
+            "graphicriver.net",
+            "photodune.net",
+            "3docean.net",
+        ],
+    },
+    GlobalDomains {
+        atype: 77,
+        domains: &["x10hosting.com", "x10premium.com"],
+    },
+    GlobalDomains {
+        atype: 78,
+        domains: &["dnsomatic.com", "opendns.com", "umbrella.com"],
+    },
+    GlobalDomains {
+        atype: 79,
+        domains: &[
+            "cagreatamerica.com",
+            "canadaswonderland.com",
+            "carowinds.com",
+            "cedarfair.com",
+            "cedarpoint.com",
+            "dorneypark.com",
+            "kingsdominion.com",
+            "knotts.com",
+            "miadventure.com",
+            "schlitterbahn.com",
+            "valleyfair.com",
+            "visitkingsisland.com",
+            "worldsoffun.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 80,
+        domains: &["ubnt.com", "ui.com"],
+    },
+    GlobalDomains {
+        atype: 81,
+        domains: &["discordapp.com", "discord.com"],
+    },
+    GlobalDomains {
+        atype: 82,
+        domains: &["netcup.de", "netcup.eu", "customercontrolpanel.de"],
+    },
+    GlobalDomains {
+        atype: 83,
+        domains: &[
+            "yandex.com",
+            "ya.ru",
+            "yandex.az",
+            "yandex.by",
+            "yandex.co.il",
+            "yandex.com.am",
+            "yandex.com.ge",
+            "yandex.com.tr",
+            "yandex.ee",
+            "yandex.fi",
+            "yandex.fr",
+            "yandex.kg",
+            "yandex.kz",
+            "yandex.lt",
+            "yandex.lv",
+            "yandex.md",
+            "yandex.pl",
+            "yandex.ru",
+            "yandex.tj",
+            "yandex.tm",
+            "yandex.ua",
+            "yandex.uz",
+        ],
+    },
+    GlobalDomains {
+        atype: 84,
+        domains: &["sonyentertainmentnetwork.com", "sony.com"],
+    },
+    GlobalDomains {
+        atype: 85,
+        domains: &["proton.me", "protonmail.com", "protonvpn.com"],
+    },
+    GlobalDomains {
+        atype: 86,
+        domains: &["ubisoft.com", "ubi.com"],
+    },
+    GlobalDomains {
+        atype: 87,
+        domains: &["transferwise.com", "wise.com"],
+    },
+    GlobalDomains {
+        atype: 88,
+        domains: &[
+            "takeaway.com",
+            "just-eat.dk",
+            "just-eat.no",
+            "just-eat.fr",
+            "just-eat.ch",
+            "lieferando.de",
+            "lieferando.at",
+            "thuisbezorgd.nl",
+            "pyszne.pl",
+        ],
+    },
+    GlobalDomains {
+        atype: 89,
+        domains: &[
+            "atlassian.com",
+            "bitbucket.org",
+            "trello.com",
+            "statuspage.io",
+            "atlassian.net",
+            "jira.com",
+        ],
+    },
+    GlobalDomains {
+        atype: 90,
+        domains: &[
+            "pinterest.com",
+            "pinterest.com.au",
+            "pinterest.cl",
+            "pinterest.de",
+            "pinterest.dk",
+            "pinterest.es",
+            "pinterest.fr",
+            "pinterest.co.uk",
+            "pinterest.jp",
+            "pinterest.co.kr",
+            "pinterest.nz",
+            "pinterest.pt",
+            "pinterest.se",
+        ],
+    },
+];
+
+/// Looks a group up by its type id.
+#[allow(dead_code)] // Not every caller needs a single group yet
+pub fn find(atype: i32) -> Option<&'static GlobalDomains> {
+    GLOBAL_DOMAINS
+        .binary_search_by_key(&atype, |group| group.atype)
+        .ok()
+        .map(|index| &GLOBAL_DOMAINS[index])
+}
+
+/// `globalEquivalentDomains` with the user's exclusions applied: every group marked `excluded`
+/// for the settings page, or only the groups still in use for sync.
+pub fn to_json(excluded_globals: &[i32], include_excluded: bool) -> Value {
+    Value::Array(
+        GLOBAL_DOMAINS
+            .iter()
+            .filter_map(|group| {
+                let excluded = excluded_globals.contains(&group.atype);
+                (include_excluded || !excluded).then(|| group.to_json(excluded))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn well_known_groups_keep_their_ids() {
+        for (atype, domains) in [
+            (0, &["google.com", "youtube.com", "gmail.com"][..]),
+            (1, &["apple.com", "icloud.com"]),
+            (
+                10,
+                &["bing.com", "hotmail.com", "live.com", "microsoft.com"],
+            ),
+            (18, &["amazon.com", "amazon.ca"]),
+            (26, &["steampowered.com", "steamcommunity.com"]),
+            (89, &["atlassian.com", "bitbucket.org", "trello.com"]),
+        ] {
+            let group = find(atype).unwrap_or_else(|| panic!("no group {atype}"));
+            for domain in domains {
+                assert!(group.domains.contains(domain), "{domain} not in {atype}");
+            }
+        }
+    }
+
+    #[test]
+    fn ids_are_sorted_and_unique() {
+        // `find` relies on the order
+        assert!(GLOBAL_DOMAINS
+            .windows(2)
+            .all(|pair| pair[0].atype < pair[1].atype));
+        assert_eq!(find(-1).map(|group| group.atype), None);
+        assert_eq!(find(i32::MAX).map(|group| group.atype), None);
+    }
+
+    #[test]
+    fn groups_hold_distinct_domains() {
+        for group in GLOBAL_DOMAINS {
+            assert!(group.domains.len() >= 2, "group {} is alone", group.atype);
+            let unique: HashSet<_> = group.domains.iter().collect();
+            assert_eq!(unique.len(), group.domains.len(), "group {}", group.atype);
+        }
+    }
+
+    #[test]
+    fn exclusions_are_marked_or_left_out() {
+        let all = to_json(&[0, 10], true);
+        let listed = all.as_array().unwrap();
+        assert_eq!(listed.len(), GLOBAL_DOMAINS.len());
+        assert_eq!(listed[0]["type"], 0);
+        assert_eq!(listed[0]["excluded"], true);
+        assert_eq!(listed[1]["excluded"], false);
+
+        let in_use = to_json(&[0, 10], false);
+        let types: Vec<i64> = in_use
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|group| group["type"].as_i64().unwrap())
+            .collect();
+        assert_eq!(types.len(), GLOBAL_DOMAINS.len() - 2);
+        assert!(!types.contains(&0) && !types.contains(&10));
+        assert!(in_use[0]["excluded"] == false);
+    }
+}
@@ -0,0 +1,562 @@
+//! Account recovery: members enroll their user key, encrypted with the organization's public key,
+//! so owners and admins can set a new master password for them when they forget theirs.
+//!
+//! The admin's client decrypts the member's user key with the organization's private key and
+//! re-encrypts it with the new master key; the server only ever sees encrypted keys. After a
+//! reset the member has to choose a new password on their next login.
+
+use axum::{extract::State, Extension, Json};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::Settings;
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
+use crate::{
+    auth::Claims,
+    crypto::{generate_salt, hash_password_for_storage},
+    db::{self, Database, Db},
+    error::{db_error, AppError},
+    handlers::{
+        events::{self, member_event, EventSource},
+        organizations::{find_member, find_organization_for_member},
+        policies::reset_password_policy,
+    },
+    models::{
+        event::EventType,
+        organization::{
+            AdminResetPasswordRequest, Membership, MembershipStatus, MembershipType, Organization,
+            ResetPasswordEnrollmentRequest,
+        },
+        user::User,
+    },
+    notify::{self, UpdateType},
+    time,
+};
+
+async fn find_user(db: &Db, user_id: &str) -> Result<User, AppError> {
+    db.first("SELECT * FROM users WHERE id = ?1", &[user_id.into()])
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+}
+
+/// The enrolled, confirmed member whose password an admin wants to reset, with their account.
+async fn find_recoverable_member(
+    db: &Db,
+    org: &Organization,
+    admin: &Membership,
+    member_id: &str,
+) -> Result<(Membership, User), AppError> {
+    if !admin.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can recover accounts".to_string(),
+        ));
+    }
+    if reset_password_policy(db, &org.id).await?.is_none() {
+        return Err(AppError::BadRequest(
+            "The organization doesn't have the account recovery policy enabled".to_string(),
+        ));
+    }
+
+    let member = find_member(db, &org.id, member_id).await?;
+    if member.atype == MembershipType::Owner as i32 && !admin.is_owner() {
+        return Err(AppError::Forbidden(
+            "Only owners can recover other owners' accounts".to_string(),
+        ));
+    }
+    let Some(user_id) = member
+        .user_id
+        .as_deref()
+        .filter(|_| member.status == MembershipStatus::Confirmed as i32)
+    else {
+        return Err(AppError::BadRequest(
+            "Only confirmed members can have their account recovered".to_string(),
+        ));
+    };
+    if member.reset_password_key.is_none() {
+        return Err(AppError::BadRequest(
+            "The member isn't enrolled in account recovery".to_string(),
+        ));
+    }
+    let user = find_user(db, user_id).await?;
+
+    Ok((member, user))
+}
+
+/// GET /api/organizations/{id}/public-key
+///
+/// The key members encrypt their user key with to enroll. Invited users need it too, to enroll
+/// while accepting.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/public-key",
+    tag = "account_recovery",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_organization_public_key(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let org: Organization = db
+        .first(
+            "SELECT o.* FROM organizations o
+         WHERE o.id = ?1 AND EXISTS (
+             SELECT 1 FROM organization_users ou
+             WHERE ou.organization_id = o.id AND (ou.user_id = ?2 OR (ou.user_id IS NULL AND ou.email = ?3))
+         )",
+            &[
+                org_id.as_str().into(),
+                claims.sub.as_str().into(),
+                claims.email.to_lowercase().into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("Organization not found".to_string()))?;
+
+    Ok(Json(json!({
+        "publicKey": org.public_key,
+        "object": "organizationPublicKey",
+    })))
+}
+
+/// PUT /api/organizations/{id}/users/{user_id}/reset-password-enrollment
+///
+/// A member enrolls in account recovery, or withdraws by sending no key. Enrolling needs the
+/// member's master password; withdrawing isn't allowed while the organization enrolls everyone
+/// automatically.
+#[utoipa::path(
+    put,
+    path = "/api/organizations/{id}/users/{member_id}/reset-password-enrollment",
+    tag = "account_recovery",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    request_body = ResetPasswordEnrollmentRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_reset_password_enrollment(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, user_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<ResetPasswordEnrollmentRequest>,
+) -> Result<Json<()>, AppError> {
+    if user_id != claims.sub {
+        return Err(AppError::Forbidden(
+            "Members can only enroll themselves".to_string(),
+        ));
+    }
+
+    let db = db::get_db(&env)?;
+    let membership: Membership = db
+        .first(
+            "SELECT * FROM organization_users WHERE organization_id = ?1 AND user_id = ?2 AND status IN (?3, ?4)",
+            &[
+                org_id.as_str().into(),
+                claims.sub.as_str().into(),
+                (MembershipStatus::Accepted as i32).into(),
+                (MembershipStatus::Confirmed as i32).into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("Organization not found".to_string()))?;
+
+    let policy = reset_password_policy(&db, &org_id).await?;
+    let reset_password_key = payload
+        .reset_password_key
+        .filter(|key| !key.trim().is_empty());
+    let event_type = match &reset_password_key {
+        Some(_) => {
+            if policy.is_none() {
+                return Err(AppError::BadRequest(
+                    "The organization doesn't have the account recovery policy enabled".to_string(),
+                ));
+            }
+            let master_password_hash = payload.master_password_hash.ok_or_else(|| {
+                AppError::BadRequest("The master password is required to enroll".to_string())
+            })?;
+            let user = find_user(&db, &claims.sub).await?;
+            if !user
+                .verify_master_password(&master_password_hash)
+                .await?
+                .is_valid()
+            {
+                return Err(AppError::BadRequest("Invalid password".to_string()));
+            }
+            EventType::OrganizationUserResetPasswordEnroll
+        }
+        None => {
+            if policy.is_some_and(|policy| policy.auto_enroll_enabled) {
+                return Err(AppError::BadRequest(
+                    "The organization requires account recovery enrollment".to_string(),
+                ));
+            }
+            EventType::OrganizationUserResetPasswordWithdraw
+        }
+    };
+
+    let now = time::now_bw();
+    Database::batch(
+        &db,
+        vec![
+        Database::prepare(
+            &db,
+            "UPDATE organization_users SET reset_password_key = ?1, updated_at = ?2 WHERE id = ?3",
+            &[
+                reset_password_key.as_deref().into(),
+                now.as_str().into(),
+                membership.id.as_str().into(),
+            ],
+        )
+        .map_err(db_error!())?,
+        Database::prepare(
+            &db,
+            "UPDATE users SET updated_at = ?1 WHERE id = ?2",
+            &[now.as_str().into(), claims.sub.as_str().into()],
+        )
+        .map_err(db_error!())?,
+    ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    events::log_event(
+        &db,
+        &source,
+        member_event(event_type, &claims.sub, &membership),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+/// GET /api/organizations/{id}/users/{member_id}/reset-password-details
+///
+/// What an admin's client needs to recover a member's account: the member's enrolled key, the
+/// organization's encrypted private key to decrypt it, and the KDF settings to derive the new
+/// master key with.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/users/{member_id}/reset-password-details",
+    tag = "account_recovery",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_reset_password_details(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    let (member, user) = find_recoverable_member(&db, &org, &membership, &member_id).await?;
+
+    Ok(Json(json!({
+        "organizationUserId": member.id,
+        "kdf": user.kdf_type,
+        "kdfIterations": user.kdf_iterations,
+        "kdfMemory": user.kdf_memory,
+        "kdfParallelism": user.kdf_parallelism,
+        "resetPasswordKey": member.reset_password_key,
+        "encryptedPrivateKey": org.private_key,
+        "object": "organizationUserResetPasswordDetails",
+    })))
+}
+
+/// PUT /api/organizations/{id}/users/{member_id}/admin-reset-password
+///
+/// An admin sets a new master password for an enrolled member. The member's sessions end and
+/// they must pick their own password on their next login.
+#[utoipa::path(
+    put,
+    path = "/api/organizations/{id}/users/{member_id}/admin-reset-password",
+    tag = "account_recovery",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    request_body = AdminResetPasswordRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_admin_reset_password(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<AdminResetPasswordRequest>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    let (member, user) = find_recoverable_member(&db, &org, &membership, &member_id).await?;
+    if payload.new_master_password_hash.is_empty() || payload.key.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "The new master password and key are required".to_string(),
+        ));
+    }
+
+    let new_salt = generate_salt()?;
+    let password_iterations = settings.password_iterations as i32;
+    let new_hashed_password = hash_password_for_storage(
+        &payload.new_master_password_hash,
+        &new_salt,
+        password_iterations as u32,
+    )
+    .await?;
+
+    // A new security stamp invalidates the member's refresh tokens
+    db.run(
+        "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, key = ?4, security_stamp = ?5, force_password_reset = 1, updated_at = ?6 WHERE id = ?7",
+        &[
+            new_hashed_password.into(),
+            new_salt.into(),
+            password_iterations.into(),
+            payload.key.as_str().into(),
+            Uuid::new_v4().to_string().into(),
+            time::now_bw().into(),
+            user.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    notify::notify_user(&env, &db, UpdateType::LogOut, &user.id, None).await;
+    events::log_event(
+        &db,
+        &source,
+        member_event(
+            EventType::OrganizationUserAdminResetPassword,
+            &claims.sub,
+            &member,
+        ),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::organization::MembershipType;
+    use crate::models::policy::PolicyType;
+    use crate::native::testing::{self, PASSWORD_HASH};
+    use crate::native::{self, block_on};
+    use axum::http::{Method, StatusCode};
+    const RESET_HASH: &str = "cmVzZXQtcGFzc3dvcmQtaGFzaA==";
+    const CHOSEN_HASH: &str = "Y2hvc2VuLXBhc3N3b3JkLWhhc2g=";
+    const ENROLLED_KEY: &str = "4.ZW5yb2xsZWQta2V5";
+
+    struct Org {
+        env: native::Env,
+        /// Owner of the organization.
+        alice: String,
+        /// A confirmed member.
+        bob: String,
+        bob_id: String,
+    }
+
+    /// alice and bob, registered with [`PASSWORD_HASH`], in an organization alice owns. The account
+    /// recovery policy is enabled when `policy` holds its data.
+    fn org(policy: Option<Value>) -> Org {
+        let env = testing::env();
+        let alice = testing::register(&env, "alice@example.com");
+        let bob = testing::register(&env, "bob@example.com");
+        testing::organization(
+            &env,
+            "org",
+            &[
+                ("alice", MembershipType::Owner),
+                ("bob", MembershipType::User),
+            ],
+        );
+
+        let db = env.d1("vault1").unwrap();
+        block_on(db.run(
+            "UPDATE organizations SET public_key = 'cHVibGlj', private_key = '2.cHJpdmF0ZQ==|aXY=|bWFj'
+             WHERE id = 'org'",
+            &[],
+        ))
+        .unwrap();
+        if let Some(data) = policy {
+            block_on(db.run(
+                "INSERT INTO organization_policies (id, organization_id, atype, enabled, data, created_at, updated_at)
+                 VALUES ('policy', 'org', ?1, 1, ?2, ?3, ?3)",
+                &[
+                    (PolicyType::ResetPassword as i32).into(),
+                    data.to_string().into(),
+                    time::now_bw().into(),
+                ],
+            ))
+            .unwrap();
+        }
+
+        let bob_id = block_on(db.first_column::<String>(
+            "SELECT id FROM users WHERE email = 'bob@example.com'",
+            &[],
+            "id",
+        ))
+        .unwrap()
+        .unwrap();
+        Org {
+            alice,
+            bob,
+            bob_id,
+            env,
+        }
+    }
+
+    fn send(
+        env: &native::Env,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        testing::request(env, method, path, token, Some(body))
+    }
+
+    /// bob's password login with `password_hash`, as the token endpoint answers it.
+    fn login(env: &native::Env, password_hash: &str) -> (StatusCode, Value) {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs([
+                ("grant_type", "password"),
+                ("username", "bob@example.com"),
+                ("password", password_hash),
+                ("scope", "api offline_access"),
+                ("client_id", "web"),
+                ("deviceType", "9"),
+                ("deviceIdentifier", "8f1c0e52-3f4d-4c35-9d7e-8f0b6f7a2c11"),
+                ("deviceName", "firefox"),
+            ])
+            .finish();
+        testing::post_form(env, "/identity/connect/token", &body)
+    }
+
+    fn enroll(org: &Org, key: Option<&str>, password_hash: &str) -> StatusCode {
+        send(
+            &org.env,
+            Method::PUT,
+            &format!(
+                "/api/organizations/org/users/{}/reset-password-enrollment",
+                org.bob_id
+            ),
+            Some(&org.bob),
+            json!({ "resetPasswordKey": key, "masterPasswordHash": password_hash }),
+        )
+        .0
+    }
+
+    fn admin_reset(org: &Org, token: &str, member_id: &str) -> StatusCode {
+        send(
+            &org.env,
+            Method::PUT,
+            &format!("/api/organizations/org/users/{member_id}/admin-reset-password"),
+            Some(token),
+            json!({ "newMasterPasswordHash": RESET_HASH, "key": "2.cmVzZXQta2V5|aXY=|bWFj" }),
+        )
+        .0
+    }
+
+    #[test]
+    fn an_enrolled_member_is_recovered_and_picks_a_new_password() {
+        let org = org(Some(json!({ "autoEnrollEnabled": false })));
+        assert_eq!(
+            enroll(&org, Some(ENROLLED_KEY), "d3JvbmctaGFzaA=="),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            enroll(&org, Some(ENROLLED_KEY), PASSWORD_HASH),
+            StatusCode::OK
+        );
+
+        let (status, details) = send(
+            &org.env,
+            Method::GET,
+            "/api/organizations/org/users/bob-membership/reset-password-details",
+            Some(&org.alice),
+            Value::Null,
+        );
+        assert_eq!(status, StatusCode::OK, "{details}");
+        assert_eq!(details["resetPasswordKey"], ENROLLED_KEY);
+        assert_eq!(details["encryptedPrivateKey"], "2.cHJpdmF0ZQ==|aXY=|bWFj");
+
+        assert_eq!(
+            admin_reset(&org, &org.alice, "bob-membership"),
+            StatusCode::OK
+        );
+
+        assert_eq!(login(&org.env, PASSWORD_HASH).0, StatusCode::BAD_REQUEST);
+        let (status, session) = login(&org.env, RESET_HASH);
+        assert_eq!(status, StatusCode::OK, "{session}");
+        assert_eq!(session["ForcePasswordReset"], true);
+        assert_eq!(session["Key"], "2.cmVzZXQta2V5|aXY=|bWFj");
+
+        let (status, body) = send(
+            &org.env,
+            Method::PUT,
+            "/api/accounts/update-temp-password",
+            session["access_token"].as_str(),
+            json!({ "newMasterPasswordHash": CHOSEN_HASH, "key": "2.Y2hvc2Vu|aXY=|bWFj" }),
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, session) = login(&org.env, CHOSEN_HASH);
+        assert_eq!(status, StatusCode::OK, "{session}");
+        assert_eq!(session["ForcePasswordReset"], false);
+    }
+
+    #[test]
+    fn only_enrolled_members_of_a_policy_enabled_organization_are_recovered() {
+        let without_policy = org(None);
+        assert_eq!(
+            enroll(&without_policy, Some(ENROLLED_KEY), PASSWORD_HASH),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            admin_reset(&without_policy, &without_policy.alice, "bob-membership"),
+            StatusCode::BAD_REQUEST
+        );
+
+        let not_enrolled = org(Some(json!({})));
+        assert_eq!(
+            admin_reset(&not_enrolled, &not_enrolled.alice, "bob-membership"),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(login(&not_enrolled.env, PASSWORD_HASH).0, StatusCode::OK);
+    }
+
+    #[test]
+    fn members_cant_recover_other_accounts() {
+        let org = org(Some(json!({})));
+        assert_eq!(
+            enroll(&org, Some(ENROLLED_KEY), PASSWORD_HASH),
+            StatusCode::OK
+        );
+
+        assert_eq!(
+            admin_reset(&org, &org.bob, "alice-membership"),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn automatic_enrollment_forbids_withdrawing() {
+        let org = org(Some(json!({ "autoEnrollEnabled": true })));
+        assert_eq!(
+            enroll(&org, Some(ENROLLED_KEY), PASSWORD_HASH),
+            StatusCode::OK
+        );
+
+        assert_eq!(enroll(&org, None, PASSWORD_HASH), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,27 +1,38 @@
-use axum::{extract::State, http::HeaderMap, Json};
+use axum::{extract::State, http::HeaderMap, Extension, Json};
 use chrono::Utc;
 use glob_match::glob_match;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
 use uuid::Uuid;
-use worker::{query, D1PreparedStatement, Env};
 
-use super::{get_batch_size, server_password_iterations, two_factor_enabled};
+use super::two_factor_enabled;
+use crate::config::Settings;
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
 use crate::{
     auth::Claims,
     crypto::{generate_salt, hash_password_for_storage},
-    db,
-    error::AppError,
-    handlers::attachments,
+    db::{self, Database, Retry, Statement},
+    error::{db_error, internal_error, AppError},
+    handlers::{
+        attachments,
+        events::{log_account_event, EventSource},
+        import::with_import_lock,
+        invitations, organizations,
+    },
+    mail::{self, templates},
     models::{
         cipher::CipherData,
+        event::EventType,
         sync::Profile,
         user::{
             AvatarData, ChangeKdfRequest, ChangePasswordRequest, MasterPasswordUnlockData,
             PasswordHintRequest, PasswordOrOtpData, PreloginResponse, ProfileData, RegisterRequest,
-            RotateKeyRequest, User,
+            RotateKeyRequest, UpdateTempPasswordRequest, User, VerifyDevicesRequest,
         },
     },
+    time,
 };
 
 const KDF_TYPE_PBKDF2: i32 = 0;
@@ -118,15 +129,33 @@ fn validate_rotation_metadata(
     Ok(())
 }
 
+/// The KDF settings of an account, which the client needs to derive the master key.
+#[derive(Deserialize)]
+struct PreloginKdf {
+    kdf_type: i32,
+    kdf_iterations: i32,
+    kdf_memory: Option<i32>,
+    kdf_parallelism: Option<i32>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/identity/accounts/prelogin",
+    tag = "accounts",
+    request_body = serde_json::Value,
+    responses((status = 200, description = "Success", body = PreloginResponse))
+)]
 #[worker::send]
 pub async fn prelogin(
     State(env): State<Arc<Env>>,
     headers: HeaderMap,
-    Json(payload): Json<serde_json::Value>,
+    retry: Retry,
+    AppJson(payload): AppJson<serde_json::Value>,
 ) -> Result<Json<PreloginResponse>, AppError> {
     let email = payload["email"]
         .as_str()
-        .ok_or_else(|| AppError::BadRequest("Missing email".to_string()))?;
+        .or_else(|| payload["Email"].as_str())
+        .ok_or_else(|| AppError::validation("Email", "The Email field is required."))?;
 
     // Check rate limit using IP address as key to prevent email enumeration attacks
     if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
@@ -145,48 +174,51 @@ pub async fn prelogin(
     }
 
     let db = db::get_db(&env)?;
+    let email = email.to_lowercase();
 
-    let stmt = db.prepare(
-        "SELECT kdf_type, kdf_iterations, kdf_memory, kdf_parallelism FROM users WHERE email = ?1",
-    );
-    let query = stmt.bind(&[email.into()])?;
-    let row: Option<Value> = query.first(None).await.map_err(|_| AppError::Database)?;
-
-    let (kdf_type, kdf_iterations, kdf_memory, kdf_parallelism) = if let Some(row) = row {
-        let kdf_type = row
-            .get("kdf_type")
-            .and_then(|value| value.as_i64())
-            .map(|value| value as i32);
-        let kdf_iterations = row
-            .get("kdf_iterations")
-            .and_then(|value| value.as_i64())
-            .map(|value| value as i32);
-        let kdf_memory = row
-            .get("kdf_memory")
-            .and_then(|value| value.as_i64())
-            .map(|value| value as i32);
-        let kdf_parallelism = row
-            .get("kdf_parallelism")
-            .and_then(|value| value.as_i64())
-            .map(|value| value as i32);
-        (kdf_type, kdf_iterations, kdf_memory, kdf_parallelism)
-    } else {
-        (None, None, None, None)
-    };
+    let kdf: Option<PreloginKdf> = retry
+        .run("prelogin: kdf", || async {
+            db.first(
+                "SELECT kdf_type, kdf_iterations, kdf_memory, kdf_parallelism FROM users WHERE email = ?1",
+                &[email.as_str().into()],
+            )
+            .await
+            .map_err(db_error!())
+        })
+        .await?;
 
+    // Unknown emails get the defaults, so prelogin doesn't tell which accounts exist
+    let Some(kdf) = kdf else {
+        return Ok(Json(PreloginResponse {
+            kdf: KDF_TYPE_PBKDF2,
+            kdf_iterations: DEFAULT_PBKDF2_ITERATIONS,
+            kdf_memory: None,
+            kdf_parallelism: None,
+        }));
+    };
+    // The Argon2 parameters mean nothing to PBKDF2 and would only confuse the clients
+    let argon2 = kdf.kdf_type == KDF_TYPE_ARGON2ID;
     Ok(Json(PreloginResponse {
-        kdf: kdf_type.unwrap_or(KDF_TYPE_PBKDF2),
-        kdf_iterations: kdf_iterations.unwrap_or(DEFAULT_PBKDF2_ITERATIONS),
-        kdf_memory,
-        kdf_parallelism,
+        kdf: kdf.kdf_type,
+        kdf_iterations: kdf.kdf_iterations,
+        kdf_memory: kdf.kdf_memory.filter(|_| argon2),
+        kdf_parallelism: kdf.kdf_parallelism.filter(|_| argon2),
     }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/identity/accounts/register",
+    tag = "accounts",
+    request_body = RegisterRequest,
+    responses((status = 200, description = "Success"))
+)]
 #[worker::send]
 pub async fn register(
     State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
     headers: HeaderMap,
-    Json(payload): Json<RegisterRequest>,
+    AppJson(payload): AppJson<RegisterRequest>,
 ) -> Result<Json<Value>, AppError> {
     // Check rate limit using IP address as key to prevent mass registration and email enumeration
     if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
@@ -204,18 +236,18 @@ pub async fn register(
         }
     }
 
-    let allowed_emails = env
-        .secret("ALLOWED_EMAILS")
-        .map_err(|_| AppError::Internal)?;
-    let allowed_emails = allowed_emails
-        .as_ref()
-        .as_string()
-        .ok_or_else(|| AppError::Internal)?;
-    if !allowed_emails
-        .split(',')
-        .any(|pattern| glob_match(pattern.trim(), &payload.email))
-    {
-        return Err(AppError::Unauthorized("Not allowed to signup".to_string()));
+    let db = db::get_db(&env)?;
+    let email = payload.email.to_lowercase();
+
+    // Invited addresses don't need to match ALLOWED_EMAILS
+    if !invitations::is_invited(&db, &email).await? {
+        let allowed_emails = settings.allowed_emails.clone()?;
+        if !allowed_emails
+            .iter()
+            .any(|pattern| glob_match(pattern, &payload.email))
+        {
+            return Err(AppError::Unauthorized("Not allowed to signup".to_string()));
+        }
     }
 
     ensure_supported_kdf(
@@ -227,7 +259,7 @@ pub async fn register(
 
     // Generate salt and hash the password with server-side PBKDF2
     let password_salt = generate_salt()?;
-    let password_iterations = server_password_iterations(&env) as i32;
+    let password_iterations = settings.password_iterations as i32;
     let hashed_password = hash_password_for_storage(
         &payload.master_password_hash,
         &password_salt,
@@ -235,8 +267,7 @@ pub async fn register(
     )
     .await?;
 
-    let db = db::get_db(&env)?;
-    let now = Utc::now().to_rfc3339();
+    let now = time::now_bw();
 
     // Only store kdf_memory and kdf_parallelism for Argon2id, clear for PBKDF2
     let (kdf_memory, kdf_parallelism) = if payload.kdf == KDF_TYPE_ARGON2ID {
@@ -249,7 +280,7 @@ pub async fn register(
         id: Uuid::new_v4().to_string(),
         name: payload.name,
         avatar_color: None,
-        email: payload.email.to_lowercase(),
+        email,
         email_verified: false,
         master_password_hash: hashed_password,
         master_password_hint: payload.master_password_hint,
@@ -266,61 +297,91 @@ pub async fn register(
         equivalent_domains: "[]".to_string(),
         excluded_globals: "[]".to_string(),
         totp_recover: None,
+        force_password_reset: false,
+        verify_devices: true,
         created_at: now.clone(),
         updated_at: now,
     };
 
-    query!(
+    let insert = Database::prepare(
         &db,
         "INSERT INTO users (id, name, email, master_password_hash, master_password_hint, password_salt, password_iterations, key, private_key, public_key, kdf_type, kdf_iterations, kdf_memory, kdf_parallelism, security_stamp, equivalent_domains, excluded_globals, totp_recover, created_at, updated_at)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
-         user.id,
-         user.name,
-         user.email,
-         user.master_password_hash,
-         user.master_password_hint,
-         user.password_salt,
-         user.password_iterations,
-         user.key,
-         user.private_key,
-         user.public_key,
-         user.kdf_type,
-         user.kdf_iterations,
-         user.kdf_memory,
-         user.kdf_parallelism,
-         user.security_stamp,
-         user.equivalent_domains,
-         user.excluded_globals,
-         user.totp_recover,
-         user.created_at,
-         user.updated_at
-    ).map_err(|_|{
-        AppError::Database
-    })?
-    .run()
+        &[
+            user.id.into(),
+            user.name.as_deref().into(),
+            user.email.as_str().into(),
+            user.master_password_hash.into(),
+            user.master_password_hint.into(),
+            user.password_salt.into(),
+            user.password_iterations.into(),
+            user.key.into(),
+            user.private_key.into(),
+            user.public_key.into(),
+            user.kdf_type.into(),
+            user.kdf_iterations.into(),
+            user.kdf_memory.into(),
+            user.kdf_parallelism.into(),
+            user.security_stamp.into(),
+            user.equivalent_domains.into(),
+            user.excluded_globals.into(),
+            user.totp_recover.into(),
+            user.created_at.into(),
+            user.updated_at.into(),
+        ],
+    )
+    .map_err(db_error!())?;
+    Database::batch(
+        &db,
+        vec![insert, invitations::accepted_statement(&db, &user.email)?],
+    )
     .await
-    .map_err(|_|{
-        AppError::Database
-    })?;
+    .map_err(|err| db::classify_error(err, "An account with this email"))?;
 
     Ok(Json(json!({})))
 }
 
+#[utoipa::path(
+    post,
+    path = "/identity/accounts/register/send-verification-email",
+    tag = "accounts",
+    responses((status = 200, description = "Success", body = String))
+)]
 #[worker::send]
 pub async fn send_verification_email() -> Result<Json<String>, AppError> {
     Ok(Json("fixed-token-to-mock".to_string()))
 }
 
+#[derive(Deserialize)]
+struct HintRow {
+    master_password_hint: Option<String>,
+}
+
 /// POST /api/accounts/password-hint
 ///
-/// Bitwarden normally sends the master password hint via email. This project does not implement
-/// email delivery, so we return the hint directly.
+/// Emails the master password hint. The answer is the same whether or not an account uses the
+/// address, so it can't be used to find out which ones do. Without mail delivery hints can't be
+/// sent at all.
+#[utoipa::path(
+    post,
+    path = "/api/accounts/password-hint",
+    tag = "accounts",
+    request_body = PasswordHintRequest,
+    responses((status = 200, description = "Success"))
+)]
 #[worker::send]
 pub async fn password_hint(
     State(env): State<Arc<Env>>,
     headers: HeaderMap,
-    Json(payload): Json<PasswordHintRequest>,
+    AppJson(payload): AppJson<PasswordHintRequest>,
 ) -> Result<Json<Value>, AppError> {
+    if !matches!(Settings::get(&env).mail, Ok(Some(_))) {
+        return Err(AppError::BadRequest(
+            "This server can't email password hints because mail delivery isn't configured"
+                .to_string(),
+        ));
+    }
+
     // Basic rate limit by IP to slow down bulk email enumeration attempts.
     if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
         let ip = headers
@@ -337,36 +398,37 @@ pub async fn password_hint(
         }
     }
 
-    const NO_HINT: &str = "Sorry, you have no password hint...";
-
     let db = db::get_db(&env)?;
     let email = payload.email.to_lowercase();
 
-    let hint: Option<String> = db
-        .prepare("SELECT master_password_hint FROM users WHERE email = ?1")
-        .bind(&[email.into()])?
-        .first(Some("master_password_hint"))
+    let hint: Option<Option<String>> = db
+        .first::<HintRow>(
+            "SELECT master_password_hint FROM users WHERE email = ?1",
+            &[email.as_str().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?;
-
-    let hint = hint.and_then(|h| {
-        let trimmed = h.trim();
-        if trimmed.is_empty() {
-            None
-        } else {
-            Some(trimmed.to_string())
-        }
-    });
+        .map_err(db_error!())?
+        .map(|row| row.master_password_hint);
 
     if let Some(hint) = hint {
-        return Err(AppError::BadRequest(format!(
-            "Your password hint is: {hint}"
-        )));
+        let hint = hint
+            .as_deref()
+            .map(str::trim)
+            .filter(|hint| !hint.is_empty());
+        // Failures are logged by mail::send; the answer mustn't tell them apart from no account
+        let _ = mail::send(&env, &templates::password_hint(hint).to(email)).await;
     }
 
-    Err(AppError::BadRequest(NO_HINT.to_string()))
+    Ok(Json(json!({})))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/accounts/revision-date",
+    tag = "accounts",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn revision_date(
     claims: Claims,
@@ -376,17 +438,19 @@ pub async fn revision_date(
 
     // get the user's updated_at timestamp
     let updated_at: Option<String> = db
-        .prepare("SELECT updated_at FROM users WHERE id = ?1")
-        .bind(&[claims.sub.into()])?
-        .first(Some("updated_at"))
+        .first_column(
+            "SELECT updated_at FROM users WHERE id = ?1",
+            &[claims.sub.into()],
+            "updated_at",
+        )
         .await
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
 
     // convert the timestamp to a millisecond-level Unix timestamp
     let revision_date = updated_at
-        .and_then(|ts| chrono::DateTime::parse_from_rfc3339(&ts).ok())
+        .and_then(|ts| time::parse_bw(&ts))
         .map(|dt| dt.timestamp_millis())
-        .unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+        .unwrap_or_else(|| Utc::now().timestamp_millis());
 
     Ok(Json(revision_date))
 }
@@ -395,6 +459,12 @@ pub async fn revision_date(
 ///
 /// Vaultwarden returns an empty list here; some official clients call this endpoint.
 /// We don't implement task workflows, so always return an empty list.
+#[utoipa::path(
+    get,
+    path = "/api/accounts/tasks",
+    tag = "accounts",
+    responses((status = 200, description = "Success"))
+)]
 #[worker::send]
 pub async fn get_tasks() -> Result<Json<Value>, AppError> {
     Ok(Json(json!({
@@ -403,29 +473,48 @@ pub async fn get_tasks() -> Result<Json<Value>, AppError> {
     })))
 }
 
-/// GET /api/auth-requests
-///
-/// Bitwarden clients may call this to fetch pending "login with device" auth requests.
-/// This minimal implementation doesn't support device auth requests, so we always return an empty list.
+/// GET /api/users/{id}/public-key
 ///
-/// Vaultwarden currently aliases this endpoint to `/api/auth-requests/pending`.
+/// Admins encrypt the organization key with a new member's public key when confirming them.
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}/public-key",
+    tag = "accounts",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
-pub async fn get_auth_requests(claims: Claims) -> Result<Json<Value>, AppError> {
-    get_auth_requests_pending(claims).await
-}
+pub async fn get_user_public_key(
+    _claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(user_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let public_key: Option<String> = db
+        .first_column(
+            "SELECT public_key FROM users WHERE id = ?1",
+            &[user_id.as_str().into()],
+            "public_key",
+        )
+        .await
+        .map_err(db_error!())?;
+    let public_key = public_key.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-/// GET /api/auth-requests/pending
-///
-/// Stub: always returns an empty list.
-#[worker::send]
-pub async fn get_auth_requests_pending(_claims: Claims) -> Result<Json<Value>, AppError> {
     Ok(Json(json!({
-        "data": [],
-        "continuationToken": null,
-        "object": "list"
+        "userId": user_id,
+        "publicKey": public_key,
+        "object": "userKey",
     })))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/accounts/profile",
+    tag = "accounts",
+    responses((status = 200, description = "Success", body = Profile)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_profile(
     claims: Claims,
@@ -435,23 +524,33 @@ pub async fn get_profile(
     let user_id = claims.sub;
 
     let user: User = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
     let two_factor_enabled = two_factor_enabled(&db, &user_id).await?;
-    let profile = Profile::from_user(user, two_factor_enabled)?;
+    let mut profile = Profile::from_user(user, two_factor_enabled)?;
+    profile.organizations = organizations::list_profile_organizations(&db, &user_id).await?;
 
     Ok(Json(profile))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/accounts/profile",
+    tag = "accounts",
+    request_body = ProfileData,
+    responses((status = 200, description = "Success", body = Profile)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn post_profile(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<ProfileData>,
+    AppJson(payload): AppJson<ProfileData>,
 ) -> Result<Json<Profile>, AppError> {
     if payload.name.len() > 50 {
         return Err(AppError::BadRequest(
@@ -463,51 +562,68 @@ pub async fn post_profile(
     let user_id = &claims.sub;
 
     let user_value: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-    let mut user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
-    let now = Utc::now().to_rfc3339();
+    let mut user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
+    let now = time::now_bw();
 
     user.name = Some(payload.name);
     user.updated_at = now.clone();
 
-    query!(
-        &db,
+    db.run(
         "UPDATE users SET name = ?1, updated_at = ?2 WHERE id = ?3",
-        user.name,
-        now,
-        user_id
+        &[
+            user.name.as_deref().into(),
+            now.as_str().into(),
+            user_id.as_str().into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
-    .map_err(|_| AppError::Database)?;
+    .map_err(db_error!())?;
 
     let two_factor_enabled = two_factor_enabled(&db, user_id).await?;
-    let profile = Profile::from_user(user, two_factor_enabled)?;
+    let mut profile = Profile::from_user(user, two_factor_enabled)?;
+    profile.organizations = organizations::list_profile_organizations(&db, user_id).await?;
 
     Ok(Json(profile))
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/accounts/profile",
+    tag = "accounts",
+    request_body = ProfileData,
+    responses((status = 200, description = "Success", body = Profile)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn put_profile(
     claims: Claims,
     state: State<Arc<Env>>,
-    json: Json<ProfileData>,
+    json: AppJson<ProfileData>,
 ) -> Result<Json<Profile>, AppError> {
     post_profile(claims, state, json).await
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/accounts/avatar",
+    tag = "accounts",
+    request_body = AvatarData,
+    responses((status = 200, description = "Success", body = Profile)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn put_avatar(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<AvatarData>,
+    AppJson(payload): AppJson<AvatarData>,
 ) -> Result<Json<Profile>, AppError> {
     if let Some(color) = &payload.avatar_color {
         if color.len() != 7 {
@@ -522,55 +638,142 @@ pub async fn put_avatar(
     let user_id = &claims.sub;
 
     let user_value: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-    let mut user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
-    let now = Utc::now().to_rfc3339();
+    let mut user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
+    let now = time::now_bw();
 
     user.avatar_color = payload.avatar_color;
     user.updated_at = now.clone();
 
-    query!(
-        &db,
+    db.run(
         "UPDATE users SET avatar_color = ?1, updated_at = ?2 WHERE id = ?3",
-        user.avatar_color,
-        now,
-        user_id
+        &[
+            user.avatar_color.as_deref().into(),
+            now.as_str().into(),
+            user_id.as_str().into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
-    .map_err(|_| AppError::Database)?;
+    .map_err(db_error!())?;
 
     let two_factor_enabled = two_factor_enabled(&db, user_id).await?;
-    let profile = Profile::from_user(user, two_factor_enabled)?;
+    let mut profile = Profile::from_user(user, two_factor_enabled)?;
+    profile.organizations = organizations::list_profile_organizations(&db, user_id).await?;
 
     Ok(Json(profile))
 }
 
+/// GET /api/accounts/verify-devices
+///
+/// Whether the user's password logins from new devices need an emailed code. Only enforced when
+/// NEW_DEVICE_VERIFICATION is on.
+#[utoipa::path(
+    get,
+    path = "/api/accounts/verify-devices",
+    tag = "accounts",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_verify_devices(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user: User = db
+        .first("SELECT * FROM users WHERE id = ?1", &[claims.sub.into()])
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    Ok(Json(json!({ "verifyDevices": user.verify_devices })))
+}
+
+/// PUT /api/accounts/verify-devices
+///
+/// Turns new device verification on or off for the user, who confirms with the master password.
+#[utoipa::path(
+    put,
+    path = "/api/accounts/verify-devices",
+    tag = "accounts",
+    request_body = VerifyDevicesRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_verify_devices(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppJson(payload): AppJson<VerifyDevicesRequest>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user_id = &claims.sub;
+
+    let user: User = db
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    let provided_hash = payload
+        .master_password_hash
+        .ok_or_else(|| AppError::BadRequest("Missing master password hash".to_string()))?;
+    let verification = user.verify_master_password(&provided_hash).await?;
+    if !verification.is_valid() {
+        return Err(AppError::Unauthorized("Invalid password".to_string()));
+    }
+
+    db.run(
+        "UPDATE users SET verify_devices = ?1, updated_at = ?2 WHERE id = ?3",
+        &[
+            payload.verify_devices.into(),
+            time::now_bw().into(),
+            user_id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    Ok(Json(json!({ "verifyDevices": payload.verify_devices })))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/accounts",
+    tag = "accounts",
+    request_body = PasswordOrOtpData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn delete_account(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<PasswordOrOtpData>,
+    AppJson(payload): AppJson<PasswordOrOtpData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
     let user_id = &claims.sub;
 
     // Get the user from the database
     let user: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user).map_err(internal_error!())?;
 
     // Verify the master password hash
     let provided_hash = payload
@@ -589,45 +792,59 @@ pub async fn delete_account(
     }
 
     // Delete all user's ciphers
-    query!(&db, "DELETE FROM ciphers WHERE user_id = ?1", user_id)
-        .map_err(|_| AppError::Database)?
-        .run()
-        .await?;
+    db.run(
+        "DELETE FROM ciphers WHERE user_id = ?1",
+        &[user_id.as_str().into()],
+    )
+    .await?;
 
     // Delete all user's folders
-    query!(&db, "DELETE FROM folders WHERE user_id = ?1", user_id)
-        .map_err(|_| AppError::Database)?
-        .run()
-        .await?;
+    db.run(
+        "DELETE FROM folders WHERE user_id = ?1",
+        &[user_id.as_str().into()],
+    )
+    .await?;
 
     // Delete the user
-    query!(&db, "DELETE FROM users WHERE id = ?1", user_id)
-        .map_err(|_| AppError::Database)?
-        .run()
-        .await?;
+    db.run(
+        "DELETE FROM users WHERE id = ?1",
+        &[user_id.as_str().into()],
+    )
+    .await?;
 
     Ok(Json(json!({})))
 }
 
 /// POST /accounts/password - Change master password
+#[utoipa::path(
+    post,
+    path = "/api/accounts/password",
+    tag = "accounts",
+    request_body = ChangePasswordRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn post_password(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<ChangePasswordRequest>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppJson(payload): AppJson<ChangePasswordRequest>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
     let user_id = &claims.sub;
 
     // Get the user from the database
     let user: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user).map_err(internal_error!())?;
 
     // Verify the current master password
     let verification = user
@@ -640,7 +857,7 @@ pub async fn post_password(
 
     // Generate new salt and hash the new password
     let new_salt = generate_salt()?;
-    let password_iterations = server_password_iterations(&env) as i32;
+    let password_iterations = settings.password_iterations as i32;
     let new_hashed_password = hash_password_for_storage(
         &payload.new_master_password_hash,
         &new_salt,
@@ -650,48 +867,148 @@ pub async fn post_password(
 
     // Generate new security stamp and update timestamp
     let new_security_stamp = Uuid::new_v4().to_string();
-    let now = Utc::now().to_rfc3339();
+    let now = time::now_bw();
 
     // Update user record
-    query!(
-        &db,
-        "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, key = ?4, master_password_hint = ?5, security_stamp = ?6, updated_at = ?7 WHERE id = ?8",
-        new_hashed_password,
-        new_salt,
-        password_iterations,
-        payload.key,
-        payload.master_password_hint,
-        new_security_stamp,
-        now,
-        user_id
+    db.run(
+        "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, key = ?4, master_password_hint = ?5, security_stamp = ?6, force_password_reset = 0, updated_at = ?7 WHERE id = ?8",
+        &[
+            new_hashed_password.into(),
+            new_salt.into(),
+            password_iterations.into(),
+            payload.key.into(),
+            payload.master_password_hint.into(),
+            new_security_stamp.into(),
+            now.as_str().into(),
+            user_id.as_str().into(),
+        ],
+    )
+    .await?;
+    log_account_event(&db, &source, EventType::UserChangedPassword, user_id).await;
+
+    Ok(Json(json!({})))
+}
+
+/// PUT /accounts/update-temp-password - Replace the master password set by an admin reset
+///
+/// The user is already authenticated with the temporary password, so the current hash isn't
+/// asked for again. Only allowed while a reset is pending.
+#[utoipa::path(
+    put,
+    path = "/api/accounts/update-temp-password",
+    tag = "accounts",
+    request_body = UpdateTempPasswordRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_update_temp_password(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppJson(payload): AppJson<UpdateTempPasswordRequest>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user_id = &claims.sub;
+
+    let user: Value = db
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let user: User = serde_json::from_value(user).map_err(internal_error!())?;
+
+    if !user.force_password_reset {
+        return Err(AppError::BadRequest(
+            "No password reset is pending".to_string(),
+        ));
+    }
+
+    let new_salt = generate_salt()?;
+    let password_iterations = settings.password_iterations as i32;
+    let new_hashed_password = hash_password_for_storage(
+        &payload.new_master_password_hash,
+        &new_salt,
+        password_iterations as u32,
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
 
+    let new_security_stamp = Uuid::new_v4().to_string();
+    let now = time::now_bw();
+
+    db.run(
+        "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, key = ?4, master_password_hint = ?5, security_stamp = ?6, force_password_reset = 0, updated_at = ?7 WHERE id = ?8",
+        &[
+            new_hashed_password.into(),
+            new_salt.into(),
+            password_iterations.into(),
+            payload.key.into(),
+            payload.master_password_hint.into(),
+            new_security_stamp.into(),
+            now.as_str().into(),
+            user_id.as_str().into(),
+        ],
+    )
+    .await?;
+    log_account_event(&db, &source, EventType::UserChangedPassword, user_id).await;
+
     Ok(Json(json!({})))
 }
 
 /// POST /accounts/key-management/rotate-user-account-keys - Rotate user encryption keys
+#[utoipa::path(
+    post,
+    path = "/api/accounts/key-management/rotate-user-account-keys",
+    tag = "accounts",
+    request_body = RotateKeyRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn post_rotatekey(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<RotateKeyRequest>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppJson(payload): AppJson<RotateKeyRequest>,
+) -> Result<Json<Value>, AppError> {
+    // A rotation rewrites every item, just like an import: the two mustn't interleave
+    let db = db::get_db(&env)?;
+    let user_id = claims.sub.clone();
+    with_import_lock(
+        &db,
+        &user_id,
+        rotate_user_keys(claims, source, env, settings, payload),
+    )
+    .await
+}
+
+/// The rotation of [`post_rotatekey`], under the user's import lock.
+async fn rotate_user_keys(
+    claims: Claims,
+    source: EventSource,
+    env: Arc<Env>,
+    settings: Arc<Settings>,
+    payload: RotateKeyRequest,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
     let user_id = &claims.sub;
-    let batch_size = get_batch_size(&env);
+    let batch_size = settings.batch_size;
 
     // Get the user from the database
     let user: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user).map_err(internal_error!())?;
 
     // Verify the current master password
     let verification = user
@@ -750,50 +1067,35 @@ pub async fn post_rotatekey(
         .filter_map(|f| f.id.clone())
         .collect();
 
-    let cipher_ids_json =
-        serde_json::to_string(&request_cipher_ids).map_err(|_| AppError::Internal)?;
-    let folder_ids_json =
-        serde_json::to_string(&request_folder_ids).map_err(|_| AppError::Internal)?;
-
-    // Batch: 2 COUNT queries + 2 EXCEPT queries
-    let validation_results = db
-        .batch(vec![
-            // Count ciphers in DB
-            db.prepare(
-                "SELECT COUNT(*) AS cnt FROM ciphers WHERE user_id = ?1 AND organization_id IS NULL",
-            )
-            .bind(&[user_id.clone().into()])?,
-            // Count folders in DB
-            db.prepare("SELECT COUNT(*) AS cnt FROM folders WHERE user_id = ?1")
-                .bind(&[user_id.clone().into()])?,
-            // DB cipher IDs EXCEPT request cipher IDs (finds missing)
-            db.prepare(
-                "SELECT id FROM ciphers WHERE user_id = ?1 AND organization_id IS NULL
+    let cipher_ids_json = serde_json::to_string(&request_cipher_ids).map_err(internal_error!())?;
+    let folder_ids_json = serde_json::to_string(&request_folder_ids).map_err(internal_error!())?;
+
+    // The 2 counts and the 2 EXCEPT checks, in one round trip
+    let validation: Value = db
+        .first(
+            "SELECT
+                (SELECT COUNT(*) FROM ciphers WHERE user_id = ?1 AND organization_id IS NULL) AS cipher_count,
+                (SELECT COUNT(*) FROM folders WHERE user_id = ?1) AS folder_count,
+                -- DB cipher IDs EXCEPT request cipher IDs (finds missing)
+                (SELECT id FROM ciphers WHERE user_id = ?1 AND organization_id IS NULL
                  EXCEPT
-                 SELECT value FROM json_each(?2) LIMIT 1",
-            )
-            .bind(&[user_id.clone().into(), cipher_ids_json.into()])?,
-            // DB folder IDs EXCEPT request folder IDs (finds missing)
-            db.prepare(
-                "SELECT id FROM folders WHERE user_id = ?1
+                 SELECT value FROM json_each(?2) LIMIT 1) AS missing_cipher,
+                -- DB folder IDs EXCEPT request folder IDs (finds missing)
+                (SELECT id FROM folders WHERE user_id = ?1
                  EXCEPT
-                 SELECT value FROM json_each(?2) LIMIT 1",
-            )
-            .bind(&[user_id.clone().into(), folder_ids_json.into()])?,
-        ])
-        .await?;
+                 SELECT value FROM json_each(?3) LIMIT 1) AS missing_folder",
+            &[
+                user_id.as_str().into(),
+                cipher_ids_json.into(),
+                folder_ids_json.into(),
+            ],
+        )
+        .await?
+        .unwrap_or_default();
 
     // Check counts match
-    let db_cipher_count = validation_results[0]
-        .results::<Value>()?
-        .first()
-        .and_then(|v| v.get("cnt")?.as_i64())
-        .unwrap_or(0) as usize;
-    let db_folder_count = validation_results[1]
-        .results::<Value>()?
-        .first()
-        .and_then(|v| v.get("cnt")?.as_i64())
-        .unwrap_or(0) as usize;
+    let db_cipher_count = validation["cipher_count"].as_i64().unwrap_or(0) as usize;
+    let db_folder_count = validation["folder_count"].as_i64().unwrap_or(0) as usize;
 
     if db_cipher_count != request_cipher_ids.len() || db_folder_count != request_folder_ids.len() {
         log::error!(
@@ -809,8 +1111,8 @@ pub async fn post_rotatekey(
     }
 
     // Check EXCEPT results (if count matches but IDs differ)
-    let has_missing_ciphers = !validation_results[2].results::<Value>()?.is_empty();
-    let has_missing_folders = !validation_results[3].results::<Value>()?.is_empty();
+    let has_missing_ciphers = !validation["missing_cipher"].is_null();
+    let has_missing_folders = !validation["missing_folder"].is_null();
 
     if has_missing_ciphers || has_missing_folders {
         log::error!(
@@ -823,38 +1125,41 @@ pub async fn post_rotatekey(
         ));
     }
 
-    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let now = time::now_bw();
 
     // Update all folders with new encrypted names (batch operation)
     // Skip null folder IDs (Bitwarden client bug: https://github.com/bitwarden/clients/issues/8453)
-    let mut folder_statements: Vec<D1PreparedStatement> =
+    let mut folder_statements: Vec<Statement> =
         Vec::with_capacity(payload.account_data.folders.len());
     for folder in &payload.account_data.folders {
         // Skip null folder id entries
         let Some(folder_id) = &folder.id else {
             continue;
         };
-        let stmt = query!(
+        let stmt = Database::prepare(
             &db,
             "UPDATE folders SET name = ?1, updated_at = ?2 WHERE id = ?3 AND user_id = ?4",
-            folder.name,
-            now,
-            folder_id,
-            user_id
+            &[
+                folder.name.as_str().into(),
+                now.as_str().into(),
+                folder_id.as_str().into(),
+                user_id.as_str().into(),
+            ],
         )
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
         folder_statements.push(stmt);
     }
     db::execute_in_batches(&db, folder_statements, batch_size).await?;
 
     // Update all ciphers with new encrypted data (batch operation)
     // Only update personal ciphers (organization_id is None)
-    let mut cipher_statements: Vec<D1PreparedStatement> =
-        Vec::with_capacity(personal_ciphers.len());
-    let mut attachment_statements: Vec<D1PreparedStatement> = Vec::new();
+    let mut cipher_statements: Vec<Statement> = Vec::with_capacity(personal_ciphers.len());
+    let mut attachment_statements: Vec<Statement> = Vec::new();
     for cipher in personal_ciphers {
-        // id is guaranteed to exist (validated above)
-        let cipher_id = cipher.id.as_ref().unwrap();
+        // Ciphers without an id were rejected above
+        let Some(cipher_id) = cipher.id.as_ref() else {
+            continue;
+        };
 
         let cipher_data = CipherData {
             name: cipher.name.clone(),
@@ -862,35 +1167,46 @@ pub async fn post_rotatekey(
             type_fields: cipher.type_fields.clone(),
         };
 
-        let data = serde_json::to_string(&cipher_data).map_err(|_| AppError::Internal)?;
+        let data = serde_json::to_string(&cipher_data).map_err(internal_error!())?;
 
-        let stmt = query!(
+        // The folder and favorite flag are kept when left out, like on a single update
+        let stmt = Database::prepare(
             &db,
-            "UPDATE ciphers SET data = ?1, folder_id = ?2, favorite = ?3, updated_at = ?4 WHERE id = ?5 AND user_id = ?6",
-            data,
-            cipher.folder_id,
-            cipher.favorite.unwrap_or(false),
-            now,
-            cipher_id,
-            user_id
+            "UPDATE ciphers SET data = ?1,
+                 folder_id = CASE WHEN ?7 THEN folder_id ELSE ?2 END,
+                 favorite = CASE WHEN ?8 THEN favorite ELSE ?3 END,
+                 updated_at = ?4
+             WHERE id = ?5 AND user_id = ?6",
+            &[
+                data.into(),
+                cipher.folder_id.value().cloned().into(),
+                cipher.favorite.value().copied().unwrap_or(false).into(),
+                now.as_str().into(),
+                cipher_id.as_str().into(),
+                user_id.as_str().into(),
+                cipher.folder_id.is_missing().into(),
+                cipher.favorite.is_missing().into(),
+            ],
         )
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
         cipher_statements.push(stmt);
 
         // Update attachments key and encrypted filename when rotating.
         // The Bitwarden clients send `attachments2` only during key rotation.
         if let Some(attachments2) = &cipher.attachments2 {
             for (attachment_id, attachment) in attachments2 {
-                let stmt = query!(
+                let stmt = Database::prepare(
                     &db,
                     "UPDATE attachments SET file_name = ?1, akey = ?2, updated_at = ?3 WHERE id = ?4 AND cipher_id = ?5",
-                    attachment.file_name,
-                    attachment.key,
-                    now,
-                    attachment_id,
-                    cipher_id
+                    &[
+                        attachment.file_name.as_str().into(),
+                        attachment.key.as_str().into(),
+                        now.as_str().into(),
+                        attachment_id.as_str().into(),
+                        cipher_id.as_str().into(),
+                    ],
                 )
-                .map_err(|_| AppError::Database)?;
+                .map_err(db_error!())?;
                 attachment_statements.push(stmt);
             }
         }
@@ -900,7 +1216,7 @@ pub async fn post_rotatekey(
 
     // Generate new salt and hash the new password
     let new_salt = generate_salt()?;
-    let password_iterations = server_password_iterations(&env) as i32;
+    let password_iterations = settings.password_iterations as i32;
     let new_hashed_password = hash_password_for_storage(
         &unlock_data.master_key_authentication_hash,
         &new_salt,
@@ -919,25 +1235,28 @@ pub async fn post_rotatekey(
     };
 
     // Update user record with new keys and password
-    query!(
-        &db,
+    db.run(
         "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, key = ?4, private_key = ?5, kdf_type = ?6, kdf_iterations = ?7, kdf_memory = ?8, kdf_parallelism = ?9, security_stamp = ?10, updated_at = ?11 WHERE id = ?12",
-        new_hashed_password,
-        new_salt,
-        password_iterations,
-        unlock_data.master_key_encrypted_user_key,
-        payload.account_keys.user_key_encrypted_account_private_key,
-        unlock_data.kdf_type,
-        unlock_data.kdf_iterations,
-        kdf_memory,
-        kdf_parallelism,
-        new_security_stamp,
-        now,
-        user_id
+        &[
+            new_hashed_password.into(),
+            new_salt.into(),
+            password_iterations.into(),
+            unlock_data.master_key_encrypted_user_key.as_str().into(),
+            payload
+                .account_keys
+                .user_key_encrypted_account_private_key
+                .into(),
+            unlock_data.kdf_type.into(),
+            unlock_data.kdf_iterations.into(),
+            kdf_memory.into(),
+            kdf_parallelism.into(),
+            new_security_stamp.into(),
+            now.as_str().into(),
+            user_id.as_str().into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
+    log_account_event(&db, &source, EventType::UserChangedPassword, user_id).await;
 
     Ok(Json(json!({})))
 }
@@ -956,24 +1275,35 @@ pub async fn post_rotatekey(
 ///
 /// 2. Complex format (Bitwarden >= v2025.10.0, e.g. official client 2025.11.x):
 /// { "authenticationData": {...}, "unlockData": {...}, "key": "...", "masterPasswordHash": "...", "newMasterPasswordHash": "..." }
+#[utoipa::path(
+    post,
+    path = "/api/accounts/kdf",
+    tag = "accounts",
+    request_body = ChangeKdfRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn post_kdf(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<ChangeKdfRequest>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppJson(payload): AppJson<ChangeKdfRequest>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
     let user_id = &claims.sub;
 
     // Get the user from the database
     let user: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user).map_err(internal_error!())?;
 
     // Verify the current master password
     let verification = user
@@ -1012,7 +1342,7 @@ pub async fn post_kdf(
 
     // Generate new salt and hash the new password
     let new_salt = generate_salt()?;
-    let password_iterations = server_password_iterations(&env) as i32;
+    let password_iterations = settings.password_iterations as i32;
     let new_hashed_password = hash_password_for_storage(
         payload.get_new_password_hash(),
         &new_salt,
@@ -1022,7 +1352,7 @@ pub async fn post_kdf(
 
     // Generate new security stamp
     let new_security_stamp = Uuid::new_v4().to_string();
-    let now = Utc::now().to_rfc3339();
+    let now = time::now_bw();
 
     // Determine kdf_memory and kdf_parallelism based on KDF type
     let (final_kdf_memory, final_kdf_parallelism) = if kdf_type == KDF_TYPE_ARGON2ID {
@@ -1036,24 +1366,110 @@ pub async fn post_kdf(
     let new_key = payload.get_new_key();
 
     // Update user record with new KDF settings and password
-    query!(
-        &db,
+    db.run(
         "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, key = ?4, kdf_type = ?5, kdf_iterations = ?6, kdf_memory = ?7, kdf_parallelism = ?8, security_stamp = ?9, updated_at = ?10 WHERE id = ?11",
-        new_hashed_password,
-        new_salt,
-        password_iterations,
-        new_key,
-        kdf_type,
-        kdf_iterations,
-        final_kdf_memory,
-        final_kdf_parallelism,
-        new_security_stamp,
-        now,
-        user_id
+        &[
+            new_hashed_password.into(),
+            new_salt.into(),
+            password_iterations.into(),
+            new_key.into(),
+            kdf_type.into(),
+            kdf_iterations.into(),
+            final_kdf_memory.into(),
+            final_kdf_parallelism.into(),
+            new_security_stamp.into(),
+            now.as_str().into(),
+            user_id.as_str().into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
+    log_account_event(&db, &source, EventType::UserChangedPassword, user_id).await;
 
     Ok(Json(json!({})))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Database;
+    use crate::native::testing::{self, registration};
+    use crate::native::{block_on, Env};
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    fn post(env: &Env, path: &str, body: Value) -> (StatusCode, Value) {
+        testing::request(env, Method::POST, path, None, Some(body))
+    }
+
+    fn register(env: &Env, email: &str) -> (StatusCode, Value) {
+        post(env, "/identity/accounts/register", registration(email))
+    }
+
+    fn prelogin(env: &Env, email: &str) -> Value {
+        let (status, body) = post(
+            env,
+            "/identity/accounts/prelogin",
+            json!({ "email": email }),
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body
+    }
+
+    #[test]
+    fn registering_an_email_twice_is_a_conflict() {
+        let env = testing::env();
+        assert_eq!(register(&env, "alice@example.com").0, StatusCode::OK);
+        for email in ["alice@example.com", "ALICE@example.com"] {
+            let (status, body) = register(&env, email);
+            assert_eq!(status, StatusCode::CONFLICT, "{email}");
+            assert_eq!(body["message"], "An account with this email already exists");
+        }
+    }
+
+    #[test]
+    fn prelogin_returns_the_registered_argon2_settings() {
+        let env = testing::env();
+        let mut body = registration("alice@example.com");
+        body["kdf"] = json!(1);
+        body["kdfIterations"] = json!(3);
+        body["kdfMemory"] = json!(64);
+        body["kdfParallelism"] = json!(4);
+        let (status, body) = post(&env, "/identity/accounts/register", body);
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        for email in ["alice@example.com", "ALICE@Example.COM"] {
+            let kdf = prelogin(&env, email);
+            assert_eq!(kdf["kdf"], 1, "{email}");
+            assert_eq!(kdf["kdfIterations"], 3, "{email}");
+            assert_eq!(kdf["kdfMemory"], 64, "{email}");
+            assert_eq!(kdf["kdfParallelism"], 4, "{email}");
+        }
+    }
+
+    #[test]
+    fn prelogin_leaves_argon2_parameters_out_for_pbkdf2() {
+        let env = testing::env();
+        assert_eq!(register(&env, "alice@example.com").0, StatusCode::OK);
+        // Left over from an earlier Argon2 setup
+        block_on(
+            env.d1("vault1")
+                .unwrap()
+                .run("UPDATE users SET kdf_memory = 64, kdf_parallelism = 4", &[]),
+        )
+        .unwrap();
+
+        let kdf = prelogin(&env, "alice@example.com");
+        assert_eq!(kdf["kdf"], 0);
+        assert_eq!(kdf["kdfIterations"], 600000);
+        assert_eq!(kdf["kdfMemory"], Value::Null);
+        assert_eq!(kdf["kdfParallelism"], Value::Null);
+    }
+
+    #[test]
+    fn prelogin_gives_unknown_emails_the_defaults() {
+        let env = testing::env();
+        let kdf = prelogin(&env, "nobody@example.com");
+        assert_eq!(kdf["kdf"], super::KDF_TYPE_PBKDF2);
+        assert_eq!(kdf["kdfIterations"], super::DEFAULT_PBKDF2_ITERATIONS);
+        assert_eq!(kdf["kdfMemory"], Value::Null);
+    }
+}
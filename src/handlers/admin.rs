@@ -0,0 +1,323 @@
+//! Operator endpoints, authenticated with the `ADMIN_TOKEN` secret (see [`AdminAuth`]).
+
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::Env;
+use crate::{
+    auth::AdminAuth,
+    config::Settings,
+    db::{self, Database},
+    error::{db_error, AppError},
+    extract::{AppJson, AppPath, AppQuery},
+    handlers::{
+        attachments, backup,
+        events::{self, EventSource},
+        orphans::{self, OrphanSummary},
+        purge::{self, MaintenanceSummary},
+    },
+    mail::{self, Message},
+    migrations::{self, MigrationSummary},
+    models::{
+        event::{Event, EventType},
+        send::Send,
+    },
+    notify, quota,
+};
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MailTestRequest {
+    pub to: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeVaultRequest {
+    /// The user's email, repeated to make sure the right account is purged.
+    pub confirm: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeVaultQuery {
+    /// Only count what would be deleted.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// What a vault purge deleted, or would delete on a dry run.
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeVaultSummary {
+    #[serde(default)]
+    pub dry_run: bool,
+    pub ciphers: u32,
+    pub folders: u32,
+    pub attachments: u32,
+    pub sends: u32,
+}
+
+/// GET /admin/diagnostics
+///
+/// The schema version of the database and the one this build expects, and the last scheduled
+/// backup (`null` before the first one, or without `BACKUP_BUCKET`).
+#[utoipa::path(
+    get,
+    path = "/admin/diagnostics",
+    tag = "admin",
+    responses((status = 200, description = "Success")),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn get_diagnostics(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let applied: Option<u32> = db
+        .first_column(
+            "SELECT MAX(version) AS version FROM schema_migrations",
+            &[],
+            "version",
+        )
+        .await
+        .map_err(db_error!())?;
+
+    Ok(Json(json!({
+        "schemaVersion": applied,
+        "expectedSchemaVersion": backup::schema_version(),
+        "backupBucket": env.bucket(backup::BACKUP_BUCKET).is_ok(),
+        "lastBackup": backup::last_snapshot(&db).await?,
+    })))
+}
+
+/// POST /admin/maintenance
+///
+/// Runs the same cleanup as the scheduled cron trigger and returns what was removed.
+#[utoipa::path(
+    post,
+    path = "/admin/maintenance",
+    tag = "admin",
+    responses((status = 200, description = "Success", body = MaintenanceSummary)),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn post_maintenance(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<MaintenanceSummary>, AppError> {
+    Ok(Json(purge::run_maintenance(&env).await))
+}
+
+/// POST /admin/orphans
+///
+/// Runs only the orphan repairs from the scheduled maintenance and returns what was fixed, per
+/// category. Each call works off a bounded slice of any backlog; repeat it until the counts drop
+/// to zero.
+#[utoipa::path(
+    post,
+    path = "/admin/orphans",
+    tag = "admin",
+    responses((status = 200, description = "Success", body = OrphanSummary)),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn post_orphans(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<OrphanSummary>, AppError> {
+    Ok(Json(orphans::run_orphan_cleanup(&env).await))
+}
+
+/// POST /admin/migrations
+///
+/// Applies the pending schema migrations and returns which ran. Safe to call repeatedly, and
+/// concurrently with AUTO_MIGRATE.
+#[utoipa::path(
+    post,
+    path = "/admin/migrations",
+    tag = "admin",
+    responses((status = 200, description = "Success", body = MigrationSummary)),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn post_migrations(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<MigrationSummary>, AppError> {
+    let db = db::get_db(&env)?;
+    Ok(Json(migrations::run(&db).await?))
+}
+
+/// POST /admin/mail/test
+///
+/// Sends a test email to `to` through the configured provider. Returns whether the provider
+/// accepted it and, if not, its answer.
+#[utoipa::path(
+    post,
+    path = "/admin/mail/test",
+    tag = "admin",
+    request_body = MailTestRequest,
+    responses((status = 200, description = "Success")),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn post_mail_test(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    AppJson(payload): AppJson<MailTestRequest>,
+) -> Result<Json<Value>, AppError> {
+    let to = payload.to.trim().to_string();
+    if !to.contains('@') {
+        return Err(AppError::validation("to", "Invalid email address"));
+    }
+
+    let settings = Settings::get(&env);
+    let provider = match &settings.mail {
+        Ok(Some(mail)) => mail.provider.name(),
+        Ok(None) => {
+            return Err(AppError::BadRequest(
+                "Mail is not configured: set MAIL_PROVIDER".to_string(),
+            ))
+        }
+        Err(invalid) => return Err(AppError::BadRequest(invalid.to_string())),
+    };
+
+    let message = Message {
+        to,
+        subject: format!("{} test email", settings.server_name),
+        text: format!(
+            "This is a test email from {}. Mail is configured correctly.",
+            settings.server_name
+        ),
+        html: None,
+    };
+    let result = mail::send(&env, &message).await;
+    Ok(Json(json!({
+        "provider": provider,
+        "to": message.to,
+        "sent": result.is_ok(),
+        "error": result.err().map(|err| err.to_string()),
+    })))
+}
+
+/// POST /admin/users/{id}/purge-vault
+///
+/// Deletes the user's personal ciphers (with their attachments), folders and Sends, including the
+/// stored files, and keeps the account. `confirm` in the body must be the user's email. With
+/// `?dryRun=true`, only returns the counts that would be deleted. Organization items are left
+/// alone.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/purge-vault",
+    tag = "admin",
+    params(("id" = String, Path)),
+    request_body = PurgeVaultRequest,
+    responses((status = 200, description = "Success", body = PurgeVaultSummary)),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn post_purge_user_vault(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    source: EventSource,
+    AppPath(user_id): AppPath<String>,
+    AppQuery(query): AppQuery<PurgeVaultQuery>,
+    AppJson(payload): AppJson<PurgeVaultRequest>,
+) -> Result<Json<PurgeVaultSummary>, AppError> {
+    let db = db::get_db(&env)?;
+    let email: Option<String> = db
+        .first(
+            "SELECT email FROM users WHERE id = ?1",
+            &[user_id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .and_then(|row: Value| row["email"].as_str().map(str::to_string));
+    let email = email.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    if !payload.confirm.trim().eq_ignore_ascii_case(&email) {
+        return Err(AppError::validation(
+            "confirm",
+            "Must be the email of the user whose vault is purged",
+        ));
+    }
+
+    let mut summary: PurgeVaultSummary = db
+        .first(
+            "SELECT
+                 (SELECT COUNT(*) FROM ciphers WHERE user_id = ?1 AND organization_id IS NULL) AS ciphers,
+                 (SELECT COUNT(*) FROM folders WHERE user_id = ?1) AS folders,
+                 (SELECT COUNT(*) FROM attachments a JOIN ciphers c ON a.cipher_id = c.id
+                   WHERE c.user_id = ?1 AND c.organization_id IS NULL) AS attachments,
+                 (SELECT COUNT(*) FROM sends WHERE user_id = ?1) AS sends",
+            &[user_id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .unwrap_or_default();
+    if query.dry_run {
+        summary.dry_run = true;
+        return Ok(Json(summary));
+    }
+
+    if attachments::attachments_enabled(env.as_ref()) {
+        let sends: Vec<Send> = db
+            .all(
+                "SELECT * FROM sends WHERE user_id = ?1",
+                &[user_id.as_str().into()],
+            )
+            .await
+            .map_err(db_error!())?;
+        let mut keys = attachments::list_attachment_keys_for_personal_vault(&db, &user_id).await?;
+        keys.extend(sends.iter().filter_map(Send::storage_key));
+        attachments::delete_storage_objects(env.as_ref(), &keys).await?;
+    }
+
+    // Attachment rows and collection links go with their ciphers
+    Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "DELETE FROM ciphers WHERE user_id = ?1 AND organization_id IS NULL",
+                &[user_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM folders WHERE user_id = ?1",
+                &[user_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM sends WHERE user_id = ?1",
+                &[user_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            quota::recount(&db, &[user_id.as_str()])?,
+        ],
+    )
+    .await?;
+
+    db::touch_user_updated_at(&db, &user_id).await?;
+    notify::notify_vault_sync(&env, &db, &user_id, None).await;
+
+    let mut event = Event::new(EventType::AdminPurgedUserVault, &user_id);
+    event.user_id = Some(user_id.clone());
+    event.acting_user_id = None;
+    events::log_event(&db, &source, event).await;
+    log::info!(
+        "Admin purged the vault of user {user_id}: {} cipher(s), {} folder(s), {} attachment(s), {} Send(s)",
+        summary.ciphers,
+        summary.folders,
+        summary.attachments,
+        summary.sends
+    );
+
+    Ok(Json(summary))
+}
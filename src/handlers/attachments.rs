@@ -2,33 +2,40 @@ use std::{collections::HashMap, sync::Arc};
 
 use axum::{
     body::Bytes,
-    extract::{Multipart, Path, State},
+    extract::{Multipart, State},
     Extension, Json,
 };
 use chrono::{TimeZone, Utc};
-use jwt_compact::AlgorithmExt;
-use jwt_compact::{alg::Hs256Key, Claims as JwtClaims, Header};
-use log;
+use jwt_compact::Claims as JwtClaims;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use utoipa::ToSchema;
 use uuid::Uuid;
-use worker::{query, wasm_bindgen::JsValue, Bucket, D1Database, Env, HttpMetadata};
+use worker::{Bucket, HttpMetadata};
 
+use crate::config::Settings;
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
 use crate::{
-    auth::{Claims, JWT_VALIDATION_LEEWAY_SECS},
-    db,
-    error::AppError,
+    auth::{keys::KeyRing, Claims},
+    db::{
+        self,
+        scoped::{self, org_scoped},
+        Database, Db, Param,
+    },
+    error::{db_error, internal_error, AppError},
+    metrics,
     models::{
         attachment::{AttachmentDB, AttachmentResponse},
         cipher::{Cipher, CipherDBModel},
     },
-    BaseUrl,
+    notify::{self, UpdateType},
+    time, BaseUrl,
 };
 
-const ATTACHMENTS_BUCKET: &str = "ATTACHMENTS_BUCKET";
+pub(crate) const ATTACHMENTS_BUCKET: &str = "ATTACHMENTS_BUCKET";
 const ATTACHMENTS_KV: &str = "ATTACHMENTS_KV";
-const SIZE_LEEWAY_BYTES: i64 = 1024 * 1024; // 1 MiB
-const DEFAULT_ATTACHMENT_TTL_SECS: i64 = 300; // 5 minutes
+pub(crate) const SIZE_LEEWAY_BYTES: i64 = 1024 * 1024; // 1 MiB
 const KV_MAX_VALUE_BYTES: i64 = 25 * 1024 * 1024; // 25 MiB (KV hard limit)
 
 /// Storage backend for attachments
@@ -57,7 +64,7 @@ pub(crate) fn is_kv_backend(env: &Env) -> bool {
     get_storage_backend(env) == Some(StorageBackend::KV)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AttachmentCreateRequest {
     pub key: String,
@@ -68,7 +75,7 @@ pub struct AttachmentCreateRequest {
     pub admin_request: Option<bool>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AttachmentUploadResponse {
     pub object: String,
@@ -79,13 +86,13 @@ pub struct AttachmentUploadResponse {
     pub cipher_response: Cipher,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AttachmentDeleteResponse {
     pub cipher: Cipher,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(untagged)]
 pub enum NumberOrString {
     Number(i64),
@@ -117,38 +124,45 @@ impl NumberOrString {
     }
 }
 
-async fn touch_cipher_updated_at(db: &D1Database, cipher_id: &str) -> Result<(), AppError> {
-    let now = now_string();
-    query!(
-        db,
+/// Bumps the cipher's revision date and returns it.
+async fn touch_cipher_updated_at(db: &Db, cipher_id: &str) -> Result<String, AppError> {
+    let now = time::now_bw();
+    db.run(
         "UPDATE ciphers SET updated_at = ?1 WHERE id = ?2",
-        now,
-        cipher_id
+        &[now.as_str().into(), cipher_id.into()],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
-    Ok(())
+    Ok(now)
 }
 
 /// POST /api/ciphers/{cipher_id}/attachment/v2
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/{id}/attachment/v2",
+    tag = "attachments",
+    params(("id" = String, Path)),
+    request_body = AttachmentCreateRequest,
+    responses((status = 200, description = "Success", body = AttachmentUploadResponse)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn create_attachment_v2(
     claims: Claims,
     State(env): State<Arc<Env>>,
     Extension(BaseUrl(base_url)): Extension<BaseUrl>,
-    Path(cipher_id): Path<String>,
-    Json(payload): Json<AttachmentCreateRequest>,
+    AppPath(cipher_id): AppPath<String>,
+    AppJson(payload): AppJson<AttachmentCreateRequest>,
 ) -> Result<Json<AttachmentUploadResponse>, AppError> {
+    let db = db::get_db(&env)?;
+
+    // Another user's cipher is missing whether or not attachments are enabled
+    let cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
     // Require storage backend; fail directly if missing
     if !attachments_enabled(&env) {
         return Err(AppError::BadRequest(
             "Attachments are not enabled".to_string(),
         ));
     }
-    let db = db::get_db(&env)?;
-
-    let cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
 
     let AttachmentCreateRequest {
         key,
@@ -174,22 +188,21 @@ pub async fn create_attachment_v2(
     .await?;
 
     let attachment_id = Uuid::new_v4().to_string();
-    let now = now_string();
+    let now = time::now_bw();
 
-    query!(
-        &db,
+    db.run(
         "INSERT INTO attachments_pending (id, cipher_id, file_name, file_size, akey, created_at, updated_at, organization_id)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?7)",
-        attachment_id,
-        cipher.id,
-        file_name,
-        declared_size,
-        key,
-        now,
-        cipher.organization_id,
+        &[
+            attachment_id.as_str().into(),
+            cipher.id.as_str().into(),
+            file_name.as_str().into(),
+            declared_size.into(),
+            key.as_str().into(),
+            now.as_str().into(),
+            cipher.organization_id.clone().into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
 
     // Return upload URL pointing to local upload endpoint
@@ -228,21 +241,30 @@ pub async fn create_attachment_v2(
 }
 
 /// POST /api/ciphers/{cipher_id}/attachment/{attachment_id}
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/{id}/attachment/{attachment_id}",
+    tag = "attachments",
+    params(("id" = String, Path), ("attachment_id" = String, Path)),
+    request_body(content_type = "multipart/form-data"),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn upload_attachment_v2_data(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path((cipher_id, attachment_id)): Path<(String, String)>,
+    AppPath((cipher_id, attachment_id)): AppPath<(String, String)>,
     mut multipart: Multipart,
 ) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+
+    let _cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
     if !attachments_enabled(&env) {
         return Err(AppError::BadRequest(
             "Attachments are not enabled".to_string(),
         ));
     }
-    let db = db::get_db(&env)?;
-
-    let _cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
 
     let mut pending = fetch_pending_attachment(&db, &attachment_id).await?;
     if pending.cipher_id != cipher_id {
@@ -259,13 +281,10 @@ pub async fn upload_attachment_v2_data(
     // For KV backend: skip this check (we trust the actual upload size)
     if !is_kv_backend(&env) {
         if let Err(e) = validate_size_within_declared(&pending, actual_size) {
-            query!(
-                &db,
+            db.run(
                 "DELETE FROM attachments_pending WHERE id = ?1",
-                pending.id
+                &[pending.id.into()],
             )
-            .map_err(|_| AppError::Database)?
-            .run()
             .await?;
             return Err(e);
         }
@@ -286,58 +305,74 @@ pub async fn upload_attachment_v2_data(
 
     // Save to storage (KV or R2)
     upload_to_storage(&env, &pending.r2_key(), content_type, file_bytes.to_vec()).await?;
+    metrics::record(&env, metrics::attachment(&claims.sub, file_bytes.len()));
 
     // Finalize: move pending -> attachments and touch timestamps
-    let now = now_string();
-    query!(
-        &db,
+    let now = time::now_bw();
+    db.run(
         "INSERT INTO attachments (id, cipher_id, file_name, file_size, akey, created_at, updated_at, organization_id)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-        pending.id,
-        pending.cipher_id,
-        pending.file_name,
-        actual_size,
-        pending.akey,
-        pending.created_at,
-        now,
-        pending.organization_id,
+        &[
+            pending.id.as_str().into(),
+            pending.cipher_id.into(),
+            pending.file_name.into(),
+            actual_size.into(),
+            pending.akey.into(),
+            pending.created_at.into(),
+            now.into(),
+            pending.organization_id.into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
 
-    query!(
-        &db,
+    db.run(
         "DELETE FROM attachments_pending WHERE id = ?1",
-        pending.id
+        &[pending.id.into()],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
 
-    touch_cipher_updated_at(&db, &cipher_id).await?;
+    let revision_date = touch_cipher_updated_at(&db, &cipher_id).await?;
     db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_cipher_update(
+        &env,
+        &db,
+        UpdateType::SyncCipherUpdate,
+        &claims.sub,
+        &cipher_id,
+        &revision_date,
+        claims.device.as_deref(),
+    )
+    .await;
 
     Ok(Json(()))
 }
 
 /// POST /api/ciphers/{cipher_id}/attachment
 /// Legacy API for creating an attachment associated with a cipher.
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/{id}/attachment",
+    tag = "attachments",
+    params(("id" = String, Path)),
+    request_body(content_type = "multipart/form-data"),
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn upload_attachment_legacy(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path(cipher_id): Path<String>,
+    AppPath(cipher_id): AppPath<String>,
     mut multipart: Multipart,
 ) -> Result<Json<Cipher>, AppError> {
+    let db = db::get_db(&env)?;
+
+    let cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
     if !attachments_enabled(&env) {
         return Err(AppError::BadRequest(
             "Attachments are not enabled".to_string(),
         ));
     }
-    let db = db::get_db(&env)?;
-
-    let cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
 
     let (file_bytes, content_type, key, file_name) = read_multipart(&mut multipart).await?;
     let key = key.ok_or_else(|| AppError::BadRequest("No attachment key provided".to_string()))?;
@@ -355,22 +390,21 @@ pub async fn upload_attachment_legacy(
     enforce_limits(&db, &env, &claims.sub, actual_size, None).await?;
 
     let attachment_id = Uuid::new_v4().to_string();
-    let now = now_string();
+    let now = time::now_bw();
 
-    query!(
-        &db,
+    db.run(
         "INSERT INTO attachments (id, cipher_id, file_name, file_size, akey, created_at, updated_at, organization_id)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?7)",
-        attachment_id,
-        cipher.id,
-        file_name,
-        actual_size,
-        key,
-        now,
-        cipher.organization_id,
+        &[
+            attachment_id.as_str().into(),
+            cipher.id.as_str().into(),
+            file_name.into(),
+            actual_size.into(),
+            key.into(),
+            now.into(),
+            cipher.organization_id.clone().into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
 
     // Save to storage (KV or R2)
@@ -381,9 +415,20 @@ pub async fn upload_attachment_legacy(
         file_bytes.to_vec(),
     )
     .await?;
+    metrics::record(&env, metrics::attachment(&claims.sub, file_bytes.len()));
 
-    touch_cipher_updated_at(&db, &cipher_id).await?;
+    let revision_date = touch_cipher_updated_at(&db, &cipher_id).await?;
     db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_cipher_update(
+        &env,
+        &db,
+        UpdateType::SyncCipherUpdate,
+        &claims.sub,
+        &cipher_id,
+        &revision_date,
+        claims.device.as_deref(),
+    )
+    .await;
 
     // reload cipher to return fresh updated_at and attachments state
     let mut cipher_response: Cipher = cipher.into();
@@ -393,21 +438,29 @@ pub async fn upload_attachment_legacy(
 }
 
 /// GET /api/ciphers/{cipher_id}/attachment/{attachment_id}
+#[utoipa::path(
+    get,
+    path = "/api/ciphers/{id}/attachment/{attachment_id}",
+    tag = "attachments",
+    params(("id" = String, Path), ("attachment_id" = String, Path)),
+    responses((status = 200, description = "Success", body = AttachmentResponse)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_attachment(
     claims: Claims,
     State(env): State<Arc<Env>>,
     Extension(BaseUrl(base_url)): Extension<BaseUrl>,
-    Path((cipher_id, attachment_id)): Path<(String, String)>,
+    AppPath((cipher_id, attachment_id)): AppPath<(String, String)>,
 ) -> Result<Json<AttachmentResponse>, AppError> {
+    let db = db::get_db(&env)?;
+
+    let cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
     if !attachments_enabled(&env) {
         return Err(AppError::BadRequest(
             "Attachments are not enabled".to_string(),
         ));
     }
-    let db = db::get_db(&env)?;
-
-    let cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
     let attachment = fetch_attachment(&db, &attachment_id).await?;
 
     if attachment.cipher_id != cipher.id {
@@ -421,20 +474,28 @@ pub async fn get_attachment(
 }
 
 /// DELETE /api/ciphers/{cipher_id}/attachment/{attachment_id}
+#[utoipa::path(
+    delete,
+    path = "/api/ciphers/{id}/attachment/{attachment_id}",
+    tag = "attachments",
+    params(("id" = String, Path), ("attachment_id" = String, Path)),
+    responses((status = 200, description = "Success", body = AttachmentDeleteResponse)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn delete_attachment(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path((cipher_id, attachment_id)): Path<(String, String)>,
+    AppPath((cipher_id, attachment_id)): AppPath<(String, String)>,
 ) -> Result<Json<AttachmentDeleteResponse>, AppError> {
+    let db = db::get_db(&env)?;
+
+    let cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
     if !attachments_enabled(&env) {
         return Err(AppError::BadRequest(
             "Attachments are not enabled".to_string(),
         ));
     }
-    let db = db::get_db(&env)?;
-
-    let cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub).await?;
     let attachment = fetch_attachment(&db, &attachment_id).await?;
 
     if attachment.cipher_id != cipher.id {
@@ -446,13 +507,24 @@ pub async fn delete_attachment(
     // Delete storage object; ignore missing objects
     delete_storage_objects(&env, &[attachment.r2_key()]).await?;
 
-    query!(&db, "DELETE FROM attachments WHERE id = ?1", attachment.id)
-        .map_err(|_| AppError::Database)?
-        .run()
-        .await?;
+    db.run(
+        "DELETE FROM attachments WHERE id = ?1",
+        &[attachment.id.into()],
+    )
+    .await?;
 
-    touch_cipher_updated_at(&db, &cipher_id).await?;
+    let revision_date = touch_cipher_updated_at(&db, &cipher_id).await?;
     db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_cipher_update(
+        &env,
+        &db,
+        UpdateType::SyncCipherUpdate,
+        &claims.sub,
+        &cipher_id,
+        &revision_date,
+        claims.device.as_deref(),
+    )
+    .await;
 
     // Reload cipher to return fresh updated_at and attachments state
     let mut cipher_response: Cipher = ensure_cipher_for_user(&db, &cipher_id, &claims.sub)
@@ -467,18 +539,26 @@ pub async fn delete_attachment(
 
 /// POST /api/ciphers/{cipher_id}/attachment/{attachment_id}/delete
 /// Legacy API for deleting an attachment associated with a cipher.
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/{id}/attachment/{attachment_id}/delete",
+    tag = "attachments",
+    params(("id" = String, Path), ("attachment_id" = String, Path)),
+    responses((status = 200, description = "Success", body = AttachmentDeleteResponse)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn delete_attachment_post(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path((cipher_id, attachment_id)): Path<(String, String)>,
+    AppPath((cipher_id, attachment_id)): AppPath<(String, String)>,
 ) -> Result<Json<AttachmentDeleteResponse>, AppError> {
-    delete_attachment(claims, State(env), Path((cipher_id, attachment_id))).await
+    delete_attachment(claims, State(env), AppPath((cipher_id, attachment_id))).await
 }
 
 /// Attach attachment information to Cipher (used by other handlers)
 pub async fn hydrate_cipher_attachments(
-    db: &D1Database,
+    db: &Db,
     env: &Env,
     cipher: &mut Cipher,
 ) -> Result<(), AppError> {
@@ -487,7 +567,7 @@ pub async fn hydrate_cipher_attachments(
         return Ok(());
     }
 
-    let ids_json = serde_json::to_string(&[&cipher.id]).map_err(|_| AppError::Internal)?;
+    let ids_json = serde_json::to_string(&[&cipher.id]).map_err(internal_error!())?;
     let mut map = load_attachment_map_json(db, &ids_json, "$").await?;
     if let Some(list) = map.remove(&cipher.id) {
         if !list.is_empty() {
@@ -521,20 +601,17 @@ pub(crate) async fn delete_r2_objects(bucket: &Bucket, keys: &[String]) -> Resul
 pub(crate) async fn delete_storage_objects(env: &Env, keys: &[String]) -> Result<(), AppError> {
     match get_storage_backend(env) {
         Some(StorageBackend::KV) => {
-            let kv = env.kv(ATTACHMENTS_KV).map_err(|_| AppError::Internal)?;
+            let kv = env.kv(ATTACHMENTS_KV).map_err(internal_error!())?;
             for key in keys {
                 // KV delete is idempotent - no error if key doesn't exist
                 if let Err(e) = kv.delete(key).await {
-                    log::error!("KV delete error for key '{}': {:?}", key, e);
-                    return Err(AppError::Internal);
+                    return Err(AppError::internal(format!("KV delete of '{key}'"), e));
                 }
             }
             Ok(())
         }
         Some(StorageBackend::R2) => {
-            let bucket = env
-                .bucket(ATTACHMENTS_BUCKET)
-                .map_err(|_| AppError::Internal)?;
+            let bucket = env.bucket(ATTACHMENTS_BUCKET).map_err(internal_error!())?;
             delete_r2_objects(&bucket, keys).await
         }
         None => Ok(()), // No-op if attachments not enabled
@@ -551,68 +628,73 @@ fn map_rows_to_keys(rows: Vec<AttachmentKeyRow>) -> Vec<String> {
 /// - `json_body`: JSON text containing the ids array
 /// - `ids_path`: path to ids array within json_body (e.g. "$.ids" or "$" if top-level)
 pub(crate) async fn list_attachment_keys_for_cipher_ids_json(
-    db: &D1Database,
+    db: &Db,
     json_body: &str,
     ids_path: &str,
     user_id: Option<&str>,
 ) -> Result<Vec<String>, AppError> {
     let mut sql = "SELECT a.cipher_id, a.id FROM attachments a JOIN ciphers c ON a.cipher_id = c.id WHERE c.id IN (SELECT value FROM json_each(?1, ?2))".to_string();
-    let mut params: Vec<worker::wasm_bindgen::JsValue> =
-        vec![json_body.to_owned().into(), ids_path.to_owned().into()];
+    let mut params: Vec<Param> = vec![json_body.into(), ids_path.into()];
 
     if let Some(uid) = user_id {
-        sql.push_str(" AND c.user_id = ?3");
+        sql.push_str(" AND ");
+        sql.push_str(&org_scoped::cipher_writable_sql("?3"));
         params.push(uid.into());
     }
 
-    let rows: Vec<AttachmentKeyRow> = db
-        .prepare(&sql)
-        .bind(&params)?
-        .all()
-        .await
-        .map_err(db::map_d1_json_error)?
-        .results()
-        .map_err(|_| AppError::Database)?;
+    let rows: Vec<AttachmentKeyRow> = db.all(&sql, &params).await.map_err(db::map_d1_json_error)?;
 
     Ok(map_rows_to_keys(rows))
 }
 
 pub(crate) async fn list_attachment_keys_for_user(
-    db: &D1Database,
+    db: &Db,
     user_id: &str,
 ) -> Result<Vec<String>, AppError> {
     let rows: Vec<AttachmentKeyRow> = db
-        .prepare(
+        .all(
             "SELECT a.cipher_id, a.id FROM attachments a \
              JOIN ciphers c ON a.cipher_id = c.id \
              WHERE c.user_id = ?1",
+            &[user_id.into()],
         )
-        .bind(&[user_id.into()])?
-        .all()
         .await
-        .map_err(|_| AppError::Database)?
-        .results()
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
 
     Ok(map_rows_to_keys(rows))
 }
 
-pub(crate) async fn list_attachment_keys_for_soft_deleted_before(
-    db: &D1Database,
-    cutoff_exclusive: &str,
+/// Like [`list_attachment_keys_for_user`], but only for the user's personal ciphers.
+pub(crate) async fn list_attachment_keys_for_personal_vault(
+    db: &Db,
+    user_id: &str,
 ) -> Result<Vec<String>, AppError> {
     let rows: Vec<AttachmentKeyRow> = db
-        .prepare(
+        .all(
             "SELECT a.cipher_id, a.id FROM attachments a \
              JOIN ciphers c ON a.cipher_id = c.id \
-             WHERE c.deleted_at IS NOT NULL AND c.deleted_at < ?1",
+             WHERE c.user_id = ?1 AND c.organization_id IS NULL",
+            &[user_id.into()],
         )
-        .bind(&[cutoff_exclusive.into()])?
-        .all()
         .await
-        .map_err(|_| AppError::Database)?
-        .results()
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
+
+    Ok(map_rows_to_keys(rows))
+}
+
+pub(crate) async fn list_attachment_keys_for_organization(
+    db: &Db,
+    org_id: &str,
+) -> Result<Vec<String>, AppError> {
+    let rows: Vec<AttachmentKeyRow> = db
+        .all(
+            "SELECT a.cipher_id, a.id FROM attachments a \
+             JOIN ciphers c ON a.cipher_id = c.id \
+             WHERE c.organization_id = ?1",
+            &[org_id.into()],
+        )
+        .await
+        .map_err(db_error!())?;
 
     Ok(map_rows_to_keys(rows))
 }
@@ -631,29 +713,26 @@ fn download_url(
     ))
 }
 
-fn now_string() -> String {
-    Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
-}
-
 async fn ensure_cipher_for_user(
-    db: &D1Database,
+    db: &Db,
     cipher_id: &str,
     user_id: &str,
 ) -> Result<CipherDBModel, AppError> {
-    let cipher: Option<CipherDBModel> = db
-        .prepare("SELECT * FROM ciphers WHERE id = ?1 AND user_id = ?2")
-        .bind(&[cipher_id.into(), user_id.into()])?
-        .first(None)
+    let cipher = scoped::cipher_by_id(db, user_id, cipher_id)
         .await
-        .map_err(|_| AppError::Database)?;
-
-    let cipher = cipher.ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))?;
-
-    if cipher.organization_id.is_some() {
-        return Err(AppError::BadRequest(
-            "Organization attachments are not supported".to_string(),
-        ));
-    }
+        .map_err(db_error!())?;
+    let Some(cipher) = cipher else {
+        // An organization cipher the user can see exists, but can't hold attachments yet
+        let visible = org_scoped::cipher_by_id(db, user_id, cipher_id)
+            .await
+            .map_err(db_error!())?;
+        return Err(match visible {
+            Some(_) => {
+                AppError::BadRequest("Organization attachments are not supported".to_string())
+            }
+            None => AppError::NotFound("Cipher not found".to_string()),
+        });
+    };
 
     if cipher.deleted_at.is_some() {
         return Err(AppError::BadRequest("Cipher is deleted".to_string()));
@@ -662,42 +741,38 @@ async fn ensure_cipher_for_user(
     Ok(cipher)
 }
 
-async fn fetch_attachment(db: &D1Database, attachment_id: &str) -> Result<AttachmentDB, AppError> {
-    db.prepare("SELECT * FROM attachments WHERE id = ?1")
-        .bind(&[attachment_id.into()])?
-        .first(None)
-        .await
-        .map_err(|_| AppError::Database)?
-        .ok_or_else(|| AppError::NotFound("Attachment not found".to_string()))
+async fn fetch_attachment(db: &Db, attachment_id: &str) -> Result<AttachmentDB, AppError> {
+    db.first(
+        "SELECT * FROM attachments WHERE id = ?1",
+        &[attachment_id.into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(|| AppError::NotFound("Attachment not found".to_string()))
 }
 
-async fn fetch_pending_attachment(
-    db: &D1Database,
-    attachment_id: &str,
-) -> Result<AttachmentDB, AppError> {
-    db.prepare("SELECT * FROM attachments_pending WHERE id = ?1")
-        .bind(&[attachment_id.into()])?
-        .first(None)
-        .await
-        .map_err(|_| AppError::Database)?
-        .ok_or_else(|| AppError::NotFound("Attachment not found".to_string()))
+async fn fetch_pending_attachment(db: &Db, attachment_id: &str) -> Result<AttachmentDB, AppError> {
+    db.first(
+        "SELECT * FROM attachments_pending WHERE id = ?1",
+        &[attachment_id.into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(|| AppError::NotFound("Attachment not found".to_string()))
 }
 
 async fn load_attachment_map_json(
-    db: &D1Database,
+    db: &Db,
     json_body: &str,
     ids_path: &str,
 ) -> Result<HashMap<String, Vec<AttachmentResponse>>, AppError> {
     let attachments: Vec<AttachmentDB> = db
-        .prepare(
+        .all(
             "SELECT * FROM attachments WHERE cipher_id IN (SELECT value FROM json_each(?1, ?2))",
+            &[json_body.to_owned().into(), ids_path.to_owned().into()],
         )
-        .bind(&[json_body.to_owned().into(), ids_path.to_owned().into()])?
-        .all()
         .await
-        .map_err(db::map_d1_json_error)?
-        .results()
-        .map_err(|_| AppError::Database)?;
+        .map_err(db::map_d1_json_error)?;
 
     Ok(build_attachment_map(attachments))
 }
@@ -737,7 +812,7 @@ async fn upload_to_r2(
 }
 
 /// Upload data to storage (KV or R2 based on configured backend)
-async fn upload_to_storage(
+pub(crate) async fn upload_to_storage(
     env: &Env,
     key: &str,
     _content_type: Option<String>,
@@ -745,23 +820,20 @@ async fn upload_to_storage(
 ) -> Result<(), AppError> {
     match get_storage_backend(env) {
         Some(StorageBackend::KV) => {
-            let kv = env.kv(ATTACHMENTS_KV).map_err(|_| AppError::Internal)?;
+            let kv = env.kv(ATTACHMENTS_KV).map_err(internal_error!())?;
             // KV put_bytes stores raw binary data
             if let Err(e) = kv
                 .put_bytes(key, &data)
-                .map_err(|_| AppError::Internal)?
+                .map_err(internal_error!())?
                 .execute()
                 .await
             {
-                log::error!("KV put error for key '{}': {:?}", key, e);
-                return Err(AppError::Internal);
+                return Err(AppError::internal(format!("KV put of '{key}'"), e));
             }
             Ok(())
         }
         Some(StorageBackend::R2) => {
-            let bucket = env
-                .bucket(ATTACHMENTS_BUCKET)
-                .map_err(|_| AppError::Internal)?;
+            let bucket = env.bucket(ATTACHMENTS_BUCKET).map_err(internal_error!())?;
             upload_to_r2(&bucket, key, _content_type, data).await
         }
         None => Err(AppError::BadRequest(
@@ -770,7 +842,7 @@ async fn upload_to_storage(
     }
 }
 
-async fn read_multipart(
+pub(crate) async fn read_multipart(
     multipart: &mut Multipart,
 ) -> Result<(Bytes, Option<String>, Option<String>, Option<String>), AppError> {
     let mut file_bytes: Option<Bytes> = None;
@@ -838,26 +910,24 @@ fn build_upload_download_token(
     cipher_id: &str,
     attachment_id: &str,
 ) -> Result<String, AppError> {
-    let ttl_secs = download_ttl_secs(env)?;
+    let settings = Settings::get(env);
+    let ttl_secs = settings.attachment_ttl_secs.clone()?;
     let now = Utc::now().timestamp();
     let exp = now
         .checked_add(ttl_secs)
-        .and_then(|exp| exp.checked_sub(JWT_VALIDATION_LEEWAY_SECS as i64))
-        .ok_or_else(|| AppError::Internal)?;
+        .and_then(|exp| exp.checked_sub(settings.jwt_leeway_secs as i64))
+        .ok_or_else(|| AppError::Internal(Some("download expiry overflowed".to_string())))?;
 
     if exp < 0 {
-        log::error!(
-            "Computed negative expiration for attachment token: cipher={}, attachment={}",
-            cipher_id,
-            attachment_id
-        );
-        return Err(AppError::Internal);
+        return Err(AppError::Internal(Some(format!(
+            "Computed negative expiration for attachment token: cipher={cipher_id}, attachment={attachment_id}"
+        ))));
     }
 
     let expiration = Utc
         .timestamp_opt(exp, 0)
         .single()
-        .ok_or_else(|| AppError::Internal)?;
+        .ok_or_else(|| AppError::Internal(Some("download expiry out of range".to_string())))?;
     let mut claims = JwtClaims::new(AttachmentDownloadClaims {
         sub: user_id.to_string(),
         cipher_id: cipher_id.to_string(),
@@ -865,11 +935,8 @@ fn build_upload_download_token(
     });
     claims.expiration = Some(expiration);
 
-    let secret = jwt_secret(env)?;
-    let key = Hs256Key::new(secret.as_bytes());
-    jwt_compact::alg::Hs256
-        .token(&Header::empty(), &claims, &key)
-        .map_err(|_| AppError::Crypto("Failed to create attachment token".to_string()))
+    // Signed with the primary `JWT_SECRET`, which is also what the JS fast-path verifies against.
+    KeyRing::access(env)?.sign(&claims)
 }
 
 fn upload_url(
@@ -886,32 +953,8 @@ fn upload_url(
     ))
 }
 
-fn jwt_secret(env: &Env) -> Result<String, AppError> {
-    Ok(env.secret("JWT_SECRET")?.to_string())
-}
-
-fn download_ttl_secs(env: &Env) -> Result<i64, AppError> {
-    match env.var("ATTACHMENT_TTL_SECS") {
-        Ok(v) => {
-            let raw = v.to_string();
-            let ttl = raw.parse::<i64>().map_err(|err| {
-                log::error!("Invalid ATTACHMENT_TTL_SECS '{}': {}", raw, err);
-                AppError::Internal
-            })?;
-
-            if ttl <= 0 {
-                log::error!("ATTACHMENT_TTL_SECS '{}' must be positive", raw);
-                return Err(AppError::Internal);
-            }
-
-            Ok(ttl)
-        }
-        Err(_) => Ok(DEFAULT_ATTACHMENT_TTL_SECS),
-    }
-}
-
-async fn enforce_limits(
-    db: &D1Database,
+pub(crate) async fn enforce_limits(
+    db: &Db,
     env: &Env,
     user_id: &str,
     new_size: i64,
@@ -931,7 +974,8 @@ async fn enforce_limits(
         )));
     }
 
-    let max_bytes = attachment_max_bytes(env)?;
+    let settings = Settings::get(env);
+    let max_bytes = settings.attachment_max_bytes.clone()?;
     if let Some(max_bytes) = max_bytes {
         if new_size as u64 > max_bytes {
             return Err(AppError::BadRequest(
@@ -941,7 +985,7 @@ async fn enforce_limits(
     }
 
     // Check total storage limit
-    let limit_bytes = total_limit_bytes(env)?;
+    let limit_bytes = settings.attachment_total_limit_bytes.clone()?;
     if let Some(limit_bytes) = limit_bytes {
         let used = user_attachment_usage(db, user_id, exclude_attachment).await?;
         let limit = limit_bytes as i64;
@@ -959,48 +1003,12 @@ async fn enforce_limits(
     Ok(())
 }
 
-fn attachment_max_bytes(env: &Env) -> Result<Option<u64>, AppError> {
-    match env.var("ATTACHMENT_MAX_BYTES") {
-        Ok(v) => {
-            let raw = v.to_string();
-            raw.parse::<u64>().map(Some).map_err(|err| {
-                log::error!("Invalid ATTACHMENT_MAX_BYTES '{}': {}", raw, err);
-                AppError::Internal
-            })
-        }
-        Err(_) => Ok(None),
-    }
-}
-
-fn total_limit_bytes(env: &Env) -> Result<Option<u64>, AppError> {
-    match env.var("ATTACHMENT_TOTAL_LIMIT_KB") {
-        Ok(v) => {
-            let raw = v.to_string();
-            let kb = raw.parse::<u64>().map_err(|err| {
-                log::error!("Invalid ATTACHMENT_TOTAL_LIMIT_KB '{}': {}", raw, err);
-                AppError::Internal
-            })?;
-
-            let bytes = kb.checked_mul(1024).ok_or_else(|| {
-                log::error!(
-                    "ATTACHMENT_TOTAL_LIMIT_KB '{}' overflowed when converting to bytes",
-                    raw
-                );
-                AppError::Internal
-            })?;
-
-            Ok(Some(bytes))
-        }
-        Err(_) => Ok(None),
-    }
-}
-
 async fn user_attachment_usage(
-    db: &D1Database,
+    db: &Db,
     user_id: &str,
     exclude_attachment: Option<&str>,
 ) -> Result<i64, AppError> {
-    let (query_str, bindings): (String, Vec<JsValue>) = if let Some(id) = exclude_attachment {
+    let (query_str, bindings): (String, Vec<Param>) = if let Some(id) = exclude_attachment {
         (
             "SELECT COALESCE(SUM(file_size), 0) as total FROM (
                 SELECT a.file_size AS file_size
@@ -1012,9 +1020,13 @@ async fn user_attachment_usage(
                 FROM attachments_pending p
                 JOIN ciphers c2 ON c2.id = p.cipher_id
                 WHERE c2.user_id = ?1 AND p.id != ?2
+                UNION ALL
+                SELECT CAST(json_extract(s.data, '$.size') AS INTEGER) AS file_size
+                FROM sends s
+                WHERE s.user_id = ?1 AND s.atype = 1 AND json_extract(s.data, '$.id') != ?2
             ) AS files"
                 .to_string(),
-            vec![JsValue::from_str(user_id), JsValue::from_str(id)],
+            vec![user_id.into(), id.into()],
         )
     } else {
         (
@@ -1028,18 +1040,17 @@ async fn user_attachment_usage(
                 FROM attachments_pending p
                 JOIN ciphers c2 ON c2.id = p.cipher_id
                 WHERE c2.user_id = ?1
+                UNION ALL
+                SELECT CAST(json_extract(s.data, '$.size') AS INTEGER) AS file_size
+                FROM sends s
+                WHERE s.user_id = ?1 AND s.atype = 1
             ) AS files"
                 .to_string(),
-            vec![JsValue::from_str(user_id)],
+            vec![user_id.into()],
         )
     };
 
-    let row: Option<Value> = db
-        .prepare(query_str)
-        .bind(&bindings)?
-        .first(None)
-        .await
-        .map_err(|_| AppError::Database)?;
+    let row: Option<Value> = db.first(&query_str, &bindings).await.map_err(db_error!())?;
 
     let total = row
         .and_then(|v| v.get("total").cloned())
@@ -0,0 +1,532 @@
+//! Login with device ("auth requests").
+//!
+//! Flow:
+//! 1. The new device posts `/api/auth-requests` (unauthenticated) with its public key and a random
+//!    access code.
+//! 2. An already logged-in device of the same user lists pending requests and approves one with
+//!    `PUT /api/auth-requests/{id}`, sending the user key encrypted to the requester's public key.
+//! 3. The new device polls `/api/auth-requests/{id}/response?code=...` and, once approved, calls
+//!    the token endpoint with `authRequest={id}` and the access code as `password`.
+//!
+//! Requests expire 15 minutes after creation (`AUTH_REQUEST_TTL_MINUTES`).
+
+use axum::{extract::State, http::HeaderMap, Extension, Json};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::extract::{AppJson, AppPath, AppQuery};
+use crate::Env;
+use crate::{
+    auth::Claims,
+    crypto::ct_eq,
+    db::{self, Database, Db},
+    error::{db_error, AppError},
+    models::auth_request::{
+        expiry_cutoff, AuthRequest, AuthRequestCreate, AuthRequestUpdate, AuthResponseQuery,
+    },
+    time, BaseUrl,
+};
+
+fn not_found() -> AppError {
+    AppError::NotFound("AuthRequest doesn't exist".to_string())
+}
+
+/// Client IP as reported by Cloudflare.
+pub(crate) fn client_ip(headers: &HeaderMap) -> String {
+    headers
+        .get("CF-Connecting-IP")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Bitwarden `DeviceType` sent by clients in the `Device-Type` header.
+pub(crate) fn device_type(headers: &HeaderMap) -> i32 {
+    headers
+        .get("Device-Type")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<i32>().ok())
+        .unwrap_or(14) // UnknownBrowser
+}
+
+async fn find_auth_request(db: &Db, id: &str) -> Result<Option<AuthRequest>, AppError> {
+    db.first("SELECT * FROM auth_requests WHERE id = ?1", &[id.into()])
+        .await
+        .map_err(db_error!())
+}
+
+/// Loads an auth request that may be used to log in as `user_id`.
+///
+/// The request must be approved, unexpired, not yet used, and `access_code` must match.
+/// On success the request is marked as used so it cannot be replayed.
+pub(crate) async fn consume_auth_request(
+    db: &Db,
+    id: &str,
+    user_id: &str,
+    access_code: &str,
+) -> Result<(), AppError> {
+    let invalid = || AppError::Unauthorized("Invalid credentials".to_string());
+    let auth_request = find_auth_request(db, id).await?.ok_or_else(invalid)?;
+
+    if auth_request.user_id != user_id
+        || !auth_request.is_approved()
+        || auth_request.is_expired()
+        || auth_request.authentication_date.is_some()
+        || !ct_eq(&auth_request.access_code, access_code)
+    {
+        return Err(invalid());
+    }
+
+    let now = time::now_bw();
+    let changes = db
+        .run(
+            "UPDATE auth_requests SET authentication_date = ?1 WHERE id = ?2 AND authentication_date IS NULL",
+            &[now.as_str().into(), id.into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    // Lost a race with a concurrent login using the same request.
+    if changes == 0 {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+/// POST /api/auth-requests
+///
+/// Unauthenticated: creates a pending login request for `email`. The answer is the same whether
+/// or not an account uses the address.
+#[utoipa::path(
+    post,
+    path = "/api/auth-requests",
+    tag = "auth_requests",
+    request_body = AuthRequestCreate,
+    responses((status = 200, description = "Success"))
+)]
+#[worker::send]
+pub async fn create_auth_request(
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(origin)): Extension<BaseUrl>,
+    headers: HeaderMap,
+    AppJson(payload): AppJson<AuthRequestCreate>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let email = payload.email.to_lowercase();
+
+    // Share the login limiter: each request lets the user's other devices be prompted.
+    if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
+        let rate_limit_key = format!("auth-request:{}", email);
+        if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
+            if !outcome.success {
+                return Err(AppError::TooManyRequests(
+                    "Too many login attempts. Please try again later.".to_string(),
+                ));
+            }
+        }
+    }
+
+    let user_id: Option<String> = db
+        .first_column(
+            "SELECT id FROM users WHERE email = ?1",
+            &[email.into()],
+            "id",
+        )
+        .await
+        .map_err(db_error!())?;
+
+    let auth_request = AuthRequest {
+        id: Uuid::new_v4().to_string(),
+        user_id: user_id.unwrap_or_default(),
+        request_device_identifier: payload.device_identifier,
+        device_type: device_type(&headers),
+        request_ip: client_ip(&headers),
+        response_device_id: None,
+        access_code: payload.access_code,
+        public_key: payload.public_key,
+        enc_key: None,
+        master_password_hash: None,
+        approved: None,
+        creation_date: time::now_bw(),
+        response_date: None,
+        authentication_date: None,
+    };
+
+    // An address without an account gets the same answer, for a request that is never stored,
+    // so the endpoint doesn't tell which addresses have one.
+    if !auth_request.user_id.is_empty() {
+        db.run(
+            "INSERT INTO auth_requests (id, user_id, request_device_identifier, device_type, request_ip, access_code, public_key, creation_date)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            &[
+                auth_request.id.as_str().into(),
+                auth_request.user_id.as_str().into(),
+                auth_request.request_device_identifier.as_str().into(),
+                auth_request.device_type.into(),
+                auth_request.request_ip.as_str().into(),
+                auth_request.access_code.as_str().into(),
+                auth_request.public_key.as_str().into(),
+                auth_request.creation_date.as_str().into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?;
+    }
+
+    Ok(Json(auth_request.to_json(&origin)))
+}
+
+/// GET /api/auth-requests/{id}
+#[utoipa::path(
+    get,
+    path = "/api/auth-requests/{id}",
+    tag = "auth_requests",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_auth_request(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(origin)): Extension<BaseUrl>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let auth_request = find_auth_request(&db, &id)
+        .await?
+        .filter(|r| r.user_id == claims.sub)
+        .ok_or_else(not_found)?;
+
+    Ok(Json(auth_request.to_json(&origin)))
+}
+
+/// PUT /api/auth-requests/{id}
+///
+/// Approves or denies a pending request. Only the owning user's authenticated session may respond.
+#[utoipa::path(
+    put,
+    path = "/api/auth-requests/{id}",
+    tag = "auth_requests",
+    params(("id" = String, Path)),
+    request_body = AuthRequestUpdate,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_auth_request(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(origin)): Extension<BaseUrl>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<AuthRequestUpdate>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut auth_request = find_auth_request(&db, &id)
+        .await?
+        .filter(|r| r.user_id == claims.sub)
+        .ok_or_else(not_found)?;
+
+    if auth_request.is_expired() {
+        return Err(not_found());
+    }
+    if !auth_request.is_pending() {
+        return Err(AppError::BadRequest(
+            "This request has already been answered".to_string(),
+        ));
+    }
+
+    if payload.request_approved {
+        let key = payload
+            .key
+            .ok_or_else(|| AppError::BadRequest("Missing key".to_string()))?;
+        auth_request.enc_key = Some(key);
+        auth_request.master_password_hash = payload.master_password_hash;
+        auth_request.approved = Some(1);
+    } else {
+        auth_request.approved = Some(0);
+    }
+    auth_request.response_device_id = Some(payload.device_identifier);
+    auth_request.response_date = Some(time::now_bw());
+
+    db.run(
+        "UPDATE auth_requests SET approved = ?1, enc_key = ?2, master_password_hash = ?3, response_device_id = ?4, response_date = ?5
+         WHERE id = ?6 AND approved IS NULL",
+        &[
+            auth_request.approved.into(),
+            auth_request.enc_key.as_deref().into(),
+            auth_request.master_password_hash.as_deref().into(),
+            auth_request.response_device_id.as_deref().into(),
+            auth_request.response_date.as_deref().into(),
+            auth_request.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    Ok(Json(auth_request.to_json(&origin)))
+}
+
+/// GET /api/auth-requests/{id}/response?code=...
+///
+/// Unauthenticated poll by the requesting device; the access code proves ownership.
+#[utoipa::path(
+    get,
+    path = "/api/auth-requests/{id}/response",
+    tag = "auth_requests",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success"))
+)]
+#[worker::send]
+pub async fn get_auth_request_response(
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(origin)): Extension<BaseUrl>,
+    AppPath(id): AppPath<String>,
+    AppQuery(params): AppQuery<AuthResponseQuery>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let auth_request = find_auth_request(&db, &id)
+        .await?
+        .filter(|r| !r.is_expired() && ct_eq(&r.access_code, &params.code))
+        .ok_or_else(not_found)?;
+
+    Ok(Json(auth_request.to_json(&origin)))
+}
+
+/// GET /api/auth-requests
+///
+/// Vaultwarden aliases this endpoint to `/api/auth-requests/pending`.
+#[utoipa::path(
+    get,
+    path = "/api/auth-requests",
+    tag = "auth_requests",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_auth_requests(
+    claims: Claims,
+    state: State<Arc<Env>>,
+    origin: Extension<BaseUrl>,
+) -> Result<Json<Value>, AppError> {
+    get_auth_requests_pending(claims, state, origin).await
+}
+
+/// GET /api/auth-requests/pending
+///
+/// Lists the user's unanswered, unexpired requests (newest first).
+#[utoipa::path(
+    get,
+    path = "/api/auth-requests/pending",
+    tag = "auth_requests",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_auth_requests_pending(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(origin)): Extension<BaseUrl>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let cutoff = expiry_cutoff();
+    let requests: Vec<AuthRequest> = db
+        .all(
+            "SELECT * FROM auth_requests
+         WHERE user_id = ?1 AND approved IS NULL AND creation_date >= ?2
+         ORDER BY creation_date DESC",
+            &[claims.sub.as_str().into(), cutoff.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    let data: Vec<Value> = requests.iter().map(|r| r.to_json(&origin)).collect();
+
+    Ok(Json(json!({
+        "data": data,
+        "continuationToken": null,
+        "object": "list"
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth_request::AUTH_REQUEST_TTL_MINUTES;
+    use crate::native::testing::{self, ORIGIN};
+    use crate::native::{block_on, Env};
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, StatusCode};
+    use chrono::{Duration, Utc};
+
+    fn env() -> Env {
+        let env = testing::env();
+        testing::user(&env, "alice");
+        env
+    }
+
+    fn create(env: &Env, email: &str) -> (StatusCode, Value) {
+        let body = json!({
+            "email": email,
+            "publicKey": "cHVibGljLWtleQ==",
+            "deviceIdentifier": "3f1c2d4e-device",
+            "accessCode": "access-code",
+            "type": 0,
+        });
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{ORIGIN}/api/auth-requests"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Device-Type", "9")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        testing::json_response(testing::fetch(env, req))
+    }
+
+    /// A pending request from a new device of alice's, and its id.
+    fn pending(env: &Env) -> String {
+        let (status, body) = create(env, "alice@example.com");
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    /// Approves request `id` as the user of `token`.
+    fn approve(env: &Env, token: &str, id: &str) -> (StatusCode, Value) {
+        testing::request(
+            env,
+            Method::PUT,
+            &format!("/api/auth-requests/{id}"),
+            Some(token),
+            Some(json!({
+                "deviceIdentifier": "7a2e9c41-approving-device",
+                "key": "4.ZW5jcnlwdGVkLXVzZXIta2V5",
+                "masterPasswordHash": null,
+                "requestApproved": true,
+            })),
+        )
+    }
+
+    /// Logs in with request `id`, its access code standing in for the master password.
+    fn login(env: &Env, id: &str) -> (StatusCode, Value) {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs([
+                ("grant_type", "password"),
+                ("username", "alice@example.com"),
+                ("password", "access-code"),
+                ("scope", "api offline_access"),
+                ("client_id", "web"),
+                ("deviceType", "9"),
+                ("deviceIdentifier", "3f1c2d4e-device"),
+                ("deviceName", "firefox"),
+                ("authRequest", id),
+            ])
+            .finish();
+        testing::post_form(env, "/identity/connect/token", &body)
+    }
+
+    fn stored(env: &Env) -> Vec<String> {
+        block_on(
+            env.d1("vault1")
+                .unwrap()
+                .texts("SELECT user_id FROM auth_requests", &[]),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn answers_the_same_whether_or_not_the_account_exists() {
+        let env = env();
+        let (known_status, mut known) = create(&env, "Alice@example.com");
+        let (unknown_status, mut unknown) = create(&env, "nobody@example.com");
+
+        assert_eq!(known_status, StatusCode::OK);
+        assert_eq!(unknown_status, StatusCode::OK);
+        for response in [&mut known, &mut unknown] {
+            let fields = response.as_object_mut().unwrap();
+            assert!(Uuid::parse_str(fields.remove("id").unwrap().as_str().unwrap()).is_ok());
+            assert!(
+                time::parse_bw(fields.remove("creationDate").unwrap().as_str().unwrap()).is_some()
+            );
+        }
+        assert_eq!(known, unknown);
+    }
+
+    #[test]
+    fn stores_requests_only_for_existing_accounts() {
+        let env = env();
+        create(&env, "alice@example.com");
+        create(&env, "nobody@example.com");
+
+        assert_eq!(stored(&env), vec!["alice"]);
+    }
+
+    #[test]
+    fn another_users_approval_is_not_found() {
+        let env = env();
+        let bob = testing::user(&env, "bob");
+        let id = pending(&env);
+
+        assert_eq!(approve(&env, &bob, &id).0, StatusCode::NOT_FOUND);
+        let (status, _) = testing::request(
+            &env,
+            Method::GET,
+            &format!("/api/auth-requests/{id}"),
+            Some(&bob),
+            None,
+        );
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // The request is still waiting for alice's answer
+        let alice = testing::token(&env, "alice@example.com");
+        let (status, request) = testing::request(
+            &env,
+            Method::GET,
+            &format!("/api/auth-requests/{id}"),
+            Some(&alice),
+            None,
+        );
+        assert_eq!(status, StatusCode::OK, "{request}");
+        assert_eq!(request["requestApproved"], Value::Null);
+    }
+
+    #[test]
+    fn polling_an_expired_request_is_not_found() {
+        let env = env();
+        let id = pending(&env);
+        let poll = |code: &str| {
+            let path = format!("/api/auth-requests/{id}/response?code={code}");
+            testing::request(&env, Method::GET, &path, None, None).0
+        };
+        assert_eq!(poll("access-code"), StatusCode::OK);
+        assert_eq!(poll("another-code"), StatusCode::NOT_FOUND);
+
+        let created = Utc::now() - Duration::minutes(AUTH_REQUEST_TTL_MINUTES + 1);
+        block_on(env.d1("vault1").unwrap().run(
+            "UPDATE auth_requests SET creation_date = ?1 WHERE id = ?2",
+            &[time::format_bw(created).into(), id.as_str().into()],
+        ))
+        .unwrap();
+        assert_eq!(poll("access-code"), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn the_auth_request_grant_logs_in_once_after_approval() {
+        let env = env();
+        let alice = testing::token(&env, "alice@example.com");
+        let id = pending(&env);
+
+        let (status, body) = login(&env, &id);
+        assert!(status.is_client_error(), "unapproved: {status} {body}");
+
+        let (status, body) = approve(&env, &alice, &id);
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = login(&env, &id);
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(body["access_token"].is_string());
+
+        let (status, body) = login(&env, &id);
+        assert!(status.is_client_error(), "replayed: {status} {body}");
+    }
+}
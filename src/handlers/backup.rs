@@ -0,0 +1,714 @@
+//! Whole-database backups as JSON, for deployments without Wrangler access to D1.
+//!
+//! A backup is a versioned envelope holding every row of the tables in [`BACKUP_TABLES`]:
+//!
+//! ```json
+//! {"format": "warden-backup", "version": 1, "schemaVersion": 29,
+//!  "createdAt": "2025-01-01T00:00:00.000Z", "tables": {"users": [{"id": "…", …}], …}}
+//! ```
+//!
+//! Rows keep the database's column names and values. Transient state (pending uploads, login
+//! requests, import sessions) and the seeded equivalent domains are left out, and so are
+//! attachment and Send files, which stay in their R2 bucket or KV namespace. Nothing from the
+//! Worker's configuration, such as `ADMIN_TOKEN` or `JWT_SECRET`, is part of a backup.
+//!
+//! D1 has no read transaction spanning requests, so a backup taken while clients are writing may
+//! mix rows from before and after a change; take it while the server is quiet.
+//!
+//! With a `BACKUP_BUCKET` R2 binding, the scheduled handler also writes a backup there every day
+//! (see [`run_scheduled_backup`]), under `backups/` with the time in its name, and deletes the
+//! ones older than BACKUP_RETENTION_DAYS. Like the download, it is written page by page, so large
+//! databases never sit in memory whole.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use worker::{
+    send::{SendFuture, SendWrapper},
+    Bucket, MultipartUpload, UploadedPart,
+};
+
+use crate::Env;
+use crate::{
+    auth::AdminAuth,
+    config::Settings,
+    db::{self, ConstraintViolation, Database, Db},
+    error::{db_error, internal_error, AppError},
+    extract::{AppJson, AppQuery},
+    migrations::MIGRATIONS,
+    time,
+};
+
+const BACKUP_FORMAT: &str = "warden-backup";
+/// Version of the envelope; bumped when its layout changes, not when the schema does.
+const BACKUP_VERSION: u32 = 1;
+/// Tables in a backup, parents before the tables referring to them.
+const BACKUP_TABLES: &[&str] = &[
+    "users",
+    "organizations",
+    "organization_users",
+    "groups",
+    "groups_users",
+    "collections",
+    "collections_users",
+    "collections_groups",
+    "organization_policies",
+    "folders",
+    "ciphers",
+    "attachments",
+    "ciphers_collections",
+    "twofactor",
+    "devices",
+    "sends",
+    "emergency_access",
+    "events",
+    "invitations",
+];
+/// Rows read per query while writing a backup.
+const BACKUP_PAGE_SIZE: u32 = 500;
+/// Column the rowid is selected as, to page through a table.
+const ROWID_COLUMN: &str = "_backup_rowid";
+/// R2 bucket the scheduled backups are written to.
+pub(crate) const BACKUP_BUCKET: &str = "BACKUP_BUCKET";
+/// Prefix of the scheduled backups' keys.
+const SNAPSHOT_PREFIX: &str = "backups/";
+/// Size of the parts a large scheduled backup is uploaded in. R2 wants every part but the last
+/// to be the same size, of at least 5 MiB.
+const SNAPSHOT_PART_BYTES: usize = 8 * 1024 * 1024;
+/// `server_state` key of the last scheduled backup.
+const LAST_BACKUP_STATE: &str = "last_backup";
+
+/// A backup, as [`get_backup`] writes it.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    pub schema_version: u32,
+    pub tables: BTreeMap<String, Vec<Map<String, Value>>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreQuery {
+    /// Delete the existing data first instead of refusing to restore over it.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Rows restored per table.
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreSummary {
+    pub tables: BTreeMap<String, usize>,
+}
+
+/// The last backup written by the scheduled handler.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotRecord {
+    /// Key of the backup in `BACKUP_BUCKET`.
+    pub key: String,
+    pub created_at: String,
+    pub bytes: usize,
+}
+
+/// Latest migration this build knows, recorded in backups.
+pub(crate) fn schema_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// The start of a backup document, up to the opening of its tables.
+fn envelope_header(created_at: DateTime<Utc>) -> String {
+    format!(
+        "{{\"format\":\"{BACKUP_FORMAT}\",\"version\":{BACKUP_VERSION},\"schemaVersion\":{},\"createdAt\":{},\"tables\":{{",
+        schema_version(),
+        json!(time::format_bw(created_at)),
+    )
+}
+
+/// Where a backup being written is at.
+struct BackupCursor {
+    db: Db,
+    /// Index in [`BACKUP_TABLES`] of the table being written.
+    table: usize,
+    /// rowid of the last row written from the table.
+    after: i64,
+    /// Rows written from the table so far.
+    rows: usize,
+    finished: bool,
+}
+
+impl BackupCursor {
+    fn new(db: Db) -> Self {
+        BackupCursor {
+            db,
+            table: 0,
+            after: 0,
+            rows: 0,
+            finished: false,
+        }
+    }
+
+    /// The next piece of the document after the header: a page of rows, with the opening or
+    /// closing of their table as needed, and finally the end of the envelope.
+    async fn next_chunk(&mut self) -> Result<Option<String>, AppError> {
+        if self.finished {
+            return Ok(None);
+        }
+        let Some(table) = BACKUP_TABLES.get(self.table) else {
+            self.finished = true;
+            return Ok(Some("}}".to_string()));
+        };
+
+        let mut chunk = String::new();
+        if self.rows == 0 && self.after == 0 {
+            if self.table > 0 {
+                chunk.push(',');
+            }
+            chunk.push_str(&format!("\"{table}\":["));
+        }
+
+        let rows: Vec<Map<String, Value>> = self
+            .db
+            .all(
+                &format!("SELECT rowid AS {ROWID_COLUMN}, * FROM {table} WHERE rowid > ?1 ORDER BY rowid LIMIT {BACKUP_PAGE_SIZE}"),
+                &[self.after.into()],
+            )
+            .await
+            .map_err(db_error!())?;
+        let page_len = rows.len();
+        for mut row in rows {
+            if let Some(rowid) = row.remove(ROWID_COLUMN).and_then(|rowid| rowid.as_i64()) {
+                self.after = rowid;
+            }
+            if self.rows > 0 {
+                chunk.push(',');
+            }
+            chunk.push_str(&Value::Object(row).to_string());
+            self.rows += 1;
+        }
+
+        if page_len < BACKUP_PAGE_SIZE as usize {
+            chunk.push(']');
+            self.table += 1;
+            self.after = 0;
+            self.rows = 0;
+        }
+        Ok(Some(chunk))
+    }
+}
+
+/// GET /admin/backup
+///
+/// Downloads every account, vault, organization and event as one JSON document, written page by
+/// page as it is read. Restore it with `POST /admin/restore`.
+#[utoipa::path(
+    get,
+    path = "/admin/backup",
+    tag = "backup",
+    responses((status = 200, description = "Success")),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn get_backup(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Response, AppError> {
+    let db = db::get_db(&env)?;
+    let now = Utc::now();
+    let envelope = envelope_header(now);
+
+    let cursor = BackupCursor::new(db);
+    // D1 futures aren't Send, but Workers run them on a single thread anyway
+    let chunks = stream::unfold(Some(SendWrapper::new(cursor)), |cursor| {
+        SendFuture::new(async move {
+            let mut cursor = cursor?;
+            match cursor.next_chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(cursor))),
+                Ok(None) => None,
+                Err(err) => {
+                    // The status is already sent: cutting the body short marks the backup as broken
+                    log::error!("Backup failed: {err:?}");
+                    Some((Err(std::io::Error::other(err.to_string())), None))
+                }
+            }
+        })
+    });
+    let body = Body::from_stream(stream::iter([Ok(envelope)]).chain(chunks));
+
+    let filename = format!("warden-backup-{}.json", now.format("%Y%m%d-%H%M%S"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// POST /admin/restore
+///
+/// Loads a backup from `GET /admin/backup` into the database, whose schema must be up to date.
+/// Refuses with 409 when the database already has data, unless `?force=true`, which deletes it
+/// first. See [`restore`].
+#[utoipa::path(
+    post,
+    path = "/admin/restore",
+    tag = "backup",
+    request_body = Backup,
+    responses((status = 200, description = "Success", body = RestoreSummary)),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn post_restore(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    AppQuery(query): AppQuery<RestoreQuery>,
+    AppJson(backup): AppJson<Backup>,
+) -> Result<Json<RestoreSummary>, AppError> {
+    if backup.format != BACKUP_FORMAT {
+        return Err(AppError::validation("format", "Not a warden-worker backup"));
+    }
+    if backup.version != BACKUP_VERSION {
+        return Err(AppError::validation(
+            "version",
+            format!(
+                "Unsupported backup version {} (expected {BACKUP_VERSION})",
+                backup.version
+            ),
+        ));
+    }
+    if backup.schema_version > schema_version() {
+        return Err(AppError::validation(
+            "schemaVersion",
+            format!(
+                "The backup is from a newer schema (version {}); update this deployment first",
+                backup.schema_version
+            ),
+        ));
+    }
+    if let Some(table) = backup
+        .tables
+        .keys()
+        .find(|table| !BACKUP_TABLES.contains(&table.as_str()))
+    {
+        return Err(AppError::validation(
+            "tables",
+            format!("Unknown table: {table}"),
+        ));
+    }
+
+    let db = db::get_db(&env)?;
+    let summary = restore(&db, &backup, query.force).await?;
+
+    log::info!("Restored backup: {:?}", summary.tables);
+    Ok(Json(summary))
+}
+
+/// Loads the rows of `backup` into `db`, replacing its data when `force` is set.
+///
+/// Every column is checked against the table's schema before anything changes, and the deletes
+/// and inserts then run as one batch, which D1 runs as a transaction: a restore that fails
+/// leaves the existing data as it was.
+async fn restore(db: &Db, backup: &Backup, force: bool) -> Result<RestoreSummary, AppError> {
+    let any_rows = BACKUP_TABLES
+        .iter()
+        .map(|table| format!("EXISTS (SELECT 1 FROM {table})"))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let existing: Option<Value> = db
+        .first(&format!("SELECT {any_rows} AS existing"), &[])
+        .await
+        .map_err(db_error!())?;
+    let has_data = existing.is_some_and(|row| row["existing"].as_f64().unwrap_or(0.0) != 0.0);
+    if has_data && !force {
+        return Err(AppError::Conflict(
+            "The database already has data; restore with ?force=true to replace it".to_string(),
+        ));
+    }
+
+    let mut summary = RestoreSummary::default();
+    let mut statements = Vec::new();
+    if has_data {
+        // Children first; cascades would cover most of them, but not every table has a parent
+        for table in BACKUP_TABLES.iter().rev() {
+            statements.push(
+                Database::prepare(db, &format!("DELETE FROM {table}"), &[]).map_err(db_error!())?,
+            );
+        }
+    }
+    for table in BACKUP_TABLES {
+        let Some(rows) = backup.tables.get(*table) else {
+            continue;
+        };
+        // Column names come from the backup and end up in SQL, so only the table's own pass
+        let known: HashSet<String> = db
+            .texts("SELECT name FROM pragma_table_info(?1)", &[(*table).into()])
+            .await
+            .map_err(db_error!())?
+            .into_iter()
+            .collect();
+        for row in rows {
+            if let Some(column) = row.keys().find(|column| !known.contains(*column)) {
+                return Err(AppError::validation(
+                    "tables",
+                    format!("Unknown column in {table}: {column}"),
+                ));
+            }
+            let columns: Vec<&str> = row.keys().map(String::as_str).collect();
+            let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+            let params: Vec<Value> = row.values().cloned().collect();
+            statements.push(
+                Database::prepare(
+                    db,
+                    &format!(
+                        "INSERT INTO {table} ({}) VALUES ({})",
+                        columns.join(", "),
+                        placeholders.join(", ")
+                    ),
+                    &params,
+                )
+                .map_err(db_error!())?,
+            );
+        }
+        summary.tables.insert(table.to_string(), rows.len());
+    }
+
+    Database::batch(db, statements)
+        .await
+        .map_err(|err| match ConstraintViolation::of(&err) {
+            Some(_) => AppError::validation("tables", format!("The backup doesn't fit: {err}")),
+            None => AppError::database("The backup", err),
+        })?;
+    if has_data {
+        log::warn!("Restore: existing data replaced (force)");
+    }
+    Ok(summary)
+}
+
+/// Writes a backup to `BACKUP_BUCKET` and records it as the last one, then deletes the backups
+/// older than BACKUP_RETENTION_DAYS. Run by the scheduled handler; does nothing without the
+/// bucket. A failed backup keeps the older ones.
+pub async fn run_scheduled_backup(env: &Env) {
+    let Ok(bucket) = env.bucket(BACKUP_BUCKET) else {
+        log::debug!("{BACKUP_BUCKET} is not bound; skipping the scheduled backup");
+        return;
+    };
+
+    match write_snapshot(env, &bucket).await {
+        Ok(snapshot) => log::info!("Backup: wrote {} ({} bytes)", snapshot.key, snapshot.bytes),
+        Err(err) => {
+            log::error!("Backup failed: {err:?}");
+            return;
+        }
+    }
+
+    let retention_days = Settings::get(env).backup_retention_days;
+    match prune_snapshots(&bucket, retention_days).await {
+        Ok(count) => log::info!("Backup: {count} expired backup(s) deleted"),
+        Err(err) => log::error!("Backup: deleting expired backups failed: {err:?}"),
+    }
+}
+
+/// The last backup [`run_scheduled_backup`] wrote, if any.
+pub async fn last_snapshot(db: &Db) -> Result<Option<SnapshotRecord>, AppError> {
+    let value: Option<String> = db
+        .first_column(
+            "SELECT value FROM server_state WHERE key = ?1",
+            &[LAST_BACKUP_STATE.into()],
+            "value",
+        )
+        .await
+        .map_err(db_error!())?;
+    value
+        .map(|value| serde_json::from_str(&value))
+        .transpose()
+        .map_err(internal_error!())
+}
+
+async fn write_snapshot(env: &Env, bucket: &Bucket) -> Result<SnapshotRecord, AppError> {
+    let now = Utc::now();
+    let key = format!(
+        "{SNAPSHOT_PREFIX}warden-backup-{}.json",
+        now.format("%Y%m%d-%H%M%S")
+    );
+
+    let db: Db = env.d1("vault1")?;
+    let mut cursor = BackupCursor::new(db.clone());
+    let mut writer = SnapshotWriter {
+        bucket,
+        key: key.clone(),
+        buffer: Vec::new(),
+        upload: None,
+        parts: Vec::new(),
+        bytes: 0,
+    };
+    let written = async {
+        writer.write(envelope_header(now).as_bytes()).await?;
+        while let Some(chunk) = cursor.next_chunk().await? {
+            writer.write(chunk.as_bytes()).await?;
+        }
+        writer.finish().await
+    }
+    .await;
+    if let Err(err) = written {
+        writer.abort().await;
+        return Err(err);
+    }
+
+    let snapshot = SnapshotRecord {
+        key,
+        created_at: time::format_bw(now),
+        bytes: writer.bytes,
+    };
+    db.run(
+        "INSERT INTO server_state (key, value, updated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+        &[
+            LAST_BACKUP_STATE.into(),
+            serde_json::to_string(&snapshot)
+                .map_err(internal_error!())?
+                .into(),
+            snapshot.created_at.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+    Ok(snapshot)
+}
+
+/// Deletes the scheduled backups uploaded more than `retention_days` ago; none when it is `0` or
+/// less.
+async fn prune_snapshots(bucket: &Bucket, retention_days: i64) -> Result<u32, worker::Error> {
+    if retention_days <= 0 {
+        return Ok(0);
+    }
+    let cutoff_ms = (Utc::now() - Duration::days(retention_days)).timestamp_millis() as u64;
+
+    let mut count = 0;
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut list = bucket.list().prefix(SNAPSHOT_PREFIX);
+        if let Some(cursor) = cursor.take() {
+            list = list.cursor(cursor);
+        }
+        let page = list.execute().await?;
+
+        for object in page.objects() {
+            if object.uploaded().as_millis() < cutoff_ms {
+                bucket.delete(object.key()).await?;
+                count += 1;
+            }
+        }
+
+        match page.cursor() {
+            Some(next) if page.truncated() => cursor = Some(next),
+            _ => break,
+        }
+    }
+    Ok(count)
+}
+
+/// Uploads a scheduled backup as it is written: in a single put when it stays under
+/// [`SNAPSHOT_PART_BYTES`], else as a multipart upload.
+struct SnapshotWriter<'a> {
+    bucket: &'a Bucket,
+    key: String,
+    /// Written data not uploaded yet, shorter than a part.
+    buffer: Vec<u8>,
+    /// Started with the first full part.
+    upload: Option<MultipartUpload>,
+    parts: Vec<UploadedPart>,
+    bytes: usize,
+}
+
+impl SnapshotWriter<'_> {
+    async fn write(&mut self, data: &[u8]) -> Result<(), AppError> {
+        self.buffer.extend_from_slice(data);
+        self.bytes += data.len();
+        while self.buffer.len() >= SNAPSHOT_PART_BYTES {
+            let rest = self.buffer.split_off(SNAPSHOT_PART_BYTES);
+            let part = std::mem::replace(&mut self.buffer, rest);
+            self.upload_part(part).await?;
+        }
+        Ok(())
+    }
+
+    async fn upload_part(&mut self, part: Vec<u8>) -> Result<(), AppError> {
+        let upload = match self.upload.take() {
+            Some(upload) => upload,
+            None => {
+                self.bucket
+                    .create_multipart_upload(self.key.clone())
+                    .execute()
+                    .await?
+            }
+        };
+        let uploaded = upload.upload_part(self.parts.len() as u16 + 1, part).await;
+        self.upload = Some(upload);
+        self.parts.push(uploaded?);
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), AppError> {
+        let rest = std::mem::take(&mut self.buffer);
+        if self.upload.is_none() {
+            self.bucket.put(self.key.clone(), rest).execute().await?;
+            return Ok(());
+        }
+        if !rest.is_empty() {
+            self.upload_part(rest).await?;
+        }
+        if let Some(upload) = self.upload.take() {
+            upload.complete(std::mem::take(&mut self.parts)).await?;
+        }
+        Ok(())
+    }
+
+    /// Drops the parts of an unfinished multipart upload.
+    async fn abort(&mut self) {
+        if let Some(upload) = self.upload.take() {
+            if let Err(err) = upload.abort().await {
+                log::warn!(
+                    "Backup: aborting the upload of {} failed: {err:?}",
+                    self.key
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::block_on;
+
+    fn database() -> Db {
+        let db = Db::in_memory().unwrap();
+        block_on(crate::migrations::run(&db)).unwrap();
+        db
+    }
+
+    fn user(id: &str) -> Value {
+        json!({
+            "id": id, "email": format!("{id}@example.com"), "master_password_hash": "hash",
+            "key": "key", "private_key": "private", "public_key": "public",
+            "created_at": "2025-01-01T00:00:00.000Z", "updated_at": "2025-01-01T00:00:00.000Z",
+        })
+    }
+
+    fn folder(id: &str, user_id: &str) -> Value {
+        json!({
+            "id": id, "user_id": user_id, "name": "2.bmFtZQ==|aXY=|bWFj",
+            "created_at": "2025-01-01T00:00:00.000Z", "updated_at": "2025-01-01T00:00:00.000Z",
+        })
+    }
+
+    fn backup(tables: Value) -> Backup {
+        serde_json::from_value(json!({
+            "format": BACKUP_FORMAT,
+            "version": BACKUP_VERSION,
+            "schemaVersion": schema_version(),
+            "tables": tables,
+        }))
+        .unwrap()
+    }
+
+    fn folder_ids(db: &Db) -> Vec<String> {
+        block_on(db.texts("SELECT id FROM folders ORDER BY id", &[])).unwrap()
+    }
+
+    /// A database holding the user `old` and their folder `kept`.
+    fn database_with_data() -> Db {
+        let db = database();
+        block_on(restore(
+            &db,
+            &backup(json!({ "users": [user("old")], "folders": [folder("kept", "old")] })),
+            false,
+        ))
+        .unwrap();
+        db
+    }
+
+    #[test]
+    fn restores_into_an_empty_database() {
+        let db = database();
+        let summary = block_on(restore(
+            &db,
+            &backup(json!({ "users": [user("u1")], "folders": [folder("f1", "u1")] })),
+            false,
+        ))
+        .unwrap();
+
+        assert_eq!(summary.tables["users"], 1);
+        assert_eq!(summary.tables["folders"], 1);
+        assert_eq!(folder_ids(&db), vec!["f1"]);
+    }
+
+    #[test]
+    fn refuses_to_restore_over_data_without_force() {
+        let db = database_with_data();
+        let err = block_on(restore(
+            &db,
+            &backup(json!({ "users": [user("u1")] })),
+            false,
+        ));
+
+        assert!(matches!(err, Err(AppError::Conflict(_))));
+        assert_eq!(folder_ids(&db), vec!["kept"]);
+    }
+
+    #[test]
+    fn replaces_the_data_with_force() {
+        let db = database_with_data();
+        block_on(restore(
+            &db,
+            &backup(json!({ "users": [user("u1")], "folders": [folder("f1", "u1")] })),
+            true,
+        ))
+        .unwrap();
+
+        assert_eq!(folder_ids(&db), vec!["f1"]);
+    }
+
+    #[test]
+    fn an_unknown_column_changes_nothing() {
+        let db = database_with_data();
+        let mut row = user("u1");
+        row["dropped_long_ago"] = json!(1);
+        let err = block_on(restore(&db, &backup(json!({ "users": [row] })), true));
+
+        assert!(matches!(err, Err(AppError::Validation(_))));
+        assert_eq!(folder_ids(&db), vec!["kept"]);
+    }
+
+    #[test]
+    fn a_failing_insert_changes_nothing() {
+        let db = database_with_data();
+        // The folder's user isn't in the backup, so its foreign key fails after the deletes
+        let err = block_on(restore(
+            &db,
+            &backup(json!({ "users": [user("u1")], "folders": [folder("f1", "missing")] })),
+            true,
+        ));
+
+        assert!(matches!(err, Err(AppError::Validation(_))), "{err:?}");
+        assert_eq!(folder_ids(&db), vec!["kept"]);
+    }
+}
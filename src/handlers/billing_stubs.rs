@@ -0,0 +1,456 @@
+//! Billing stubs: static answers to the plan, subscription and tax requests the web vault makes
+//! while creating organizations and rendering their settings.
+//!
+//! Billing isn't implemented; every organization is a self-hosted one with all features (see
+//! `Organization::to_json`). These endpoints only exist so those pages don't fail on 404s, and
+//! can go once billing is handled for real.
+
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::extract::AppPath;
+use crate::Env;
+use crate::{
+    auth::Claims, db, error::AppError, handlers::organizations::find_organization_for_member,
+};
+
+fn empty_list() -> Value {
+    json!({
+        "data": [],
+        "object": "list",
+        "continuationToken": null,
+    })
+}
+
+/// Tax details nobody entered (`taxInfo`).
+fn empty_tax_info() -> Value {
+    json!({
+        "taxIdNumber": null,
+        "taxIdType": null,
+        "line1": null,
+        "line2": null,
+        "city": null,
+        "state": null,
+        "postalCode": null,
+        "country": null,
+        "object": "taxInfo",
+    })
+}
+
+/// The free plan without limits, which is what organizations created here get.
+fn free_plan() -> Value {
+    // Built in two parts: a single `json!` this deep exceeds the macro recursion limit
+    let password_manager = json!({
+        "stripePlanId": null,
+        "stripeSeatPlanId": null,
+        "stripeProviderPortalSeatPlanId": null,
+        "stripeStoragePlanId": null,
+        "stripePremiumAccessPlanId": null,
+        "basePrice": 0,
+        "seatPrice": 0,
+        "providerPortalSeatPrice": 0,
+        "additionalStoragePricePerGb": 0,
+        "premiumAccessOptionPrice": 0,
+        "baseSeats": 0,
+        "maxAdditionalSeats": null,
+        "baseStorageGb": null,
+        "maxCollections": null,
+        "maxSeats": null,
+        "maxAdditionalStorage": null,
+        "maxProjects": null,
+        "hasAdditionalSeatsOption": false,
+        "hasAdditionalStorageOption": false,
+        "hasPremiumAccessOption": false,
+        "allowSeatAutoscale": false,
+    });
+    json!({
+        "type": 0, // Free
+        "productTier": 0,
+        "name": "Free",
+        "isAnnual": false,
+        "nameLocalizationKey": "planNameFree",
+        "descriptionLocalizationKey": "planDescFree",
+        "canBeUsedByBusiness": true,
+        "trialPeriodDays": null,
+        "hasSelfHost": true,
+        "hasPolicies": true,
+        "hasGroups": true,
+        "hasDirectory": false,
+        "hasEvents": true,
+        "hasTotp": true,
+        "has2fa": true,
+        "hasApi": false,
+        "hasSso": false,
+        "hasKeyConnector": false,
+        "hasScim": false,
+        "hasResetPassword": true,
+        "usersGetPremium": true,
+        "hasCustomPermissions": false,
+        "upgradeSortOrder": 0,
+        "displaySortOrder": 0,
+        "legacyYear": null,
+        "disabled": false,
+        "passwordManager": password_manager,
+        "secretsManager": null,
+        "object": "plan",
+    })
+}
+
+/// GET /api/plans
+#[utoipa::path(
+    get,
+    path = "/api/plans",
+    tag = "billing_stubs",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_plans(_claims: Claims) -> Json<Value> {
+    Json(json!({
+        "data": [free_plan()],
+        "object": "list",
+        "continuationToken": null,
+    }))
+}
+
+/// GET /api/plans/sales-tax-rates
+#[utoipa::path(
+    get,
+    path = "/api/plans/sales-tax-rates",
+    tag = "billing_stubs",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_sales_tax_rates(_claims: Claims) -> Json<Value> {
+    Json(empty_list())
+}
+
+/// GET /api/accounts/tax
+#[utoipa::path(
+    get,
+    path = "/api/accounts/tax",
+    tag = "billing_stubs",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_account_tax(_claims: Claims) -> Json<Value> {
+    Json(empty_tax_info())
+}
+
+/// GET /api/organizations/{id}/tax
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/tax",
+    tag = "billing_stubs",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_organization_tax(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    Ok(Json(empty_tax_info()))
+}
+
+/// GET /api/organizations/{id}/billing-status
+///
+/// Nothing is ever due, so nothing can fail.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/billing-status",
+    tag = "billing_stubs",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_billing_status(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, _membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    Ok(Json(json!({
+        "organizationId": org.id,
+        "organizationName": org.name,
+        "risksSubscriptionFailure": false,
+        "object": "organizationBillingStatus",
+    })))
+}
+
+/// GET /api/organizations/{id}/billing
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/billing",
+    tag = "billing_stubs",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_billing(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    Ok(Json(json!({
+        "balance": 0,
+        "paymentSource": null,
+        "invoices": [],
+        "transactions": [],
+        "object": "billing",
+    })))
+}
+
+/// GET /api/organizations/{id}/billing/metadata
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/billing/metadata",
+    tag = "billing_stubs",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_billing_metadata(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    Ok(Json(json!({
+        "isEligibleForSelfHost": true,
+        "isManaged": false,
+        "isOnSecretsManagerStandalone": false,
+        "isSubscriptionUnpaid": false,
+        "hasSubscription": false,
+        "hasOpenInvoice": false,
+        "isSubscriptionCanceled": false,
+        "invoiceDueDate": null,
+        "invoiceCreatedDate": null,
+        "subPeriodEndDate": null,
+        "organizationOccupiedSeats": null,
+        "object": "organizationBillingMetadata",
+    })))
+}
+
+/// GET /api/organizations/{id}/subscription
+///
+/// The organization's details without a subscription, storage or expiration.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/subscription",
+    tag = "billing_stubs",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_subscription(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, _membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+
+    let mut response = org.to_json();
+    if let Some(fields) = response.as_object_mut() {
+        fields.extend([
+            ("storageName".to_string(), Value::Null),
+            ("storageGb".to_string(), Value::Null),
+            ("subscription".to_string(), Value::Null),
+            ("upcomingInvoice".to_string(), Value::Null),
+            ("customerDiscount".to_string(), Value::Null),
+            ("expiration".to_string(), Value::Null),
+            ("expirationWithoutGracePeriod".to_string(), Value::Null),
+            ("object".to_string(), json!("organizationSubscription")),
+        ]);
+    }
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::organization::MembershipType;
+    use crate::native::testing;
+    use crate::native::Env;
+    use axum::http::{Method, StatusCode};
+
+    /// An organization of alice's, and access tokens for alice and for bob, who isn't a member.
+    fn env() -> (Env, String, String) {
+        let env = testing::env();
+        let alice = testing::user(&env, "alice");
+        let bob = testing::user(&env, "bob");
+        testing::organization(&env, "org", &[("alice", MembershipType::Owner)]);
+        (env, alice, bob)
+    }
+
+    fn get(env: &Env, path: &str, token: &str) -> (StatusCode, Value) {
+        testing::request(env, Method::GET, path, Some(token), None)
+    }
+
+    /// `GET path` as alice answers 200 with every one of `keys` in the body.
+    #[track_caller]
+    fn assert_shape(env: &Env, token: &str, path: &str, keys: &[&str]) -> Value {
+        let (status, body) = get(env, path, token);
+        assert_eq!(status, StatusCode::OK, "{path}: {body}");
+        for key in keys {
+            assert!(body.get(key).is_some(), "{path} lacks {key}: {body}");
+        }
+        body
+    }
+
+    // The keys below are the ones the web vault we bundle (v2025.12.0) reads from each response.
+
+    #[test]
+    fn plans_list_the_free_plan() {
+        let (env, alice, _) = env();
+        let plans = assert_shape(&env, &alice, "/api/plans", &["data", "object"]);
+
+        let plan = &plans["data"][0];
+        for key in [
+            "type",
+            "productTier",
+            "name",
+            "isAnnual",
+            "nameLocalizationKey",
+            "descriptionLocalizationKey",
+            "canBeUsedByBusiness",
+            "hasPolicies",
+            "hasGroups",
+            "hasResetPassword",
+            "upgradeSortOrder",
+            "disabled",
+            "passwordManager",
+            "secretsManager",
+        ] {
+            assert!(plan.get(key).is_some(), "plan lacks {key}");
+        }
+        for key in [
+            "basePrice",
+            "seatPrice",
+            "baseSeats",
+            "maxSeats",
+            "hasAdditionalSeatsOption",
+        ] {
+            assert!(
+                plan["passwordManager"].get(key).is_some(),
+                "passwordManager lacks {key}"
+            );
+        }
+        assert_eq!(plan["type"], 0);
+        assert_eq!(plans["data"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn tax_endpoints_answer_empty_details() {
+        let (env, alice, _) = env();
+
+        let rates = assert_shape(
+            &env,
+            &alice,
+            "/api/plans/sales-tax-rates",
+            &["data", "object"],
+        );
+        assert_eq!(rates["data"], json!([]));
+        for path in ["/api/accounts/tax", "/api/organizations/org/tax"] {
+            let tax = assert_shape(
+                &env,
+                &alice,
+                path,
+                &["taxIdNumber", "country", "postalCode"],
+            );
+            assert_eq!(tax["object"], "taxInfo");
+        }
+    }
+
+    #[test]
+    fn billing_never_fails_or_owes_anything() {
+        let (env, alice, _) = env();
+
+        let status = assert_shape(
+            &env,
+            &alice,
+            "/api/organizations/org/billing-status",
+            &[
+                "organizationId",
+                "organizationName",
+                "risksSubscriptionFailure",
+            ],
+        );
+        assert_eq!(status["risksSubscriptionFailure"], false);
+        let billing = assert_shape(
+            &env,
+            &alice,
+            "/api/organizations/org/billing",
+            &["balance", "paymentSource", "invoices", "transactions"],
+        );
+        assert_eq!(billing["balance"], 0);
+        let metadata = assert_shape(
+            &env,
+            &alice,
+            "/api/organizations/org/billing/metadata",
+            &[
+                "isEligibleForSelfHost",
+                "isManaged",
+                "isOnSecretsManagerStandalone",
+                "isSubscriptionUnpaid",
+                "hasSubscription",
+                "hasOpenInvoice",
+            ],
+        );
+        assert_eq!(metadata["isSubscriptionUnpaid"], false);
+    }
+
+    #[test]
+    fn subscription_is_the_organization_without_one() {
+        let (env, alice, _) = env();
+
+        let subscription = assert_shape(
+            &env,
+            &alice,
+            "/api/organizations/org/subscription",
+            &[
+                "id",
+                "name",
+                "planType",
+                "seats",
+                "subscription",
+                "storageGb",
+                "expiration",
+            ],
+        );
+        assert_eq!(subscription["id"], "org");
+        assert_eq!(subscription["subscription"], Value::Null);
+        assert_eq!(subscription["object"], "organizationSubscription");
+    }
+
+    #[test]
+    fn organization_stubs_are_for_members_only() {
+        let (env, _, bob) = env();
+
+        for path in [
+            "/api/organizations/org/tax",
+            "/api/organizations/org/billing-status",
+            "/api/organizations/org/billing",
+            "/api/organizations/org/billing/metadata",
+            "/api/organizations/org/subscription",
+        ] {
+            assert_eq!(get(&env, path, &bob).0, StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+}
@@ -1,24 +1,48 @@
-use axum::extract::Path;
 use axum::http::header;
 use axum::response::{IntoResponse, Response};
 use axum::{extract::State, Extension, Json};
-use chrono::{DateTime, Utc};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL, Engine};
 use log; // Used for warning logs on parse failures
 use serde::Deserialize;
 use serde_json::Value;
 use std::sync::Arc;
 use uuid::Uuid;
-use worker::{query, wasm_bindgen::JsValue, Env};
 
 use crate::auth::Claims;
-use crate::db;
-use crate::error::AppError;
-use crate::handlers::attachments;
+use crate::config::Settings;
+use crate::db::{
+    self,
+    scoped::{
+        self,
+        org_scoped::{
+            self, cipher_view_password_sql, cipher_visible_sql, cipher_writable_sql,
+            organization_use_totp_sql,
+        },
+        CipherFilter,
+    },
+    Batch, Database, Db, Param, Statement,
+};
+use crate::error::{db_error, internal_error, AppError};
+use crate::extract::{AppJson, AppPath, AppQuery};
+use crate::handlers::{
+    attachments, collections,
+    events::{self, EventSource},
+    organizations::find_organization_for_member,
+};
 use crate::models::cipher::{
     Cipher, CipherDBModel, CipherData, CipherRequestData, CreateCipherRequest, PartialCipherData,
 };
+use crate::models::event::{Event, EventType};
+use crate::models::import::SUPPORTED_CIPHER_TYPES;
+use crate::models::organization::Organization;
+use crate::models::response::{close_raw_list, open_raw_list};
+use crate::models::serde_d1;
 use crate::models::user::{PasswordOrOtpData, User};
+use crate::notify::{self, UpdateType};
+use crate::quota::{self, Object};
+use crate::time;
 use crate::BaseUrl;
+use crate::Env;
 
 /// A wrapper for raw JSON strings that implements IntoResponse.
 /// Use this to return pre-built JSON without re-parsing/re-serializing.
@@ -30,30 +54,109 @@ impl IntoResponse for RawJson {
     }
 }
 
-/// Helper to fetch a cipher by id for a user or return NotFound.
+/// The requesting user's access to a cipher. Always full for personal ciphers.
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct CipherAccess {
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
+    edit: bool,
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
+    view_password: bool,
+    /// Not access as such, but read along with it to spare a query.
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
+    organization_use_totp: bool,
+}
+
+impl CipherAccess {
+    /// Full access to an item of `org`.
+    fn full(org: &Organization) -> CipherAccess {
+        CipherAccess {
+            edit: true,
+            view_password: true,
+            organization_use_totp: org.use_totp,
+        }
+    }
+
+    fn apply(self, cipher: &mut Cipher) {
+        cipher.edit = self.edit;
+        cipher.view_password = self.view_password;
+        cipher.organization_use_totp = self.organization_use_totp;
+    }
+}
+
+/// Helper to fetch a cipher the user can see, with their access to it, or return NotFound.
 async fn fetch_cipher_for_user(
-    db: &worker::D1Database,
+    db: &Db,
     cipher_id: &str,
     user_id: &str,
-) -> Result<CipherDBModel, AppError> {
-    db.prepare("SELECT * FROM ciphers WHERE id = ?1 AND user_id = ?2")
-        .bind(&[cipher_id.to_string().into(), user_id.to_string().into()])?
-        .first(None)
+) -> Result<(CipherDBModel, CipherAccess), AppError> {
+    let sql = format!(
+        "SELECT {writable} AS edit, {view_password} AS view_password,
+                {use_totp} AS organization_use_totp
+         FROM ciphers c WHERE c.id = ?2 AND {visible}",
+        writable = cipher_writable_sql("?1"),
+        view_password = cipher_view_password_sql("?1"),
+        use_totp = organization_use_totp_sql(),
+        visible = cipher_visible_sql("?1"),
+    );
+    let access: CipherAccess = db
+        .first(
+            &sql,
+            &[user_id.to_string().into(), cipher_id.to_string().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))?;
+
+    let cipher = org_scoped::cipher_by_id(db, user_id, cipher_id)
         .await
-        .map_err(|_| AppError::Database)?
-        .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))?;
+
+    Ok((cipher, access))
 }
 
-#[worker::send]
-pub async fn create_cipher(
-    claims: Claims,
-    State(env): State<Arc<Env>>,
-    Json(payload): Json<CreateCipherRequest>,
-) -> Result<Json<Cipher>, AppError> {
-    let db = db::get_db(&env)?;
-    let now = Utc::now();
-    let now = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-    let cipher_data_req = payload.cipher;
+/// Like `fetch_cipher_for_user`, but rejects ciphers the user can only read.
+async fn fetch_cipher_for_write(
+    db: &Db,
+    cipher_id: &str,
+    user_id: &str,
+) -> Result<(CipherDBModel, CipherAccess), AppError> {
+    let (cipher, access) = fetch_cipher_for_user(db, cipher_id, user_id).await?;
+    if !access.edit {
+        return Err(AppError::Forbidden(
+            "You don't have permission to edit this item".to_string(),
+        ));
+    }
+    Ok((cipher, access))
+}
+
+/// Inserts a new cipher with its collection assignments, which the caller has already checked.
+async fn insert_cipher(
+    env: &Env,
+    db: &Db,
+    claims: &Claims,
+    source: &EventSource,
+    cipher_data_req: CipherRequestData,
+    collection_ids: Vec<String>,
+) -> Result<Cipher, AppError> {
+    // Validate folder ownership if provided
+    if let Some(folder_id) = cipher_data_req.folder_id.value() {
+        let folder = scoped::folder_by_id(db, &claims.sub, folder_id)
+            .await
+            .map_err(db_error!())?;
+
+        if folder.is_none() {
+            return Err(AppError::BadRequest(
+                "Invalid folder: Folder does not exist or belongs to another user".to_string(),
+            ));
+        }
+    }
+    // Items are only shared into organizations the user is a member of
+    if let Some(org_id) = cipher_data_req.organization_id.as_deref() {
+        find_organization_for_member(db, org_id, &claims.sub).await?;
+    }
+
+    let now = time::now_bw();
 
     let cipher_data = CipherData {
         name: cipher_data_req.name,
@@ -61,7 +164,7 @@ pub async fn create_cipher(
         type_fields: cipher_data_req.type_fields,
     };
 
-    let data_value = serde_json::to_value(&cipher_data).map_err(|_| AppError::Internal)?;
+    let data_value = serde_json::to_value(&cipher_data).map_err(internal_error!())?;
 
     let mut cipher = Cipher {
         id: Uuid::new_v4().to_string(),
@@ -69,106 +172,301 @@ pub async fn create_cipher(
         organization_id: cipher_data_req.organization_id.clone(),
         r#type: cipher_data_req.r#type,
         data: data_value,
-        favorite: cipher_data_req.favorite.unwrap_or(false),
-        folder_id: cipher_data_req.folder_id.clone(),
+        favorite: cipher_data_req.favorite.into_option().unwrap_or(false),
+        folder_id: cipher_data_req.folder_id.into_option(),
         deleted_at: None,
         created_at: now.clone(),
         updated_at: now.clone(),
         object: "cipher".to_string(),
-        organization_use_totp: false,
+        organization_use_totp: organization_use_totp(
+            db,
+            cipher_data_req.organization_id.as_deref(),
+        )
+        .await?,
         edit: true,
         view_password: true,
-        collection_ids: if payload.collection_ids.is_empty() {
-            None
-        } else {
-            Some(payload.collection_ids)
-        },
+        collection_ids: Some(collection_ids),
         attachments: None,
     };
 
-    let data = serde_json::to_string(&cipher.data).map_err(|_| AppError::Internal)?;
+    let data = serde_json::to_string(&cipher.data).map_err(internal_error!())?;
+    quota::check(db, env, &claims.sub, &[(Object::Cipher, 1)]).await?;
 
-    query!(
-        &db,
-        "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
+    // The cipher, its owner's count and revision, its collection assignments and its event are
+    // written together
+    let mut batch = Batch::default();
+    batch.push(
+        "ciphers",
+        Database::prepare(
+            db,
+            "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-         cipher.id,
-         cipher.user_id,
-         cipher.organization_id,
-         cipher.r#type,
-         data,
-         cipher.favorite,
-
-         cipher.folder_id,
-         cipher.created_at,
-         cipher.updated_at,
-    ).map_err(|_|AppError::Database)?
-    .run()
-    .await?;
+            &[
+                cipher.id.as_str().into(),
+                cipher.user_id.clone().into(),
+                cipher.organization_id.clone().into(),
+                cipher.r#type.into(),
+                data.into(),
+                cipher.favorite.into(),
+                cipher.folder_id.clone().into(),
+                cipher.created_at.as_str().into(),
+                cipher.updated_at.as_str().into(),
+            ],
+        )
+        .map_err(db_error!())?,
+    );
+    batch.push("users", quota::added(db, &claims.sub, Object::Cipher)?);
+    if let Some(collection_ids) = &cipher.collection_ids {
+        batch.extend(
+            "ciphers_collections",
+            collections::cipher_assignment_statements(db, &cipher.id, collection_ids)?,
+        );
+    }
+    batch.push(
+        "users",
+        db::touch_user_updated_at_statement(db, &claims.sub)?,
+    );
+    push_cipher_event(
+        &mut batch,
+        db,
+        source,
+        EventType::CipherCreated,
+        claims,
+        &cipher,
+    )?;
+    batch.run(db, "The cipher").await?;
+
+    attachments::hydrate_cipher_attachments(db, env, &mut cipher).await?;
+    notify::notify_cipher_update(
+        env,
+        db,
+        UpdateType::SyncCipherCreate,
+        &claims.sub,
+        &cipher.id,
+        &cipher.updated_at,
+        claims.device.as_deref(),
+    )
+    .await;
 
-    attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
-    db::touch_user_updated_at(&db, &claims.sub).await?;
+    Ok(cipher)
+}
+
+/// Loads an organization cipher for the admin routes of the organization vault. Owners and
+/// admins manage every item of their organization, whatever their collection access.
+async fn fetch_cipher_for_admin(
+    db: &Db,
+    cipher_id: &str,
+    user_id: &str,
+) -> Result<(CipherDBModel, CipherAccess), AppError> {
+    let cipher = org_scoped::organization_cipher_by_id(db, user_id, cipher_id)
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))?;
+    let org_id = cipher
+        .organization_id
+        .as_deref()
+        .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))?;
+    let org = require_org_admin(db, org_id, user_id).await?;
+
+    Ok((cipher, CipherAccess::full(&org)))
+}
+
+async fn require_org_admin(db: &Db, org_id: &str, user_id: &str) -> Result<Organization, AppError> {
+    let (org, membership) = find_organization_for_member(db, org_id, user_id).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can manage the organization's items".to_string(),
+        ));
+    }
+    Ok(org)
+}
+
+/// Whether the members of the organization of a cipher see its TOTP codes; false for personal
+/// ciphers.
+async fn organization_use_totp(db: &Db, organization_id: Option<&str>) -> Result<bool, AppError> {
+    let Some(organization_id) = organization_id else {
+        return Ok(false);
+    };
+    let use_totp: Option<i32> = db
+        .first_column(
+            "SELECT use_totp FROM organizations WHERE id = ?1",
+            &[organization_id.into()],
+            "use_totp",
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(use_totp == Some(1))
+}
 
+/// An event about a cipher. Personal ciphers have no audit trail.
+fn cipher_event(
+    atype: EventType,
+    claims: &Claims,
+    organization_id: Option<&str>,
+    cipher_id: &str,
+) -> Option<Event> {
+    Some(Event {
+        organization_id: Some(organization_id?.to_string()),
+        cipher_id: Some(cipher_id.to_string()),
+        ..Event::new(atype, &claims.sub)
+    })
+}
+
+/// Records an event about a cipher.
+async fn log_cipher_event(
+    db: &Db,
+    source: &EventSource,
+    atype: EventType,
+    claims: &Claims,
+    organization_id: Option<&str>,
+    cipher_id: &str,
+) {
+    if let Some(event) = cipher_event(atype, claims, organization_id, cipher_id) {
+        events::log_event(db, source, event).await;
+    }
+}
+
+/// Adds the event about a cipher to the batch writing it.
+fn push_cipher_event(
+    batch: &mut Batch<Statement>,
+    db: &Db,
+    source: &EventSource,
+    atype: EventType,
+    claims: &Claims,
+    cipher: &Cipher,
+) -> Result<(), AppError> {
+    let organization_id = cipher.organization_id.as_deref();
+    if let Some(event) = cipher_event(atype, claims, organization_id, &cipher.id) {
+        batch.push(
+            "events",
+            events::event_statement(db, source, &event).map_err(db_error!())?,
+        );
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/create",
+    tag = "ciphers",
+    request_body = CreateCipherRequest,
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn create_cipher(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppJson(payload): AppJson<CreateCipherRequest>,
+) -> Result<Json<Cipher>, AppError> {
+    let db = db::get_db(&env)?;
+
+    if !payload.collection_ids.is_empty() {
+        let org_id = payload.cipher.organization_id.as_deref().ok_or_else(|| {
+            AppError::BadRequest("Only organization items can be added to collections".to_string())
+        })?;
+        find_organization_for_member(&db, org_id, &claims.sub).await?;
+        collections::check_writable_collections(&db, &claims.sub, org_id, &payload.collection_ids)
+            .await?;
+    }
+
+    let cipher = insert_cipher(
+        &env,
+        &db,
+        &claims,
+        &source,
+        payload.cipher,
+        payload.collection_ids,
+    )
+    .await?;
     Ok(Json(cipher))
 }
 
+#[utoipa::path(
+    method(post, put),
+    path = "/api/ciphers/{id}",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    request_body = CipherRequestData,
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn update_cipher(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
     Extension(BaseUrl(_base_url)): Extension<BaseUrl>,
-    Path(id): Path<String>,
-    Json(payload): Json<CipherRequestData>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<CipherRequestData>,
 ) -> Result<Json<Cipher>, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now();
-    let now = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-
-    let existing_cipher: crate::models::cipher::CipherDBModel = query!(
+    let (existing_cipher, access) = fetch_cipher_for_write(&db, &id, &claims.sub).await?;
+    let cipher = apply_cipher_update(
+        &env,
         &db,
-        "SELECT * FROM ciphers WHERE id = ?1 AND user_id = ?2",
-        id,
-        claims.sub
+        &claims,
+        &source,
+        existing_cipher,
+        access,
+        payload,
     )
-    .map_err(|_| AppError::Database)?
-    .first(None)
-    .await?
-    .ok_or(AppError::NotFound("Cipher not found".to_string()))?;
+    .await?;
+    Ok(Json(cipher))
+}
+
+/// Replaces a cipher's content with the request, once the caller has checked the user may edit
+/// it.
+async fn apply_cipher_update(
+    env: &Env,
+    db: &Db,
+    claims: &Claims,
+    source: &EventSource,
+    existing_cipher: CipherDBModel,
+    access: CipherAccess,
+    payload: CipherRequestData,
+) -> Result<Cipher, AppError> {
+    let now = time::now_bw();
+    let id = existing_cipher.id.clone();
 
     // Validate folder ownership if provided
-    if let Some(ref folder_id) = payload.folder_id {
-        let folder_exists: Option<serde_json::Value> = db
-            .prepare("SELECT id FROM folders WHERE id = ?1 AND user_id = ?2")
-            .bind(&[folder_id.clone().into(), claims.sub.clone().into()])?
-            .first(None)
-            .await?;
+    if let Some(folder_id) = payload.folder_id.value() {
+        let folder = scoped::folder_by_id(db, &claims.sub, folder_id)
+            .await
+            .map_err(db_error!())?;
 
-        if folder_exists.is_none() {
+        if folder.is_none() {
             return Err(AppError::BadRequest(
                 "Invalid folder: Folder does not exist or belongs to another user".to_string(),
             ));
         }
     }
+    if existing_cipher.organization_id.is_none() {
+        if let Some(org_id) = payload.organization_id.as_deref() {
+            find_organization_for_member(db, org_id, &claims.sub).await?;
+        }
+    }
 
     // Reject updates based on stale client data when the last known revision is provided
     if let Some(dt) = payload.last_known_revision_date.as_deref() {
-        match DateTime::parse_from_rfc3339(dt) {
-            Ok(client_dt) => match DateTime::parse_from_rfc3339(&existing_cipher.updated_at) {
-                Ok(server_dt) => {
+        match time::parse_bw(dt) {
+            Some(client_dt) => match time::parse_bw(&existing_cipher.updated_at) {
+                Some(server_dt) => {
                     if server_dt.signed_duration_since(client_dt).num_seconds() > 1 {
                         return Err(AppError::BadRequest(
-                            "The client copy of this cipher is out of date. Resync the client and try again.".to_string(),
+                            "The client copy of this cipher is out of date. Resync the client and try again."
+                                .to_string(),
                         ));
                     }
                 }
-                Err(err) => log::warn!(
-                    "Error parsing server revisionDate '{}' for cipher {}: {}",
+                None => log::warn!(
+                    "Error parsing server revisionDate '{}' for cipher {}",
                     existing_cipher.updated_at,
-                    existing_cipher.id,
-                    err
+                    existing_cipher.id
                 ),
             },
-            Err(err) => log::warn!("Error parsing lastKnownRevisionDate '{}': {}", dt, err),
+            None => log::warn!("Error parsing lastKnownRevisionDate '{}'", dt),
         }
     }
 
@@ -180,256 +478,608 @@ pub async fn update_cipher(
         type_fields: cipher_data_req.type_fields,
     };
 
-    let data_value = serde_json::to_value(&cipher_data).map_err(|_| AppError::Internal)?;
+    let data_value = serde_json::to_value(&cipher_data).map_err(internal_error!())?;
 
     let mut cipher = Cipher {
         id: id.clone(),
-        user_id: Some(claims.sub.clone()),
-        organization_id: cipher_data_req.organization_id.clone(),
+        user_id: Some(existing_cipher.user_id),
+        // Organization ciphers stay in their organization
+        organization_id: existing_cipher
+            .organization_id
+            .or(cipher_data_req.organization_id),
         r#type: cipher_data_req.r#type,
         data: data_value,
-        favorite: cipher_data_req.favorite.unwrap_or(false),
-        folder_id: cipher_data_req.folder_id.clone(),
+        favorite: cipher_data_req
+            .favorite
+            .apply(Some(existing_cipher.favorite != 0))
+            .unwrap_or(false),
+        folder_id: cipher_data_req.folder_id.apply(existing_cipher.folder_id),
         deleted_at: None,
         created_at: existing_cipher.created_at,
         updated_at: now.clone(),
         object: "cipher".to_string(),
-        organization_use_totp: false,
-        edit: true,
-        view_password: true,
+        organization_use_totp: access.organization_use_totp,
+        edit: access.edit,
+        view_password: access.view_password,
         collection_ids: None,
         attachments: None,
     };
 
-    let data = serde_json::to_string(&cipher.data).map_err(|_| AppError::Internal)?;
+    let data = serde_json::to_string(&cipher.data).map_err(internal_error!())?;
+
+    // The cipher, its owner's revision and its event are written together
+    let mut batch = Batch::default();
+    batch.push(
+        "ciphers",
+        Database::prepare(
+            db,
+            "UPDATE ciphers SET organization_id = ?1, type = ?2, data = ?3, favorite = ?4, folder_id = ?5, updated_at = ?6 WHERE id = ?7",
+            &[
+                cipher.organization_id.clone().into(),
+                cipher.r#type.into(),
+                data.into(),
+                cipher.favorite.into(),
+                cipher.folder_id.clone().into(),
+                cipher.updated_at.as_str().into(),
+                id.into(),
+            ],
+        )
+        .map_err(db_error!())?,
+    );
+    batch.push(
+        "users",
+        db::touch_user_updated_at_statement(db, &claims.sub)?,
+    );
+    push_cipher_event(
+        &mut batch,
+        db,
+        source,
+        EventType::CipherUpdated,
+        claims,
+        &cipher,
+    )?;
+    batch.run(db, "The cipher").await?;
+
+    attachments::hydrate_cipher_attachments(db, env, &mut cipher).await?;
+    collections::hydrate_cipher_collections(db, &mut cipher).await?;
+    notify::notify_cipher_update(
+        env,
+        db,
+        UpdateType::SyncCipherUpdate,
+        &claims.sub,
+        &cipher.id,
+        &cipher.updated_at,
+        claims.device.as_deref(),
+    )
+    .await;
+
+    Ok(cipher)
+}
+
+/// Query of GET /api/ciphers. Every filter is optional, and a cipher has to match all of them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CipherListQuery {
+    /// `null` for the ciphers in no folder.
+    pub folder_id: Option<String>,
+    pub collection_id: Option<String>,
+    pub organization_id: Option<String>,
+    #[serde(rename = "type")]
+    pub r#type: Option<i32>,
+    /// `true` lists the trash instead of the ciphers outside of it.
+    #[serde(default)]
+    pub deleted: bool,
+    /// Lists pages of this many ciphers instead of all of them, up to [`CIPHERS_MAX_PAGE_SIZE`].
+    pub page_size: Option<usize>,
+    /// Resumes after the page that returned it.
+    pub continuation_token: Option<String>,
+}
+
+/// Page size of GET /api/ciphers when a continuation token comes without `pageSize`.
+const CIPHERS_DEFAULT_PAGE_SIZE: usize = 100;
+const CIPHERS_MAX_PAGE_SIZE: usize = 1000;
+
+/// Continuation token of a page of ciphers: the revision date and id of its last cipher, which
+/// the next page starts after. It stays valid when that cipher is deleted.
+fn encode_cipher_cursor(updated_at: &str, id: &str) -> String {
+    BASE64URL.encode(format!("{updated_at}|{id}"))
+}
+
+fn decode_cipher_cursor(token: &str) -> Result<(String, String), AppError> {
+    let invalid = || AppError::BadRequest("Invalid continuation token".to_string());
+    let decoded = BASE64URL.decode(token).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (updated_at, id) = decoded.split_once('|').ok_or_else(invalid)?;
+    Ok((updated_at.to_string(), id.to_string()))
+}
+
+/// Revision date and id of a cipher, the order pages of ciphers are listed in.
+#[derive(Debug, Deserialize)]
+struct CipherPosition {
+    updated_at: String,
+    id: String,
+}
 
-    query!(
+impl CipherListQuery {
+    /// `None` when the whole list is asked for, as the official clients do.
+    fn page_size(&self) -> Option<usize> {
+        let page_size = match (self.page_size, &self.continuation_token) {
+            (Some(page_size), _) => page_size,
+            (None, Some(_)) => CIPHERS_DEFAULT_PAGE_SIZE,
+            (None, None) => return None,
+        };
+        Some(page_size.clamp(1, CIPHERS_MAX_PAGE_SIZE))
+    }
+
+    /// The ciphers the query lists, of those the user can see.
+    fn filter(&self) -> Result<CipherFilter, AppError> {
+        if let Some(cipher_type) = self.r#type {
+            if !SUPPORTED_CIPHER_TYPES.contains(&cipher_type) {
+                return Err(AppError::BadRequest(format!(
+                    "Unknown cipher type {cipher_type}"
+                )));
+            }
+        }
+        Ok(CipherFilter {
+            folder_id: self.folder_id.as_deref().map(|folder_id| match folder_id {
+                "null" => None,
+                folder_id => Some(folder_id.to_string()),
+            }),
+            collection_id: self.collection_id.clone(),
+            organization_id: self.organization_id.clone().map(Some),
+            r#type: self.r#type,
+            deleted: Some(self.deleted),
+            after: self
+                .continuation_token
+                .as_deref()
+                .map(decode_cipher_cursor)
+                .transpose()?,
+            ..CipherFilter::default()
+        })
+    }
+}
+
+/// GET /api/ciphers
+///
+/// The user's ciphers outside of the trash, or those matching the [`CipherListQuery`] filters.
+/// Filtering on a folder, collection or organization the user can't see lists nothing.
+///
+/// With `pageSize`, ciphers come in pages, most recently changed first; each page but the last
+/// has a `continuationToken` to pass back for the next one. Pages start after the previous one's
+/// last cipher rather than at an offset, so changes between pages don't shift them.
+#[utoipa::path(
+    get,
+    path = "/api/ciphers",
+    tag = "ciphers",
+    responses((status = 200, description = "Success", content_type = "application/json")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn list_ciphers(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppQuery(query): AppQuery<CipherListQuery>,
+) -> Result<RawJson, AppError> {
+    let db = db::get_db(&env)?;
+    let include_attachments = attachments::attachments_enabled(env.as_ref());
+    let force_row_query = settings.ciphers_default_row_query;
+    let mut filter = query.filter()?;
+
+    let mut continuation_token = None;
+    if let Some(page_size) = query.page_size() {
+        // Find where the page ends first; the ciphers are then read up to there
+        let positions: Vec<CipherPosition> = scoped::user_ciphers(
+            &db,
+            &claims.sub,
+            &CipherFilter {
+                limit: Some(page_size + 1),
+                ..filter.clone()
+            },
+            "c.updated_at, c.id",
+        )
+        .await
+        .map_err(db_error!())?;
+        if positions.len() > page_size {
+            let last = &positions[page_size - 1];
+            filter.through = Some((last.updated_at.clone(), last.id.clone()));
+            continuation_token = Some(encode_cipher_cursor(&last.updated_at, &last.id));
+        }
+    }
+
+    let mut response = String::new();
+    open_raw_list(&mut response);
+    append_cipher_json_array_raw(
+        &mut response,
         &db,
-        "UPDATE ciphers SET organization_id = ?1, type = ?2, data = ?3, favorite = ?4, folder_id = ?5, updated_at = ?6 WHERE id = ?7 AND user_id = ?8",
-        cipher.organization_id,
-        cipher.r#type,
-        data,
-        cipher.favorite,
-        cipher.folder_id,
-        cipher.updated_at,
-        id,
-        claims.sub,
-    ).map_err(|_|AppError::Database)?
-    .run()
+        CipherJsonFormat::details(include_attachments),
+        &claims.sub,
+        &filter,
+        force_row_query,
+    )
     .await?;
+    close_raw_list(&mut response, continuation_token.as_deref());
 
-    attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
-    db::touch_user_updated_at(&db, &claims.sub).await?;
+    Ok(RawJson(response))
+}
 
-    Ok(Json(cipher))
+#[derive(Debug, Deserialize)]
+pub struct OrganizationDetailsQuery {
+    #[serde(rename = "organizationId")]
+    pub organization_id: Option<String>,
 }
 
-/// GET /api/ciphers - list all non-trashed ciphers for current user
+/// GET /api/ciphers/organization-details?organizationId={id}
+///
+/// Every item of the organization vault, trashed ones included: all of them for owners and
+/// admins, those in the member's collections for everyone else.
+#[utoipa::path(
+    get,
+    path = "/api/ciphers/organization-details",
+    tag = "ciphers",
+    responses((status = 200, description = "Success", content_type = "application/json")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
-pub async fn list_ciphers(
+pub async fn list_organization_ciphers(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppQuery(query): AppQuery<OrganizationDetailsQuery>,
 ) -> Result<RawJson, AppError> {
     let db = db::get_db(&env)?;
+    let org_id = query
+        .organization_id
+        .ok_or_else(|| AppError::NotFound("Organization not found".to_string()))?;
+    let (org, _membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+
     let include_attachments = attachments::attachments_enabled(env.as_ref());
-    let force_row_query = super::ciphers_default_row_query(env.as_ref());
-    // Response schema: {"data":[...],"object":"list","continuationToken":null}
+    let force_row_query = settings.ciphers_default_row_query;
     let mut response = String::new();
-    response.push_str("{\"data\":");
+    open_raw_list(&mut response);
     append_cipher_json_array_raw(
         &mut response,
         &db,
-        include_attachments,
-        "WHERE c.user_id = ?1 AND c.deleted_at IS NULL",
-        &[claims.sub.clone().into()],
-        "ORDER BY c.updated_at DESC",
+        CipherJsonFormat::mini_details(include_attachments),
+        &claims.sub,
+        &CipherFilter {
+            organization_id: Some(Some(org.id)),
+            ..CipherFilter::default()
+        },
         force_row_query,
     )
     .await?;
-    response.push_str(",\"object\":\"list\",\"continuationToken\":null}");
+    close_raw_list(&mut response, None);
 
     Ok(RawJson(response))
 }
 
 /// GET /api/ciphers/{id}
+#[utoipa::path(
+    get,
+    path = "/api/ciphers/{id}",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_cipher(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path(id): Path<String>,
+    AppPath(id): AppPath<String>,
 ) -> Result<Json<Cipher>, AppError> {
     let db = db::get_db(&env)?;
-    let cipher = fetch_cipher_for_user(&db, &id, &claims.sub).await?;
+    let (cipher, access) = fetch_cipher_for_user(&db, &id, &claims.sub).await?;
     let mut cipher: Cipher = cipher.into();
+    access.apply(&mut cipher);
 
     attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
+    collections::hydrate_cipher_collections(&db, &mut cipher).await?;
 
     Ok(Json(cipher))
 }
 
 /// GET /api/ciphers/{id}/details
+#[utoipa::path(
+    get,
+    path = "/api/ciphers/{id}/details",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_cipher_details(
     claims: Claims,
     state: State<Arc<Env>>,
-    id: Path<String>,
+    id: AppPath<String>,
 ) -> Result<Json<Cipher>, AppError> {
     get_cipher(claims, state, id).await
 }
 
 /// PUT/POST /api/ciphers/{id}/partial
+#[utoipa::path(
+    method(put, post),
+    path = "/api/ciphers/{id}/partial",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    request_body = PartialCipherData,
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn update_cipher_partial(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path(id): Path<String>,
-    Json(payload): Json<PartialCipherData>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<PartialCipherData>,
 ) -> Result<Json<Cipher>, AppError> {
     let db = db::get_db(&env)?;
     let user_id = &claims.sub;
 
     // Validate folder ownership if provided
-    if let Some(ref folder_id) = payload.folder_id {
-        let folder_exists: Option<serde_json::Value> = db
-            .prepare("SELECT id FROM folders WHERE id = ?1 AND user_id = ?2")
-            .bind(&[folder_id.clone().into(), user_id.clone().into()])?
-            .first(None)
-            .await?;
+    if let Some(folder_id) = payload.folder_id.value() {
+        let folder = scoped::folder_by_id(&db, user_id, folder_id)
+            .await
+            .map_err(db_error!())?;
 
-        if folder_exists.is_none() {
+        if folder.is_none() {
             return Err(AppError::BadRequest(
                 "Invalid folder: Folder does not exist or belongs to another user".to_string(),
             ));
         }
     }
 
-    // Ensure cipher exists and belongs to user
-    fetch_cipher_for_user(&db, &id, user_id).await?;
+    // Ensure the cipher exists and the user can edit it
+    let (existing, _) = fetch_cipher_for_write(&db, &id, user_id).await?;
+
+    let now = time::now_bw();
+    let folder_id = payload.folder_id.apply(existing.folder_id);
+    let favorite = payload
+        .favorite
+        .apply(Some(existing.favorite != 0))
+        .unwrap_or(false);
 
-    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut batch = Batch::default();
+    batch.push(
+        "ciphers",
+        Database::prepare(
+            &db,
+            "UPDATE ciphers SET folder_id = ?1, favorite = ?2, updated_at = ?3 WHERE id = ?4",
+            &[
+                folder_id.into(),
+                favorite.into(),
+                now.as_str().into(),
+                id.as_str().into(),
+            ],
+        )
+        .map_err(db_error!())?,
+    );
+    batch.push("users", db::touch_user_updated_at_statement(&db, user_id)?);
+    batch.run(&db, "The cipher").await?;
 
-    query!(
+    notify::notify_cipher_update(
+        &env,
         &db,
-        "UPDATE ciphers SET folder_id = ?1, favorite = ?2, updated_at = ?3 WHERE id = ?4 AND user_id = ?5",
-        payload.folder_id,
-        payload.favorite,
-        now,
-        id,
-        user_id,
+        UpdateType::SyncCipherUpdate,
+        &claims.sub,
+        &id,
+        &now,
+        claims.device.as_deref(),
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await?;
+    .await;
 
-    db::touch_user_updated_at(&db, user_id).await?;
-
-    let cipher = fetch_cipher_for_user(&db, &id, user_id).await?;
+    let (cipher, access) = fetch_cipher_for_user(&db, &id, user_id).await?;
     let mut cipher: Cipher = cipher.into();
+    access.apply(&mut cipher);
 
     attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
+    collections::hydrate_cipher_collections(&db, &mut cipher).await?;
 
     Ok(Json(cipher))
 }
 
 /// Soft delete a single cipher (PUT /api/ciphers/{id}/delete)
 /// Sets deleted_at to current timestamp
+#[utoipa::path(
+    put,
+    path = "/api/ciphers/{id}/delete",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn soft_delete_cipher(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
-    Path(id): Path<String>,
+    AppPath(id): AppPath<String>,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let (cipher, _) = fetch_cipher_for_write(&db, &id, &claims.sub).await?;
+    soft_delete_cipher_by_id(&env, &db, &claims, &source, &cipher).await?;
+    Ok(Json(()))
+}
 
-    query!(
-        &db,
-        "UPDATE ciphers SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2 AND user_id = ?3",
-        now,
+/// Moves a cipher to the trash, once the caller has checked the user may edit it.
+async fn soft_delete_cipher_by_id(
+    env: &Env,
+    db: &Db,
+    claims: &Claims,
+    source: &EventSource,
+    cipher: &CipherDBModel,
+) -> Result<(), AppError> {
+    let id = cipher.id.as_str();
+    let now = time::now_bw();
+
+    org_scoped::set_cipher_deleted_at(db, &claims.sub, id, Some(&now), &now)
+        .await
+        .map_err(db_error!())?;
+
+    db::touch_user_updated_at(db, &claims.sub).await?;
+    notify::notify_cipher_update(
+        env,
+        db,
+        UpdateType::SyncCipherUpdate,
+        &claims.sub,
         id,
-        claims.sub
+        &now,
+        claims.device.as_deref(),
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await?;
-
-    db::touch_user_updated_at(&db, &claims.sub).await?;
+    .await;
+    log_cipher_event(
+        db,
+        source,
+        EventType::CipherSoftDeleted,
+        claims,
+        cipher.organization_id.as_deref(),
+        id,
+    )
+    .await;
 
-    Ok(Json(()))
+    Ok(())
 }
 
 /// Soft delete multiple ciphers (PUT /api/ciphers/delete)
 /// Accepts raw JSON body and uses json_each with path to extract ids directly.
 /// Expected JSON: {"ids": ["cipher_id1", "cipher_id2", ...]}
+#[utoipa::path(
+    put,
+    path = "/api/ciphers/delete",
+    tag = "ciphers",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn soft_delete_ciphers_bulk(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
     body: String,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-
-    query!(
-        &db,
-        "UPDATE ciphers SET deleted_at = ?1, updated_at = ?1 WHERE user_id = ?2 AND id IN (SELECT value FROM json_each(?3, '$.ids'))",
-        now,
-        claims.sub,
-        body
+    let now = time::now_bw();
+
+    // Ciphers the user can't edit are skipped
+    db.run(
+        &format!(
+            "UPDATE ciphers AS c SET deleted_at = ?1, updated_at = ?1
+         WHERE c.id IN (SELECT value FROM json_each(?3, '$.ids')) AND {}",
+            cipher_writable_sql("?2")
+        ),
+        &[
+            now.clone().into(),
+            claims.sub.clone().into(),
+            body.clone().into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
     .map_err(db::map_d1_json_error)?;
+    events::log_cipher_events(
+        &db,
+        &source,
+        EventType::CipherSoftDeleted,
+        &claims.sub,
+        &body,
+        "$.ids",
+    )
+    .await;
 
     db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_vault_sync(&env, &db, &claims.sub, claims.device.as_deref()).await;
 
     Ok(Json(()))
 }
 
 /// Hard delete a single cipher (DELETE /api/ciphers/{id} or POST /api/ciphers/{id}/delete)
 /// Permanently removes the cipher from database
+#[utoipa::path(
+    delete,
+    path = "/api/ciphers/{id}",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn hard_delete_cipher(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
-    Path(id): Path<String>,
+    AppPath(id): AppPath<String>,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
+    let (cipher, _) = fetch_cipher_for_write(&db, &id, &claims.sub).await?;
+    hard_delete_cipher_by_id(&env, &db, &claims, &source, &cipher).await?;
+    Ok(Json(()))
+}
 
-    if attachments::attachments_enabled(env.as_ref()) {
-        let id_json = serde_json::to_string(&[&id]).map_err(|_| AppError::Internal)?;
-        let keys = attachments::list_attachment_keys_for_cipher_ids_json(
-            &db,
-            &id_json,
-            "$",
-            Some(&claims.sub),
-        )
-        .await?;
-        attachments::delete_storage_objects(env.as_ref(), &keys).await?;
+/// Permanently deletes a cipher and its attachments, once the caller has checked the user may
+/// edit it.
+async fn hard_delete_cipher_by_id(
+    env: &Env,
+    db: &Db,
+    claims: &Claims,
+    source: &EventSource,
+    cipher: &CipherDBModel,
+) -> Result<(), AppError> {
+    let id = cipher.id.as_str();
+    if attachments::attachments_enabled(env) {
+        let id_json = serde_json::to_string(&[id]).map_err(internal_error!())?;
+        let keys =
+            attachments::list_attachment_keys_for_cipher_ids_json(db, &id_json, "$", None).await?;
+        attachments::delete_storage_objects(env, &keys).await?;
     }
 
-    query!(
-        &db,
-        "DELETE FROM ciphers WHERE id = ?1 AND user_id = ?2",
-        id,
-        claims.sub
+    Database::batch(
+        db,
+        vec![
+            org_scoped::delete_cipher_statement(db, &claims.sub, id).map_err(db_error!())?,
+            quota::removed(db, &cipher.user_id, Object::Cipher)?,
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await?;
 
-    db::touch_user_updated_at(&db, &claims.sub).await?;
+    db::touch_user_updated_at(db, &claims.sub).await?;
+    notify::notify_cipher_update(
+        env,
+        db,
+        UpdateType::SyncCipherDelete,
+        &claims.sub,
+        id,
+        &time::now_bw(),
+        claims.device.as_deref(),
+    )
+    .await;
+    log_cipher_event(
+        db,
+        source,
+        EventType::CipherDeleted,
+        claims,
+        cipher.organization_id.as_deref(),
+        id,
+    )
+    .await;
 
-    Ok(Json(()))
+    Ok(())
 }
 
 /// Hard delete multiple ciphers (DELETE /api/ciphers or POST /api/ciphers/delete)
 /// Accepts raw JSON body and uses json_each with path to extract ids directly.
 /// Expected JSON: {"ids": ["cipher_id1", "cipher_id2", ...]}
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/delete",
+    tag = "ciphers",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn hard_delete_ciphers_bulk(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
     body: String,
 ) -> Result<Json<()>, AppError> {
@@ -446,110 +1096,181 @@ pub async fn hard_delete_ciphers_bulk(
         attachments::delete_storage_objects(env.as_ref(), &keys).await?;
     }
 
-    query!(
+    // Recorded first, while the ciphers still exist
+    events::log_cipher_events(
+        &db,
+        &source,
+        EventType::CipherDeleted,
+        &claims.sub,
+        &body,
+        "$.ids",
+    )
+    .await;
+
+    // Ciphers the user can't edit are skipped
+    let condition = format!(
+        "c.id IN (SELECT value FROM json_each(?2, '$.ids')) AND {}",
+        cipher_writable_sql("?1")
+    );
+    let params: [Param; 2] = [claims.sub.clone().into(), body.into()];
+    Database::batch(
         &db,
-        "DELETE FROM ciphers WHERE user_id = ?1 AND id IN (SELECT value FROM json_each(?2, '$.ids'))",
-        claims.sub,
-        body
+        vec![
+            quota::removing_ciphers(&db, &condition, &params)?,
+            Database::prepare(
+                &db,
+                &format!("DELETE FROM ciphers AS c WHERE {condition}"),
+                &params,
+            )?,
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
     .map_err(db::map_d1_json_error)?;
 
     db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_vault_sync(&env, &db, &claims.sub, claims.device.as_deref()).await;
 
     Ok(Json(()))
 }
 
 /// Restore a single cipher (PUT /api/ciphers/{id}/restore)
 /// Clears the deleted_at timestamp
+#[utoipa::path(
+    put,
+    path = "/api/ciphers/{id}/restore",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn restore_cipher(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
-    Path(id): Path<String>,
+    AppPath(id): AppPath<String>,
 ) -> Result<Json<Cipher>, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let (_, access) = fetch_cipher_for_write(&db, &id, &claims.sub).await?;
+    let cipher = restore_cipher_by_id(&env, &db, &claims, &source, &id, access).await?;
+    Ok(Json(cipher))
+}
+
+/// Takes a cipher out of the trash, once the caller has checked the user may edit it.
+async fn restore_cipher_by_id(
+    env: &Env,
+    db: &Db,
+    claims: &Claims,
+    source: &EventSource,
+    id: &str,
+    access: CipherAccess,
+) -> Result<Cipher, AppError> {
+    let now = time::now_bw();
 
     // Update the cipher to clear deleted_at
-    query!(
-        &db,
-        "UPDATE ciphers SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2 AND user_id = ?3",
-        now,
-        id,
-        claims.sub
-    )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await?;
+    org_scoped::set_cipher_deleted_at(db, &claims.sub, id, None, &now)
+        .await
+        .map_err(db_error!())?;
 
     // Fetch and return the restored cipher
-    let cipher_db: crate::models::cipher::CipherDBModel = query!(
-        &db,
-        "SELECT * FROM ciphers WHERE id = ?1 AND user_id = ?2",
-        id,
-        claims.sub
-    )
-    .map_err(|_| AppError::Database)?
-    .first(None)
-    .await?
-    .ok_or(AppError::NotFound("Cipher not found".to_string()))?;
+    let cipher_db = org_scoped::cipher_by_id(db, &claims.sub, id)
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))?;
 
     let mut cipher: Cipher = cipher_db.into();
-    attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
-
-    db::touch_user_updated_at(&db, &claims.sub).await?;
+    access.apply(&mut cipher);
+    attachments::hydrate_cipher_attachments(db, env, &mut cipher).await?;
+    collections::hydrate_cipher_collections(db, &mut cipher).await?;
+
+    db::touch_user_updated_at(db, &claims.sub).await?;
+    notify::notify_cipher_update(
+        env,
+        db,
+        UpdateType::SyncCipherUpdate,
+        &claims.sub,
+        &cipher.id,
+        &cipher.updated_at,
+        claims.device.as_deref(),
+    )
+    .await;
+    log_cipher_event(
+        db,
+        source,
+        EventType::CipherRestored,
+        claims,
+        cipher.organization_id.as_deref(),
+        &cipher.id,
+    )
+    .await;
 
-    Ok(Json(cipher))
+    Ok(cipher)
 }
 
 /// Restore multiple ciphers (PUT /api/ciphers/restore)
 /// Accepts raw JSON body and uses json_each with path to extract ids directly.
 /// Expected JSON: {"ids": ["cipher_id1", "cipher_id2", ...]}
+#[utoipa::path(
+    put,
+    path = "/api/ciphers/restore",
+    tag = "ciphers",
+    responses((status = 200, description = "Success", content_type = "application/json")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn restore_ciphers_bulk(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
     body: String,
 ) -> Result<RawJson, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-
-    // Single bulk UPDATE using json_each() with path
-    query!(
-        &db,
-        "UPDATE ciphers SET deleted_at = NULL, updated_at = ?1 WHERE user_id = ?2 AND id IN (SELECT value FROM json_each(?3, '$.ids'))",
-        now,
-        claims.sub,
-        body
+    let now = time::now_bw();
+
+    // Single bulk UPDATE using json_each() with path; ciphers the user can't edit are skipped
+    db.run(
+        &format!(
+            "UPDATE ciphers AS c SET deleted_at = NULL, updated_at = ?1
+         WHERE c.id IN (SELECT value FROM json_each(?3, '$.ids')) AND {}",
+            cipher_writable_sql("?2")
+        ),
+        &[now.into(), claims.sub.clone().into(), body.clone().into()],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
     .map_err(db::map_d1_json_error)?;
+    events::log_cipher_events(
+        &db,
+        &source,
+        EventType::CipherRestored,
+        &claims.sub,
+        &body,
+        "$.ids",
+    )
+    .await;
 
     let include_attachments = attachments::attachments_enabled(env.as_ref());
-    let force_row_query = super::ciphers_default_row_query(env.as_ref());
+    let force_row_query = settings.ciphers_default_row_query;
 
     db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_vault_sync(&env, &db, &claims.sub, claims.device.as_deref()).await;
 
     // Build response JSON via string concatenation (no parsing!)
-    // Response schema: {"data":[...],"object":"list","continuationToken":null}
     let mut response = String::new();
-    response.push_str("{\"data\":");
+    open_raw_list(&mut response);
     append_cipher_json_array_raw(
         &mut response,
         &db,
-        include_attachments,
-        "WHERE c.user_id = ?1 AND c.id IN (SELECT value FROM json_each(?2, '$.ids'))",
-        &[claims.sub.clone().into(), body.clone().into()],
-        "",
+        CipherJsonFormat::details(include_attachments),
+        &claims.sub,
+        &CipherFilter {
+            ids_json: Some(body),
+            ..CipherFilter::default()
+        },
         force_row_query,
     )
     .await?;
-    response.push_str(",\"object\":\"list\",\"continuationToken\":null}");
+    close_raw_list(&mut response, None);
 
     Ok(RawJson(response))
 }
@@ -557,65 +1278,23 @@ pub async fn restore_ciphers_bulk(
 /// Handler for POST /api/ciphers
 /// Accepts flat JSON structure (camelCase) as sent by Bitwarden clients
 /// when creating a cipher without collection assignments.
+#[utoipa::path(
+    post,
+    path = "/api/ciphers",
+    tag = "ciphers",
+    request_body = CipherRequestData,
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn create_cipher_simple(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<CipherRequestData>,
+    AppJson(payload): AppJson<CipherRequestData>,
 ) -> Result<Json<Cipher>, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now();
-    let now = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-
-    let cipher_data = CipherData {
-        name: payload.name,
-        notes: payload.notes,
-        type_fields: payload.type_fields,
-    };
-
-    let data_value = serde_json::to_value(&cipher_data).map_err(|_| AppError::Internal)?;
-
-    let mut cipher = Cipher {
-        id: Uuid::new_v4().to_string(),
-        user_id: Some(claims.sub.clone()),
-        organization_id: payload.organization_id.clone(),
-        r#type: payload.r#type,
-        data: data_value,
-        favorite: payload.favorite.unwrap_or(false),
-        folder_id: payload.folder_id.clone(),
-        deleted_at: None,
-        created_at: now.clone(),
-        updated_at: now.clone(),
-        object: "cipher".to_string(),
-        organization_use_totp: false,
-        edit: true,
-        view_password: true,
-        collection_ids: None,
-        attachments: None,
-    };
-
-    let data = serde_json::to_string(&cipher.data).map_err(|_| AppError::Internal)?;
-
-    query!(
-        &db,
-        "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-         cipher.id,
-         cipher.user_id,
-         cipher.organization_id,
-         cipher.r#type,
-         data,
-         cipher.favorite,
-         cipher.folder_id,
-         cipher.created_at,
-         cipher.updated_at,
-    ).map_err(|_| AppError::Database)?
-    .run()
-    .await?;
-
-    attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
-    db::touch_user_updated_at(&db, &claims.sub).await?;
-
+    let cipher = insert_cipher(&env, &db, &claims, &source, payload, Vec::new()).await?;
     Ok(Json(cipher))
 }
 
@@ -624,6 +1303,13 @@ pub async fn create_cipher_simple(
 /// Expected JSON: {"folderId": "optional-folder-id-or-null", "ids": ["cipher_id1", ...]}
 /// The folderId is optional and treated as null if not provided in vaultwarden.
 /// D1/SQLite's json_extract returns SQL NULL for non-existent paths, which is identical to the behavior in vaultwarden.
+#[utoipa::path(
+    method(post, put),
+    path = "/api/ciphers/move",
+    tag = "ciphers",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn move_cipher_selected(
     claims: Claims,
@@ -632,19 +1318,18 @@ pub async fn move_cipher_selected(
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
     let user_id = &claims.sub;
-    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let now = time::now_bw();
 
     // Validate folder exists and belongs to user (if folder_id is provided)
     // Uses json_extract to get folderId from request body
     let folder_invalid: Option<Value> = db
-        .prepare(
+        .first(
             "SELECT 1 WHERE json_extract(?1, '$.folderId') IS NOT NULL 
              AND NOT EXISTS (
                  SELECT 1 FROM folders WHERE id = json_extract(?1, '$.folderId') AND user_id = ?2
              )",
+            &[body.clone().into(), user_id.clone().into()],
         )
-        .bind(&[body.clone().into(), user_id.clone().into()])?
-        .first(None)
         .await
         .map_err(db::map_d1_json_error)?;
 
@@ -656,45 +1341,221 @@ pub async fn move_cipher_selected(
 
     // Update folder_id for all ciphers that belong to the user and are in the ids list
     // Uses json_extract for folderId and json_each for ids array
-    db.prepare(
-        "UPDATE ciphers SET folder_id = json_extract(?1, '$.folderId'), updated_at = ?2 
-         WHERE user_id = ?3 AND id IN (SELECT value FROM json_each(?1, '$.ids'))",
+    db.run(
+        &format!(
+            "UPDATE ciphers AS c SET folder_id = json_extract(?1, '$.folderId'), updated_at = ?2
+         WHERE c.id IN (SELECT value FROM json_each(?1, '$.ids')) AND {}",
+            cipher_writable_sql("?3")
+        ),
+        &[body.into(), now.into(), user_id.clone().into()],
     )
-    .bind(&[body.into(), now.into(), user_id.clone().into()])?
-    .run()
     .await
     .map_err(db::map_d1_json_error)?;
 
     // Update user's revision date
     db::touch_user_updated_at(&db, user_id).await?;
+    notify::notify_vault_sync(&env, &db, user_id, claims.device.as_deref()).await;
 
     Ok(Json(()))
 }
 
 /// Purge the user's vault - delete all ciphers and folders
+/// POST /api/ciphers/admin
+///
+/// Creates an item from the organization vault view.
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/admin",
+    tag = "ciphers",
+    request_body = CreateCipherRequest,
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn create_cipher_admin(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppJson(payload): AppJson<CreateCipherRequest>,
+) -> Result<Json<Cipher>, AppError> {
+    let db = db::get_db(&env)?;
+    let org_id = payload.cipher.organization_id.as_deref().ok_or_else(|| {
+        AppError::BadRequest("Organization items need an organization".to_string())
+    })?;
+    require_org_admin(&db, org_id, &claims.sub).await?;
+    collections::check_writable_collections(&db, &claims.sub, org_id, &payload.collection_ids)
+        .await?;
+
+    let cipher = insert_cipher(
+        &env,
+        &db,
+        &claims,
+        &source,
+        payload.cipher,
+        payload.collection_ids,
+    )
+    .await?;
+    Ok(Json(cipher))
+}
+
+/// GET /api/ciphers/{id}/admin
+#[utoipa::path(
+    get,
+    path = "/api/ciphers/{id}/admin",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_cipher_admin(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Cipher>, AppError> {
+    let db = db::get_db(&env)?;
+    let (cipher, access) = fetch_cipher_for_admin(&db, &id, &claims.sub).await?;
+    let mut cipher: Cipher = cipher.into();
+    access.apply(&mut cipher);
+
+    attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
+    collections::hydrate_cipher_collections(&db, &mut cipher).await?;
+
+    Ok(Json(cipher))
+}
+
+/// PUT/POST /api/ciphers/{id}/admin
+#[utoipa::path(
+    method(put, post),
+    path = "/api/ciphers/{id}/admin",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    request_body = CipherRequestData,
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn update_cipher_admin(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<CipherRequestData>,
+) -> Result<Json<Cipher>, AppError> {
+    let db = db::get_db(&env)?;
+    let (existing_cipher, access) = fetch_cipher_for_admin(&db, &id, &claims.sub).await?;
+    let cipher = apply_cipher_update(
+        &env,
+        &db,
+        &claims,
+        &source,
+        existing_cipher,
+        access,
+        payload,
+    )
+    .await?;
+    Ok(Json(cipher))
+}
+
+/// PUT /api/ciphers/{id}/delete-admin
+#[utoipa::path(
+    put,
+    path = "/api/ciphers/{id}/delete-admin",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn soft_delete_cipher_admin(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let (cipher, _) = fetch_cipher_for_admin(&db, &id, &claims.sub).await?;
+    soft_delete_cipher_by_id(&env, &db, &claims, &source, &cipher).await?;
+    Ok(Json(()))
+}
+
+/// DELETE /api/ciphers/{id}/admin or POST /api/ciphers/{id}/delete-admin
+#[utoipa::path(
+    delete,
+    path = "/api/ciphers/{id}/admin",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn hard_delete_cipher_admin(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let (cipher, _) = fetch_cipher_for_admin(&db, &id, &claims.sub).await?;
+    hard_delete_cipher_by_id(&env, &db, &claims, &source, &cipher).await?;
+    Ok(Json(()))
+}
+
+/// PUT /api/ciphers/{id}/restore-admin
+#[utoipa::path(
+    put,
+    path = "/api/ciphers/{id}/restore-admin",
+    tag = "ciphers",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = Cipher)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn restore_cipher_admin(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Cipher>, AppError> {
+    let db = db::get_db(&env)?;
+    let (_, access) = fetch_cipher_for_admin(&db, &id, &claims.sub).await?;
+    let cipher = restore_cipher_by_id(&env, &db, &claims, &source, &id, access).await?;
+    Ok(Json(cipher))
+}
+
 /// POST /api/ciphers/purge
 ///
 /// This is a destructive operation that requires password verification.
 /// In vaultwarden, this endpoint also supports purging organization vaults,
 /// but this simplified version only supports personal vault purge.
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/purge",
+    tag = "ciphers",
+    request_body = PasswordOrOtpData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn purge_vault(
     claims: Claims,
+    source: EventSource,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<PasswordOrOtpData>,
+    AppJson(payload): AppJson<PasswordOrOtpData>,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
     let user_id = &claims.sub;
 
     // Get the user from the database
     let user: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user).map_err(internal_error!())?;
 
     // Validate password (OTP not supported in this simplified version)
     let provided_hash = payload
@@ -712,32 +1573,66 @@ pub async fn purge_vault(
         attachments::delete_storage_objects(env.as_ref(), &keys).await?;
     }
 
-    // Delete all user's ciphers (both active and soft-deleted)
-    query!(&db, "DELETE FROM ciphers WHERE user_id = ?1", user_id)
-        .map_err(|_| AppError::Database)?
-        .run()
-        .await?;
-
-    // Delete all user's folders
-    query!(&db, "DELETE FROM folders WHERE user_id = ?1", user_id)
-        .map_err(|_| AppError::Database)?
-        .run()
-        .await?;
+    // Delete all user's ciphers (both active and soft-deleted) and folders
+    Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "DELETE FROM ciphers WHERE user_id = ?1",
+                &[user_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            quota::removed(&db, user_id, Object::Cipher)?,
+            Database::prepare(
+                &db,
+                "DELETE FROM folders WHERE user_id = ?1",
+                &[user_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            quota::removed(&db, user_id, Object::Folder)?,
+        ],
+    )
+    .await?;
+    events::log_account_event(&db, &source, EventType::UserPurgedVault, user_id).await;
 
     // Update user's revision date to trigger client sync
     db::touch_user_updated_at(&db, user_id).await?;
+    notify::notify_vault_sync(&env, &db, user_id, claims.device.as_deref()).await;
 
     Ok(Json(()))
 }
 
-#[derive(Deserialize)]
-struct CipherJsonArrayRow {
-    ciphers_json: String,
+/// Build the SQL expression for a single cipher as JSON.
+/// `?1` must be bound to the requesting user's id, which `edit` and `viewPassword` depend on.
+/// Shape of the cipher objects built by the raw JSON helpers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CipherJsonFormat {
+    pub attachments_enabled: bool,
+    /// `object` of each cipher.
+    pub object: &'static str,
 }
 
-/// Build the SQL expression for a single cipher as JSON.
-fn cipher_json_expr(attachments_enabled: bool) -> String {
-    let attachments_expr = if attachments_enabled {
+impl CipherJsonFormat {
+    /// `cipherDetails`, as in the user's own vault and `/api/sync`.
+    pub(crate) fn details(attachments_enabled: bool) -> Self {
+        Self {
+            attachments_enabled,
+            object: "cipherDetails",
+        }
+    }
+
+    /// `cipherMiniDetails`, as in the organization vault.
+    pub(crate) fn mini_details(attachments_enabled: bool) -> Self {
+        Self {
+            attachments_enabled,
+            object: "cipherMiniDetails",
+        }
+    }
+}
+
+fn cipher_json_expr(format: CipherJsonFormat) -> String {
+    let attachments_expr = if format.attachments_enabled {
         "
             (
                 SELECT CASE WHEN COUNT(1)=0 THEN NULL ELSE json_group_array(
@@ -768,18 +1663,22 @@ fn cipher_json_expr(attachments_enabled: bool) -> String {
 
     format!(
         "json_object(
-            'object', 'cipherDetails',
+            'object', '{object}',
             'id', c.id,
             'userId', c.user_id,
             'organizationId', c.organization_id,
             'folderId', c.folder_id,
             'type', c.type,
             'favorite', CASE WHEN c.favorite THEN json('true') ELSE json('false') END,
-            'edit', json('true'),
-            'viewPassword', json('true'),
-            'permissions', json_object('delete', json('true'), 'restore', json('true')),
-            'organizationUseTotp', json('false'),
-            'collectionIds', NULL,
+            'edit', {edit},
+            'viewPassword', {view_password},
+            'permissions', json_object('delete', {edit}, 'restore', {edit}),
+            'organizationUseTotp', {use_totp},
+            'collectionIds', json(COALESCE((
+                SELECT json_group_array(cc.collection_id)
+                FROM ciphers_collections cc
+                WHERE cc.cipher_id = c.id
+            ), '[]')),
             'revisionDate', c.updated_at,
             'creationDate', c.created_at,
             'deletedDate', c.deleted_at,
@@ -795,47 +1694,16 @@ fn cipher_json_expr(attachments_enabled: bool) -> String {
             'identity', CASE WHEN c.type = 4 THEN json_extract(c.data, '$.identity') ELSE NULL END,
             'sshKey', CASE WHEN c.type = 5 THEN json_extract(c.data, '$.sshKey') ELSE NULL END
         )",
+        object = format.object,
         attachments_expr = attachments_expr,
+        edit = sql_bool(&cipher_writable_sql("?1")),
+        view_password = sql_bool(&cipher_view_password_sql("?1")),
+        use_totp = sql_bool(organization_use_totp_sql()),
     )
 }
 
-/// Build SQL that returns ciphers as a JSON array string (using json_group_array).
-fn cipher_json_array_sql(
-    attachments_enabled: bool,
-    where_clause: &str,
-    order_clause: &str,
-) -> String {
-    let cipher_expr = cipher_json_expr(attachments_enabled);
-    // Use a subquery to ensure ORDER BY is applied before json_group_array
-    format!(
-        "SELECT COALESCE(json_group_array(json(sub.cipher_json)), '[]') AS ciphers_json
-        FROM (
-            SELECT {cipher_expr} AS cipher_json
-            FROM ciphers c
-            {where_clause}
-            {order_clause}
-        ) sub",
-        cipher_expr = cipher_expr,
-        where_clause = where_clause,
-        order_clause = order_clause,
-    )
-}
-
-fn cipher_json_rows_sql(
-    attachments_enabled: bool,
-    where_clause: &str,
-    order_clause: &str,
-) -> String {
-    let cipher_expr = cipher_json_expr(attachments_enabled);
-    format!(
-        "SELECT {cipher_expr} AS cipher_json
-        FROM ciphers c
-        {where_clause}
-        {order_clause}",
-        cipher_expr = cipher_expr,
-        where_clause = where_clause,
-        order_clause = order_clause,
-    )
+fn sql_bool(condition: &str) -> String {
+    format!("CASE WHEN {condition} THEN json('true') ELSE json('false') END")
 }
 
 fn is_sqlite_toobig(err: &worker::Error) -> bool {
@@ -843,54 +1711,38 @@ fn is_sqlite_toobig(err: &worker::Error) -> bool {
     msg.contains("sqlite_toobig") || msg.contains("string or blob too big")
 }
 
-/// Append ciphers JSON array to an existing buffer.
+/// Append ciphers JSON array to an existing buffer: the ciphers of `user_id` matching `filter`,
+/// read through [`scoped::user_cipher_texts`].
 /// This avoids JSON parsing in Rust, significantly reducing CPU time.
 pub(crate) async fn append_cipher_json_array_raw(
     out: &mut String,
-    db: &worker::D1Database,
-    attachments_enabled: bool,
-    where_clause: &str,
-    params: &[JsValue],
-    order_clause: &str,
+    db: &Db,
+    format: CipherJsonFormat,
+    user_id: &str,
+    filter: &CipherFilter,
     force_row_query: bool,
 ) -> Result<(), AppError> {
     if force_row_query {
-        return append_from_rows(
-            out,
-            db,
-            attachments_enabled,
-            where_clause,
-            params,
-            order_clause,
-        )
-        .await;
+        return append_from_rows(out, db, format, user_id, filter).await;
     }
 
-    let sql = cipher_json_array_sql(attachments_enabled, where_clause, order_clause);
-
-    let row: Result<Option<CipherJsonArrayRow>, worker::Error> =
-        db.prepare(&sql).bind(params)?.first(None).await;
-
-    match row {
-        Ok(row) => {
-            if let Some(r) = row {
-                out.reserve(r.ciphers_json.len());
-                out.push_str(&r.ciphers_json);
-            } else {
-                out.push_str("[]");
+    let column = format!(
+        "COALESCE(json_group_array(json({})), '[]')",
+        cipher_json_expr(format)
+    );
+    match scoped::user_cipher_texts(db, user_id, filter, &column).await {
+        Ok(rows) => {
+            match rows.first() {
+                Some(ciphers_json) => {
+                    out.reserve(ciphers_json.len());
+                    out.push_str(ciphers_json);
+                }
+                None => out.push_str("[]"),
             }
             Ok(())
         }
         Err(err) if is_sqlite_toobig(&err) => {
-            append_from_rows(
-                out,
-                db,
-                attachments_enabled,
-                where_clause,
-                params,
-                order_clause,
-            )
-            .await
+            append_from_rows(out, db, format, user_id, filter).await
         }
         Err(err) => Err(db::map_d1_json_error(err)),
     }
@@ -899,51 +1751,641 @@ pub(crate) async fn append_cipher_json_array_raw(
 /// Append ciphers JSON array to an existing buffer row by row.
 /// This avoids JSON array exceeding the maximum size that can be returned in a single string.
 ///
-/// Uses `raw_js_value()` to bypass Serde deserialization entirely, which should reduce
-/// CPU time for large payloads. Each row from `raw_js_value()` is a JS array `[cipher_json]`
-/// where the first element is the JSON string we need.
+/// Reads each cipher's JSON as text through [`scoped::user_cipher_texts`], which skips Serde
+/// deserialization and should reduce CPU time for large payloads.
 pub(crate) async fn append_from_rows(
     out: &mut String,
-    db: &worker::D1Database,
-    attachments_enabled: bool,
-    where_clause: &str,
-    params: &[JsValue],
-    order_clause: &str,
+    db: &Db,
+    format: CipherJsonFormat,
+    user_id: &str,
+    filter: &CipherFilter,
 ) -> Result<(), AppError> {
-    use js_sys::Array;
-    use wasm_bindgen::JsCast;
-
-    let sql = cipher_json_rows_sql(attachments_enabled, where_clause, order_clause);
-
-    // Use raw_js_value() to get Vec<JsValue> without Serde deserialization.
-    // Each JsValue is a JS array: [cipher_json_string]
-    let raw_rows: Vec<JsValue> = db
-        .prepare(&sql)
-        .bind(params)?
-        .raw_js_value()
+    let rows = scoped::user_cipher_texts(db, user_id, filter, &cipher_json_expr(format))
         .await
         .map_err(db::map_d1_json_error)?;
 
-    if raw_rows.is_empty() {
+    if rows.is_empty() {
         out.push_str("[]");
         return Ok(());
     }
 
     out.push('[');
-    for (idx, row_js) in raw_rows.iter().enumerate() {
+    for (idx, cipher_json) in rows.iter().enumerate() {
         if idx > 0 {
             out.push(',');
         }
-        // Each row is a JS array [column0, column1, ...]. We only select one column (cipher_json).
-        let row_array = row_js
-            .dyn_ref::<Array>()
-            .ok_or_else(|| AppError::Internal)?;
-        let cipher_json_js = row_array.get(0);
-        let cipher_json = cipher_json_js
-            .as_string()
-            .ok_or_else(|| AppError::Internal)?;
-        out.push_str(&cipher_json);
+        out.push_str(cipher_json);
     }
     out.push(']');
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::organization::MembershipType;
+    use crate::native::{self, block_on, testing};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    const CIPHER_DATA: &str = r#"{"name":"2.bmFtZQ==|aXY=|bWFj","notes":null,"login":{"username":null,"password":"2.cGFzc3dvcmQ=|aXY=|bWFj","uris":[]}}"#;
+
+    /// An organization owned by alice with one collection holding `org-cipher`, which bob is
+    /// assigned to with `read_only` and `hide_passwords`. Bob also has a personal `bob-cipher`.
+    /// Returns the env and access tokens for alice and bob.
+    fn org(read_only: bool, hide_passwords: bool) -> (native::Env, String, String) {
+        let env = testing::env();
+        let alice = testing::user(&env, "alice");
+        let bob = testing::user(&env, "bob");
+        testing::organization(
+            &env,
+            "org",
+            &[
+                ("alice", MembershipType::Owner),
+                ("bob", MembershipType::User),
+            ],
+        );
+        let db = env.d1("vault1").unwrap();
+        let now = time::now_bw();
+        block_on(db.run(
+            "INSERT INTO collections (id, organization_id, name, created_at, updated_at)
+             VALUES ('collection', 'org', '2.Y29s|aXY=|bWFj', ?1, ?1)",
+            &[now.as_str().into()],
+        ))
+        .unwrap();
+        block_on(db.run(
+            "INSERT INTO ciphers (id, user_id, organization_id, type, data, created_at, updated_at)
+             VALUES ('org-cipher', NULL, 'org', 1, ?2, ?1, ?1),
+                    ('bob-cipher', 'bob', NULL, 1, ?2, ?1, ?1)",
+            &[now.as_str().into(), CIPHER_DATA.into()],
+        ))
+        .unwrap();
+        block_on(db.run(
+            "INSERT INTO ciphers_collections (cipher_id, collection_id) VALUES ('org-cipher', 'collection')",
+            &[],
+        ))
+        .unwrap();
+        block_on(db.run(
+            "INSERT INTO collections_users (collection_id, membership_id, read_only, hide_passwords)
+             VALUES ('collection', 'bob-membership', ?1, ?2)",
+            &[(read_only as i32).into(), (hide_passwords as i32).into()],
+        ))
+        .unwrap();
+        (env, alice, bob)
+    }
+
+    fn request(
+        env: &native::Env,
+        method: Method,
+        path: &str,
+        token: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        testing::request(env, method, path, Some(token), body)
+    }
+
+    fn edit(organization_id: Option<&str>) -> Value {
+        json!({
+            "type": 1,
+            "organizationId": organization_id,
+            "name": "2.cmVuYW1lZA==|aXY=|bWFj",
+            "notes": null,
+            "favorite": false,
+            "login": { "username": null, "password": "2.cGFzc3dvcmQ=|aXY=|bWFj", "uris": [] },
+            "fields": [],
+            "reprompt": 0,
+        })
+    }
+
+    /// `edit` and `viewPassword` of `id` as `token`'s user gets it.
+    fn access(env: &native::Env, token: &str, id: &str) -> (bool, bool) {
+        let (status, cipher) =
+            request(env, Method::GET, &format!("/api/ciphers/{id}"), token, None);
+        assert_eq!(status, StatusCode::OK, "{cipher}");
+        (
+            cipher["edit"].as_bool().unwrap(),
+            cipher["viewPassword"].as_bool().unwrap(),
+        )
+    }
+
+    #[test]
+    fn read_only_members_cant_change_the_cipher() {
+        let (env, _, bob) = org(true, false);
+        assert_eq!(access(&env, &bob, "org-cipher"), (false, true));
+
+        let path = "/api/ciphers/org-cipher";
+        let writes = [
+            (Method::PUT, path.to_string(), Some(edit(Some("org")))),
+            (Method::PUT, format!("{path}/delete"), None),
+            (Method::DELETE, path.to_string(), None),
+        ];
+        for (method, path, body) in writes {
+            let (status, body) = request(&env, method.clone(), &path, &bob, body);
+            assert_eq!(status, StatusCode::FORBIDDEN, "{method} {path}: {body}");
+        }
+
+        let db = env.d1("vault1").unwrap();
+        let row: Option<Value> = block_on(db.first(
+            "SELECT data, deleted_at FROM ciphers WHERE id = 'org-cipher'",
+            &[],
+        ))
+        .unwrap();
+        let row = row.unwrap();
+        assert_eq!(row["data"], CIPHER_DATA);
+        assert_eq!(row["deleted_at"], Value::Null);
+    }
+
+    #[test]
+    fn hidden_passwords_are_reflected_in_the_cipher() {
+        let (env, _, bob) = org(false, true);
+
+        assert_eq!(access(&env, &bob, "org-cipher"), (true, false));
+    }
+
+    #[test]
+    fn members_with_write_access_can_edit() {
+        let (env, _, bob) = org(false, false);
+        assert_eq!(access(&env, &bob, "org-cipher"), (true, true));
+
+        let (status, cipher) = request(
+            &env,
+            Method::PUT,
+            "/api/ciphers/org-cipher",
+            &bob,
+            Some(edit(Some("org"))),
+        );
+        assert_eq!(status, StatusCode::OK, "{cipher}");
+        assert_eq!(cipher["name"], "2.cmVuYW1lZA==|aXY=|bWFj");
+    }
+
+    #[test]
+    fn owners_and_personal_ciphers_have_full_access() {
+        let (env, alice, bob) = org(true, true);
+
+        assert_eq!(access(&env, &alice, "org-cipher"), (true, true));
+        assert_eq!(access(&env, &bob, "bob-cipher"), (true, true));
+        let (status, body) = request(
+            &env,
+            Method::PUT,
+            "/api/ciphers/bob-cipher",
+            &bob,
+            Some(edit(None)),
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+    }
+
+    #[test]
+    fn broken_constraints_on_create_are_client_errors() {
+        let (env, _, bob) = org(false, false);
+        let db = env.d1("vault1").unwrap();
+        for (message, status, reason) in [
+            (
+                "D1_ERROR: UNIQUE constraint failed: ciphers.id: SQLITE_CONSTRAINT",
+                StatusCode::CONFLICT,
+                "The cipher already exists",
+            ),
+            (
+                "D1_ERROR: FOREIGN KEY constraint failed: SQLITE_CONSTRAINT",
+                StatusCode::BAD_REQUEST,
+                "The cipher refers to an item that doesn't exist",
+            ),
+        ] {
+            db.fail_batches(0..1, message);
+            let (rendered, body) =
+                request(&env, Method::POST, "/api/ciphers", &bob, Some(edit(None)));
+            assert_eq!(rendered, status, "{body}");
+            assert_eq!(body["message"], reason);
+        }
+        // Anything else is still the server's fault
+        db.fail_batches(0..1, "D1_ERROR: no such table: ciphers");
+        let (status, _) = request(&env, Method::POST, "/api/ciphers", &bob, Some(edit(None)));
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    /// Ids of the ciphers GET /api/ciphers lists for `token`'s user with `query`, sorted.
+    fn listed(env: &native::Env, token: &str, query: &str) -> Vec<String> {
+        let (status, body) = request(
+            env,
+            Method::GET,
+            &format!("/api/ciphers?{query}"),
+            token,
+            None,
+        );
+        assert_eq!(status, StatusCode::OK, "{query}: {body}");
+        let mut ids: Vec<String> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|cipher| cipher["id"].as_str().unwrap().to_string())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn the_list_narrows_by_each_filter() {
+        let (env, _, bob) = org(false, false);
+        let db = env.d1("vault1").unwrap();
+        block_on(db.run(
+            "INSERT INTO folders (id, user_id, name, created_at, updated_at)
+             VALUES ('bob-folder', 'bob', '2.Zm9sZGVy|aXY=|bWFj', ?1, ?1)",
+            &[time::now_bw().into()],
+        ))
+        .unwrap();
+        block_on(db.run(
+            "INSERT INTO ciphers (id, user_id, organization_id, type, data, folder_id, created_at, updated_at, deleted_at)
+             VALUES ('bob-note', 'bob', NULL, 2, ?2, 'bob-folder', ?1, ?1, NULL),
+                    ('bob-trashed', 'bob', NULL, 1, ?2, NULL, ?1, ?1, ?1)",
+            &[time::now_bw().into(), CIPHER_DATA.into()],
+        ))
+        .unwrap();
+
+        assert_eq!(
+            listed(&env, &bob, ""),
+            ["bob-cipher", "bob-note", "org-cipher"]
+        );
+        assert_eq!(listed(&env, &bob, "folderId=bob-folder"), ["bob-note"]);
+        assert_eq!(
+            listed(&env, &bob, "folderId=null"),
+            ["bob-cipher", "org-cipher"]
+        );
+        assert_eq!(
+            listed(&env, &bob, "collectionId=collection"),
+            ["org-cipher"]
+        );
+        assert_eq!(listed(&env, &bob, "organizationId=org"), ["org-cipher"]);
+        assert_eq!(listed(&env, &bob, "type=2"), ["bob-note"]);
+        assert_eq!(listed(&env, &bob, "deleted=true"), ["bob-trashed"]);
+        assert_eq!(
+            listed(&env, &bob, "deleted=false&type=1"),
+            ["bob-cipher", "org-cipher"]
+        );
+        assert_eq!(
+            listed(&env, &bob, "folderId=null&type=1&deleted=true"),
+            ["bob-trashed"]
+        );
+        assert!(listed(&env, &bob, "organizationId=org&type=2").is_empty());
+    }
+
+    #[test]
+    fn filters_outside_the_users_access_list_nothing() {
+        let (env, alice, bob) = org(false, false);
+        block_on(env.d1("vault1").unwrap().run(
+            "DELETE FROM collections_users WHERE membership_id = 'bob-membership'",
+            &[],
+        ))
+        .unwrap();
+
+        assert_eq!(
+            listed(&env, &alice, "collectionId=collection"),
+            ["org-cipher"]
+        );
+        assert!(listed(&env, &bob, "collectionId=collection").is_empty());
+        assert!(listed(&env, &bob, "organizationId=org").is_empty());
+        assert!(listed(&env, &alice, "organizationId=another-org").is_empty());
+        // Values are bound, never spliced into the SQL
+        assert!(listed(&env, &bob, "folderId=x%27%20OR%201%3D1%20--").is_empty());
+        assert!(listed(&env, &bob, "organizationId=%27%20OR%20%271%27%3D%271").is_empty());
+    }
+
+    #[test]
+    fn unknown_types_are_bad_requests() {
+        let (env, _, bob) = org(false, false);
+        for query in ["type=99", "type=-1", "type=login"] {
+            let (status, body) = request(
+                &env,
+                Method::GET,
+                &format!("/api/ciphers?{query}"),
+                &bob,
+                None,
+            );
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{query}: {body}");
+        }
+    }
+
+    /// Gives bob 250 more ciphers; revision dates repeat, so pages also split ties by id.
+    fn many_ciphers(env: &native::Env) {
+        block_on(env.d1("vault1").unwrap().run(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 250)
+             INSERT INTO ciphers (id, user_id, type, data, created_at, updated_at)
+             SELECT printf('bulk-%03d', i), 'bob', 1, ?1, '2025-01-01T00:00:00.000Z',
+                    printf('2025-01-%02dT00:00:00.000Z', 1 + i % 7)
+             FROM n",
+            &[CIPHER_DATA.into()],
+        ))
+        .unwrap();
+    }
+
+    /// Ids of a page of GET /api/ciphers, in order, and its continuation token.
+    fn page(env: &native::Env, token: &str, query: &str) -> (Vec<String>, Option<String>) {
+        let (status, body) = request(
+            env,
+            Method::GET,
+            &format!("/api/ciphers?{query}"),
+            token,
+            None,
+        );
+        assert_eq!(status, StatusCode::OK, "{query}: {body}");
+        let ids = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|cipher| cipher["id"].as_str().unwrap().to_string())
+            .collect();
+        (ids, body["continuationToken"].as_str().map(str::to_string))
+    }
+
+    #[test]
+    fn pages_cover_the_list_without_gaps_or_repeats() {
+        let (env, _, bob) = org(false, false);
+        many_ciphers(&env);
+        let (everything, token) = page(&env, &bob, "");
+        assert_eq!(everything.len(), 252);
+        assert_eq!(token, None);
+
+        let mut paged = Vec::new();
+        let mut sizes = Vec::new();
+        let mut query = "pageSize=100".to_string();
+        loop {
+            let (ids, token) = page(&env, &bob, &query);
+            sizes.push(ids.len());
+            paged.extend(ids);
+            match token {
+                Some(token) => query = format!("pageSize=100&continuationToken={token}"),
+                None => break,
+            }
+        }
+        assert_eq!(sizes, [100, 100, 52]);
+        assert_eq!(paged, everything);
+    }
+
+    #[test]
+    fn pages_resume_after_a_deleted_cipher() {
+        let (env, _, bob) = org(false, false);
+        many_ciphers(&env);
+        let (everything, _) = page(&env, &bob, "");
+        let (first, token) = page(&env, &bob, "pageSize=100");
+        let token = token.unwrap();
+
+        block_on(env.d1("vault1").unwrap().run(
+            "DELETE FROM ciphers WHERE id = ?1",
+            &[first.last().unwrap().as_str().into()],
+        ))
+        .unwrap();
+        let (second, _) = page(
+            &env,
+            &bob,
+            &format!("pageSize=100&continuationToken={token}"),
+        );
+        assert_eq!(second, everything[100..200]);
+    }
+
+    #[test]
+    fn malformed_continuation_tokens_are_bad_requests() {
+        let (env, _, bob) = org(false, false);
+        let no_separator = BASE64URL.encode("2025-01-01T00:00:00.000Z");
+        for token in ["not%20base64!", no_separator.as_str()] {
+            let (status, body) = request(
+                &env,
+                Method::GET,
+                &format!("/api/ciphers?continuationToken={token}"),
+                &bob,
+                None,
+            );
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{token}: {body}");
+        }
+    }
+
+    #[test]
+    fn page_sizes_are_capped() {
+        let query = |page_size: Option<usize>, token: Option<&str>| CipherListQuery {
+            page_size,
+            continuation_token: token.map(str::to_string),
+            ..CipherListQuery::default()
+        };
+        assert_eq!(query(None, None).page_size(), None);
+        assert_eq!(
+            query(None, Some("t")).page_size(),
+            Some(CIPHERS_DEFAULT_PAGE_SIZE)
+        );
+        assert_eq!(query(Some(0), None).page_size(), Some(1));
+        assert_eq!(
+            query(Some(50_000), None).page_size(),
+            Some(CIPHERS_MAX_PAGE_SIZE)
+        );
+    }
+
+    /// `organizationUseTotp` of `id` in bob's single item, list and sync responses.
+    fn use_totp(env: &native::Env, bob: &str, id: &str) -> [Value; 3] {
+        let in_list = |path: &str, key: &str| {
+            let (status, body) = request(env, Method::GET, path, bob, None);
+            assert_eq!(status, StatusCode::OK, "{path}: {body}");
+            body[key]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|cipher| cipher["id"] == id)
+                .unwrap()["organizationUseTotp"]
+                .clone()
+        };
+        let (_, cipher) = request(env, Method::GET, &format!("/api/ciphers/{id}"), bob, None);
+        [
+            cipher["organizationUseTotp"].clone(),
+            in_list("/api/ciphers", "data"),
+            in_list("/api/sync", "ciphers"),
+        ]
+    }
+
+    #[test]
+    fn organization_use_totp_follows_the_organization() {
+        let (env, alice, bob) = org(false, false);
+        block_on(env.d1("vault1").unwrap().run(
+            "UPDATE ciphers SET data = json_set(data, '$.login.totp', '2.dG90cA==|aXY=|bWFj')",
+            &[],
+        ))
+        .unwrap();
+        assert_eq!(use_totp(&env, &bob, "org-cipher"), [true, true, true]);
+        // Personal TOTP codes depend on the user's premium status instead
+        assert_eq!(use_totp(&env, &bob, "bob-cipher"), [false, false, false]);
+
+        let (status, body) = request(
+            &env,
+            Method::PUT,
+            "/api/organizations/org",
+            &alice,
+            Some(json!({ "name": "Org", "useTotp": false })),
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(use_totp(&env, &bob, "org-cipher"), [false, false, false]);
+    }
+
+    /// Puts bob's cipher in his folder, marked as a favorite, with notes.
+    fn filed_favorite(env: &native::Env) {
+        let db = env.d1("vault1").unwrap();
+        block_on(db.run(
+            "INSERT INTO folders (id, user_id, name, created_at, updated_at)
+             VALUES ('bob-folder', 'bob', '2.Zm9sZGVy|aXY=|bWFj', ?1, ?1)",
+            &[time::now_bw().into()],
+        ))
+        .unwrap();
+        block_on(db.run(
+            "UPDATE ciphers SET folder_id = 'bob-folder', favorite = 1,
+                 data = json_set(data, '$.notes', '2.bm90ZXM=|aXY=|bWFj')
+             WHERE id = 'bob-cipher'",
+            &[],
+        ))
+        .unwrap();
+    }
+
+    /// `folderId`, `favorite` and `notes` of bob's cipher after `method` `path` with `body`.
+    fn after_update(
+        env: &native::Env,
+        bob: &str,
+        method: Method,
+        path: &str,
+        body: Value,
+    ) -> (Value, Value, Value) {
+        let (status, cipher) = request(env, method, path, bob, Some(body));
+        assert_eq!(status, StatusCode::OK, "{cipher}");
+        let (_, cipher) = request(env, Method::GET, "/api/ciphers/bob-cipher", bob, None);
+        (
+            cipher["folderId"].clone(),
+            cipher["favorite"].clone(),
+            cipher["notes"].clone(),
+        )
+    }
+
+    #[test]
+    fn updates_keep_what_was_left_out_and_clear_what_was_null() {
+        let (env, _, bob) = org(false, false);
+        let put =
+            |body: Value| after_update(&env, &bob, Method::PUT, "/api/ciphers/bob-cipher", body);
+        filed_favorite(&env);
+
+        // The encrypted content is replaced as a whole: notes left out are cleared too
+        let mut left_out = edit(None);
+        left_out.as_object_mut().unwrap().remove("favorite");
+        assert_eq!(
+            put(left_out),
+            (json!("bob-folder"), json!(true), Value::Null)
+        );
+
+        let mut body = edit(None);
+        body["notes"] = json!("2.bmV3|aXY=|bWFj");
+        body["folderId"] = Value::Null;
+        body["favorite"] = Value::Null;
+        assert_eq!(
+            put(body),
+            (Value::Null, json!(false), json!("2.bmV3|aXY=|bWFj"))
+        );
+
+        let mut body = edit(None);
+        body["folderId"] = json!("bob-folder");
+        body["favorite"] = json!(true);
+        assert_eq!(put(body), (json!("bob-folder"), json!(true), Value::Null));
+    }
+
+    #[test]
+    fn partial_updates_change_only_what_was_sent() {
+        let (env, _, bob) = org(false, false);
+        let partial = |body: Value| {
+            after_update(
+                &env,
+                &bob,
+                Method::PUT,
+                "/api/ciphers/bob-cipher/partial",
+                body,
+            )
+        };
+        filed_favorite(&env);
+        let notes = json!("2.bm90ZXM=|aXY=|bWFj");
+
+        assert_eq!(
+            partial(json!({})),
+            (json!("bob-folder"), json!(true), notes.clone())
+        );
+        assert_eq!(
+            partial(json!({ "favorite": false })),
+            (json!("bob-folder"), json!(false), notes.clone())
+        );
+        assert_eq!(
+            partial(json!({ "folderId": null })),
+            (Value::Null, json!(false), notes.clone())
+        );
+        assert_eq!(
+            partial(json!({ "folderId": "bob-folder", "favorite": true })),
+            (json!("bob-folder"), json!(true), notes)
+        );
+    }
+
+    /// Makes recording events fail, after the cipher statements of a batch ran.
+    fn fail_events(env: &native::Env) {
+        block_on(env.d1("vault1").unwrap().exec(
+            "CREATE TRIGGER fail_events BEFORE INSERT ON events
+             BEGIN SELECT RAISE(ABORT, 'events are down'); END",
+        ))
+        .unwrap();
+    }
+
+    fn count(env: &native::Env, sql: &str) -> i64 {
+        block_on(env.d1("vault1").unwrap().first_column(sql, &[], "n"))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn failed_creations_leave_no_partial_rows() {
+        let (env, alice, _) = org(false, false);
+        let revision = || {
+            block_on(env.d1("vault1").unwrap().first_column::<String>(
+                "SELECT updated_at FROM users WHERE id = 'alice'",
+                &[],
+                "updated_at",
+            ))
+            .unwrap()
+            .unwrap()
+        };
+        let before = revision();
+        fail_events(&env);
+
+        let (status, body) = request(
+            &env,
+            Method::POST,
+            "/api/ciphers/create",
+            &alice,
+            Some(json!({ "cipher": edit(Some("org")), "collectionIds": ["collection"] })),
+        );
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{body}");
+        assert_eq!(count(&env, "SELECT COUNT(*) AS n FROM ciphers"), 2);
+        assert_eq!(
+            count(&env, "SELECT COUNT(*) AS n FROM ciphers_collections"),
+            1
+        );
+        assert_eq!(
+            count(
+                &env,
+                "SELECT cipher_count AS n FROM users WHERE id = 'alice'"
+            ),
+            0
+        );
+        // Neither the revision date nor anything else moved
+        assert_eq!(revision(), before);
+    }
+
+    #[test]
+    fn failed_updates_leave_the_cipher_as_it_was() {
+        let (env, alice, _) = org(false, false);
+        fail_events(&env);
+
+        let (status, body) = request(
+            &env,
+            Method::PUT,
+            "/api/ciphers/org-cipher",
+            &alice,
+            Some(edit(Some("org"))),
+        );
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{body}");
+        let (_, cipher) = request(&env, Method::GET, "/api/ciphers/org-cipher", &alice, None);
+        assert_eq!(cipher["name"], "2.bmFtZQ==|aXY=|bWFj");
+    }
+}
@@ -0,0 +1,958 @@
+//! Organization collections: named groups of organization ciphers that members are given access to.
+
+use axum::{extract::State, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use uuid::Uuid;
+
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
+use crate::{
+    auth::Claims,
+    db::{self, scoped::org_scoped::COLLECTION_ASSIGNMENTS_SQL, Database, Db, Statement},
+    error::{db_error, internal_error, AppError},
+    handlers::{
+        events::{self, EventSource},
+        organizations::{find_organization_for_member, touch_members_statement},
+    },
+    models::{
+        cipher::Cipher,
+        collection::{
+            Collection, CollectionAccess, CollectionAccessRequest, CollectionRequest,
+            CollectionUser,
+        },
+        event::{Event, EventType},
+        organization::{Membership, MembershipStatus},
+        response::ListResponse,
+    },
+    time,
+};
+
+fn collection_not_found() -> AppError {
+    AppError::NotFound("Collection not found".to_string())
+}
+
+/// A collection joined with the requesting member's membership and assignment.
+#[derive(Deserialize)]
+struct CollectionAccessRow {
+    id: String,
+    organization_id: String,
+    name: String,
+    external_id: Option<String>,
+    created_at: String,
+    updated_at: String,
+    member_type: i32,
+    access_all: i32,
+    read_only: Option<i32>,
+    hide_passwords: Option<i32>,
+    manage: Option<i32>,
+}
+
+impl CollectionAccessRow {
+    fn into_parts(self) -> (Collection, CollectionAccess) {
+        let full = Membership::full_access_for(self.member_type, self.access_all);
+        let access = if full {
+            CollectionAccess::FULL
+        } else {
+            CollectionAccess {
+                read_only: self.read_only.unwrap_or(0) != 0,
+                hide_passwords: self.hide_passwords.unwrap_or(0) != 0,
+                manage: self.manage.unwrap_or(0) != 0,
+            }
+        };
+        let collection = Collection {
+            id: self.id,
+            organization_id: self.organization_id,
+            name: self.name,
+            external_id: self.external_id,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        };
+        (collection, access)
+    }
+}
+
+/// Collections the user can see, with their access to each, across all their confirmed
+/// memberships or within a single organization.
+pub(crate) async fn list_user_collections(
+    db: &Db,
+    user_id: &str,
+    org_id: Option<&str>,
+) -> Result<Vec<(Collection, CollectionAccess)>, AppError> {
+    let rows: Vec<CollectionAccessRow> = db
+        .all(
+            &format!(
+                "SELECT c.*, ou.atype AS member_type, ou.access_all,
+                    MIN(cu.read_only) AS read_only, MIN(cu.hide_passwords) AS hide_passwords,
+                    MAX(cu.manage) AS manage
+             FROM collections c
+             JOIN organization_users ou
+               ON ou.organization_id = c.organization_id AND ou.user_id = ?1 AND ou.status = ?2
+             LEFT JOIN ({COLLECTION_ASSIGNMENTS_SQL}) cu
+               ON cu.collection_id = c.id AND cu.membership_id = ou.id
+             WHERE (?3 IS NULL OR c.organization_id = ?3)
+               -- Owners (0) and admins (1) see every collection
+               AND (ou.access_all = 1 OR ou.atype IN (0, 1) OR cu.collection_id IS NOT NULL)
+             GROUP BY c.id, ou.id
+             ORDER BY c.organization_id, c.name"
+            ),
+            &[
+                user_id.into(),
+                (MembershipStatus::Confirmed as i32).into(),
+                org_id.into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    Ok(rows
+        .into_iter()
+        .map(CollectionAccessRow::into_parts)
+        .collect())
+}
+
+/// `collections` for `/api/sync` and `/api/collections`.
+pub(crate) async fn list_collection_details(
+    db: &Db,
+    user_id: &str,
+) -> Result<Vec<Value>, AppError> {
+    Ok(list_user_collections(db, user_id, None)
+        .await?
+        .iter()
+        .map(|(collection, access)| collection.to_details_json(access))
+        .collect())
+}
+
+/// Checks that every collection belongs to the organization and that the user can add items to
+/// it, i.e. isn't limited to read-only access.
+pub(crate) async fn check_writable_collections(
+    db: &Db,
+    user_id: &str,
+    org_id: &str,
+    collection_ids: &[String],
+) -> Result<(), AppError> {
+    let accessible: HashMap<String, CollectionAccess> =
+        list_user_collections(db, user_id, Some(org_id))
+            .await?
+            .into_iter()
+            .map(|(collection, access)| (collection.id, access))
+            .collect();
+
+    for collection_id in collection_ids {
+        match accessible.get(collection_id) {
+            None => {
+                return Err(AppError::BadRequest(
+                    "Collection does not belong to the organization".to_string(),
+                ))
+            }
+            Some(access) if access.read_only => {
+                return Err(AppError::Forbidden(
+                    "You don't have permission to add items to this collection".to_string(),
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Statements assigning a cipher to collections.
+pub(crate) fn cipher_assignment_statements(
+    db: &Db,
+    cipher_id: &str,
+    collection_ids: &[String],
+) -> Result<Vec<Statement>, AppError> {
+    collection_ids
+        .iter()
+        .map(|collection_id| {
+            Database::prepare(
+                db,
+                "INSERT OR IGNORE INTO ciphers_collections (cipher_id, collection_id) VALUES (?1, ?2)",
+                &[cipher_id.into(), collection_id.as_str().into()],
+            )
+            .map_err(db_error!())
+        })
+        .collect()
+}
+
+/// Fills in `collectionIds` of a cipher response from `ciphers_collections`.
+pub(crate) async fn hydrate_cipher_collections(
+    db: &Db,
+    cipher: &mut Cipher,
+) -> Result<(), AppError> {
+    #[derive(Deserialize)]
+    struct CipherCollection {
+        collection_id: String,
+    }
+    let rows: Vec<CipherCollection> = db
+        .all(
+            "SELECT collection_id FROM ciphers_collections WHERE cipher_id = ?1",
+            &[cipher.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    cipher.collection_ids = Some(rows.into_iter().map(|row| row.collection_id).collect());
+    Ok(())
+}
+
+/// Loads a collection of the organization together with the member's access to it. Collections
+/// the member can't see look like missing ones.
+async fn find_collection_for_member(
+    db: &Db,
+    membership: &Membership,
+    collection_id: &str,
+) -> Result<(Collection, CollectionAccess), AppError> {
+    let collection: Collection = db
+        .first(
+            "SELECT * FROM collections WHERE id = ?1 AND organization_id = ?2",
+            &[
+                collection_id.into(),
+                membership.organization_id.as_str().into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(collection_not_found)?;
+
+    if membership.has_full_access() {
+        return Ok((collection, CollectionAccess::FULL));
+    }
+
+    let assignment: CollectionUser = db
+        .first(
+            &format!(
+                "SELECT collection_id, membership_id, MIN(read_only) AS read_only,
+                    MIN(hide_passwords) AS hide_passwords, MAX(manage) AS manage
+             FROM ({COLLECTION_ASSIGNMENTS_SQL})
+             WHERE collection_id = ?1 AND membership_id = ?2
+             GROUP BY collection_id, membership_id"
+            ),
+            &[collection_id.into(), membership.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(collection_not_found)?;
+
+    Ok((collection, assignment.access()))
+}
+
+/// Statements replacing a collection's member assignments. Every assigned membership must
+/// belong to the collection's organization.
+async fn assignment_statements(
+    db: &Db,
+    collection: &Collection,
+    users: &[CollectionAccessRequest],
+) -> Result<Vec<Statement>, AppError> {
+    grantee_statements(
+        db,
+        collection,
+        ("collections_users", "membership_id"),
+        "organization_users",
+        users,
+        "Collection users must be members of the organization",
+    )
+    .await
+}
+
+/// Statements replacing a collection's group access. Every group must belong to the collection's
+/// organization.
+async fn group_statements(
+    db: &Db,
+    collection: &Collection,
+    groups: &[CollectionAccessRequest],
+) -> Result<Vec<Statement>, AppError> {
+    grantee_statements(
+        db,
+        collection,
+        ("collections_groups", "group_id"),
+        "groups",
+        groups,
+        "Collection groups must belong to the organization",
+    )
+    .await
+}
+
+/// Statements replacing a collection's rows of `table`, whose `column` points into
+/// `grantee_table`. Every grantee must belong to the collection's organization, or the request
+/// is rejected with `unknown_grantee`.
+async fn grantee_statements(
+    db: &Db,
+    collection: &Collection,
+    (table, column): (&str, &str),
+    grantee_table: &str,
+    grantees: &[CollectionAccessRequest],
+    unknown_grantee: &str,
+) -> Result<Vec<Statement>, AppError> {
+    let mut statements = vec![Database::prepare(
+        db,
+        &format!("DELETE FROM {table} WHERE collection_id = ?1"),
+        &[collection.id.as_str().into()],
+    )
+    .map_err(db_error!())?];
+    if grantees.is_empty() {
+        return Ok(statements);
+    }
+
+    let grantees: HashMap<&str, &CollectionAccessRequest> = grantees
+        .iter()
+        .map(|grantee| (grantee.id.as_str(), grantee))
+        .collect();
+    let grantee_ids: Vec<&str> = grantees.keys().copied().collect();
+    let grantee_ids_json = serde_json::to_string(&grantee_ids).map_err(internal_error!())?;
+
+    #[derive(Deserialize)]
+    struct GranteeId {
+        id: String,
+    }
+    let known: Vec<GranteeId> = db
+        .all(
+            &format!(
+                "SELECT id FROM {grantee_table}
+             WHERE organization_id = ?1 AND id IN (SELECT value FROM json_each(?2))"
+            ),
+            &[
+                collection.organization_id.as_str().into(),
+                grantee_ids_json.into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?;
+    if known.len() != grantees.len() {
+        return Err(AppError::BadRequest(unknown_grantee.to_string()));
+    }
+
+    for grantee in known {
+        let access = grantees[grantee.id.as_str()];
+        statements.push(
+            Database::prepare(
+                db,
+                &format!("INSERT INTO {table} (collection_id, {column}, read_only, hide_passwords, manage)
+                     VALUES (?1, ?2, ?3, ?4, ?5)"),
+                &[
+                    collection.id.as_str().into(),
+                    grantee.id.as_str().into(),
+                    (access.read_only as i32).into(),
+                    (access.hide_passwords as i32).into(),
+                    (access.manage as i32).into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    Ok(statements)
+}
+
+/// Statements replacing a member's collection assignments. Every collection must belong to the
+/// member's organization.
+pub(crate) async fn member_assignment_statements(
+    db: &Db,
+    org_id: &str,
+    membership_id: &str,
+    collections: &[CollectionAccessRequest],
+) -> Result<Vec<Statement>, AppError> {
+    access_statements(
+        db,
+        org_id,
+        "collections_users",
+        "membership_id",
+        membership_id,
+        collections,
+    )
+    .await
+}
+
+/// Statements replacing a group's collection access. Every collection must belong to the
+/// group's organization.
+pub(crate) async fn group_assignment_statements(
+    db: &Db,
+    org_id: &str,
+    group_id: &str,
+    collections: &[CollectionAccessRequest],
+) -> Result<Vec<Statement>, AppError> {
+    access_statements(
+        db,
+        org_id,
+        "collections_groups",
+        "group_id",
+        group_id,
+        collections,
+    )
+    .await
+}
+
+/// Statements replacing the rows of `table` (`collections_users` or `collections_groups`) whose
+/// `column` is `grantee_id` with the given collection access.
+async fn access_statements(
+    db: &Db,
+    org_id: &str,
+    table: &str,
+    column: &str,
+    grantee_id: &str,
+    collections: &[CollectionAccessRequest],
+) -> Result<Vec<Statement>, AppError> {
+    let mut statements = vec![Database::prepare(
+        db,
+        &format!("DELETE FROM {table} WHERE {column} = ?1"),
+        &[grantee_id.into()],
+    )
+    .map_err(db_error!())?];
+    if collections.is_empty() {
+        return Ok(statements);
+    }
+
+    let collections: HashMap<&str, &CollectionAccessRequest> = collections
+        .iter()
+        .map(|collection| (collection.id.as_str(), collection))
+        .collect();
+    let collection_ids: Vec<&str> = collections.keys().copied().collect();
+    let collection_ids_json = serde_json::to_string(&collection_ids).map_err(internal_error!())?;
+
+    #[derive(Deserialize)]
+    struct CollectionId {
+        id: String,
+    }
+    let known: Vec<CollectionId> = db
+        .all(
+            "SELECT id FROM collections
+         WHERE organization_id = ?1 AND id IN (SELECT value FROM json_each(?2))",
+            &[org_id.into(), collection_ids_json.into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    if known.len() != collections.len() {
+        return Err(AppError::BadRequest(
+            "Collection does not belong to the organization".to_string(),
+        ));
+    }
+
+    for collection in known {
+        let access = collections[collection.id.as_str()];
+        statements.push(
+            Database::prepare(
+                db,
+                &format!("INSERT INTO {table} (collection_id, {column}, read_only, hide_passwords, manage)
+                     VALUES (?1, ?2, ?3, ?4, ?5)"),
+                &[
+                    collection.id.as_str().into(),
+                    grantee_id.into(),
+                    (access.read_only as i32).into(),
+                    (access.hide_passwords as i32).into(),
+                    (access.manage as i32).into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    Ok(statements)
+}
+
+/// A row of `collections_users` or `collections_groups`, with the member or group as `grantee_id`.
+#[derive(Deserialize)]
+struct AccessRow {
+    collection_id: String,
+    grantee_id: String,
+    read_only: i32,
+    hide_passwords: i32,
+    manage: i32,
+}
+
+impl AccessRow {
+    /// Access as listed in member, group and collection details; `id` is the other side of the
+    /// assignment.
+    fn to_json(&self, id: &str) -> Value {
+        json!({
+            "id": id,
+            "readOnly": self.read_only != 0,
+            "hidePasswords": self.hide_passwords != 0,
+            "manage": self.manage != 0,
+        })
+    }
+}
+
+/// Every row of `table` (`collections_users` or `collections_groups`) within an organization.
+async fn list_access_rows(
+    db: &Db,
+    org_id: &str,
+    table: &str,
+    column: &str,
+) -> Result<Vec<AccessRow>, AppError> {
+    db.all(
+        &format!("SELECT a.collection_id, a.{column} AS grantee_id, a.read_only, a.hide_passwords, a.manage
+             FROM {table} a
+             JOIN collections c ON c.id = a.collection_id
+             WHERE c.organization_id = ?1"),
+        &[org_id.into()],
+    )
+    .await
+    .map_err(db_error!())
+}
+
+fn by_collection(rows: Vec<AccessRow>) -> HashMap<String, Vec<AccessRow>> {
+    let mut access: HashMap<String, Vec<AccessRow>> = HashMap::new();
+    for row in rows {
+        access
+            .entry(row.collection_id.clone())
+            .or_default()
+            .push(row);
+    }
+    access
+}
+
+fn by_grantee(rows: Vec<AccessRow>) -> HashMap<String, Vec<Value>> {
+    let mut assignments: HashMap<String, Vec<Value>> = HashMap::new();
+    for row in rows {
+        let access = row.to_json(&row.collection_id);
+        assignments.entry(row.grantee_id).or_default().push(access);
+    }
+    assignments
+}
+
+/// Collection assignments of every member of an organization, keyed by membership id, as the
+/// `collections` of the member details. Access through groups isn't included.
+pub(crate) async fn list_member_assignments(
+    db: &Db,
+    org_id: &str,
+) -> Result<HashMap<String, Vec<Value>>, AppError> {
+    let rows = list_access_rows(db, org_id, "collections_users", "membership_id").await?;
+    Ok(by_grantee(rows))
+}
+
+/// Collection access of every group of an organization, keyed by group id, as the
+/// `collections` of the group details.
+pub(crate) async fn list_group_assignments(
+    db: &Db,
+    org_id: &str,
+) -> Result<HashMap<String, Vec<Value>>, AppError> {
+    let rows = list_access_rows(db, org_id, "collections_groups", "group_id").await?;
+    Ok(by_grantee(rows))
+}
+
+fn validate_name(payload: &CollectionRequest) -> Result<(), AppError> {
+    if payload.name.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "Collection name is required".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// GET /api/collections
+///
+/// Collections of every organization the user belongs to, for the vault sidebar.
+#[utoipa::path(
+    get,
+    path = "/api/collections",
+    tag = "collections",
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_user_collections(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    let collections = list_collection_details(&db, &claims.sub).await?;
+
+    Ok(Json(ListResponse::new(collections)))
+}
+
+/// GET /api/organizations/{id}/collections
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/collections",
+    tag = "collections",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_org_collections(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    let collections: Vec<Value> = list_user_collections(&db, &claims.sub, Some(&org_id))
+        .await?
+        .iter()
+        .map(|(collection, _)| collection.to_json())
+        .collect();
+
+    Ok(Json(ListResponse::new(collections)))
+}
+
+/// `collectionAccessDetails` of the given collections of the member's organization.
+async fn access_details_json(
+    db: &Db,
+    membership: &Membership,
+    collections: Vec<(Collection, CollectionAccess)>,
+) -> Result<Vec<Value>, AppError> {
+    let org_id = &membership.organization_id;
+    let mut users =
+        by_collection(list_access_rows(db, org_id, "collections_users", "membership_id").await?);
+    let mut groups =
+        by_collection(list_access_rows(db, org_id, "collections_groups", "group_id").await?);
+
+    #[derive(Deserialize)]
+    struct GroupId {
+        group_id: String,
+    }
+    let member_groups: Vec<GroupId> = db
+        .all(
+            "SELECT group_id FROM groups_users WHERE membership_id = ?1",
+            &[membership.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    let member_groups: HashSet<String> = member_groups.into_iter().map(|g| g.group_id).collect();
+
+    Ok(collections
+        .into_iter()
+        .map(|(collection, access)| {
+            let users = users.remove(&collection.id).unwrap_or_default();
+            let groups = groups.remove(&collection.id).unwrap_or_default();
+            let assigned = users.iter().any(|row| row.grantee_id == membership.id)
+                || groups
+                    .iter()
+                    .any(|row| member_groups.contains(&row.grantee_id));
+            collection.to_access_details_json(
+                &access,
+                assigned,
+                groups
+                    .iter()
+                    .map(|row| row.to_json(&row.grantee_id))
+                    .collect(),
+                users
+                    .iter()
+                    .map(|row| row.to_json(&row.grantee_id))
+                    .collect(),
+            )
+        })
+        .collect())
+}
+
+/// GET /api/organizations/{id}/collections/details
+///
+/// The collections the member can see with everyone's access to them. Owners, admins and
+/// managers only.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/collections/details",
+    tag = "collections",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_org_collections_details(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_manager() {
+        return Err(AppError::Forbidden(
+            "You don't have permission to manage collections".to_string(),
+        ));
+    }
+    let collections = list_user_collections(&db, &claims.sub, Some(&org.id)).await?;
+    let data = access_details_json(&db, &membership, collections).await?;
+
+    Ok(Json(ListResponse::new(data)))
+}
+
+/// GET /api/organizations/{id}/collections/{collection_id}/details
+///
+/// Requires manage access to the collection.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/collections/{collection_id}/details",
+    tag = "collections",
+    params(("id" = String, Path), ("collection_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_collection_details(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, collection_id)): AppPath<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (_org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    let (collection, access) = find_collection_for_member(&db, &membership, &collection_id).await?;
+    if !access.manage {
+        return Err(AppError::Forbidden(
+            "You don't have permission to manage this collection".to_string(),
+        ));
+    }
+    let mut details = access_details_json(&db, &membership, vec![(collection, access)]).await?;
+
+    Ok(Json(details.remove(0)))
+}
+
+async fn log_collection_event(
+    db: &Db,
+    source: &EventSource,
+    atype: EventType,
+    claims: &Claims,
+    collection: &Collection,
+) {
+    let event = Event {
+        organization_id: Some(collection.organization_id.clone()),
+        collection_id: Some(collection.id.clone()),
+        ..Event::new(atype, &claims.sub)
+    };
+    events::log_event(db, source, event).await;
+}
+
+/// POST /api/organizations/{id}/collections
+///
+/// Owners, admins and managers can create collections. A manager who can't see every collection
+/// is given manage access to the new one so it doesn't disappear from under them.
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{id}/collections",
+    tag = "collections",
+    params(("id" = String, Path)),
+    request_body = CollectionRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_collection(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+    AppJson(payload): AppJson<CollectionRequest>,
+) -> Result<Json<Value>, AppError> {
+    validate_name(&payload)?;
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_manager() {
+        return Err(AppError::Forbidden(
+            "Only owners, admins and managers can create collections".to_string(),
+        ));
+    }
+
+    let groups = payload
+        .groups
+        .filter(|_| org.use_groups)
+        .unwrap_or_default();
+    let now = time::now_bw();
+    let collection = Collection {
+        id: Uuid::new_v4().to_string(),
+        organization_id: org.id,
+        name: payload.name,
+        external_id: payload.external_id,
+        created_at: now.clone(),
+        updated_at: now.clone(),
+    };
+    let mut users = payload.users.unwrap_or_default();
+    let access = if membership.has_full_access() {
+        CollectionAccess::FULL
+    } else {
+        users.retain(|user| user.id != membership.id);
+        users.push(CollectionAccessRequest {
+            id: membership.id.clone(),
+            read_only: false,
+            hide_passwords: false,
+            manage: true,
+        });
+        CollectionAccess {
+            read_only: false,
+            hide_passwords: false,
+            manage: true,
+        }
+    };
+
+    let mut statements = vec![Database::prepare(
+        &db,
+        "INSERT INTO collections (id, organization_id, name, external_id, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        &[
+            collection.id.as_str().into(),
+            collection.organization_id.as_str().into(),
+            collection.name.as_str().into(),
+            collection.external_id.as_deref().into(),
+            collection.created_at.as_str().into(),
+            collection.updated_at.as_str().into(),
+        ],
+    )
+    .map_err(db_error!())?];
+    statements.extend(assignment_statements(&db, &collection, &users).await?);
+    statements.extend(group_statements(&db, &collection, &groups).await?);
+    statements.push(touch_members_statement(
+        &db,
+        &collection.organization_id,
+        &now,
+    )?);
+    Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+    log_collection_event(
+        &db,
+        &source,
+        EventType::CollectionCreated,
+        &claims,
+        &collection,
+    )
+    .await;
+
+    Ok(Json(collection.to_details_json(&access)))
+}
+
+/// PUT /api/organizations/{id}/collections/{collection_id}
+///
+/// Requires manage access to the collection. Assignments are replaced when `users` is given.
+#[utoipa::path(
+    method(put, post),
+    path = "/api/organizations/{id}/collections/{collection_id}",
+    tag = "collections",
+    params(("id" = String, Path), ("collection_id" = String, Path)),
+    request_body = CollectionRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_collection(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, collection_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<CollectionRequest>,
+) -> Result<Json<Value>, AppError> {
+    validate_name(&payload)?;
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    let (mut collection, access) =
+        find_collection_for_member(&db, &membership, &collection_id).await?;
+    if !access.manage {
+        return Err(AppError::Forbidden(
+            "You don't have permission to manage this collection".to_string(),
+        ));
+    }
+
+    let now = time::now_bw();
+    collection.name = payload.name;
+    collection.external_id = payload.external_id;
+    collection.updated_at = now.clone();
+
+    let mut statements = vec![Database::prepare(
+        &db,
+        "UPDATE collections SET name = ?1, external_id = ?2, updated_at = ?3 WHERE id = ?4",
+        &[
+            collection.name.as_str().into(),
+            collection.external_id.as_deref().into(),
+            collection.updated_at.as_str().into(),
+            collection.id.as_str().into(),
+        ],
+    )
+    .map_err(db_error!())?];
+    if let Some(users) = payload.users {
+        statements.extend(assignment_statements(&db, &collection, &users).await?);
+    }
+    if let Some(groups) = payload.groups.filter(|_| org.use_groups) {
+        statements.extend(group_statements(&db, &collection, &groups).await?);
+    }
+    statements.push(touch_members_statement(
+        &db,
+        &collection.organization_id,
+        &now,
+    )?);
+    Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+    log_collection_event(
+        &db,
+        &source,
+        EventType::CollectionUpdated,
+        &claims,
+        &collection,
+    )
+    .await;
+
+    Ok(Json(collection.to_details_json(&access)))
+}
+
+/// DELETE /api/organizations/{id}/collections/{collection_id}
+///
+/// Requires manage access to the collection. The collection's ciphers are kept, only their
+/// assignments to it are removed.
+#[utoipa::path(
+    delete,
+    path = "/api/organizations/{id}/collections/{collection_id}",
+    tag = "collections",
+    params(("id" = String, Path), ("collection_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn delete_collection(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, collection_id)): AppPath<(String, String)>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let (_org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    let (collection, access) = find_collection_for_member(&db, &membership, &collection_id).await?;
+    if !access.manage {
+        return Err(AppError::Forbidden(
+            "You don't have permission to delete this collection".to_string(),
+        ));
+    }
+
+    let now = time::now_bw();
+    Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "DELETE FROM ciphers_collections WHERE collection_id = ?1",
+                &[collection.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM collections_users WHERE collection_id = ?1",
+                &[collection.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM collections_groups WHERE collection_id = ?1",
+                &[collection.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM collections WHERE id = ?1",
+                &[collection.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            touch_members_statement(&db, &collection.organization_id, &now)?,
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+    log_collection_event(
+        &db,
+        &source,
+        EventType::CollectionDeleted,
+        &claims,
+        &collection,
+    )
+    .await;
+
+    Ok(Json(()))
+}
@@ -1,72 +1,150 @@
 use axum::{extract::State, Extension, Json};
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
 use std::sync::Arc;
-use worker::Env;
 
-use crate::BaseUrl;
+use crate::Env;
+use crate::{config::Settings, push, BaseUrl};
 
-/// Get the disable_user_registration setting from environment variable.
-/// Defaults to true if not set. Only "false" will disable it.
-fn get_disable_user_registration(env: &Env) -> bool {
-    env.var("DISABLE_USER_REGISTRATION")
-        .ok()
-        .map(|v| v.to_string().to_lowercase() != "false")
-        .unwrap_or(true)
+// Note: The clients use this version to handle backwards compatibility concerns
+// This means they expect a version that closely matches the Bitwarden server version
+// We should make sure that we keep this updated when we support the new server features
+// Version history:
+// - Individual cipher key encryption: 2024.2.0
+// - Mobile app support for MasterPasswordUnlockData: 2025.8.0
+pub(crate) const SERVER_VERSION: &str = "2025.12.0";
+
+/// Feature flags advertised unless FEATURE_FLAGS says otherwise.
+//
+// Official available feature flags can be found here:
+// Server (v2025.6.2): https://github.com/bitwarden/server/blob/d094be3267f2030bd0dc62106bc6871cf82682f5/src/Core/Constants.cs#L103
+// Client (web-v2025.6.1): https://github.com/bitwarden/clients/blob/747c2fd6a1c348a57a76e4a7de8128466ffd3c01/libs/common/src/enums/feature-flag.enum.ts#L12
+// Android (v2025.6.0): https://github.com/bitwarden/android/blob/b5b022caaad33390c31b3021b2c1205925b0e1a2/app/src/main/kotlin/com/x8bit/bitwarden/data/platform/manager/model/FlagKey.kt#L22
+// iOS (v2025.6.0): https://github.com/bitwarden/ios/blob/ff06d9c6cc8da89f78f37f376495800201d7261a/BitwardenShared/Core/Platform/Models/Enum/FeatureFlag.swift#L7
+const DEFAULT_FEATURE_FLAGS: &[(&str, bool)] =
+    &[("email-verification", true), ("unauth-ui-refresh", true)];
+
+/// `featureStates`: the defaults, overridden and extended by FEATURE_FLAGS.
+fn feature_states(settings: &Settings) -> Map<String, Value> {
+    let mut states: Map<String, Value> = DEFAULT_FEATURE_FLAGS
+        .iter()
+        .map(|(key, value)| (key.to_string(), Value::Bool(*value)))
+        .collect();
+    states.extend(
+        settings
+            .feature_flags
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::Bool(*value))),
+    );
+    states
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/config",
+    tag = "config",
+    responses((status = 200, description = "Success"))
+)]
 #[worker::send]
 pub async fn config(
     State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
     Extension(BaseUrl(domain)): Extension<BaseUrl>,
 ) -> Json<Value> {
-    // Official available feature flags can be found here:
-    // Server (v2025.6.2): https://github.com/bitwarden/server/blob/d094be3267f2030bd0dc62106bc6871cf82682f5/src/Core/Constants.cs#L103
-    // Client (web-v2025.6.1): https://github.com/bitwarden/clients/blob/747c2fd6a1c348a57a76e4a7de8128466ffd3c01/libs/common/src/enums/feature-flag.enum.ts#L12
-    // Android (v2025.6.0): https://github.com/bitwarden/android/blob/b5b022caaad33390c31b3021b2c1205925b0e1a2/app/src/main/kotlin/com/x8bit/bitwarden/data/platform/manager/model/FlagKey.kt#L22
-    // iOS (v2025.6.0): https://github.com/bitwarden/ios/blob/ff06d9c6cc8da89f78f37f376495800201d7261a/BitwardenShared/Core/Platform/Models/Enum/FeatureFlag.swift#L7
-    // let mut feature_states =
-    //     parse_experimental_client_feature_flags(&crate::CONFIG.experimental_client_feature_flags());
-    // feature_states.insert("duo-redirect".to_string(), true);
-    // feature_states.insert("email-verification".to_string(), true);
-    // feature_states.insert("unauth-ui-refresh".to_string(), true);
-    // feature_states.insert("enable-pm-flight-recorder".to_string(), true);
-    // feature_states.insert("mobile-error-reporting".to_string(), true);
-
-    let disable_user_registration = get_disable_user_registration(&env);
+    let vapid_public_key = push::web_push_public_key(&env);
+    // Clients connect to `{notifications}/hub`; advertise our own hub when it is bound
+    let notifications_url = if settings.notifications_url.is_empty()
+        && env.durable_object("NOTIFICATIONS_HUB").is_ok()
+    {
+        format!("{domain}/notifications")
+    } else {
+        settings.notifications_url.clone()
+    };
 
     Json(json!({
-        // Note: The clients use this version to handle backwards compatibility concerns
-        // This means they expect a version that closely matches the Bitwarden server version
-        // We should make sure that we keep this updated when we support the new server features
-        // Version history:
-        // - Individual cipher key encryption: 2024.2.0
-        // - Mobile app support for MasterPasswordUnlockData: 2025.8.0
-        "version": "2025.12.0",
-        "gitHash": "5d84f176",
+        "version": SERVER_VERSION,
+        "gitHash": env!("GIT_HASH"),
         "server": {
-          "name": "Vaultwarden",
-          "url": "https://github.com/dani-garcia/vaultwarden"
+          "name": settings.server_name,
+          "url": settings.server_url,
         },
         "settings": {
-            "disableUserRegistration": disable_user_registration,
+            "disableUserRegistration": settings.disable_user_registration,
         },
         "environment": {
           "vault": domain,
           "api": format!("{domain}/api"),
           "identity": format!("{domain}/identity"),
-          "notifications": format!(""),
+          "notifications": notifications_url,
           "sso": format!(""),
           "cloudRegion": null,
         },
         // Bitwarden uses this for the self-hosted servers to indicate the default push technology
+        // (0 = SignalR, 1 = Web Push). Browsers only subscribe when a VAPID key is advertised.
         "push": {
-          "pushTechnology": 0,
-          "vapidPublicKey": null
-        },
-        "featureStates": {
-            // "duo-redirect": true,
-            // "flexible-collections-v-1": false
+          "pushTechnology": if vapid_public_key.is_some() { 1 } else { 0 },
+          "vapidPublicKey": vapid_public_key
         },
+        "featureStates": feature_states(&settings),
         "object": "config",
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::native::{self, testing};
+    use axum::http::{Method, StatusCode};
+    use serde_json::Value;
+
+    fn config(env: &native::Env) -> Value {
+        let (status, config) = testing::request(env, Method::GET, "/api/config", None, None);
+        assert_eq!(status, StatusCode::OK);
+        config
+    }
+
+    #[test]
+    fn defaults_are_advertised() {
+        let config = config(&testing::env());
+        assert_eq!(config["version"], super::SERVER_VERSION);
+        assert_eq!(config["gitHash"], env!("GIT_HASH"));
+        assert_eq!(config["environment"]["vault"], "https://vault.example.com");
+        assert_eq!(
+            config["environment"]["api"],
+            "https://vault.example.com/api"
+        );
+        assert_eq!(
+            config["environment"]["identity"],
+            "https://vault.example.com/identity"
+        );
+        assert_eq!(
+            config["featureStates"],
+            serde_json::json!({ "email-verification": true, "unauth-ui-refresh": true })
+        );
+    }
+
+    #[test]
+    fn feature_flags_and_server_info_come_from_env() {
+        let env = testing::env()
+            .with_var(
+                "FEATURE_FLAGS",
+                "email-verification=false,pm-123-new-thing=true,broken",
+            )
+            .with_var("SERVER_NAME", "Example Vault")
+            .with_var("SERVER_URL", "https://example.com/vault")
+            .with_var("NOTIFICATIONS_URL", "https://push.example.com");
+        let config = config(&env);
+        assert_eq!(
+            config["featureStates"],
+            serde_json::json!({
+                "email-verification": false,
+                "unauth-ui-refresh": true,
+                "pm-123-new-thing": true,
+            })
+        );
+        assert_eq!(config["server"]["name"], "Example Vault");
+        assert_eq!(config["server"]["url"], "https://example.com/vault");
+        assert_eq!(
+            config["environment"]["notifications"],
+            "https://push.example.com"
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Local development helpers. They exist only when `DEV_SEED` is set; never set it on a
+//! deployment anyone relies on.
+
+use axum::{extract::State, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::Env;
+use crate::{
+    config::Settings,
+    crypto::{generate_salt, hash_password_for_storage},
+    db::{self, Database},
+    error::{db_error, internal_error, AppError},
+    quota, time,
+};
+
+/// The seeded account and vault, generated by `scripts/generate-dev-seed.py`.
+const DEV_SEED_JSON: &str = include_str!("../../sql/dev_seed.json");
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DevSeed {
+    user: SeedUser,
+    folders: Vec<SeedFolder>,
+    ciphers: Vec<SeedCipher>,
+}
+
+/// The account, with its hashes and keys derived the way the clients do.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SeedUser {
+    id: String,
+    name: String,
+    email: String,
+    password: String,
+    kdf_iterations: i32,
+    /// Client-side hash; hashed again for storage like at registration.
+    master_password_hash: String,
+    key: String,
+    public_key: String,
+    private_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SeedFolder {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SeedCipher {
+    id: String,
+    #[serde(rename = "type")]
+    r#type: i32,
+    folder_id: Option<String>,
+    #[serde(default)]
+    favorite: bool,
+    /// In the trash.
+    #[serde(default)]
+    deleted: bool,
+    data: Value,
+}
+
+/// POST /dev/seed
+///
+/// Creates a test account with a few folders and items of every type, one of them in the trash,
+/// and returns its credentials. Only what is missing is created, so it can be called again at
+/// any time; items changed since are left as they are. Returns 404 unless `DEV_SEED` is set.
+#[utoipa::path(
+    post,
+    path = "/dev/seed",
+    tag = "dev",
+    responses((status = 200, description = "Success"))
+)]
+#[worker::send]
+pub async fn post_seed(State(env): State<Arc<Env>>) -> Result<Json<Value>, AppError> {
+    let settings = Settings::get(&env);
+    if !settings.dev_seed {
+        return Err(AppError::NotFound("Not found".to_string()));
+    }
+    let seed: DevSeed = serde_json::from_str(DEV_SEED_JSON).map_err(internal_error!())?;
+    let user = &seed.user;
+    let db = db::get_db(&env)?;
+    let now = time::now_bw();
+
+    let existing: Option<Value> = db
+        .first(
+            "SELECT id FROM users WHERE email = ?1",
+            &[user.email.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    let user_created = match existing {
+        Some(row) if row["id"].as_str() == Some(user.id.as_str()) => false,
+        Some(_) => {
+            return Err(AppError::Conflict(format!(
+                "{} is already registered as a different account; delete it to seed",
+                user.email
+            )))
+        }
+        None => {
+            let password_salt = generate_salt()?;
+            let password_iterations = settings.password_iterations;
+            let master_password_hash = hash_password_for_storage(
+                &user.master_password_hash,
+                &password_salt,
+                password_iterations,
+            )
+            .await?;
+            db.run(
+                "INSERT INTO users (id, name, email, email_verified, master_password_hash, password_salt, password_iterations, key, private_key, public_key, kdf_type, kdf_iterations, security_stamp, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, 1, ?4, ?5, ?6, ?7, ?8, ?9, 0, ?10, ?11, ?12, ?12)
+                 ON CONFLICT DO NOTHING",
+                &[
+                    user.id.as_str().into(),
+                    user.name.as_str().into(),
+                    user.email.as_str().into(),
+                    master_password_hash.into(),
+                    password_salt.into(),
+                    password_iterations.into(),
+                    user.key.as_str().into(),
+                    user.private_key.as_str().into(),
+                    user.public_key.as_str().into(),
+                    user.kdf_iterations.into(),
+                    Uuid::new_v4().to_string().into(),
+                    now.as_str().into(),
+                ],
+            )
+            .await
+            .map_err(db_error!())?
+                > 0
+        }
+    };
+
+    let mut statements = Vec::new();
+    for folder in &seed.folders {
+        statements.push(
+            Database::prepare(
+                &db,
+                "INSERT INTO folders (id, user_id, name, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?4)
+                 ON CONFLICT DO NOTHING",
+                &[
+                    folder.id.as_str().into(),
+                    user.id.as_str().into(),
+                    folder.name.as_str().into(),
+                    now.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    for cipher in &seed.ciphers {
+        statements.push(
+            Database::prepare(
+                &db,
+                "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, deleted_at, created_at, updated_at)
+                 VALUES (?1, ?2, NULL, ?3, ?4, ?5, ?6, ?7, ?8, ?8)
+                 ON CONFLICT DO NOTHING",
+                &[
+                    cipher.id.as_str().into(),
+                    user.id.as_str().into(),
+                    cipher.r#type.into(),
+                    cipher.data.to_string().into(),
+                    cipher.favorite.into(),
+                    cipher.folder_id.as_deref().into(),
+                    cipher.deleted.then(|| now.clone()).into(),
+                    now.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    statements.push(quota::recount(&db, &[user.id.as_str()])?);
+    let changes = Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+    let (folder_changes, cipher_changes) = changes.split_at(seed.folders.len());
+    let folders_created: usize = folder_changes.iter().sum();
+    let ciphers_created: usize = cipher_changes[..seed.ciphers.len()].iter().sum();
+    if folders_created + ciphers_created > 0 {
+        db::touch_user_updated_at(&db, &user.id).await?;
+    }
+
+    Ok(Json(json!({
+        "email": user.email,
+        "masterPassword": user.password,
+        "kdf": 0,
+        "kdfIterations": user.kdf_iterations,
+        "userId": user.id,
+        "created": {
+            "user": user_created,
+            "folders": folders_created,
+            "ciphers": ciphers_created,
+        },
+    })))
+}
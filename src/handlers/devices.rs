@@ -1,104 +1,732 @@
-use axum::{extract::Path, Json};
+use axum::{extract::State, http::HeaderMap, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL, Engine};
 use serde::Deserialize;
 use serde_json::{json, Value};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
-use crate::error::AppError;
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
+use crate::{
+    auth::{revocation, Claims},
+    db::{self, Database, Db},
+    error::{db_error, AppError},
+    models::{
+        device::{Device, DeviceKeysRequest, WebPushAuthRequest},
+        response::ListResponse,
+    },
+    push, time,
+};
 
-/// GET /devices
+fn device_not_found() -> AppError {
+    AppError::NotFound("No device found".to_string())
+}
+
+/// Looks up a device of `user_id` by its client-generated identifier.
+pub(crate) async fn find_device(
+    db: &Db,
+    user_id: &str,
+    identifier: &str,
+) -> Result<Option<Device>, AppError> {
+    db.first(
+        "SELECT * FROM devices WHERE user_id = ?1 AND identifier = ?2",
+        &[user_id.into(), identifier.into()],
+    )
+    .await
+    .map_err(db_error!())
+}
+
+/// Records a successful login from a device, creating it on first use.
+pub(crate) async fn register_device(
+    db: &Db,
+    user_id: &str,
+    identifier: &str,
+    name: &str,
+    atype: i32,
+) -> Result<Device, AppError> {
+    let now = time::now_bw();
+    db.run(
+        "INSERT INTO devices (id, user_id, identifier, name, atype, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+         ON CONFLICT(user_id, identifier) DO UPDATE SET name = ?4, atype = ?5, updated_at = ?6",
+        &[
+            Uuid::new_v4().to_string().into(),
+            user_id.into(),
+            identifier.into(),
+            name.into(),
+            atype.into(),
+            now.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    find_device(db, user_id, identifier)
+        .await?
+        .ok_or_else(|| AppError::Database(Some("saved device was not found".to_string())))
+}
+
+/// Starts a new session for a device, invalidating refresh tokens issued to it earlier.
+/// Returns the id to embed in the new refresh token.
+pub(crate) async fn start_device_session(db: &Db, device: &Device) -> Result<String, AppError> {
+    let session_id = Uuid::new_v4().to_string();
+    let now = time::now_bw();
+    db.run(
+        "UPDATE devices SET refresh_token_id = ?1, last_active_at = ?2 WHERE id = ?3",
+        &[
+            session_id.as_str().into(),
+            now.as_str().into(),
+            device.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+    Ok(session_id)
+}
+
+/// Records use of a device's refresh token.
+/// Returns false if the session was revoked (or replaced by a newer login).
+pub(crate) async fn touch_device_session(
+    db: &Db,
+    user_id: &str,
+    identifier: &str,
+    session_id: &str,
+) -> Result<bool, AppError> {
+    let now = time::now_bw();
+    let changes = db
+        .run(
+            "UPDATE devices SET last_active_at = ?1
+         WHERE user_id = ?2 AND identifier = ?3 AND refresh_token_id = ?4",
+            &[
+                now.as_str().into(),
+                user_id.into(),
+                identifier.into(),
+                session_id.into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(changes > 0)
+}
+
+async fn find_device_by_id(db: &Db, user_id: &str, id: &str) -> Result<Device, AppError> {
+    db.first(
+        "SELECT * FROM devices WHERE id = ?1 AND user_id = ?2",
+        &[id.into(), user_id.into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(device_not_found)
+}
+
+/// GET /api/devices
 ///
-/// Returns an empty list of devices.
-/// Device tracking is not implemented in this minimal Bitwarden-compatible server.
-/// This does not affect authentication since we use stateless JWT tokens.
-/// Clients will show "No devices currently logged in" in the device management settings.
+/// Lists all devices that have logged in to the account.
+#[utoipa::path(
+    get,
+    path = "/api/devices",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
-pub async fn get_devices() -> Json<Value> {
-    Json(json!({
-        "data": [],
-        "continuationToken": null,
-        "object": "list"
-    }))
+pub async fn get_devices(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    let devices: Vec<Device> = db
+        .all(
+            "SELECT * FROM devices WHERE user_id = ?1 ORDER BY updated_at DESC",
+            &[claims.sub.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    let data: Vec<Value> = devices.iter().map(Device::to_json).collect();
+
+    Ok(Json(ListResponse::new(data)))
 }
 
-/// GET /devices/knowndevice
+/// GET /api/devices/knowndevice
 ///
-/// Checks if a device is known to the server.
-/// Always returns false since we don't track devices.
-/// This is used by clients to determine if a device has been previously used.
-/// X-Request-Email and X-Device-Identifier headers are expected but we ignore them.
+/// Checks if a device has previously logged in to the account.
+/// Expects `X-Request-Email` (base64url encoded) and `X-Device-Identifier` headers.
+#[utoipa::path(
+    get,
+    path = "/api/devices/knowndevice",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = bool))
+)]
 #[worker::send]
-pub async fn get_known_device() -> Json<bool> {
-    // Always return false - we don't track devices
-    Json(false)
+pub async fn get_known_device(
+    State(env): State<Arc<Env>>,
+    headers: HeaderMap,
+) -> Result<Json<bool>, AppError> {
+    let email = headers
+        .get("X-Request-Email")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| BASE64URL.decode(v.trim_end_matches('=')).ok())
+        .and_then(|bytes| String::from_utf8(bytes).ok());
+    let identifier = headers
+        .get("X-Device-Identifier")
+        .and_then(|v| v.to_str().ok());
+
+    let (Some(email), Some(identifier)) = (email, identifier) else {
+        return Ok(Json(false));
+    };
+
+    let db = db::get_db(&env)?;
+    let known: Option<String> = db
+        .first_column(
+            "SELECT d.id FROM devices d JOIN users u ON u.id = d.user_id
+             WHERE u.email = ?1 AND d.identifier = ?2",
+            &[email.to_lowercase().into(), identifier.into()],
+            "id",
+        )
+        .await
+        .map_err(db_error!())?;
+
+    Ok(Json(known.is_some()))
+}
+
+/// GET /api/devices/identifier/{device_id}
+#[utoipa::path(
+    get,
+    path = "/api/devices/identifier/{device_id}",
+    tag = "devices",
+    params(("device_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_device(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(device_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let device = find_device(&db, &claims.sub, &device_id)
+        .await?
+        .ok_or_else(device_not_found)?;
+
+    Ok(Json(device.to_json()))
 }
 
-/// GET /devices/identifier/{device_id}
+/// PUT /api/devices/{identifier}/keys
 ///
-/// Returns information about a specific device.
-/// Since we don't track devices, we return a 404-like error.
-/// However, to avoid client errors, we return an empty device stub.
+/// Stores trusted device encryption keys ("remember this device").
+#[utoipa::path(
+    method(put, post),
+    path = "/api/devices/{id}/keys",
+    tag = "devices",
+    params(("id" = String, Path)),
+    request_body = DeviceKeysRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
-pub async fn get_device(Path(device_id): Path<String>) -> Result<Json<Value>, AppError> {
-    // Return a minimal device stub to prevent client errors
-    // The client may request device info after login
-    Ok(Json(json!({
-        "id": device_id,
-        "name": "Unknown Device",
-        "type": 0,
-        "identifier": device_id,
-        "creationDate": "2000-01-01T00:00:00.000Z",
-        "object": "device"
-    })))
-}
-
-#[derive(Deserialize)]
+pub async fn put_device_keys(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(identifier): AppPath<String>,
+    AppJson(payload): AppJson<DeviceKeysRequest>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut device = find_device(&db, &claims.sub, &identifier)
+        .await?
+        .ok_or_else(device_not_found)?;
+
+    let now = time::now_bw();
+    db.run(
+        "UPDATE devices SET encrypted_user_key = ?1, encrypted_public_key = ?2, encrypted_private_key = ?3, updated_at = ?4
+         WHERE id = ?5",
+        &[
+            payload.encrypted_user_key.as_str().into(),
+            payload.encrypted_public_key.as_str().into(),
+            payload.encrypted_private_key.as_str().into(),
+            now.as_str().into(),
+            device.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    device.encrypted_user_key = Some(payload.encrypted_user_key);
+    device.encrypted_public_key = Some(payload.encrypted_public_key);
+    device.encrypted_private_key = Some(payload.encrypted_private_key);
+    device.updated_at = now;
+
+    Ok(Json(device.to_json()))
+}
+
+/// POST /api/devices/{identifier}/retrieve-keys
+///
+/// Returns the stored trusted device keys so the device can unlock without the master password.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/retrieve-keys",
+    tag = "devices",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_retrieve_device_keys(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(identifier): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let device = find_device(&db, &claims.sub, &identifier)
+        .await?
+        .filter(Device::is_trusted)
+        .ok_or_else(device_not_found)?;
+
+    Ok(Json(device.to_protected_json()))
+}
+
+/// DELETE /api/devices/{id}/sessions
+///
+/// Signs a single device out: its refresh token stops working and access tokens already issued
+/// to it are denied. The device record (and any trusted device keys) is kept.
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}/sessions",
+    tag = "devices",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn delete_device_sessions(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let device = find_device_by_id(&db, &claims.sub, &id).await?;
+
+    db.run(
+        "UPDATE devices SET refresh_token_id = NULL WHERE id = ?1",
+        &[device.id.as_str().into()],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    revocation::revoke_device_tokens(&env, &claims.sub, &device.identifier).await;
+
+    Ok(Json(json!({})))
+}
+
+/// DELETE /api/devices/{id}
+/// POST /api/devices/{id}/deactivate
+///
+/// Removes a device, revoking its session.
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn delete_device(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let device = find_device_by_id(&db, &claims.sub, &id).await?;
+
+    db.run(
+        "DELETE FROM devices WHERE id = ?1",
+        &[device.id.as_str().into()],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    revocation::revoke_device_tokens(&env, &claims.sub, &device.identifier).await;
+    if let Some(push_uuid) = device.push_uuid {
+        push::unregister_push_device(&env, &push_uuid).await;
+    }
+
+    Ok(Json(json!({})))
+}
+
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PushToken {
-    #[allow(dead_code)]
     push_token: String,
 }
 
+/// Stores the device's push token and registers it with the push relay (when configured).
+async fn set_push_token(
+    env: &Arc<Env>,
+    user_id: &str,
+    identifier: &str,
+    push_token: String,
+) -> Result<(), AppError> {
+    let db = db::get_db(env)?;
+    let mut device = find_device(&db, user_id, identifier)
+        .await?
+        .ok_or_else(device_not_found)?;
+
+    if device.push_token.as_deref() == Some(push_token.as_str()) {
+        return Ok(());
+    }
+
+    let push_uuid = device
+        .push_uuid
+        .clone()
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    db.run(
+        "UPDATE devices SET push_uuid = ?1, push_token = ?2 WHERE id = ?3",
+        &[
+            push_uuid.as_str().into(),
+            push_token.as_str().into(),
+            device.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    device.push_uuid = Some(push_uuid);
+    device.push_token = Some(push_token);
+    push::register_push_device(env, &device).await;
+    Ok(())
+}
+
+/// Clears the device's push token and removes it from the push relay.
+async fn clear_push_token(env: &Arc<Env>, user_id: &str, identifier: &str) -> Result<(), AppError> {
+    let db = db::get_db(env)?;
+    let Some(device) = find_device(&db, user_id, identifier).await? else {
+        return Ok(());
+    };
+
+    db.run(
+        "UPDATE devices SET push_token = NULL WHERE id = ?1",
+        &[device.id.as_str().into()],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    if let Some(push_uuid) = device.push_uuid {
+        push::unregister_push_device(env, &push_uuid).await;
+    }
+    Ok(())
+}
+
 /// POST /devices/identifier/{device_id}/token
 ///
 /// Registers a push token for a device.
-/// We accept but ignore this since push notifications are not implemented.
+#[utoipa::path(
+    post,
+    path = "/api/devices/identifier/{device_id}/token",
+    tag = "devices",
+    params(("device_id" = String, Path)),
+    request_body = PushToken,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn post_device_token(
-    Path(_device_id): Path<String>,
-    Json(_data): Json<PushToken>,
-) -> Json<Value> {
-    // Accept but ignore - push notifications not implemented
-    Json(json!({}))
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(device_id): AppPath<String>,
+    AppJson(data): AppJson<PushToken>,
+) -> Result<Json<Value>, AppError> {
+    set_push_token(&env, &claims.sub, &device_id, data.push_token).await?;
+    Ok(Json(json!({})))
 }
 
 /// PUT /devices/identifier/{device_id}/token
 ///
 /// Updates a push token for a device.
-/// We accept but ignore this since push notifications are not implemented.
+#[utoipa::path(
+    put,
+    path = "/api/devices/identifier/{device_id}/token",
+    tag = "devices",
+    params(("device_id" = String, Path)),
+    request_body = PushToken,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn put_device_token(
-    Path(_device_id): Path<String>,
-    Json(_data): Json<PushToken>,
-) -> Json<Value> {
-    // Accept but ignore - push notifications not implemented
-    Json(json!({}))
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(device_id): AppPath<String>,
+    AppJson(data): AppJson<PushToken>,
+) -> Result<Json<Value>, AppError> {
+    set_push_token(&env, &claims.sub, &device_id, data.push_token).await?;
+    Ok(Json(json!({})))
 }
 
 /// PUT /devices/identifier/{device_id}/clear-token
 ///
 /// Clears the push token for a device.
-/// We accept but ignore this since push notifications are not implemented.
+#[utoipa::path(
+    put,
+    path = "/api/devices/identifier/{device_id}/clear-token",
+    tag = "devices",
+    params(("device_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
-pub async fn put_clear_device_token(Path(_device_id): Path<String>) -> Json<Value> {
-    // Accept but ignore - push notifications not implemented
-    Json(json!({}))
+pub async fn put_clear_device_token(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(device_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    clear_push_token(&env, &claims.sub, &device_id).await?;
+    Ok(Json(json!({})))
 }
 
 /// POST /devices/identifier/{device_id}/clear-token
 ///
 /// Clears the push token for a device.
-/// We accept but ignore this since push notifications are not implemented.
+#[utoipa::path(
+    post,
+    path = "/api/devices/identifier/{device_id}/clear-token",
+    tag = "devices",
+    params(("device_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_clear_device_token(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(device_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    clear_push_token(&env, &claims.sub, &device_id).await?;
+    Ok(Json(json!({})))
+}
+
+/// Stores a browser's Web Push subscription on the device.
+async fn set_web_push_subscription(
+    env: &Arc<Env>,
+    user_id: &str,
+    identifier: &str,
+    data: WebPushAuthRequest,
+) -> Result<(), AppError> {
+    if push::web_push_public_key(env).is_none() {
+        return Err(AppError::BadRequest(
+            "Web Push is not supported by this server".to_string(),
+        ));
+    }
+    let (endpoint, p256dh, auth) = data
+        .into_parts()
+        .ok_or_else(|| AppError::BadRequest("Missing Web Push subscription keys".to_string()))?;
+    if !endpoint.starts_with("https://") {
+        return Err(AppError::BadRequest(
+            "Invalid Web Push endpoint".to_string(),
+        ));
+    }
+
+    let db = db::get_db(env)?;
+    let device = find_device(&db, user_id, identifier)
+        .await?
+        .ok_or_else(device_not_found)?;
+
+    db.run(
+        "UPDATE devices SET web_push_endpoint = ?1, web_push_p256dh = ?2, web_push_auth = ?3 WHERE id = ?4",
+        &[
+            endpoint.as_str().into(),
+            p256dh.as_str().into(),
+            auth.as_str().into(),
+            device.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    Ok(())
+}
+
+/// PUT /devices/identifier/{device_id}/web-push-auth
+///
+/// Registers the Web Push subscription of a browser client.
+#[utoipa::path(
+    method(put, post),
+    path = "/api/devices/identifier/{device_id}/web-push-auth",
+    tag = "devices",
+    params(("device_id" = String, Path)),
+    request_body = WebPushAuthRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
-pub async fn post_clear_device_token(Path(_device_id): Path<String>) -> Json<Value> {
-    // Accept but ignore - push notifications not implemented
-    Json(json!({}))
+pub async fn put_device_web_push_auth(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(device_id): AppPath<String>,
+    AppJson(data): AppJson<WebPushAuthRequest>,
+) -> Result<Json<Value>, AppError> {
+    set_web_push_subscription(&env, &claims.sub, &device_id, data).await?;
+    Ok(Json(json!({})))
+}
+
+/// POST /web-push/register
+///
+/// Same as `web-push-auth`, for the device the access token was issued to.
+#[utoipa::path(
+    post,
+    path = "/api/web-push/register",
+    tag = "devices",
+    request_body = WebPushAuthRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_web_push_register(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppJson(data): AppJson<WebPushAuthRequest>,
+) -> Result<Json<Value>, AppError> {
+    let device_id = claims
+        .device
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("Token is not bound to a device".to_string()))?;
+    set_web_push_subscription(&env, &claims.sub, device_id, data).await?;
+    Ok(Json(json!({})))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing::{self, PASSWORD_HASH};
+    use crate::native::Env;
+    use axum::http::{Method, StatusCode};
+
+    const EMAIL: &str = "alice@example.com";
+    const LAPTOP: &str = "8f1c0e52-3f4d-4c35-9d7e-8f0b6f7a2c11";
+    const PHONE: &str = "1d7e4b90-6a2c-4f83-b5e1-0c9f3a8d2e67";
+
+    /// A migrated env where alice is registered.
+    fn env() -> Env {
+        let env = testing::env();
+        testing::register(&env, EMAIL);
+        env
+    }
+
+    /// Logs alice in from `device` and returns the token response.
+    fn login(env: &Env, device: &str) -> Value {
+        let body = format!(
+            "grant_type=password&username={EMAIL}&password={PASSWORD_HASH}&scope=api%20offline_access\
+             &client_id=web&deviceType=9&deviceIdentifier={device}&deviceName=firefox"
+        );
+        let (status, body) = testing::post_form(env, "/identity/connect/token", &body);
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body
+    }
+
+    /// Refreshes `session`; the OAuth error is returned when that is refused.
+    fn refresh(env: &Env, session: &Value) -> Result<(), String> {
+        let body = format!(
+            "grant_type=refresh_token&client_id=web&refresh_token={}",
+            session["refresh_token"].as_str().unwrap()
+        );
+        let (status, body) = testing::post_form(env, "/identity/connect/token", &body);
+        match status {
+            StatusCode::OK => Ok(()),
+            _ => Err(body["error"].as_str().unwrap_or_default().to_string()),
+        }
+    }
+
+    /// alice's devices, by identifier, as `GET /api/devices` lists them.
+    fn devices(env: &Env, session: &Value) -> Vec<Value> {
+        let (status, body) = testing::request(
+            env,
+            Method::GET,
+            "/api/devices",
+            session["access_token"].as_str(),
+            None,
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let mut devices = body["data"].as_array().unwrap().clone();
+        devices.sort_by_key(|device| device["identifier"].as_str().unwrap().to_string());
+        devices
+    }
+
+    fn revoke(env: &Env, session: &Value, device: &str) -> StatusCode {
+        let id = devices(env, session)
+            .into_iter()
+            .find(|listed| listed["identifier"] == device)
+            .unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        testing::request(
+            env,
+            Method::DELETE,
+            &format!("/api/devices/{id}/sessions"),
+            session["access_token"].as_str(),
+            None,
+        )
+        .0
+    }
+
+    #[test]
+    fn revoking_a_device_ends_only_its_session() {
+        let env = env();
+        let laptop = login(&env, LAPTOP);
+        let phone = login(&env, PHONE);
+
+        assert_eq!(revoke(&env, &phone, LAPTOP), StatusCode::OK);
+
+        assert_eq!(refresh(&env, &laptop), Err("invalid_grant".to_string()));
+        assert_eq!(refresh(&env, &phone), Ok(()));
+        // Logging in again starts a new session
+        let laptop = login(&env, LAPTOP);
+        assert_eq!(refresh(&env, &laptop), Ok(()));
+    }
+
+    #[test]
+    fn the_device_list_shows_which_sessions_are_active() {
+        let env = env();
+        login(&env, LAPTOP);
+        let phone = login(&env, PHONE);
+
+        revoke(&env, &phone, LAPTOP);
+
+        let listed = devices(&env, &phone);
+        let active: Vec<(&str, bool)> = listed
+            .iter()
+            .map(|device| {
+                (
+                    device["identifier"].as_str().unwrap(),
+                    device["isActive"].as_bool().unwrap(),
+                )
+            })
+            .collect();
+        let mut expected = [(LAPTOP, false), (PHONE, true)];
+        expected.sort_unstable();
+        assert_eq!(active, expected);
+        assert!(listed
+            .iter()
+            .all(|device| device["lastActivityDate"].is_string()));
+    }
+
+    #[test]
+    fn revoking_an_unknown_device_is_not_found() {
+        let env = env();
+        let session = login(&env, LAPTOP);
+
+        let status = testing::request(
+            &env,
+            Method::DELETE,
+            "/api/devices/not-a-device/sessions",
+            session["access_token"].as_str(),
+            None,
+        )
+        .0;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(refresh(&env, &session), Ok(()));
+    }
 }
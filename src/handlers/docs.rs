@@ -0,0 +1,428 @@
+//! API documentation: the OpenAPI document of the implemented routes, and an optional Swagger UI
+//! to browse it.
+//!
+//! The document is derived from the `#[utoipa::path]` attribute of each handler and the
+//! `ToSchema` derives of the bodies they take and return; a handler only appears once it is
+//! listed in [`ApiDoc`]. Routes that mount a handler a second time (the compatibility paths and
+//! the `POST .../delete` forms) aren't listed separately.
+
+use axum::{
+    extract::State,
+    response::{Html, IntoResponse, Response},
+};
+use std::collections::HashSet;
+use std::sync::Arc;
+use utoipa::{
+    openapi::{
+        path::Operation,
+        security::{Http, HttpAuthScheme, SecurityScheme},
+        Server,
+    },
+    Modify, OpenApi,
+};
+
+use crate::config::Settings;
+use crate::error::internal_error;
+use crate::handlers::{
+    account_recovery, accounts, admin, attachments, auth_requests, backup, billing_stubs, ciphers,
+    collections, config, dev, devices, domains, emergency_access, events, folders, groups, icons,
+    identity, import, invitations, meta, notifications, organizations, policies, sends, stubs,
+    sync, twofactor, vaultwarden, webauth,
+};
+use crate::Env;
+use crate::{error::AppError, handlers::ciphers::RawJson};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Warden",
+        description = "The subset of the Bitwarden API this server implements.",
+        license(name = "MIT")
+    ),
+    paths(
+        accounts::prelogin, accounts::register, accounts::send_verification_email,
+        accounts::revision_date, accounts::password_hint, accounts::get_tasks,
+        accounts::get_profile, accounts::post_profile, accounts::put_profile,
+        accounts::put_avatar, accounts::get_verify_devices, accounts::put_verify_devices,
+        accounts::delete_account, accounts::post_kdf, accounts::post_password,
+        accounts::put_update_temp_password, accounts::post_rotatekey,
+        accounts::get_user_public_key,
+        identity::token,
+        ciphers::update_cipher, ciphers::list_ciphers, ciphers::create_cipher_simple,
+        ciphers::create_cipher, ciphers::list_organization_ciphers, ciphers::get_cipher,
+        ciphers::get_cipher_details, ciphers::soft_delete_cipher,
+        ciphers::hard_delete_cipher, ciphers::update_cipher_partial,
+        ciphers::soft_delete_ciphers_bulk, ciphers::hard_delete_ciphers_bulk,
+        ciphers::restore_cipher, ciphers::create_cipher_admin, ciphers::get_cipher_admin,
+        ciphers::update_cipher_admin, ciphers::hard_delete_cipher_admin,
+        ciphers::soft_delete_cipher_admin, ciphers::restore_cipher_admin,
+        ciphers::restore_ciphers_bulk, ciphers::move_cipher_selected, ciphers::purge_vault,
+        folders::update_folder, folders::list_folders, folders::create_folder,
+        folders::get_folder, folders::delete_folder,
+        sync::get_sync_data,
+        auth_requests::get_auth_requests, auth_requests::create_auth_request,
+        auth_requests::get_auth_requests_pending, auth_requests::get_auth_request,
+        auth_requests::put_auth_request, auth_requests::get_auth_request_response,
+        import::import_data, import::start_import_session, import::post_import_chunk,
+        import::commit_import_session, import::get_import_session,
+        import::import_organization_data, import::import_organization,
+        attachments::create_attachment_v2, attachments::upload_attachment_legacy,
+        attachments::upload_attachment_v2_data, attachments::get_attachment,
+        attachments::delete_attachment, attachments::delete_attachment_post,
+        sends::get_sends, sends::post_send, sends::get_send, sends::put_send,
+        sends::delete_send, sends::put_remove_password, sends::post_send_file_v2,
+        sends::post_send_file, sends::post_access, sends::post_access_file,
+        config::config,
+        icons::get_icon,
+        meta::alive, meta::now, meta::version, meta::hibp_breach,
+        get_openapi, get_docs_ui,
+        billing_stubs::get_plans, billing_stubs::get_sales_tax_rates,
+        billing_stubs::get_account_tax, billing_stubs::get_organization_tax,
+        billing_stubs::get_billing, billing_stubs::get_billing_metadata,
+        billing_stubs::get_billing_status, billing_stubs::get_subscription,
+        stubs::get_sso_user_identifier, stubs::get_notifications, stubs::get_security_tasks,
+        stubs::get_organization_auth_requests,
+        domains::get_domains, domains::post_domains, domains::put_domains,
+        emergency_access::get_trusted_contacts, emergency_access::get_granted_access,
+        emergency_access::post_invite, emergency_access::get_emergency_access,
+        emergency_access::put_emergency_access, emergency_access::delete_emergency_access,
+        emergency_access::post_reinvite, emergency_access::post_accept,
+        emergency_access::post_confirm, emergency_access::post_initiate,
+        emergency_access::post_approve, emergency_access::post_reject,
+        emergency_access::post_view, emergency_access::post_takeover,
+        emergency_access::get_policies, emergency_access::post_password,
+        devices::get_devices, devices::get_known_device, devices::get_device,
+        devices::delete_device, devices::delete_device_sessions, devices::put_device_keys,
+        devices::post_retrieve_device_keys, devices::post_device_token,
+        devices::put_device_token, devices::put_clear_device_token,
+        devices::post_clear_device_token, devices::put_device_web_push_auth,
+        devices::post_web_push_register,
+        webauth::get_webauthn_credentials,
+        twofactor::get_twofactor, twofactor::get_authenticator,
+        twofactor::activate_authenticator, twofactor::activate_authenticator_put,
+        twofactor::disable_authenticator, twofactor::disable_twofactor,
+        twofactor::disable_twofactor_put, twofactor::get_recover, twofactor::recover,
+        organizations::get_organizations, organizations::post_organization,
+        organizations::get_organization, organizations::put_organization,
+        organizations::delete_organization, organizations::post_leave,
+        organizations::get_organization_keys, organizations::post_organization_keys,
+        organizations::get_members, organizations::post_invite, organizations::get_member,
+        organizations::put_member, organizations::delete_member,
+        organizations::post_accept_invite, organizations::post_confirm_member,
+        account_recovery::get_organization_public_key,
+        account_recovery::put_reset_password_enrollment,
+        account_recovery::get_reset_password_details,
+        account_recovery::put_admin_reset_password,
+        policies::get_policies, policies::get_policy, policies::put_policy,
+        groups::get_groups, groups::post_group, groups::get_groups_details,
+        groups::get_group, groups::put_group, groups::delete_group,
+        groups::get_group_details, groups::get_group_users, groups::put_group_users,
+        groups::get_member_groups, groups::put_member_groups,
+        events::get_account_events, events::get_org_events, events::get_member_events,
+        collections::get_user_collections, collections::get_org_collections,
+        collections::post_collection, collections::get_org_collections_details,
+        collections::get_collection_details, collections::put_collection,
+        collections::delete_collection,
+        notifications::get_hub, notifications::post_negotiate,
+        dev::post_seed,
+        backup::get_backup, backup::post_restore,
+        admin::get_diagnostics, admin::post_mail_test, admin::post_maintenance,
+        admin::post_migrations, admin::post_orphans, admin::post_purge_user_vault,
+        invitations::post_invite, invitations::get_invites, invitations::delete_invite,
+        vaultwarden::post_migrate_vaultwarden,
+    ),
+    modifiers(&SecuritySchemes, &Operations)
+)]
+pub struct ApiDoc;
+
+/// `bearerAuth` (access tokens) and `adminToken` (ADMIN_TOKEN), as the handlers' `security`
+/// refers to them.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearerAuth",
+            SecurityScheme::Http(
+                Http::builder()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some("Access token from /identity/connect/token"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "adminToken",
+            SecurityScheme::Http(
+                Http::builder()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("The ADMIN_TOKEN secret"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Tidies what the handlers' attributes and doc comments give each operation.
+///
+/// Operation ids, the handlers' names, get their module in front: several modules have a
+/// `post_invite` or a `get_policies`. A handler serving more than one method of its path also
+/// gets the method appended for all but the first.
+struct Operations;
+
+impl Modify for Operations {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let mut seen = HashSet::new();
+        for item in openapi.paths.paths.values_mut() {
+            let operations = [
+                ("get", &mut item.get),
+                ("put", &mut item.put),
+                ("post", &mut item.post),
+                ("delete", &mut item.delete),
+                ("patch", &mut item.patch),
+            ];
+            for (method, operation) in operations {
+                let Some(operation) = operation else {
+                    continue;
+                };
+                let module = operation.tags.as_ref().and_then(|tags| tags.first());
+                if let (Some(module), Some(id)) = (module, operation.operation_id.as_mut()) {
+                    *id = format!("{module}_{id}");
+                    if !seen.insert(id.clone()) {
+                        id.push_str(&format!("_{method}"));
+                    }
+                }
+                describe(operation);
+            }
+        }
+    }
+}
+
+/// Handler docs usually open with their route ("GET /api/ciphers - what it does"), which the
+/// document already says: the rest of that line, or else the next paragraph, is the summary.
+/// Rustdoc links lose their brackets.
+fn describe(operation: &mut Operation) {
+    let unlink = |text: &str| text.replace("[`", "`").replace("`]", "`");
+    let mut summary = operation.summary.take().map(|summary| unlink(&summary));
+    let mut description = operation.description.take().map(|text| unlink(&text));
+
+    let is_route = summary
+        .as_deref()
+        .and_then(|summary| summary.split_once(' '))
+        .is_some_and(|(method, path)| {
+            method.chars().all(|c| c.is_ascii_uppercase() || c == '/') && path.starts_with('/')
+        });
+    if is_route {
+        let route = summary.take().unwrap_or_default();
+        summary = match route.split_once(" - ") {
+            Some((_, rest)) => Some(rest.to_string()),
+            None => match description.take() {
+                Some(text) => match text.split_once("\n\n") {
+                    Some((first, rest)) => {
+                        description = Some(rest.to_string());
+                        Some(first.to_string())
+                    }
+                    None => Some(text),
+                },
+                None => None,
+            },
+        };
+    }
+    operation.summary = summary.map(|summary| summary.replace('\n', " "));
+    operation.description = description;
+}
+
+/// Swagger UI loading the document from `{openapi_url}`. Its assets come from a CDN, pinned to one
+/// release and checked against their hashes.
+const SWAGGER_UI_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>Warden API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui.css"
+    integrity="sha384-wxLW6kwyHktdDGr6Pv1zgm/VGJh99lfUbzSn6HNHBENZlCN7W602k9VkGdxuFvPn"
+    crossorigin="anonymous">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui-bundle.js"
+    integrity="sha384-wmyclcVGX/WhUkdkATwhaK1X1JtiNrr2EoYJ+diV3vj4v6OC5yCeSu+yW13SYJep"
+    crossorigin="anonymous"></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: {openapi_url}, dom_id: "#swagger-ui" });
+    };
+  </script>
+</body>
+</html>
+"##;
+
+/// The document, with BASE_PATH as its server so that requests tried from it reach the routes.
+fn openapi_json(base_path: &str) -> Result<String, AppError> {
+    let mut openapi = ApiDoc::openapi();
+    if !base_path.is_empty() {
+        openapi.servers = Some(vec![Server::new(base_path)]);
+    }
+    openapi
+        .to_json()
+        .map_err(internal_error!("serialize the OpenAPI document"))
+}
+
+/// The Swagger UI page for a deployment mounted at `base_path`.
+fn swagger_ui_html(base_path: &str) -> String {
+    let url = format!("{base_path}/api/docs/openapi.json");
+    SWAGGER_UI_HTML.replace("{openapi_url}", &serde_json::Value::String(url).to_string())
+}
+
+/// GET /api/docs/openapi.json
+#[utoipa::path(
+    get,
+    path = "/api/docs/openapi.json",
+    tag = "docs",
+    responses((status = 200, description = "Success", content_type = "application/json"))
+)]
+#[worker::send]
+pub async fn get_openapi(State(env): State<Arc<Env>>) -> Result<RawJson, AppError> {
+    openapi_json(&Settings::get(&env).base_path).map(RawJson)
+}
+
+/// GET /api/docs
+///
+/// Swagger UI for the OpenAPI document, when enabled.
+#[utoipa::path(
+    get,
+    path = "/api/docs",
+    tag = "docs",
+    responses((status = 200, description = "Success", content_type = "text/html"))
+)]
+#[worker::send]
+pub async fn get_docs_ui(State(env): State<Arc<Env>>) -> Result<Response, AppError> {
+    let settings = Settings::get(&env);
+    if !settings.api_docs_ui {
+        return Err(AppError::NotFound("Not found".to_string()));
+    }
+    Ok(Html(swagger_ui_html(&settings.base_path)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing::{self, ORIGIN};
+    use axum::body::Body;
+    use axum::http::{header::ALLOW, Method, Request};
+    use serde_json::Value;
+
+    fn document() -> Value {
+        serde_json::from_str(&openapi_json("").unwrap()).unwrap()
+    }
+
+    fn operations(document: &Value) -> Vec<(String, String, Value)> {
+        let mut operations = Vec::new();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                operations.push((path.clone(), method.clone(), operation.clone()));
+            }
+        }
+        operations
+    }
+
+    #[test]
+    fn every_documented_operation_is_routed() {
+        let env = testing::env();
+        let document = document();
+        for (path, method, _) in operations(&document) {
+            // The router answers OPTIONS on a known path with the methods it has for it, before
+            // any handler runs
+            let uri = path.replace(['{', '}'], "");
+            let req = Request::builder()
+                .method(Method::OPTIONS)
+                .uri(format!("{ORIGIN}{uri}"))
+                .body(Body::empty())
+                .unwrap();
+            let response = testing::fetch(&env, req);
+            let allow = response
+                .headers()
+                .get(ALLOW)
+                .and_then(|allow| allow.to_str().ok())
+                .unwrap_or_default();
+            assert!(
+                allow
+                    .split(',')
+                    .any(|allowed| allowed.eq_ignore_ascii_case(&method)),
+                "{method} {path} is documented but not routed (allowed: {allow:?})"
+            );
+        }
+    }
+
+    #[test]
+    fn operation_ids_are_unique_and_carry_the_module() {
+        let mut seen = HashSet::new();
+        for (path, method, operation) in operations(&document()) {
+            let id = operation["operationId"].as_str().unwrap().to_string();
+            let tag = operation["tags"][0].as_str().unwrap();
+            assert!(id.starts_with(&format!("{tag}_")), "{method} {path}: {id}");
+            assert!(seen.insert(id.clone()), "{id} is used twice");
+        }
+        assert!(seen.contains("ciphers_update_cipher"));
+        assert!(seen.contains("ciphers_update_cipher_post"));
+    }
+
+    #[test]
+    fn bodies_and_auth_come_from_the_handlers() {
+        let document = document();
+        let token = &document["paths"]["/identity/connect/token"]["post"];
+        assert!(
+            token["requestBody"]["content"]["application/x-www-form-urlencoded"]["schema"]["$ref"]
+                .as_str()
+                .unwrap()
+                .ends_with("/TokenRequest")
+        );
+        assert!(token.get("security").is_none());
+
+        let create = &document["paths"]["/api/ciphers"]["post"];
+        assert!(
+            create["requestBody"]["content"]["application/json"]["schema"]["$ref"]
+                .as_str()
+                .unwrap()
+                .ends_with("/CipherRequestData")
+        );
+        assert_eq!(create["security"][0]["bearerAuth"], serde_json::json!([]));
+        let restore = &document["paths"]["/admin/restore"]["post"];
+        assert_eq!(restore["security"][0]["adminToken"], serde_json::json!([]));
+
+        let schemas = &document["components"]["schemas"];
+        for name in ["Cipher", "AttachmentResponse", "FolderResponse", "Profile"] {
+            assert!(schemas.get(name).is_some(), "{name} isn't in the document");
+        }
+        assert_eq!(
+            schemas["Cipher"]["properties"]["attachments"]["items"]["$ref"],
+            "#/components/schemas/AttachmentResponse"
+        );
+    }
+
+    #[test]
+    fn the_document_and_the_ui_follow_base_path() {
+        assert!(document().get("servers").is_none());
+        let document: Value = serde_json::from_str(&openapi_json("/vault").unwrap()).unwrap();
+        assert_eq!(document["servers"][0]["url"], "/vault");
+
+        let html = swagger_ui_html("/vault");
+        assert!(html.contains(r#"url: "/vault/api/docs/openapi.json""#));
+        assert!(swagger_ui_html("").contains(r#"url: "/api/docs/openapi.json""#));
+    }
+
+    #[test]
+    fn swagger_ui_assets_are_pinned() {
+        let html = swagger_ui_html("");
+        for asset in ["swagger-ui.css", "swagger-ui-bundle.js"] {
+            let start = html.find(asset).unwrap();
+            let tag = &html[html[..start].rfind('<').unwrap()..];
+            let tag = &tag[..tag.find('>').unwrap()];
+            assert!(tag.contains("swagger-ui-dist@5.17.14/"), "{tag}");
+            assert!(tag.contains(r#"integrity="sha384-"#), "{tag}");
+            assert!(tag.contains(r#"crossorigin="anonymous""#), "{tag}");
+        }
+    }
+}
@@ -1,28 +1,37 @@
 use axum::{extract::State, Json};
-use chrono::Utc;
 use log::warn;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
-use worker::{query, Env};
+use utoipa::ToSchema;
 
+use crate::extract::AppJson;
 use crate::handlers::ciphers::RawJson;
-use crate::{auth::Claims, db, error::AppError};
-
-/// Build `globalEquivalentDomains` JSON (as a raw JSON string) in SQLite/D1.
+use crate::Env;
+use crate::{
+    auth::Claims,
+    db::{self, Database, Db},
+    error::{db_error, AppError},
+    global_domains, time,
+};
+
+/// Build `globalEquivalentDomains` JSON (as a raw JSON string).
 ///
 /// - `include_excluded=true`  => returns all groups, each with `excluded` boolean (settings UI).
 /// - `include_excluded=false` => returns only non-excluded groups, with `excluded=false` (sync payload).
 ///
-/// This keeps the Worker from parsing the large upstream dataset.
+/// The built-in list is used unless a dataset was seeded into D1 (see README), which takes
+/// precedence so deployments can pin a newer upstream list. The seeded dataset is assembled in
+/// SQL to keep the Worker from parsing it.
 pub(crate) async fn global_equivalent_domains_json(
-    db: &worker::D1Database,
+    db: &Db,
     excluded_globals_json: &str,
     include_excluded: bool,
 ) -> String {
     let sql = if include_excluded {
         r#"
-SELECT COALESCE(
+SELECT (SELECT COUNT(*) FROM global_equivalent_domains) AS seeded,
+COALESCE(
   (SELECT json_group_array(json(value))
    FROM (
      SELECT json_object(
@@ -39,7 +48,8 @@ SELECT COALESCE(
 "#
     } else {
         r#"
-SELECT COALESCE(
+SELECT (SELECT COUNT(*) FROM global_equivalent_domains) AS seeded,
+COALESCE(
   (SELECT json_group_array(json(value))
    FROM (
      SELECT json_object(
@@ -57,43 +67,46 @@ SELECT COALESCE(
 "#
     };
 
-    async fn run_once(db: &worker::D1Database, sql: &str, excluded: &str) -> Result<String, ()> {
+    /// The seeded dataset's JSON, or `None` if nothing was seeded.
+    async fn run_once(db: &Db, sql: &str, excluded: &str) -> Result<Option<String>, ()> {
         let row: Option<Value> = db
-            .prepare(sql)
-            .bind(&[excluded.to_string().into()])
-            .map_err(|_| ())?
-            .first(None)
+            .first(sql, &[excluded.to_string().into()])
             .await
             .map_err(|_| ())?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        if row.get("seeded").and_then(|v| v.as_f64()).unwrap_or(0.0) == 0.0 {
+            return Ok(None);
+        }
 
-        Ok(row
-            .and_then(|r| {
-                r.get("globals")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string())
-            })
-            .unwrap_or_else(|| "[]".to_string()))
+        Ok(Some(
+            row.get("globals")
+                .and_then(|v| v.as_str())
+                .unwrap_or("[]")
+                .to_string(),
+        ))
     }
 
-    // If excluded_globals is invalid JSON, json_each() can fail.
+    // If excluded_globals is invalid JSON, json_each() can fail (and parsing it below too).
     // Fallback to treating it as empty list.
-    match run_once(db, sql, excluded_globals_json).await {
-        Ok(s) => s,
+    let seeded = match run_once(db, sql, excluded_globals_json).await {
+        Err(_) if excluded_globals_json != "[]" => run_once(db, sql, "[]").await,
+        seeded => seeded,
+    };
+    match seeded {
+        Ok(Some(seeded)) => return seeded,
+        Ok(None) => {}
         Err(_) => {
-            if excluded_globals_json != "[]" {
-                match run_once(db, sql, "[]").await {
-                    Ok(s) => s,
-                    Err(_) => {
-                        warn!("Failed to build globalEquivalentDomains JSON (falling back to [])");
-                        "[]".to_string()
-                    }
-                }
-            } else {
-                warn!("Failed to build globalEquivalentDomains JSON (falling back to [])");
-                "[]".to_string()
-            }
+            warn!(
+                "Failed to read seeded globalEquivalentDomains (falling back to the built-in list)"
+            )
         }
     }
+
+    let excluded_globals: Vec<i32> =
+        serde_json::from_str(excluded_globals_json).unwrap_or_default();
+    global_domains::to_json(&excluded_globals, include_excluded).to_string()
 }
 
 /// GET /api/settings/domains
@@ -105,19 +118,26 @@ SELECT COALESCE(
 /// - `equivalentDomains`: custom groups set by the user
 /// - `excludedGlobalEquivalentDomains`: which predefined groups are disabled
 ///
-/// This server persists only the per-user settings in `users`.
-/// The optional global dataset can be seeded into D1 (see README), and will then be
-/// included in responses without parsing the large JSON in the Worker.
+/// This server persists only the per-user settings in `users`. The global groups are built in,
+/// unless a newer dataset was seeded into D1 (see README).
+#[utoipa::path(
+    get,
+    path = "/api/settings/domains",
+    tag = "domains",
+    responses((status = 200, description = "Success", content_type = "application/json")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_domains(claims: Claims, State(env): State<Arc<Env>>) -> Result<RawJson, AppError> {
     let db = db::get_db(&env)?;
 
     let row: Option<Value> = db
-        .prepare("SELECT equivalent_domains, excluded_globals FROM users WHERE id = ?1")
-        .bind(&[claims.sub.into()])?
-        .first(None)
+        .first(
+            "SELECT equivalent_domains, excluded_globals FROM users WHERE id = ?1",
+            &[claims.sub.into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
 
     let row = row.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
@@ -131,7 +151,6 @@ pub async fn get_domains(claims: Claims, State(env): State<Arc<Env>>) -> Result<
         .unwrap_or("[]");
 
     // Include ALL global groups and mark `excluded` (settings UI semantics).
-    // Falls back to [] if the dataset isn't seeded yet.
     let global_equivalent_domains =
         global_equivalent_domains_json(&db, excluded_globals, true).await;
 
@@ -142,7 +161,7 @@ pub async fn get_domains(claims: Claims, State(env): State<Arc<Env>>) -> Result<
     Ok(RawJson(response))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EquivDomainData {
     pub excluded_global_equivalent_domains: Option<Vec<i32>>,
@@ -152,11 +171,19 @@ pub struct EquivDomainData {
 /// POST /api/settings/domains
 ///
 /// Persist per-user eq_domains settings (no notifications/push).
+#[utoipa::path(
+    post,
+    path = "/api/settings/domains",
+    tag = "domains",
+    request_body = EquivDomainData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn post_domains(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<EquivDomainData>,
+    AppJson(payload): AppJson<EquivDomainData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
 
@@ -170,19 +197,18 @@ pub async fn post_domains(
     let equivalent_domains_json = serde_json::to_string(&equivalent_domains)
         .map_err(|_| AppError::BadRequest("Invalid equivalent domains".to_string()))?;
 
-    let now = Utc::now().to_rfc3339();
-    query!(
-        &db,
+    let now = time::now_bw();
+    db.run(
         "UPDATE users SET equivalent_domains = ?1, excluded_globals = ?2, updated_at = ?3 WHERE id = ?4",
-        equivalent_domains_json,
-        excluded_globals_json,
-        now,
-        claims.sub
+        &[
+            equivalent_domains_json.into(),
+            excluded_globals_json.into(),
+            now.into(),
+            claims.sub.into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
-    .map_err(|_| AppError::Database)?;
+    .map_err(db_error!())?;
 
     Ok(Json(json!({})))
 }
@@ -190,11 +216,19 @@ pub async fn post_domains(
 /// PUT /api/settings/domains
 ///
 /// Behaves like POST.
+#[utoipa::path(
+    put,
+    path = "/api/settings/domains",
+    tag = "domains",
+    request_body = EquivDomainData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn put_domains(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    payload: Json<EquivDomainData>,
+    payload: AppJson<EquivDomainData>,
 ) -> Result<Json<Value>, AppError> {
     post_domains(claims, State(env), payload).await
 }
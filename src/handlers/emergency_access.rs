@@ -1,32 +1,1157 @@
-use axum::Json;
+//! Emergency access: users designate trusted contacts who may later request access to their vault.
+//!
+//! The grantor invites a contact by email, the contact accepts with the token from the invitation
+//! link, and the grantor confirms by handing over their user key encrypted with the grantee's
+//! public key.
+//!
+//! A confirmed grantee can then initiate a recovery. The grantor approves or rejects it; if they
+//! don't answer within the wait time it counts as approved, checked both when the grantee uses the
+//! access and by the scheduled job. Once approved the grantee can view the grantor's vault or, for
+//! takeover access, set a new master password for the grantor.
+
+use axum::{extract::State, Extension, Json};
+use chrono::{Duration, Utc};
+use jwt_compact::Claims as JwtClaims;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
 
-/// GET /emergency-access/trusted
-///
-/// Returns the list of trusted emergency access contacts (grantees) for the current user.
-/// This is a stub implementation that always returns an empty list since emergency access
-/// is not supported in this minimal Bitwarden-compatible implementation.
-///
-/// In vaultwarden, when `emergency_access_allowed` is disabled, it returns an empty list.
-/// We follow the same pattern here.
-#[worker::send]
-pub async fn get_trusted_contacts() -> Json<Value> {
+use crate::config::Settings;
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
+use crate::{
+    auth::{jwt_time_options, keys::KeyRing, validate_token_times, Claims},
+    crypto::{generate_salt, hash_password_for_storage},
+    db::{self, scoped::CipherFilter, Database, Db},
+    error::{db_error, internal_error, AppError},
+    handlers::{
+        ciphers::{append_cipher_json_array_raw, CipherJsonFormat, RawJson},
+        policies,
+    },
+    mail::{
+        self,
+        templates::{self, RecoveryNotice},
+    },
+    models::{
+        emergency_access::{
+            EmergencyAccess, EmergencyAccessAcceptRequest, EmergencyAccessConfirmRequest,
+            EmergencyAccessInviteRequest, EmergencyAccessPasswordRequest, EmergencyAccessStatus,
+            EmergencyAccessType, EmergencyAccessUpdateRequest, EmergencyContact,
+        },
+        policy::PolicyType,
+        user::User,
+    },
+    notify::{self, UpdateType},
+    time, BaseUrl,
+};
+
+const EMERGENCY_INVITE_PURPOSE: &str = "emergency_invite";
+/// Invitations can be accepted for this long.
+const EMERGENCY_INVITE_TTL_DAYS: i64 = 5;
+/// Upper bound on the wait time, as in Bitwarden's request validation.
+const MAX_WAIT_TIME_DAYS: i32 = i16::MAX as i32;
+
+/// Signed into the invitation link; presented back when the invitation is accepted.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmergencyInviteClaims {
+    purpose: String,
+    emergency_access_id: String,
+    email: String,
+}
+
+fn not_found() -> AppError {
+    AppError::NotFound("Emergency access not found".to_string())
+}
+
+fn list(data: Vec<Value>) -> Json<Value> {
     Json(json!({
-        "data": [],
+        "data": data,
         "object": "list",
-        "continuationToken": null
+        "continuationToken": null,
     }))
 }
 
-/// GET /emergency-access/granted
+fn validate_settings(atype: i32, wait_time_days: i32) -> Result<(), AppError> {
+    if EmergencyAccessType::from_i32(atype).is_none() {
+        return Err(AppError::BadRequest(
+            "Invalid emergency access type".to_string(),
+        ));
+    }
+    if !(1..=MAX_WAIT_TIME_DAYS).contains(&wait_time_days) {
+        return Err(AppError::BadRequest(
+            "The wait time must be at least one day".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+async fn find_contact(db: &Db, user_id: &str) -> Result<Option<EmergencyContact>, AppError> {
+    db.first(
+        "SELECT id, name, email, avatar_color FROM users WHERE id = ?1",
+        &[user_id.into()],
+    )
+    .await
+    .map_err(db_error!())
+}
+
+/// Loads a grant the user gave, whatever its status.
+async fn find_as_grantor(db: &Db, id: &str, user_id: &str) -> Result<EmergencyAccess, AppError> {
+    db.first(
+        "SELECT * FROM emergency_access WHERE id = ?1 AND grantor_id = ?2",
+        &[id.into(), user_id.into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(not_found)
+}
+
+/// Loads a grant the user received, whatever its status.
+async fn find_as_grantee(db: &Db, id: &str, user_id: &str) -> Result<EmergencyAccess, AppError> {
+    db.first(
+        "SELECT * FROM emergency_access WHERE id = ?1 AND grantee_id = ?2",
+        &[id.into(), user_id.into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(not_found)
+}
+
+/// Moves a grant to another status, setting or clearing when its recovery was initiated.
+async fn update_status(
+    db: &Db,
+    access: &EmergencyAccess,
+    status: EmergencyAccessStatus,
+    recovery_initiated_at: Option<&str>,
+) -> Result<(), AppError> {
+    db.run(
+        "UPDATE emergency_access SET status = ?1, recovery_initiated_at = ?2, updated_at = ?3 WHERE id = ?4",
+        &[
+            (status as i32).into(),
+            recovery_initiated_at.into(),
+            time::now_bw().into(),
+            access.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+    Ok(())
+}
+
+/// Emails the other side of a grant that its recovery moved on. The recovery goes on whether or
+/// not the email goes out; [`mail::send`] logs failures.
+async fn notify_contact(env: &Env, db: &Db, access: &EmergencyAccess, notice: RecoveryNotice) {
+    let grantor = Some(access.grantor_id.as_str());
+    let grantee = access.grantee_id.as_deref();
+    let (recipient, contact) = match notice {
+        RecoveryNotice::Initiated { .. } | RecoveryNotice::PasswordChanged => (grantor, grantee),
+        RecoveryNotice::Approved | RecoveryNotice::Rejected => (grantee, grantor),
+    };
+    let (Some(recipient), Some(contact)) = (recipient, contact) else {
+        return;
+    };
+    let (recipient, contact) = match (
+        find_contact(db, recipient).await,
+        find_contact(db, contact).await,
+    ) {
+        (Ok(Some(recipient)), Ok(Some(contact))) => (recipient, contact),
+        _ => {
+            log::warn!(
+                "Couldn't load the users of emergency access {} to email a notice",
+                access.id
+            );
+            return;
+        }
+    };
+
+    let contact_name = contact.name.unwrap_or(contact.email);
+    let message = templates::emergency_access_notice(notice, &contact_name).to(recipient.email);
+    let _ = mail::send(env, &message).await;
+}
+
+/// Approves a recovery that has waited out the wait time, unless the grantor answered it since
+/// `access` was loaded. Returns whether it was approved.
+async fn approve_after_wait(db: &Db, access: &EmergencyAccess) -> Result<bool, worker::Error> {
+    let changes = db
+        .run(
+            "UPDATE emergency_access SET status = ?1, updated_at = ?2 WHERE id = ?3 AND status = ?4",
+            &[
+                (EmergencyAccessStatus::RecoveryApproved as i32).into(),
+                time::now_bw().into(),
+                access.id.as_str().into(),
+                (EmergencyAccessStatus::RecoveryInitiated as i32).into(),
+            ],
+        )
+        .await?;
+    Ok(changes > 0)
+}
+
+/// A grant the user received whose recovery has been approved, explicitly or by waiting out the
+/// wait time, and that allows `atype` access.
+async fn find_approved(
+    env: &Env,
+    db: &Db,
+    id: &str,
+    user_id: &str,
+    atype: EmergencyAccessType,
+) -> Result<EmergencyAccess, AppError> {
+    let mut access = find_as_grantee(db, id, user_id).await?;
+    if access.atype != atype as i32 {
+        return Err(AppError::Forbidden(
+            "This emergency access doesn't allow that".to_string(),
+        ));
+    }
+    if access.recovery_wait_elapsed(Utc::now())
+        && approve_after_wait(db, &access).await.map_err(db_error!())?
+    {
+        access.status = EmergencyAccessStatus::RecoveryApproved as i32;
+        notify_contact(env, db, &access, RecoveryNotice::Approved).await;
+    }
+    if access.status != EmergencyAccessStatus::RecoveryApproved as i32 {
+        return Err(AppError::Forbidden(
+            "Emergency access hasn't been approved".to_string(),
+        ));
+    }
+    Ok(access)
+}
+
+/// Web vault link accepting an invitation, carrying a signed invitation token.
+fn invite_url(
+    env: &Env,
+    base_url: &str,
+    access: &EmergencyAccess,
+    grantor_name: &str,
+) -> Result<String, AppError> {
+    let mut claims = JwtClaims::new(EmergencyInviteClaims {
+        purpose: EMERGENCY_INVITE_PURPOSE.to_string(),
+        emergency_access_id: access.id.clone(),
+        email: access.email.clone(),
+    });
+    claims.expiration = Some(Utc::now() + Duration::days(EMERGENCY_INVITE_TTL_DAYS));
+    let token = KeyRing::access(env)?.sign(&claims)?;
+
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("id", &access.id)
+        .append_pair("name", grantor_name)
+        .append_pair("email", &access.email)
+        .append_pair("token", &token)
+        .finish();
+    Ok(format!(
+        "{}/#/accept-emergency/?{query}",
+        base_url.trim_end_matches('/')
+    ))
+}
+
+/// Emails the invitation when mail delivery is configured. Returns the invitation link when it
+/// wasn't emailed and invitation links are enabled.
+async fn send_invite(
+    env: &Env,
+    db: &Db,
+    base_url: &str,
+    access: &EmergencyAccess,
+) -> Result<Json<Value>, AppError> {
+    let settings = Settings::get(env);
+    let mail_enabled = matches!(settings.mail, Ok(Some(_)));
+    let mut emailed = false;
+    let mut url = None;
+    if mail_enabled || settings.org_invite_links {
+        let grantor = find_contact(db, &access.grantor_id).await?;
+        let grantor_name = grantor
+            .map(|user| user.name.unwrap_or(user.email))
+            .unwrap_or_default();
+        let invite_url = invite_url(env, base_url, access, &grantor_name)?;
+        if mail_enabled {
+            let message = templates::emergency_access_invite(
+                &grantor_name,
+                &invite_url,
+                Duration::days(EMERGENCY_INVITE_TTL_DAYS),
+            )
+            .to(access.email.as_str());
+            emailed = mail::send(env, &message).await.is_ok();
+        }
+        url = Some(invite_url);
+    }
+    if !mail_enabled {
+        log::info!(
+            "Mail delivery isn't configured; emergency access invitation for {} was not emailed",
+            access.email
+        );
+    }
+    Ok(Json(json!({
+        "id": access.id,
+        "email": access.email,
+        "inviteUrl": url.filter(|_| settings.org_invite_links && !emailed),
+    })))
+}
+
+/// GET /api/emergency-access/trusted
 ///
-/// Returns the list of emergency access grants where the current user is a grantee.
-/// This is a stub implementation that always returns an empty list.
+/// The user's trusted contacts, invited or not.
+#[utoipa::path(
+    get,
+    path = "/api/emergency-access/trusted",
+    tag = "emergency_access",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
-pub async fn get_granted_access() -> Json<Value> {
-    Json(json!({
-        "data": [],
-        "object": "list",
-        "continuationToken": null
-    }))
+pub async fn get_trusted_contacts(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let accesses: Vec<EmergencyAccess> = db
+        .all(
+            "SELECT * FROM emergency_access WHERE grantor_id = ?1 ORDER BY created_at",
+            &[claims.sub.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    let grantees: HashMap<String, EmergencyContact> = db
+        .all::<EmergencyContact>(
+            "SELECT id, name, email, avatar_color FROM users
+         WHERE id IN (SELECT grantee_id FROM emergency_access WHERE grantor_id = ?1)",
+            &[claims.sub.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .into_iter()
+        .map(|user| (user.id.clone(), user))
+        .collect();
+
+    Ok(list(
+        accesses
+            .iter()
+            .map(|access| {
+                let grantee = access.grantee_id.as_ref().and_then(|id| grantees.get(id));
+                access.to_grantee_details_json(grantee)
+            })
+            .collect(),
+    ))
+}
+
+/// GET /api/emergency-access/granted
+///
+/// Grants the user accepted from others.
+#[utoipa::path(
+    get,
+    path = "/api/emergency-access/granted",
+    tag = "emergency_access",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_granted_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let accesses: Vec<EmergencyAccess> = db
+        .all(
+            "SELECT * FROM emergency_access WHERE grantee_id = ?1 ORDER BY created_at",
+            &[claims.sub.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    let grantors: HashMap<String, EmergencyContact> = db
+        .all::<EmergencyContact>(
+            "SELECT id, name, email, avatar_color FROM users
+         WHERE id IN (SELECT grantor_id FROM emergency_access WHERE grantee_id = ?1)",
+            &[claims.sub.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .into_iter()
+        .map(|user| (user.id.clone(), user))
+        .collect();
+
+    Ok(list(
+        accesses
+            .iter()
+            .map(|access| access.to_grantor_details_json(grantors.get(&access.grantor_id)))
+            .collect(),
+    ))
+}
+
+/// GET /api/emergency-access/{id}
+#[utoipa::path(
+    get,
+    path = "/api/emergency-access/{id}",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_as_grantor(&db, &id, &claims.sub).await?;
+    let grantee = match &access.grantee_id {
+        Some(grantee_id) => find_contact(&db, grantee_id).await?,
+        None => None,
+    };
+    Ok(Json(access.to_grantee_details_json(grantee.as_ref())))
+}
+
+/// PUT/POST /api/emergency-access/{id}
+///
+/// The grantor changes the access type or wait time.
+#[utoipa::path(
+    method(put, post),
+    path = "/api/emergency-access/{id}",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    request_body = EmergencyAccessUpdateRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<EmergencyAccessUpdateRequest>,
+) -> Result<Json<Value>, AppError> {
+    validate_settings(payload.atype, payload.wait_time_days)?;
+    let db = db::get_db(&env)?;
+    let mut access = find_as_grantor(&db, &id, &claims.sub).await?;
+
+    // A new key is only meaningful once the grantee has one
+    if let Some(key) = payload.key_encrypted.filter(|key| !key.trim().is_empty()) {
+        if access.key_encrypted.is_some() {
+            access.key_encrypted = Some(key);
+        }
+    }
+    access.atype = payload.atype;
+    access.wait_time_days = payload.wait_time_days;
+    access.updated_at = time::now_bw();
+    db.run(
+        "UPDATE emergency_access SET atype = ?1, wait_time_days = ?2, key_encrypted = ?3, updated_at = ?4 WHERE id = ?5",
+        &[
+            access.atype.into(),
+            access.wait_time_days.into(),
+            access.key_encrypted.as_deref().into(),
+            access.updated_at.as_str().into(),
+            access.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    let grantee = match &access.grantee_id {
+        Some(grantee_id) => find_contact(&db, grantee_id).await?,
+        None => None,
+    };
+    Ok(Json(access.to_grantee_details_json(grantee.as_ref())))
+}
+
+/// DELETE /api/emergency-access/{id} (also POST /api/emergency-access/{id}/delete)
+///
+/// Either side can end the grant; for the grantor this also revokes a pending invitation.
+#[utoipa::path(
+    delete,
+    path = "/api/emergency-access/{id}",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn delete_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let deleted = db
+        .run(
+            "DELETE FROM emergency_access WHERE id = ?1 AND (grantor_id = ?2 OR grantee_id = ?2)",
+            &[id.as_str().into(), claims.sub.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    if deleted == 0 {
+        return Err(not_found());
+    }
+    Ok(Json(()))
+}
+
+/// POST /api/emergency-access/invite
+///
+/// Invites a trusted contact by email. They don't need an account yet; the grant is bound to
+/// whoever accepts the invitation with that address.
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/invite",
+    tag = "emergency_access",
+    request_body = EmergencyAccessInviteRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_invite(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    AppJson(payload): AppJson<EmergencyAccessInviteRequest>,
+) -> Result<Json<Value>, AppError> {
+    let email = payload.email.trim().to_lowercase();
+    if email.is_empty() || !email.contains('@') {
+        return Err(AppError::BadRequest(
+            "A valid email is required".to_string(),
+        ));
+    }
+    if email == claims.email.to_lowercase() {
+        return Err(AppError::BadRequest(
+            "You can't be your own emergency contact".to_string(),
+        ));
+    }
+    validate_settings(payload.atype, payload.wait_time_days)?;
+
+    let db = db::get_db(&env)?;
+    let existing: Option<String> = db
+        .first_column(
+            "SELECT id FROM emergency_access WHERE grantor_id = ?1 AND email = ?2",
+            &[claims.sub.as_str().into(), email.as_str().into()],
+            "id",
+        )
+        .await
+        .map_err(db_error!())?;
+    if existing.is_some() {
+        return Err(AppError::BadRequest(
+            "This contact has already been invited".to_string(),
+        ));
+    }
+
+    let now = time::now_bw();
+    let access = EmergencyAccess {
+        id: Uuid::new_v4().to_string(),
+        grantor_id: claims.sub.clone(),
+        grantee_id: None,
+        email,
+        key_encrypted: None,
+        atype: payload.atype,
+        status: EmergencyAccessStatus::Invited as i32,
+        wait_time_days: payload.wait_time_days,
+        recovery_initiated_at: None,
+        created_at: now.clone(),
+        updated_at: now,
+    };
+    db.run(
+        "INSERT INTO emergency_access (id, grantor_id, grantee_id, email, key_encrypted, atype, status, wait_time_days, created_at, updated_at)
+         VALUES (?1, ?2, NULL, ?3, NULL, ?4, ?5, ?6, ?7, ?7)",
+        &[
+            access.id.as_str().into(),
+            access.grantor_id.as_str().into(),
+            access.email.as_str().into(),
+            access.atype.into(),
+            access.status.into(),
+            access.wait_time_days.into(),
+            access.created_at.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    send_invite(env.as_ref(), &db, &base_url, &access).await
+}
+
+/// POST /api/emergency-access/{id}/reinvite
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/reinvite",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_reinvite(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_as_grantor(&db, &id, &claims.sub).await?;
+    if access.status != EmergencyAccessStatus::Invited as i32 {
+        return Err(AppError::BadRequest(
+            "This invitation has already been accepted".to_string(),
+        ));
+    }
+    send_invite(env.as_ref(), &db, &base_url, &access).await
+}
+
+/// POST /api/emergency-access/{id}/accept
+///
+/// The invited contact accepts with the token from their invitation link. The grant is bound to
+/// their account and waits for the grantor to confirm it.
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/accept",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    request_body = EmergencyAccessAcceptRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_accept(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<EmergencyAccessAcceptRequest>,
+) -> Result<Json<()>, AppError> {
+    // Token problems aren't session problems: answering 401 would log the client out
+    let invalid = || AppError::BadRequest("Invalid invitation token".to_string());
+    let token = KeyRing::access(env.as_ref())?
+        .verify::<EmergencyInviteClaims>(&payload.token)
+        .ok_or_else(invalid)?;
+    validate_token_times(token.claims(), &jwt_time_options(&settings))
+        .map_err(|_| AppError::BadRequest("This invitation has expired".to_string()))?;
+    let invite = &token.claims().custom;
+    if invite.purpose != EMERGENCY_INVITE_PURPOSE || invite.emergency_access_id != id {
+        return Err(invalid());
+    }
+    if invite.email != claims.email.to_lowercase() {
+        return Err(AppError::BadRequest(
+            "This invitation was sent to a different email address".to_string(),
+        ));
+    }
+
+    let db = db::get_db(&env)?;
+    // A revoked invitation no longer has a row
+    let access: EmergencyAccess = db
+        .first(
+            "SELECT * FROM emergency_access WHERE id = ?1",
+            &[id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(not_found)?;
+    if access.status != EmergencyAccessStatus::Invited as i32 {
+        return Err(AppError::BadRequest(
+            "This invitation has already been accepted".to_string(),
+        ));
+    }
+    if access.grantor_id == claims.sub {
+        return Err(AppError::BadRequest(
+            "You can't be your own emergency contact".to_string(),
+        ));
+    }
+
+    db.run(
+        "UPDATE emergency_access SET grantee_id = ?1, status = ?2, updated_at = ?3 WHERE id = ?4",
+        &[
+            claims.sub.as_str().into(),
+            (EmergencyAccessStatus::Accepted as i32).into(),
+            time::now_bw().into(),
+            access.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    Ok(Json(()))
+}
+
+/// POST /api/emergency-access/{id}/confirm
+///
+/// The grantor hands an accepted contact their user key, encrypted with the contact's public key
+/// (from GET /api/users/{id}/public-key).
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/confirm",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    request_body = EmergencyAccessConfirmRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_confirm(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<EmergencyAccessConfirmRequest>,
+) -> Result<Json<Value>, AppError> {
+    if payload.key.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "The encrypted user key is required".to_string(),
+        ));
+    }
+    let db = db::get_db(&env)?;
+    let mut access = find_as_grantor(&db, &id, &claims.sub).await?;
+    let Some(grantee_id) = access
+        .grantee_id
+        .clone()
+        .filter(|_| access.status == EmergencyAccessStatus::Accepted as i32)
+    else {
+        return Err(AppError::BadRequest(
+            "Only contacts who accepted their invitation can be confirmed".to_string(),
+        ));
+    };
+
+    access.key_encrypted = Some(payload.key);
+    access.status = EmergencyAccessStatus::Confirmed as i32;
+    access.updated_at = time::now_bw();
+    db.run(
+        "UPDATE emergency_access SET key_encrypted = ?1, status = ?2, updated_at = ?3 WHERE id = ?4",
+        &[
+            access.key_encrypted.as_deref().into(),
+            access.status.into(),
+            access.updated_at.as_str().into(),
+            access.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    let grantee = find_contact(&db, &grantee_id).await?;
+    Ok(Json(access.to_grantee_details_json(grantee.as_ref())))
+}
+
+/// POST /api/emergency-access/{id}/initiate
+///
+/// A confirmed grantee requests access. The grantor has the wait time to reject the request.
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/initiate",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_initiate(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_as_grantee(&db, &id, &claims.sub).await?;
+    if access.status != EmergencyAccessStatus::Confirmed as i32 {
+        return Err(AppError::BadRequest(
+            "Emergency access can't be requested now".to_string(),
+        ));
+    }
+
+    update_status(
+        &db,
+        &access,
+        EmergencyAccessStatus::RecoveryInitiated,
+        Some(&time::now_bw()),
+    )
+    .await?;
+    let notice = RecoveryNotice::Initiated {
+        takeover: access.atype == EmergencyAccessType::Takeover as i32,
+        wait: Duration::days(access.wait_time_days.into()),
+    };
+    notify_contact(&env, &db, &access, notice).await;
+    Ok(Json(()))
+}
+
+/// POST /api/emergency-access/{id}/approve
+///
+/// The grantor approves a request without waiting out the wait time.
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/approve",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_approve(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_as_grantor(&db, &id, &claims.sub).await?;
+    if access.status != EmergencyAccessStatus::RecoveryInitiated as i32 {
+        return Err(AppError::BadRequest(
+            "Emergency access hasn't been requested".to_string(),
+        ));
+    }
+
+    update_status(
+        &db,
+        &access,
+        EmergencyAccessStatus::RecoveryApproved,
+        access.recovery_initiated_at.as_deref(),
+    )
+    .await?;
+    notify_contact(&env, &db, &access, RecoveryNotice::Approved).await;
+    Ok(Json(()))
+}
+
+/// POST /api/emergency-access/{id}/reject
+///
+/// The grantor rejects a request, or takes back an approval. The grant stays confirmed, so the
+/// grantee can request access again later.
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/reject",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_reject(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_as_grantor(&db, &id, &claims.sub).await?;
+    if access.status != EmergencyAccessStatus::RecoveryInitiated as i32
+        && access.status != EmergencyAccessStatus::RecoveryApproved as i32
+    {
+        return Err(AppError::BadRequest(
+            "Emergency access hasn't been requested".to_string(),
+        ));
+    }
+
+    update_status(&db, &access, EmergencyAccessStatus::Confirmed, None).await?;
+    notify_contact(&env, &db, &access, RecoveryNotice::Rejected).await;
+    Ok(Json(()))
+}
+
+/// POST /api/emergency-access/{id}/view
+///
+/// The grantor's personal vault, with their user key encrypted for the grantee. Attachments
+/// aren't shared through emergency access.
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/view",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", content_type = "application/json")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_view(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppPath(id): AppPath<String>,
+) -> Result<RawJson, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_approved(&env, &db, &id, &claims.sub, EmergencyAccessType::View).await?;
+    let key_encrypted = serde_json::to_string(&access.key_encrypted).map_err(internal_error!())?;
+
+    // Response schema: {"ciphers":[...],"keyEncrypted":"...","object":"emergencyAccessView"}
+    let mut response = String::new();
+    response.push_str("{\"ciphers\":");
+    append_cipher_json_array_raw(
+        &mut response,
+        &db,
+        CipherJsonFormat::details(false),
+        &access.grantor_id,
+        &CipherFilter {
+            organization_id: Some(None),
+            deleted: Some(false),
+            ..CipherFilter::default()
+        },
+        settings.ciphers_default_row_query,
+    )
+    .await?;
+    response.push_str(",\"keyEncrypted\":");
+    response.push_str(&key_encrypted);
+    response.push_str(",\"object\":\"emergencyAccessView\"}");
+
+    Ok(RawJson(response))
+}
+
+async fn find_grantor(db: &Db, access: &EmergencyAccess) -> Result<User, AppError> {
+    db.first(
+        "SELECT * FROM users WHERE id = ?1",
+        &[access.grantor_id.as_str().into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+}
+
+/// POST /api/emergency-access/{id}/takeover
+///
+/// What the grantee's client needs to derive a new master key for the grantor: their KDF
+/// settings and user key.
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/takeover",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_takeover(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_approved(&env, &db, &id, &claims.sub, EmergencyAccessType::Takeover).await?;
+    let grantor = find_grantor(&db, &access).await?;
+
+    Ok(Json(json!({
+        "keyEncrypted": access.key_encrypted,
+        "kdf": grantor.kdf_type,
+        "kdfIterations": grantor.kdf_iterations,
+        "kdfMemory": grantor.kdf_memory,
+        "kdfParallelism": grantor.kdf_parallelism,
+        "object": "emergencyAccessTakeover",
+    })))
+}
+
+/// GET /api/emergency-access/{id}/policies
+///
+/// Master password requirements of the grantor's organizations, which the new password has to
+/// meet.
+#[utoipa::path(
+    get,
+    path = "/api/emergency-access/{id}/policies",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_policies(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_approved(&env, &db, &id, &claims.sub, EmergencyAccessType::Takeover).await?;
+    let data = policies::list_user_policies(&db, &access.grantor_id)
+        .await?
+        .iter()
+        .filter(|policy| policy.atype == PolicyType::MasterPassword as i32)
+        .map(|policy| policy.to_json())
+        .collect();
+    Ok(list(data))
+}
+
+/// POST /api/emergency-access/{id}/password
+///
+/// The grantee sets a new master password for the grantor. The grantor's two-step login is
+/// removed, since the grantee couldn't pass it, and their sessions are ended.
+#[utoipa::path(
+    post,
+    path = "/api/emergency-access/{id}/password",
+    tag = "emergency_access",
+    params(("id" = String, Path)),
+    request_body = EmergencyAccessPasswordRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_password(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<EmergencyAccessPasswordRequest>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_approved(&env, &db, &id, &claims.sub, EmergencyAccessType::Takeover).await?;
+    if payload.new_master_password_hash.is_empty() || payload.key.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "The new master password and key are required".to_string(),
+        ));
+    }
+    let grantor = find_grantor(&db, &access).await?;
+
+    let new_salt = generate_salt()?;
+    let password_iterations = settings.password_iterations as i32;
+    let new_hashed_password = hash_password_for_storage(
+        &payload.new_master_password_hash,
+        &new_salt,
+        password_iterations as u32,
+    )
+    .await?;
+
+    // A new security stamp invalidates the grantor's refresh tokens
+    Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, key = ?4, security_stamp = ?5, totp_recover = NULL, updated_at = ?6 WHERE id = ?7",
+                &[
+                    new_hashed_password.into(),
+                    new_salt.into(),
+                    password_iterations.into(),
+                    payload.key.as_str().into(),
+                    Uuid::new_v4().to_string().into(),
+                    time::now_bw().into(),
+                    grantor.id.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM twofactor WHERE user_uuid = ?1",
+                &[grantor.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    notify::notify_user(&env, &db, UpdateType::LogOut, &grantor.id, None).await;
+    notify_contact(&env, &db, &access, RecoveryNotice::PasswordChanged).await;
+    Ok(Json(()))
+}
+
+/// Approves recoveries whose wait time has passed without an answer from the grantor. Run by the
+/// scheduled job, so grantees find their access approved without having to try it first.
+pub async fn approve_overdue_recoveries(env: &Env) -> Result<u32, worker::Error> {
+    let db: Db = env.d1("vault1")?;
+    let initiated: Vec<EmergencyAccess> = db
+        .all(
+            "SELECT * FROM emergency_access WHERE status = ?1",
+            &[(EmergencyAccessStatus::RecoveryInitiated as i32).into()],
+        )
+        .await?;
+
+    let now = Utc::now();
+    let mut approved = 0;
+    for access in initiated
+        .iter()
+        .filter(|access| access.recovery_wait_elapsed(now))
+    {
+        if approve_after_wait(&db, access).await? {
+            notify_contact(env, &db, access, RecoveryNotice::Approved).await;
+            approved += 1;
+        }
+    }
+    Ok(approved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::block_on;
+
+    const GRANTOR: &str = "grantor";
+    const GRANTEE: &str = "grantee";
+    const ACCESS: &str = "access";
+
+    /// A takeover grant from `grantor` to `grantee` with a one-day wait time, whose recovery was
+    /// initiated `initiated_days_ago` days ago.
+    fn database(initiated_days_ago: i64) -> Db {
+        let db = Db::in_memory().unwrap();
+        block_on(crate::migrations::run(&db)).unwrap();
+        for id in [GRANTOR, GRANTEE] {
+            block_on(db.run(
+                "INSERT INTO users (id, email, master_password_hash, key, private_key, public_key, created_at, updated_at)
+                 VALUES (?1, ?2, 'hash', 'key', 'private', 'public', ?3, ?3)",
+                &[id.into(), format!("{id}@example.com").into(), time::now_bw().into()],
+            ))
+            .unwrap();
+        }
+        let initiated_at = time::format_bw(Utc::now() - Duration::days(initiated_days_ago));
+        block_on(db.run(
+            "INSERT INTO emergency_access (id, grantor_id, grantee_id, email, key_encrypted, atype, status, wait_time_days, recovery_initiated_at, created_at, updated_at)
+             VALUES (?1, ?2, ?3, 'grantee@example.com', 'key', ?4, ?5, 1, ?6, ?6, ?6)",
+            &[
+                ACCESS.into(),
+                GRANTOR.into(),
+                GRANTEE.into(),
+                (EmergencyAccessType::Takeover as i32).into(),
+                (EmergencyAccessStatus::RecoveryInitiated as i32).into(),
+                initiated_at.into(),
+            ],
+        ))
+        .unwrap();
+        db
+    }
+
+    fn status(db: &Db) -> i64 {
+        block_on(db.first_column::<i64>(
+            "SELECT status FROM emergency_access WHERE id = ?1",
+            &[ACCESS.into()],
+            "status",
+        ))
+        .unwrap()
+        .unwrap()
+    }
+
+    fn find_takeover(db: &Db) -> Result<EmergencyAccess, AppError> {
+        let env = crate::native::Env::new(db.clone());
+        block_on(find_approved(
+            &env,
+            db,
+            ACCESS,
+            GRANTEE,
+            EmergencyAccessType::Takeover,
+        ))
+    }
+
+    #[test]
+    fn takeover_is_refused_before_the_wait_time() {
+        let db = database(0);
+
+        assert!(matches!(find_takeover(&db), Err(AppError::Forbidden(_))));
+        assert_eq!(status(&db), EmergencyAccessStatus::RecoveryInitiated as i64);
+    }
+
+    #[test]
+    fn takeover_is_allowed_once_the_wait_time_passed() {
+        let db = database(2);
+
+        let access = find_takeover(&db).unwrap();
+        assert_eq!(
+            access.status,
+            EmergencyAccessStatus::RecoveryApproved as i32
+        );
+        assert_eq!(status(&db), EmergencyAccessStatus::RecoveryApproved as i64);
+    }
+
+    #[test]
+    fn takeover_is_refused_after_a_reject() {
+        let db = database(2);
+        let access = block_on(find_as_grantee(&db, ACCESS, GRANTEE)).unwrap();
+        block_on(update_status(
+            &db,
+            &access,
+            EmergencyAccessStatus::Confirmed,
+            None,
+        ))
+        .unwrap();
+
+        assert!(matches!(find_takeover(&db), Err(AppError::Forbidden(_))));
+        assert_eq!(status(&db), EmergencyAccessStatus::Confirmed as i64);
+    }
+
+    #[test]
+    fn a_reject_racing_the_wait_time_wins() {
+        let db = database(2);
+        // The grantee's request loaded the grant before the grantor rejected it
+        let stale = block_on(find_as_grantee(&db, ACCESS, GRANTEE)).unwrap();
+        block_on(update_status(
+            &db,
+            &stale,
+            EmergencyAccessStatus::Confirmed,
+            None,
+        ))
+        .unwrap();
+
+        assert!(stale.recovery_wait_elapsed(Utc::now()));
+        assert!(!block_on(approve_after_wait(&db, &stale)).unwrap());
+        assert_eq!(status(&db), EmergencyAccessStatus::Confirmed as i64);
+    }
 }
@@ -0,0 +1,365 @@
+//! Event logs: an audit trail of what members did to an organization's data, and a personal log
+//! of each user's account activity (logins, password and 2FA changes, vault purges).
+//!
+//! Events are written best-effort: failing to record one is logged and never fails the request
+//! that caused it. The exceptions are the events of cipher creations and updates, which are
+//! written in the same batch as the cipher and stand or fall with it. The scheduled job drops
+//! events older than EVENTS_RETENTION_DAYS.
+
+use axum::{
+    extract::{FromRequestParts, State},
+    http::request::Parts,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{convert::Infallible, sync::Arc};
+
+use crate::extract::{AppPath, AppQuery};
+use crate::Env;
+use crate::{
+    auth::Claims,
+    db::{self, scoped::org_scoped::cipher_writable_sql, Database, Db, Param, Statement},
+    error::{db_error, AppError},
+    handlers::{
+        auth_requests::{client_ip, device_type},
+        organizations::find_organization_for_member,
+    },
+    models::{
+        event::{Event, EventType},
+        organization::{Membership, MembershipStatus},
+        response::ListResponse,
+    },
+    time,
+};
+
+/// Events returned per page.
+const EVENTS_PAGE_SIZE: usize = 100;
+/// Range listed when the client doesn't give a start date.
+const DEFAULT_EVENTS_RANGE_DAYS: i64 = 30;
+
+/// Where a request came from, recorded with the events it causes.
+#[derive(Debug, Clone)]
+pub struct EventSource {
+    pub ip_address: String,
+    pub device_type: i32,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for EventSource {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(EventSource {
+            ip_address: client_ip(&parts.headers),
+            device_type: device_type(&parts.headers),
+        })
+    }
+}
+
+/// The statement recording an event, for handlers that write it in the batch of the change it
+/// records.
+pub(crate) fn event_statement(
+    db: &Db,
+    source: &EventSource,
+    event: &Event,
+) -> Result<Statement, worker::Error> {
+    Database::prepare(
+        db,
+        "INSERT INTO events (id, atype, organization_id, user_id, cipher_id, collection_id, group_id, policy_id, member_id, acting_user_id, device_type, ip_address, date)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        &[
+            event.id.as_str().into(),
+            event.atype.into(),
+            event.organization_id.as_deref().into(),
+            event.user_id.as_deref().into(),
+            event.cipher_id.as_deref().into(),
+            event.collection_id.as_deref().into(),
+            event.group_id.as_deref().into(),
+            event.policy_id.as_deref().into(),
+            event.member_id.as_deref().into(),
+            event.acting_user_id.as_deref().into(),
+            source.device_type.into(),
+            source.ip_address.as_str().into(),
+            event.date.as_str().into(),
+        ],
+    )
+}
+
+/// Records an event. Failures are only logged.
+pub(crate) async fn log_event(db: &Db, source: &EventSource, event: Event) {
+    let result = match event_statement(db, source, &event) {
+        Ok(statement) => Database::batch(db, vec![statement]).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        log::warn!("Failed to record event {}: {:?}", event.atype, err);
+    }
+}
+
+/// Records an event for each organization cipher among the ids at `ids_path` of `ids_json` that
+/// the acting user may edit. Personal ciphers have no audit trail. Failures are only logged.
+pub(crate) async fn log_cipher_events(
+    db: &Db,
+    source: &EventSource,
+    atype: EventType,
+    acting_user_id: &str,
+    ids_json: &str,
+    ids_path: &str,
+) {
+    let sql = format!(
+        "INSERT INTO events (id, atype, organization_id, cipher_id, acting_user_id, device_type, ip_address, date)
+         SELECT lower(hex(randomblob(16))), ?2, c.organization_id, c.id, ?1, ?3, ?4, ?5
+         FROM ciphers c
+         WHERE c.id IN (SELECT value FROM json_each(?6, ?7))
+           AND c.organization_id IS NOT NULL AND {}",
+        cipher_writable_sql("?1")
+    );
+    let params: [Param; 7] = [
+        acting_user_id.into(),
+        (atype as i32).into(),
+        source.device_type.into(),
+        source.ip_address.as_str().into(),
+        time::now_bw().into(),
+        ids_json.into(),
+        ids_path.into(),
+    ];
+    if let Err(err) = db.run(&sql, &params).await {
+        log::warn!("Failed to record cipher events {}: {:?}", atype as i32, err);
+    }
+}
+
+/// Records a login in every organization the user is a confirmed member of. Failures are only
+/// logged.
+pub(crate) async fn log_login_events(db: &Db, source: &EventSource, user_id: &str) {
+    let result = db
+        .run(
+            "INSERT INTO events (id, atype, organization_id, user_id, member_id, acting_user_id, device_type, ip_address, date)
+         SELECT lower(hex(randomblob(16))), ?1, ou.organization_id, ou.user_id, ou.id, ou.user_id, ?2, ?3, ?4
+         FROM organization_users ou
+         WHERE ou.user_id = ?5 AND ou.status = ?6",
+            &[
+                (EventType::UserLoggedIn as i32).into(),
+                source.device_type.into(),
+                source.ip_address.as_str().into(),
+                time::now_bw().into(),
+                user_id.into(),
+                (MembershipStatus::Confirmed as i32).into(),
+            ],
+        )
+        .await;
+    if let Err(err) = result {
+        log::warn!("Failed to record login events: {:?}", err);
+    }
+}
+
+/// Records an event about the user's own account, which shows in their personal event log.
+/// Failures are only logged.
+pub(crate) async fn log_account_event(
+    db: &Db,
+    source: &EventSource,
+    atype: EventType,
+    user_id: &str,
+) {
+    let event = Event {
+        user_id: Some(user_id.to_string()),
+        ..Event::new(atype, user_id)
+    };
+    log_event(db, source, event).await;
+}
+
+/// An event about a member of an organization.
+pub(crate) fn member_event(atype: EventType, acting_user_id: &str, member: &Membership) -> Event {
+    Event {
+        organization_id: Some(member.organization_id.clone()),
+        user_id: member.user_id.clone(),
+        member_id: Some(member.id.clone()),
+        ..Event::new(atype, acting_user_id)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventsQuery {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub continuation_token: Option<String>,
+}
+
+fn parse_date(field: &str, value: &str) -> Result<DateTime<Utc>, AppError> {
+    time::parse_bw(value)
+        .ok_or_else(|| AppError::validation(field, format!("Invalid date: {value}")))
+}
+
+/// Which events a log lists.
+enum EventScope<'a> {
+    /// The organization's events, optionally limited to one acting user.
+    Organization(&'a str, Option<&'a str>),
+    /// Events about the user's own account, outside any organization.
+    Account(&'a str),
+}
+
+/// A page of events, newest first.
+async fn list_events(
+    db: &Db,
+    scope: EventScope<'_>,
+    query: EventsQuery,
+) -> Result<ListResponse<Value>, AppError> {
+    let end = match query.end.as_deref() {
+        Some(end) => parse_date("end", end)?,
+        None => Utc::now(),
+    };
+    let start = match query.start.as_deref() {
+        Some(start) => parse_date("start", start)?,
+        None => end - Duration::days(DEFAULT_EVENTS_RANGE_DAYS),
+    };
+
+    // The continuation token is the date and id of the last event of the previous page
+    let (after_date, after_id) = match query.continuation_token.as_deref() {
+        Some(token) => {
+            let (date, id) = token
+                .split_once('|')
+                .ok_or_else(|| AppError::BadRequest("Invalid continuation token".to_string()))?;
+            (Param::from(date), Param::from(id))
+        }
+        None => (Param::Null, Param::Null),
+    };
+    let (scope_sql, scope_id, acting_user) = match scope {
+        EventScope::Organization(org_id, acting_user_id) => {
+            ("organization_id = ?1", org_id, acting_user_id.into())
+        }
+        EventScope::Account(user_id) => (
+            "organization_id IS NULL AND user_id = ?1",
+            user_id,
+            Param::Null,
+        ),
+    };
+
+    let events: Vec<Event> = db
+        .all(
+            &format!(
+                "SELECT * FROM events
+             WHERE {scope_sql} AND date >= ?2 AND date <= ?3
+               AND (?4 IS NULL OR date < ?4 OR (date = ?4 AND id < ?5))
+               AND (?6 IS NULL OR acting_user_id = ?6)
+             ORDER BY date DESC, id DESC
+             LIMIT {}",
+                EVENTS_PAGE_SIZE + 1
+            ),
+            &[
+                scope_id.into(),
+                time::format_bw(start).into(),
+                time::format_bw(end).into(),
+                after_date,
+                after_id,
+                acting_user,
+            ],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    Ok(ListResponse::page(events, EVENTS_PAGE_SIZE, |event| {
+        format!("{}|{}", event.date, event.id)
+    })
+    .map(|event| event.to_json()))
+}
+
+/// GET /api/organizations/{id}/events
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/events",
+    tag = "events",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_org_events(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+    AppQuery(query): AppQuery<EventsQuery>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can view event logs".to_string(),
+        ));
+    }
+
+    Ok(Json(
+        list_events(&db, EventScope::Organization(&org.id, None), query).await?,
+    ))
+}
+
+/// GET /api/organizations/{id}/users/{member_id}/events
+///
+/// Events caused by one member of the organization.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/users/{member_id}/events",
+    tag = "events",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_member_events(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+    AppQuery(query): AppQuery<EventsQuery>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can view event logs".to_string(),
+        ));
+    }
+
+    let member: Membership = db
+        .first(
+            "SELECT * FROM organization_users WHERE id = ?1 AND organization_id = ?2",
+            &[member_id.as_str().into(), org.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("Member not found".to_string()))?;
+    let Some(user_id) = member.user_id else {
+        // Members who haven't joined yet can't have done anything
+        return Ok(Json(ListResponse::new(Vec::new())));
+    };
+
+    Ok(Json(
+        list_events(
+            &db,
+            EventScope::Organization(&org.id, Some(&user_id)),
+            query,
+        )
+        .await?,
+    ))
+}
+
+/// GET /api/accounts/events
+///
+/// The user's own account activity. Not in Bitwarden, whose clients only show organization logs.
+#[utoipa::path(
+    get,
+    path = "/api/accounts/events",
+    tag = "events",
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_account_events(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppQuery(query): AppQuery<EventsQuery>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    Ok(Json(
+        list_events(&db, EventScope::Account(&claims.sub), query).await?,
+    ))
+}
@@ -1,75 +1,82 @@
-use axum::extract::{Path, State};
+use axum::extract::State;
 use axum::Json;
-use chrono::Utc;
-use serde_json::{json, Value};
 use std::sync::Arc;
 use uuid::Uuid;
-use worker::{query, Env};
 
 use crate::auth::Claims;
-use crate::db::{self, touch_user_updated_at};
-use crate::error::AppError;
+use crate::db::{self, scoped, touch_user_updated_at, Database};
+use crate::error::{db_error, AppError};
+use crate::extract::{AppJson, AppPath};
 use crate::models::folder::{CreateFolderRequest, Folder, FolderResponse};
-
+use crate::models::response::ListResponse;
+use crate::notify::{self, UpdateType};
+use crate::quota::{self, Object};
+use crate::time;
+use crate::Env;
+
+#[utoipa::path(
+    get,
+    path = "/api/folders",
+    tag = "folders",
+    responses((status = 200, description = "Success", body = ListResponse<FolderResponse>)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn list_folders(
     claims: Claims,
     State(env): State<Arc<Env>>,
-) -> Result<Json<Value>, AppError> {
+) -> Result<Json<ListResponse<FolderResponse>>, AppError> {
     let db = db::get_db(&env)?;
 
-    let folders_db: Vec<Folder> = db
-        .prepare("SELECT * FROM folders WHERE user_id = ?1")
-        .bind(&[claims.sub.clone().into()])?
-        .all()
-        .await?
-        .results()
-        .map_err(|_| AppError::Database)?;
+    let folders_db = scoped::user_folders(&db, &claims.sub)
+        .await
+        .map_err(db_error!())?;
 
     let folders: Vec<FolderResponse> = folders_db.into_iter().map(|f| f.into()).collect();
 
-    Ok(Json(json!({
-        "data": folders,
-        "object": "list",
-        "continuationToken": null,
-    })))
+    Ok(Json(ListResponse::new(folders)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/folders/{id}",
+    tag = "folders",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = FolderResponse)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_folder(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path(id): Path<String>,
+    AppPath(id): AppPath<String>,
 ) -> Result<Json<FolderResponse>, AppError> {
     let db = db::get_db(&env)?;
 
-    let folder: Folder = query!(
-        &db,
-        "SELECT * FROM folders WHERE id = ?1 AND user_id = ?2",
-        &id,
-        &claims.sub
-    )
-    .map_err(|_| AppError::Database)?
-    .first(None)
-    .await?
-    .ok_or_else(|| {
-        AppError::BadRequest(
-            "Invalid folder: Folder does not exist or belongs to another user".to_string(),
-        )
-    })?;
+    let folder = scoped::folder_by_id(&db, &claims.sub, &id)
+        .await
+        .map_err(db_error!())?
+        .ok_or(AppError::NotFound("Folder not found".to_string()))?;
 
     Ok(Json(folder.into()))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/folders",
+    tag = "folders",
+    request_body = CreateFolderRequest,
+    responses((status = 200, description = "Success", body = FolderResponse)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn create_folder(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Json(payload): Json<CreateFolderRequest>,
+    AppJson(payload): AppJson<CreateFolderRequest>,
 ) -> Result<Json<FolderResponse>, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now();
-    let now = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let now = time::now_bw();
 
     let folder = Folder {
         id: Uuid::new_v4().to_string(),
@@ -79,20 +86,39 @@ pub async fn create_folder(
         updated_at: now.clone(),
     };
 
-    query!(
+    quota::check(&db, &env, &claims.sub, &[(Object::Folder, 1)]).await?;
+    Database::batch(
         &db,
-        "INSERT INTO folders (id, user_id, name, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-        folder.id,
-        folder.user_id,
-        folder.name,
-        folder.created_at,
-        folder.updated_at
+        vec![
+            Database::prepare(
+                &db,
+                "INSERT INTO folders (id, user_id, name, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                &[
+                    folder.id.as_str().into(),
+                    folder.user_id.as_str().into(),
+                    folder.name.as_str().into(),
+                    folder.created_at.as_str().into(),
+                    folder.updated_at.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+            quota::added(&db, &claims.sub, Object::Folder)?,
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await?;
+    .await
+    .map_err(|err| db::classify_error(err, "The folder"))?;
 
     touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_folder_update(
+        &env,
+        &db,
+        UpdateType::SyncFolderCreate,
+        &claims.sub,
+        &folder.id,
+        &folder.updated_at,
+        claims.device.as_deref(),
+    )
+    .await;
 
     let response = FolderResponse {
         id: folder.id,
@@ -104,49 +130,69 @@ pub async fn create_folder(
     Ok(Json(response))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/folders/{id}",
+    tag = "folders",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn delete_folder(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path(id): Path<String>,
+    AppPath(id): AppPath<String>,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
 
-    query!(
+    Database::batch(
         &db,
-        "DELETE FROM folders WHERE id = ?1 AND user_id = ?2",
-        id,
-        claims.sub
+        vec![
+            scoped::delete_folder_statement(&db, &claims.sub, &id).map_err(db_error!())?,
+            quota::removed(&db, &claims.sub, Object::Folder)?,
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await?;
+    .await
+    .map_err(db_error!())?;
 
     touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_folder_update(
+        &env,
+        &db,
+        UpdateType::SyncFolderDelete,
+        &claims.sub,
+        &id,
+        &time::now_bw(),
+        claims.device.as_deref(),
+    )
+    .await;
 
     Ok(Json(()))
 }
+#[utoipa::path(
+    method(post, put),
+    path = "/api/folders/{id}",
+    tag = "folders",
+    params(("id" = String, Path)),
+    request_body = CreateFolderRequest,
+    responses((status = 200, description = "Success", body = FolderResponse)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn update_folder(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Path(id): Path<String>,
-    Json(payload): Json<CreateFolderRequest>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<CreateFolderRequest>,
 ) -> Result<Json<FolderResponse>, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now();
-    let now = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let now = time::now_bw();
 
-    let existing_folder: Folder = query!(
-        &db,
-        "SELECT * FROM folders WHERE id = ?1 AND user_id = ?2",
-        id,
-        claims.sub
-    )
-    .map_err(|_| AppError::Database)?
-    .first(None)
-    .await?
-    .ok_or(AppError::NotFound("Folder not found".to_string()))?;
+    let existing_folder = scoped::folder_by_id(&db, &claims.sub, &id)
+        .await
+        .map_err(db_error!())?
+        .ok_or(AppError::NotFound("Folder not found".to_string()))?;
 
     let folder = Folder {
         id: id.clone(),
@@ -156,19 +202,27 @@ pub async fn update_folder(
         updated_at: now.clone(),
     };
 
-    query!(
+    scoped::rename_folder(
         &db,
-        "UPDATE folders SET name = ?1, updated_at = ?2 WHERE id = ?3 AND user_id = ?4",
-        folder.name,
-        folder.updated_at,
-        folder.id,
-        folder.user_id
+        &folder.user_id,
+        &folder.id,
+        &folder.name,
+        &folder.updated_at,
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await?;
+    .await
+    .map_err(db_error!())?;
 
     touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_folder_update(
+        &env,
+        &db,
+        UpdateType::SyncFolderUpdate,
+        &claims.sub,
+        &folder.id,
+        &folder.updated_at,
+        claims.device.as_deref(),
+    )
+    .await;
 
     let response = FolderResponse {
         id: folder.id,
@@ -0,0 +1,783 @@
+//! Organization groups: sets of members that are given collection access together.
+//!
+//! A member's access to a collection is the union of their own assignment and those of their
+//! groups. Groups are only offered to organizations that use them (`useGroups`).
+
+use axum::{extract::State, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
+use crate::{
+    auth::Claims,
+    db::{self, Database, Db, Statement},
+    error::{db_error, internal_error, AppError},
+    handlers::{
+        collections,
+        events::{self, member_event, EventSource},
+        organizations::{find_organization_for_member, touch_members_statement},
+    },
+    models::{
+        event::{Event, EventType},
+        group::{Group, GroupRequest, MemberGroupsRequest},
+        organization::{Membership, Organization},
+    },
+    time,
+};
+
+fn group_not_found() -> AppError {
+    AppError::NotFound("Group not found".to_string())
+}
+
+fn require_groups_enabled(org: &Organization) -> Result<(), AppError> {
+    if !org.use_groups {
+        return Err(AppError::BadRequest(
+            "Groups are not enabled for this organization".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Loads the member's organization membership, checking that the organization uses groups and
+/// that they may view them (owners, admins and managers) or, with `manage`, change them (owners
+/// and admins).
+async fn membership_for_groups(
+    db: &Db,
+    org_id: &str,
+    user_id: &str,
+    manage: bool,
+) -> Result<Membership, AppError> {
+    let (org, membership) = find_organization_for_member(db, org_id, user_id).await?;
+    require_groups_enabled(&org)?;
+    if manage && !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can manage groups".to_string(),
+        ));
+    }
+    if !membership.is_manager() {
+        return Err(AppError::Forbidden(
+            "You don't have permission to view the organization's groups".to_string(),
+        ));
+    }
+    Ok(membership)
+}
+
+async fn find_group(db: &Db, org_id: &str, group_id: &str) -> Result<Group, AppError> {
+    db.first(
+        "SELECT * FROM groups WHERE id = ?1 AND organization_id = ?2",
+        &[group_id.into(), org_id.into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(group_not_found)
+}
+
+async fn list_groups(db: &Db, org_id: &str) -> Result<Vec<Group>, AppError> {
+    db.all(
+        "SELECT * FROM groups WHERE organization_id = ?1 ORDER BY name",
+        &[org_id.into()],
+    )
+    .await
+    .map_err(db_error!())
+}
+
+#[derive(Deserialize)]
+struct GroupMember {
+    group_id: String,
+    membership_id: String,
+}
+
+async fn list_group_members(db: &Db, org_id: &str) -> Result<Vec<GroupMember>, AppError> {
+    db.all(
+        "SELECT gu.group_id, gu.membership_id FROM groups_users gu
+         JOIN groups g ON g.id = gu.group_id
+         WHERE g.organization_id = ?1",
+        &[org_id.into()],
+    )
+    .await
+    .map_err(db_error!())
+}
+
+/// Group ids of every member of an organization, keyed by membership id, as the `groups` of the
+/// member details.
+pub(crate) async fn list_member_groups(
+    db: &Db,
+    org_id: &str,
+) -> Result<HashMap<String, Vec<String>>, AppError> {
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for row in list_group_members(db, org_id).await? {
+        groups
+            .entry(row.membership_id)
+            .or_default()
+            .push(row.group_id);
+    }
+    Ok(groups)
+}
+
+/// Checks that every id is a row of `table` in the organization.
+async fn check_in_organization(
+    db: &Db,
+    table: &str,
+    org_id: &str,
+    ids: &[String],
+    error: &str,
+) -> Result<(), AppError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let ids_json = serde_json::to_string(ids).map_err(internal_error!())?;
+    let known: Option<u32> = db
+        .first_column(
+            &format!(
+                "SELECT COUNT(*) AS count FROM {table}
+             WHERE organization_id = ?1 AND id IN (SELECT value FROM json_each(?2))"
+            ),
+            &[org_id.into(), ids_json.into()],
+            "count",
+        )
+        .await
+        .map_err(db_error!())?;
+    if known.unwrap_or(0) as usize != ids.len() {
+        return Err(AppError::BadRequest(error.to_string()));
+    }
+    Ok(())
+}
+
+fn dedup(mut ids: Vec<String>) -> Vec<String> {
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Statements replacing a group's members. Every membership must belong to the group's
+/// organization.
+async fn group_member_statements(
+    db: &Db,
+    group: &Group,
+    membership_ids: Vec<String>,
+) -> Result<Vec<Statement>, AppError> {
+    let membership_ids = dedup(membership_ids);
+    check_in_organization(
+        db,
+        "organization_users",
+        &group.organization_id,
+        &membership_ids,
+        "Group members must be members of the organization",
+    )
+    .await?;
+
+    let mut statements = vec![Database::prepare(
+        db,
+        "DELETE FROM groups_users WHERE group_id = ?1",
+        &[group.id.as_str().into()],
+    )
+    .map_err(db_error!())?];
+    for membership_id in &membership_ids {
+        statements.push(
+            Database::prepare(
+                db,
+                "INSERT INTO groups_users (group_id, membership_id) VALUES (?1, ?2)",
+                &[group.id.as_str().into(), membership_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    Ok(statements)
+}
+
+/// Statements replacing the groups a member belongs to. Every group must belong to the member's
+/// organization.
+pub(crate) async fn member_group_statements(
+    db: &Db,
+    org_id: &str,
+    membership_id: &str,
+    group_ids: Vec<String>,
+) -> Result<Vec<Statement>, AppError> {
+    let group_ids = dedup(group_ids);
+    check_in_organization(
+        db,
+        "groups",
+        org_id,
+        &group_ids,
+        "Group does not belong to the organization",
+    )
+    .await?;
+
+    let mut statements = vec![Database::prepare(
+        db,
+        "DELETE FROM groups_users WHERE membership_id = ?1",
+        &[membership_id.into()],
+    )
+    .map_err(db_error!())?];
+    for group_id in &group_ids {
+        statements.push(
+            Database::prepare(
+                db,
+                "INSERT INTO groups_users (group_id, membership_id) VALUES (?1, ?2)",
+                &[group_id.as_str().into(), membership_id.into()],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    Ok(statements)
+}
+
+fn validate_name(payload: &GroupRequest) -> Result<(), AppError> {
+    if payload.name.trim().is_empty() {
+        return Err(AppError::validation("Name", "Group name is required"));
+    }
+    Ok(())
+}
+
+async fn log_group_event(
+    db: &Db,
+    source: &EventSource,
+    atype: EventType,
+    claims: &Claims,
+    group: &Group,
+) {
+    let event = Event {
+        organization_id: Some(group.organization_id.clone()),
+        group_id: Some(group.id.clone()),
+        ..Event::new(atype, &claims.sub)
+    };
+    events::log_event(db, source, event).await;
+}
+
+/// GET /api/organizations/{id}/groups
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/groups",
+    tag = "groups",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_groups(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, false).await?;
+    let data: Vec<Value> = list_groups(&db, &membership.organization_id)
+        .await?
+        .iter()
+        .map(Group::to_json)
+        .collect();
+
+    Ok(Json(json!({
+        "data": data,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+/// GET /api/organizations/{id}/groups/details
+///
+/// Every group with its collection access.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/groups/details",
+    tag = "groups",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_groups_details(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, false).await?;
+    let org_id = &membership.organization_id;
+    let mut assignments = collections::list_group_assignments(&db, org_id).await?;
+    let data: Vec<Value> = list_groups(&db, org_id)
+        .await?
+        .iter()
+        .map(|group| group.to_details_json(assignments.remove(&group.id).unwrap_or_default()))
+        .collect();
+
+    Ok(Json(json!({
+        "data": data,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+/// GET /api/organizations/{id}/groups/{group_id}
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/groups/{group_id}",
+    tag = "groups",
+    params(("id" = String, Path), ("group_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_group(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, group_id)): AppPath<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, false).await?;
+    let group = find_group(&db, &membership.organization_id, &group_id).await?;
+
+    Ok(Json(group.to_json()))
+}
+
+/// GET /api/organizations/{id}/groups/{group_id}/details
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/groups/{group_id}/details",
+    tag = "groups",
+    params(("id" = String, Path), ("group_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_group_details(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, group_id)): AppPath<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, false).await?;
+    let group = find_group(&db, &membership.organization_id, &group_id).await?;
+    let collections = collections::list_group_assignments(&db, &group.organization_id)
+        .await?
+        .remove(&group.id)
+        .unwrap_or_default();
+
+    Ok(Json(group.to_details_json(collections)))
+}
+
+/// POST /api/organizations/{id}/groups
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{id}/groups",
+    tag = "groups",
+    params(("id" = String, Path)),
+    request_body = GroupRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_group(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+    AppJson(payload): AppJson<GroupRequest>,
+) -> Result<Json<Value>, AppError> {
+    validate_name(&payload)?;
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, true).await?;
+
+    let now = time::now_bw();
+    let group = Group {
+        id: Uuid::new_v4().to_string(),
+        organization_id: membership.organization_id,
+        name: payload.name,
+        external_id: payload.external_id,
+        created_at: now.clone(),
+        updated_at: now.clone(),
+    };
+
+    let mut statements = vec![Database::prepare(
+        &db,
+        "INSERT INTO groups (id, organization_id, name, external_id, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        &[
+            group.id.as_str().into(),
+            group.organization_id.as_str().into(),
+            group.name.as_str().into(),
+            group.external_id.as_deref().into(),
+            group.created_at.as_str().into(),
+            group.updated_at.as_str().into(),
+        ],
+    )
+    .map_err(db_error!())?];
+    statements.extend(
+        collections::group_assignment_statements(
+            &db,
+            &group.organization_id,
+            &group.id,
+            &payload.collections,
+        )
+        .await?,
+    );
+    if let Some(users) = payload.users {
+        statements.extend(group_member_statements(&db, &group, users).await?);
+    }
+    statements.push(touch_members_statement(&db, &group.organization_id, &now)?);
+    Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+    log_group_event(&db, &source, EventType::GroupCreated, &claims, &group).await;
+
+    Ok(Json(group.to_json()))
+}
+
+/// PUT /api/organizations/{id}/groups/{group_id}
+///
+/// Renames the group and replaces its collection access, and its members when `users` is given.
+#[utoipa::path(
+    method(put, post),
+    path = "/api/organizations/{id}/groups/{group_id}",
+    tag = "groups",
+    params(("id" = String, Path), ("group_id" = String, Path)),
+    request_body = GroupRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_group(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, group_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<GroupRequest>,
+) -> Result<Json<Value>, AppError> {
+    validate_name(&payload)?;
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, true).await?;
+    let mut group = find_group(&db, &membership.organization_id, &group_id).await?;
+
+    let now = time::now_bw();
+    group.name = payload.name;
+    group.external_id = payload.external_id;
+    group.updated_at = now.clone();
+
+    let mut statements = vec![Database::prepare(
+        &db,
+        "UPDATE groups SET name = ?1, external_id = ?2, updated_at = ?3 WHERE id = ?4",
+        &[
+            group.name.as_str().into(),
+            group.external_id.as_deref().into(),
+            group.updated_at.as_str().into(),
+            group.id.as_str().into(),
+        ],
+    )
+    .map_err(db_error!())?];
+    statements.extend(
+        collections::group_assignment_statements(
+            &db,
+            &group.organization_id,
+            &group.id,
+            &payload.collections,
+        )
+        .await?,
+    );
+    if let Some(users) = payload.users {
+        statements.extend(group_member_statements(&db, &group, users).await?);
+    }
+    statements.push(touch_members_statement(&db, &group.organization_id, &now)?);
+    Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+    log_group_event(&db, &source, EventType::GroupUpdated, &claims, &group).await;
+
+    Ok(Json(group.to_json()))
+}
+
+/// DELETE /api/organizations/{id}/groups/{group_id}
+///
+/// Members keep the access they have directly or through other groups.
+#[utoipa::path(
+    delete,
+    path = "/api/organizations/{id}/groups/{group_id}",
+    tag = "groups",
+    params(("id" = String, Path), ("group_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn delete_group(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, group_id)): AppPath<(String, String)>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, true).await?;
+    let group = find_group(&db, &membership.organization_id, &group_id).await?;
+
+    let now = time::now_bw();
+    Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "DELETE FROM collections_groups WHERE group_id = ?1",
+                &[group.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM groups_users WHERE group_id = ?1",
+                &[group.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM groups WHERE id = ?1",
+                &[group.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            touch_members_statement(&db, &group.organization_id, &now)?,
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+    log_group_event(&db, &source, EventType::GroupDeleted, &claims, &group).await;
+
+    Ok(Json(()))
+}
+
+/// GET /api/organizations/{id}/groups/{group_id}/users
+///
+/// Membership ids of the group's members.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/groups/{group_id}/users",
+    tag = "groups",
+    params(("id" = String, Path), ("group_id" = String, Path)),
+    responses((status = 200, description = "Success", body = Vec<String>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_group_users(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, group_id)): AppPath<(String, String)>,
+) -> Result<Json<Vec<String>>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, false).await?;
+    let group = find_group(&db, &membership.organization_id, &group_id).await?;
+    let members = list_group_members(&db, &group.organization_id)
+        .await?
+        .into_iter()
+        .filter(|row| row.group_id == group.id)
+        .map(|row| row.membership_id)
+        .collect();
+
+    Ok(Json(members))
+}
+
+/// PUT /api/organizations/{id}/groups/{group_id}/users
+///
+/// Replaces the group's members with the given membership ids.
+#[utoipa::path(
+    put,
+    path = "/api/organizations/{id}/groups/{group_id}/users",
+    tag = "groups",
+    params(("id" = String, Path), ("group_id" = String, Path)),
+    request_body = Vec<String>,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_group_users(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, group_id)): AppPath<(String, String)>,
+    AppJson(membership_ids): AppJson<Vec<String>>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, true).await?;
+    let group = find_group(&db, &membership.organization_id, &group_id).await?;
+
+    let now = time::now_bw();
+    let mut statements = group_member_statements(&db, &group, membership_ids).await?;
+    statements.push(touch_members_statement(&db, &group.organization_id, &now)?);
+    Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+    log_group_event(&db, &source, EventType::GroupUpdated, &claims, &group).await;
+
+    Ok(Json(()))
+}
+
+/// GET /api/organizations/{id}/users/{member_id}/groups
+///
+/// Ids of the groups the member belongs to.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/users/{member_id}/groups",
+    tag = "groups",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    responses((status = 200, description = "Success", body = Vec<String>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_member_groups(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+) -> Result<Json<Vec<String>>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, false).await?;
+    let groups = list_member_groups(&db, &membership.organization_id)
+        .await?
+        .remove(&member_id)
+        .unwrap_or_default();
+
+    Ok(Json(groups))
+}
+
+/// PUT /api/organizations/{id}/users/{member_id}/groups
+///
+/// Replaces the groups the member belongs to.
+#[utoipa::path(
+    method(put, post),
+    path = "/api/organizations/{id}/users/{member_id}/groups",
+    tag = "groups",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    request_body = MemberGroupsRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_member_groups(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<MemberGroupsRequest>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = membership_for_groups(&db, &org_id, &claims.sub, true).await?;
+    let member: Membership = db
+        .first(
+            "SELECT * FROM organization_users WHERE id = ?1 AND organization_id = ?2",
+            &[
+                member_id.as_str().into(),
+                membership.organization_id.as_str().into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("Member not found".to_string()))?;
+
+    let now = time::now_bw();
+    let mut statements =
+        member_group_statements(&db, &member.organization_id, &member.id, payload.group_ids)
+            .await?;
+    if let Some(user_id) = &member.user_id {
+        statements.push(
+            Database::prepare(
+                &db,
+                "UPDATE users SET updated_at = ?1 WHERE id = ?2",
+                &[now.as_str().into(), user_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+    events::log_event(
+        &db,
+        &source,
+        member_event(
+            EventType::OrganizationUserUpdatedGroups,
+            &claims.sub,
+            &member,
+        ),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Database;
+    use crate::models::organization::MembershipType;
+    use crate::native::{block_on, testing};
+    use crate::time;
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[test]
+    fn group_members_lose_access_when_the_organization_stops_using_groups() {
+        let env = testing::env();
+        let alice = testing::user(&env, "alice");
+        let bob = testing::user(&env, "bob");
+        testing::organization(
+            &env,
+            "org",
+            &[
+                ("alice", MembershipType::Owner),
+                ("bob", MembershipType::User),
+            ],
+        );
+        let db = env.d1("vault1").unwrap();
+        let now = time::now_bw();
+        for sql in [
+            "INSERT INTO collections (id, organization_id, name, created_at, updated_at)
+             VALUES ('collection', 'org', '2.Y29s|aXY=|bWFj', ?1, ?1)",
+            "INSERT INTO ciphers (id, user_id, organization_id, type, data, created_at, updated_at)
+             VALUES ('org-cipher', NULL, 'org', 1, '{\"name\":\"2.bmFtZQ==|aXY=|bWFj\"}', ?1, ?1)",
+        ] {
+            block_on(db.run(sql, &[now.as_str().into()])).unwrap();
+        }
+        block_on(db.run(
+            "INSERT INTO ciphers_collections (cipher_id, collection_id) VALUES ('org-cipher', 'collection')",
+            &[],
+        ))
+        .unwrap();
+        let use_groups = |enabled: bool| {
+            let (status, org) = testing::request(
+                &env,
+                Method::PUT,
+                "/api/organizations/org",
+                Some(&alice),
+                Some(json!({ "name": "Org", "useGroups": enabled })),
+            );
+            assert_eq!(status, StatusCode::OK, "{org}");
+            assert_eq!(org["useGroups"], enabled);
+        };
+        let bob_sees_the_cipher = || {
+            let path = "/api/ciphers/org-cipher";
+            let (status, _) = testing::request(&env, Method::GET, path, Some(&bob), None);
+            status == StatusCode::OK
+        };
+
+        let groups = "/api/organizations/org/groups";
+        let (status, _) = testing::request(&env, Method::GET, groups, Some(&alice), None);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        use_groups(true);
+        let (status, group) = testing::request(
+            &env,
+            Method::POST,
+            groups,
+            Some(&alice),
+            Some(json!({
+                "name": "Everyone",
+                "collections": [{ "id": "collection" }],
+                "users": ["bob-membership"],
+            })),
+        );
+        assert_eq!(status, StatusCode::OK, "{group}");
+        assert!(bob_sees_the_cipher());
+
+        use_groups(false);
+        assert!(!bob_sees_the_cipher());
+        let (status, _) = testing::request(&env, Method::GET, groups, Some(&alice), None);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        use_groups(true);
+        assert!(bob_sees_the_cipher());
+    }
+}
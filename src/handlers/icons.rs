@@ -0,0 +1,416 @@
+//! Website icons shown next to vault items, served at /icons/{domain}/icon.png.
+//!
+//! Icons are fetched from the site itself (its /favicon.ico, or the icon its home page links to)
+//! and kept in the Workers cache, missing ones included. ICON_SERVICE can delegate to an
+//! external icon service instead.
+
+use axum::{
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION, X_CONTENT_TYPE_OPTIONS},
+        StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Extension,
+};
+use futures_util::{Stream, StreamExt};
+use std::sync::Arc;
+use worker::{Cache, Fetch, Headers, Method, Request, RequestInit, RequestRedirect, Url};
+
+use crate::config::Settings;
+use crate::extract::AppPath;
+use crate::{
+    error::{internal_error, AppError},
+    BaseUrl,
+};
+
+/// Larger icons are ignored.
+const MAX_ICON_BYTES: usize = 512 * 1024;
+/// Home pages larger than this aren't searched for icon links.
+const MAX_PAGE_BYTES: usize = 1024 * 1024;
+const MAX_REDIRECTS: usize = 5;
+const USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
+
+/// Top-level domains that never point at public websites.
+const PRIVATE_TLDS: &[&str] = &[
+    "arpa",
+    "corp",
+    "example",
+    "home",
+    "internal",
+    "intranet",
+    "invalid",
+    "lan",
+    "local",
+    "localdomain",
+    "localhost",
+    "onion",
+    "private",
+    "test",
+];
+
+/// Whether `domain` is a public host name worth fetching an icon from. IP addresses, single-label
+/// names and private suffixes are refused so the icon fetcher can't be pointed at internal hosts.
+fn is_public_domain(domain: &str) -> bool {
+    if domain.is_empty() || domain.len() > 253 {
+        return false;
+    }
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return false;
+    }
+    let valid_labels = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+    });
+    // Real top-level domains are alphabetic (or punycode), which also rules out IPv4 addresses
+    let tld = labels[labels.len() - 1];
+    let valid_tld = tld.starts_with("xn--") || tld.bytes().all(|b| b.is_ascii_lowercase());
+
+    valid_labels && valid_tld && !PRIVATE_TLDS.contains(&tld)
+}
+
+/// The image type of `bytes`, judged by its signature. SVG isn't accepted: served from the
+/// vault's origin it could run scripts.
+fn image_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0x00, 0x00, 0x01, 0x00, ..] => Some("image/x-icon"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        _ => None,
+    }
+}
+
+/// Fetches `url`, following redirects only to other public hosts. Returns the final response if
+/// it was successful.
+async fn fetch_public(mut url: Url) -> Result<Option<worker::Response>, AppError> {
+    for _ in 0..=MAX_REDIRECTS {
+        let public = matches!(url.scheme(), "http" | "https")
+            && url.port().is_none()
+            && url.host_str().is_some_and(is_public_domain);
+        if !public {
+            return Ok(None);
+        }
+
+        let headers = Headers::new();
+        headers.set("User-Agent", USER_AGENT)?;
+        let mut init = RequestInit::new();
+        init.with_method(Method::Get)
+            .with_headers(headers)
+            .with_redirect(RequestRedirect::Manual);
+        let response = Fetch::Request(Request::new_with_init(url.as_str(), &init)?)
+            .send()
+            .await?;
+
+        match response.status_code() {
+            200 => return Ok(Some(response)),
+            300..=399 => {
+                let Some(location) = response.headers().get("Location")? else {
+                    return Ok(None);
+                };
+                url = match url.join(&location) {
+                    Ok(next) => next,
+                    Err(_) => return Ok(None),
+                };
+            }
+            _ => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+/// Collects `chunks` unless they add up to more than `limit` bytes, in which case the rest
+/// isn't pulled.
+async fn collect_limited(
+    chunks: impl Stream<Item = worker::Result<Vec<u8>>>,
+    limit: usize,
+) -> worker::Result<Option<Vec<u8>>> {
+    let mut chunks = std::pin::pin!(chunks);
+    let mut bytes = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+/// The body of `response` if it is at most `limit` bytes. A larger Content-Length is refused
+/// before reading anything; otherwise reading stops as soon as the body goes past the limit.
+async fn read_limited(
+    response: &mut worker::Response,
+    limit: usize,
+) -> Result<Option<Vec<u8>>, AppError> {
+    let declared = response.headers().get("Content-Length")?;
+    if declared
+        .and_then(|length| length.trim().parse::<usize>().ok())
+        .is_some_and(|length| length > limit)
+    {
+        return Ok(None);
+    }
+    Ok(collect_limited(response.stream()?, limit).await?)
+}
+
+/// The image at `url`, if it is one of a reasonable size.
+async fn fetch_image(url: Url) -> Result<Option<(Vec<u8>, &'static str)>, AppError> {
+    let Some(mut response) = fetch_public(url).await? else {
+        return Ok(None);
+    };
+    let Some(bytes) = read_limited(&mut response, MAX_ICON_BYTES).await? else {
+        return Ok(None);
+    };
+    Ok(image_type(&bytes).map(|content_type| (bytes, content_type)))
+}
+
+/// The value of the attribute `name` in an HTML start tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let bytes = tag.as_bytes();
+    let len = bytes.len();
+    let skip_whitespace = |mut i: usize| {
+        while i < len && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    };
+
+    // Skip the tag name
+    let mut i = 0;
+    while i < len && !bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    while i < len {
+        i = skip_whitespace(i);
+        let key_start = i;
+        while i < len && !bytes[i].is_ascii_whitespace() && bytes[i] != b'=' {
+            i += 1;
+        }
+        let key = &tag[key_start..i];
+        i = skip_whitespace(i);
+
+        let mut value = "";
+        if i < len && bytes[i] == b'=' {
+            i = skip_whitespace(i + 1);
+            if i < len && (bytes[i] == b'"' || bytes[i] == b'\'') {
+                let quote = bytes[i];
+                let value_start = i + 1;
+                i = value_start;
+                while i < len && bytes[i] != quote {
+                    i += 1;
+                }
+                value = &tag[value_start..i];
+                i += 1;
+            } else {
+                let value_start = i;
+                while i < len && !bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                value = &tag[value_start..i];
+            }
+        }
+
+        if key.eq_ignore_ascii_case(name) {
+            return Some(value.replace("&amp;", "&"));
+        }
+        if i == key_start {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Icons linked from a page, favicons before Apple touch icons.
+fn icon_links(html: &str, page: &Url) -> Vec<Url> {
+    // ASCII lowercasing keeps byte offsets, so they can be used on the original
+    let lower = html.to_ascii_lowercase();
+    let mut icons = Vec::new();
+    let mut touch_icons = Vec::new();
+
+    let mut offset = 0;
+    while let Some(found) = lower[offset..].find("<link") {
+        let start = offset + found;
+        let end = lower[start..]
+            .find('>')
+            .map_or(lower.len(), |found| start + found);
+        offset = end;
+
+        let tag = html[start..end].trim_end_matches('/');
+        let (Some(rel), Some(href)) = (attribute(tag, "rel"), attribute(tag, "href")) else {
+            continue;
+        };
+        let Ok(url) = page.join(href.trim()) else {
+            continue;
+        };
+        let rel = rel.to_ascii_lowercase();
+        if rel.split_whitespace().any(|rel| rel == "icon") {
+            icons.push(url);
+        } else if rel.contains("apple-touch-icon") {
+            touch_icons.push(url);
+        }
+    }
+
+    icons.extend(touch_icons);
+    icons
+}
+
+/// Looks for the domain's icon: its /favicon.ico first, then the icons its home page links to.
+async fn find_icon(domain: &str) -> Result<Option<(Vec<u8>, &'static str)>, AppError> {
+    let home = Url::parse(&format!("https://{domain}/")).map_err(internal_error!())?;
+    let favicon = home.join("/favicon.ico").map_err(internal_error!())?;
+    if let Some(icon) = fetch_image(favicon).await? {
+        return Ok(Some(icon));
+    }
+
+    let Some(mut page) = fetch_public(home.clone()).await? else {
+        return Ok(None);
+    };
+    let Some(html) = read_limited(&mut page, MAX_PAGE_BYTES).await? else {
+        return Ok(None);
+    };
+    let html = String::from_utf8_lossy(&html);
+    for link in icon_links(&html, &home) {
+        if let Some(icon) = fetch_image(link).await? {
+            return Ok(Some(icon));
+        }
+    }
+    Ok(None)
+}
+
+fn icon_response(icon: Option<(Vec<u8>, &str)>, ttl: usize, negative_ttl: usize) -> Response {
+    match icon {
+        Some((bytes, content_type)) => (
+            [
+                (CONTENT_TYPE, content_type.to_string()),
+                (CACHE_CONTROL, format!("public, max-age={ttl}")),
+                (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            ],
+            bytes,
+        )
+            .into_response(),
+        // Clients show their placeholder for missing icons
+        None => (
+            StatusCode::NOT_FOUND,
+            [(CACHE_CONTROL, format!("public, max-age={negative_ttl}"))],
+        )
+            .into_response(),
+    }
+}
+
+/// Reads a cached icon lookup: `Some(None)` is a cached miss.
+async fn cached_icon(cache: &Cache, key: &str) -> Option<Option<(Vec<u8>, &'static str)>> {
+    let mut cached = cache.get(key, false).await.ok()??;
+    if cached.status_code() != 200 {
+        return Some(None);
+    }
+    let bytes = cached.bytes().await.ok()?;
+    let content_type = image_type(&bytes)?;
+    Some(Some((bytes, content_type)))
+}
+
+async fn cache_icon(
+    cache: &Cache,
+    key: &str,
+    icon: Option<&(Vec<u8>, &str)>,
+    ttl: usize,
+) -> Result<(), AppError> {
+    let headers = Headers::new();
+    headers.set("Cache-Control", &format!("public, max-age={ttl}"))?;
+    let response = match icon {
+        Some((bytes, content_type)) => {
+            headers.set("Content-Type", content_type)?;
+            worker::Response::from_bytes(bytes.clone())?
+        }
+        None => worker::Response::empty()?.with_status(404),
+    };
+    cache.put(key, response.with_headers(headers)).await?;
+    Ok(())
+}
+
+/// GET /icons/{domain}/icon.png
+#[utoipa::path(
+    get,
+    path = "/icons/{domain}/icon.png",
+    tag = "icons",
+    params(("domain" = String, Path)),
+    responses((status = 200, description = "Success"))
+)]
+#[worker::send]
+pub async fn get_icon(
+    Extension(settings): Extension<Arc<Settings>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    AppPath(domain): AppPath<String>,
+) -> Response {
+    let ttl = settings.icon_cache_ttl;
+    let negative_ttl = settings.icon_cache_negttl;
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    if !is_public_domain(&domain) {
+        return icon_response(None, ttl, negative_ttl);
+    }
+
+    if let Some(service) = &settings.icon_service {
+        return (
+            StatusCode::FOUND,
+            [
+                (LOCATION, service.replace("{}", &domain)),
+                (CACHE_CONTROL, format!("public, max-age={ttl}")),
+            ],
+        )
+            .into_response();
+    }
+
+    let cache = Cache::default();
+    let key = format!("{base_url}/icons/{domain}/icon.png");
+    if let Some(icon) = cached_icon(&cache, &key).await {
+        return icon_response(icon, ttl, negative_ttl);
+    }
+
+    let icon = find_icon(&domain).await.unwrap_or_else(|err| {
+        log::debug!("Failed to fetch the icon of {domain}: {err:?}");
+        None
+    });
+    let cache_ttl = if icon.is_some() { ttl } else { negative_ttl };
+    if let Err(err) = cache_icon(&cache, &key, icon.as_ref(), cache_ttl).await {
+        log::warn!("Failed to cache the icon of {domain}: {err:?}");
+    }
+    icon_response(icon, ttl, negative_ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::block_on;
+    use futures_util::stream;
+    use std::cell::Cell;
+
+    #[test]
+    fn bodies_within_the_limit_are_collected() {
+        let chunks = stream::iter([Ok(vec![1; 3]), Ok(vec![2; 2])]);
+
+        assert_eq!(
+            block_on(collect_limited(chunks, 5)).unwrap(),
+            Some(vec![1, 1, 1, 2, 2])
+        );
+    }
+
+    #[test]
+    fn reading_stops_once_the_body_goes_past_the_limit() {
+        // An endless body, as a hostile site could serve
+        let pulled = Cell::new(0);
+        let chunks = stream::repeat_with(|| {
+            pulled.set(pulled.get() + 1);
+            Ok(vec![0; 1024])
+        });
+
+        assert_eq!(block_on(collect_limited(chunks, 4096)).unwrap(), None);
+        assert_eq!(pulled.get(), 5);
+    }
+}
@@ -1,24 +1,38 @@
-use axum::{extract::State, Form, Json};
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request, State},
+    Extension, Json,
+};
 use chrono::{Duration, Utc};
 use constant_time_eq::constant_time_eq;
-use jwt_compact::AlgorithmExt;
-use jwt_compact::{alg::Hs256Key, Claims as JwtClaims, Header, UntrustedToken};
-use serde::{Deserialize, Deserializer, Serialize};
+use jwt_compact::Claims as JwtClaims;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use std::sync::Arc;
-use worker::{query, Env};
+use utoipa::ToSchema;
 
+use crate::config::Settings;
+use crate::Env;
 use crate::{
-    auth::{jwt_time_options, Claims},
+    auth::{
+        device_verification, jwt_time_options, keys::KeyRing, token_audience, token_issuer,
+        validate_token_times, Claims,
+    },
     crypto::{ct_eq, generate_salt, hash_password_for_storage, validate_totp},
-    db,
-    error::AppError,
+    db::{self, Database, Db, Retry},
+    error::{db_error, internal_error, AppError},
     handlers::{
-        allow_totp_drift, server_password_iterations,
+        auth_requests::consume_auth_request,
+        devices::{register_device, start_device_session, touch_device_session},
+        events::{log_account_event, log_login_events, EventSource},
+        policies::master_password_policy,
         twofactor::{is_twofactor_enabled, list_user_twofactors},
     },
+    models::device::{device_type_name, Device},
+    models::event::EventType,
     models::twofactor::{RememberTokenData, TwoFactor, TwoFactorType},
     models::user::User,
+    time,
 };
 
 /// Deserialize an Option<i32> that may have trailing/leading whitespace.
@@ -45,9 +59,42 @@ where
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// `application/x-www-form-urlencoded` extractor that tolerates what real clients send.
+///
+/// Unlike `axum::Form` it ignores the `Content-Type` header (some clients send charset variations
+/// or omit it), keeps the first value of duplicate keys instead of failing, and reports parse
+/// failures as an OAuth `invalid_request` error. Unknown fields are ignored by `T` itself.
+pub struct LenientForm<T>(pub T);
+
+impl<T, S> FromRequest<S> for LenientForm<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|_| AppError::OAuth("invalid_request", "Invalid request body".to_string()))?;
+
+        let mut fields = serde_json::Map::new();
+        for (key, value) in form_urlencoded::parse(&body) {
+            fields
+                .entry(key.into_owned())
+                .or_insert_with(|| Value::String(value.into_owned()));
+        }
+
+        serde_json::from_value(Value::Object(fields))
+            .map(LenientForm)
+            .map_err(|e| AppError::OAuth("invalid_request", e.to_string()))
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct TokenRequest {
     grant_type: String,
+    scope: Option<String>,
     username: Option<String>,
     password: Option<String>, // This is the masterPasswordHash
     refresh_token: Option<String>,
@@ -68,9 +115,23 @@ pub struct TokenRequest {
     two_factor_remember: Option<i32>,
     #[serde(rename = "deviceIdentifier")]
     device_identifier: Option<String>,
+    #[serde(rename = "deviceName")]
+    device_name: Option<String>,
+    #[serde(
+        rename = "deviceType",
+        default,
+        deserialize_with = "deserialize_trimmed_i32"
+    )]
+    device_type: Option<i32>,
+    // Login with device: approved auth request id, with the access code sent as `password`
+    #[serde(rename = "authRequest")]
+    auth_request: Option<String>,
+    // New device verification: the code emailed after a login from an unknown device
+    #[serde(rename = "newDeviceOtp")]
+    new_device_otp: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "PascalCase")]
 pub struct TokenResponse {
     #[serde(rename = "access_token")]
@@ -81,6 +142,8 @@ pub struct TokenResponse {
     token_type: String,
     #[serde(rename = "refresh_token")]
     refresh_token: String,
+    #[serde(rename = "scope")]
+    scope: String,
     #[serde(rename = "Key")]
     key: String,
     #[serde(rename = "PrivateKey")]
@@ -103,17 +166,38 @@ pub struct TokenResponse {
     account_keys: serde_json::Value,
     #[serde(rename = "TwoFactorToken", skip_serializing_if = "Option::is_none")]
     two_factor_token: Option<String>,
+    /// Strictest master password requirements of the user's organizations, checked by clients
+    /// on password login and change.
+    #[serde(
+        rename = "MasterPasswordPolicy",
+        skip_serializing_if = "Option::is_none"
+    )]
+    master_password_policy: Option<Value>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "PascalCase")]
 pub struct UserDecryptionOptions {
     pub has_master_password: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub master_password_unlock: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trusted_device_option: Option<TrustedDeviceOption>,
     pub object: String,
 }
 
+/// Trusted device encryption state of the logging-in device.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "PascalCase")]
+pub struct TrustedDeviceOption {
+    pub has_admin_approval: bool,
+    pub has_login_approving_device: bool,
+    pub has_manage_reset_password_permission: bool,
+    pub is_tde_offboarding: bool,
+    pub encrypted_private_key: Option<String>,
+    pub encrypted_user_key: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct RefreshClaims {
     pub sub: String, // User ID
@@ -123,16 +207,50 @@ struct RefreshClaims {
     // currently avoid device/session management. If we later add minimal device state, we can add
     // refresh token rotation (jti/family) + reuse detection on top.
     pub sstamp: String,
+
+    // Login context, carried over so refreshed access tokens keep the same claims.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub two_factor: bool,
+    /// Device session id; must match the device's current session for the refresh to succeed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Issuer; empty for refresh tokens issued before it was introduced.
+    #[serde(default)]
+    pub iss: String,
+}
+
+/// How the user authenticated, reflected in the issued token claims.
+struct LoginContext {
+    device: Option<String>,
+    two_factor: bool,
+    trusted_device_option: Option<TrustedDeviceOption>,
+    scope: &'static str,
+    session: Option<String>,
+    master_password_policy: Option<Value>,
+}
+
+impl LoginContext {
+    fn amr(&self) -> Vec<String> {
+        let mut amr = vec!["Application".to_string()];
+        if self.two_factor {
+            amr.push("mfa".to_string());
+        }
+        amr
+    }
 }
 
 fn generate_tokens_and_response(
     user: User,
     env: &Arc<Env>,
+    settings: &Settings,
+    login: LoginContext,
     two_factor_token: Option<String>,
 ) -> Result<Json<TokenResponse>, AppError> {
     let now = Utc::now();
     let expires_in = Duration::hours(1);
-    let time_options = jwt_time_options();
+    let time_options = jwt_time_options(settings);
 
     let access_claims = JwtClaims::new(Claims {
         sub: user.id.clone(),
@@ -140,30 +258,29 @@ fn generate_tokens_and_response(
         premium: true,
         name: user.name.clone().unwrap_or_else(|| "User".to_string()),
         email: user.email.clone(),
-        email_verified: true,
-        amr: vec!["Application".into()],
+        email_verified: user.email_verified,
+        amr: login.amr(),
+        device: login.device.clone(),
+        iss: token_issuer(settings),
+        aud: token_audience(settings),
     })
     .set_duration_and_issuance(&time_options, expires_in)
     .set_not_before(now);
 
-    let jwt_secret = env.secret("JWT_SECRET")?.to_string();
-    let access_key = Hs256Key::new(jwt_secret.as_bytes());
-    let access_token = jwt_compact::alg::Hs256
-        .token(&Header::empty(), &access_claims, &access_key)
-        .map_err(|_| AppError::Crypto("Failed to create access token".to_string()))?;
+    let access_token = KeyRing::access(env)?.sign(&access_claims)?;
 
     let refresh_expires_in = Duration::days(30);
     let refresh_claims = JwtClaims::new(RefreshClaims {
         sub: user.id,
         sstamp: user.security_stamp,
+        device: login.device,
+        two_factor: login.two_factor,
+        session: login.session,
+        iss: token_issuer(settings),
     })
     .set_duration_and_issuance(&time_options, refresh_expires_in)
     .set_not_before(now);
-    let jwt_refresh_secret = env.secret("JWT_REFRESH_SECRET")?.to_string();
-    let refresh_key = Hs256Key::new(jwt_refresh_secret.as_bytes());
-    let refresh_token = jwt_compact::alg::Hs256
-        .token(&Header::empty(), &refresh_claims, &refresh_key)
-        .map_err(|_| AppError::Crypto("Failed to create refresh token".to_string()))?;
+    let refresh_token = KeyRing::refresh(env)?.sign(&refresh_claims)?;
 
     let has_master_password = !user.master_password_hash.is_empty();
     let master_password_unlock = if has_master_password {
@@ -198,32 +315,64 @@ fn generate_tokens_and_response(
         expires_in: expires_in.num_seconds(),
         token_type: "Bearer".to_string(),
         refresh_token,
+        scope: login.scope.to_string(),
         key: user.key,
         private_key: user.private_key,
         kdf: user.kdf_type,
         kdf_iterations: user.kdf_iterations,
         kdf_memory: user.kdf_memory,
         kdf_parallelism: user.kdf_parallelism,
-        force_password_reset: false,
+        force_password_reset: user.force_password_reset,
         reset_master_password: false,
         user_decryption_options: UserDecryptionOptions {
             has_master_password,
             master_password_unlock,
+            trusted_device_option: login.trusted_device_option,
             object: "userDecryptionOptions".to_string(),
         },
         account_keys,
         two_factor_token,
+        master_password_policy: login.master_password_policy,
     }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/identity/connect/token",
+    tag = "identity",
+    request_body(content = TokenRequest, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 200, description = "Success", body = TokenResponse))
+)]
 #[worker::send]
 pub async fn token(
     State(env): State<Arc<Env>>,
-    Form(payload): Form<TokenRequest>,
+    Extension(settings): Extension<Arc<Settings>>,
+    source: EventSource,
+    retry: Retry,
+    LenientForm(payload): LenientForm<TokenRequest>,
+) -> Result<Json<TokenResponse>, AppError> {
+    // The identity clients read `error`/`error_description`, not the API error model.
+    issue_token(env, settings, source, retry, payload)
+        .await
+        .map_err(AppError::into_oauth)
+}
+
+async fn issue_token(
+    env: Arc<Env>,
+    settings: Arc<Settings>,
+    source: EventSource,
+    retry: Retry,
+    payload: TokenRequest,
 ) -> Result<Json<TokenResponse>, AppError> {
-    let db = db::get_db(&env)?;
+    let db = &db::get_db(&env)?;
+    let scope = granted_scope(&payload.grant_type, payload.scope.as_deref())?;
     match payload.grant_type.as_str() {
         "password" => {
+            let source = EventSource {
+                // The token request names the client's device type in its body
+                device_type: payload.device_type.unwrap_or(source.device_type),
+                ..source
+            };
             let username = payload
                 .username
                 .ok_or_else(|| AppError::BadRequest("Missing username".to_string()))?;
@@ -244,27 +393,44 @@ pub async fn token(
                 }
             }
 
-            let user_value: Value = db
-                .prepare("SELECT * FROM users WHERE email = ?1")
-                .bind(&[username.to_lowercase().into()])?
-                .first(None)
+            let user_value: Value = retry
+                .run("token: user", || async {
+                    Ok(db
+                        .first(
+                            "SELECT * FROM users WHERE email = ?1",
+                            &[username.to_lowercase().into()],
+                        )
+                        .await?)
+                })
                 .await
                 .map_err(|_| AppError::Unauthorized("Invalid credentials".to_string()))?
                 .ok_or_else(|| AppError::Unauthorized("Invalid credentials".to_string()))?;
-            let user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
-
-            let verification = user.verify_master_password(&password_hash).await?;
+            let user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
 
-            if !verification.is_valid() {
-                return Err(AppError::Unauthorized("Invalid credentials".to_string()));
-            }
+            // With an approved auth request the access code stands in for the master password.
+            // The approving device already passed 2FA, so (like the official server) we skip it.
+            let verification = match payload.auth_request.as_deref() {
+                Some(auth_request_id) => {
+                    consume_auth_request(db, auth_request_id, &user.id, &password_hash).await?;
+                    None
+                }
+                None => {
+                    let verification = user.verify_master_password(&password_hash).await?;
+                    if !verification.is_valid() {
+                        log_account_event(db, &source, EventType::UserFailedLogIn, &user.id).await;
+                        return Err(AppError::Unauthorized("Invalid credentials".to_string()));
+                    }
+                    Some(verification)
+                }
+            };
 
             // Check for 2FA (TOTP) for this user.
-            let twofactors: Vec<TwoFactor> = list_user_twofactors(&db, &user.id).await?;
+            let twofactors: Vec<TwoFactor> = list_user_twofactors(db, &user.id).await?;
 
             let mut two_factor_remember_token: Option<String> = None;
+            let two_factor = verification.is_some() && is_twofactor_enabled(&twofactors);
 
-            if is_twofactor_enabled(&twofactors) {
+            if two_factor {
                 // Only advertise Authenticator (TOTP) as the real provider for now.
                 let twofactor_ids: Vec<i32> = vec![TwoFactorType::Authenticator as i32];
                 let selected_id = payload.two_factor_provider.unwrap_or(twofactor_ids[0]);
@@ -291,22 +457,35 @@ pub async fn token(
                             })?;
 
                         // Validate TOTP code
-                        let allow_drift = allow_totp_drift(&env);
-                        let new_last_used =
-                            validate_totp(twofactor_code, &tf.data, tf.last_used, allow_drift)
-                                .await?;
+                        let allow_drift = settings.allow_totp_drift;
+                        let new_last_used = match validate_totp(
+                            twofactor_code,
+                            &tf.data,
+                            tf.last_used,
+                            allow_drift,
+                        )
+                        .await
+                        {
+                            Ok(step) => step,
+                            Err(err) => {
+                                log_account_event(
+                                    db,
+                                    &source,
+                                    EventType::UserFailedLogIn2fa,
+                                    &user.id,
+                                )
+                                .await;
+                                return Err(err);
+                            }
+                        };
 
                         // Update last_used
-                        query!(
-                            &db,
+                        db.run(
                             "UPDATE twofactor SET last_used = ?1 WHERE uuid = ?2",
-                            new_last_used,
-                            &tf.uuid
+                            &[new_last_used.into(), tf.uuid.as_str().into()],
                         )
-                        .map_err(|_| AppError::Database)?
-                        .run()
                         .await
-                        .map_err(|_| AppError::Database)?;
+                        .map_err(db_error!())?;
                     }
                     Some(TwoFactorType::Remember) => {
                         // Remember is handled separately - client sends remember token from previous login
@@ -332,16 +511,12 @@ pub async fn token(
 
                                 // Update database with cleaned tokens (remove expired)
                                 let updated_data = token_data.to_json();
-                                query!(
-                                    &db,
+                                db.run(
                                     "UPDATE twofactor SET data = ?1 WHERE uuid = ?2",
-                                    &updated_data,
-                                    &tf.uuid
+                                    &[updated_data.as_str().into(), tf.uuid.as_str().into()],
                                 )
-                                .map_err(|_| AppError::Database)?
-                                .run()
                                 .await
-                                .map_err(|_| AppError::Database)?;
+                                .map_err(db_error!())?;
 
                                 // Remember token valid, proceed with login
                             } else {
@@ -359,27 +534,34 @@ pub async fn token(
                         // Check recovery code
                         if let Some(ref stored_code) = user.totp_recover {
                             if !ct_eq(&stored_code.to_uppercase(), &twofactor_code.to_uppercase()) {
+                                log_account_event(
+                                    db,
+                                    &source,
+                                    EventType::UserFailedLogIn2fa,
+                                    &user.id,
+                                )
+                                .await;
                                 return Err(AppError::BadRequest(
                                     "Recovery code is incorrect".to_string(),
                                 ));
                             }
 
                             // Delete all 2FA and clear recovery code
-                            query!(&db, "DELETE FROM twofactor WHERE user_uuid = ?1", &user.id)
-                                .map_err(|_| AppError::Database)?
-                                .run()
-                                .await
-                                .map_err(|_| AppError::Database)?;
+                            db.run(
+                                "DELETE FROM twofactor WHERE user_uuid = ?1",
+                                &[user.id.as_str().into()],
+                            )
+                            .await
+                            .map_err(db_error!())?;
 
-                            query!(
-                                &db,
+                            db.run(
                                 "UPDATE users SET totp_recover = NULL WHERE id = ?1",
-                                &user.id
+                                &[user.id.as_str().into()],
                             )
-                            .map_err(|_| AppError::Database)?
-                            .run()
                             .await
-                            .map_err(|_| AppError::Database)?;
+                            .map_err(db_error!())?;
+                            log_account_event(db, &source, EventType::UserRecovered2fa, &user.id)
+                                .await;
                         } else {
                             return Err(AppError::BadRequest(
                                 "Recovery code is incorrect".to_string(),
@@ -416,33 +598,48 @@ pub async fn token(
                         let json_data = token_data.to_json();
 
                         // Store or update remember token
-                        query!(
-                            &db,
+                        db.run(
                             "INSERT INTO twofactor (uuid, user_uuid, atype, enabled, data, last_used) 
                              VALUES (?1, ?2, ?3, 1, ?4, 0)
                              ON CONFLICT(user_uuid, atype) DO UPDATE SET data = ?4",
-                            uuid::Uuid::new_v4().to_string(),
-                            &user.id,
-                            TwoFactorType::Remember as i32,
-                            &json_data
+                            &[
+                                uuid::Uuid::new_v4().to_string().into(),
+                                user.id.as_str().into(),
+                                (TwoFactorType::Remember as i32).into(),
+                                json_data.as_str().into(),
+                            ],
                         )
-                        .map_err(|_| AppError::Database)?
-                        .run()
                         .await
-                        .map_err(|_| AppError::Database)?;
+                        .map_err(db_error!())?;
 
                         two_factor_remember_token = Some(remember_token);
                     }
                 }
             }
 
+            // Without two-step login, a password login from a new device needs an emailed code.
+            if verification.is_some() && !two_factor && user.verify_devices {
+                if let Some(kv) = device_verification::store(&env, &settings) {
+                    if is_new_device(db, &user.id, payload.device_identifier.as_deref()).await? {
+                        device_verification::verify(
+                            &env,
+                            &kv,
+                            &user,
+                            payload.new_device_otp.as_deref(),
+                        )
+                        .await?;
+                    }
+                }
+            }
+
             // Migrate/upgrade server-side password hashing parameters on successful verification.
             //
             // - Legacy users (no salt) are upgraded to server-side PBKDF2.
             // - Existing users are upgraded if their per-user iteration count is below the configured minimum.
-            let desired_iterations = server_password_iterations(&env) as i32;
-            let needs_upgrade =
-                verification.needs_migration() || user.password_iterations < desired_iterations;
+            let desired_iterations = settings.password_iterations as i32;
+            let needs_upgrade = verification.is_some_and(|verification| {
+                verification.needs_migration() || user.password_iterations < desired_iterations
+            });
 
             let user = if needs_upgrade {
                 // Generate new salt and hash the password using the desired iterations.
@@ -450,22 +647,21 @@ pub async fn token(
                 let new_hash =
                     hash_password_for_storage(&password_hash, &new_salt, desired_iterations as u32)
                         .await?;
-                let now = Utc::now().to_rfc3339();
+                let now = time::now_bw();
 
                 // Update user in database
-                query!(
-                    &db,
+                db.run(
                     "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, updated_at = ?4 WHERE id = ?5",
-                    &new_hash,
-                    &new_salt,
-                    desired_iterations,
-                    &now,
-                    &user.id
+                    &[
+                        new_hash.as_str().into(),
+                        new_salt.as_str().into(),
+                        desired_iterations.into(),
+                        now.as_str().into(),
+                        user.id.as_str().into(),
+                    ],
                 )
-                .map_err(|_| AppError::Database)?
-                .run()
                 .await
-                .map_err(|_| AppError::Database)?;
+                .map_err(db_error!())?;
 
                 // Return updated user
                 User {
@@ -479,41 +675,68 @@ pub async fn token(
                 user
             };
 
-            generate_tokens_and_response(user, &env, two_factor_remember_token)
+            let (trusted_device_option, session) = match payload.device_identifier.as_deref() {
+                Some(identifier) => {
+                    let atype = payload.device_type.unwrap_or(14); // UnknownBrowser
+                    let name = payload
+                        .device_name
+                        .clone()
+                        .unwrap_or_else(|| device_type_name(atype).to_string());
+                    let device = register_device(db, &user.id, identifier, &name, atype).await?;
+                    let session = start_device_session(db, &device).await?;
+                    (
+                        trusted_device_option(db, &user.id, &device).await?,
+                        Some(session),
+                    )
+                }
+                None => (None, None),
+            };
+
+            let master_password_policy = master_password_policy(db, &user.id)
+                .await?
+                .map(|policy| policy.to_token_json());
+            let login = LoginContext {
+                device: payload.device_identifier,
+                two_factor,
+                trusted_device_option,
+                scope,
+                session,
+                master_password_policy,
+            };
+            log_account_event(db, &source, EventType::UserLoggedIn, &user.id).await;
+            log_login_events(db, &source, &user.id).await;
+            generate_tokens_and_response(user, &env, &settings, login, two_factor_remember_token)
         }
         "refresh_token" => {
             let refresh_token = payload
                 .refresh_token
                 .ok_or_else(|| AppError::BadRequest("Missing refresh_token".to_string()))?;
 
-            let jwt_refresh_secret = env.secret("JWT_REFRESH_SECRET")?.to_string();
-            let refresh_key = Hs256Key::new(jwt_refresh_secret.as_bytes());
-            let token = UntrustedToken::new(&refresh_token)
-                .map_err(|_| AppError::Unauthorized("Invalid refresh token".to_string()))?;
-            let token = jwt_compact::alg::Hs256
-                .validator::<RefreshClaims>(&refresh_key)
-                .validate(&token)
-                .map_err(|_| AppError::Unauthorized("Invalid refresh token".to_string()))?;
-            let time_options = jwt_time_options();
-            token
-                .claims()
-                .validate_expiration(&time_options)
-                .map_err(|_| AppError::Unauthorized("Invalid refresh token".to_string()))?;
-            token
-                .claims()
-                .validate_maturity(&time_options)
-                .map_err(|_| AppError::Unauthorized("Invalid refresh token".to_string()))?;
+            let token = KeyRing::refresh(&env)?
+                .verify::<RefreshClaims>(&refresh_token)
+                .ok_or_else(|| AppError::Unauthorized("Invalid refresh token".to_string()))?;
+            validate_token_times(token.claims(), &jwt_time_options(&settings))?;
 
             let refresh_claims = token.into_parts().1.custom;
+            // Refresh tokens live for 30 days, so ones issued before `iss` existed are still
+            // accepted; any issuer that is present must be ours.
+            if !refresh_claims.iss.is_empty() && refresh_claims.iss != token_issuer(&settings) {
+                return Err(AppError::Unauthorized("Invalid refresh token".to_string()));
+            }
             let user_id = refresh_claims.sub;
-            let user: Value = db
-                .prepare("SELECT * FROM users WHERE id = ?1")
-                .bind(&[user_id.into()])?
-                .first(None)
+            let user: Value = retry
+                .run("token: user", || async {
+                    Ok(db
+                        .first(
+                            "SELECT * FROM users WHERE id = ?1",
+                            &[user_id.clone().into()],
+                        )
+                        .await?)
+                })
                 .await
                 .map_err(|_| AppError::Unauthorized("Invalid user".to_string()))?
                 .ok_or_else(|| AppError::Unauthorized("Invalid user".to_string()))?;
-            let user: User = serde_json::from_value(user).map_err(|_| AppError::Internal)?;
+            let user: User = serde_json::from_value(user).map_err(internal_error!())?;
 
             if !constant_time_eq(
                 refresh_claims.sstamp.as_bytes(),
@@ -522,10 +745,117 @@ pub async fn token(
                 return Err(AppError::Unauthorized("Invalid refresh token".to_string()));
             }
 
-            generate_tokens_and_response(user, &env, None)
+            // Device-bound refresh tokens stop working once that device's session is revoked.
+            if let Some(ref device) = refresh_claims.device {
+                let session = refresh_claims.session.as_deref().unwrap_or_default();
+                let touched = retry
+                    .run("token: device session", || {
+                        touch_device_session(db, &user.id, device, session)
+                    })
+                    .await?;
+                if !touched {
+                    return Err(AppError::Unauthorized("Invalid refresh token".to_string()));
+                }
+            }
+
+            let login = LoginContext {
+                device: refresh_claims.device,
+                two_factor: refresh_claims.two_factor,
+                trusted_device_option: None,
+                scope,
+                session: refresh_claims.session,
+                master_password_policy: None,
+            };
+            generate_tokens_and_response(user, &env, &settings, login, None)
+        }
+        _ => Err(AppError::OAuth(
+            "unsupported_grant_type",
+            "Unsupported grant_type".to_string(),
+        )),
+    }
+}
+
+const SCOPE_PASSWORD: &str = "api offline_access";
+const SCOPE_CLIENT_CREDENTIALS: &str = "api";
+
+/// Validates the requested `scope` for a grant type and returns the granted scope.
+///
+/// Password and refresh grants get `api offline_access`, client credentials only `api`.
+/// A missing scope grants the default; any scope outside the allowed set is `invalid_scope`.
+fn granted_scope(grant_type: &str, requested: Option<&str>) -> Result<&'static str, AppError> {
+    let allowed = match grant_type {
+        "password" | "refresh_token" => SCOPE_PASSWORD,
+        "client_credentials" => SCOPE_CLIENT_CREDENTIALS,
+        _ => {
+            return Err(AppError::OAuth(
+                "unsupported_grant_type",
+                "Unsupported grant_type".to_string(),
+            ))
+        }
+    };
+
+    if let Some(requested) = requested {
+        let allowed_scopes: Vec<&str> = allowed.split(' ').collect();
+        if let Some(unknown) = requested
+            .split_whitespace()
+            .find(|scope| !allowed_scopes.contains(scope))
+        {
+            return Err(AppError::OAuth(
+                "invalid_scope",
+                format!(
+                    "Scope '{}' is not allowed for grant_type {}",
+                    unknown, grant_type
+                ),
+            ));
         }
-        _ => Err(AppError::BadRequest("Unsupported grant_type".to_string())),
     }
+
+    Ok(allowed)
+}
+
+/// Builds `UserDecryptionOptions.TrustedDeviceOption` for a device with stored TDE keys.
+async fn trusted_device_option(
+    db: &Db,
+    user_id: &str,
+    device: &Device,
+) -> Result<Option<TrustedDeviceOption>, AppError> {
+    if !device.is_trusted() {
+        return Ok(None);
+    }
+
+    // Any other trusted device of the user can approve a login request.
+    let approving: Option<String> = db
+        .first_column(
+            "SELECT id FROM devices WHERE user_id = ?1 AND id != ?2 AND encrypted_user_key IS NOT NULL LIMIT 1",
+            &[user_id.into(), device.id.clone().into()],
+            "id",
+        )
+        .await
+        .map_err(db_error!())?;
+
+    Ok(Some(TrustedDeviceOption {
+        has_admin_approval: false,
+        has_login_approving_device: approving.is_some(),
+        has_manage_reset_password_permission: false,
+        is_tde_offboarding: false,
+        encrypted_private_key: device.encrypted_private_key.clone(),
+        encrypted_user_key: device.encrypted_user_key.clone(),
+    }))
+}
+
+/// Whether a login from `identifier` comes from a new device: the user has logged in before, but
+/// never from this one. A user's first device needs no verification.
+async fn is_new_device(db: &Db, user_id: &str, identifier: Option<&str>) -> Result<bool, AppError> {
+    let new_device: Option<i64> = db
+        .first_column(
+            "SELECT COUNT(*) > 0 AND COALESCE(SUM(identifier = ?2), 0) = 0 AS new_device
+         FROM devices WHERE user_id = ?1",
+            &[user_id.into(), identifier.into()],
+            "new_device",
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(new_device.unwrap_or(0) != 0)
 }
 
 /// Generates the JSON error response for 2FA required
@@ -550,3 +880,210 @@ fn json_err_twofactor(providers: &[i32]) -> Value {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing::{self, ORIGIN, PASSWORD_HASH};
+    use crate::native::Env;
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, StatusCode};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+
+    const EMAIL: &str = "alice@example.com";
+    const DEVICE: &str = "8f1c0e52-3f4d-4c35-9d7e-8f0b6f7a2c11";
+
+    /// A migrated env where alice is registered.
+    fn env() -> Env {
+        let env = testing::env();
+        testing::register(&env, EMAIL);
+        env
+    }
+
+    /// Posts `body`, form-encoded as clients send it, to the token endpoint.
+    fn token(env: &Env, body: &str) -> (StatusCode, Value) {
+        testing::post_form(env, "/identity/connect/token", body)
+    }
+
+    fn password_login() -> String {
+        format!(
+            "grant_type=password&username={EMAIL}&password={PASSWORD_HASH}&scope=api%20offline_access\
+             &client_id=web&deviceType=9&deviceIdentifier={DEVICE}&deviceName=firefox"
+        )
+    }
+
+    /// The claims of `token`, as clients decode them.
+    fn claims(token: &Value) -> Value {
+        let payload = token.as_str().unwrap().split('.').nth(1).unwrap();
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn access_tokens_carry_the_claims_clients_read() {
+        let env = env();
+        let (status, body) = token(&env, &password_login());
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let claims = claims(&body["access_token"]);
+        assert_eq!(claims["email"], EMAIL);
+        assert_eq!(claims["email_verified"], false);
+        assert_eq!(claims["name"], "alice");
+        assert_eq!(claims["premium"], true);
+        assert_eq!(claims["device"], DEVICE);
+        assert_eq!(claims["amr"], serde_json::json!(["Application"]));
+        assert!(claims["sstamp"]
+            .as_str()
+            .is_some_and(|stamp| !stamp.is_empty()));
+        assert!(claims["sub"].as_str().is_some_and(|id| !id.is_empty()));
+    }
+
+    #[test]
+    fn amr_records_two_factor_logins() {
+        let login = |two_factor| LoginContext {
+            device: None,
+            two_factor,
+            trusted_device_option: None,
+            scope: "api offline_access",
+            session: None,
+            master_password_policy: None,
+        };
+
+        assert_eq!(login(false).amr(), ["Application"]);
+        assert_eq!(login(true).amr(), ["Application", "mfa"]);
+    }
+
+    #[test]
+    fn scope_is_checked_against_the_grant_type() {
+        assert_eq!(
+            granted_scope("password", Some("api offline_access")).unwrap(),
+            "api offline_access"
+        );
+        assert_eq!(
+            granted_scope("password", Some("api")).unwrap(),
+            "api offline_access"
+        );
+        assert_eq!(
+            granted_scope("refresh_token", None).unwrap(),
+            "api offline_access"
+        );
+        assert_eq!(
+            granted_scope("client_credentials", Some("api")).unwrap(),
+            "api"
+        );
+
+        for (grant_type, scope, error) in [
+            ("client_credentials", "api offline_access", "invalid_scope"),
+            ("password", "api admin", "invalid_scope"),
+            ("implicit", "api", "unsupported_grant_type"),
+        ] {
+            match granted_scope(grant_type, Some(scope)) {
+                Err(AppError::OAuth(got, _)) => assert_eq!(got, error, "{grant_type} {scope}"),
+                other => panic!("{grant_type} {scope}: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_the_bodies_real_clients_send() {
+        let env = env();
+        let bodies = [
+            // Desktop app
+            format!(
+                "scope=api%20offline_access&client_id=desktop&deviceType=6&deviceIdentifier={DEVICE}\
+                 &deviceName=windows&devicePushToken=&grant_type=password&username=alice%40example.com\
+                 &password={PASSWORD_HASH}"
+            ),
+            // CLI
+            format!(
+                "grant_type=password&username={EMAIL}&password={PASSWORD_HASH}&scope=api%20offline_access\
+                 &client_id=cli&deviceType=25&deviceIdentifier={DEVICE}&deviceName=linux"
+            ),
+            // Old Android build: padded device type and the scope sent twice
+            format!(
+                "grant_type=password&username={EMAIL}&password={PASSWORD_HASH}&scope=api+offline_access\
+                 &scope=api&client_id=mobile&deviceType=0+&deviceIdentifier={DEVICE}&deviceName=pixel"
+            ),
+        ];
+
+        for body in bodies {
+            let req = Request::post(format!("{ORIGIN}/identity/connect/token"))
+                .header(
+                    header::CONTENT_TYPE,
+                    "application/x-www-form-urlencoded; charset=utf-8",
+                )
+                .body(Body::from(body.clone()))
+                .unwrap();
+            let (status, response) = testing::json_response(testing::fetch(&env, req));
+            assert_eq!(status, StatusCode::OK, "{body}: {response}");
+            assert_eq!(response["scope"], "api offline_access", "{body}");
+        }
+    }
+
+    #[test]
+    fn unknown_scopes_are_an_oauth_error() {
+        let env = env();
+        let body = password_login().replace("scope=api%20offline_access", "scope=api%20admin");
+
+        let (status, response) = token(&env, &body);
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(response["error"], "invalid_scope");
+    }
+
+    #[test]
+    fn malformed_bodies_are_an_oauth_error() {
+        let env = env();
+
+        let (status, response) = token(&env, "username=alice%40example.com");
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(response["error"], "invalid_request");
+    }
+
+    /// The events in alice's account log, oldest first.
+    fn account_events(env: &Env, access_token: &str) -> Vec<Value> {
+        let (status, body) = testing::request(
+            env,
+            Method::GET,
+            "/api/accounts/events",
+            Some(access_token),
+            None,
+        );
+        assert_eq!(status, StatusCode::OK);
+        let mut events = body["data"].as_array().unwrap().clone();
+        events.reverse();
+        events
+    }
+
+    #[test]
+    fn token_grants_are_recorded_in_the_account_log() {
+        let env = env();
+        let wrong_password = password_login().replace(PASSWORD_HASH, "d3Jvbmc=");
+        let (status, _) = token(&env, &wrong_password);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        // Unknown accounts have no log to write to
+        let (status, _) = token(&env, &password_login().replace(EMAIL, "bob@example.com"));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = token(&env, &password_login());
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let user_id = claims(&body["access_token"])["sub"].clone();
+
+        let events = account_events(&env, body["access_token"].as_str().unwrap());
+        let types: Vec<_> = events.iter().map(|event| event["type"].clone()).collect();
+        assert_eq!(
+            types,
+            [
+                EventType::UserFailedLogIn as i32,
+                EventType::UserLoggedIn as i32
+            ]
+        );
+        for event in &events {
+            assert_eq!(event["userId"], user_id);
+            assert_eq!(event["actingUserId"], user_id);
+            assert_eq!(event["organizationId"], Value::Null);
+            // The device type of the token request's body
+            assert_eq!(event["deviceType"], 9);
+        }
+    }
+}
@@ -1,189 +1,2253 @@
-use axum::{extract::State, Json};
-use chrono::Utc;
-use std::collections::{HashMap, HashSet};
+use axum::{body::Body, extract::State, http::StatusCode, Extension, Json};
+use chrono::{Duration, Utc};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
 use std::sync::Arc;
 use uuid::Uuid;
-use worker::{query, D1PreparedStatement, Env};
 
 use crate::auth::Claims;
-use crate::db::{self, touch_user_updated_at};
-use crate::error::AppError;
-use crate::models::cipher::{Cipher, CipherData};
-use crate::models::folder::Folder;
-use crate::models::import::ImportRequest;
+use crate::config::Settings;
+use crate::db::{self, touch_user_updated_at, Database, Db, Statement};
+use crate::error::{db_error, internal_error, AppError};
+use crate::extract::{AppJson, AppPath, AppQuery};
+use crate::handlers::organizations::{find_organization_for_member, touch_members_statement};
+use crate::handlers::{attachments, collections};
+use crate::metrics;
+use crate::models::cipher::CipherData;
+use crate::models::import::{
+    ImportChunkRequest, ImportCipher, ImportCommitRequest, ImportQuery, ImportRequest,
+    ImportSession, ImportSessionProgress, ImportSessionStartRequest, ImportSummary,
+    OrganizationImportRequest, IMPORT_SESSION_TTL_MINUTES,
+};
+use crate::models::user::User;
+use crate::notify;
+use crate::quota::{self, Object};
+use crate::time;
+use crate::Env;
 
-use super::get_batch_size;
+/// Rough size of the parameters every insert binds (ids, timestamps) besides its payload.
+const STATEMENT_OVERHEAD_BYTES: usize = 200;
+
+/// Longest encrypted names and notes, as in Bitwarden's request models.
+const MAX_NAME_LENGTH: usize = 1000;
+const MAX_NOTES_LENGTH: usize = 10_000;
+
+/// How long an import lock holds when its request never releases it.
+const IMPORT_LOCK_TTL_MINUTES: i64 = 15;
+
+/// Errors by payload path, reported together as `AppError::Validation`.
+type ValidationErrors = BTreeMap<String, Vec<String>>;
+
+fn add_error(errors: &mut ValidationErrors, path: String, message: String) {
+    errors.entry(path).or_default().push(message);
+}
+
+/// Runs `operation` while holding the user's import lock, which keeps two imports, or an import
+/// and a key rotation, from interleaving their writes. Returns 409 while another request holds
+/// it. The lock is released once the operation finishes, and expires if the request dies first.
+pub(crate) async fn with_import_lock<T>(
+    db: &Db,
+    user_id: &str,
+    operation: impl Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+    let now = Utc::now();
+    let token = Uuid::new_v4().to_string();
+    let acquired = db
+        .run(
+            "INSERT INTO import_locks (user_id, token, expires_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(user_id) DO UPDATE SET token = excluded.token, expires_at = excluded.expires_at
+             WHERE import_locks.expires_at <= ?4",
+            &[
+                user_id.into(),
+                token.as_str().into(),
+                time::format_bw(now + Duration::minutes(IMPORT_LOCK_TTL_MINUTES)).into(),
+                time::format_bw(now).into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?;
+    if acquired == 0 {
+        return Err(AppError::Conflict(
+            "An import is already in progress.".to_string(),
+        ));
+    }
+
+    let result = operation.await;
+
+    if let Err(err) = db
+        .run(
+            "DELETE FROM import_locks WHERE user_id = ?1 AND token = ?2",
+            &[user_id.into(), token.as_str().into()],
+        )
+        .await
+    {
+        log::warn!("Failed to release the import lock, which will expire: {err:?}");
+    }
+    result
+}
+
+/// Reads and parses an import payload, refusing bodies over IMPORT_MAX_BODY_BYTES (default
+/// 10 MiB, `0` for no limit) before they're buffered completely.
+async fn read_payload<T: DeserializeOwned>(env: &Env, body: Body) -> Result<T, AppError> {
+    let max_bytes = Settings::get(env).import_max_body_bytes;
+    let bytes = axum::body::to_bytes(body, max_bytes).await.map_err(|_| {
+        AppError::BadRequest(format!(
+            "The import is larger than {} KiB. Split the export into smaller files and import them one at a time",
+            max_bytes / 1024
+        ))
+    })?;
+    serde_json::from_slice(&bytes)
+        .map_err(|err| AppError::BadRequest(format!("Invalid import payload: {err}")))
+}
+
+/// The error for an import that stopped part way, telling the client how many ciphers made it
+/// in and from which cipher of the payload to send the export again.
+fn import_incomplete(
+    error: AppError,
+    inserted: usize,
+    resume_offset: usize,
+    total: usize,
+    rolled_back: bool,
+) -> AppError {
+    log::error!("Import failed after {inserted} of {total} ciphers: {error}");
+    let mut message = if rolled_back {
+        "The import failed before any cipher was saved, and nothing was kept. Try again".to_string()
+    } else {
+        format!("The import failed after {inserted} of {total} ciphers were saved. Import the rest by sending the ciphers from index {resume_offset} on")
+    };
+    // A broken constraint is the payload's fault, not a server failure
+    let status = match &error {
+        AppError::Conflict(_) => StatusCode::CONFLICT,
+        AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if let AppError::Conflict(reason) | AppError::BadRequest(reason) = &error {
+        message = format!("{reason}. {message}");
+    }
+    AppError::ImportIncomplete(
+        status,
+        serde_json::json!({
+            "error": message,
+            "message": message,
+            "ciphersInserted": inserted,
+            "ciphersTotal": total,
+            "resumeOffset": resume_offset,
+            "rolledBack": rolled_back,
+            "object": "error",
+        }),
+    )
+}
+
+fn unsupported_warning(index: usize, cipher: &ImportCipher) -> String {
+    format!(
+        "Cipher {index} was skipped because its type ({}) isn't supported",
+        cipher.r#type
+    )
+}
+
+/// Ids the statements at `positions` created, given their changes (possibly fewer than
+/// statements, when a batch failed).
+fn created_ids(positions: &[usize], ids: &[Option<String>], changes: &[usize]) -> Vec<String> {
+    positions
+        .iter()
+        .zip(changes)
+        .filter(|(_, changed)| **changed > 0)
+        .filter_map(|(position, _)| ids.get(*position).cloned().flatten())
+        .collect()
+}
+
+/// Deletes the folders or collections a failed import created. `delete` takes the id and its
+/// owner. Returns whether that worked.
+async fn remove_created(db: &Db, delete: &str, owner_id: &str, ids: Vec<String>) -> bool {
+    if ids.is_empty() {
+        return true;
+    }
+    let statements: Result<Vec<_>, _> = ids
+        .iter()
+        .map(|id| Database::prepare(db, delete, &[id.as_str().into(), owner_id.into()]))
+        .collect();
+    // Recounting an organization, which owns collections, matches no user
+    let recount =
+        quota::recount(db, &[owner_id]).map_err(|err| worker::Error::RustError(err.to_string()));
+    let result = match statements.and_then(|statements| Ok((statements, recount?))) {
+        Ok((mut statements, recount)) => {
+            statements.push(recount);
+            Database::batch(db, statements).await.map(|_| ())
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = &result {
+        log::error!("Removing what a failed import created failed: {err}");
+    }
+    result.is_ok()
+}
+
+/// Rejects imports with more ciphers, folders and collections than IMPORT_MAX_ITEMS (default
+/// 5000, `0` for no limit), which wouldn't fit in a Worker's CPU and memory limits.
+fn check_item_count(env: &Env, items: usize) -> Result<(), AppError> {
+    let max_items = Settings::get(env).import_max_items;
+    if max_items != 0 && items > max_items {
+        return Err(AppError::BadRequest(format!(
+            "The import has {items} items, but at most {max_items} can be imported at once. Split the export into smaller files and import them one at a time"
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that each cipher carries the data of its type and that encrypted fields fit. `offset`
+/// is the index of the first cipher in the export.
+///
+/// Ciphers of unsupported types aren't checked; imports skip them.
+fn validate_ciphers(ciphers: &[ImportCipher], offset: usize, errors: &mut ValidationErrors) {
+    for (index, cipher) in ciphers.iter().enumerate() {
+        let index = offset + index;
+        if !cipher.is_supported() {
+            continue;
+        }
+        let fields = &cipher.type_fields;
+        let sub_objects = [
+            (1, "login", fields.login.as_ref()),
+            (2, "secureNote", fields.secure_note.as_ref()),
+            (3, "card", fields.card.as_ref()),
+            (4, "identity", fields.identity.as_ref()),
+            (5, "sshKey", fields.ssh_key.as_ref()),
+        ];
+        for (cipher_type, name, value) in sub_objects {
+            let present = value.is_some_and(|value| !value.is_null());
+            if cipher_type == cipher.r#type && !present {
+                add_error(
+                    errors,
+                    format!("ciphers[{index}].{name}"),
+                    format!("The {name} data is missing for a cipher of type {cipher_type}."),
+                );
+            } else if cipher_type != cipher.r#type && present {
+                add_error(
+                    errors,
+                    format!("ciphers[{index}].{name}"),
+                    format!("A cipher of type {} can't have {name} data.", cipher.r#type),
+                );
+            }
+        }
+        if cipher.name.len() > MAX_NAME_LENGTH {
+            add_error(
+                errors,
+                format!("ciphers[{index}].name"),
+                format!("The field Name exceeds the maximum encrypted value length of {MAX_NAME_LENGTH} characters."),
+            );
+        }
+        if cipher
+            .notes
+            .as_ref()
+            .is_some_and(|notes| notes.len() > MAX_NOTES_LENGTH)
+        {
+            add_error(
+                errors,
+                format!("ciphers[{index}].notes"),
+                format!("The field Notes exceeds the maximum encrypted value length of {MAX_NOTES_LENGTH} characters."),
+            );
+        }
+    }
+}
+
+/// Checks that ciphers were encrypted with the importing user's key. Exports from older clients
+/// and other tools don't say whom they were encrypted for (or leave it empty); those are accepted,
+/// as upstream does.
+fn validate_encrypted_for(
+    ciphers: &[ImportCipher],
+    offset: usize,
+    user_id: &str,
+    errors: &mut ValidationErrors,
+) {
+    for (index, cipher) in ciphers.iter().enumerate() {
+        if cipher
+            .encrypted_for
+            .as_deref()
+            .is_some_and(|encrypted_for| !encrypted_for.is_empty() && encrypted_for != user_id)
+        {
+            add_error(
+                errors,
+                format!("ciphers[{}].encryptedFor", offset + index),
+                "The cipher was not encrypted for the current user.".to_string(),
+            );
+        }
+    }
+}
+
+/// Checks that named items (folders, collections) have names that fit.
+fn validate_names<'a>(
+    field: &str,
+    names: impl Iterator<Item = &'a str>,
+    offset: usize,
+    errors: &mut ValidationErrors,
+) {
+    for (index, name) in names.enumerate() {
+        let index = offset + index;
+        if name.len() > MAX_NAME_LENGTH {
+            add_error(
+                errors,
+                format!("{field}[{index}].name"),
+                format!("The field Name exceeds the maximum encrypted value length of {MAX_NAME_LENGTH} characters."),
+            );
+        }
+    }
+}
+
+/// Checks that relationships (cipher index -> folder or collection index) point into the arrays.
+fn validate_relationships(
+    field: &str,
+    relationships: impl Iterator<Item = (usize, usize)>,
+    cipher_count: usize,
+    target: &str,
+    target_count: usize,
+    errors: &mut ValidationErrors,
+) {
+    for (index, (key, value)) in relationships.enumerate() {
+        if key >= cipher_count {
+            add_error(
+                errors,
+                format!("{field}[{index}].key"),
+                format!("Cipher index {key} is out of range; there are {cipher_count} ciphers."),
+            );
+        }
+        if value >= target_count {
+            add_error(
+                errors,
+                format!("{field}[{index}].value"),
+                format!(
+                    "{target} index {value} is out of range; there are {target_count} {target}s."
+                ),
+            );
+        }
+    }
+}
 
 /// Import ciphers and folders.
 /// Aligned with vaultwarden's POST /ciphers/import implementation.
+///
+/// Exports that carry collections are imported into the organization their ciphers belong to,
+/// as the organization import would.
+///
+/// With `?replace=true` (an extension official clients never send) the personal vault is made
+/// exactly the export: once the payload validates, its ciphers and folders are deleted in the
+/// same batch that inserts the first folders. That needs the `masterPasswordHash` of the user.
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/import",
+    tag = "import",
+    responses((status = 200, description = "Success", body = ImportSummary)),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn import_data(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Json(data): Json<ImportRequest>,
-) -> Result<Json<()>, AppError> {
+    Extension(settings): Extension<Arc<Settings>>,
+    AppQuery(query): AppQuery<ImportQuery>,
+    body: Body,
+) -> Result<Json<ImportSummary>, AppError> {
     let db = db::get_db(&env)?;
-    let now = Utc::now();
-    let now = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-    let batch_size = get_batch_size(&env);
-
-    // Get existing folders for this user
-    let existing_folder_rows = query!(
+    let user_id = claims.sub.clone();
+    with_import_lock(
         &db,
-        "SELECT id FROM folders WHERE user_id = ?1",
-        &claims.sub
+        &user_id,
+        import_into_vault(claims, env, settings, query, body),
     )
-    .map_err(|_| AppError::Database)?
-    .all()
-    .await?
-    .results::<FolderIdRow>()?;
+    .await
+}
 
-    let existing_folders: HashSet<String> =
-        existing_folder_rows.into_iter().map(|row| row.id).collect();
+/// The import of [`import_data`], under the user's import lock.
+async fn import_into_vault(
+    claims: Claims,
+    env: Arc<Env>,
+    settings: Arc<Settings>,
+    query: ImportQuery,
+    body: Body,
+) -> Result<Json<ImportSummary>, AppError> {
+    let started = Utc::now();
+    let mut data: ImportRequest = read_payload(&env, body).await?;
+    data.ciphers.iter_mut().for_each(ImportCipher::normalize);
+    let has_collections = !data.collections.is_empty() || !data.collection_relationships.is_empty();
+    if query.replace && has_collections {
+        return Err(AppError::BadRequest(
+            "Only the personal vault can be replaced; this export contains collections".to_string(),
+        ));
+    }
+    if has_collections {
+        let org_ids: HashSet<&str> = data
+            .ciphers
+            .iter()
+            .filter_map(|cipher| cipher.organization_id.as_deref())
+            .collect();
+        let mut org_ids = org_ids.into_iter();
+        let org_id = match (org_ids.next(), org_ids.next()) {
+            (Some(org_id), None) => org_id.to_string(),
+            (None, _) => {
+                return Err(AppError::BadRequest(
+                    "This export contains collections, which only exist in organizations. Import it into an organization instead".to_string(),
+                ))
+            }
+            (Some(_), Some(_)) => {
+                return Err(AppError::BadRequest(
+                    "This export contains collections of several organizations. Import each organization's items separately".to_string(),
+                ))
+            }
+        };
+        // Organization ciphers never live in a member's folder, so folders can't be honoured
+        if !data.folder_relationships.is_empty() {
+            return Err(AppError::BadRequest(
+                "Organization items can't be imported into folders. Remove the folders or the collections from the export".to_string(),
+            ));
+        }
+        let data = OrganizationImportRequest {
+            ciphers: data.ciphers,
+            collections: data.collections,
+            collection_relationships: data.collection_relationships,
+        };
+        return import_into_organization(claims, env, org_id, data).await;
+    }
 
-    // Process folders and build the folder_id list
-    let mut folder_statements: Vec<D1PreparedStatement> = Vec::new();
-    let mut folders: Vec<String> = Vec::with_capacity(data.folders.len());
+    // Validate everything up front so a bad payload doesn't leave a partial import behind
+    check_item_count(&env, data.ciphers.len() + data.folders.len())?;
+    let mut errors = ValidationErrors::new();
+    validate_encrypted_for(&data.ciphers, 0, &claims.sub, &mut errors);
+    validate_ciphers(&data.ciphers, 0, &mut errors);
+    validate_names(
+        "folders",
+        data.folders.iter().map(|folder| folder.name.as_str()),
+        0,
+        &mut errors,
+    );
+    validate_relationships(
+        "folderRelationships",
+        data.folder_relationships
+            .iter()
+            .map(|relation| (relation.key, relation.value)),
+        data.ciphers.len(),
+        "folder",
+        data.folders.len(),
+        &mut errors,
+    );
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
 
-    for import_folder in data.folders {
-        let folder_id = if let Some(ref id) = import_folder.id {
-            if existing_folders.contains(id) {
-                // Folder already exists, use existing ID
-                id.clone()
-            } else {
-                // Folder doesn't exist, create new one with provided ID
-                let folder = Folder {
-                    id: id.clone(),
-                    user_id: claims.sub.clone(),
-                    name: import_folder.name.clone(),
-                    created_at: now.clone(),
-                    updated_at: now.clone(),
-                };
-
-                let stmt = query!(
-                    &db,
-                    "INSERT INTO folders (id, user_id, name, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-                    folder.id,
-                    folder.user_id,
-                    folder.name,
-                    folder.created_at,
-                    folder.updated_at
-                )
-                .map_err(|_| AppError::Database)?;
+    let db = db::get_db(&env)?;
+    let now = time::now_bw();
+    let limits = settings.import_batch_limits;
 
-                folder_statements.push(stmt);
-                id.clone()
-            }
-        } else {
-            // No ID provided, create new folder with generated UUID
-            let new_id = Uuid::new_v4().to_string();
-            let folder = Folder {
-                id: new_id.clone(),
-                user_id: claims.sub.clone(),
-                name: import_folder.name.clone(),
-                created_at: now.clone(),
-                updated_at: now.clone(),
-            };
+    let mut folder_statements: Vec<Statement> = Vec::new();
+    let mut replaced_attachments = Vec::new();
+    let existing_folders: HashSet<String> = if query.replace {
+        let user: User = db
+            .first(
+                "SELECT * FROM users WHERE id = ?1",
+                &[claims.sub.as_str().into()],
+            )
+            .await
+            .map_err(db_error!())?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+        let provided_hash = data.master_password_hash.as_deref().ok_or_else(|| {
+            AppError::BadRequest(
+                "Replacing the vault requires the master password hash".to_string(),
+            )
+        })?;
+        if !user.verify_master_password(provided_hash).await?.is_valid() {
+            return Err(AppError::Unauthorized("Invalid password".to_string()));
+        }
 
-            let stmt = query!(
+        if attachments::attachments_enabled(env.as_ref()) {
+            replaced_attachments =
+                attachments::list_attachment_keys_for_personal_vault(&db, &claims.sub).await?;
+        }
+        // Organization items stay; they aren't part of a personal export
+        folder_statements.push(
+            Database::prepare(
+                &db,
+                "DELETE FROM ciphers WHERE user_id = ?1 AND organization_id IS NULL",
+                &[claims.sub.as_str().into()],
+            )
+            .map_err(db_error!())?,
+        );
+        folder_statements.push(
+            Database::prepare(
                 &db,
-                "INSERT INTO folders (id, user_id, name, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-                folder.id,
-                folder.user_id,
-                folder.name,
-                folder.created_at,
-                folder.updated_at
+                "DELETE FROM folders WHERE user_id = ?1",
+                &[claims.sub.as_str().into()],
             )
-            .map_err(|_| AppError::Database)?;
+            .map_err(db_error!())?,
+        );
+        // Nothing exists once the deletes have run
+        HashSet::new()
+    } else {
+        // Get existing folders for this user
+        let existing_folder_rows = db
+            .all::<FolderIdRow>(
+                "SELECT id FROM folders WHERE user_id = ?1",
+                &[claims.sub.as_str().into()],
+            )
+            .await?;
+
+        existing_folder_rows.into_iter().map(|row| row.id).collect()
+    };
+    // The deletes, if any, lead the first batch
+    let deletes = folder_statements.len();
+    let mut folder_sizes = vec![STATEMENT_OVERHEAD_BYTES; deletes];
+
+    // Process folders and build the folder_id list. A folder id that's taken by another user's
+    // folder makes its insert a no-op, which the batch results reveal.
+    let mut summary = ImportSummary::default();
+    let mut inserted_folders: Vec<usize> = Vec::new();
+    let mut folders: Vec<Option<String>> = Vec::with_capacity(data.folders.len());
 
-            folder_statements.push(stmt);
-            new_id
+    for (index, import_folder) in data.folders.into_iter().enumerate() {
+        let folder_id = match import_folder.id {
+            // Folder already exists, use existing ID
+            Some(id) if existing_folders.contains(&id) => {
+                summary.folders_skipped += 1;
+                folders.push(Some(id));
+                continue;
+            }
+            // Folder doesn't exist, create new one with provided ID
+            Some(id) => id,
+            // No ID provided, create new folder with generated UUID
+            None => Uuid::new_v4().to_string(),
         };
+        let name = import_folder.name;
+        folder_sizes.push(STATEMENT_OVERHEAD_BYTES + name.len());
 
-        folders.push(folder_id);
+        let stmt = Database::prepare(
+            &db,
+            "INSERT OR IGNORE INTO folders (id, user_id, name, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4)",
+            &[
+                folder_id.as_str().into(),
+                claims.sub.as_str().into(),
+                name.into(),
+                now.as_str().into(),
+            ],
+        )
+        .map_err(db_error!())?;
+
+        folder_statements.push(stmt);
+        inserted_folders.push(index);
+        folders.push(Some(folder_id));
     }
 
-    // Execute folder inserts in batches
-    if !folder_statements.is_empty() {
-        db::execute_in_batches(&db, folder_statements, batch_size).await?;
+    let incoming = [
+        (Object::Folder, inserted_folders.len()),
+        (
+            Object::Cipher,
+            data.ciphers
+                .iter()
+                .filter(|cipher| cipher.is_supported())
+                .count(),
+        ),
+    ];
+    if query.replace {
+        quota::check_replacing(&db, &env, &claims.sub, &incoming).await?;
+    } else {
+        quota::check(&db, &env, &claims.sub, &incoming).await?;
     }
+    // Every batch ends by recounting the user, so the quota counters follow what committed
+    let recount = quota::recount(&db, &[&claims.sub])?;
 
-    // Build the relations map: cipher_index -> folder_index
+    // Execute folder inserts in batches. Until a cipher is written, a failure leaves only
+    // orphaned folders behind, which are removed again.
+    let mut changes = match db::execute_in_batches_retrying(
+        &db,
+        folder_statements,
+        &folder_sizes,
+        limits,
+        Some(&recount),
+    )
+    .await
+    {
+        Ok(changes) => changes,
+        Err(failure) => {
+            let created = created_ids(
+                &inserted_folders,
+                &folders,
+                failure.changes.get(deletes..).unwrap_or_default(),
+            );
+            let removed = remove_created(
+                &db,
+                "DELETE FROM folders WHERE id = ?1 AND user_id = ?2",
+                &claims.sub,
+                created,
+            )
+            .await;
+            // The deletes went through with the first batch, so the vault is empty now
+            let vault_deleted = deletes > 0 && !failure.changes.is_empty();
+            if vault_deleted {
+                attachments::delete_storage_objects(env.as_ref(), &replaced_attachments).await?;
+                touch_user_updated_at(&db, &claims.sub).await?;
+                notify::notify_vault_sync(&env, &db, &claims.sub, claims.device.as_deref()).await;
+            }
+            let rolled_back = removed && !vault_deleted;
+            return Err(import_incomplete(
+                failure.error,
+                0,
+                0,
+                data.ciphers.len(),
+                rolled_back,
+            ));
+        }
+    };
+    let folder_changes = changes.split_off(deletes);
+    if deletes > 0 {
+        summary.ciphers_deleted = changes.first().copied().unwrap_or(0);
+        summary.folders_deleted = changes.get(1).copied().unwrap_or(0);
+        // The rows are gone, so the files go too
+        attachments::delete_storage_objects(env.as_ref(), &replaced_attachments).await?;
+    }
+    let changes = folder_changes;
+    let created_folders = created_ids(&inserted_folders, &folders, &changes);
+    for (&index, changed) in inserted_folders.iter().zip(changes) {
+        if changed > 0 {
+            summary.folders_inserted += 1;
+        } else {
+            summary.folders_skipped += 1;
+            summary.warnings.push(format!(
+                "Folder {index} was skipped because its id is already in use; its ciphers were imported without a folder"
+            ));
+            if let Some(folder) = folders.get_mut(index) {
+                *folder = None;
+            }
+        }
+    }
+
+    // Build the relations list: cipher_index -> folder_index
     // Each cipher can only be in one folder at a time
-    let mut relations_map: HashMap<usize, usize> =
-        HashMap::with_capacity(data.folder_relationships.len());
+    let mut cipher_folders: Vec<Option<usize>> = vec![None; data.ciphers.len()];
     for relation in data.folder_relationships {
-        relations_map.insert(relation.key, relation.value);
+        // Checked by `validate_relationships`
+        let Some(cipher_folder) = cipher_folders.get_mut(relation.key) else {
+            continue;
+        };
+        if cipher_folder.replace(relation.value).is_some() {
+            summary.warnings.push(format!(
+                "Cipher {} has several folder relationships; only the last one was used",
+                relation.key
+            ));
+        }
     }
 
-    // Prepare all cipher insert statements
-    let mut cipher_statements: Vec<D1PreparedStatement> = Vec::with_capacity(data.ciphers.len());
+    // Prepare all cipher insert statements. Each cipher is consumed as its statement is
+    // prepared, so the parsed payload and the bound parameters aren't held twice.
+    let mut cipher_statements: Vec<Statement> = Vec::with_capacity(data.ciphers.len());
+    let mut in_folder: Vec<bool> = Vec::with_capacity(data.ciphers.len());
+    let mut cipher_sizes: Vec<usize> = Vec::with_capacity(data.ciphers.len());
+    // Payload index of each statement's cipher; unsupported items leave gaps
+    let mut cipher_indexes: Vec<usize> = Vec::with_capacity(data.ciphers.len());
+    let payload_ciphers = data.ciphers.len();
 
-    for (index, import_cipher) in data.ciphers.into_iter().enumerate() {
+    for (index, (import_cipher, folder_index)) in
+        data.ciphers.into_iter().zip(cipher_folders).enumerate()
+    {
+        if !import_cipher.is_supported() {
+            summary
+                .warnings
+                .push(unsupported_warning(index, &import_cipher));
+            continue;
+        }
+        cipher_indexes.push(index);
         // Determine folder_id from folder_relationships
-        let folder_id = relations_map
-            .get(&index)
-            .and_then(|folder_idx| folders.get(*folder_idx).cloned());
+        let folder_id = folder_index
+            .and_then(|folder_index| folders.get(folder_index))
+            .and_then(Option::as_deref);
 
+        let cipher_type = import_cipher.r#type;
+        let favorite = import_cipher.favorite.unwrap_or(false);
+        let organization_id = import_cipher.organization_id;
         let cipher_data = CipherData {
             name: import_cipher.name,
             notes: import_cipher.notes,
             type_fields: import_cipher.type_fields,
         };
 
-        let data_value = serde_json::to_value(&cipher_data).map_err(|_| AppError::Internal)?;
-
-        let cipher = Cipher {
-            id: Uuid::new_v4().to_string(),
-            user_id: Some(claims.sub.clone()),
-            organization_id: import_cipher.organization_id,
-            r#type: import_cipher.r#type,
-            data: data_value,
-            favorite: import_cipher.favorite.unwrap_or(false),
-            folder_id,
-            deleted_at: None,
-            created_at: now.clone(),
-            updated_at: now.clone(),
-            object: "cipher".to_string(),
-            organization_use_totp: false,
-            edit: true,
-            view_password: true,
-            collection_ids: None,
-            attachments: None,
-        };
-
-        let data = serde_json::to_string(&cipher.data).map_err(|_| AppError::Internal)?;
+        let data = serde_json::to_string(&cipher_data).map_err(internal_error!())?;
+        cipher_sizes.push(STATEMENT_OVERHEAD_BYTES + data.len());
+        in_folder.push(folder_id.is_some());
 
-        let stmt = query!(
+        let stmt = Database::prepare(
             &db,
             "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-             cipher.id,
-             cipher.user_id,
-             cipher.organization_id,
-             cipher.r#type,
-             data,
-             cipher.favorite,
-             cipher.folder_id,
-             cipher.created_at,
-             cipher.updated_at,
-        ).map_err(|_| AppError::Database)?;
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
+            &[
+                Uuid::new_v4().to_string().into(),
+                claims.sub.as_str().into(),
+                organization_id.into(),
+                cipher_type.into(),
+                data.into(),
+                favorite.into(),
+                folder_id.into(),
+                now.as_str().into(),
+            ],
+        )
+        .map_err(db_error!())?;
 
         cipher_statements.push(stmt);
     }
 
-    // Execute cipher inserts in batches
-    if !cipher_statements.is_empty() {
-        db::execute_in_batches(&db, cipher_statements, batch_size).await?;
+    // Execute cipher inserts in batches. Each cipher is one statement, so the committed
+    // statements tell where to resume.
+    let cipher_count = payload_ciphers;
+    let changes = match db::execute_in_batches_retrying(
+        &db,
+        cipher_statements,
+        &cipher_sizes,
+        limits,
+        Some(&recount),
+    )
+    .await
+    {
+        Ok(changes) => changes,
+        Err(failure) => {
+            let inserted: usize = failure.changes.iter().sum();
+            let rolled_back = inserted == 0
+                && deletes == 0
+                && remove_created(
+                    &db,
+                    "DELETE FROM folders WHERE id = ?1 AND user_id = ?2",
+                    &claims.sub,
+                    created_folders,
+                )
+                .await;
+            if !rolled_back {
+                touch_user_updated_at(&db, &claims.sub).await?;
+                notify::notify_vault_sync(&env, &db, &claims.sub, claims.device.as_deref()).await;
+            }
+            let inserted = failure.changes.len();
+            return Err(import_incomplete(
+                failure.error,
+                inserted,
+                cipher_indexes
+                    .get(inserted)
+                    .copied()
+                    .unwrap_or(cipher_count),
+                cipher_count,
+                rolled_back,
+            ));
+        }
+    };
+    for (changed, in_folder) in changes.into_iter().zip(in_folder) {
+        summary.ciphers_inserted += changed;
+        if changed > 0 && in_folder {
+            summary.relationships_resolved += 1;
+        }
     }
+    metrics::record(
+        &env,
+        metrics::import(
+            &claims.sub,
+            summary.ciphers_inserted,
+            limits.plan(&cipher_sizes).len(),
+            (Utc::now() - started).num_milliseconds(),
+        ),
+    );
 
     touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_vault_sync(&env, &db, &claims.sub, claims.device.as_deref()).await;
 
-    Ok(Json(()))
+    Ok(Json(summary))
 }
 
-/// Helper struct for querying existing folder IDs
+#[derive(serde::Deserialize)]
+pub struct OrganizationImportQuery {
+    #[serde(rename = "organizationId")]
+    pub organization_id: String,
+}
+
+/// POST /api/ciphers/import-organization?organizationId={id}
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/import-organization",
+    tag = "import",
+    responses((status = 200, description = "Success", body = ImportSummary)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn import_organization_data(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppQuery(query): AppQuery<OrganizationImportQuery>,
+    body: Body,
+) -> Result<Json<ImportSummary>, AppError> {
+    let data = read_payload(&env, body).await?;
+    let db = db::get_db(&env)?;
+    let user_id = claims.sub.clone();
+    with_import_lock(
+        &db,
+        &user_id,
+        import_into_organization(claims, env, query.organization_id, data),
+    )
+    .await
+}
+
+/// POST /api/organizations/{id}/import
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{id}/import",
+    tag = "import",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = ImportSummary)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn import_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+    body: Body,
+) -> Result<Json<ImportSummary>, AppError> {
+    let data = read_payload(&env, body).await?;
+    let db = db::get_db(&env)?;
+    let user_id = claims.sub.clone();
+    with_import_lock(
+        &db,
+        &user_id,
+        import_into_organization(claims, env, org_id, data),
+    )
+    .await
+}
+
+/// Import ciphers and collections into an organization.
+/// Aligned with vaultwarden's POST /ciphers/import-organization implementation.
+async fn import_into_organization(
+    claims: Claims,
+    env: Arc<Env>,
+    org_id: String,
+    mut data: OrganizationImportRequest,
+) -> Result<Json<ImportSummary>, AppError> {
+    data.ciphers.iter_mut().for_each(ImportCipher::normalize);
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can import into an organization".to_string(),
+        ));
+    }
+    let now = time::now_bw();
+    let limits = Settings::get(&env).import_batch_limits;
+
+    // Validate everything up front so a bad payload doesn't leave a partial import behind
+    check_item_count(&env, data.ciphers.len() + data.collections.len())?;
+    let mut errors = ValidationErrors::new();
+    validate_encrypted_for(&data.ciphers, 0, &claims.sub, &mut errors);
+    validate_ciphers(&data.ciphers, 0, &mut errors);
+    validate_names(
+        "collections",
+        data.collections
+            .iter()
+            .map(|collection| collection.name.as_str()),
+        0,
+        &mut errors,
+    );
+    validate_relationships(
+        "collectionRelationships",
+        data.collection_relationships
+            .iter()
+            .map(|relation| (relation.key, relation.value)),
+        data.ciphers.len(),
+        "collection",
+        data.collections.len(),
+        &mut errors,
+    );
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+
+    // Get existing collections of the organization
+    let existing_collection_rows = db
+        .all::<FolderIdRow>(
+            "SELECT id FROM collections WHERE organization_id = ?1",
+            &[org.id.as_str().into()],
+        )
+        .await?;
+
+    let existing_collections: HashSet<String> = existing_collection_rows
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+    // Process collections and build the collection_id list. Ids from another server are
+    // replaced by fresh ones.
+    let mut summary = ImportSummary::default();
+    let mut statements: Vec<Statement> = Vec::new();
+    // What each statement inserts, to read the summary off the batch results
+    let mut kinds: Vec<ImportedRow> = Vec::new();
+    let mut sizes: Vec<usize> = Vec::new();
+    // Payload index of each inserted cipher; unsupported items leave gaps
+    let mut cipher_indexes: Vec<usize> = Vec::with_capacity(data.ciphers.len());
+    let cipher_count = data.ciphers.len();
+    // Collection each statement creates, to remove them again if no cipher makes it in
+    let mut statement_collection_ids: Vec<Option<String>> = Vec::new();
+    let mut collection_ids: Vec<String> = Vec::with_capacity(data.collections.len());
+
+    for import_collection in data.collections {
+        match import_collection.id {
+            Some(id) if existing_collections.contains(&id) => {
+                summary.collections_skipped += 1;
+                collection_ids.push(id);
+            }
+            _ => {
+                let new_id = Uuid::new_v4().to_string();
+                sizes.push(
+                    STATEMENT_OVERHEAD_BYTES
+                        + import_collection.name.len()
+                        + import_collection
+                            .external_id
+                            .as_ref()
+                            .map_or(0, String::len),
+                );
+                let stmt = Database::prepare(
+                    &db,
+                    "INSERT INTO collections (id, organization_id, name, external_id, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+                    &[
+                        new_id.as_str().into(),
+                        org.id.as_str().into(),
+                        import_collection.name.into(),
+                        import_collection.external_id.into(),
+                        now.as_str().into(),
+                    ],
+                )
+                .map_err(db_error!())?;
+
+                statements.push(stmt);
+                kinds.push(ImportedRow::Collection);
+                statement_collection_ids.push(Some(new_id.clone()));
+                collection_ids.push(new_id);
+            }
+        }
+    }
+
+    // Build the relations map: cipher_index -> collection ids
+    // Unlike folders, a cipher can be in several collections
+    let mut relations_map: HashMap<usize, Vec<String>> = HashMap::new();
+    for relation in data.collection_relationships {
+        // Checked by `validate_relationships`
+        let Some(collection_id) = collection_ids.get(relation.value) else {
+            continue;
+        };
+        relations_map
+            .entry(relation.key)
+            .or_default()
+            .push(collection_id.clone());
+    }
+
+    for (index, import_cipher) in data.ciphers.into_iter().enumerate() {
+        if !import_cipher.is_supported() {
+            summary
+                .warnings
+                .push(unsupported_warning(index, &import_cipher));
+            continue;
+        }
+        cipher_indexes.push(index);
+        let cipher_type = import_cipher.r#type;
+        let favorite = import_cipher.favorite.unwrap_or(false);
+        let cipher_data = CipherData {
+            name: import_cipher.name,
+            notes: import_cipher.notes,
+            type_fields: import_cipher.type_fields,
+        };
+
+        let data = serde_json::to_string(&cipher_data).map_err(internal_error!())?;
+        let cipher_id = Uuid::new_v4().to_string();
+        sizes.push(STATEMENT_OVERHEAD_BYTES + data.len());
+
+        // Organization ciphers never live in a member's folder
+        let stmt = Database::prepare(
+            &db,
+            "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, NULL, ?7, ?7)",
+            &[
+                cipher_id.as_str().into(),
+                claims.sub.as_str().into(),
+                org.id.as_str().into(),
+                cipher_type.into(),
+                data.into(),
+                favorite.into(),
+                now.as_str().into(),
+            ],
+        )
+        .map_err(db_error!())?;
+
+        statements.push(stmt);
+        kinds.push(ImportedRow::Cipher);
+        statement_collection_ids.push(None);
+        if let Some(cipher_collections) = relations_map.get(&index) {
+            let assignments =
+                collections::cipher_assignment_statements(&db, &cipher_id, cipher_collections)?;
+            kinds.extend(assignments.iter().map(|_| ImportedRow::Relationship));
+            statement_collection_ids.extend(assignments.iter().map(|_| None));
+            sizes.extend(assignments.iter().map(|_| STATEMENT_OVERHEAD_BYTES));
+            statements.extend(assignments);
+        }
+    }
+
+    // Execute inserts in batches; collections come first so assignments can reference them
+    // Organization ciphers count against the member importing them
+    quota::check(
+        &db,
+        &env,
+        &claims.sub,
+        &[(Object::Cipher, cipher_indexes.len())],
+    )
+    .await?;
+    let recount = quota::recount(&db, &[&claims.sub])?;
+    let changes = match db::execute_in_batches_retrying(
+        &db,
+        statements,
+        &sizes,
+        limits,
+        Some(&recount),
+    )
+    .await
+    {
+        Ok(changes) => changes,
+        Err(failure) => {
+            // A cipher is done once its collection assignments are too
+            let committed = failure.changes.len();
+            let cipher_starts: Vec<usize> = kinds
+                .iter()
+                .enumerate()
+                .filter(|(_, kind)| matches!(kind, ImportedRow::Cipher))
+                .map(|(position, _)| position)
+                .collect();
+            let complete = cipher_starts
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| {
+                    cipher_starts.get(index + 1).copied().unwrap_or(kinds.len()) <= committed
+                })
+                .count();
+            let any_cipher = cipher_starts
+                .first()
+                .is_some_and(|start| *start < committed);
+            let rolled_back = if any_cipher {
+                Database::batch(&db, vec![touch_members_statement(&db, &org.id, &now)?])
+                    .await
+                    .map_err(db_error!())?;
+                false
+            } else {
+                let created: Vec<String> = kinds
+                    .iter()
+                    .zip(&statement_collection_ids)
+                    .take(committed)
+                    .filter(|(kind, _)| matches!(kind, ImportedRow::Collection))
+                    .filter_map(|(_, collection_id)| collection_id.clone())
+                    .collect();
+                remove_created(
+                    &db,
+                    "DELETE FROM collections WHERE id = ?1 AND organization_id = ?2",
+                    &org.id,
+                    created,
+                )
+                .await
+            };
+            return Err(import_incomplete(
+                failure.error,
+                complete,
+                cipher_indexes
+                    .get(complete)
+                    .copied()
+                    .unwrap_or(cipher_count),
+                cipher_count,
+                rolled_back,
+            ));
+        }
+    };
+    for (kind, changed) in kinds.into_iter().zip(changes) {
+        match kind {
+            ImportedRow::Collection => summary.collections_inserted += changed,
+            ImportedRow::Cipher => summary.ciphers_inserted += changed,
+            ImportedRow::Relationship => summary.relationships_resolved += changed,
+        }
+    }
+
+    Database::batch(&db, vec![touch_members_statement(&db, &org.id, &now)?])
+        .await
+        .map_err(db_error!())?;
+    notify::notify_vault_sync(&env, &db, &claims.sub, claims.device.as_deref()).await;
+
+    Ok(Json(summary))
+}
+
+/// Row an organization import statement inserts
+enum ImportedRow {
+    Collection,
+    Cipher,
+    Relationship,
+}
+
+/// Helper struct for querying existing folder and collection IDs
 #[derive(serde::Deserialize)]
 struct FolderIdRow {
     id: String,
 }
+
+// Chunked import
+//
+// An extension for exports too large for a single request; official clients only use the
+// single-shot endpoints above. The flow:
+//
+// 1. POST /api/ciphers/import/start creates a session, optionally with the export's totals.
+// 2. POST /api/ciphers/import/chunk stages a slice of the export. Items are keyed by their
+//    export-wide index, so a chunk that failed (or whose answer got lost) is simply sent again.
+// 3. GET /api/ciphers/import/{id} reports how much has been staged, to find where to resume.
+// 4. POST /api/ciphers/import/commit resolves folder relationships across all chunks and moves
+//    the staged items into the vault in one batch.
+//
+// Sessions expire an hour after they're started and are purged by the scheduled job.
+
+async fn find_import_session(
+    db: &Db,
+    session_id: &str,
+    user_id: &str,
+) -> Result<ImportSession, AppError> {
+    db.first(
+        "SELECT * FROM import_sessions WHERE id = ?1 AND user_id = ?2 AND expires_at > ?3",
+        &[session_id.into(), user_id.into(), time::now_bw().into()],
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound("Import session not found or expired".to_string()))
+}
+
+/// Like [`find_import_session`], but only for sessions that still accept chunks.
+async fn find_open_import_session(
+    db: &Db,
+    session_id: &str,
+    user_id: &str,
+) -> Result<ImportSession, AppError> {
+    let session = find_import_session(db, session_id, user_id).await?;
+    if session.committed_at.is_some() {
+        return Err(AppError::BadRequest(
+            "This import session has already been committed".to_string(),
+        ));
+    }
+    Ok(session)
+}
+
+async fn import_session_progress(
+    db: &Db,
+    session_id: &str,
+) -> Result<ImportSessionProgress, AppError> {
+    db.first(
+        "SELECT
+            (SELECT COUNT(*) FROM import_session_folders WHERE session_id = ?1) AS folders,
+            (SELECT COUNT(*) FROM import_session_ciphers WHERE session_id = ?1) AS ciphers,
+            (SELECT COUNT(*) FROM import_session_relationships WHERE session_id = ?1) AS relationships",
+        &[session_id.into()],
+    )
+    .await?
+    .ok_or_else(|| AppError::Database(Some("import session counts returned no row".to_string())))
+}
+
+/// POST /api/ciphers/import/start - start a chunked import into the personal vault
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/import/start",
+    tag = "import",
+    request_body = ImportSessionStartRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn start_import_session(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppJson(payload): AppJson<ImportSessionStartRequest>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let now = Utc::now();
+    let expires_at = time::format_bw(now + Duration::minutes(IMPORT_SESSION_TTL_MINUTES));
+    let now = time::format_bw(now);
+    let session = ImportSession {
+        id: Uuid::new_v4().to_string(),
+        user_id: claims.sub,
+        expected_folders: payload.folders.map(|count| count as i64),
+        expected_ciphers: payload.ciphers.map(|count| count as i64),
+        committed_at: None,
+        created_at: now.clone(),
+        updated_at: now,
+        expires_at,
+    };
+
+    db.run(
+        "INSERT INTO import_sessions (id, user_id, expected_folders, expected_ciphers, created_at, updated_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        &[
+            session.id.as_str().into(),
+            session.user_id.as_str().into(),
+            session.expected_folders.into(),
+            session.expected_ciphers.into(),
+            session.created_at.as_str().into(),
+            session.updated_at.as_str().into(),
+            session.expires_at.as_str().into(),
+        ],
+    )
+    .await?;
+
+    let progress = ImportSessionProgress {
+        folders: 0,
+        ciphers: 0,
+        relationships: 0,
+    };
+    Ok(Json(session.to_json(&progress)))
+}
+
+/// GET /api/ciphers/import/{id} - how much of a chunked import has been staged
+#[utoipa::path(
+    get,
+    path = "/api/ciphers/import/{id}",
+    tag = "import",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_import_session(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(session_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let session = find_import_session(&db, &session_id, &claims.sub).await?;
+    let progress = import_session_progress(&db, &session.id).await?;
+    Ok(Json(session.to_json(&progress)))
+}
+
+/// POST /api/ciphers/import/chunk - stage a slice of a chunked import
+///
+/// Items are validated like a single-shot import, and nothing is staged unless the whole chunk
+/// validates. Sending a chunk again replaces the items it staged before.
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/import/chunk",
+    tag = "import",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_import_chunk(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    body: Body,
+) -> Result<Json<Value>, AppError> {
+    let mut data: ImportChunkRequest = read_payload(&env, body).await?;
+    data.ciphers.iter_mut().for_each(ImportCipher::normalize);
+    check_item_count(&env, data.ciphers.len() + data.folders.len())?;
+    let db = db::get_db(&env)?;
+    let session = find_open_import_session(&db, &data.session_id, &claims.sub).await?;
+
+    let mut errors = ValidationErrors::new();
+    validate_encrypted_for(&data.ciphers, data.cipher_offset, &claims.sub, &mut errors);
+    validate_ciphers(&data.ciphers, data.cipher_offset, &mut errors);
+    validate_names(
+        "folders",
+        data.folders.iter().map(|folder| folder.name.as_str()),
+        data.folder_offset,
+        &mut errors,
+    );
+    for (index, cipher) in data.ciphers.iter().enumerate() {
+        // Skipping would leave a gap the commit can't tell from a missing chunk
+        if !cipher.is_supported() {
+            add_error(
+                &mut errors,
+                format!("ciphers[{}].type", data.cipher_offset + index),
+                format!(
+                    "Cipher type {} isn't supported; remove the item from the export.",
+                    cipher.r#type
+                ),
+            );
+        }
+        if cipher.organization_id.is_some() {
+            add_error(
+                &mut errors,
+                format!("ciphers[{}].organizationId", data.cipher_offset + index),
+                "Chunked imports only go into the personal vault.".to_string(),
+            );
+        }
+    }
+    // Relationships may point into later chunks, so they can only be range-checked here when
+    // the totals are known
+    if let (Some(expected_ciphers), Some(expected_folders)) =
+        (session.expected_ciphers, session.expected_folders)
+    {
+        validate_relationships(
+            "folderRelationships",
+            data.folder_relationships
+                .iter()
+                .map(|relation| (relation.key, relation.value)),
+            expected_ciphers as usize,
+            "folder",
+            expected_folders as usize,
+            &mut errors,
+        );
+    }
+    if session
+        .expected_folders
+        .is_some_and(|expected| data.folder_offset + data.folders.len() > expected as usize)
+    {
+        add_error(
+            &mut errors,
+            "folders".to_string(),
+            "The chunk goes past the number of folders announced at start.".to_string(),
+        );
+    }
+    if session
+        .expected_ciphers
+        .is_some_and(|expected| data.cipher_offset + data.ciphers.len() > expected as usize)
+    {
+        add_error(
+            &mut errors,
+            "ciphers".to_string(),
+            "The chunk goes past the number of ciphers announced at start.".to_string(),
+        );
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+
+    let mut statements: Vec<Statement> = Vec::new();
+    for (index, import_folder) in data.folders.into_iter().enumerate() {
+        let idx = (data.folder_offset + index) as i64;
+        let folder_id = import_folder
+            .id
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let name = import_folder.name;
+        statements.push(
+            Database::prepare(
+                &db,
+                "INSERT OR REPLACE INTO import_session_folders (session_id, idx, folder_id, name)
+                 VALUES (?1, ?2, ?3, ?4)",
+                &[
+                    session.id.as_str().into(),
+                    idx.into(),
+                    folder_id.into(),
+                    name.into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    for (index, import_cipher) in data.ciphers.into_iter().enumerate() {
+        let idx = (data.cipher_offset + index) as i64;
+        let cipher_type = import_cipher.r#type;
+        let favorite = import_cipher.favorite.unwrap_or(false);
+        let cipher_data = CipherData {
+            name: import_cipher.name,
+            notes: import_cipher.notes,
+            type_fields: import_cipher.type_fields,
+        };
+        let cipher_data = serde_json::to_string(&cipher_data).map_err(internal_error!())?;
+        statements.push(
+            Database::prepare(
+                &db,
+                "INSERT OR REPLACE INTO import_session_ciphers (session_id, idx, cipher_id, type, data, favorite)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                &[
+                    session.id.as_str().into(),
+                    idx.into(),
+                    Uuid::new_v4().to_string().into(),
+                    cipher_type.into(),
+                    cipher_data.into(),
+                    favorite.into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    for relation in data.folder_relationships {
+        statements.push(
+            Database::prepare(
+                &db,
+                "INSERT OR REPLACE INTO import_session_relationships (session_id, cipher_idx, folder_idx)
+                 VALUES (?1, ?2, ?3)",
+                &[
+                    session.id.as_str().into(),
+                    (relation.key as i64).into(),
+                    (relation.value as i64).into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    statements.push(
+        Database::prepare(
+            &db,
+            "UPDATE import_sessions SET updated_at = ?1 WHERE id = ?2",
+            &[time::now_bw().into(), session.id.as_str().into()],
+        )
+        .map_err(db_error!())?,
+    );
+    // A chunk is staged in one batch so a failure leaves nothing half-written
+    Database::batch(&db, statements).await?;
+
+    let session = find_import_session(&db, &session.id, &claims.sub).await?;
+    let progress = import_session_progress(&db, &session.id).await?;
+    Ok(Json(session.to_json(&progress)))
+}
+
+/// POST /api/ciphers/import/commit - move a chunked import's staged items into the vault
+///
+/// Fails without writing anything while items are missing or a folder relationship points at an
+/// item that was never uploaded; upload the missing chunks and commit again.
+#[utoipa::path(
+    post,
+    path = "/api/ciphers/import/commit",
+    tag = "import",
+    request_body = ImportCommitRequest,
+    responses((status = 200, description = "Success", body = ImportSummary)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn commit_import_session(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppJson(payload): AppJson<ImportCommitRequest>,
+) -> Result<Json<ImportSummary>, AppError> {
+    let db = db::get_db(&env)?;
+    let user_id = claims.sub.clone();
+    with_import_lock(&db, &user_id, commit_staged_import(claims, env, payload)).await
+}
+
+/// The commit of [`commit_import_session`], under the user's import lock.
+async fn commit_staged_import(
+    claims: Claims,
+    env: Arc<Env>,
+    payload: ImportCommitRequest,
+) -> Result<Json<ImportSummary>, AppError> {
+    let db = db::get_db(&env)?;
+    let session = find_open_import_session(&db, &payload.session_id, &claims.sub).await?;
+
+    #[derive(serde::Deserialize)]
+    struct StagedCount {
+        count: i64,
+        extent: i64,
+    }
+    #[derive(serde::Deserialize)]
+    struct DanglingRelationship {
+        cipher_idx: i64,
+        folder_idx: i64,
+        has_cipher: i64,
+        has_folder: i64,
+    }
+
+    // Staged items must cover the export without gaps
+    let mut errors = ValidationErrors::new();
+    let mut staged_folders = 0;
+    let mut staged_ciphers = 0;
+    for (field, table, expected) in [
+        (
+            "folders",
+            "import_session_folders",
+            session.expected_folders,
+        ),
+        (
+            "ciphers",
+            "import_session_ciphers",
+            session.expected_ciphers,
+        ),
+    ] {
+        let staged: StagedCount = db
+            .first(
+                &format!("SELECT COUNT(*) AS count, COALESCE(MAX(idx) + 1, 0) AS extent FROM {table} WHERE session_id = ?1"),
+                &[session.id.as_str().into()],
+            )
+            .await?
+            .ok_or_else(|| AppError::Database(Some(format!("{table} count returned no row"))))?;
+        let total = expected.unwrap_or(staged.extent);
+        if staged.count != total {
+            add_error(
+                &mut errors,
+                field.to_string(),
+                format!(
+                    "Only {} of {total} {field} have been uploaded.",
+                    staged.count
+                ),
+            );
+        }
+        match field {
+            "folders" => staged_folders = staged.count as usize,
+            _ => staged_ciphers = staged.count as usize,
+        }
+    }
+
+    let dangling: Vec<DanglingRelationship> = db.all("SELECT r.cipher_idx, r.folder_idx,
+                EXISTS (SELECT 1 FROM import_session_ciphers c WHERE c.session_id = r.session_id AND c.idx = r.cipher_idx) AS has_cipher,
+                EXISTS (SELECT 1 FROM import_session_folders f WHERE f.session_id = r.session_id AND f.idx = r.folder_idx) AS has_folder
+         FROM import_session_relationships r
+         WHERE r.session_id = ?1 AND (has_cipher = 0 OR has_folder = 0)
+         ORDER BY r.cipher_idx
+         LIMIT 100", &[session.id.as_str().into()]).await?;
+    for relation in dangling {
+        let path = format!("folderRelationships[{}]", relation.cipher_idx);
+        if relation.has_cipher == 0 {
+            add_error(
+                &mut errors,
+                path.clone(),
+                format!("Cipher {} hasn't been uploaded.", relation.cipher_idx),
+            );
+        }
+        if relation.has_folder == 0 {
+            add_error(
+                &mut errors,
+                path,
+                format!("Folder {} hasn't been uploaded.", relation.folder_idx),
+            );
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+
+    // Folders whose id another user's folder already has can't be created; their ciphers are
+    // imported without a folder
+    let mut summary = ImportSummary::default();
+    let taken: Vec<FolderIndexRow> = db
+        .all(
+            "SELECT sf.idx FROM import_session_folders sf
+         JOIN folders f ON f.id = sf.folder_id AND f.user_id <> ?2
+         WHERE sf.session_id = ?1
+         ORDER BY sf.idx",
+            &[session.id.as_str().into(), claims.sub.as_str().into()],
+        )
+        .await?;
+    for row in taken {
+        summary.warnings.push(format!(
+            "Folder {} was skipped because its id is already in use; its ciphers were imported without a folder",
+            row.idx
+        ));
+    }
+
+    // Folders that already exist aren't created again
+    let existing_folders: Option<CountRow> = db
+        .first(
+            "SELECT COUNT(*) AS count FROM import_session_folders sf
+         JOIN folders f ON f.id = sf.folder_id
+         WHERE sf.session_id = ?1",
+            &[session.id.as_str().into()],
+        )
+        .await?;
+    let existing_folders = existing_folders.map_or(0, |row| row.count as usize);
+    quota::check(
+        &db,
+        &env,
+        &claims.sub,
+        &[
+            (
+                Object::Folder,
+                staged_folders.saturating_sub(existing_folders),
+            ),
+            (Object::Cipher, staged_ciphers),
+        ],
+    )
+    .await?;
+
+    // Relationships whose folder ends up the user's; the batch below creates the missing ones
+    let relationships: Option<CountRow> = db
+        .first(
+            "SELECT COUNT(*) AS count FROM import_session_relationships r
+         JOIN import_session_folders sf ON sf.session_id = r.session_id AND sf.idx = r.folder_idx
+         LEFT JOIN folders f ON f.id = sf.folder_id
+         WHERE r.session_id = ?1 AND (f.id IS NULL OR f.user_id = ?2)",
+            &[session.id.as_str().into(), claims.sub.as_str().into()],
+        )
+        .await?;
+
+    let now = time::now_bw();
+    let results = Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "INSERT OR IGNORE INTO folders (id, user_id, name, created_at, updated_at)
+                 SELECT folder_id, ?2, name, ?3, ?3 FROM import_session_folders
+                 WHERE session_id = ?1 ORDER BY idx",
+                &[
+                    session.id.as_str().into(),
+                    claims.sub.as_str().into(),
+                    now.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
+                 SELECT c.cipher_id, ?2, NULL, c.type, c.data, c.favorite, f.id, ?3, ?3
+                 FROM import_session_ciphers c
+                 LEFT JOIN import_session_relationships r ON r.session_id = c.session_id AND r.cipher_idx = c.idx
+                 LEFT JOIN import_session_folders sf ON sf.session_id = r.session_id AND sf.idx = r.folder_idx
+                 LEFT JOIN folders f ON f.id = sf.folder_id AND f.user_id = ?2
+                 WHERE c.session_id = ?1
+                 ORDER BY c.idx",
+                &[
+                    session.id.as_str().into(),
+                    claims.sub.as_str().into(),
+                    now.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM import_session_relationships WHERE session_id = ?1",
+                &[session.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM import_session_ciphers WHERE session_id = ?1",
+                &[session.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM import_session_folders WHERE session_id = ?1",
+                &[session.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "UPDATE import_sessions SET committed_at = ?1, updated_at = ?1 WHERE id = ?2",
+                &[now.as_str().into(), session.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            quota::recount(&db, &[&claims.sub])?,
+        ],
+    )
+    .await
+    .map_err(|err| db::classify_error(err, "An imported item"))?;
+
+    let changes = |index: usize| results.get(index).copied().unwrap_or(0);
+    summary.folders_inserted = changes(0);
+    summary.folders_skipped = staged_folders.saturating_sub(summary.folders_inserted);
+    summary.ciphers_inserted = changes(1);
+    summary.relationships_resolved = relationships.map_or(0, |row| row.count as usize);
+
+    touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_vault_sync(&env, &db, &claims.sub, claims.device.as_deref()).await;
+
+    Ok(Json(summary))
+}
+
+#[derive(serde::Deserialize)]
+struct FolderIndexRow {
+    idx: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct CountRow {
+    count: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::organization::MembershipType;
+    use crate::native::{self, block_on, testing, Env};
+    use axum::http::Method;
+    use serde_json::json;
+
+    fn env() -> (Env, String) {
+        with_alice(testing::env())
+    }
+
+    /// An environment where every import statement gets a batch of its own.
+    fn batch_per_statement() -> (Env, String) {
+        with_alice(testing::env().with_var("IMPORT_BATCH_SIZE", "1"))
+    }
+
+    /// `env` with the user alice, and an access token for her.
+    fn with_alice(env: Env) -> (Env, String) {
+        let token = testing::user(&env, "alice");
+        (env, token)
+    }
+
+    fn send(
+        env: &Env,
+        method: Method,
+        path: &str,
+        token: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        testing::request(env, method, path, Some(token), body)
+    }
+
+    fn post(env: &Env, path: &str, token: &str, body: Value) -> (StatusCode, Value) {
+        send(env, Method::POST, path, token, Some(body))
+    }
+
+    fn note(name: &str) -> Value {
+        json!({ "type": 2, "name": name, "secureNote": { "type": 0 } })
+    }
+
+    /// Names of alice's ciphers with the name of their folder, by cipher name.
+    fn vault(env: &Env) -> Vec<(String, Option<String>)> {
+        let db = env.d1("vault1").unwrap();
+        let rows: Vec<Value> = block_on(db.all(
+            "SELECT json_extract(c.data, '$.name') AS name, f.name AS folder
+             FROM ciphers c LEFT JOIN folders f ON f.id = c.folder_id
+             WHERE c.user_id = 'alice' ORDER BY 1",
+            &[],
+        ))
+        .unwrap();
+        rows.into_iter()
+            .map(|row| {
+                (
+                    row["name"].as_str().unwrap().to_string(),
+                    row["folder"].as_str().map(str::to_string),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunked_import_resumes_after_a_failed_chunk() {
+        let (env, token) = env();
+        let (status, session) = post(
+            &env,
+            "/api/ciphers/import/start",
+            &token,
+            json!({ "folders": 2, "ciphers": 4 }),
+        );
+        assert_eq!(status, StatusCode::OK, "{session}");
+        let id = session["id"].as_str().unwrap();
+        assert_eq!(session["object"], "importSession");
+
+        // The first chunk points at a folder the second one carries
+        let (status, progress) = post(
+            &env,
+            "/api/ciphers/import/chunk",
+            &token,
+            json!({
+                "sessionId": id,
+                "folders": [{ "name": "work" }],
+                "ciphers": [note("a"), note("b")],
+                "folderRelationships": [{ "key": 0, "value": 0 }, { "key": 1, "value": 1 }],
+            }),
+        );
+        assert_eq!(status, StatusCode::OK, "{progress}");
+        assert_eq!(progress["ciphersReceived"], 2);
+
+        // A chunk that doesn't validate stages nothing
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import/chunk",
+            &token,
+            json!({
+                "sessionId": id,
+                "folderOffset": 1,
+                "folders": [{ "name": "home" }],
+                "cipherOffset": 2,
+                "ciphers": [note("c"), { "type": 1, "name": "d" }],
+                "folderRelationships": [{ "key": 3, "value": 1 }],
+            }),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            error["validationErrors"]["ciphers[3].login"].is_array(),
+            "{error}"
+        );
+        let (_, progress) = send(
+            &env,
+            Method::GET,
+            &format!("/api/ciphers/import/{id}"),
+            &token,
+            None,
+        );
+        assert_eq!(progress["foldersReceived"], 1);
+        assert_eq!(progress["ciphersReceived"], 2);
+        assert_eq!(progress["folderRelationshipsReceived"], 2);
+
+        // Committing now would leave items out
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import/commit",
+            &token,
+            json!({ "sessionId": id }),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error["validationErrors"]["ciphers"].is_array(), "{error}");
+        assert!(vault(&env).is_empty());
+
+        // Sending the chunk again, fixed, completes the export
+        let (status, progress) = post(
+            &env,
+            "/api/ciphers/import/chunk",
+            &token,
+            json!({
+                "sessionId": id,
+                "folderOffset": 1,
+                "folders": [{ "name": "home" }],
+                "cipherOffset": 2,
+                "ciphers": [note("c"), note("d")],
+                "folderRelationships": [{ "key": 3, "value": 1 }],
+            }),
+        );
+        assert_eq!(status, StatusCode::OK, "{progress}");
+        assert_eq!(progress["ciphersReceived"], 4);
+
+        let (status, summary) = post(
+            &env,
+            "/api/ciphers/import/commit",
+            &token,
+            json!({ "sessionId": id }),
+        );
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        assert_eq!(summary["foldersInserted"], 2);
+        assert_eq!(summary["ciphersInserted"], 4);
+        assert_eq!(summary["relationshipsResolved"], 3);
+        let folder = |name: &str| Some(name.to_string());
+        assert_eq!(
+            vault(&env),
+            [
+                ("a".to_string(), folder("work")),
+                ("b".to_string(), folder("home")),
+                ("c".to_string(), None),
+                ("d".to_string(), folder("home")),
+            ]
+        );
+
+        // A committed session takes no more chunks and isn't committed twice
+        let (status, _) = post(
+            &env,
+            "/api/ciphers/import/commit",
+            &token,
+            json!({ "sessionId": id }),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(vault(&env).len(), 4);
+    }
+
+    #[test]
+    fn resent_chunks_replace_what_they_staged() {
+        let (env, token) = env();
+        let (_, session) = post(&env, "/api/ciphers/import/start", &token, json!({}));
+        let id = session["id"].as_str().unwrap();
+        for name in ["first try", "second try"] {
+            let (status, progress) = post(
+                &env,
+                "/api/ciphers/import/chunk",
+                &token,
+                json!({ "sessionId": id, "ciphers": [note(name)] }),
+            );
+            assert_eq!(status, StatusCode::OK, "{progress}");
+            assert_eq!(progress["ciphersReceived"], 1);
+        }
+        let (status, _) = post(
+            &env,
+            "/api/ciphers/import/commit",
+            &token,
+            json!({ "sessionId": id }),
+        );
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(vault(&env), [("second try".to_string(), None)]);
+    }
+
+    #[test]
+    fn expired_sessions_are_gone_and_purged() {
+        let (env, token) = env();
+        let (_, session) = post(&env, "/api/ciphers/import/start", &token, json!({}));
+        let id = session["id"].as_str().unwrap();
+        post(
+            &env,
+            "/api/ciphers/import/chunk",
+            &token,
+            json!({ "sessionId": id, "ciphers": [note("a")] }),
+        );
+        let db = env.d1("vault1").unwrap();
+        let past = time::format_bw(Utc::now() - Duration::minutes(1));
+        block_on(db.run("UPDATE import_sessions SET expires_at = ?1", &[past.into()])).unwrap();
+
+        let path = format!("/api/ciphers/import/{id}");
+        assert_eq!(
+            send(&env, Method::GET, &path, &token, None).0,
+            StatusCode::NOT_FOUND
+        );
+        let (status, _) = post(
+            &env,
+            "/api/ciphers/import/chunk",
+            &token,
+            json!({ "sessionId": id, "ciphers": [note("b")] }),
+        );
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        block_on(native::scheduled(&env));
+        let staged: Vec<String> = block_on(db.texts(
+            "SELECT session_id FROM import_session_ciphers UNION ALL SELECT id FROM import_sessions",
+            &[],
+        ))
+        .unwrap();
+        assert!(staged.is_empty(), "{staged:?}");
+    }
+
+    #[test]
+    fn sessions_belong_to_their_user() {
+        let (env, token) = env();
+        let (_, session) = post(&env, "/api/ciphers/import/start", &token, json!({}));
+        let bob = testing::user(&env, "bob");
+        let (status, _) = post(
+            &env,
+            "/api/ciphers/import/chunk",
+            &bob,
+            json!({ "sessionId": session["id"], "ciphers": [note("a")] }),
+        );
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// An import as desktop 2023.x sends it, before ciphers said whom they were encrypted for.
+    fn older_desktop_import() -> Value {
+        json!({
+            "ciphers": [{
+                "type": 1,
+                "organizationId": null,
+                "folderId": null,
+                "name": "2.c2l0ZQ==|aXY=|bWFj",
+                "notes": null,
+                "favorite": false,
+                "login": {
+                    "uris": [{ "uri": "2.dXJp|aXY=|bWFj", "match": null }],
+                    "username": "2.dXNlcg==|aXY=|bWFj",
+                    "password": "2.cGFzcw==|aXY=|bWFj",
+                    "totp": null,
+                    "passwordRevisionDate": null,
+                },
+                "fields": null,
+                "passwordHistory": null,
+                "reprompt": 0,
+                "key": null,
+            }],
+            "folders": [{ "name": "2.Zm9sZGVy|aXY=|bWFj" }],
+            "folderRelationships": [{ "key": 0, "value": 0 }],
+        })
+    }
+
+    /// The same import as current clients send it, with `encryptedFor` on every cipher.
+    fn current_desktop_import(encrypted_for: &str) -> Value {
+        let mut import = older_desktop_import();
+        let cipher = &mut import["ciphers"][0];
+        cipher["encryptedFor"] = json!(encrypted_for);
+        cipher["login"]["fido2Credentials"] = json!([]);
+        cipher["sshKey"] = Value::Null;
+        import
+    }
+
+    #[test]
+    fn imports_without_encrypted_for_are_accepted() {
+        let (env, token) = env();
+        let (status, summary) = post(&env, "/api/ciphers/import", &token, older_desktop_import());
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        assert_eq!(summary["ciphersInserted"], 1);
+        assert_eq!(summary["relationshipsResolved"], 1);
+    }
+
+    #[test]
+    fn imports_encrypted_for_the_user_are_accepted() {
+        let (env, token) = env();
+        let (status, summary) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            current_desktop_import("alice"),
+        );
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        assert_eq!(summary["ciphersInserted"], 1);
+
+        // Some tools leave it empty rather than out
+        let (status, summary) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            current_desktop_import(""),
+        );
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        assert_eq!(vault(&env).len(), 2);
+    }
+
+    #[test]
+    fn imports_encrypted_for_someone_else_are_refused() {
+        let (env, token) = env();
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            current_desktop_import("mallory"),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error["validationErrors"]["ciphers[0].encryptedFor"],
+            json!(["The cipher was not encrypted for the current user."])
+        );
+        assert!(vault(&env).is_empty());
+    }
+
+    /// What D1 says when it loses its connection, which is worth retrying.
+    const TRANSIENT: &str = "D1_ERROR: Network connection lost.";
+
+    #[test]
+    fn failed_batches_are_retried() {
+        let (env, token) = batch_per_statement();
+        // The second cipher's batch fails twice, and goes through on its last attempt
+        env.d1("vault1").unwrap().fail_batches(1..3, TRANSIENT);
+        let (status, summary) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            json!({
+                "folders": [],
+                "ciphers": [note("a"), note("b"), note("c")],
+                "folderRelationships": [],
+            }),
+        );
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        assert_eq!(summary["ciphersInserted"], 3);
+        assert_eq!(vault(&env).len(), 3);
+    }
+
+    #[test]
+    fn a_failing_batch_reports_where_to_resume() {
+        let (env, token) = batch_per_statement();
+        // Every attempt at the second cipher's batch fails
+        env.d1("vault1").unwrap().fail_batches(2..5, TRANSIENT);
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            json!({
+                "folders": [{ "name": "work" }],
+                "ciphers": [note("a"), note("b"), note("c")],
+                "folderRelationships": [{ "key": 0, "value": 0 }, { "key": 2, "value": 0 }],
+            }),
+        );
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error["ciphersInserted"], 1);
+        assert_eq!(error["ciphersTotal"], 3);
+        assert_eq!(error["resumeOffset"], 1);
+        assert_eq!(error["rolledBack"], false);
+        // What committed stays, folder included
+        assert_eq!(vault(&env), [("a".to_string(), Some("work".to_string()))]);
+    }
+
+    #[test]
+    fn folders_are_removed_when_no_cipher_made_it_in() {
+        let (env, token) = batch_per_statement();
+        // The folder's batch commits, then the first cipher's fails for good
+        env.d1("vault1").unwrap().fail_batches(1..4, TRANSIENT);
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            json!({
+                "folders": [{ "name": "work" }],
+                "ciphers": [note("a"), note("b")],
+                "folderRelationships": [{ "key": 0, "value": 0 }],
+            }),
+        );
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error["ciphersInserted"], 0);
+        assert_eq!(error["resumeOffset"], 0);
+        assert_eq!(error["rolledBack"], true);
+        let db = env.d1("vault1").unwrap();
+        let folders = block_on(db.texts("SELECT id FROM folders", &[])).unwrap();
+        assert!(folders.is_empty(), "{folders:?}");
+        assert!(vault(&env).is_empty());
+    }
+
+    #[test]
+    fn organization_imports_resume_after_the_last_complete_cipher() {
+        let (env, token) = batch_per_statement();
+        testing::organization(&env, "org", &[("alice", MembershipType::Owner)]);
+        let db = env.d1("vault1").unwrap();
+        let cipher = |name: &str| {
+            let mut cipher = note(name);
+            cipher["organizationId"] = json!("org");
+            cipher
+        };
+        // Statements: the collection, then each cipher followed by its assignment. The batch of
+        // the second cipher's assignment keeps failing, after the cipher itself committed.
+        db.fail_batches(4..7, TRANSIENT);
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import-organization?organizationId=org",
+            &token,
+            json!({
+                "collections": [{ "name": "shared" }],
+                "ciphers": [cipher("a"), cipher("b"), cipher("c")],
+                "collectionRelationships": [
+                    { "key": 0, "value": 0 },
+                    { "key": 1, "value": 0 },
+                    { "key": 2, "value": 0 },
+                ],
+            }),
+        );
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{error}");
+        assert_eq!(error["ciphersInserted"], 1);
+        assert_eq!(error["ciphersTotal"], 3);
+        assert_eq!(error["resumeOffset"], 1);
+        assert_eq!(error["rolledBack"], false);
+        let collections = block_on(db.texts("SELECT id FROM collections", &[])).unwrap();
+        assert_eq!(collections.len(), 1);
+    }
+
+    #[test]
+    fn ciphers_are_stored_as_sent() {
+        let (env, token) = env();
+        let (status, summary) = post(&env, "/api/ciphers/import", &token, older_desktop_import());
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        let db = env.d1("vault1").unwrap();
+        let data = block_on(db.texts("SELECT data FROM ciphers", &[])).unwrap();
+        let data: Value = serde_json::from_str(&data[0]).unwrap();
+        let sent = &older_desktop_import()["ciphers"][0];
+        assert_eq!(data["name"], sent["name"]);
+        assert_eq!(data["login"], sent["login"]);
+    }
+
+    #[test]
+    fn the_last_folder_relationship_of_a_cipher_wins() {
+        let (env, token) = env();
+        let (status, summary) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            json!({
+                "folders": [{ "name": "work" }, { "name": "home" }],
+                "ciphers": [note("a")],
+                "folderRelationships": [{ "key": 0, "value": 0 }, { "key": 0, "value": 1 }],
+            }),
+        );
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        assert_eq!(
+            summary["warnings"],
+            json!(["Cipher 0 has several folder relationships; only the last one was used"])
+        );
+        assert_eq!(vault(&env), [("a".to_string(), Some("home".to_string()))]);
+    }
+
+    #[test]
+    fn large_imports_go_in_with_one_timestamp() {
+        let (env, token) = env();
+        let ciphers: Vec<Value> = (0..1500)
+            .map(|index| note(&format!("{index:04}")))
+            .collect();
+        let folders: Vec<Value> = (0..10)
+            .map(|index| json!({ "name": format!("folder {index}") }))
+            .collect();
+        let relationships: Vec<Value> = (0..1500)
+            .map(|index| json!({ "key": index, "value": index % 10 }))
+            .collect();
+        let (status, summary) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            json!({ "folders": folders, "ciphers": ciphers, "folderRelationships": relationships }),
+        );
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        assert_eq!(summary["ciphersInserted"], 1500);
+        assert_eq!(summary["relationshipsResolved"], 1500);
+
+        let db = env.d1("vault1").unwrap();
+        let timestamps = block_on(db.texts(
+            "SELECT DISTINCT created_at FROM ciphers UNION SELECT DISTINCT updated_at FROM ciphers",
+            &[],
+        ))
+        .unwrap();
+        assert_eq!(timestamps.len(), 1, "{timestamps:?}");
+        let vault = vault(&env);
+        assert_eq!(
+            vault[42],
+            ("0042".to_string(), Some("folder 2".to_string()))
+        );
+    }
+
+    /// Items as a 2017 export and a third-party converter write them.
+    fn legacy_import() -> Value {
+        json!({
+            "folders": [],
+            "ciphers": [
+                { "type": "1", "name": "login", "login": { "username": "u", "password": "p" } },
+                { "type": 2, "name": "note without details", "notes": "n" },
+                { "type": "2", "name": "note without type", "secureNote": {} },
+                { "type": 7, "name": "something newer" },
+                { "type": "3", "name": "card", "card": { "number": "4111" } },
+            ],
+            "folderRelationships": [],
+        })
+    }
+
+    #[test]
+    fn legacy_cipher_types_are_imported_and_unknown_ones_skipped() {
+        let (env, token) = env();
+        let (status, summary) = post(&env, "/api/ciphers/import", &token, legacy_import());
+        assert_eq!(status, StatusCode::OK, "{summary}");
+        assert_eq!(summary["ciphersInserted"], 4);
+        assert_eq!(
+            summary["warnings"],
+            json!(["Cipher 3 was skipped because its type (7) isn't supported"])
+        );
+
+        let db = env.d1("vault1").unwrap();
+        let notes = block_on(db.texts(
+            "SELECT json_extract(data, '$.secureNote') FROM ciphers WHERE type = 2",
+            &[],
+        ))
+        .unwrap();
+        assert_eq!(notes, [r#"{"type":0}"#, r#"{"type":0}"#]);
+    }
+
+    #[test]
+    fn chunked_imports_refuse_unknown_types() {
+        let (env, token) = env();
+        let (_, session) = post(&env, "/api/ciphers/import/start", &token, json!({}));
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import/chunk",
+            &token,
+            json!({ "sessionId": session["id"], "ciphers": legacy_import()["ciphers"] }),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let errors = error["validationErrors"].as_object().unwrap();
+        assert_eq!(errors.keys().collect::<Vec<_>>(), ["ciphers[3].type"]);
+    }
+
+    #[test]
+    fn broken_constraints_fail_the_import_without_retrying() {
+        let (env, token) = env();
+        // Only the first attempt fails: a retry would go through
+        env.d1("vault1").unwrap().fail_batches(
+            0..1,
+            "D1_ERROR: UNIQUE constraint failed: ciphers.id: SQLITE_CONSTRAINT",
+        );
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            json!({ "folders": [], "ciphers": [note("a")], "folderRelationships": [] }),
+        );
+        assert_eq!(status, StatusCode::CONFLICT, "{error}");
+        assert!(
+            error["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("An item of the batch already exists. "),
+            "{error}"
+        );
+        assert_eq!(error["rolledBack"], true);
+        assert!(vault(&env).is_empty());
+
+        env.d1("vault1").unwrap().fail_batches(
+            0..1,
+            "D1_ERROR: FOREIGN KEY constraint failed: SQLITE_CONSTRAINT",
+        );
+        let (status, error) = post(
+            &env,
+            "/api/ciphers/import",
+            &token,
+            json!({ "folders": [], "ciphers": [note("a")], "folderRelationships": [] }),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{error}");
+        assert!(vault(&env).is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+//! Registration invitations, managed by the operator through the admin endpoints.
+//!
+//! An invited address may register even when it doesn't match `ALLOWED_EMAILS` (or when that
+//! secret isn't set), until the invitation expires. Registering consumes the invitation.
+
+use axum::{extract::State, Extension, Json};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::Env;
+use crate::{
+    auth::AdminAuth,
+    config::Settings,
+    db::{self, Database, Db, Statement},
+    error::{db_error, AppError},
+    extract::{AppJson, AppPath},
+    mail::{self, templates},
+    models::invitation::{Invitation, InvitationResponse, InviteRequest},
+    time, BaseUrl,
+};
+
+/// Days an invitation stays valid.
+const INVITATION_TTL_DAYS: i64 = 5;
+
+/// Whether `email` (lowercased) has an unexpired invitation.
+pub(crate) async fn is_invited(db: &Db, email: &str) -> Result<bool, AppError> {
+    let invitation: Option<Invitation> = db
+        .first(
+            "SELECT * FROM invitations WHERE email = ?1 AND expires_at > ?2",
+            &[email.into(), time::now_bw().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(invitation.is_some())
+}
+
+/// Removes the invitation of a newly registered `email`, in the registration's batch.
+pub(crate) fn accepted_statement(db: &Db, email: &str) -> Result<Statement, AppError> {
+    Database::prepare(
+        db,
+        "DELETE FROM invitations WHERE email = ?1",
+        &[email.into()],
+    )
+    .map_err(db_error!())
+}
+
+/// Web vault link starting the registration of `email`.
+fn signup_url(base_url: &str, email: &str) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("email", email)
+        .finish();
+    format!("{}/#/signup?{query}", base_url.trim_end_matches('/'))
+}
+
+/// POST /admin/invite
+///
+/// Invites `email` to register, replacing any earlier invitation of the address. The invitation
+/// is emailed when mail is configured; otherwise, or when sending fails, the response carries the
+/// registration link (`registrationUrl`) to share by hand. Returns 409 when the address already
+/// has an account.
+#[utoipa::path(
+    post,
+    path = "/admin/invite",
+    tag = "invitations",
+    request_body = InviteRequest,
+    responses((status = 200, description = "Success")),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn post_invite(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    AppJson(payload): AppJson<InviteRequest>,
+) -> Result<Json<Value>, AppError> {
+    let email = payload.email.trim().to_lowercase();
+    if !email.contains('@') {
+        return Err(AppError::validation("email", "Invalid email address"));
+    }
+
+    let db = db::get_db(&env)?;
+    let existing: Option<Value> = db
+        .first(
+            "SELECT id FROM users WHERE email = ?1",
+            &[email.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    if existing.is_some() {
+        return Err(AppError::Conflict(
+            "An account with this email already exists".to_string(),
+        ));
+    }
+
+    let now = Utc::now();
+    let invitation = Invitation {
+        email,
+        created_at: time::format_bw(now),
+        expires_at: time::format_bw(now + Duration::days(INVITATION_TTL_DAYS)),
+    };
+    db.run(
+        "INSERT INTO invitations (email, created_at, expires_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(email) DO UPDATE SET created_at = excluded.created_at, expires_at = excluded.expires_at",
+        &[
+            invitation.email.as_str().into(),
+            invitation.created_at.as_str().into(),
+            invitation.expires_at.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    let settings = Settings::get(&env);
+    let url = signup_url(&base_url, &invitation.email);
+    let emailed = match &settings.mail {
+        Ok(Some(_)) => {
+            let message = templates::registration_invite(
+                &settings.server_name,
+                &url,
+                Duration::days(INVITATION_TTL_DAYS),
+            )
+            .to(invitation.email.as_str());
+            mail::send(&env, &message).await.is_ok()
+        }
+        _ => false,
+    };
+
+    Ok(Json(json!({
+        "email": invitation.email,
+        "expirationDate": invitation.expires_at,
+        "emailed": emailed,
+        "registrationUrl": (!emailed).then_some(url),
+    })))
+}
+
+/// GET /admin/invites
+///
+/// The pending invitations, newest first. Expired ones are left out.
+#[utoipa::path(
+    get,
+    path = "/admin/invites",
+    tag = "invitations",
+    responses((status = 200, description = "Success", body = Vec<InvitationResponse>)),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn get_invites(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Vec<InvitationResponse>>, AppError> {
+    let db = db::get_db(&env)?;
+    let invitations: Vec<Invitation> = db
+        .all(
+            "SELECT * FROM invitations WHERE expires_at > ?1 ORDER BY created_at DESC",
+            &[time::now_bw().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(Json(invitations.into_iter().map(Into::into).collect()))
+}
+
+/// DELETE /admin/invites/{email}
+///
+/// Revokes an invitation; the address can no longer register through it.
+#[utoipa::path(
+    delete,
+    path = "/admin/invites/{email}",
+    tag = "invitations",
+    params(("email" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn delete_invite(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    AppPath(email): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let removed = db
+        .run(
+            "DELETE FROM invitations WHERE email = ?1",
+            &[email.trim().to_lowercase().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    if removed == 0 {
+        return Err(AppError::NotFound("Invitation not found".to_string()));
+    }
+    Ok(Json(()))
+}
@@ -1,54 +1,175 @@
-use axum::{extract::State, Json};
-use chrono::{SecondsFormat, Utc};
+use axum::{
+    extract::State,
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
 use serde::Deserialize;
-use serde_json::{json, Value};
+use serde_json::json;
 use std::sync::Arc;
-use worker::Env;
+use worker::{Fetch, Headers, Method, Request, RequestInit, Url};
 
-use crate::{db, error::AppError};
+use crate::config::Settings;
+use crate::extract::AppQuery;
+use crate::Env;
+use crate::{
+    auth::Claims,
+    db::{self, Database},
+    error::{internal_error, AppError},
+    handlers::{ciphers::RawJson, config::SERVER_VERSION},
+    time,
+};
 
-/// GET /api/now
+/// GET /now, /api/now
 ///
 /// Mirrors vaultwarden's `/api/now`: returns current UTC timestamp as an RFC3339 string.
+#[utoipa::path(
+    get,
+    path = "/now",
+    tag = "meta",
+    responses((status = 200, description = "Success", body = String))
+)]
 #[worker::send]
 pub async fn now() -> Json<String> {
-    Json(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true))
+    Json(time::now_bw())
 }
 
-/// GET /api/alive
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AliveQuery {
+    /// Also check the worker's bindings.
+    pub deep: bool,
+}
+
+/// GET /alive, /api/alive
 ///
-/// Simple healthcheck. Vaultwarden uses this to also verify DB connectivity.
+/// Simple healthcheck for uptime monitors. It doesn't touch D1 so frequent checks don't use up
+/// its quota; `?deep=true` also runs a trivial query and answers 503 when it fails, so a broken
+/// binding can be told apart from a healthy worker.
+#[utoipa::path(
+    get,
+    path = "/alive",
+    tag = "meta",
+    responses((status = 200, description = "Success"))
+)]
 #[worker::send]
-pub async fn alive(State(env): State<Arc<Env>>) -> Result<Json<String>, AppError> {
-    // Verify D1 binding is present + basic query works.
-    let db = db::get_db(&env)?;
-    db.prepare("SELECT 1 as ok")
-        .first::<i32>(Some("ok"))
-        .await
-        .map_err(|_| AppError::Database)?;
-    Ok(now().await)
+pub async fn alive(State(env): State<Arc<Env>>, AppQuery(query): AppQuery<AliveQuery>) -> Response {
+    let Json(time) = now().await;
+    if !query.deep {
+        return Json(time).into_response();
+    }
+
+    let database = match db::get_db(&env) {
+        Ok(db) => db
+            .first_column::<i32>("SELECT 1 as ok", &[], "ok")
+            .await
+            .is_ok_and(|ok| ok == Some(1)),
+        Err(_) => false,
+    };
+    let status = |ok: bool| if ok { "ok" } else { "error" };
+    let code = if database {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        code,
+        Json(json!({
+            "status": status(database),
+            "time": time,
+            "checks": {
+                "database": status(database),
+            },
+        })),
+    )
+        .into_response()
 }
 
 /// GET /api/version
 ///
 /// Returns a Bitwarden-server-like version string. Clients sometimes call this endpoint.
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    tag = "meta",
+    responses((status = 200, description = "Success"))
+)]
 #[worker::send]
 pub async fn version() -> Json<&'static str> {
-    // Keep this in sync with `src/handlers/config.rs`'s `version`.
-    Json("2025.12.0")
+    Json(SERVER_VERSION)
 }
 
 #[derive(Debug, Deserialize)]
 pub struct HibpBreachQuery {
-    #[allow(dead_code)] // stub endpoint doesn't use it yet
     pub username: String,
 }
 
+const HIBP_BREACHED_ACCOUNT_URL: &str = "https://haveibeenpwned.com/api/v3/breachedaccount";
+
 /// GET /api/hibp/breach?username=...
 ///
-/// Vaultwarden can proxy HaveIBeenPwned if configured. This minimal server doesn't.
-/// We return an empty array to indicate "no breach data" without surfacing an error in clients.
+/// Proxies HaveIBeenPwned's breached account lookup for the web vault's data breach report,
+/// using the HIBP_API_KEY secret. Without a key the feature is reported as disabled.
+#[utoipa::path(
+    get,
+    path = "/api/hibp/breach",
+    tag = "meta",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
-pub async fn hibp_breach(_query: axum::extract::Query<HibpBreachQuery>) -> Json<Value> {
-    Json(json!([]))
+pub async fn hibp_breach(
+    _claims: Claims,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppQuery(query): AppQuery<HibpBreachQuery>,
+) -> Result<Response, AppError> {
+    let api_key = settings.hibp_api_key.clone().ok_or_else(|| {
+        AppError::BadRequest(
+            "Breach reports are disabled: no HaveIBeenPwned API key is configured".to_string(),
+        )
+    })?;
+
+    let username = query.username.trim();
+    if username.is_empty() {
+        return Err(AppError::validation("username", "A username is required"));
+    }
+
+    let mut url = Url::parse(HIBP_BREACHED_ACCOUNT_URL).map_err(internal_error!())?;
+    url.path_segments_mut()
+        .map_err(|_| AppError::Internal(Some("breach lookup URL cannot be a base".to_string())))?
+        .push(username);
+    url.query_pairs_mut()
+        .append_pair("truncateResponse", "false")
+        .append_pair("includeUnverified", "false");
+
+    let headers = Headers::new();
+    headers.set("hibp-api-key", &api_key)?;
+    headers.set("User-Agent", "warden-worker")?;
+    let mut init = RequestInit::new();
+    init.with_method(Method::Get).with_headers(headers);
+    let mut response = Fetch::Request(Request::new_with_init(url.as_str(), &init)?)
+        .send()
+        .await?;
+
+    match response.status_code() {
+        200 => Ok(RawJson(response.text().await?).into_response()),
+        // The account isn't in any breach
+        404 => Ok(Json(json!([])).into_response()),
+        429 => {
+            let retry_after = response
+                .headers()
+                .get("Retry-After")?
+                .unwrap_or_else(|| "2".to_string());
+            Ok((
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after)],
+                Json(json!({ "error": "HaveIBeenPwned rate limit reached, try again later" })),
+            )
+                .into_response())
+        }
+        status => Err(AppError::Internal(Some(format!(
+            "HaveIBeenPwned breach lookup failed with status {status}"
+        )))),
+    }
 }
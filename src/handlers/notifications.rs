@@ -0,0 +1,97 @@
+//! The notifications hub: desktop and browser clients keep a SignalR WebSocket open to
+//! `/notifications/hub` and sync as soon as another device changes something, instead of at
+//! their next poll. The connections live in the user's
+//! [`NotificationsHub`](crate::durable::notifications_hub::NotificationsHub) Durable Object,
+//! which [`crate::notify`] posts every notification to.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap},
+    response::Response,
+    Json,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+use worker::{Headers, HttpResponse, Request, RequestInit};
+
+use crate::Env;
+use crate::{
+    auth::{Claims, QueryTokenClaims},
+    durable::CONNECT_PATH,
+    error::AppError,
+    notify,
+};
+
+/// POST /notifications/hub/negotiate
+///
+/// The first step of the SignalR connection: the only transport offered is the WebSocket. The
+/// connection id isn't kept; the hub knows connections by their socket.
+#[utoipa::path(
+    post,
+    path = "/notifications/hub/negotiate",
+    tag = "notifications",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_negotiate(_claims: Claims) -> Json<Value> {
+    Json(json!({
+        "connectionId": Uuid::new_v4().to_string(),
+        "availableTransports": [
+            {
+                "transport": "WebSockets",
+                "transferFormats": ["Text", "Binary"],
+            }
+        ],
+    }))
+}
+
+/// GET /notifications/hub
+///
+/// Upgrades to the user's hub connection. Browsers can't set headers on a WebSocket, so the
+/// access token comes in the `access_token` query parameter. Returns 404 when
+/// `NOTIFICATIONS_HUB` isn't bound.
+#[utoipa::path(
+    get,
+    path = "/notifications/hub",
+    tag = "notifications",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_hub(
+    QueryTokenClaims(claims): QueryTokenClaims,
+    State(env): State<Arc<Env>>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let upgrade = headers
+        .get(header::UPGRADE)
+        .and_then(|upgrade| upgrade.to_str().ok());
+    if !upgrade.is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")) {
+        return Err(AppError::BadRequest(
+            "Expected a WebSocket upgrade".to_string(),
+        ));
+    }
+    let stub = notify::hub_stub(&env, &claims.sub)
+        .ok_or_else(|| AppError::NotFound("The notifications hub is not enabled".to_string()))?;
+
+    let upgrade_headers = Headers::new();
+    upgrade_headers.set("Upgrade", "websocket")?;
+    let mut init = RequestInit::new();
+    init.with_headers(upgrade_headers);
+    let mut url = format!("https://notifications-hub{CONNECT_PATH}");
+    if let Some(device) = claims.device.as_deref() {
+        url.push('?');
+        url.push_str(
+            &form_urlencoded::Serializer::new(String::new())
+                .append_pair("device", device)
+                .finish(),
+        );
+    }
+    let request = Request::new_with_init(&url, &init)?;
+    // The client end of the socket travels in the response's extensions
+    let response: HttpResponse = stub.fetch_with_request(request).await?.try_into()?;
+    Ok(response.map(Body::new))
+}
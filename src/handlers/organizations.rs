@@ -0,0 +1,1544 @@
+//! Organizations: shared vaults whose members each hold the organization key, encrypted to them.
+
+use axum::{extract::State, Extension, Json};
+use chrono::{Duration, Utc};
+use jwt_compact::Claims as JwtClaims;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use uuid::Uuid;
+
+use crate::config::Settings;
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
+use crate::{
+    auth::{jwt_time_options, keys::KeyRing, validate_token_times, Claims},
+    db::{self, Database, Db, Statement},
+    error::{db_error, internal_error, AppError},
+    handlers::{
+        attachments, collections,
+        events::{self, member_event, EventSource},
+        groups, policies,
+    },
+    mail::{self, templates},
+    models::{
+        event::{Event, EventType},
+        organization::{
+            Membership, MembershipStatus, MembershipType, Organization, OrganizationAcceptRequest,
+            OrganizationConfirmRequest, OrganizationCreateRequest, OrganizationInviteRequest,
+            OrganizationKeysRequest, OrganizationMemberUpdateRequest, OrganizationUpdateRequest,
+        },
+        response::ListResponse,
+        user::{PasswordOrOtpData, User},
+    },
+    notify::{self, UpdateType},
+    quota, time, BaseUrl,
+};
+
+pub(crate) const ORG_INVITE_PURPOSE: &str = "org_invite";
+/// Invitations can be accepted for this long.
+const ORG_INVITE_TTL_DAYS: i64 = 5;
+
+/// Signed into the invitation link; presented back when the invitation is accepted.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OrgInviteClaims {
+    pub purpose: String,
+    pub org_id: String,
+    pub membership_id: String,
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+struct MemberUserId {
+    user_id: String,
+}
+
+fn organization_not_found() -> AppError {
+    AppError::NotFound("Organization not found".to_string())
+}
+
+/// Loads an organization together with the user's confirmed membership in it. Organizations the
+/// user isn't a confirmed member of look like missing ones.
+pub(crate) async fn find_organization_for_member(
+    db: &Db,
+    org_id: &str,
+    user_id: &str,
+) -> Result<(Organization, Membership), AppError> {
+    let membership: Membership = db
+        .first(
+            "SELECT * FROM organization_users WHERE organization_id = ?1 AND user_id = ?2 AND status = ?3",
+            &[
+                org_id.into(),
+                user_id.into(),
+                (MembershipStatus::Confirmed as i32).into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(organization_not_found)?;
+
+    let org: Organization = db
+        .first(
+            "SELECT * FROM organizations WHERE id = ?1",
+            &[org_id.into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(organization_not_found)?;
+
+    Ok((org, membership))
+}
+
+/// Bumps the revision date of every member so their clients resync the organization's data.
+pub(crate) fn touch_members_statement(
+    db: &Db,
+    org_id: &str,
+    now: &str,
+) -> Result<Statement, AppError> {
+    Database::prepare(
+        db,
+        "UPDATE users SET updated_at = ?1
+         WHERE id IN (SELECT user_id FROM organization_users WHERE organization_id = ?2)",
+        &[now.into(), org_id.into()],
+    )
+    .map_err(db_error!())
+}
+
+/// The user's confirmed memberships with their organizations.
+pub(crate) async fn list_user_organizations(
+    db: &Db,
+    user_id: &str,
+) -> Result<Vec<(Organization, Membership)>, AppError> {
+    let memberships: Vec<Membership> = db
+        .all(
+            "SELECT * FROM organization_users WHERE user_id = ?1 AND status = ?2",
+            &[user_id.into(), (MembershipStatus::Confirmed as i32).into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    if memberships.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let org_ids: Vec<&str> = memberships
+        .iter()
+        .map(|m| m.organization_id.as_str())
+        .collect();
+    let org_ids_json = serde_json::to_string(&org_ids).map_err(internal_error!())?;
+    let organizations: Vec<Organization> = db
+        .all(
+            "SELECT * FROM organizations WHERE id IN (SELECT value FROM json_each(?1)) ORDER BY name",
+            &[org_ids_json.into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    let mut memberships: HashMap<String, Membership> = memberships
+        .into_iter()
+        .map(|m| (m.organization_id.clone(), m))
+        .collect();
+    Ok(organizations
+        .into_iter()
+        .filter_map(|org| memberships.remove(&org.id).map(|m| (org, m)))
+        .collect())
+}
+
+/// `profile.organizations` for the profile and `/api/sync`.
+pub(crate) async fn list_profile_organizations(
+    db: &Db,
+    user_id: &str,
+) -> Result<Vec<Value>, AppError> {
+    Ok(list_user_organizations(db, user_id)
+        .await?
+        .iter()
+        .map(|(org, membership)| org.to_profile_json(membership))
+        .collect())
+}
+
+/// POST /api/organizations
+///
+/// Creates an organization with the caller as its confirmed owner, plus the default collection.
+#[utoipa::path(
+    post,
+    path = "/api/organizations",
+    tag = "organizations",
+    request_body = OrganizationCreateRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppJson(payload): AppJson<OrganizationCreateRequest>,
+) -> Result<Json<Value>, AppError> {
+    if payload.name.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "Organization name is required".to_string(),
+        ));
+    }
+
+    let OrganizationCreateRequest {
+        name,
+        billing_email,
+        key,
+        collection_name,
+        keys,
+        ..
+    } = payload;
+
+    let now = time::now_bw();
+    let (public_key, private_key) = match keys {
+        Some(keys) => (Some(keys.public_key), Some(keys.encrypted_private_key)),
+        None => (None, None),
+    };
+    let org = Organization {
+        id: Uuid::new_v4().to_string(),
+        name,
+        billing_email: billing_email.to_lowercase(),
+        public_key,
+        private_key,
+        use_totp: true,
+        use_groups: settings.org_groups_enabled,
+        created_at: now.clone(),
+        updated_at: now.clone(),
+    };
+    let membership_id = Uuid::new_v4().to_string();
+    let collection_id = Uuid::new_v4().to_string();
+
+    let db = db::get_db(&env)?;
+    Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "INSERT INTO organizations (id, name, billing_email, public_key, private_key, use_groups, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                &[
+                    org.id.as_str().into(),
+                    org.name.as_str().into(),
+                    org.billing_email.as_str().into(),
+                    org.public_key.as_deref().into(),
+                    org.private_key.as_deref().into(),
+                    org.use_groups.into(),
+                    org.created_at.as_str().into(),
+                    org.updated_at.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "INSERT INTO organization_users (id, organization_id, user_id, email, akey, status, atype, access_all, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 1, ?8, ?8)",
+                &[
+                    membership_id.as_str().into(),
+                    org.id.as_str().into(),
+                    claims.sub.as_str().into(),
+                    claims.email.to_lowercase().into(),
+                    key.as_str().into(),
+                    (MembershipStatus::Confirmed as i32).into(),
+                    (MembershipType::Owner as i32).into(),
+                    now.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "INSERT INTO collections (id, organization_id, name, external_id, created_at, updated_at)
+             VALUES (?1, ?2, ?3, NULL, ?4, ?4)",
+                &[
+                    collection_id.as_str().into(),
+                    org.id.as_str().into(),
+                    collection_name.as_str().into(),
+                    now.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    db::touch_user_updated_at(&db, &claims.sub).await?;
+
+    Ok(Json(org.to_json()))
+}
+
+/// GET /api/organizations
+#[utoipa::path(
+    get,
+    path = "/api/organizations",
+    tag = "organizations",
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_organizations(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    let organizations = list_profile_organizations(&db, &claims.sub).await?;
+
+    Ok(Json(ListResponse::new(organizations)))
+}
+
+/// GET /api/organizations/{id}
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}",
+    tag = "organizations",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, _membership) = find_organization_for_member(&db, &id, &claims.sub).await?;
+    Ok(Json(org.to_json()))
+}
+
+/// GET /api/organizations/{id}/keys
+///
+/// The organization's key pair, for any confirmed member.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/keys",
+    tag = "organizations",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_organization_keys(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, _membership) = find_organization_for_member(&db, &id, &claims.sub).await?;
+    Ok(Json(org.to_keys_json()))
+}
+
+/// POST /api/organizations/{id}/keys
+///
+/// Owners add a key pair to an organization created without one. Existing keys are never
+/// replaced: members' data is encrypted against them.
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{id}/keys",
+    tag = "organizations",
+    params(("id" = String, Path)),
+    request_body = OrganizationKeysRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_organization_keys(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<OrganizationKeysRequest>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (mut org, membership) = find_organization_for_member(&db, &id, &claims.sub).await?;
+    if !membership.is_owner() {
+        return Err(AppError::Forbidden(
+            "Only owners can set the organization's keys".to_string(),
+        ));
+    }
+    if org.has_keys() {
+        return Err(AppError::BadRequest(
+            "The organization already has keys".to_string(),
+        ));
+    }
+    if payload.public_key.trim().is_empty() || payload.encrypted_private_key.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "Both the public and the encrypted private key are required".to_string(),
+        ));
+    }
+
+    let now = time::now_bw();
+    // The key check guards against a concurrent request having set them in the meantime
+    let changed = db
+        .run(
+            "UPDATE organizations SET public_key = ?1, private_key = ?2, updated_at = ?3
+         WHERE id = ?4 AND (public_key IS NULL OR private_key IS NULL)",
+            &[
+                payload.public_key.as_str().into(),
+                payload.encrypted_private_key.as_str().into(),
+                now.as_str().into(),
+                org.id.as_str().into(),
+            ],
+        )
+        .await
+        .map_err(db_error!())?;
+    if changed == 0 {
+        return Err(AppError::BadRequest(
+            "The organization already has keys".to_string(),
+        ));
+    }
+    Database::batch(&db, vec![touch_members_statement(&db, &org.id, &now)?])
+        .await
+        .map_err(db_error!())?;
+
+    org.public_key = Some(payload.public_key);
+    org.private_key = Some(payload.encrypted_private_key);
+    org.updated_at = now;
+    Ok(Json(org.to_keys_json()))
+}
+
+/// PUT /api/organizations/{id}
+///
+/// Owners and admins can rename the organization, change its billing email and turn its groups
+/// and its members' TOTP codes on or off.
+#[utoipa::path(
+    method(put, post),
+    path = "/api/organizations/{id}",
+    tag = "organizations",
+    params(("id" = String, Path)),
+    request_body = OrganizationUpdateRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_organization(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<OrganizationUpdateRequest>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (mut org, membership) = find_organization_for_member(&db, &id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can change organization settings".to_string(),
+        ));
+    }
+    if payload.name.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "Organization name is required".to_string(),
+        ));
+    }
+
+    org.name = payload.name;
+    if let Some(billing_email) = payload.billing_email {
+        org.billing_email = billing_email.to_lowercase();
+    }
+    if let Some(use_totp) = payload.use_totp {
+        org.use_totp = use_totp;
+    }
+    if let Some(use_groups) = payload.use_groups {
+        org.use_groups = use_groups;
+    }
+    org.updated_at = time::now_bw();
+
+    db.run(
+        "UPDATE organizations SET name = ?1, billing_email = ?2, use_totp = ?3, use_groups = ?4,
+         updated_at = ?5 WHERE id = ?6",
+        &[
+            org.name.as_str().into(),
+            org.billing_email.as_str().into(),
+            org.use_totp.into(),
+            org.use_groups.into(),
+            org.updated_at.as_str().into(),
+            org.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    let event = Event {
+        organization_id: Some(org.id.clone()),
+        ..Event::new(EventType::OrganizationUpdated, &claims.sub)
+    };
+    events::log_event(&db, &source, event).await;
+
+    Ok(Json(org.to_json()))
+}
+
+/// DELETE /api/organizations/{id}
+///
+/// Owner only, confirmed with the master password. Removes the organization together with its
+/// ciphers (and their attachment files), collections and memberships.
+#[utoipa::path(
+    delete,
+    path = "/api/organizations/{id}",
+    tag = "organizations",
+    params(("id" = String, Path)),
+    request_body = PasswordOrOtpData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn delete_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<PasswordOrOtpData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &id, &claims.sub).await?;
+    if !membership.is_owner() {
+        return Err(AppError::Forbidden(
+            "Only owners can delete an organization".to_string(),
+        ));
+    }
+
+    let user: User = db
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[claims.sub.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let provided_hash = payload
+        .master_password_hash
+        .ok_or_else(|| AppError::BadRequest("Missing master password hash".to_string()))?;
+    if !user
+        .verify_master_password(&provided_hash)
+        .await?
+        .is_valid()
+    {
+        return Err(AppError::Unauthorized("Invalid password".to_string()));
+    }
+
+    let member_ids: Vec<MemberUserId> = db
+        .all(
+            "SELECT user_id FROM organization_users WHERE organization_id = ?1 AND user_id IS NOT NULL",
+            &[org.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    if attachments::attachments_enabled(env.as_ref()) {
+        let keys = attachments::list_attachment_keys_for_organization(&db, &org.id).await?;
+        attachments::delete_storage_objects(env.as_ref(), &keys).await?;
+    }
+
+    let now = time::now_bw();
+    Database::batch(
+        &db,
+        vec![
+            // Members resync so the organization and its ciphers disappear from their vaults
+            touch_members_statement(&db, &org.id, &now)?,
+            Database::prepare(
+                &db,
+                "DELETE FROM ciphers_collections
+             WHERE collection_id IN (SELECT id FROM collections WHERE organization_id = ?1)",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM collections_groups
+             WHERE group_id IN (SELECT id FROM groups WHERE organization_id = ?1)",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM groups_users
+             WHERE group_id IN (SELECT id FROM groups WHERE organization_id = ?1)",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM groups WHERE organization_id = ?1",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            quota::removing_ciphers(&db, "c.organization_id = ?1", &[org.id.as_str().into()])?,
+            Database::prepare(
+                &db,
+                "DELETE FROM ciphers WHERE organization_id = ?1",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM organization_policies WHERE organization_id = ?1",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM events WHERE organization_id = ?1",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM collections WHERE organization_id = ?1",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM organization_users WHERE organization_id = ?1",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            Database::prepare(
+                &db,
+                "DELETE FROM organizations WHERE id = ?1",
+                &[org.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    for member in &member_ids {
+        let acting_device = if member.user_id == claims.sub {
+            claims.device.as_deref()
+        } else {
+            None
+        };
+        notify::notify_vault_sync(&env, &db, &member.user_id, acting_device).await;
+    }
+
+    Ok(Json(()))
+}
+
+/// Web vault link accepting an invitation, carrying a signed invitation token.
+fn invite_url(
+    env: &Env,
+    base_url: &str,
+    org: &Organization,
+    membership_id: &str,
+    email: &str,
+    has_existing_user: bool,
+) -> Result<String, AppError> {
+    let mut claims = JwtClaims::new(OrgInviteClaims {
+        purpose: ORG_INVITE_PURPOSE.to_string(),
+        org_id: org.id.clone(),
+        membership_id: membership_id.to_string(),
+        email: email.to_string(),
+    });
+    claims.expiration = Some(Utc::now() + Duration::days(ORG_INVITE_TTL_DAYS));
+    let token = KeyRing::access(env)?.sign(&claims)?;
+
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("organizationId", &org.id)
+        .append_pair("organizationUserId", membership_id)
+        .append_pair("email", email)
+        .append_pair("organizationName", &org.name)
+        .append_pair("token", &token)
+        .append_pair("orgUserHasExistingUser", &has_existing_user.to_string())
+        .finish();
+    Ok(format!(
+        "{}/#/accept-organization/?{query}",
+        base_url.trim_end_matches('/')
+    ))
+}
+
+#[derive(Deserialize)]
+struct MemberUserDetails {
+    id: String,
+    name: Option<String>,
+    two_factor_enabled: i32,
+}
+
+/// The account behind a membership. Invited members haven't registered or accepted yet, so they
+/// have no account details to show.
+fn member_user(member: &Membership) -> Option<&String> {
+    member
+        .user_id
+        .as_ref()
+        .filter(|_| member.status != MembershipStatus::Invited as i32)
+}
+
+/// GET /api/organizations/{id}/users
+///
+/// Members of the organization with their status and collection access. Visible to owners,
+/// admins and managers.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/users",
+    tag = "organizations",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_members(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_manager() {
+        return Err(AppError::Forbidden(
+            "You don't have permission to view the organization's members".to_string(),
+        ));
+    }
+
+    let members: Vec<Membership> = db
+        .all(
+            "SELECT * FROM organization_users WHERE organization_id = ?1 ORDER BY email",
+            &[org.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    let users: Vec<MemberUserDetails> = db
+        .all(
+            "SELECT u.id, u.name,
+                EXISTS (
+                    SELECT 1 FROM twofactor t
+                    WHERE t.user_uuid = u.id AND t.enabled = 1 AND t.atype = 0 -- Authenticator
+                ) AS two_factor_enabled
+         FROM users u
+         WHERE u.id IN (SELECT user_id FROM organization_users WHERE organization_id = ?1)",
+            &[org.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    let users: HashMap<String, MemberUserDetails> = users
+        .into_iter()
+        .map(|user| (user.id.clone(), user))
+        .collect();
+
+    let mut assignments = collections::list_member_assignments(&db, &org.id).await?;
+    let mut member_groups = groups::list_member_groups(&db, &org.id).await?;
+    let data: Vec<Value> = members
+        .iter()
+        .map(|member| {
+            let user = member_user(member).and_then(|user_id| users.get(user_id));
+            member.to_user_details_json(
+                user.and_then(|user| user.name.as_deref()),
+                user.is_some_and(|user| user.two_factor_enabled != 0),
+                assignments.remove(&member.id).unwrap_or_default(),
+                member_groups.remove(&member.id).unwrap_or_default(),
+            )
+        })
+        .collect();
+
+    Ok(Json(ListResponse::new(data)))
+}
+
+/// POST /api/organizations/{id}/users/invite
+///
+/// Owners and admins invite members by email. Each invitation is a membership in "Invited"
+/// status carrying a signed link to accept it, emailed when mail delivery is configured. With
+/// ORG_INVITE_LINKS set, links that weren't emailed are returned for the admin to share.
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{id}/users/invite",
+    tag = "organizations",
+    params(("id" = String, Path)),
+    request_body = OrganizationInviteRequest,
+    responses((status = 200, description = "Success", body = ListResponse<Value>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_invite(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    AppPath(org_id): AppPath<String>,
+    AppJson(payload): AppJson<OrganizationInviteRequest>,
+) -> Result<Json<ListResponse<Value>>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can invite members".to_string(),
+        ));
+    }
+    let role = MembershipType::from_i32(payload.atype)
+        .ok_or_else(|| AppError::validation("Type", "Invalid member type"))?;
+    if role == MembershipType::Owner && !membership.is_owner() {
+        return Err(AppError::Forbidden(
+            "Only owners can invite other owners".to_string(),
+        ));
+    }
+
+    let mut seen = HashSet::new();
+    let emails: Vec<String> = payload
+        .emails
+        .iter()
+        .map(|email| email.trim().to_lowercase())
+        .filter(|email| !email.is_empty() && seen.insert(email.clone()))
+        .collect();
+    if emails.is_empty() {
+        return Err(AppError::BadRequest(
+            "At least one email is required".to_string(),
+        ));
+    }
+    let emails_json = serde_json::to_string(&emails).map_err(internal_error!())?;
+
+    #[derive(Deserialize)]
+    struct EmailRow {
+        email: String,
+    }
+    let existing: Option<EmailRow> = db
+        .first(
+            "SELECT email FROM organization_users
+         WHERE organization_id = ?1 AND email IN (SELECT value FROM json_each(?2))",
+            &[org.id.as_str().into(), emails_json.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    if let Some(existing) = existing {
+        return Err(AppError::BadRequest(format!(
+            "{} is already a member of the organization",
+            existing.email
+        )));
+    }
+
+    #[derive(Deserialize)]
+    struct UserRow {
+        id: String,
+        email: String,
+    }
+    let users: Vec<UserRow> = db
+        .all(
+            "SELECT id, email FROM users WHERE email IN (SELECT value FROM json_each(?1))",
+            &[emails_json.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    let users: HashMap<String, String> = users
+        .into_iter()
+        .map(|user| (user.email, user.id))
+        .collect();
+
+    let now = time::now_bw();
+    let access_all = payload.access_all;
+    let collections_access = if access_all {
+        Vec::new()
+    } else {
+        payload.collections
+    };
+    let group_ids = if org.use_groups {
+        payload.groups
+    } else {
+        Vec::new()
+    };
+    let mut statements = Vec::new();
+    let mut invites = Vec::new();
+    for email in emails {
+        let membership_id = Uuid::new_v4().to_string();
+        statements.push(
+            Database::prepare(
+                &db,
+                "INSERT INTO organization_users (id, organization_id, user_id, email, akey, status, atype, access_all, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, NULL, ?5, ?6, ?7, ?8, ?8)",
+                &[
+                    membership_id.as_str().into(),
+                    org.id.as_str().into(),
+                    users.get(&email).cloned().into(),
+                    email.as_str().into(),
+                    (MembershipStatus::Invited as i32).into(),
+                    (role as i32).into(),
+                    (access_all as i32).into(),
+                    now.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+        );
+        statements.extend(
+            collections::member_assignment_statements(
+                &db,
+                &org.id,
+                &membership_id,
+                &collections_access,
+            )
+            .await?,
+        );
+        if !group_ids.is_empty() {
+            statements.extend(
+                groups::member_group_statements(&db, &org.id, &membership_id, group_ids.clone())
+                    .await?,
+            );
+        }
+        invites.push((membership_id, email));
+    }
+    Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+
+    let mail_enabled = matches!(settings.mail, Ok(Some(_)));
+    let links_enabled = settings.org_invite_links;
+    let mut data = Vec::with_capacity(invites.len());
+    for (membership_id, email) in invites {
+        let event = Event {
+            organization_id: Some(org.id.clone()),
+            user_id: users.get(&email).cloned(),
+            member_id: Some(membership_id.clone()),
+            ..Event::new(EventType::OrganizationUserInvited, &claims.sub)
+        };
+        events::log_event(&db, &source, event).await;
+        let mut emailed = false;
+        let mut url = None;
+        if mail_enabled || links_enabled {
+            let invite_url = invite_url(
+                env.as_ref(),
+                &base_url,
+                &org,
+                &membership_id,
+                &email,
+                users.contains_key(&email),
+            )?;
+            if mail_enabled {
+                let message = templates::organization_invite(
+                    &org.name,
+                    &invite_url,
+                    Duration::days(ORG_INVITE_TTL_DAYS),
+                )
+                .to(email.as_str());
+                emailed = mail::send(&env, &message).await.is_ok();
+            }
+            url = Some(invite_url);
+        }
+        if !mail_enabled {
+            log::info!("Mail delivery isn't configured; invitation for {email} was not emailed");
+        }
+        data.push(json!({
+            "id": membership_id,
+            "email": email,
+            "inviteUrl": url.filter(|_| links_enabled && !emailed),
+        }));
+    }
+
+    Ok(Json(ListResponse::new(data)))
+}
+
+fn member_not_found() -> AppError {
+    AppError::NotFound("Member not found".to_string())
+}
+
+/// Loads a membership of the organization, whatever its status.
+pub(crate) async fn find_member(
+    db: &Db,
+    org_id: &str,
+    member_id: &str,
+) -> Result<Membership, AppError> {
+    db.first(
+        "SELECT * FROM organization_users WHERE id = ?1 AND organization_id = ?2",
+        &[member_id.into(), org_id.into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(member_not_found)
+}
+
+/// POST /api/organizations/{id}/users/{member_id}/accept
+///
+/// The invited user accepts with the token from their invitation link. The membership is bound
+/// to their account and waits for an admin to confirm it. Organizations that enroll members in
+/// account recovery automatically require the member's encrypted user key here.
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{id}/users/{member_id}/accept",
+    tag = "organizations",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    request_body = OrganizationAcceptRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_accept_invite(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<OrganizationAcceptRequest>,
+) -> Result<Json<()>, AppError> {
+    // Token problems aren't session problems: answering 401 would log the client out
+    let invalid = || AppError::BadRequest("Invalid invitation token".to_string());
+    let token = KeyRing::access(env.as_ref())?
+        .verify::<OrgInviteClaims>(&payload.token)
+        .ok_or_else(invalid)?;
+    validate_token_times(token.claims(), &jwt_time_options(&settings))
+        .map_err(|_| AppError::BadRequest("This invitation has expired".to_string()))?;
+    let invite = &token.claims().custom;
+    if invite.purpose != ORG_INVITE_PURPOSE
+        || invite.org_id != org_id
+        || invite.membership_id != member_id
+    {
+        return Err(invalid());
+    }
+    if invite.email != claims.email.to_lowercase() {
+        return Err(AppError::BadRequest(
+            "This invitation was sent to a different email address".to_string(),
+        ));
+    }
+
+    let db = db::get_db(&env)?;
+    // A revoked invitation no longer has a membership row
+    let member = find_member(&db, &org_id, &member_id).await?;
+    if member.status != MembershipStatus::Invited as i32 {
+        return Err(AppError::BadRequest(
+            "This invitation has already been accepted".to_string(),
+        ));
+    }
+
+    let reset_password_key = payload
+        .reset_password_key
+        .filter(|key| !key.trim().is_empty());
+    if reset_password_key.is_none()
+        && policies::reset_password_policy(&db, &org_id)
+            .await?
+            .is_some_and(|policy| policy.auto_enroll_enabled)
+    {
+        return Err(AppError::BadRequest(
+            "The organization requires account recovery enrollment".to_string(),
+        ));
+    }
+
+    db.run(
+        "UPDATE organization_users SET user_id = ?1, status = ?2, reset_password_key = ?3, updated_at = ?4 WHERE id = ?5",
+        &[
+            claims.sub.as_str().into(),
+            (MembershipStatus::Accepted as i32).into(),
+            reset_password_key.as_deref().into(),
+            time::now_bw().into(),
+            member.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    Ok(Json(()))
+}
+
+/// POST /api/organizations/{id}/users/{member_id}/confirm
+///
+/// An owner or admin hands an accepted member the organization key, encrypted with the member's
+/// public key. From then on the member syncs the organization, its collections and ciphers.
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{id}/users/{member_id}/confirm",
+    tag = "organizations",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    request_body = OrganizationConfirmRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_confirm_member(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<OrganizationConfirmRequest>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can confirm members".to_string(),
+        ));
+    }
+    if payload.key.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "The encrypted organization key is required".to_string(),
+        ));
+    }
+
+    let member = find_member(&db, &org.id, &member_id).await?;
+    let Some(member_user_id) = member
+        .user_id
+        .clone()
+        .filter(|_| member.status == MembershipStatus::Accepted as i32)
+    else {
+        return Err(AppError::BadRequest(
+            "Only members who accepted their invitation can be confirmed".to_string(),
+        ));
+    };
+    if member.atype == MembershipType::Owner as i32 && !membership.is_owner() {
+        return Err(AppError::Forbidden(
+            "Only owners can confirm other owners".to_string(),
+        ));
+    }
+
+    let now = time::now_bw();
+    Database::batch(
+        &db,
+        vec![
+        Database::prepare(
+            &db,
+            "UPDATE organization_users SET akey = ?1, status = ?2, updated_at = ?3 WHERE id = ?4",
+            &[
+                payload.key.as_str().into(),
+                (MembershipStatus::Confirmed as i32).into(),
+                now.as_str().into(),
+                member.id.as_str().into(),
+            ],
+        )
+        .map_err(db_error!())?,
+        Database::prepare(
+            &db,
+            "UPDATE users SET updated_at = ?1 WHERE id = ?2",
+            &[now.as_str().into(), member_user_id.as_str().into()],
+        )
+        .map_err(db_error!())?,
+    ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    notify::notify_user(&env, &db, UpdateType::SyncOrgKeys, &member_user_id, None).await;
+    events::log_event(
+        &db,
+        &source,
+        member_event(EventType::OrganizationUserConfirmed, &claims.sub, &member),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+/// Whether removing or demoting `member` would leave the organization without a confirmed owner.
+async fn is_last_owner(db: &Db, member: &Membership) -> Result<bool, AppError> {
+    if !member.is_owner() || member.status != MembershipStatus::Confirmed as i32 {
+        return Ok(false);
+    }
+    let others: Option<i64> = db
+        .first_column(
+            "SELECT COUNT(*) AS count FROM organization_users
+         WHERE organization_id = ?1 AND id != ?2 AND atype = ?3 AND status = ?4",
+            &[
+                member.organization_id.as_str().into(),
+                member.id.as_str().into(),
+                (MembershipType::Owner as i32).into(),
+                (MembershipStatus::Confirmed as i32).into(),
+            ],
+            "count",
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(others.unwrap_or(0) == 0)
+}
+
+/// Deletes a membership with its collection assignments and resyncs the former member.
+async fn remove_member(env: &Env, db: &Db, member: &Membership) -> Result<(), AppError> {
+    let now = time::now_bw();
+    let mut statements = vec![
+        Database::prepare(
+            db,
+            "DELETE FROM collections_users WHERE membership_id = ?1",
+            &[member.id.as_str().into()],
+        )
+        .map_err(db_error!())?,
+        Database::prepare(
+            db,
+            "DELETE FROM groups_users WHERE membership_id = ?1",
+            &[member.id.as_str().into()],
+        )
+        .map_err(db_error!())?,
+        Database::prepare(
+            db,
+            "DELETE FROM organization_users WHERE id = ?1",
+            &[member.id.as_str().into()],
+        )
+        .map_err(db_error!())?,
+    ];
+    if let Some(user_id) = &member.user_id {
+        statements.push(
+            Database::prepare(
+                db,
+                "UPDATE users SET updated_at = ?1 WHERE id = ?2",
+                &[now.as_str().into(), user_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    Database::batch(db, statements).await.map_err(db_error!())?;
+
+    if let Some(user_id) = member_user(member) {
+        notify::notify_vault_sync(env, db, user_id, None).await;
+    }
+    Ok(())
+}
+
+/// GET /api/organizations/{id}/users/{member_id}
+///
+/// A single member with their collection access, for the edit dialog.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/users/{member_id}",
+    tag = "organizations",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_member(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_manager() {
+        return Err(AppError::Forbidden(
+            "You don't have permission to view the organization's members".to_string(),
+        ));
+    }
+    let member = find_member(&db, &org.id, &member_id).await?;
+
+    let user: Option<MemberUserDetails> = match member_user(&member) {
+        Some(user_id) => db
+            .first(
+                "SELECT u.id, u.name,
+                    EXISTS (
+                        SELECT 1 FROM twofactor t
+                        WHERE t.user_uuid = u.id AND t.enabled = 1 AND t.atype = 0 -- Authenticator
+                    ) AS two_factor_enabled
+             FROM users u
+             WHERE u.id = ?1",
+                &[user_id.as_str().into()],
+            )
+            .await
+            .map_err(db_error!())?,
+        None => None,
+    };
+    let collections = collections::list_member_assignments(&db, &org.id)
+        .await?
+        .remove(&member.id)
+        .unwrap_or_default();
+    let member_groups = groups::list_member_groups(&db, &org.id)
+        .await?
+        .remove(&member.id)
+        .unwrap_or_default();
+
+    Ok(Json(
+        member.to_user_details_json(
+            user.as_ref().and_then(|user| user.name.as_deref()),
+            user.as_ref()
+                .is_some_and(|user| user.two_factor_enabled != 0),
+            collections,
+            member_groups,
+        ),
+    ))
+}
+
+/// PUT /api/organizations/{id}/users/{member_id}
+///
+/// Owners and admins change a member's role and collection access. Only owners can edit owners
+/// or make someone an owner, and the last owner can't be demoted.
+#[utoipa::path(
+    method(put, post),
+    path = "/api/organizations/{id}/users/{member_id}",
+    tag = "organizations",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    request_body = OrganizationMemberUpdateRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_member(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<OrganizationMemberUpdateRequest>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can edit members".to_string(),
+        ));
+    }
+    let role = MembershipType::from_i32(payload.atype)
+        .ok_or_else(|| AppError::validation("Type", "Invalid member type"))?;
+
+    let member = find_member(&db, &org.id, &member_id).await?;
+    if (member.is_owner() || role == MembershipType::Owner) && !membership.is_owner() {
+        return Err(AppError::Forbidden(
+            "Only owners can manage other owners".to_string(),
+        ));
+    }
+    if role != MembershipType::Owner && is_last_owner(&db, &member).await? {
+        return Err(AppError::BadRequest(
+            "The organization must keep at least one confirmed owner".to_string(),
+        ));
+    }
+
+    let now = time::now_bw();
+    let access_all = payload.access_all;
+    let collections_access = if access_all {
+        Vec::new()
+    } else {
+        payload.collections
+    };
+    let mut statements = vec![Database::prepare(
+        &db,
+        "UPDATE organization_users SET atype = ?1, access_all = ?2, updated_at = ?3 WHERE id = ?4",
+        &[
+            (role as i32).into(),
+            (access_all as i32).into(),
+            now.as_str().into(),
+            member.id.as_str().into(),
+        ],
+    )
+    .map_err(db_error!())?];
+    statements.extend(
+        collections::member_assignment_statements(&db, &org.id, &member.id, &collections_access)
+            .await?,
+    );
+    if let Some(group_ids) = payload.groups.filter(|_| org.use_groups) {
+        statements
+            .extend(groups::member_group_statements(&db, &org.id, &member.id, group_ids).await?);
+    }
+    if let Some(user_id) = &member.user_id {
+        statements.push(
+            Database::prepare(
+                &db,
+                "UPDATE users SET updated_at = ?1 WHERE id = ?2",
+                &[now.as_str().into(), user_id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+        );
+    }
+    Database::batch(&db, statements)
+        .await
+        .map_err(db_error!())?;
+
+    if let Some(user_id) = member_user(&member) {
+        notify::notify_user(&env, &db, UpdateType::SyncOrgKeys, user_id, None).await;
+    }
+    events::log_event(
+        &db,
+        &source,
+        member_event(EventType::OrganizationUserUpdated, &claims.sub, &member),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+/// DELETE /api/organizations/{id}/users/{member_id}
+///
+/// Removes a member, or revokes a pending invitation. The member loses access to the
+/// organization's items right away; their personal vault is untouched.
+#[utoipa::path(
+    delete,
+    path = "/api/organizations/{id}/users/{member_id}",
+    tag = "organizations",
+    params(("id" = String, Path), ("member_id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn delete_member(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, member_id)): AppPath<(String, String)>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can remove members".to_string(),
+        ));
+    }
+
+    let member = find_member(&db, &org.id, &member_id).await?;
+    if member.is_owner() && !membership.is_owner() {
+        return Err(AppError::Forbidden(
+            "Only owners can manage other owners".to_string(),
+        ));
+    }
+    if is_last_owner(&db, &member).await? {
+        return Err(AppError::BadRequest(
+            "The organization must keep at least one confirmed owner".to_string(),
+        ));
+    }
+
+    remove_member(&env, &db, &member).await?;
+    events::log_event(
+        &db,
+        &source,
+        member_event(EventType::OrganizationUserRemoved, &claims.sub, &member),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+/// POST /api/organizations/{id}/leave
+///
+/// A member leaves the organization. Its items stay with the organization; the sole owner has
+/// to hand over ownership or delete the organization instead.
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{id}/leave",
+    tag = "organizations",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_leave(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let member: Membership = db
+        .first(
+            "SELECT * FROM organization_users WHERE organization_id = ?1 AND user_id = ?2",
+            &[org_id.as_str().into(), claims.sub.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(organization_not_found)?;
+    if is_last_owner(&db, &member).await? {
+        return Err(AppError::BadRequest(
+            "You are the only owner of this organization. Make another member an owner or delete the organization instead".to_string(),
+        ));
+    }
+
+    remove_member(&env, &db, &member).await?;
+    events::log_event(
+        &db,
+        &source,
+        member_event(EventType::OrganizationUserLeft, &claims.sub, &member),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::{self, block_on, testing};
+    use axum::http::{Method, StatusCode};
+
+    /// An organization owned by alice, where bob is a confirmed member assigned to the collection
+    /// holding `org-cipher`. Returns the env and access tokens for alice and bob.
+    fn org() -> (native::Env, String, String) {
+        let env = testing::env();
+        let alice = testing::user(&env, "alice");
+        let bob = testing::user(&env, "bob");
+        testing::organization(
+            &env,
+            "org",
+            &[
+                ("alice", MembershipType::Owner),
+                ("bob", MembershipType::User),
+            ],
+        );
+        let db = env.d1("vault1").unwrap();
+        let now = time::now_bw();
+        let statements = [
+            "INSERT INTO collections (id, organization_id, name, created_at, updated_at)
+             VALUES ('collection', 'org', '2.Y29s|aXY=|bWFj', ?1, ?1)",
+            "INSERT INTO ciphers (id, user_id, organization_id, type, data, created_at, updated_at)
+             VALUES ('org-cipher', NULL, 'org', 1, '{\"name\":\"2.bmFtZQ==|aXY=|bWFj\"}', ?1, ?1)",
+        ];
+        for sql in statements {
+            block_on(db.run(sql, &[now.as_str().into()])).unwrap();
+        }
+        for sql in [
+            "INSERT INTO ciphers_collections (cipher_id, collection_id) VALUES ('org-cipher', 'collection')",
+            "INSERT INTO collections_users (collection_id, membership_id) VALUES ('collection', 'bob-membership')",
+        ] {
+            block_on(db.run(sql, &[])).unwrap();
+        }
+        (env, alice, bob)
+    }
+
+    fn request(env: &native::Env, method: Method, path: &str, token: &str) -> (StatusCode, Value) {
+        testing::request(env, method, path, Some(token), None)
+    }
+
+    /// Organization ids and cipher ids in `token`'s user's sync.
+    fn synced(env: &native::Env, token: &str) -> (Vec<String>, Vec<String>) {
+        let (status, sync) = request(env, Method::GET, "/api/sync", token);
+        assert_eq!(status, StatusCode::OK, "{sync}");
+        let ids = |list: &Value| {
+            list.as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        (
+            ids(&sync["profile"]["organizations"]),
+            ids(&sync["ciphers"]),
+        )
+    }
+
+    fn count(env: &native::Env, sql: &str) -> i64 {
+        let db = env.d1("vault1").unwrap();
+        block_on(db.first_column::<i64>(sql, &[], "n"))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn the_sole_owner_cant_leave() {
+        let (env, alice, _) = org();
+
+        let (status, body) = request(&env, Method::POST, "/api/organizations/org/leave", &alice);
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.to_string().contains("only owner"), "{body}");
+        assert_eq!(
+            synced(&env, &alice),
+            (vec!["org".to_string()], vec!["org-cipher".to_string()])
+        );
+    }
+
+    #[test]
+    fn a_member_leaves_and_the_items_stay() {
+        let (env, alice, bob) = org();
+        assert_eq!(
+            synced(&env, &bob),
+            (vec!["org".to_string()], vec!["org-cipher".to_string()])
+        );
+
+        let (status, body) = request(&env, Method::POST, "/api/organizations/org/leave", &bob);
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        assert_eq!(synced(&env, &bob), (vec![], vec![]));
+        assert_eq!(
+            count(
+                &env,
+                "SELECT COUNT(*) AS n FROM collections_users WHERE membership_id = 'bob-membership'"
+            ),
+            0
+        );
+        assert_eq!(
+            synced(&env, &alice),
+            (vec!["org".to_string()], vec!["org-cipher".to_string()])
+        );
+    }
+
+    #[test]
+    fn leaving_an_organization_you_arent_in_is_not_found() {
+        let (env, _, bob) = org();
+        request(&env, Method::POST, "/api/organizations/org/leave", &bob);
+
+        let (status, _) = request(&env, Method::POST, "/api/organizations/org/leave", &bob);
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,351 @@
+//! Detection and repair of orphaned data
+//!
+//! Foreign keys keep most of the schema consistent, but databases created before they were
+//! enforced, interrupted batches and bugs can still leave rows and objects pointing at nothing.
+//! [`run_orphan_cleanup`] repairs each class it knows about. Every pass is bounded (a fixed
+//! number of statements, object checks or list pages per run) so a large backlog is worked off
+//! over several runs instead of blowing the CPU or subrequest limit in one.
+
+use crate::db::{Database, Db};
+use crate::handlers::attachments::{self, StorageBackend};
+use crate::handlers::purge::{run_task, touch_users, PURGE_BATCH_SIZE};
+use crate::models::send::Send;
+use crate::time;
+use crate::Env;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use worker::Bucket;
+
+/// Statements run per database repair in one pass, of [`PURGE_BATCH_SIZE`] rows each
+const MAX_BATCHES: u32 = 10;
+/// Attachment rows whose object is looked up (one R2 request each) in one pass
+const MAX_OBJECT_CHECKS: usize = 200;
+/// Bucket list pages (up to 1000 objects each) inspected in one pass
+const MAX_LIST_PAGES: u32 = 5;
+/// Objects (and the attachment rows describing them) younger than this are left alone, as an
+/// upload may still be completing
+const MIN_ORPHAN_AGE_DAYS: i64 = 1;
+/// Key prefixes the bucket listing is split into: attachment keys start with the cipher id (a
+/// UUID), send files live under `sends/`. One is picked at random per pass.
+const LIST_SHARDS: [&str; 17] = [
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "a", "b", "c", "d", "e", "f", "sends/",
+];
+
+/// Orphans repaired by one [`run_orphan_cleanup`] pass, per category.
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanSummary {
+    /// Ciphers whose folder no longer exists, moved out of it
+    pub dangling_folder_ids: u32,
+    /// Cipher/collection links to a deleted cipher or collection
+    pub collection_links: u32,
+    /// Devices of deleted users
+    pub devices: u32,
+    /// Attachment rows whose stored object is missing
+    pub attachments_without_object: u32,
+    /// Stored objects no attachment or send refers to
+    pub unreferenced_objects: u32,
+}
+
+/// Runs every orphan repair. Part of the scheduled maintenance, and available on its own as
+/// `POST /admin/orphans`.
+pub async fn run_orphan_cleanup(env: &Env) -> OrphanSummary {
+    let summary = OrphanSummary {
+        dangling_folder_ids: run_task("dangling folder ids", repair_dangling_folder_ids(env)).await,
+        collection_links: run_task(
+            "orphaned collection links",
+            purge_orphaned_collection_links(env),
+        )
+        .await,
+        devices: run_task("devices of deleted users", purge_orphaned_devices(env)).await,
+        attachments_without_object: run_task(
+            "attachments without an object",
+            purge_attachments_without_object(env),
+        )
+        .await,
+        unreferenced_objects: run_task("unreferenced objects", purge_unreferenced_objects(env))
+            .await,
+    };
+
+    log::info!("Orphan cleanup completed: {:?}", summary);
+    summary
+}
+
+/// Clears folder_id on ciphers whose folder was deleted, and bumps their revision so clients
+/// pick up the move.
+pub async fn repair_dangling_folder_ids(env: &Env) -> Result<u32, worker::Error> {
+    let db: Db = env.d1("vault1")?;
+    let now_str = time::now_bw();
+
+    let mut count = 0;
+    let mut affected_user_ids: HashSet<String> = HashSet::new();
+    for _ in 0..MAX_BATCHES {
+        let batch: Vec<OrphanRow> = db
+            .all(
+                "SELECT c.id, c.user_id FROM ciphers c
+             WHERE c.folder_id IS NOT NULL
+             AND NOT EXISTS (SELECT 1 FROM folders f WHERE f.id = c.folder_id)
+             LIMIT ?1",
+                &[PURGE_BATCH_SIZE.into()],
+            )
+            .await?;
+        if batch.is_empty() {
+            break;
+        }
+
+        let ids: Vec<&str> = batch.iter().map(|row| row.id.as_str()).collect();
+        let result = db
+            .run(
+                "UPDATE ciphers SET folder_id = NULL, updated_at = ?1
+             WHERE id IN (SELECT value FROM json_each(?2))
+             AND NOT EXISTS (SELECT 1 FROM folders f WHERE f.id = ciphers.folder_id)",
+                &[now_str.as_str().into(), serde_json::to_string(&ids)?.into()],
+            )
+            .await?;
+        count += result as u32;
+        let done = (batch.len() as u32) < PURGE_BATCH_SIZE;
+        affected_user_ids.extend(batch.into_iter().filter_map(|row| row.user_id));
+        if done {
+            break;
+        }
+    }
+
+    if count > 0 {
+        touch_users(&db, &affected_user_ids, &now_str).await?;
+    }
+    Ok(count)
+}
+
+/// Deletes ciphers_collections rows whose cipher or collection no longer exists.
+pub async fn purge_orphaned_collection_links(env: &Env) -> Result<u32, worker::Error> {
+    let db: Db = env.d1("vault1")?;
+    delete_in_batches(
+        &db,
+        "DELETE FROM ciphers_collections WHERE rowid IN (
+             SELECT cc.rowid FROM ciphers_collections cc
+             WHERE NOT EXISTS (SELECT 1 FROM ciphers c WHERE c.id = cc.cipher_id)
+             OR NOT EXISTS (SELECT 1 FROM collections col WHERE col.id = cc.collection_id)
+             LIMIT ?1
+         )",
+    )
+    .await
+}
+
+/// Deletes devices whose user no longer exists.
+pub async fn purge_orphaned_devices(env: &Env) -> Result<u32, worker::Error> {
+    let db: Db = env.d1("vault1")?;
+    delete_in_batches(
+        &db,
+        "DELETE FROM devices WHERE id IN (
+             SELECT d.id FROM devices d
+             WHERE NOT EXISTS (SELECT 1 FROM users u WHERE u.id = d.user_id)
+             LIMIT ?1
+         )",
+    )
+    .await
+}
+
+/// Runs a `DELETE` taking the batch size as `?1` until it removes nothing or [`MAX_BATCHES`]
+/// statements have run.
+async fn delete_in_batches(db: &Db, sql: &str) -> Result<u32, worker::Error> {
+    let mut count = 0;
+    for _ in 0..MAX_BATCHES {
+        let result = db.run(sql, &[PURGE_BATCH_SIZE.into()]).await?;
+        let changes = result as u32;
+        count += changes;
+        if changes < PURGE_BATCH_SIZE {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+/// Deletes attachment rows older than [`MIN_ORPHAN_AGE_DAYS`] whose object is missing from R2.
+///
+/// Checks at most [`MAX_OBJECT_CHECKS`] rows, in id order from a random starting point and
+/// wrapping around, so repeated runs cover the whole table without remembering where the last
+/// one stopped. Skipped for the KV backend.
+pub async fn purge_attachments_without_object(env: &Env) -> Result<u32, worker::Error> {
+    let Some(bucket) = r2_bucket(env) else {
+        return Ok(0);
+    };
+    let db: Db = env.d1("vault1")?;
+    let now_str = time::now_bw();
+    let cutoff_str = time::format_bw(Utc::now() - Duration::days(MIN_ORPHAN_AGE_DAYS));
+
+    let start = Uuid::new_v4().to_string();
+    let mut after = start.clone();
+    let mut wrapped = false;
+    let mut checked = 0;
+    let mut missing: Vec<AttachmentKeyRow> = Vec::new();
+    while checked < MAX_OBJECT_CHECKS {
+        let limit = PURGE_BATCH_SIZE.min((MAX_OBJECT_CHECKS - checked) as u32);
+        let batch: Vec<AttachmentKeyRow> = db
+            .all(
+                "SELECT a.id, a.cipher_id, c.user_id FROM attachments a
+             LEFT JOIN ciphers c ON c.id = a.cipher_id
+             WHERE a.id > ?1 AND a.created_at < ?2
+             ORDER BY a.id LIMIT ?3",
+                &[after.into(), cutoff_str.as_str().into(), limit.into()],
+            )
+            .await?;
+
+        for row in &batch {
+            if wrapped && row.id > start {
+                break;
+            }
+            checked += 1;
+            if bucket.head(row.key()).await?.is_none() {
+                missing.push(row.clone());
+            }
+        }
+        match batch.last() {
+            Some(last) if !(wrapped && last.id > start) && batch.len() as u32 == limit => {
+                after = last.id.clone();
+            }
+            _ if !wrapped => {
+                // Reached the end of the table: continue from the start up to `start`
+                wrapped = true;
+                after = String::new();
+            }
+            _ => break,
+        }
+    }
+
+    if missing.is_empty() {
+        return Ok(0);
+    }
+
+    let ids: Vec<&str> = missing.iter().map(|row| row.id.as_str()).collect();
+    let result = db
+        .run(
+            "DELETE FROM attachments WHERE id IN (SELECT value FROM json_each(?1))",
+            &[serde_json::to_string(&ids)?.into()],
+        )
+        .await?;
+    let count = result as u32;
+
+    let affected_user_ids: HashSet<String> =
+        missing.into_iter().filter_map(|row| row.user_id).collect();
+    touch_users(&db, &affected_user_ids, &now_str).await?;
+    Ok(count)
+}
+
+/// Deletes R2 objects older than [`MIN_ORPHAN_AGE_DAYS`] that no attachment, pending
+/// attachment or send refers to.
+///
+/// Lists at most [`MAX_LIST_PAGES`] pages of one of the [`LIST_SHARDS`], picked at random, so
+/// repeated runs cover the whole bucket. Skipped for the KV backend, which doesn't record when a
+/// value was written.
+pub async fn purge_unreferenced_objects(env: &Env) -> Result<u32, worker::Error> {
+    let Some(bucket) = r2_bucket(env) else {
+        return Ok(0);
+    };
+    let db: Db = env.d1("vault1")?;
+    let cutoff_ms = (Utc::now() - Duration::days(MIN_ORPHAN_AGE_DAYS)).timestamp_millis() as u64;
+    let shard = LIST_SHARDS
+        [(js_sys::Math::random() * LIST_SHARDS.len() as f64) as usize % LIST_SHARDS.len()];
+
+    let mut count = 0;
+    let mut cursor: Option<String> = None;
+    for _ in 0..MAX_LIST_PAGES {
+        let mut list = bucket.list().prefix(shard);
+        if let Some(cursor) = cursor.take() {
+            list = list.cursor(cursor);
+        }
+        let page = list.execute().await?;
+
+        let old_keys: Vec<String> = page
+            .objects()
+            .iter()
+            .filter(|object| object.uploaded().as_millis() < cutoff_ms)
+            .map(|object| object.key())
+            .collect();
+        let referenced = referenced_keys(&db, &old_keys).await?;
+        let orphans: Vec<String> = old_keys
+            .into_iter()
+            .filter(|key| !referenced.contains(key))
+            .collect();
+        if !orphans.is_empty() {
+            attachments::delete_r2_objects(&bucket, &orphans)
+                .await
+                .map_err(|e| worker::Error::RustError(e.to_string()))?;
+            count += orphans.len() as u32;
+        }
+
+        match page.cursor() {
+            Some(next) if page.truncated() => cursor = Some(next),
+            _ => break,
+        }
+    }
+
+    Ok(count)
+}
+
+/// Which of `keys` belong to an attachment, a pending attachment or a send file.
+async fn referenced_keys(db: &Db, keys: &[String]) -> Result<HashSet<String>, worker::Error> {
+    let mut attachment_ids = Vec::new();
+    let mut send_ids = Vec::new();
+    for key in keys {
+        let mut parts = key.split('/');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("sends"), Some(send_id), Some(_)) => send_ids.push(send_id),
+            (Some(_), Some(attachment_id), None) => attachment_ids.push(attachment_id),
+            _ => {}
+        }
+    }
+
+    let mut referenced = HashSet::new();
+    if !attachment_ids.is_empty() {
+        let rows: Vec<AttachmentKeyRow> = db
+            .all(
+                "SELECT id, cipher_id, NULL AS user_id FROM attachments
+             WHERE id IN (SELECT value FROM json_each(?1))
+             UNION ALL
+             SELECT id, cipher_id, NULL AS user_id FROM attachments_pending
+             WHERE id IN (SELECT value FROM json_each(?1))",
+                &[serde_json::to_string(&attachment_ids)?.into()],
+            )
+            .await?;
+        referenced.extend(rows.iter().map(AttachmentKeyRow::key));
+    }
+    if !send_ids.is_empty() {
+        let sends: Vec<Send> = db
+            .all(
+                "SELECT * FROM sends WHERE id IN (SELECT value FROM json_each(?1))",
+                &[serde_json::to_string(&send_ids)?.into()],
+            )
+            .await?;
+        referenced.extend(sends.iter().filter_map(Send::storage_key));
+    }
+    Ok(referenced)
+}
+
+fn r2_bucket(env: &Env) -> Option<Bucket> {
+    match attachments::get_storage_backend(env) {
+        Some(StorageBackend::R2) => env.bucket(attachments::ATTACHMENTS_BUCKET).ok(),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+struct OrphanRow {
+    id: String,
+    user_id: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+struct AttachmentKeyRow {
+    id: String,
+    cipher_id: String,
+    user_id: Option<String>,
+}
+
+impl AttachmentKeyRow {
+    fn key(&self) -> String {
+        format!("{}/{}", self.cipher_id, self.id)
+    }
+}
@@ -0,0 +1,244 @@
+//! Organization policies: rules owners and admins set for the organization's members.
+
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
+use crate::{
+    auth::Claims,
+    db::{self, Database, Db},
+    error::{db_error, internal_error, AppError},
+    handlers::{
+        events::{self, EventSource},
+        organizations::{find_organization_for_member, touch_members_statement},
+    },
+    models::{
+        event::{Event, EventType},
+        organization::MembershipStatus,
+        policy::{
+            MasterPasswordPolicyData, OrganizationPolicy, PolicyType, PolicyUpdateRequest,
+            ResetPasswordPolicyData,
+        },
+    },
+    time,
+};
+
+fn parse_policy_type(value: i32) -> Result<PolicyType, AppError> {
+    PolicyType::from_i32(value).ok_or_else(|| AppError::NotFound("Policy not found".to_string()))
+}
+
+/// Enabled policies of every organization the user has joined, as returned by `/api/sync`.
+pub(crate) async fn list_user_policies(
+    db: &Db,
+    user_id: &str,
+) -> Result<Vec<OrganizationPolicy>, AppError> {
+    db.all(
+        "SELECT p.* FROM organization_policies p
+         JOIN organization_users ou ON ou.organization_id = p.organization_id
+         WHERE ou.user_id = ?1 AND ou.status IN (?2, ?3) AND p.enabled = 1",
+        &[
+            user_id.into(),
+            (MembershipStatus::Accepted as i32).into(),
+            (MembershipStatus::Confirmed as i32).into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())
+}
+
+/// The strictest master password requirements among the user's organizations, if any has the
+/// policy enabled.
+pub(crate) async fn master_password_policy(
+    db: &Db,
+    user_id: &str,
+) -> Result<Option<MasterPasswordPolicyData>, AppError> {
+    let policies = list_user_policies(db, user_id).await?;
+    Ok(policies
+        .iter()
+        .filter(|policy| policy.atype == PolicyType::MasterPassword as i32)
+        .map(|policy| serde_json::from_value(policy.data_json()).unwrap_or_default())
+        .reduce(MasterPasswordPolicyData::merge))
+}
+
+/// The organization's account recovery options, if it has the policy enabled.
+pub(crate) async fn reset_password_policy(
+    db: &Db,
+    org_id: &str,
+) -> Result<Option<ResetPasswordPolicyData>, AppError> {
+    let policy: Option<OrganizationPolicy> = db
+        .first(
+            "SELECT * FROM organization_policies WHERE organization_id = ?1 AND atype = ?2 AND enabled = 1",
+            &[org_id.into(), (PolicyType::ResetPassword as i32).into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(policy.map(|policy| serde_json::from_value(policy.data_json()).unwrap_or_default()))
+}
+
+/// GET /api/organizations/{id}/policies
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/policies",
+    tag = "policies",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_policies(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(org_id): AppPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can manage policies".to_string(),
+        ));
+    }
+
+    let policies: Vec<OrganizationPolicy> = db
+        .all(
+            "SELECT * FROM organization_policies WHERE organization_id = ?1 ORDER BY atype",
+            &[org.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    let data: Vec<Value> = policies.iter().map(OrganizationPolicy::to_json).collect();
+
+    Ok(Json(json!({
+        "data": data,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+/// GET /api/organizations/{id}/policies/{type}
+///
+/// Policies that were never configured are returned disabled.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/policies/{policy_type}",
+    tag = "policies",
+    params(("id" = String, Path), ("policy_type" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_policy(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, policy_type)): AppPath<(String, i32)>,
+) -> Result<Json<Value>, AppError> {
+    let policy_type = parse_policy_type(policy_type)?;
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can manage policies".to_string(),
+        ));
+    }
+
+    let policy: Option<OrganizationPolicy> = db
+        .first(
+            "SELECT * FROM organization_policies WHERE organization_id = ?1 AND atype = ?2",
+            &[org.id.as_str().into(), (policy_type as i32).into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    Ok(Json(match policy {
+        Some(policy) => policy.to_json(),
+        None => OrganizationPolicy::default_json(&org.id, policy_type),
+    }))
+}
+
+/// PUT /api/organizations/{id}/policies/{type}
+///
+/// Enables, disables or reconfigures a policy. Members pick it up on their next sync.
+#[utoipa::path(
+    put,
+    path = "/api/organizations/{id}/policies/{policy_type}",
+    tag = "policies",
+    params(("id" = String, Path), ("policy_type" = String, Path)),
+    request_body = PolicyUpdateRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_policy(
+    claims: Claims,
+    source: EventSource,
+    State(env): State<Arc<Env>>,
+    AppPath((org_id, policy_type)): AppPath<(String, i32)>,
+    AppJson(payload): AppJson<PolicyUpdateRequest>,
+) -> Result<Json<Value>, AppError> {
+    let policy_type = parse_policy_type(policy_type)?;
+    let db = db::get_db(&env)?;
+    let (org, membership) = find_organization_for_member(&db, &org_id, &claims.sub).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only owners and admins can manage policies".to_string(),
+        ));
+    }
+
+    let enabled = payload.enabled;
+    let data = match payload.data {
+        None | Some(Value::Null) => None,
+        Some(data @ Value::Object(_)) => {
+            Some(serde_json::to_string(&data).map_err(internal_error!())?)
+        }
+        Some(_) => {
+            return Err(AppError::BadRequest(
+                "Policy data must be an object".to_string(),
+            ))
+        }
+    };
+
+    let now = time::now_bw();
+    Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "INSERT INTO organization_policies (id, organization_id, atype, enabled, data, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+             ON CONFLICT(organization_id, atype) DO UPDATE SET enabled = ?4, data = ?5, updated_at = ?6",
+                &[
+                    Uuid::new_v4().to_string().into(),
+                    org.id.as_str().into(),
+                    (policy_type as i32).into(),
+                    (enabled as i32).into(),
+                    data.as_deref().into(),
+                    now.as_str().into(),
+                ],
+            )
+            .map_err(db_error!())?,
+            touch_members_statement(&db, &org.id, &now)?,
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    let policy: OrganizationPolicy = db
+        .first(
+            "SELECT * FROM organization_policies WHERE organization_id = ?1 AND atype = ?2",
+            &[org.id.as_str().into(), (policy_type as i32).into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(|| AppError::Database(Some("saved policy was not found".to_string())))?;
+
+    let event = Event {
+        organization_id: Some(org.id.clone()),
+        policy_id: Some(policy.id.clone()),
+        ..Event::new(EventType::PolicyUpdated, &claims.sub)
+    };
+    events::log_event(&db, &source, event).await;
+
+    Ok(Json(policy.to_json()))
+}
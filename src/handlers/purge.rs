@@ -2,50 +2,49 @@
 //!
 //! This module handles the automatic cleanup of ciphers that have been
 //! soft-deleted (marked with deleted_at) for longer than the configured
-//! retention period.
+//! retention period, as well as sends past their deletion date and other
+//! short-lived records.
 
-use crate::handlers::attachments;
+use crate::config::Settings;
+use crate::db::{Database, Db};
+use crate::handlers::orphans::{self, OrphanSummary};
+use crate::handlers::{attachments, emergency_access};
+use crate::models::auth_request::AUTH_REQUEST_TTL_MINUTES;
+use crate::models::send::Send;
+use crate::quota;
+use crate::time;
+use crate::Env;
 use chrono::{Duration, Utc};
+use serde::Serialize;
 use std::collections::HashSet;
-use worker::{query, D1Database, Env};
+use utoipa::ToSchema;
 
-/// Default number of days to keep soft-deleted items before purging
-const DEFAULT_PURGE_DAYS: i64 = 30;
 /// Retain pending attachments for at most this many days before cleanup
 const PENDING_RETENTION_DAYS: i64 = 1;
-
-/// Get the purge threshold days from environment variable or use default
-fn get_purge_days(env: &Env) -> i64 {
-    env.var("TRASH_AUTO_DELETE_DAYS")
-        .ok()
-        .and_then(|v| v.to_string().parse::<i64>().ok())
-        .unwrap_or(DEFAULT_PURGE_DAYS)
-}
+/// Rows deleted per statement by the batched purges, to stay well within D1's query limits
+pub(crate) const PURGE_BATCH_SIZE: u32 = 100;
 
 /// Purge pending attachments older than the configured retention window.
 pub async fn purge_stale_pending_attachments(env: &Env) -> Result<u32, worker::Error> {
-    let db: D1Database = env.d1("vault1")?;
+    let db: Db = env.d1("vault1")?;
     let now = Utc::now();
     let pending_cutoff = now - Duration::days(PENDING_RETENTION_DAYS);
-    let pending_cutoff_str = pending_cutoff.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let pending_cutoff_str = time::format_bw(pending_cutoff);
 
-    let pending_count_result = query!(
-        &db,
-        "SELECT COUNT(*) as count FROM attachments_pending WHERE created_at < ?1",
-        pending_cutoff_str
-    )?
-    .first::<CountResult>(None)
-    .await?;
+    let pending_count_result = db
+        .first::<CountResult>(
+            "SELECT COUNT(*) as count FROM attachments_pending WHERE created_at < ?1",
+            &[pending_cutoff_str.as_str().into()],
+        )
+        .await?;
 
     let pending_count = pending_count_result.map(|r| r.count).unwrap_or(0);
 
     if pending_count > 0 {
-        query!(
-            &db,
+        db.run(
             "DELETE FROM attachments_pending WHERE created_at < ?1",
-            pending_cutoff_str
-        )?
-        .run()
+            &[pending_cutoff_str.into()],
+        )
         .await?;
         log::info!(
             "Purged {} pending attachment(s) older than {} day(s)",
@@ -59,17 +58,73 @@ pub async fn purge_stale_pending_attachments(env: &Env) -> Result<u32, worker::E
     Ok(pending_count)
 }
 
+/// Purge login-with-device auth requests that expired more than a day ago.
+pub async fn purge_expired_auth_requests(env: &Env) -> Result<u32, worker::Error> {
+    let db: Db = env.d1("vault1")?;
+    let cutoff = Utc::now() - Duration::minutes(AUTH_REQUEST_TTL_MINUTES) - Duration::days(1);
+    let cutoff_str = time::format_bw(cutoff);
+
+    let result = db
+        .run(
+            "DELETE FROM auth_requests WHERE creation_date < ?1",
+            &[cutoff_str.as_str().into()],
+        )
+        .await?;
+
+    Ok(result as u32)
+}
+
+/// Purge chunked import sessions past their expiry, with whatever they had staged.
+pub async fn purge_expired_import_sessions(env: &Env) -> Result<u32, worker::Error> {
+    let db: Db = env.d1("vault1")?;
+    let now = time::now_bw();
+
+    let result = db
+        .run(
+            "DELETE FROM import_sessions WHERE expires_at < ?1",
+            &[now.into()],
+        )
+        .await?;
+
+    Ok(result as u32)
+}
+
+/// Purge organization and account events older than EVENTS_RETENTION_DAYS (default: 90 days).
+///
+/// Set to 0 or negative to keep events forever.
+pub async fn purge_old_events(env: &Env) -> Result<u32, worker::Error> {
+    let retention_days = Settings::get(env).events_retention_days;
+    if retention_days <= 0 {
+        log::info!("Event purge is disabled (EVENTS_RETENTION_DAYS <= 0)");
+        return Ok(0);
+    }
+
+    let db: Db = env.d1("vault1")?;
+    let cutoff = Utc::now() - Duration::days(retention_days);
+    let cutoff_str = time::format_bw(cutoff);
+
+    let result = db
+        .run(
+            "DELETE FROM events WHERE date < ?1",
+            &[cutoff_str.as_str().into()],
+        )
+        .await?;
+
+    Ok(result as u32)
+}
+
 /// Purge soft-deleted ciphers that are older than the configured threshold.
 ///
 /// This function:
 /// 1. Calculates the cutoff timestamp based on TRASH_AUTO_DELETE_DAYS env var (default: 30 days)
-/// 2. Deletes all ciphers where deleted_at is not null and older than the cutoff
+/// 2. Deletes ciphers where deleted_at is not null and older than the cutoff, together with their
+///    attachment objects, in batches of [`PURGE_BATCH_SIZE`]
 /// 3. Updates the affected users' updated_at to trigger client sync
 /// 4. If TRASH_AUTO_DELETE_DAYS is set to 0 or negative, skips purging (disabled)
 ///
 /// Returns the number of purged records on success.
 pub async fn purge_deleted_ciphers(env: &Env) -> Result<u32, worker::Error> {
-    let purge_days = get_purge_days(env);
+    let purge_days = Settings::get(env).trash_auto_delete_days;
 
     // If purge_days is 0 or negative, auto-purge is disabled
     if purge_days <= 0 {
@@ -77,13 +132,13 @@ pub async fn purge_deleted_ciphers(env: &Env) -> Result<u32, worker::Error> {
         return Ok(0);
     }
 
-    let db: D1Database = env.d1("vault1")?;
+    let db: Db = env.d1("vault1")?;
 
     // Calculate the cutoff timestamp
     let now = Utc::now();
     let cutoff = now - Duration::days(purge_days);
-    let cutoff_str = cutoff.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-    let now_str = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let cutoff_str = time::format_bw(cutoff);
+    let now_str = time::format_bw(now);
 
     log::info!(
         "Purging soft-deleted ciphers older than {} days (before {})",
@@ -91,80 +146,239 @@ pub async fn purge_deleted_ciphers(env: &Env) -> Result<u32, worker::Error> {
         cutoff_str
     );
 
-    // First, get the list of affected user IDs before deletion
-    let affected_users_result: Vec<AffectedUser> = query!(
-        &db,
-        "SELECT DISTINCT user_id FROM ciphers WHERE deleted_at IS NOT NULL AND deleted_at < ?1 AND user_id IS NOT NULL",
-        cutoff_str
-    )?
-    .all()
-    .await?
-    .results()?;
-
-    let affected_user_ids: HashSet<String> = affected_users_result
-        .into_iter()
-        .filter_map(|u| u.user_id)
-        .collect();
-
-    // Count the records to be deleted (for logging purposes)
-    let count_result = query!(
-        &db,
-        "SELECT COUNT(*) as count FROM ciphers WHERE deleted_at IS NOT NULL AND deleted_at < ?1",
-        cutoff_str
-    )?
-    .first::<CountResult>(None)
-    .await?;
+    let mut count = 0;
+    let mut affected_user_ids: HashSet<String> = HashSet::new();
+    loop {
+        let batch: Vec<PurgeRow> = db
+            .all(
+                "SELECT id, user_id FROM ciphers WHERE deleted_at IS NOT NULL AND deleted_at < ?1 LIMIT ?2",
+                &[cutoff_str.as_str().into(), PURGE_BATCH_SIZE.into()],
+            )
+            .await?;
+        if batch.is_empty() {
+            break;
+        }
 
-    let count = count_result.map(|r| r.count).unwrap_or(0);
+        let ids: Vec<&str> = batch.iter().map(|row| row.id.as_str()).collect();
+        let ids_json = serde_json::to_string(&ids)?;
 
-    if count > 0 {
         if attachments::attachments_enabled(env) {
-            let keys = attachments::list_attachment_keys_for_soft_deleted_before(&db, &cutoff_str)
-                .await
-                .map_err(|e| worker::Error::RustError(e.to_string()))?;
+            let keys =
+                attachments::list_attachment_keys_for_cipher_ids_json(&db, &ids_json, "$", None)
+                    .await
+                    .map_err(|e| worker::Error::RustError(e.to_string()))?;
 
             attachments::delete_storage_objects(env, &keys)
                 .await
                 .map_err(|e| worker::Error::RustError(e.to_string()))?;
         }
 
-        // Delete the records
-        query!(
+        // Re-check the cutoff so a cipher restored since the SELECT is left alone
+        let owners: Vec<&str> = batch
+            .iter()
+            .filter_map(|row| row.user_id.as_deref())
+            .collect();
+        let results = Database::batch(
             &db,
-            "DELETE FROM ciphers WHERE deleted_at IS NOT NULL AND deleted_at < ?1",
-            cutoff_str
-        )?
-        .run()
+            vec![
+                Database::prepare(
+                    &db,
+                    "DELETE FROM ciphers WHERE id IN (SELECT value FROM json_each(?1))
+                     AND deleted_at IS NOT NULL AND deleted_at < ?2",
+                    &[ids_json.into(), cutoff_str.as_str().into()],
+                )?,
+                quota::recount(&db, &owners)
+                    .map_err(|e| worker::Error::RustError(e.to_string()))?,
+            ],
+        )
         .await?;
+        count += deleted(&results);
+        affected_user_ids.extend(batch.iter().filter_map(|row| row.user_id.clone()));
 
+        if (batch.len() as u32) < PURGE_BATCH_SIZE {
+            break;
+        }
+    }
+
+    if count > 0 {
         log::info!("Successfully purged {} soft-deleted cipher(s)", count);
+        touch_users(&db, &affected_user_ids, &now_str).await?;
+    } else {
+        log::info!("No soft-deleted ciphers to purge");
+    }
 
-        // Update the affected users' updated_at to trigger client sync
-        for user_id in &affected_user_ids {
-            query!(
-                &db,
-                "UPDATE users SET updated_at = ?1 WHERE id = ?2",
-                now_str,
-                user_id
-            )?
-            .run()
+    Ok(count)
+}
+
+/// Purge sends past their deletion date, including the stored files of file sends.
+///
+/// Returns the number of purged sends on success.
+pub async fn purge_expired_sends(env: &Env) -> Result<u32, worker::Error> {
+    let db: Db = env.d1("vault1")?;
+    let now_str = time::now_bw();
+
+    let mut count = 0;
+    let mut affected_user_ids: HashSet<String> = HashSet::new();
+    loop {
+        let batch: Vec<Send> = db
+            .all(
+                "SELECT * FROM sends WHERE deletion_date <= ?1 LIMIT ?2",
+                &[now_str.as_str().into(), PURGE_BATCH_SIZE.into()],
+            )
             .await?;
+        if batch.is_empty() {
+            break;
         }
 
-        log::info!(
-            "Updated revision date for {} affected user(s)",
-            affected_user_ids.len()
-        );
+        let keys: Vec<String> = batch.iter().filter_map(Send::storage_key).collect();
+        if !keys.is_empty() && attachments::attachments_enabled(env) {
+            attachments::delete_storage_objects(env, &keys)
+                .await
+                .map_err(|e| worker::Error::RustError(e.to_string()))?;
+        }
+
+        let ids: Vec<&str> = batch.iter().map(|send| send.id.as_str()).collect();
+        let owners: Vec<&str> = batch.iter().map(|send| send.user_id.as_str()).collect();
+        let results = Database::batch(
+            &db,
+            vec![
+                Database::prepare(
+                    &db,
+                    "DELETE FROM sends WHERE id IN (SELECT value FROM json_each(?1))",
+                    &[serde_json::to_string(&ids)?.into()],
+                )?,
+                quota::recount(&db, &owners)
+                    .map_err(|e| worker::Error::RustError(e.to_string()))?,
+            ],
+        )
+        .await?;
+        count += deleted(&results);
+        affected_user_ids.extend(batch.iter().map(|send| send.user_id.clone()));
+
+        if (batch.len() as u32) < PURGE_BATCH_SIZE {
+            break;
+        }
+    }
+
+    if count > 0 {
+        log::info!("Successfully purged {} expired send(s)", count);
+        touch_users(&db, &affected_user_ids, &now_str).await?;
     } else {
-        log::info!("No soft-deleted ciphers to purge");
+        log::info!("No expired sends to purge");
     }
 
     Ok(count)
 }
 
-/// Helper struct for affected user query result
+/// Rows deleted by the first statement of a batch.
+fn deleted(results: &[usize]) -> u32 {
+    results.first().copied().unwrap_or(0) as u32
+}
+
+/// Update the affected users' updated_at to trigger client sync
+pub(crate) async fn touch_users(
+    db: &Db,
+    user_ids: &HashSet<String>,
+    now_str: &str,
+) -> Result<(), worker::Error> {
+    for user_id in user_ids {
+        db.run(
+            "UPDATE users SET updated_at = ?1 WHERE id = ?2",
+            &[now_str.into(), user_id.as_str().into()],
+        )
+        .await?;
+    }
+
+    log::info!(
+        "Updated revision date for {} affected user(s)",
+        user_ids.len()
+    );
+    Ok(())
+}
+
+/// Runs one maintenance task, logging its outcome. A failure is logged and counted as zero.
+pub(crate) async fn run_task<F: std::future::Future<Output = Result<u32, worker::Error>>>(
+    name: &str,
+    task: F,
+) -> u32 {
+    log::info!("Maintenance: purging {}", name);
+    match task.await {
+        Ok(count) => {
+            log::info!("Maintenance: {} purge completed, {} removed", name, count);
+            count
+        }
+        Err(e) => {
+            log::error!("Maintenance: {} purge failed: {:?}", name, e);
+            0
+        }
+    }
+}
+
+/// Records removed (or, for emergency access, approved) by one [`run_maintenance`] pass.
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceSummary {
+    pub pending_attachments: u32,
+    pub auth_requests: u32,
+    pub ciphers: u32,
+    pub sends: u32,
+    pub events: u32,
+    pub import_sessions: u32,
+    pub emergency_access_approvals: u32,
+    pub orphans: OrphanSummary,
+    /// Users whose cipher, folder or send counters were off and have been recounted
+    pub object_counts: u32,
+}
+
+/// Runs every periodic cleanup task, orphan repairs included. Used by the cron trigger and `POST /admin/maintenance`.
+///
+/// Each task runs independently; a failing one is logged and counted as zero so the others still
+/// get their turn.
+pub async fn run_maintenance(env: &Env) -> MaintenanceSummary {
+    let summary = MaintenanceSummary {
+        pending_attachments: run_task(
+            "stale pending attachments",
+            purge_stale_pending_attachments(env),
+        )
+        .await,
+        auth_requests: run_task("expired auth requests", purge_expired_auth_requests(env)).await,
+        ciphers: run_task("soft-deleted ciphers", purge_deleted_ciphers(env)).await,
+        sends: run_task("expired sends", purge_expired_sends(env)).await,
+        events: run_task("old events", purge_old_events(env)).await,
+        import_sessions: run_task(
+            "expired import sessions",
+            purge_expired_import_sessions(env),
+        )
+        .await,
+        emergency_access_approvals: match emergency_access::approve_overdue_recoveries(env).await {
+            Ok(count) => {
+                log::info!(
+                    "Maintenance: {} overdue emergency access request(s) approved",
+                    count
+                );
+                count
+            }
+            Err(e) => {
+                log::error!(
+                    "Maintenance: approving emergency access requests failed: {:?}",
+                    e
+                );
+                0
+            }
+        },
+        orphans: orphans::run_orphan_cleanup(env).await,
+        // After the purges and repairs, which keep the counters in step themselves
+        object_counts: run_task("miscounted users", quota::recount_all(env)).await,
+    };
+
+    log::info!("Maintenance completed: {:?}", summary);
+    summary
+}
+
+/// Helper struct for batched purge queries
 #[derive(serde::Deserialize)]
-struct AffectedUser {
+struct PurgeRow {
+    id: String,
     user_id: Option<String>,
 }
 
@@ -0,0 +1,1169 @@
+//! Bitwarden Send.
+//!
+//! Sends are owned by a single user and deleted outright (there is no trash). Everything except
+//! the dates and limits is encrypted client-side with the send key. The `access` endpoints at the
+//! bottom are the anonymous, recipient side.
+
+use axum::{
+    extract::{Multipart, State},
+    http::HeaderMap,
+    Extension, Json,
+};
+use chrono::{Duration, TimeZone, Utc};
+use jwt_compact::Claims as JwtClaims;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::Settings;
+use crate::extract::{AppJson, AppPath};
+use crate::Env;
+use crate::{
+    auth::{keys::KeyRing, Claims},
+    crypto::{generate_salt, hash_password_for_storage, verify_password},
+    db::{self, Database, Db},
+    error::{db_error, internal_error, AppError},
+    handlers::attachments,
+    models::{
+        attachment::display_size,
+        response::ListResponse,
+        send::{
+            normalize_date, send_file_key, send_id_from_access_id, Send, SendAccessRequest,
+            SendRequest, SendResponse, SEND_MAX_DELETION_DAYS, SEND_TYPE_FILE, SEND_TYPE_TEXT,
+        },
+    },
+    notify::{self, UpdateType},
+    quota::{self, Object},
+    time, BaseUrl,
+};
+
+/// Server-side PBKDF2 iterations for send passwords. The client already sends a PBKDF2 hash
+/// derived from the send key, so this only keeps the stored value from being replayable.
+const SEND_PASSWORD_ITERATIONS: u32 = 1_000;
+
+/// Request body limit for file send uploads: the largest allowed file plus multipart overhead.
+pub const SEND_FILE_BODY_LIMIT: usize = 101 * 1024 * 1024;
+
+fn send_not_found() -> AppError {
+    AppError::NotFound("Send not found".to_string())
+}
+
+pub(crate) async fn find_send_for_user(
+    db: &Db,
+    send_id: &str,
+    user_id: &str,
+) -> Result<Send, AppError> {
+    db.first(
+        "SELECT * FROM sends WHERE id = ?1 AND user_id = ?2",
+        &[send_id.into(), user_id.into()],
+    )
+    .await
+    .map_err(db_error!())?
+    .ok_or_else(send_not_found)
+}
+
+/// Lists the user's sends, as returned by the API and `/api/sync`.
+pub(crate) async fn list_send_responses(
+    db: &Db,
+    user_id: &str,
+) -> Result<Vec<SendResponse>, AppError> {
+    let sends: Vec<Send> = db
+        .all(
+            "SELECT * FROM sends WHERE user_id = ?1 ORDER BY creation_date",
+            &[user_id.into()],
+        )
+        .await
+        .map_err(db_error!())?;
+
+    Ok(sends.iter().map(Send::to_response).collect())
+}
+
+/// Validated `(deletion_date, expiration_date)` of a create/update request.
+fn validate_dates(payload: &SendRequest) -> Result<(String, Option<String>), AppError> {
+    let deletion_date = normalize_date(&payload.deletion_date)
+        .ok_or_else(|| AppError::validation("DeletionDate", "Invalid deletion date"))?;
+    let deletion = time::parse_bw(&deletion_date)
+        .ok_or_else(|| AppError::validation("DeletionDate", "Invalid deletion date"))?;
+    if deletion > Utc::now() + Duration::days(SEND_MAX_DELETION_DAYS) {
+        return Err(AppError::validation(
+            "DeletionDate",
+            "You cannot have a Send with a deletion date that far into the future. Adjust the Deletion Date to a value less than 31 days from now and try again.",
+        ));
+    }
+
+    let expiration_date = match payload.expiration_date.as_deref() {
+        Some(date) if !date.is_empty() => Some(
+            normalize_date(date)
+                .ok_or_else(|| AppError::validation("ExpirationDate", "Invalid expiration date"))?,
+        ),
+        _ => None,
+    };
+
+    Ok((deletion_date, expiration_date))
+}
+
+fn validate_max_access_count(payload: &SendRequest) -> Result<(), AppError> {
+    if payload.max_access_count.is_some_and(|max| max < 0) {
+        return Err(AppError::validation(
+            "MaxAccessCount",
+            "Max access count can't be negative",
+        ));
+    }
+    Ok(())
+}
+
+/// Hashes a send password for storage, returning `(hash, salt, iterations)`.
+async fn hash_send_password(password: &str) -> Result<(String, String, i32), AppError> {
+    let salt = generate_salt()?;
+    let hash = hash_password_for_storage(password, &salt, SEND_PASSWORD_ITERATIONS).await?;
+    Ok((hash, salt, SEND_PASSWORD_ITERATIONS as i32))
+}
+
+/// JSON text of the type-specific payload (`text` object) for text sends.
+fn text_data(payload: &SendRequest) -> Result<String, AppError> {
+    let text = payload
+        .text
+        .as_ref()
+        .filter(|t| t.is_object())
+        .ok_or_else(|| AppError::BadRequest("Send data not provided".to_string()))?;
+    serde_json::to_string(text).map_err(internal_error!())
+}
+
+/// Checks a file send's size against `SEND_FILE_MAX_BYTES` and the user's storage quota.
+async fn enforce_file_limits(
+    db: &Db,
+    env: &Env,
+    user_id: &str,
+    size: i64,
+    exclude_file: Option<&str>,
+) -> Result<(), AppError> {
+    if size > Settings::get(env).send_file_max_bytes.clone()? {
+        return Err(AppError::BadRequest(
+            "Send file size exceeds limit".to_string(),
+        ));
+    }
+    attachments::enforce_limits(db, env, user_id, size, exclude_file).await
+}
+
+async fn insert_send(db: &Db, send: &Send) -> Result<(), AppError> {
+    let insert = Database::prepare(
+        db,
+        "INSERT INTO sends (id, user_id, atype, name, notes, data, akey, password_hash, password_salt, password_iter,
+                            max_access_count, access_count, creation_date, revision_date, expiration_date, deletion_date,
+                            disabled, hide_email)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+        &[
+            send.id.as_str().into(),
+            send.user_id.as_str().into(),
+            send.atype.into(),
+            send.name.as_str().into(),
+            send.notes.as_deref().into(),
+            send.data.as_str().into(),
+            send.akey.as_str().into(),
+            send.password_hash.as_deref().into(),
+            send.password_salt.as_deref().into(),
+            send.password_iter.into(),
+            send.max_access_count.into(),
+            send.access_count.into(),
+            send.creation_date.as_str().into(),
+            send.revision_date.as_str().into(),
+            send.expiration_date.as_deref().into(),
+            send.deletion_date.as_str().into(),
+            send.disabled.into(),
+            send.hide_email.into(),
+        ],
+    )
+    .map_err(db_error!())?;
+    Database::batch(
+        db,
+        vec![insert, quota::added(db, &send.user_id, Object::Send)?],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    Ok(())
+}
+
+/// GET /api/sends
+#[utoipa::path(
+    get,
+    path = "/api/sends",
+    tag = "sends",
+    responses((status = 200, description = "Success", body = ListResponse<SendResponse>)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_sends(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<ListResponse<SendResponse>>, AppError> {
+    let db = db::get_db(&env)?;
+    let sends = list_send_responses(&db, &claims.sub).await?;
+
+    Ok(Json(ListResponse::new(sends)))
+}
+
+/// GET /api/sends/{id}
+#[utoipa::path(
+    get,
+    path = "/api/sends/{id}",
+    tag = "sends",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = SendResponse)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_send(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<SendResponse>, AppError> {
+    let db = db::get_db(&env)?;
+    let send = find_send_for_user(&db, &id, &claims.sub).await?;
+    Ok(Json(send.to_response()))
+}
+
+/// POST /api/sends
+///
+/// Creates a text send. File sends go through `/api/sends/file/v2`.
+#[utoipa::path(
+    post,
+    path = "/api/sends",
+    tag = "sends",
+    request_body = SendRequest,
+    responses((status = 200, description = "Success", body = SendResponse)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_send(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppJson(payload): AppJson<SendRequest>,
+) -> Result<Json<SendResponse>, AppError> {
+    if payload.atype == SEND_TYPE_FILE {
+        return Err(AppError::BadRequest(
+            "File sends should use /api/sends/file/v2".to_string(),
+        ));
+    }
+    if payload.atype != SEND_TYPE_TEXT {
+        return Err(AppError::validation("Type", "Invalid send type"));
+    }
+    validate_max_access_count(&payload)?;
+    let (deletion_date, expiration_date) = validate_dates(&payload)?;
+    let data = text_data(&payload)?;
+    let db = db::get_db(&env)?;
+    quota::check(&db, &env, &claims.sub, &[(Object::Send, 1)]).await?;
+
+    let (password_hash, password_salt, password_iter) = match payload.password.as_deref() {
+        Some(password) => {
+            let (hash, salt, iter) = hash_send_password(password).await?;
+            (Some(hash), Some(salt), Some(iter))
+        }
+        None => (None, None, None),
+    };
+
+    let now = time::now_bw();
+    let send = Send {
+        id: Uuid::new_v4().to_string(),
+        user_id: claims.sub.clone(),
+        atype: payload.atype,
+        name: payload.name,
+        notes: payload.notes,
+        data,
+        akey: payload.key,
+        password_hash,
+        password_salt,
+        password_iter,
+        max_access_count: payload.max_access_count,
+        access_count: 0,
+        creation_date: now.clone(),
+        revision_date: now,
+        expiration_date,
+        deletion_date,
+        disabled: payload.disabled as i32,
+        hide_email: payload.hide_email.unwrap_or(false) as i32,
+    };
+
+    insert_send(&db, &send).await?;
+
+    db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_send_update(
+        &env,
+        &db,
+        UpdateType::SyncSendCreate,
+        &claims.sub,
+        &send.id,
+        &send.revision_date,
+        claims.device.as_deref(),
+    )
+    .await;
+
+    Ok(Json(send.to_response()))
+}
+
+/// POST /api/sends/file/v2
+///
+/// Creates a file send from its metadata. The client then uploads the encrypted file to the
+/// returned URL.
+#[utoipa::path(
+    post,
+    path = "/api/sends/file/v2",
+    tag = "sends",
+    request_body = SendRequest,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_send_file_v2(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppJson(payload): AppJson<SendRequest>,
+) -> Result<Json<Value>, AppError> {
+    if payload.atype != SEND_TYPE_FILE {
+        return Err(AppError::BadRequest(
+            "Send content is not a file".to_string(),
+        ));
+    }
+    if !attachments::attachments_enabled(&env) {
+        return Err(AppError::BadRequest(
+            "File sends are not enabled".to_string(),
+        ));
+    }
+    validate_max_access_count(&payload)?;
+    let (deletion_date, expiration_date) = validate_dates(&payload)?;
+
+    let file_length = payload
+        .file_length()
+        .ok_or_else(|| AppError::BadRequest("Invalid send length".to_string()))?;
+    if file_length < 0 {
+        return Err(AppError::validation(
+            "FileLength",
+            "Send size can't be negative",
+        ));
+    }
+    let file_name = payload
+        .file
+        .as_ref()
+        .and_then(|file| file.get("fileName"))
+        .cloned()
+        .ok_or_else(|| AppError::BadRequest("Send data not provided".to_string()))?;
+
+    let db = db::get_db(&env)?;
+    enforce_file_limits(&db, &env, &claims.sub, file_length, None).await?;
+    quota::check(&db, &env, &claims.sub, &[(Object::Send, 1)]).await?;
+
+    let (password_hash, password_salt, password_iter) = match payload.password.as_deref() {
+        Some(password) => {
+            let (hash, salt, iter) = hash_send_password(password).await?;
+            (Some(hash), Some(salt), Some(iter))
+        }
+        None => (None, None, None),
+    };
+
+    let file_id = Uuid::new_v4().to_string();
+    let data = json!({
+        "id": file_id,
+        "fileName": file_name,
+        "size": file_length.to_string(),
+        "sizeName": display_size(file_length),
+    });
+
+    let now = time::now_bw();
+    let send = Send {
+        id: Uuid::new_v4().to_string(),
+        user_id: claims.sub.clone(),
+        atype: payload.atype,
+        name: payload.name,
+        notes: payload.notes,
+        data: data.to_string(),
+        akey: payload.key,
+        password_hash,
+        password_salt,
+        password_iter,
+        max_access_count: payload.max_access_count,
+        access_count: 0,
+        creation_date: now.clone(),
+        revision_date: now,
+        expiration_date,
+        deletion_date,
+        disabled: payload.disabled as i32,
+        hide_email: payload.hide_email.unwrap_or(false) as i32,
+    };
+    insert_send(&db, &send).await?;
+
+    db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_send_update(
+        &env,
+        &db,
+        UpdateType::SyncSendCreate,
+        &claims.sub,
+        &send.id,
+        &send.revision_date,
+        claims.device.as_deref(),
+    )
+    .await;
+
+    Ok(Json(json!({
+        "fileUploadType": 0, // Direct
+        "object": "send-fileUpload",
+        "url": format!("/sends/{}/file/{}", send.id, file_id),
+        "sendResponse": send.to_response(),
+    })))
+}
+
+/// POST /api/sends/{id}/file/{file_id}
+///
+/// Receives the encrypted contents of a file send created via `/api/sends/file/v2`.
+#[utoipa::path(
+    post,
+    path = "/api/sends/{id}/file/{file_id}",
+    tag = "sends",
+    params(("id" = String, Path), ("file_id" = String, Path)),
+    request_body(content_type = "multipart/form-data"),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn post_send_file(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath((id, file_id)): AppPath<(String, String)>,
+    mut multipart: Multipart,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut send = find_send_for_user(&db, &id, &claims.sub).await?;
+    if !attachments::attachments_enabled(&env) {
+        return Err(AppError::BadRequest(
+            "File sends are not enabled".to_string(),
+        ));
+    }
+    if send.file_id().as_deref() != Some(file_id.as_str()) {
+        return Err(AppError::BadRequest(
+            "Send file does not match send data".to_string(),
+        ));
+    }
+
+    let (file_bytes, content_type, _key, _file_name) =
+        attachments::read_multipart(&mut multipart).await?;
+    let actual_size = file_bytes.len() as i64;
+
+    let declared_size = send.file_size().unwrap_or(0);
+    if (actual_size - declared_size).abs() > attachments::SIZE_LEEWAY_BYTES {
+        return Err(AppError::BadRequest(format!(
+            "Send file size mismatch (expected {declared_size}, got {actual_size})"
+        )));
+    }
+    enforce_file_limits(&db, &env, &claims.sub, actual_size, Some(&file_id)).await?;
+
+    attachments::upload_to_storage(
+        &env,
+        &send_file_key(&send.id, &file_id),
+        content_type,
+        file_bytes.to_vec(),
+    )
+    .await?;
+
+    // Record the real size so quotas and clients see what was actually stored.
+    let mut data: Value = serde_json::from_str(&send.data).map_err(internal_error!())?;
+    data["size"] = json!(actual_size.to_string());
+    data["sizeName"] = json!(display_size(actual_size));
+    send.data = data.to_string();
+    send.revision_date = time::now_bw();
+
+    db.run(
+        "UPDATE sends SET data = ?1, revision_date = ?2 WHERE id = ?3",
+        &[
+            send.data.as_str().into(),
+            send.revision_date.as_str().into(),
+            send.id.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_send_update(
+        &env,
+        &db,
+        UpdateType::SyncSendUpdate,
+        &claims.sub,
+        &send.id,
+        &send.revision_date,
+        claims.device.as_deref(),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+/// PUT /api/sends/{id}
+///
+/// A missing `password` keeps the stored one and a new one replaces it (hashed again here); use
+/// `remove-password` to clear it.
+#[utoipa::path(
+    put,
+    path = "/api/sends/{id}",
+    tag = "sends",
+    params(("id" = String, Path)),
+    request_body = SendRequest,
+    responses((status = 200, description = "Success", body = SendResponse)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_send(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<SendRequest>,
+) -> Result<Json<SendResponse>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut send = find_send_for_user(&db, &id, &claims.sub).await?;
+
+    if payload.atype != send.atype {
+        return Err(AppError::BadRequest("Sends can't change type".to_string()));
+    }
+    validate_max_access_count(&payload)?;
+    if payload
+        .max_access_count
+        .is_some_and(|max| max < send.access_count)
+    {
+        return Err(AppError::BadRequest(format!(
+            "Max access count can't be lower than the current access count ({})",
+            send.access_count
+        )));
+    }
+    let (deletion_date, expiration_date) = validate_dates(&payload)?;
+
+    // File contents can't be replaced; only the metadata is editable.
+    if send.atype == SEND_TYPE_TEXT {
+        send.data = text_data(&payload)?;
+    }
+    if let Some(password) = payload.password.as_deref() {
+        let (hash, salt, iter) = hash_send_password(password).await?;
+        send.password_hash = Some(hash);
+        send.password_salt = Some(salt);
+        send.password_iter = Some(iter);
+    }
+    send.name = payload.name;
+    send.notes = payload.notes;
+    send.akey = payload.key;
+    send.max_access_count = payload.max_access_count;
+    send.expiration_date = expiration_date;
+    send.deletion_date = deletion_date;
+    send.disabled = payload.disabled as i32;
+    send.hide_email = payload.hide_email.unwrap_or(false) as i32;
+    send.revision_date = time::now_bw();
+
+    db.run(
+        "UPDATE sends SET name = ?1, notes = ?2, data = ?3, akey = ?4, password_hash = ?5, password_salt = ?6,
+                          password_iter = ?7, max_access_count = ?8, expiration_date = ?9, deletion_date = ?10,
+                          disabled = ?11, hide_email = ?12, revision_date = ?13
+         WHERE id = ?14 AND user_id = ?15",
+        &[
+            send.name.as_str().into(),
+            send.notes.as_deref().into(),
+            send.data.as_str().into(),
+            send.akey.as_str().into(),
+            send.password_hash.as_deref().into(),
+            send.password_salt.as_deref().into(),
+            send.password_iter.into(),
+            send.max_access_count.into(),
+            send.expiration_date.as_deref().into(),
+            send.deletion_date.as_str().into(),
+            send.disabled.into(),
+            send.hide_email.into(),
+            send.revision_date.as_str().into(),
+            send.id.as_str().into(),
+            claims.sub.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_send_update(
+        &env,
+        &db,
+        UpdateType::SyncSendUpdate,
+        &claims.sub,
+        &send.id,
+        &send.revision_date,
+        claims.device.as_deref(),
+    )
+    .await;
+
+    Ok(Json(send.to_response()))
+}
+
+/// PUT /api/sends/{id}/remove-password
+#[utoipa::path(
+    put,
+    path = "/api/sends/{id}/remove-password",
+    tag = "sends",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success", body = SendResponse)),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn put_remove_password(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<SendResponse>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut send = find_send_for_user(&db, &id, &claims.sub).await?;
+
+    send.password_hash = None;
+    send.password_salt = None;
+    send.password_iter = None;
+    send.revision_date = time::now_bw();
+
+    db.run(
+        "UPDATE sends SET password_hash = NULL, password_salt = NULL, password_iter = NULL, revision_date = ?1
+         WHERE id = ?2 AND user_id = ?3",
+        &[
+            send.revision_date.as_str().into(),
+            send.id.as_str().into(),
+            claims.sub.as_str().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_send_update(
+        &env,
+        &db,
+        UpdateType::SyncSendUpdate,
+        &claims.sub,
+        &send.id,
+        &send.revision_date,
+        claims.device.as_deref(),
+    )
+    .await;
+
+    Ok(Json(send.to_response()))
+}
+
+/// DELETE /api/sends/{id}
+///
+/// Sends have no trash; deletion is permanent.
+#[utoipa::path(
+    delete,
+    path = "/api/sends/{id}",
+    tag = "sends",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn delete_send(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    AppPath(id): AppPath<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let send = find_send_for_user(&db, &id, &claims.sub).await?;
+
+    if let Some(key) = send.storage_key() {
+        attachments::delete_storage_objects(&env, &[key]).await?;
+    }
+    Database::batch(
+        &db,
+        vec![
+            Database::prepare(
+                &db,
+                "DELETE FROM sends WHERE id = ?1",
+                &[send.id.as_str().into()],
+            )
+            .map_err(db_error!())?,
+            quota::removed(&db, &send.user_id, Object::Send)?,
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+
+    db::touch_user_updated_at(&db, &claims.sub).await?;
+    notify::notify_send_update(
+        &env,
+        &db,
+        UpdateType::SyncSendDelete,
+        &claims.sub,
+        &send.id,
+        &time::now_bw(),
+        claims.device.as_deref(),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+/// `purpose` of send download tokens, checked by the download handler in `attachments.js`.
+const SEND_DOWNLOAD_PURPOSE: &str = "send_download";
+
+/// Claims of the short-lived token in a file send download URL.
+///
+/// The download itself is served by the JS fast path (`handleSendDownload` in `attachments.js`),
+/// which checks these against the URL and the send's current state.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SendDownloadClaims {
+    pub purpose: String,
+    pub send_id: String,
+    pub file_id: String,
+}
+
+async fn check_access_rate_limit(env: &Env, headers: &HeaderMap) -> Result<(), AppError> {
+    if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
+        let ip = headers
+            .get("cf-connecting-ip")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("unknown");
+        let rate_limit_key = format!("send-access:{}", ip);
+        if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
+            if !outcome.success {
+                return Err(AppError::TooManyRequests(
+                    "Too many requests. Please try again later.".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Loads a send for anonymous access. Unavailable sends (disabled, expired, pending deletion or
+/// out of accesses) all look like missing ones so their existence isn't leaked.
+async fn find_accessible_send(db: &Db, id: &str) -> Result<Send, AppError> {
+    let send_id = send_id_from_access_id(id).ok_or_else(send_not_found)?;
+    let send: Send = db
+        .first(
+            "SELECT * FROM sends WHERE id = ?1",
+            &[send_id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?
+        .ok_or_else(send_not_found)?;
+
+    let now = time::now_bw();
+    if send.disabled != 0
+        || send.deletion_date <= now
+        || send.expiration_date.as_ref().is_some_and(|exp| *exp <= now)
+        || send
+            .max_access_count
+            .is_some_and(|max| send.access_count >= max)
+    {
+        return Err(send_not_found());
+    }
+
+    Ok(send)
+}
+
+async fn check_send_password(send: &Send, password: Option<&str>) -> Result<(), AppError> {
+    let (Some(hash), Some(salt)) = (send.password_hash.as_deref(), send.password_salt.as_deref())
+    else {
+        return Ok(());
+    };
+    let password =
+        password.ok_or_else(|| AppError::Unauthorized("Password not provided".to_string()))?;
+    let iterations = send
+        .password_iter
+        .unwrap_or(SEND_PASSWORD_ITERATIONS as i32) as u32;
+    if !verify_password(password, hash, salt, iterations).await? {
+        return Err(AppError::Unauthorized("Invalid password".to_string()));
+    }
+    Ok(())
+}
+
+/// Counts one access and lets the owner's clients pick up the new count.
+///
+/// The increment is conditional on the limit so concurrent recipients can't push the count past
+/// `max_access_count`; whoever loses the race gets a 404 like any other exhausted send.
+async fn record_access(env: &Arc<Env>, db: &Db, send: &mut Send) -> Result<(), AppError> {
+    let changes = db
+        .run(
+            "UPDATE sends SET access_count = access_count + 1
+         WHERE id = ?1 AND disabled = 0
+           AND (max_access_count IS NULL OR access_count < max_access_count)",
+            &[send.id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    if changes == 0 {
+        return Err(send_not_found());
+    }
+    send.access_count += 1;
+
+    db::touch_user_updated_at(db, &send.user_id).await?;
+    notify::notify_send_update(
+        env,
+        db,
+        UpdateType::SyncSendUpdate,
+        &send.user_id,
+        &send.id,
+        &send.revision_date,
+        None,
+    )
+    .await;
+
+    Ok(())
+}
+
+/// The owner's email, shown to recipients unless the send hides it.
+async fn creator_identifier(db: &Db, send: &Send) -> Result<Option<String>, AppError> {
+    if send.hide_email != 0 {
+        return Ok(None);
+    }
+    let row: Option<Value> = db
+        .first(
+            "SELECT email FROM users WHERE id = ?1",
+            &[send.user_id.as_str().into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(row.and_then(|row| row.get("email").and_then(Value::as_str).map(str::to_string)))
+}
+
+fn send_download_url(
+    env: &Env,
+    base_url: &str,
+    send_id: &str,
+    file_id: &str,
+) -> Result<String, AppError> {
+    let ttl_secs = Settings::get(env).attachment_ttl_secs.clone()?;
+    let expiration = Utc
+        .timestamp_opt(Utc::now().timestamp() + ttl_secs, 0)
+        .single()
+        .ok_or_else(|| AppError::Internal(Some("download expiry out of range".to_string())))?;
+    let mut claims = JwtClaims::new(SendDownloadClaims {
+        purpose: SEND_DOWNLOAD_PURPOSE.to_string(),
+        send_id: send_id.to_string(),
+        file_id: file_id.to_string(),
+    });
+    claims.expiration = Some(expiration);
+    let token = KeyRing::access(env)?.sign(&claims)?;
+
+    let normalized_base = base_url.trim_end_matches('/');
+    Ok(format!(
+        "{normalized_base}/api/sends/{send_id}/{file_id}?token={token}"
+    ))
+}
+
+/// POST /api/sends/access/{access_id}
+///
+/// Anonymous. Text sends count an access here; file sends count it when the file is fetched.
+#[utoipa::path(
+    post,
+    path = "/api/sends/access/{id}",
+    tag = "sends",
+    params(("id" = String, Path)),
+    request_body = SendAccessRequest,
+    responses((status = 200, description = "Success"))
+)]
+#[worker::send]
+pub async fn post_access(
+    State(env): State<Arc<Env>>,
+    headers: HeaderMap,
+    AppPath(access_id): AppPath<String>,
+    AppJson(payload): AppJson<SendAccessRequest>,
+) -> Result<Json<Value>, AppError> {
+    check_access_rate_limit(&env, &headers).await?;
+
+    let db = db::get_db(&env)?;
+    let mut send = find_accessible_send(&db, &access_id).await?;
+    check_send_password(&send, payload.password.as_deref()).await?;
+
+    if send.atype == SEND_TYPE_TEXT {
+        record_access(&env, &db, &mut send).await?;
+    }
+
+    let creator = creator_identifier(&db, &send).await?;
+    Ok(Json(send.to_access_json(creator)))
+}
+
+/// POST /api/sends/{id}/access/file/{file_id}
+///
+/// Anonymous. Returns a short-lived download URL for a file send's contents. The access is
+/// counted when the file is downloaded (`handleSendDownload` in `attachments.js`).
+#[utoipa::path(
+    post,
+    path = "/api/sends/{id}/access/file/{file_id}",
+    tag = "sends",
+    params(("id" = String, Path), ("file_id" = String, Path)),
+    request_body = SendAccessRequest,
+    responses((status = 200, description = "Success"))
+)]
+#[worker::send]
+pub async fn post_access_file(
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    headers: HeaderMap,
+    AppPath((id, file_id)): AppPath<(String, String)>,
+    AppJson(payload): AppJson<SendAccessRequest>,
+) -> Result<Json<Value>, AppError> {
+    check_access_rate_limit(&env, &headers).await?;
+
+    let db = db::get_db(&env)?;
+    let send = find_accessible_send(&db, &id).await?;
+    if send.file_id().as_deref() != Some(file_id.as_str()) {
+        return Err(send_not_found());
+    }
+    check_send_password(&send, payload.password.as_deref()).await?;
+
+    let url = send_download_url(&env, &base_url, &send.id, &file_id)?;
+    Ok(Json(json!({
+        "id": file_id,
+        "url": url,
+        "object": "send-fileDownload",
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing::{self, ORIGIN};
+    use crate::native::{block_on, Env};
+    use axum::http::{Method, StatusCode};
+
+    const EMAIL: &str = "alice@example.com";
+
+    /// A migrated env where alice is registered, and an access token for her.
+    fn env() -> (Env, String) {
+        let env = testing::env();
+        let token = testing::user(&env, "alice");
+        (env, token)
+    }
+
+    /// A text send request, with `fields` set on top.
+    fn text_send(fields: Value) -> Value {
+        let mut body = json!({
+            "type": SEND_TYPE_TEXT,
+            "key": "2.a2V5|aXY=|bWFj",
+            "name": "2.bmFtZQ==|aXY=|bWFj",
+            "text": { "text": "2.dGV4dA==|aXY=|bWFj", "hidden": false },
+            "deletionDate": time::format_bw(Utc::now() + Duration::days(7)),
+            "disabled": false,
+        });
+        body.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        body
+    }
+
+    /// Creates a send for alice and returns it as the API answers.
+    fn create(env: &Env, token: &str, fields: Value) -> Value {
+        let (status, body) = testing::request(
+            env,
+            Method::POST,
+            "/api/sends",
+            Some(token),
+            Some(text_send(fields)),
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body
+    }
+
+    fn access(env: &Env, created: &Value) -> (StatusCode, Value) {
+        let path = format!(
+            "/api/sends/access/{}",
+            created["accessId"].as_str().unwrap()
+        );
+        testing::request(env, Method::POST, &path, None, Some(json!({})))
+    }
+
+    fn access_count(env: &Env, created: &Value) -> i64 {
+        let db = env.d1("vault1").unwrap();
+        block_on(db.first_column::<i64>(
+            "SELECT access_count FROM sends WHERE id = ?1",
+            &[created["id"].as_str().unwrap().into()],
+            "access_count",
+        ))
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn disabled_sends_cant_be_accessed() {
+        let (env, token) = env();
+        let created = create(&env, &token, json!({ "disabled": true }));
+
+        assert_eq!(access(&env, &created).0, StatusCode::NOT_FOUND);
+        assert_eq!(access_count(&env, &created), 0);
+    }
+
+    #[test]
+    fn accesses_stop_at_the_limit() {
+        let (env, token) = env();
+        let created = create(&env, &token, json!({ "maxAccessCount": 2 }));
+
+        assert_eq!(access(&env, &created).0, StatusCode::OK);
+        assert_eq!(access(&env, &created).0, StatusCode::OK);
+        assert_eq!(access(&env, &created).0, StatusCode::NOT_FOUND);
+        assert_eq!(access_count(&env, &created), 2);
+    }
+
+    #[test]
+    fn concurrent_accesses_cant_pass_the_limit() {
+        let (env, token) = env();
+        let created = create(&env, &token, json!({ "maxAccessCount": 1 }));
+        let db = env.d1("vault1").unwrap();
+        let load = || {
+            block_on(find_accessible_send(
+                &db,
+                created["accessId"].as_str().unwrap(),
+            ))
+            .unwrap()
+        };
+        // Both recipients load the send before either counts their access
+        let (mut first, mut second) = (load(), load());
+        let env = Arc::new(env);
+
+        assert!(block_on(record_access(&env, &db, &mut first)).is_ok());
+        assert!(matches!(
+            block_on(record_access(&env, &db, &mut second)),
+            Err(AppError::NotFound(_))
+        ));
+        assert_eq!(access_count(&env, &created), 1);
+    }
+
+    #[test]
+    fn recipients_see_the_creator_email_unless_hidden() {
+        let (env, token) = env();
+        let shown = create(&env, &token, json!({ "hideEmail": false }));
+        let hidden = create(&env, &token, json!({ "hideEmail": true }));
+
+        assert_eq!(access(&env, &shown).1["creatorIdentifier"], EMAIL);
+        assert_eq!(access(&env, &hidden).1["creatorIdentifier"], Value::Null);
+    }
+
+    #[test]
+    fn the_limit_cant_go_below_the_accesses_made() {
+        let (env, token) = env();
+        let created = create(&env, &token, json!({ "maxAccessCount": 5 }));
+        access(&env, &created);
+        access(&env, &created);
+        let path = format!("/api/sends/{}", created["id"].as_str().unwrap());
+
+        let (status, body) = testing::request(
+            &env,
+            Method::PUT,
+            &path,
+            Some(&token),
+            Some(text_send(json!({ "maxAccessCount": 1 }))),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+
+        let (status, body) = testing::request(
+            &env,
+            Method::PUT,
+            &path,
+            Some(&token),
+            Some(text_send(json!({ "maxAccessCount": 2 }))),
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["maxAccessCount"], 2);
+    }
+
+    const FILE_ID: &str = "2f7a1c9e8b3d4e5f";
+
+    /// Stores a file send of alice's, as uploading one leaves it.
+    fn file_send(env: &Env, max_access_count: Option<i32>) -> Send {
+        let now = Utc::now();
+        let send = Send {
+            id: Uuid::new_v4().to_string(),
+            user_id: "alice".to_string(),
+            atype: SEND_TYPE_FILE,
+            name: "2.bmFtZQ==|aXY=|bWFj".to_string(),
+            notes: None,
+            data: json!({
+                "id": FILE_ID,
+                "fileName": "2.ZmlsZQ==|aXY=|bWFj",
+                "size": 3 * 1024 * 1024,
+                "sizeName": "3 MB",
+            })
+            .to_string(),
+            akey: "2.a2V5|aXY=|bWFj".to_string(),
+            password_hash: None,
+            password_salt: None,
+            password_iter: None,
+            max_access_count,
+            access_count: 0,
+            creation_date: time::format_bw(now),
+            revision_date: time::format_bw(now),
+            expiration_date: None,
+            deletion_date: time::format_bw(now + Duration::days(7)),
+            disabled: 0,
+            hide_email: 0,
+        };
+        block_on(insert_send(&env.d1("vault1").unwrap(), &send)).unwrap();
+        send
+    }
+
+    fn access_file(env: &Env, file: &Send, file_id: &str) -> (StatusCode, Value) {
+        let path = format!("/api/sends/{}/access/file/{file_id}", file.access_id());
+        testing::request(env, Method::POST, &path, None, Some(json!({})))
+    }
+
+    #[test]
+    fn file_access_returns_a_download_url_bound_to_the_send() {
+        let (env, _) = env();
+        let file = file_send(&env, None);
+
+        let (status, body) = access_file(&env, &file, FILE_ID);
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let url = body["url"].as_str().unwrap();
+        let (path, token) = url.split_once("?token=").unwrap();
+        assert_eq!(path, format!("{ORIGIN}/api/sends/{}/{FILE_ID}", file.id));
+        let token = KeyRing::access(&env)
+            .unwrap()
+            .verify::<SendDownloadClaims>(token)
+            .unwrap();
+        let claims = token.claims();
+        assert_eq!(claims.custom.purpose, SEND_DOWNLOAD_PURPOSE);
+        assert_eq!(claims.custom.send_id, file.id);
+        assert_eq!(claims.custom.file_id, FILE_ID);
+        let ttl = Settings::get(&env).attachment_ttl_secs.clone().unwrap();
+        let expiration = claims.expiration.unwrap();
+        assert!(expiration > Utc::now());
+        assert!(expiration <= Utc::now() + Duration::seconds(ttl));
+    }
+
+    #[test]
+    fn file_access_leaves_counting_to_the_download_and_stops_at_the_limit() {
+        let (env, _) = env();
+        let file = file_send(&env, Some(1));
+
+        assert_eq!(access_file(&env, &file, FILE_ID).0, StatusCode::OK);
+        assert_eq!(access_file(&env, &file, FILE_ID).0, StatusCode::OK);
+        assert_eq!(access_count(&env, &json!({ "id": file.id })), 0);
+
+        // As the download of the only allowed access leaves it
+        block_on(env.d1("vault1").unwrap().run(
+            "UPDATE sends SET access_count = 1 WHERE id = ?1",
+            &[file.id.as_str().into()],
+        ))
+        .unwrap();
+        assert_eq!(access_file(&env, &file, FILE_ID).0, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn deleted_sends_and_other_files_get_no_download_url() {
+        let (env, token) = env();
+        let file = file_send(&env, None);
+
+        assert_eq!(
+            access_file(&env, &file, "another-file").0,
+            StatusCode::NOT_FOUND
+        );
+
+        let (status, _) = testing::request(
+            &env,
+            Method::DELETE,
+            &format!("/api/sends/{}", file.id),
+            Some(&token),
+            None,
+        );
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(access_file(&env, &file, FILE_ID).0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,246 @@
+//! Stubs: empty answers to endpoints the official clients poll but that aren't implemented yet.
+//!
+//! Without them clients log errors on startup or show failure banners. Each stub returns the
+//! shape the real endpoint would when there's nothing to report; replace it when the feature is
+//! implemented. Endpoints that aren't served on purpose get a 404 saying so instead
+//! ([`not_found`]).
+
+use axum::Json;
+use serde_json::{json, Value};
+
+use crate::auth::Claims;
+use crate::error::AppError;
+use crate::extract::AppPath;
+
+/// Endpoints of the official server that aren't served on purpose, by path prefix, with what
+/// clients get instead.
+const UNSERVED: &[(&str, &str)] = &[
+    (
+        "/notifications/anonymous-hub",
+        "Login with device notifications aren't supported by this server. The requesting device checks for approval instead.",
+    ),
+    (
+        "/events/",
+        "Client event collection isn't supported by this server.",
+    ),
+    (
+        "/identity/connect/authorize",
+        "Single sign-on isn't supported by this server.",
+    ),
+    (
+        "/identity/sso/",
+        "Single sign-on isn't supported by this server.",
+    ),
+];
+
+/// The 404 for an API path no route matches, saying why when the endpoint isn't served on
+/// purpose.
+pub fn not_found(path: &str) -> AppError {
+    let message = UNSERVED
+        .iter()
+        .find(|(prefix, _)| path.starts_with(prefix))
+        .map_or("Not found.", |(_, message)| message);
+    AppError::NotFound(message.to_string())
+}
+
+/// Answers a method a path has no route for; axum adds the `Allow` header.
+pub async fn method_not_allowed() -> AppError {
+    AppError::MethodNotAllowed
+}
+
+fn empty_list() -> Value {
+    json!({
+        "data": [],
+        "object": "list",
+        "continuationToken": null,
+    })
+}
+
+/// GET /api/accounts/sso-user-identifier
+///
+/// SSO isn't supported, so no user has an SSO identifier.
+#[utoipa::path(
+    get,
+    path = "/api/accounts/sso-user-identifier",
+    tag = "stubs",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_sso_user_identifier(_claims: Claims) -> Json<Value> {
+    Json(Value::Null)
+}
+
+/// GET /api/notifications
+///
+/// The web vault's notification center; nothing is ever posted to it.
+#[utoipa::path(
+    get,
+    path = "/api/notifications",
+    tag = "stubs",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_notifications(_claims: Claims) -> Json<Value> {
+    Json(empty_list())
+}
+
+/// GET /api/tasks
+///
+/// Security tasks (such as changing at-risk passwords) are assigned by organization admins,
+/// which isn't supported.
+#[utoipa::path(
+    get,
+    path = "/api/tasks",
+    tag = "stubs",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_security_tasks(_claims: Claims) -> Json<Value> {
+    Json(empty_list())
+}
+
+/// GET /api/organizations/{id}/auth-requests
+///
+/// Admin approval of device logins needs trusted-device encryption, which isn't supported.
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{id}/auth-requests",
+    tag = "stubs",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
+#[worker::send]
+pub async fn get_organization_auth_requests(
+    _claims: Claims,
+    AppPath(_org_id): AppPath<String>,
+) -> Json<Value> {
+    Json(empty_list())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing::{self, ORIGIN};
+    use crate::native::Env;
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, Response, StatusCode};
+
+    fn env() -> (Env, String) {
+        let env = testing::env();
+        let token = testing::user(&env, "alice");
+        (env, token)
+    }
+
+    fn get(env: &Env, path: &str, token: Option<&str>) -> (StatusCode, Value) {
+        testing::request(env, Method::GET, path, token, None)
+    }
+
+    // When one of these starts failing, the endpoint got a real implementation: move its test
+    // along with it.
+    #[test]
+    fn list_stubs_answer_an_empty_list() {
+        let (env, token) = env();
+        for path in [
+            "/api/notifications",
+            "/api/tasks",
+            "/api/organizations/some-org/auth-requests",
+        ] {
+            let (status, body) = get(&env, path, Some(&token));
+            assert_eq!(status, StatusCode::OK, "{path}: {body}");
+            assert_eq!(body, empty_list(), "{path}");
+        }
+    }
+
+    #[test]
+    fn nobody_has_an_sso_identifier() {
+        let (env, token) = env();
+        let (status, body) = get(&env, "/api/accounts/sso-user-identifier", Some(&token));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, Value::Null);
+    }
+
+    #[test]
+    fn stubs_still_require_a_login() {
+        let (env, _) = env();
+        for path in [
+            "/api/notifications",
+            "/api/tasks",
+            "/api/accounts/sso-user-identifier",
+        ] {
+            assert_eq!(get(&env, path, None).0, StatusCode::UNAUTHORIZED, "{path}");
+        }
+    }
+
+    fn send(env: &Env, method: Method, path: &str) -> Response<Body> {
+        let req = Request::builder()
+            .method(method)
+            .uri(format!("{ORIGIN}{path}"))
+            .body(Body::empty())
+            .unwrap();
+        testing::fetch(env, req)
+    }
+
+    fn message(response: Response<Body>) -> String {
+        let (_, body) = testing::json_response(response);
+        assert_eq!(body["object"], "error");
+        body["message"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn unknown_api_paths_are_error_model_404s() {
+        let (env, _) = env();
+        for path in ["/api/no-such-endpoint", "/identity/nothing", "/api"] {
+            let response = send(&env, Method::GET, path);
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+            assert_eq!(message(response), "Not found.", "{path}");
+        }
+    }
+
+    #[test]
+    fn unserved_endpoints_say_so() {
+        let (env, _) = env();
+        let response = send(&env, Method::GET, "/notifications/anonymous-hub?Token=x");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(message(response).starts_with("Login with device notifications aren't supported"));
+
+        let response = send(&env, Method::POST, "/events/collect");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            message(response),
+            "Client event collection isn't supported by this server."
+        );
+    }
+
+    #[test]
+    fn wrong_methods_are_405s_with_the_allowed_ones() {
+        let (env, _) = env();
+        let response = send(&env, Method::GET, "/identity/accounts/prelogin");
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "POST");
+        assert_eq!(message(response), "Method not allowed");
+
+        let response = send(&env, Method::DELETE, "/api/config");
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "GET,HEAD");
+    }
+
+    #[test]
+    fn head_requests_follow_the_get_routes() {
+        let (env, _) = env();
+        assert_eq!(
+            send(&env, Method::HEAD, "/api/config").status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&env, Method::HEAD, "/api/no-such-endpoint").status(),
+            StatusCode::NOT_FOUND
+        );
+        let response = send(&env, Method::HEAD, "/identity/accounts/prelogin");
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "POST");
+    }
+}
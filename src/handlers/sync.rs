@@ -1,15 +1,22 @@
-use axum::extract::{Query, State};
+use axum::{extract::State, Extension};
 use std::sync::Arc;
-use worker::Env;
 
+use crate::config::Settings;
+use crate::extract::AppQuery;
+use crate::Env;
 use crate::{
     auth::Claims,
-    db,
-    error::AppError,
+    db::{
+        self,
+        scoped::{self, CipherFilter},
+        Database, Retry,
+    },
+    error::{internal_error, AppError},
     handlers::{
-        attachments, ciphers, ciphers_default_row_query, domains, sync_response_prealloc_bytes,
+        attachments, ciphers, collections, domains, organizations, policies, sends,
         two_factor_enabled,
     },
+    metrics,
     models::{
         folder::{Folder, FolderResponse},
         sync::Profile,
@@ -28,24 +35,41 @@ pub struct SyncQuery {
     pub exclude_domains: bool,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/sync",
+    tag = "sync",
+    responses((status = 200, description = "Success", content_type = "application/json")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_sync_data(
     claims: Claims,
     State(env): State<Arc<Env>>,
-    Query(query): Query<SyncQuery>,
+    Extension(settings): Extension<Arc<Settings>>,
+    retry: Retry,
+    AppQuery(query): AppQuery<SyncQuery>,
 ) -> Result<RawJson, AppError> {
     let user_id = claims.sub;
-    let db = db::get_db(&env)?;
-
-    // Fetch profile
-    let user: User = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+    let db = &db::get_db(&env)?;
+
+    // Every read below is retried on transient D1 errors: a sync that fails makes the clients
+    // show an error, and they retry it anyway.
+    let user: User = retry
+        .run("sync: user", || async {
+            Ok(db
+                .first(
+                    "SELECT * FROM users WHERE id = ?1",
+                    &[user_id.clone().into()],
+                )
+                .await?)
+        })
         .await?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-    let two_factor_enabled = two_factor_enabled(&db, &user_id).await?;
+    let two_factor_enabled = retry
+        .run("sync: two-factor", || two_factor_enabled(db, &user_id))
+        .await?;
 
     let has_master_password = !user.master_password_hash.is_empty();
     let equivalent_domains = user.equivalent_domains.clone();
@@ -70,37 +94,61 @@ pub async fn get_sync_data(
         Value::Null
     };
 
-    // Fetch folders
-    let folders_db: Vec<Folder> = db
-        .prepare("SELECT * FROM folders WHERE user_id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .all()
-        .await?
-        .results()?;
+    let folders_db: Vec<Folder> = retry
+        .run("sync: folders", || async {
+            Ok(scoped::user_folders(db, &user_id).await?)
+        })
+        .await?;
 
     let folders: Vec<FolderResponse> = folders_db.into_iter().map(|f| f.into()).collect();
 
+    let sends = retry
+        .run("sync: sends", || sends::list_send_responses(db, &user_id))
+        .await?;
+    let collections = retry
+        .run("sync: collections", || {
+            collections::list_collection_details(db, &user_id)
+        })
+        .await?;
+    let policies: Vec<Value> = retry
+        .run("sync: policies", || {
+            policies::list_user_policies(db, &user_id)
+        })
+        .await?
+        .iter()
+        .map(|policy| policy.to_json())
+        .collect();
+
     // Fetch ciphers as raw JSON array string (no parsing in Rust!)
     let include_attachments = attachments::attachments_enabled(env.as_ref());
-    let force_row_query = ciphers_default_row_query(env.as_ref());
+    let force_row_query = settings.ciphers_default_row_query;
 
     // Serialize profile and folders (small data, acceptable CPU cost)
     let mut profile = Profile::from_user(user, two_factor_enabled)?;
+    profile.organizations = retry
+        .run("sync: organizations", || {
+            organizations::list_profile_organizations(db, &user_id)
+        })
+        .await?;
     // Match vaultwarden semantics: `_status` is `Invited` when no master password is set.
     // We don't implement org invitations, but this helps clients interpret the account state.
     profile.status = if has_master_password { 0 } else { 1 };
-    let profile_json = serde_json::to_string(&profile).map_err(|_| AppError::Internal)?;
-    let folders_json = serde_json::to_string(&folders).map_err(|_| AppError::Internal)?;
+    let profile_json = serde_json::to_string(&profile).map_err(internal_error!())?;
+    let folders_json = serde_json::to_string(&folders).map_err(internal_error!())?;
+    let sends_json = serde_json::to_string(&sends).map_err(internal_error!())?;
+    let collections_json = serde_json::to_string(&collections).map_err(internal_error!())?;
+    let policies_json = serde_json::to_string(&policies).map_err(internal_error!())?;
 
     // Build response JSON via string concatenation (ciphers already raw JSON)
     let user_decryption_json = serde_json::to_string(&json!({
         "masterPasswordUnlock": master_password_unlock
     }))
-    .map_err(|_| AppError::Internal)?;
+    .map_err(internal_error!())?;
 
     const DEFAULT_SYNC_RESPONSE_PREALLOC_BYTES: usize = 1024 * 1024;
 
-    let capacity = sync_response_prealloc_bytes(env.as_ref())
+    let capacity = settings
+        .sync_response_prealloc_bytes
         .filter(|v| *v > 0)
         .unwrap_or(DEFAULT_SYNC_RESPONSE_PREALLOC_BYTES);
 
@@ -108,11 +156,11 @@ pub async fn get_sync_data(
     // {
     //   "profile": {...},
     //   "folders": [...],
-    //   "collections": [],
-    //   "policies": [],
+    //   "collections": [...],
+    //   "policies": [...],
     //   "ciphers": [...],
     //   "domains": {...} | null, // null when excludeDomains=true
-    //   "sends": [],
+    //   "sends": [...],
     //   "userDecryption": {...},
     //   "object": "sync"
     // }
@@ -123,17 +171,33 @@ pub async fn get_sync_data(
     response.push_str(&profile_json);
     response.push_str(",\"folders\":");
     response.push_str(&folders_json);
-    response.push_str(",\"collections\":[],\"policies\":[],\"ciphers\":");
-    ciphers::append_cipher_json_array_raw(
-        &mut response,
-        &db,
-        include_attachments,
-        "WHERE c.user_id = ?1",
-        &[user_id.clone().into()],
-        "",
-        force_row_query,
-    )
-    .await?;
+    response.push_str(",\"collections\":");
+    response.push_str(&collections_json);
+    response.push_str(",\"policies\":");
+    response.push_str(&policies_json);
+    response.push_str(",\"ciphers\":");
+    // Appended in place to avoid copying the array, so a failed attempt is cut off before the
+    // next one.
+    let ciphers_start = response.len();
+    let mut attempt = 1;
+    loop {
+        let result = ciphers::append_cipher_json_array_raw(
+            &mut response,
+            db,
+            ciphers::CipherJsonFormat::details(include_attachments),
+            &user_id,
+            &CipherFilter::default(),
+            force_row_query,
+        )
+        .await;
+        match result {
+            Err(err) if retry.should_retry("sync: ciphers", attempt, &err).await => {
+                response.truncate(ciphers_start);
+                attempt += 1;
+            }
+            result => break result?,
+        }
+    }
 
     response.push_str(",\"domains\":");
     if query.exclude_domains {
@@ -143,7 +207,7 @@ pub async fn get_sync_data(
         // - mark excluded in /api/settings/domains
         // - filter excluded out of sync payload
         let global_equivalent_domains =
-            domains::global_equivalent_domains_json(&db, &excluded_globals, false).await;
+            domains::global_equivalent_domains_json(db, &excluded_globals, false).await;
         response.push_str("{\"equivalentDomains\":");
         response.push_str(&equivalent_domains);
         response.push_str(",\"globalEquivalentDomains\":");
@@ -151,9 +215,21 @@ pub async fn get_sync_data(
         response.push_str(",\"object\":\"domains\"}");
     }
 
-    response.push_str(",\"sends\":[],\"userDecryption\":");
+    response.push_str(",\"sends\":");
+    response.push_str(&sends_json);
+    response.push_str(",\"userDecryption\":");
     response.push_str(&user_decryption_json);
     response.push_str(",\"object\":\"sync\"}");
 
+    metrics::record(
+        &env,
+        metrics::sync(
+            &user_id,
+            response.len(),
+            folders.len(),
+            collections.len(),
+            sends.len(),
+        ),
+    );
     Ok(RawJson(response))
 }
@@ -1,14 +1,17 @@
-use axum::{extract::State, Json};
+use axum::{extract::State, Extension, Json};
 use serde_json::Value;
 use std::sync::Arc;
-use worker::{query, Env};
 
+use crate::config::Settings;
+use crate::extract::AppJson;
+use crate::Env;
 use crate::{
     auth::AuthUser,
     crypto::{base32_decode, ct_eq, generate_recovery_code, generate_totp_secret, validate_totp},
-    db,
-    error::AppError,
-    handlers::allow_totp_drift,
+    db::{self, Database, Db},
+    error::{db_error, internal_error, AppError},
+    handlers::events::{log_account_event, EventSource},
+    models::event::EventType,
     models::twofactor::{
         DisableAuthenticatorData, DisableTwoFactorData, EnableAuthenticatorData, RecoverTwoFactor,
         TwoFactor, TwoFactorType,
@@ -18,16 +21,15 @@ use crate::{
 
 /// List all 2FA records for a user (includes Remember tokens, excludes atype >= 1000).
 pub(crate) async fn list_user_twofactors(
-    db: &worker::D1Database,
+    db: &Db,
     user_id: &str,
 ) -> Result<Vec<TwoFactor>, AppError> {
-    db.prepare("SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype < 1000")
-        .bind(&[user_id.to_string().into()])?
-        .all()
-        .await
-        .map_err(|_| AppError::Database)?
-        .results::<TwoFactor>()
-        .map_err(|_| AppError::Database)
+    db.all::<TwoFactor>(
+        "SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype < 1000",
+        &[user_id.to_string().into()],
+    )
+    .await
+    .map_err(db_error!())
 }
 
 /// Whether the user has 2FA enabled.
@@ -41,6 +43,13 @@ pub(crate) fn is_twofactor_enabled(twofactors: &[TwoFactor]) -> bool {
 }
 
 /// GET /api/two-factor - Get all enabled 2FA providers for current user
+#[utoipa::path(
+    get,
+    path = "/api/two-factor",
+    tag = "twofactor",
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_twofactor(
     State(env): State<Arc<Env>>,
@@ -59,40 +68,50 @@ pub async fn get_twofactor(
 }
 
 /// POST /api/two-factor/get-authenticator - Get or generate TOTP secret
+#[utoipa::path(
+    post,
+    path = "/api/two-factor/get-authenticator",
+    tag = "twofactor",
+    request_body = PasswordOrOtpData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_authenticator(
     State(env): State<Arc<Env>>,
     AuthUser(user_id, _): AuthUser,
-    Json(data): Json<PasswordOrOtpData>,
+    AppJson(data): AppJson<PasswordOrOtpData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
 
     // Verify master password
     let user_value: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::Unauthorized("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
 
     validate_password_or_otp(&user, &data).await?;
 
     // Check if TOTP is already configured
     let existing: Option<Value> = db
-        .prepare("SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2")
-        .bind(&[
-            user_id.clone().into(),
-            (TwoFactorType::Authenticator as i32).into(),
-        ])?
-        .first(None)
+        .first(
+            "SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2",
+            &[
+                user_id.clone().into(),
+                (TwoFactorType::Authenticator as i32).into(),
+            ],
+        )
         .await
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
 
     let (enabled, key) = match existing {
         Some(tf_value) => {
-            let tf: TwoFactor = serde_json::from_value(tf_value).map_err(|_| AppError::Internal)?;
+            let tf: TwoFactor = serde_json::from_value(tf_value).map_err(internal_error!())?;
             (true, tf.data)
         }
         None => (false, generate_totp_secret()?),
@@ -106,23 +125,34 @@ pub async fn get_authenticator(
 }
 
 /// POST /api/two-factor/authenticator - Activate TOTP
+#[utoipa::path(
+    post,
+    path = "/api/two-factor/authenticator",
+    tag = "twofactor",
+    request_body = EnableAuthenticatorData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn activate_authenticator(
     State(env): State<Arc<Env>>,
+    Extension(settings): Extension<Arc<Settings>>,
     AuthUser(user_id, _): AuthUser,
-    Json(data): Json<EnableAuthenticatorData>,
+    source: EventSource,
+    AppJson(data): AppJson<EnableAuthenticatorData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
 
     // Verify master password
     let user_value: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::Unauthorized("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
 
     validate_password_or_otp(
         &user,
@@ -143,58 +173,58 @@ pub async fn activate_authenticator(
 
     // Check if TOTP is already configured - reuse existing record for replay protection
     let existing: Option<TwoFactor> = db
-        .prepare("SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2")
-        .bind(&[
-            user_id.clone().into(),
-            (TwoFactorType::Authenticator as i32).into(),
-        ])?
-        .first(None)
+        .first(
+            "SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2",
+            &[
+                user_id.clone().into(),
+                (TwoFactorType::Authenticator as i32).into(),
+            ],
+        )
         .await
-        .map_err(|_| AppError::Database)?
-        .map(|value| serde_json::from_value(value).map_err(|_| AppError::Internal))
+        .map_err(db_error!())?
+        .map(|value| serde_json::from_value(value).map_err(internal_error!()))
         .transpose()?;
 
     // Get last_used from existing record to prevent replay during reconfiguration
     let previous_last_used = existing.as_ref().map(|tf| tf.last_used).unwrap_or(0);
 
     // Validate TOTP code and capture time step for replay protection
-    let allow_drift = allow_totp_drift(&env);
+    let allow_drift = settings.allow_totp_drift;
     let last_used_step = validate_totp(&data.token, &key, previous_last_used, allow_drift).await?;
 
     // Delete existing TOTP and any remember-device tokens bound to it to avoid stale bypass
-    query!(
-        &db,
+    db.run(
         "DELETE FROM twofactor WHERE user_uuid = ?1 AND atype IN (?2, ?3)",
-        &user_id,
-        TwoFactorType::Authenticator as i32,
-        TwoFactorType::Remember as i32
+        &[
+            user_id.as_str().into(),
+            (TwoFactorType::Authenticator as i32).into(),
+            (TwoFactorType::Remember as i32).into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
-    .map_err(|_| AppError::Database)?;
+    .map_err(db_error!())?;
 
     // Create new TOTP entry
     let mut twofactor = TwoFactor::new(user_id.clone(), TwoFactorType::Authenticator, key.clone());
     twofactor.last_used = last_used_step;
 
-    query!(
-        &db,
+    db.run(
         "INSERT INTO twofactor (uuid, user_uuid, atype, enabled, data, last_used) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        &twofactor.uuid,
-        &twofactor.user_uuid,
-        twofactor.atype,
-        twofactor.enabled as i32,
-        &twofactor.data,
-        twofactor.last_used
+        &[
+            twofactor.uuid.as_str().into(),
+            twofactor.user_uuid.as_str().into(),
+            twofactor.atype.into(),
+            (twofactor.enabled as i32).into(),
+            twofactor.data.as_str().into(),
+            twofactor.last_used.into(),
+        ],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
-    .map_err(|_| AppError::Database)?;
+    .map_err(db_error!())?;
 
     // Generate recovery code if not exists
     generate_recovery_code_for_user(&db, &user_id).await?;
+    log_account_event(&db, &source, EventType::UserUpdated2fa, &user_id).await;
 
     Ok(Json(serde_json::json!({
         "enabled": true,
@@ -204,33 +234,53 @@ pub async fn activate_authenticator(
 }
 
 /// PUT /api/two-factor/authenticator - Same as POST
+#[utoipa::path(
+    put,
+    path = "/api/two-factor/authenticator",
+    tag = "twofactor",
+    request_body = EnableAuthenticatorData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn activate_authenticator_put(
     state: State<Arc<Env>>,
+    settings: Extension<Arc<Settings>>,
     auth_user: AuthUser,
-    json: Json<EnableAuthenticatorData>,
+    source: EventSource,
+    json: AppJson<EnableAuthenticatorData>,
 ) -> Result<Json<Value>, AppError> {
-    activate_authenticator(state, auth_user, json).await
+    activate_authenticator(state, settings, auth_user, source, json).await
 }
 
 /// POST /api/two-factor/disable - Disable a 2FA method
+#[utoipa::path(
+    post,
+    path = "/api/two-factor/disable",
+    tag = "twofactor",
+    request_body = DisableTwoFactorData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn disable_twofactor(
     State(env): State<Arc<Env>>,
     AuthUser(user_id, _): AuthUser,
-    Json(data): Json<DisableTwoFactorData>,
+    source: EventSource,
+    AppJson(data): AppJson<DisableTwoFactorData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
 
     // Verify master password
     let user_value: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::Unauthorized("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
 
     validate_password_or_otp(
         &user,
@@ -244,18 +294,15 @@ pub async fn disable_twofactor(
     let type_ = data.r#type;
 
     // Delete the specified 2FA type
-    query!(
-        &db,
+    db.run(
         "DELETE FROM twofactor WHERE user_uuid = ?1 AND atype = ?2",
-        &user_id,
-        type_
+        &[user_id.as_str().into(), type_.into()],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
-    .map_err(|_| AppError::Database)?;
+    .map_err(db_error!())?;
 
     log::info!("User {} disabled 2FA type {}", user_id, type_);
+    log_account_event(&db, &source, EventType::UserDisabled2fa, &user_id).await;
 
     clear_recovery_if_no_twofactor(&db, &user_id).await?;
 
@@ -267,11 +314,20 @@ pub async fn disable_twofactor(
 }
 
 /// DELETE /api/two-factor/authenticator - Disable TOTP with key verification
+#[utoipa::path(
+    delete,
+    path = "/api/two-factor/authenticator",
+    tag = "twofactor",
+    request_body = DisableAuthenticatorData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn disable_authenticator(
     State(env): State<Arc<Env>>,
     AuthUser(user_id, _): AuthUser,
-    Json(data): Json<DisableAuthenticatorData>,
+    source: EventSource,
+    AppJson(data): AppJson<DisableAuthenticatorData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
 
@@ -281,13 +337,14 @@ pub async fn disable_authenticator(
 
     // Verify master password (OTP not supported in this minimal implementation)
     let user_value: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::Unauthorized("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
 
     validate_password_or_otp(
         &user,
@@ -300,12 +357,13 @@ pub async fn disable_authenticator(
 
     // Fetch existing TOTP and verify key matches before deleting
     let existing: Option<TwoFactor> = db
-        .prepare("SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2")
-        .bind(&[user_id.clone().into(), data.r#type.into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2",
+            &[user_id.clone().into(), data.r#type.into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
-        .map(|value| serde_json::from_value(value).map_err(|_| AppError::Internal))
+        .map_err(db_error!())?
+        .map(|value| serde_json::from_value(value).map_err(internal_error!()))
         .transpose()?;
 
     let Some(tf) = existing else {
@@ -319,17 +377,19 @@ pub async fn disable_authenticator(
         ));
     }
 
-    query!(&db, "DELETE FROM twofactor WHERE uuid = ?1", &tf.uuid)
-        .map_err(|_| AppError::Database)?
-        .run()
-        .await
-        .map_err(|_| AppError::Database)?;
+    db.run(
+        "DELETE FROM twofactor WHERE uuid = ?1",
+        &[tf.uuid.as_str().into()],
+    )
+    .await
+    .map_err(db_error!())?;
 
     log::info!(
         "User {} disabled authenticator (2FA type {})",
         user_id,
         data.r#type
     );
+    log_account_event(&db, &source, EventType::UserDisabled2fa, &user_id).await;
 
     clear_recovery_if_no_twofactor(&db, &user_id).await?;
 
@@ -341,33 +401,51 @@ pub async fn disable_authenticator(
 }
 
 /// PUT /api/two-factor/disable - Same as POST
+#[utoipa::path(
+    put,
+    path = "/api/two-factor/disable",
+    tag = "twofactor",
+    request_body = DisableTwoFactorData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn disable_twofactor_put(
     state: State<Arc<Env>>,
     auth_user: AuthUser,
-    json: Json<DisableTwoFactorData>,
+    source: EventSource,
+    json: AppJson<DisableTwoFactorData>,
 ) -> Result<Json<Value>, AppError> {
-    disable_twofactor(state, auth_user, json).await
+    disable_twofactor(state, auth_user, source, json).await
 }
 
 /// POST /api/two-factor/get-recover - Get recovery code
+#[utoipa::path(
+    post,
+    path = "/api/two-factor/get-recover",
+    tag = "twofactor",
+    request_body = PasswordOrOtpData,
+    responses((status = 200, description = "Success")),
+    security(("bearerAuth" = []))
+)]
 #[worker::send]
 pub async fn get_recover(
     State(env): State<Arc<Env>>,
     AuthUser(user_id, _): AuthUser,
-    Json(data): Json<PasswordOrOtpData>,
+    AppJson(data): AppJson<PasswordOrOtpData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
 
     // Verify master password
     let user_value: Value = db
-        .prepare("SELECT * FROM users WHERE id = ?1")
-        .bind(&[user_id.clone().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE id = ?1",
+            &[user_id.clone().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::Unauthorized("User not found".to_string()))?;
-    let user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
 
     validate_password_or_otp(&user, &data).await?;
 
@@ -378,22 +456,31 @@ pub async fn get_recover(
 }
 
 /// POST /api/two-factor/recover - Use recovery code to disable all 2FA
+#[utoipa::path(
+    post,
+    path = "/api/two-factor/recover",
+    tag = "twofactor",
+    request_body = RecoverTwoFactor,
+    responses((status = 200, description = "Success"))
+)]
 #[worker::send]
 pub async fn recover(
     State(env): State<Arc<Env>>,
-    Json(data): Json<RecoverTwoFactor>,
+    source: EventSource,
+    AppJson(data): AppJson<RecoverTwoFactor>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
 
     // Get user by email
     let user_value: Value = db
-        .prepare("SELECT * FROM users WHERE email = ?1")
-        .bind(&[data.email.to_lowercase().into()])?
-        .first(None)
+        .first(
+            "SELECT * FROM users WHERE email = ?1",
+            &[data.email.to_lowercase().into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::Unauthorized("Username or password is incorrect".to_string()))?;
-    let user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
+    let user: User = serde_json::from_value(user_value).map_err(internal_error!())?;
 
     // Verify master password
     let verification = user
@@ -420,24 +507,23 @@ pub async fn recover(
     }
 
     // Delete all 2FA methods
-    query!(&db, "DELETE FROM twofactor WHERE user_uuid = ?1", &user.id)
-        .map_err(|_| AppError::Database)?
-        .run()
-        .await
-        .map_err(|_| AppError::Database)?;
+    db.run(
+        "DELETE FROM twofactor WHERE user_uuid = ?1",
+        &[user.id.as_str().into()],
+    )
+    .await
+    .map_err(db_error!())?;
 
     // Clear recovery code
-    query!(
-        &db,
+    db.run(
         "UPDATE users SET totp_recover = NULL WHERE id = ?1",
-        &user.id
+        &[user.id.as_str().into()],
     )
-    .map_err(|_| AppError::Database)?
-    .run()
     .await
-    .map_err(|_| AppError::Database)?;
+    .map_err(db_error!())?;
 
     log::info!("User {} recovered 2FA using recovery code", user.id);
+    log_account_event(&db, &source, EventType::UserRecovered2fa, &user.id).await;
 
     Ok(Json(serde_json::json!({})))
 }
@@ -458,17 +544,15 @@ async fn validate_password_or_otp(user: &User, data: &PasswordOrOtpData) -> Resu
     Err(AppError::Unauthorized("Invalid password".to_string()))
 }
 
-async fn generate_recovery_code_for_user(
-    db: &worker::D1Database,
-    user_id: &str,
-) -> Result<(), AppError> {
+async fn generate_recovery_code_for_user(db: &Db, user_id: &str) -> Result<(), AppError> {
     // Check if recovery code already exists
     let user_value: Value = db
-        .prepare("SELECT totp_recover FROM users WHERE id = ?1")
-        .bind(&[user_id.into()])?
-        .first(None)
+        .first(
+            "SELECT totp_recover FROM users WHERE id = ?1",
+            &[user_id.into()],
+        )
         .await
-        .map_err(|_| AppError::Database)?
+        .map_err(db_error!())?
         .ok_or_else(|| AppError::Unauthorized("User not found".to_string()))?;
 
     let totp_recover: Option<String> = user_value
@@ -478,48 +562,37 @@ async fn generate_recovery_code_for_user(
 
     if totp_recover.is_none() {
         let recovery_code = generate_recovery_code()?;
-        query!(
-            db,
+        db.run(
             "UPDATE users SET totp_recover = ?1 WHERE id = ?2",
-            &recovery_code,
-            user_id
+            &[recovery_code.as_str().into(), user_id.into()],
         )
-        .map_err(|_| AppError::Database)?
-        .run()
         .await
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
     }
 
     Ok(())
 }
 
 /// Clear recovery code when no real 2FA providers remain.
-async fn clear_recovery_if_no_twofactor(
-    db: &worker::D1Database,
-    user_id: &str,
-) -> Result<(), AppError> {
+async fn clear_recovery_if_no_twofactor(db: &Db, user_id: &str) -> Result<(), AppError> {
     let remaining: Vec<TwoFactor> = db
-        .prepare("SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype < 1000 AND atype != ?2")
-        .bind(&[
-            user_id.to_string().into(),
-            (TwoFactorType::Remember as i32).into(),
-        ])?
-        .all()
+        .all(
+            "SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype < 1000 AND atype != ?2",
+            &[
+                user_id.to_string().into(),
+                (TwoFactorType::Remember as i32).into(),
+            ],
+        )
         .await
-        .map_err(|_| AppError::Database)?
-        .results()
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
 
     if remaining.is_empty() {
-        query!(
-            db,
+        db.run(
             "UPDATE users SET totp_recover = NULL WHERE id = ?1",
-            user_id
+            &[user_id.into()],
         )
-        .map_err(|_| AppError::Database)?
-        .run()
         .await
-        .map_err(|_| AppError::Database)?;
+        .map_err(db_error!())?;
     }
 
     Ok(())
@@ -0,0 +1,492 @@
+//! Migration of accounts and personal vaults from a Vaultwarden server.
+//!
+//! Vaultwarden implements the same protocol, so the client-side encryption carries over as it is:
+//! keys, encrypted fields and even the server-side password hash (PBKDF2-SHA256 of the client's
+//! master password hash, like ours) are copied, and users log in with their usual master
+//! password. Organizations, attachments, Sends, devices and two-step login are not migrated.
+//!
+//! Extract the dump from Vaultwarden's SQLite database (`data/db.sqlite3`) with:
+//!
+//! ```sql
+//! SELECT json_object(
+//!   'users', json((SELECT json_group_array(json_object(
+//!     'uuid', uuid, 'email', email, 'name', name,
+//!     'password_hash', hex(password_hash), 'salt', hex(salt),
+//!     'password_iterations', password_iterations, 'password_hint', password_hint,
+//!     'akey', akey, 'private_key', private_key, 'public_key', public_key,
+//!     'security_stamp', security_stamp, 'equivalent_domains', equivalent_domains,
+//!     'excluded_globals', excluded_globals, 'client_kdf_type', client_kdf_type,
+//!     'client_kdf_iter', client_kdf_iter, 'client_kdf_memory', client_kdf_memory,
+//!     'client_kdf_parallelism', client_kdf_parallelism, 'avatar_color', avatar_color,
+//!     'verified_at', verified_at, 'created_at', created_at, 'updated_at', updated_at))
+//!     FROM users)),
+//!   'folders', json((SELECT json_group_array(json_object(
+//!     'uuid', uuid, 'user_uuid', user_uuid, 'name', name,
+//!     'created_at', created_at, 'updated_at', updated_at)) FROM folders)),
+//!   'ciphers', json((SELECT json_group_array(json_object(
+//!     'uuid', uuid, 'user_uuid', user_uuid, 'organization_uuid', organization_uuid,
+//!     'key', key, 'atype', atype, 'name', name, 'notes', notes, 'fields', fields,
+//!     'data', data, 'password_history', password_history, 'reprompt', reprompt,
+//!     'deleted_at', deleted_at, 'created_at', created_at, 'updated_at', updated_at))
+//!     FROM ciphers)),
+//!   'folders_ciphers', json((SELECT json_group_array(json_object(
+//!     'cipher_uuid', cipher_uuid, 'folder_uuid', folder_uuid)) FROM folders_ciphers)),
+//!   'favorites', json((SELECT json_group_array(json_object(
+//!     'user_uuid', user_uuid, 'cipher_uuid', cipher_uuid)) FROM favorites))
+//! );
+//! ```
+//!
+//! for example `sqlite3 data/db.sqlite3 < extract.sql > vaultwarden.json`. The blobs
+//! (`password_hash`, `salt`) come out as hex.
+//!
+//! Vaultwarden keeps `atype` where we have `type`, favorites and folder membership in join tables
+//! instead of columns, naive UTC datetimes (`2024-01-02 03:04:05.123456`) and, for items saved
+//! by older versions, PascalCase keys inside the encrypted item data; [`map_cipher`] turns all of
+//! it into our layout. Running the migration again skips what is already there.
+
+use axum::{extract::State, Json};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::Env;
+use crate::{
+    auth::AdminAuth,
+    db::{self, Database, Db, Statement},
+    error::{db_error, AppError},
+    extract::AppJson,
+    models::vaultwarden::{
+        RowFailure, TableSummary, VaultwardenCipher, VaultwardenDump, VaultwardenFolder,
+        VaultwardenMigrationSummary, VaultwardenUser,
+    },
+    quota, time,
+};
+
+/// Inserts per D1 batch.
+const MIGRATION_BATCH_SIZE: usize = 100;
+
+/// A row mapped to our schema, waiting for its insert.
+struct PendingRow {
+    id: String,
+    statement: Statement,
+}
+
+/// POST /admin/migrate/vaultwarden
+///
+/// Copies users, folders and personal ciphers from a Vaultwarden dump (see the module
+/// documentation for the extraction query). Returns what happened per table, and why each
+/// failed row was left out; the rest is migrated regardless.
+#[utoipa::path(
+    post,
+    path = "/admin/migrate/vaultwarden",
+    tag = "vaultwarden",
+    request_body = VaultwardenDump,
+    responses((status = 200, description = "Success", body = VaultwardenMigrationSummary)),
+    security(("adminToken" = []))
+)]
+#[worker::send]
+pub async fn post_migrate_vaultwarden(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    AppJson(dump): AppJson<VaultwardenDump>,
+) -> Result<Json<VaultwardenMigrationSummary>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut summary = VaultwardenMigrationSummary::default();
+
+    // Users
+    let mut pending = Vec::new();
+    for user in &dump.users {
+        match map_user(user) {
+            Ok(params) => pending.push(PendingRow {
+                id: user.uuid.clone(),
+                statement: insert_statement(&db, "users", &params)?,
+            }),
+            Err(reason) => summary.failures.push(failure("users", &user.uuid, reason)),
+        }
+    }
+    let inserted = run_inserts(&db, pending).await?;
+    let user_ids: Vec<&str> = dump.users.iter().map(|user| user.uuid.as_str()).collect();
+    let owners: HashSet<String> = existing_ids(&db, "users", &user_ids)
+        .await?
+        .into_keys()
+        .collect();
+    for (id, was_inserted) in inserted {
+        if was_inserted {
+            summary.users.inserted += 1;
+        } else if owners.contains(&id) {
+            summary.users.skipped += 1;
+        } else {
+            summary.failures.push(failure(
+                "users",
+                &id,
+                "The email address already belongs to another account".to_string(),
+            ));
+        }
+    }
+
+    // Folders
+    let mut pending = Vec::new();
+    for folder in &dump.folders {
+        if !owners.contains(&folder.user_uuid) {
+            summary.failures.push(failure(
+                "folders",
+                &folder.uuid,
+                "Its owner wasn't migrated".to_string(),
+            ));
+            continue;
+        }
+        match map_folder(folder) {
+            Ok(params) => pending.push(PendingRow {
+                id: folder.uuid.clone(),
+                statement: insert_statement(&db, "folders", &params)?,
+            }),
+            Err(reason) => summary
+                .failures
+                .push(failure("folders", &folder.uuid, reason)),
+        }
+    }
+    let inserted = run_inserts(&db, pending).await?;
+    let folder_ids: Vec<&str> = dump
+        .folders
+        .iter()
+        .map(|folder| folder.uuid.as_str())
+        .collect();
+    // Folders in the database, with their owner
+    let folder_owners = existing_ids(&db, "folders", &folder_ids).await?;
+    let dump_folder_owners: HashMap<&str, &str> = dump
+        .folders
+        .iter()
+        .map(|folder| (folder.uuid.as_str(), folder.user_uuid.as_str()))
+        .collect();
+    for (id, was_inserted) in inserted {
+        if was_inserted {
+            summary.folders.inserted += 1;
+        } else if folder_owners.get(&id).map(String::as_str)
+            == dump_folder_owners.get(id.as_str()).copied()
+        {
+            summary.folders.skipped += 1;
+        } else {
+            summary.failures.push(failure(
+                "folders",
+                &id,
+                "The id is already used by another user's folder".to_string(),
+            ));
+        }
+    }
+
+    // Ciphers
+    let favorites: HashSet<(&str, &str)> = dump
+        .favorites
+        .iter()
+        .map(|favorite| (favorite.user_uuid.as_str(), favorite.cipher_uuid.as_str()))
+        .collect();
+    let cipher_folders: HashMap<&str, &str> = dump
+        .folders_ciphers
+        .iter()
+        .map(|link| (link.cipher_uuid.as_str(), link.folder_uuid.as_str()))
+        .collect();
+    let mut pending = Vec::new();
+    for cipher in &dump.ciphers {
+        let owner = match (&cipher.user_uuid, &cipher.organization_uuid) {
+            (_, Some(_)) => Err("Organization items aren't migrated"),
+            (None, None) => Err("The item has no owner"),
+            (Some(owner), None) if !owners.contains(owner) => Err("Its owner wasn't migrated"),
+            (Some(owner), None) => Ok(owner.as_str()),
+        };
+        let owner = match owner {
+            Ok(owner) => owner,
+            Err(reason) => {
+                summary
+                    .failures
+                    .push(failure("ciphers", &cipher.uuid, reason.to_string()));
+                continue;
+            }
+        };
+        // Only a folder of the same user that made it into the database
+        let folder_id = cipher_folders
+            .get(cipher.uuid.as_str())
+            .filter(|folder_id| folder_owners.get(**folder_id).map(String::as_str) == Some(owner))
+            .copied();
+        let favorite = favorites.contains(&(owner, cipher.uuid.as_str()));
+        match map_cipher(cipher, folder_id, favorite) {
+            Ok(params) => pending.push(PendingRow {
+                id: cipher.uuid.clone(),
+                statement: insert_statement(&db, "ciphers", &params)?,
+            }),
+            Err(reason) => summary
+                .failures
+                .push(failure("ciphers", &cipher.uuid, reason)),
+        }
+    }
+    for (_, was_inserted) in run_inserts(&db, pending).await? {
+        if was_inserted {
+            summary.ciphers.inserted += 1;
+        } else {
+            summary.ciphers.skipped += 1;
+        }
+    }
+
+    for failed in &summary.failures {
+        let table: &mut TableSummary = match failed.table {
+            "users" => &mut summary.users,
+            "folders" => &mut summary.folders,
+            _ => &mut summary.ciphers,
+        };
+        table.failed += 1;
+    }
+
+    // Counters for the quotas, and a sync for users who already had clients connected
+    if !owners.is_empty() {
+        let owners: Vec<&str> = owners.iter().map(String::as_str).collect();
+        Database::batch(
+            &db,
+            vec![
+                quota::recount(&db, &owners)?,
+                Database::prepare(
+                    &db,
+                    "UPDATE users SET updated_at = ?1 WHERE id IN (SELECT value FROM json_each(?2))",
+                    &[time::now_bw().into(), json!(owners)],
+                )
+                .map_err(db_error!())?,
+            ],
+        )
+        .await
+        .map_err(db_error!())?;
+    }
+
+    log::info!(
+        "Vaultwarden migration: {} user(s), {} folder(s), {} cipher(s) inserted, {} row(s) failed",
+        summary.users.inserted,
+        summary.folders.inserted,
+        summary.ciphers.inserted,
+        summary.failures.len()
+    );
+    Ok(Json(summary))
+}
+
+fn failure(table: &'static str, id: &str, reason: String) -> RowFailure {
+    RowFailure {
+        table,
+        id: id.to_string(),
+        reason,
+    }
+}
+
+/// `INSERT ... ON CONFLICT DO NOTHING` of the columns and values of `row`.
+fn insert_statement(db: &Db, table: &str, row: &Map<String, Value>) -> Result<Statement, AppError> {
+    let columns: Vec<&str> = row.keys().map(String::as_str).collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+    let params: Vec<Value> = row.values().cloned().collect();
+    Database::prepare(
+        db,
+        &format!(
+            "INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT DO NOTHING",
+            columns.join(", "),
+            placeholders.join(", ")
+        ),
+        &params,
+    )
+    .map_err(db_error!())
+}
+
+/// Runs the inserts in batches and returns, per row, whether it was inserted.
+async fn run_inserts(db: &Db, rows: Vec<PendingRow>) -> Result<Vec<(String, bool)>, AppError> {
+    let mut inserted = Vec::with_capacity(rows.len());
+    for chunk in rows.chunks(MIGRATION_BATCH_SIZE) {
+        let statements = chunk.iter().map(|row| row.statement.clone()).collect();
+        let changes = Database::batch(db, statements).await.map_err(db_error!())?;
+        inserted.extend(
+            chunk
+                .iter()
+                .zip(changes)
+                .map(|(row, changes)| (row.id.clone(), changes > 0)),
+        );
+    }
+    Ok(inserted)
+}
+
+/// Which of `ids` are in `table`, with their `user_id` (empty for `users`).
+async fn existing_ids(
+    db: &Db,
+    table: &str,
+    ids: &[&str],
+) -> Result<HashMap<String, String>, AppError> {
+    let owner = if table == "users" { "''" } else { "user_id" };
+    let mut existing = HashMap::new();
+    for chunk in ids.chunks(MIGRATION_BATCH_SIZE) {
+        let rows: Vec<Value> = db
+            .all(
+                &format!("SELECT id, {owner} AS owner FROM {table} WHERE id IN (SELECT value FROM json_each(?1))"),
+                &[json!(chunk)],
+            )
+            .await
+            .map_err(db_error!())?;
+        existing.extend(rows.into_iter().filter_map(|row| {
+            Some((
+                row["id"].as_str()?.to_string(),
+                row["owner"].as_str().unwrap_or_default().to_string(),
+            ))
+        }));
+    }
+    Ok(existing)
+}
+
+/// A Vaultwarden datetime (naive UTC) in our format.
+fn vaultwarden_date(field: &str, value: &str) -> Result<String, String> {
+    time::parse_bw(value)
+        .map(time::format_bw)
+        .ok_or_else(|| format!("Invalid {field}: {value}"))
+}
+
+/// A hex-encoded blob, base64-encoded as we store password hashes and salts.
+fn hex_to_base64(field: &str, value: &str) -> Result<String, String> {
+    hex::decode(value)
+        .map(|bytes| BASE64.encode(bytes))
+        .map_err(|_| format!("Invalid {field}: expected hex"))
+}
+
+fn map_user(user: &VaultwardenUser) -> Result<Map<String, Value>, String> {
+    let (Some(private_key), Some(public_key)) = (&user.private_key, &user.public_key) else {
+        return Err("The account was never set up (no key pair)".to_string());
+    };
+    let email = user.email.trim().to_lowercase();
+    if !email.contains('@') {
+        return Err(format!("Invalid email: {}", user.email));
+    }
+
+    let row = json!({
+        "id": user.uuid,
+        "name": user.name,
+        "avatar_color": user.avatar_color,
+        "email": email,
+        "email_verified": user.verified_at.is_some(),
+        "master_password_hash": hex_to_base64("password_hash", &user.password_hash)?,
+        "master_password_hint": user.password_hint,
+        "password_salt": hex_to_base64("salt", &user.salt)?,
+        "password_iterations": user.password_iterations,
+        "key": user.akey,
+        "private_key": private_key,
+        "public_key": public_key,
+        "kdf_type": user.client_kdf_type,
+        "kdf_iterations": user.client_kdf_iter,
+        "kdf_memory": user.client_kdf_memory,
+        "kdf_parallelism": user.client_kdf_parallelism,
+        "security_stamp": user.security_stamp,
+        "equivalent_domains": user.equivalent_domains.as_deref().unwrap_or("[]"),
+        "excluded_globals": user.excluded_globals.as_deref().unwrap_or("[]"),
+        "created_at": vaultwarden_date("created_at", &user.created_at)?,
+        "updated_at": vaultwarden_date("updated_at", &user.updated_at)?,
+    });
+    Ok(into_map(row))
+}
+
+fn map_folder(folder: &VaultwardenFolder) -> Result<Map<String, Value>, String> {
+    let row = json!({
+        "id": folder.uuid,
+        "user_id": folder.user_uuid,
+        "name": folder.name,
+        "created_at": vaultwarden_date("created_at", &folder.created_at)?,
+        "updated_at": vaultwarden_date("updated_at", &folder.updated_at)?,
+    });
+    Ok(into_map(row))
+}
+
+/// Our `ciphers` row for a personal Vaultwarden cipher.
+fn map_cipher(
+    cipher: &VaultwardenCipher,
+    folder_id: Option<&str>,
+    favorite: bool,
+) -> Result<Map<String, Value>, String> {
+    if cipher.key.is_some() {
+        return Err("Items with their own encryption key aren't supported".to_string());
+    }
+    let type_key = match cipher.atype {
+        1 => "login",
+        2 => "secureNote",
+        3 => "card",
+        4 => "identity",
+        5 => "sshKey",
+        atype => return Err(format!("Unknown item type {atype}")),
+    };
+    let type_data: Value =
+        serde_json::from_str(&cipher.data).map_err(|err| format!("Invalid data JSON: {err}"))?;
+    let parse_column = |field: &str, value: &Option<String>| -> Result<Value, String> {
+        match value.as_deref() {
+            None | Some("") => Ok(Value::Null),
+            Some(value) => serde_json::from_str(value)
+                .map(camel_case_keys)
+                .map_err(|err| format!("Invalid {field} JSON: {err}")),
+        }
+    };
+
+    let mut data = Map::new();
+    data.insert("name".to_string(), json!(cipher.name));
+    if let Some(notes) = &cipher.notes {
+        data.insert("notes".to_string(), json!(notes));
+    }
+    data.insert(type_key.to_string(), camel_case_keys(type_data));
+    for (key, value) in [
+        ("fields", parse_column("fields", &cipher.fields)?),
+        (
+            "passwordHistory",
+            parse_column("password_history", &cipher.password_history)?,
+        ),
+    ] {
+        if !value.is_null() {
+            data.insert(key.to_string(), value);
+        }
+    }
+    data.insert("reprompt".to_string(), json!(cipher.reprompt.unwrap_or(0)));
+
+    let deleted_at = cipher
+        .deleted_at
+        .as_deref()
+        .map(|value| vaultwarden_date("deleted_at", value))
+        .transpose()?;
+    let row = json!({
+        "id": cipher.uuid,
+        "user_id": cipher.user_uuid,
+        "organization_id": null,
+        "type": cipher.atype,
+        "data": Value::Object(data).to_string(),
+        "favorite": favorite,
+        "folder_id": folder_id,
+        "deleted_at": deleted_at,
+        "created_at": vaultwarden_date("created_at", &cipher.created_at)?,
+        "updated_at": vaultwarden_date("updated_at", &cipher.updated_at)?,
+    });
+    Ok(into_map(row))
+}
+
+/// Lowercases the first letter of every object key, recursively: older Vaultwarden versions
+/// stored the PascalCase JSON of the clients of the time (`Uris`, `Match`), and it serves such
+/// data the same way.
+fn camel_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| {
+                    let first = key.chars().next();
+                    let key = match first {
+                        Some(first) if first.is_uppercase() => {
+                            first.to_lowercase().chain(key.chars().skip(1)).collect()
+                        }
+                        _ => key,
+                    };
+                    (key, camel_case_keys(value))
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(camel_case_keys).collect()),
+        value => value,
+    }
+}
+
+fn into_map(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
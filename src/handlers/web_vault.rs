@@ -0,0 +1,135 @@
+//! Web vault: serves the bundled web vault build (`public/web-vault`) from the Worker's static
+//! assets binding, for every path the API doesn't handle.
+//!
+//! The assets binding picks content types and ETags; this adds caching suited to the build's
+//! file names and falls back to `index.html` for client-side routes.
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{
+        header::{CACHE_CONTROL, IF_MODIFIED_SINCE, IF_NONE_MATCH},
+        HeaderValue, Method, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use worker::{Headers, RequestInit};
+
+use crate::config::Settings;
+use crate::Env;
+use crate::{error::AppError, handlers::stubs, BaseUrl};
+
+/// Hashed files never change, so browsers may keep them for good.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Pages have to be revalidated so a new build's hashed files get picked up.
+const REVALIDATE: &str = "no-cache";
+
+/// Whether the file name carries a content hash, as in `main.3f9a1c0b2d4e6f8a.js`.
+fn is_hashed(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let mut parts: Vec<&str> = name.split('.').collect();
+    parts.pop(); // extension
+    parts
+        .iter()
+        .skip(1)
+        .any(|part| part.len() >= 8 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Paths without an extension are client-side routes of the single-page app.
+fn is_route(path: &str) -> bool {
+    !path.rsplit('/').next().unwrap_or_default().contains('.')
+}
+
+async fn fetch_asset(
+    env: &Env,
+    base_url: &str,
+    path: &str,
+    method: &Method,
+    conditional: &[(&str, Option<&HeaderValue>)],
+) -> Result<axum::http::Response<worker::Body>, AppError> {
+    let assets = env
+        .assets("ASSETS")
+        .map_err(|_| AppError::NotFound("Not found.".to_string()))?;
+    let headers = Headers::new();
+    for (name, value) in conditional {
+        if let Some(value) = value.and_then(|value| value.to_str().ok()) {
+            headers.set(name, value)?;
+        }
+    }
+    let mut init = RequestInit::new();
+    init.with_method(if method == Method::HEAD {
+        worker::Method::Head
+    } else {
+        worker::Method::Get
+    })
+    .with_headers(headers);
+    Ok(assets
+        .fetch(format!("{base_url}{path}"), Some(init))
+        .await?)
+}
+
+/// Fallback for every route the router doesn't know.
+///
+/// Unknown API paths stay 404s ([`stubs::not_found`]). Anything else is looked up in the web vault build; client-side
+/// routes get `index.html`.
+#[worker::send]
+pub async fn serve(State(env): State<Arc<Env>>, request: Request) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound("Not found.".to_string());
+    let path = request.uri().path().to_string();
+    let is_api = ["/api", "/identity", "/admin", "/notifications", "/events"]
+        .iter()
+        .any(|prefix| path == *prefix || path.starts_with(&format!("{prefix}/")));
+    if is_api {
+        return Err(stubs::not_found(&path));
+    }
+    let method = request.method().clone();
+    let settings = Settings::get(&env);
+    if !settings.web_vault_enabled || (method != Method::GET && method != Method::HEAD) {
+        return Err(not_found());
+    }
+
+    // The request path has BASE_PATH stripped already, and the assets are at the root
+    let base_url = request
+        .extensions()
+        .get::<BaseUrl>()
+        .map(|BaseUrl(base_url)| {
+            base_url
+                .strip_suffix(settings.base_path.as_str())
+                .unwrap_or(base_url)
+                .to_string()
+        })
+        .unwrap_or_default();
+    let conditional = [
+        ("If-None-Match", request.headers().get(IF_NONE_MATCH)),
+        (
+            "If-Modified-Since",
+            request.headers().get(IF_MODIFIED_SINCE),
+        ),
+    ];
+    let mut response = fetch_asset(&env, &base_url, &path, &method, &conditional).await?;
+    let mut served_path = path.as_str();
+    if response.status() == StatusCode::NOT_FOUND && is_route(&path) {
+        served_path = "/";
+        response = fetch_asset(&env, &base_url, served_path, &method, &conditional).await?;
+    }
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(not_found());
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // Other files keep the binding's default: cached, but revalidated
+    let cache_control = if is_hashed(served_path) {
+        Some(IMMUTABLE)
+    } else if is_route(served_path) || served_path.ends_with(".html") {
+        Some(REVALIDATE)
+    } else {
+        None
+    };
+    if let Some(cache_control) = cache_control {
+        parts
+            .headers
+            .insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    }
+    Ok(Response::from_parts(parts, Body::new(body)).into_response())
+}
@@ -6,6 +6,12 @@ use serde_json::{json, Value};
 /// Returns an empty list of WebAuthn credentials.
 /// This prevents 404 errors and key-rotation issues when passkey login is enabled.
 /// Vaultwarden does not yet support passkey login, so we return an empty list.
+#[utoipa::path(
+    get,
+    path = "/api/webauthn",
+    tag = "webauth",
+    responses((status = 200, description = "Success"))
+)]
 #[worker::send]
 pub async fn get_webauthn_credentials() -> Json<Value> {
     Json(json!({
@@ -1,23 +1,58 @@
+#[cfg(not(feature = "native-db"))]
 use std::sync::Arc;
 
-use axum::{extract::DefaultBodyLimit, Extension};
-use tower_http::cors::{Any, CorsLayer};
+#[cfg(not(feature = "native-db"))]
+use axum::Extension;
+#[cfg(not(feature = "native-db"))]
 use tower_service::Service;
+#[cfg(not(feature = "native-db"))]
 use worker::*;
 
 mod auth;
+mod config;
 mod crypto;
 mod db;
 mod durable;
 mod error;
+mod extract;
+mod global_domains;
 mod handlers;
+mod logging;
+mod mail;
+mod metrics;
+mod migrations;
 mod models;
+#[cfg(feature = "native-db")]
+pub mod native;
+mod notify;
+mod push;
+mod quota;
 mod router;
+mod time;
+
+/// The Worker's bindings; natively, the stand-in from [`native`].
+#[cfg(feature = "native-db")]
+pub use native::Env;
+#[cfg(not(feature = "native-db"))]
+pub(crate) use worker::Env;
 
 /// Base URL extracted from the incoming request, used for config endpoint.
 #[derive(Clone)]
 pub struct BaseUrl(pub String);
 
+impl BaseUrl {
+    /// The origin `uri` was sent to, followed by the BASE_PATH the routes are mounted under.
+    pub fn new(uri: &axum::http::Uri, settings: &config::Settings) -> Self {
+        BaseUrl(format!(
+            "{}://{}{}",
+            uri.scheme_str().unwrap_or("https"),
+            uri.authority().map(|a| a.as_str()).unwrap_or("localhost"),
+            settings.base_path
+        ))
+    }
+}
+
+#[cfg(not(feature = "native-db"))]
 #[event(fetch)]
 pub async fn main(
     req: HttpRequest,
@@ -25,33 +60,21 @@ pub async fn main(
     _ctx: Context,
 ) -> Result<axum::http::Response<axum::body::Body>> {
     // Set up logging
-    console_error_panic_hook::set_once();
+    logging::set_panic_hook();
     let _ = console_log::init_with_level(log::Level::Debug);
+    logging::apply_log_level(&env);
 
     // Extract base URL from the incoming request
-    let uri = req.uri().clone();
-    let base_url = format!(
-        "{}://{}",
-        uri.scheme_str().unwrap_or("https"),
-        uri.authority().map(|a| a.as_str()).unwrap_or("localhost")
-    );
-
-    let env = Arc::new(env);
+    let base_url = BaseUrl::new(req.uri(), &config::Settings::get(&env));
 
-    // Allow all origins for CORS, which is typical for a public API like Bitwarden's.
-    let cors = CorsLayer::new()
-        .allow_methods(Any)
-        .allow_headers(Any)
-        .allow_origin(Any);
+    migrations::run_on_first_request(&env).await;
 
-    // Attachment uploads/downloads are handled in entry.js for zero-copy streaming,
-    // so we can use a conservative body limit here (5MB) for regular API requests.
-    const BODY_LIMIT: usize = 5 * 1024 * 1024;
+    let env = Arc::new(env);
 
-    let mut app = router::api_router((*env).clone())
-        .layer(Extension(BaseUrl(base_url)))
-        .layer(cors)
-        .layer(DefaultBodyLimit::max(BODY_LIMIT));
+    let mut app = router::with_cors(
+        router::api_router((*env).clone()).layer(Extension(base_url)),
+        &env,
+    );
 
     Ok(app.call(req).await?)
 }
@@ -60,34 +83,19 @@ pub async fn main(
 ///
 /// This handler is triggered by Cloudflare's cron triggers configured in wrangler.toml.
 /// It performs automatic cleanup of soft-deleted ciphers that have exceeded the
-/// retention period (default: 30 days, configurable via TRASH_AUTO_DELETE_DAYS env var).
+/// retention period (default: 30 days, configurable via TRASH_AUTO_DELETE_DAYS env var),
+/// of sends past their deletion date, and of stale pending attachments and auth requests, and
+/// repairs orphaned data. It also approves emergency access requests whose wait time has passed,
+/// and writes a backup to R2 when `BACKUP_BUCKET` is bound.
+#[cfg(not(feature = "native-db"))]
 #[event(scheduled)]
 pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
     // Set up logging
-    console_error_panic_hook::set_once();
+    logging::set_panic_hook();
     let _ = console_log::init_with_level(log::Level::Debug);
+    logging::apply_log_level(&env);
 
-    log::info!("Scheduled task triggered: purging stale pending attachments");
-    match handlers::purge::purge_stale_pending_attachments(&env).await {
-        Ok(count) => {
-            log::info!(
-                "Pending attachment purge completed: {} record(s) removed",
-                count
-            );
-        }
-        Err(e) => {
-            log::error!("Pending attachment purge failed: {:?}", e);
-        }
-    }
-
-    log::info!("Scheduled task triggered: purging soft-deleted ciphers");
-
-    match handlers::purge::purge_deleted_ciphers(&env).await {
-        Ok(count) => {
-            log::info!("Scheduled purge completed: {} cipher(s) removed", count);
-        }
-        Err(e) => {
-            log::error!("Scheduled purge failed: {:?}", e);
-        }
-    }
+    log::info!("Scheduled task triggered: running maintenance");
+    handlers::purge::run_maintenance(&env).await;
+    handlers::backup::run_scheduled_backup(&env).await;
 }
@@ -0,0 +1,261 @@
+//! Per-request correlation and access logging.
+//!
+//! Every request gets an id (Cloudflare's `cf-ray` when present), returned in `x-request-id` and
+//! attached to the one JSON line logged per request and to the details of server errors. Only the
+//! method, the matched route's path template (`/api/ciphers/{id}`, never the path itself, whose ids
+//! and emails say who the request was about), status and duration are logged; never the user,
+//! bodies or headers.
+//!
+//! Panics are logged the same way, with the id of the request whose handler panicked. They can't
+//! be caught on `wasm32` (panics abort), so `entry.js` answers the failed call with a 500.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::config::Settings;
+use crate::metrics;
+use crate::Env;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+thread_local! {
+    /// Id of the request being handled right now, for the panic hook.
+    static CURRENT_REQUEST: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// Set as a request extension by [`request_log`]; the auth extractor records the user in it, for
+/// the metrics to count users by.
+#[derive(Clone)]
+pub struct RequestContext {
+    id: Arc<str>,
+    user_id: Arc<Mutex<Option<String>>>,
+    route: Arc<Mutex<Option<String>>>,
+}
+
+impl RequestContext {
+    /// The request id returned in `x-request-id`.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn set_user_id(&self, user_id: &str) {
+        if let Ok(mut slot) = self.user_id.lock() {
+            *slot = Some(user_id.to_string());
+        }
+    }
+
+    fn user_id(&self) -> Option<String> {
+        self.user_id.lock().ok().and_then(|slot| slot.clone())
+    }
+
+    fn route(&self) -> Option<String> {
+        self.route.lock().ok().and_then(|slot| slot.clone())
+    }
+}
+
+/// What caused a server error, set as a response extension by `AppError` so it is logged once,
+/// next to the request id, instead of being sent to the client.
+#[derive(Clone)]
+pub struct ErrorDetail(pub String);
+
+/// Applies LOG_LEVEL (`debug`, `info`, `warn`, `error` or `off`; default `info`). Request lines
+/// are logged at `info`, so `warn` silences them while keeping problems visible.
+pub fn apply_log_level(env: &Env) {
+    log::set_max_level(Settings::get(env).log_level);
+}
+
+/// Installs the panic hook (once per isolate): `console_error_panic_hook`'s message and stack,
+/// then a JSON line with the panic and the request id.
+#[cfg(not(feature = "native-db"))]
+pub fn set_panic_hook() {
+    static SET_HOOK: std::sync::Once = std::sync::Once::new();
+    SET_HOOK.call_once(|| {
+        std::panic::set_hook(Box::new(|info| {
+            console_error_panic_hook::hook(info);
+            let request_id = CURRENT_REQUEST.with(|current| current.borrow().clone());
+            worker::console_error!(
+                "{}",
+                json!({
+                    "requestId": request_id.as_deref(),
+                    "panic": info.payload_as_str().unwrap_or("unknown payload"),
+                    "location": info.location().map(ToString::to_string),
+                })
+            );
+        }));
+    });
+}
+
+/// Marks `request_id` as the current request while `inner` is polled. Requests interleave on an
+/// isolate, so it is set for each poll rather than once.
+struct InRequest<F> {
+    request_id: Arc<str>,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for InRequest<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let previous = CURRENT_REQUEST.replace(Some(self.request_id.clone()));
+        let result = self.inner.as_mut().poll(cx);
+        CURRENT_REQUEST.set(previous);
+        result
+    }
+}
+
+/// Route layer recording the matched route's path template for [`request_log`], which runs
+/// before routing.
+pub async fn record_route(req: Request, next: Next) -> Response {
+    if let (Some(context), Some(route)) = (
+        req.extensions().get::<RequestContext>(),
+        req.extensions().get::<MatchedPath>(),
+    ) {
+        if let Ok(mut slot) = context.route.lock() {
+            *slot = Some(route.as_str().to_string());
+        }
+    }
+    next.run(req).await
+}
+
+/// The line logged for each request.
+fn request_line(id: &str, method: &str, route: &str, status: u16, duration_ms: i64) -> String {
+    json!({
+        "requestId": id,
+        "method": method,
+        "route": route,
+        "status": status,
+        "durationMs": duration_ms,
+    })
+    .to_string()
+}
+
+pub async fn request_log(State(env): State<Arc<Env>>, mut req: Request, next: Next) -> Response {
+    let started = Utc::now();
+    let id = req
+        .headers()
+        .get("cf-ray")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let context = RequestContext {
+        id: Arc::from(id.as_str()),
+        user_id: Arc::default(),
+        route: Arc::default(),
+    };
+    req.extensions_mut().insert(context.clone());
+    let method = req.method().to_string();
+
+    let mut response = InRequest {
+        request_id: context.id.clone(),
+        inner: Box::pin(next.run(req)),
+    }
+    .await;
+
+    let route = context.route();
+    let route = route.as_deref().unwrap_or(metrics::UNMATCHED_ROUTE);
+    let duration_ms = (Utc::now() - started).num_milliseconds();
+    if let Some(ErrorDetail(detail)) = response.extensions_mut().remove::<ErrorDetail>() {
+        worker::console_error!(
+            "{}",
+            json!({ "requestId": id, "method": method, "route": route, "error": detail })
+        );
+    }
+    log::info!(
+        "{}",
+        request_line(&id, &method, route, response.status().as_u16(), duration_ms)
+    );
+    metrics::record(
+        &env,
+        metrics::request(
+            &method,
+            route,
+            context.user_id().as_deref(),
+            response.status().as_u16(),
+            duration_ms,
+        ),
+    );
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::block_on;
+    use axum::{body::Body, routing::get, Extension, Router};
+    use tower_service::Service;
+
+    fn context() -> RequestContext {
+        RequestContext {
+            id: Arc::from("request"),
+            user_id: Arc::default(),
+            route: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn records_the_route_template_not_the_path() {
+        let context = context();
+        let mut app = Router::new()
+            .route("/api/ciphers/{id}", get(|| async { "" }))
+            .route_layer(axum::middleware::from_fn(record_route))
+            .layer(Extension(context.clone()));
+
+        let req = Request::builder()
+            .uri("/api/ciphers/0b6e3f4c-alice")
+            .body(Body::empty())
+            .unwrap();
+        block_on(app.call(req)).unwrap();
+
+        assert_eq!(context.route().as_deref(), Some("/api/ciphers/{id}"));
+    }
+
+    #[test]
+    fn unmatched_paths_record_no_route() {
+        let context = context();
+        let mut app = Router::new()
+            .route("/api/ciphers/{id}", get(|| async { "" }))
+            .route_layer(axum::middleware::from_fn(record_route))
+            .layer(Extension(context.clone()));
+
+        let req = Request::builder()
+            .uri("/alice@example.com")
+            .body(Body::empty())
+            .unwrap();
+        block_on(app.call(req)).unwrap();
+
+        assert_eq!(context.route(), None);
+    }
+
+    #[test]
+    fn request_lines_carry_no_user_or_path() {
+        let line: serde_json::Value =
+            serde_json::from_str(&request_line("request", "GET", "/api/sync", 200, 12)).unwrap();
+
+        assert_eq!(
+            line,
+            json!({
+                "requestId": "request",
+                "method": "GET",
+                "route": "/api/sync",
+                "status": 200,
+                "durationMs": 12,
+            })
+        );
+    }
+}
@@ -0,0 +1,479 @@
+//! Outbound email, sent through a provider's HTTP API (Workers can't open SMTP connections).
+//!
+//! Configuration (mail is disabled unless `MAIL_PROVIDER` is set):
+//! - `MAIL_PROVIDER`: `mailchannels`, `resend` or `sendgrid`.
+//! - `MAIL_API_KEY` (secret): the provider's API key.
+//! - `MAIL_FROM`: sender address, on a domain verified with the provider.
+//! - `MAIL_FROM_NAME`: optional sender display name.
+//!
+//! Without a provider, [`NoopMailer`] logs each message instead. Sending never fails the request
+//! by itself: callers get a [`MailError`] and decide whether the request can go on without the
+//! email.
+//!
+//! The messages themselves are rendered by [`templates`].
+
+pub mod templates;
+
+use serde_json::{json, Value};
+use thiserror::Error;
+use worker::{wasm_bindgen::JsValue, Fetch, Headers, Method, Request, RequestInit};
+
+use crate::config::{InvalidSetting, MailProvider, MailSettings, Settings};
+use crate::error::AppError;
+use crate::Env;
+
+const MAILCHANNELS_URL: &str = "https://api.mailchannels.net/tx/v1/send";
+const RESEND_URL: &str = "https://api.resend.com/emails";
+const SENDGRID_URL: &str = "https://api.sendgrid.com/v3/mail/send";
+/// Most of a provider's error response kept in [`MailError::Rejected`].
+const MAX_ERROR_BODY_CHARS: usize = 500;
+
+/// An email to a single recipient. `html` is sent alongside `text` when set.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum MailError {
+    #[error("{0}")]
+    Config(InvalidSetting),
+
+    /// The provider couldn't be reached.
+    #[error("{provider} request failed: {source}")]
+    Transport {
+        provider: &'static str,
+        source: worker::Error,
+    },
+
+    /// The provider answered with an error status.
+    #[error("{provider} rejected the message with HTTP {status}: {body}")]
+    Rejected {
+        provider: &'static str,
+        status: u16,
+        body: String,
+    },
+}
+
+impl From<MailError> for AppError {
+    fn from(err: MailError) -> Self {
+        AppError::Internal(Some(err.to_string()))
+    }
+}
+
+#[allow(async_fn_in_trait)] // Only used on the single-threaded Workers runtime
+pub trait Mailer {
+    async fn send(&self, message: &Message) -> Result<(), MailError>;
+}
+
+/// Logs messages instead of sending them, when no provider is configured.
+pub struct NoopMailer;
+
+impl Mailer for NoopMailer {
+    async fn send(&self, message: &Message) -> Result<(), MailError> {
+        log::info!(
+            "Mail is not configured; not sending \"{}\" to {}",
+            message.subject,
+            message.to
+        );
+        Ok(())
+    }
+}
+
+/// Keeps messages on the current thread instead of sending them, for `MAIL_PROVIDER=mock` in
+/// tests.
+#[cfg(test)]
+pub(crate) struct MockMailer;
+
+#[cfg(test)]
+thread_local! {
+    static SENT: std::cell::RefCell<Vec<Message>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+#[cfg(test)]
+impl MockMailer {
+    /// The messages sent on this thread since the last call.
+    pub(crate) fn take() -> Vec<Message> {
+        SENT.take()
+    }
+}
+
+#[cfg(test)]
+impl Mailer for MockMailer {
+    async fn send(&self, message: &Message) -> Result<(), MailError> {
+        SENT.with_borrow_mut(|sent| sent.push(message.clone()));
+        Ok(())
+    }
+}
+
+/// <https://api.mailchannels.net/tx/v1/documentation>
+pub struct MailChannelsMailer<'a>(pub &'a MailSettings);
+
+impl Mailer for MailChannelsMailer<'_> {
+    async fn send(&self, message: &Message) -> Result<(), MailError> {
+        let settings = self.0;
+        let body = json!({
+            "personalizations": [{ "to": [{ "email": message.to }] }],
+            "from": sender(settings),
+            "subject": message.subject,
+            "content": content(message),
+        });
+        post(
+            MailProvider::MailChannels,
+            MAILCHANNELS_URL,
+            ("X-Api-Key", &settings.api_key),
+            body,
+        )
+        .await
+    }
+}
+
+/// <https://resend.com/docs/api-reference/emails/send-email>
+pub struct ResendMailer<'a>(pub &'a MailSettings);
+
+impl Mailer for ResendMailer<'_> {
+    async fn send(&self, message: &Message) -> Result<(), MailError> {
+        let settings = self.0;
+        let from = match &settings.from_name {
+            Some(name) => format!("{name} <{}>", settings.from),
+            None => settings.from.clone(),
+        };
+        let mut body = json!({
+            "from": from,
+            "to": [message.to],
+            "subject": message.subject,
+            "text": message.text,
+        });
+        if let Some(html) = &message.html {
+            body["html"] = json!(html);
+        }
+        let authorization = format!("Bearer {}", settings.api_key);
+        post(
+            MailProvider::Resend,
+            RESEND_URL,
+            ("Authorization", &authorization),
+            body,
+        )
+        .await
+    }
+}
+
+/// <https://www.twilio.com/docs/sendgrid/api-reference/mail-send/mail-send>
+pub struct SendGridMailer<'a>(pub &'a MailSettings);
+
+impl Mailer for SendGridMailer<'_> {
+    async fn send(&self, message: &Message) -> Result<(), MailError> {
+        let settings = self.0;
+        let body = json!({
+            "personalizations": [{ "to": [{ "email": message.to }] }],
+            "from": sender(settings),
+            "subject": message.subject,
+            "content": content(message),
+        });
+        let authorization = format!("Bearer {}", settings.api_key);
+        post(
+            MailProvider::SendGrid,
+            SENDGRID_URL,
+            ("Authorization", &authorization),
+            body,
+        )
+        .await
+    }
+}
+
+/// Sends `message` through the configured provider, or logs it when there is none. Failures are
+/// logged here too; callers only decide what they mean for the request.
+pub async fn send(env: &Env, message: &Message) -> Result<(), MailError> {
+    let settings = Settings::get(env);
+    let result = match &settings.mail {
+        Ok(None) => NoopMailer.send(message).await,
+        Ok(Some(mail)) => match mail.provider {
+            MailProvider::MailChannels => MailChannelsMailer(mail).send(message).await,
+            MailProvider::Resend => ResendMailer(mail).send(message).await,
+            MailProvider::SendGrid => SendGridMailer(mail).send(message).await,
+            #[cfg(test)]
+            MailProvider::Mock => MockMailer.send(message).await,
+        },
+        Err(invalid) => Err(MailError::Config(invalid.clone())),
+    };
+    if let Err(err) = &result {
+        log::error!("Failed to send \"{}\": {err}", message.subject);
+    }
+    result
+}
+
+/// MailChannels' and SendGrid's sender object.
+fn sender(settings: &MailSettings) -> Value {
+    match &settings.from_name {
+        Some(name) => json!({ "email": settings.from, "name": name }),
+        None => json!({ "email": settings.from }),
+    }
+}
+
+/// MailChannels' and SendGrid's content list; plain text has to come first.
+fn content(message: &Message) -> Value {
+    let mut content = vec![json!({ "type": "text/plain", "value": message.text })];
+    if let Some(html) = &message.html {
+        content.push(json!({ "type": "text/html", "value": html }));
+    }
+    Value::Array(content)
+}
+
+async fn post(
+    provider: MailProvider,
+    url: &str,
+    (auth_header, auth_value): (&str, &str),
+    body: Value,
+) -> Result<(), MailError> {
+    let provider = provider.name();
+    let transport = |source| MailError::Transport { provider, source };
+
+    let headers = Headers::new();
+    headers
+        .set("Content-Type", "application/json")
+        .map_err(transport)?;
+    headers.set(auth_header, auth_value).map_err(transport)?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_headers(headers)
+        .with_body(Some(JsValue::from_str(&body.to_string())));
+
+    let request = Request::new_with_init(url, &init).map_err(transport)?;
+    let mut response = Fetch::Request(request).send().await.map_err(transport)?;
+    match response.status_code() {
+        200..=299 => Ok(()),
+        status => {
+            let body = response.text().await.unwrap_or_default();
+            Err(MailError::Rejected {
+                provider,
+                status,
+                body: body.chars().take(MAX_ERROR_BODY_CHARS).collect(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::native::testing::{self, ORIGIN};
+    use crate::native::{block_on, Env};
+    use axum::http::{Method, StatusCode};
+
+    fn env(mail: bool) -> Env {
+        let env = testing::env();
+        if !mail {
+            return env;
+        }
+        env.with_var("MAIL_PROVIDER", "mock")
+            .with_secret("MAIL_API_KEY", "key")
+            .with_var("MAIL_FROM", "vault@example.com")
+    }
+
+    /// Adds the user `name`, at `name@example.com`, and returns an access token for them.
+    fn user(env: &Env, name: &str, hint: Option<&str>) -> String {
+        testing::user(env, name);
+        block_on(env.d1("vault1").unwrap().run(
+            "UPDATE users SET name = ?1, master_password_hint = ?2 WHERE id = ?1",
+            &[name.into(), hint.into()],
+        ))
+        .unwrap();
+        testing::token(env, &format!("{name}@example.com"))
+    }
+
+    fn post(env: &Env, path: &str, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+        testing::request(env, Method::POST, path, token, Some(body))
+    }
+
+    fn message(to: &str, subject: &str) -> Message {
+        Message {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            text: "text".to_string(),
+            html: Some("<p>html</p>".to_string()),
+        }
+    }
+
+    #[test]
+    fn sends_through_the_configured_provider() {
+        let env = env(true);
+        block_on(send(&env, &message("a@example.com", "Hello"))).unwrap();
+
+        let sent = MockMailer::take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "a@example.com");
+        assert_eq!(sent[0].subject, "Hello");
+    }
+
+    #[test]
+    fn only_logs_without_a_provider() {
+        let env = env(false);
+        block_on(send(&env, &message("a@example.com", "Hello"))).unwrap();
+
+        assert!(MockMailer::take().is_empty());
+    }
+
+    #[test]
+    fn plain_text_comes_first() {
+        let content = content(&message("a@example.com", "Hello"));
+
+        assert_eq!(content[0]["type"], "text/plain");
+        assert_eq!(content[1]["type"], "text/html");
+        assert_eq!(content[1]["value"], "<p>html</p>");
+    }
+
+    #[test]
+    fn password_hints_are_emailed_not_returned() {
+        let env = env(true);
+        user(&env, "alice", Some("<my cat> & co"));
+
+        let (status, body) = post(
+            &env,
+            "/api/accounts/password-hint",
+            None,
+            json!({ "email": "Alice@example.com" }),
+        );
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.to_string().contains("my cat"));
+
+        let sent = MockMailer::take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "alice@example.com");
+        assert_eq!(sent[0].subject, "Your master password hint");
+        assert!(sent[0].text.contains("<my cat> & co"));
+        let html = sent[0].html.as_deref().unwrap();
+        assert!(html.contains("&lt;my cat&gt; &amp; co"));
+        assert!(!html.contains("<my cat>"));
+    }
+
+    #[test]
+    fn password_hints_for_unknown_addresses_answer_the_same() {
+        let env = env(true);
+        user(&env, "alice", None);
+
+        let known = post(
+            &env,
+            "/api/accounts/password-hint",
+            None,
+            json!({ "email": "alice@example.com" }),
+        );
+        let unknown = post(
+            &env,
+            "/api/accounts/password-hint",
+            None,
+            json!({ "email": "nobody@example.com" }),
+        );
+
+        assert_eq!(known, unknown);
+        let sent = MockMailer::take();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].text.contains("no master password hint"));
+    }
+
+    #[test]
+    fn organization_invitations_are_emailed() {
+        let env = env(true);
+        let token = user(&env, "owner", None);
+        let db = env.d1("vault1").unwrap();
+        let now = crate::time::now_bw();
+        block_on(db.run(
+            "INSERT INTO organizations (id, name, billing_email, created_at, updated_at)
+             VALUES ('org', 'Acme <Ops>', 'owner@example.com', ?1, ?1)",
+            &[now.as_str().into()],
+        ))
+        .unwrap();
+        block_on(db.run(
+            "INSERT INTO organization_users (id, organization_id, user_id, email, akey, status, atype, access_all, created_at, updated_at)
+             VALUES ('owner-member', 'org', 'owner', 'owner@example.com', 'akey', 2, 0, 1, ?1, ?1)",
+            &[now.as_str().into()],
+        ))
+        .unwrap();
+
+        let (status, body) = post(
+            &env,
+            "/api/organizations/org/users/invite",
+            Some(&token),
+            json!({ "emails": ["New@example.com"], "type": 2 }),
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"][0]["inviteUrl"], Value::Null);
+
+        let sent = MockMailer::take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "new@example.com");
+        assert_eq!(sent[0].subject, "Join Acme <Ops>");
+        assert!(sent[0]
+            .text
+            .contains(&format!("{ORIGIN}/#/accept-organization/?")));
+        assert!(sent[0].text.contains("expires in 5 days"));
+        assert!(sent[0]
+            .html
+            .as_deref()
+            .unwrap()
+            .contains("Acme &lt;Ops&gt;"));
+    }
+
+    #[test]
+    fn emergency_access_invitations_and_notices_are_emailed() {
+        let env = env(true);
+        let grantor = user(&env, "grantor", None);
+        let grantee = user(&env, "grantee", None);
+
+        let (status, body) = post(
+            &env,
+            "/api/emergency-access/invite",
+            Some(&grantor),
+            json!({ "email": "grantee@example.com", "type": 1, "waitTimeDays": 7 }),
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let sent = MockMailer::take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "grantee@example.com");
+        assert!(sent[0].text.starts_with("grantor has invited you"));
+        assert!(sent[0]
+            .text
+            .contains(&format!("{ORIGIN}/#/accept-emergency/?")));
+
+        // Skip ahead to a confirmed grant
+        let db = env.d1("vault1").unwrap();
+        block_on(db.run(
+            "UPDATE emergency_access SET grantee_id = 'grantee', key_encrypted = 'key', status = 2",
+            &[],
+        ))
+        .unwrap();
+        let id = body["id"].as_str().unwrap();
+
+        let (status, _) = post(
+            &env,
+            &format!("/api/emergency-access/{id}/initiate"),
+            Some(&grantee),
+            json!({}),
+        );
+        assert_eq!(status, StatusCode::OK);
+        let sent = MockMailer::take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "grantor@example.com");
+        assert_eq!(sent[0].subject, "Emergency access requested");
+        assert!(sent[0]
+            .text
+            .contains("grantee has requested emergency access to take over"));
+        assert!(sent[0].text.contains("in 7 days"));
+
+        let (status, _) = post(
+            &env,
+            &format!("/api/emergency-access/{id}/reject"),
+            Some(&grantor),
+            json!({}),
+        );
+        assert_eq!(status, StatusCode::OK);
+        let sent = MockMailer::take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "grantee@example.com");
+        assert_eq!(sent[0].subject, "Emergency access rejected");
+    }
+}
@@ -0,0 +1,345 @@
+//! Transactional emails, rendered as a subject with plain text and HTML bodies.
+//!
+//! Each template takes typed parameters and links built by the caller or from the request's base
+//! URL. Values coming from users (names, hints, device names) are HTML-escaped in the HTML body;
+//! the plain text body carries them as they are.
+
+use chrono::Duration;
+
+use super::Message;
+
+/// A rendered email, ready to be addressed with [`Email::to`].
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl Email {
+    pub fn to(self, to: impl Into<String>) -> Message {
+        Message {
+            to: to.into(),
+            subject: self.subject,
+            text: self.text,
+            html: Some(self.html),
+        }
+    }
+}
+
+/// The master password hint, or a note that there is none.
+pub fn password_hint(hint: Option<&str>) -> Email {
+    let (text, html) = match hint {
+        Some(hint) => (
+            format!("Your master password hint is:\n\n{hint}"),
+            format!(
+                "<p>Your master password hint is:</p><p><b>{}</b></p>",
+                escape(hint)
+            ),
+        ),
+        None => (
+            "Your account has no master password hint.".to_string(),
+            "<p>Your account has no master password hint.</p>".to_string(),
+        ),
+    };
+    let footer =
+        "Someone asked for it on the login page. If that wasn't you, you can ignore this email.";
+
+    Email {
+        subject: "Your master password hint".to_string(),
+        text: format!("{text}\n\n{footer}"),
+        html: layout(&format!("{html}<p>{footer}</p>")),
+    }
+}
+
+/// Invitation to create an account on this server, sent by the operator.
+pub fn registration_invite(server_name: &str, signup_url: &str, valid_for: Duration) -> Email {
+    let expiry = describe(valid_for);
+
+    Email {
+        subject: format!("Join {server_name}"),
+        text: format!(
+            "You have been invited to create an account on {server_name}. Sign up here:\n\n\
+             {signup_url}\n\nThe invitation expires in {expiry}."
+        ),
+        html: layout(&format!(
+            "<p>You have been invited to create an account on <b>{}</b>.</p>{}\
+             <p>The invitation expires in {expiry}.</p>",
+            escape(server_name),
+            button(signup_url, "Create account"),
+        )),
+    }
+}
+
+/// Invitation to join an organization; `invite_url` carries the signed invitation token.
+pub fn organization_invite(org_name: &str, invite_url: &str, valid_for: Duration) -> Email {
+    let expiry = describe(valid_for);
+
+    Email {
+        subject: format!("Join {org_name}"),
+        text: format!(
+            "You have been invited to join the {org_name} organization. Accept the invitation:\n\n\
+             {invite_url}\n\nThe invitation expires in {expiry}."
+        ),
+        html: layout(&format!(
+            "<p>You have been invited to join the <b>{}</b> organization.</p>{}\
+             <p>The invitation expires in {expiry}.</p>",
+            escape(org_name),
+            button(invite_url, "Join organization"),
+        )),
+    }
+}
+
+/// Invitation to become a grantor's emergency contact.
+pub fn emergency_access_invite(grantor_name: &str, invite_url: &str, valid_for: Duration) -> Email {
+    let expiry = describe(valid_for);
+
+    Email {
+        subject: "Emergency access contact invitation".to_string(),
+        text: format!(
+            "{grantor_name} has invited you to be an emergency contact. Accept the invitation:\n\n\
+             {invite_url}\n\nThe invitation expires in {expiry}."
+        ),
+        html: layout(&format!(
+            "<p><b>{}</b> has invited you to be an emergency contact.</p>{}\
+             <p>The invitation expires in {expiry}.</p>",
+            escape(grantor_name),
+            button(invite_url, "Become emergency contact"),
+        )),
+    }
+}
+
+/// A step of an emergency access recovery, told to the other side of the grant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryNotice {
+    /// To the grantor: the contact asked for access, granted after `wait` unless rejected.
+    Initiated { takeover: bool, wait: Duration },
+    /// To the grantee: the grantor approved, or didn't answer within the wait time.
+    Approved,
+    /// To the grantee: the grantor rejected the request.
+    Rejected,
+    /// To the grantor: the contact took over the account and set a new master password.
+    PasswordChanged,
+}
+
+/// Notice about an emergency access recovery; `contact_name` is the other side of the grant.
+pub fn emergency_access_notice(notice: RecoveryNotice, contact_name: &str) -> Email {
+    let name = escape(contact_name);
+    let (subject, text, html) = match notice {
+        RecoveryNotice::Initiated { takeover, wait } => {
+            let access = if takeover {
+                "take over your account"
+            } else {
+                "view your vault"
+            };
+            let wait = describe(wait);
+            let rest = format!(
+                "Unless you reject the request from the emergency access settings, it will be \
+                 approved automatically in {wait}."
+            );
+            (
+                "Emergency access requested",
+                format!("{contact_name} has requested emergency access to {access}.\n\n{rest}"),
+                format!(
+                    "<p><b>{name}</b> has requested emergency access to {access}.</p><p>{rest}</p>"
+                ),
+            )
+        }
+        RecoveryNotice::Approved => (
+            "Emergency access approved",
+            format!(
+                "{contact_name} has approved your emergency access request. You can use it from \
+                 the emergency access settings."
+            ),
+            format!(
+                "<p><b>{name}</b> has approved your emergency access request. You can use it from \
+                 the emergency access settings.</p>"
+            ),
+        ),
+        RecoveryNotice::Rejected => (
+            "Emergency access rejected",
+            format!("{contact_name} has rejected your emergency access request."),
+            format!("<p><b>{name}</b> has rejected your emergency access request.</p>"),
+        ),
+        RecoveryNotice::PasswordChanged => {
+            let rest = "All your sessions have been logged out; log in with the new password.";
+            (
+                "Master password changed through emergency access",
+                format!(
+                    "{contact_name} took over your account through emergency access and set a new \
+                     master password. {rest}"
+                ),
+                format!(
+                    "<p><b>{name}</b> took over your account through emergency access and set a \
+                     new master password. {rest}</p>"
+                ),
+            )
+        }
+    };
+
+    Email {
+        subject: subject.to_string(),
+        text,
+        html: layout(&html),
+    }
+}
+
+/// Code confirming a login from a device the account hasn't used before.
+pub fn new_device_verification(code: &str, valid_for: Duration) -> Email {
+    let expiry = describe(valid_for);
+    let footer = "Never share this code. If you didn't try to log in, change your master password.";
+
+    Email {
+        subject: "Your new device verification code".to_string(),
+        text: format!(
+            "Someone logged into your account from a new device. Enter {code} on that device to \
+             finish logging in. The code expires in {expiry}.\n\n{footer}"
+        ),
+        html: layout(&format!(
+            "<p>Someone logged into your account from a new device. Enter this code on that \
+             device to finish logging in:</p>\
+             <p style=\"font-size:24px;letter-spacing:4px\"><b>{}</b></p>\
+             <p>The code expires in {expiry}.</p><p>{footer}</p>",
+            escape(code),
+        )),
+    }
+}
+
+/// `&`, `<`, `>`, `"` and `'` as entities, for text and attribute values.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A link styled as a button, followed by the bare URL for clients that don't render it.
+fn button(url: &str, label: &str) -> String {
+    let url = escape(url);
+    format!(
+        "<p><a href=\"{url}\" style=\"display:inline-block;padding:10px 18px;background:#175ddc;\
+         color:#fff;border-radius:4px;text-decoration:none\">{label}</a></p>\
+         <p style=\"font-size:12px;color:#666;word-break:break-all\">{url}</p>"
+    )
+}
+
+fn layout(body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><body style=\"margin:0;padding:24px;background:#f3f6f9;\
+         font-family:-apple-system,'Segoe UI',Helvetica,Arial,sans-serif;font-size:15px;\
+         color:#333\"><div style=\"max-width:560px;margin:0 auto;padding:24px;background:#fff;\
+         border-radius:6px\">{body}</div></body></html>"
+    )
+}
+
+/// `10 minutes`, `1 hour`, `5 days`: the largest whole unit.
+fn describe(duration: Duration) -> String {
+    let (count, unit) = if duration.num_days() > 0 {
+        (duration.num_days(), "day")
+    } else if duration.num_hours() > 0 {
+        (duration.num_hours(), "hour")
+    } else {
+        (duration.num_minutes().max(1), "minute")
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_html_but_not_text() {
+        let email = organization_invite(
+            "<script>\"Tom & Jerry's\"</script>",
+            "https://vault.example.com/#/accept?a=1&b=\"2\"",
+            Duration::days(5),
+        );
+
+        assert!(email.text.contains("<script>\"Tom & Jerry's\"</script>"));
+        assert!(email
+            .html
+            .contains("&lt;script&gt;&quot;Tom &amp; Jerry&#39;s&quot;&lt;/script&gt;"));
+        assert!(!email.html.contains("<script>"));
+        assert!(email
+            .html
+            .contains("href=\"https://vault.example.com/#/accept?a=1&amp;b=&quot;2&quot;\""));
+    }
+
+    #[test]
+    fn describes_the_largest_whole_unit() {
+        assert_eq!(describe(Duration::days(5)), "5 days");
+        assert_eq!(describe(Duration::hours(25)), "1 day");
+        assert_eq!(describe(Duration::minutes(90)), "1 hour");
+        assert_eq!(describe(Duration::minutes(10)), "10 minutes");
+        assert_eq!(describe(Duration::seconds(5)), "1 minute");
+    }
+
+    #[test]
+    fn password_hint_without_a_hint() {
+        let email = password_hint(None);
+
+        assert!(email
+            .text
+            .starts_with("Your account has no master password hint."));
+        assert!(email.html.contains("no master password hint"));
+    }
+
+    #[test]
+    fn recovery_notices_name_the_contact() {
+        let view = emergency_access_notice(
+            RecoveryNotice::Initiated {
+                takeover: false,
+                wait: Duration::days(1),
+            },
+            "Bob <b>",
+        );
+        assert_eq!(view.subject, "Emergency access requested");
+        assert!(view
+            .text
+            .starts_with("Bob <b> has requested emergency access to view your vault"));
+        assert!(view.text.contains("approved automatically in 1 day"));
+        assert!(view.html.contains("<b>Bob &lt;b&gt;</b>"));
+
+        let takeover = emergency_access_notice(
+            RecoveryNotice::Initiated {
+                takeover: true,
+                wait: Duration::days(7),
+            },
+            "Bob",
+        );
+        assert!(takeover.text.contains("to take over your account"));
+
+        for (notice, subject) in [
+            (RecoveryNotice::Approved, "Emergency access approved"),
+            (RecoveryNotice::Rejected, "Emergency access rejected"),
+            (
+                RecoveryNotice::PasswordChanged,
+                "Master password changed through emergency access",
+            ),
+        ] {
+            let email = emergency_access_notice(notice, "Bob");
+            assert_eq!(email.subject, subject);
+            assert!(email.text.starts_with("Bob "), "{}", email.text);
+        }
+    }
+
+    #[test]
+    fn to_addresses_the_rendered_email() {
+        let message = new_device_verification("123456", Duration::minutes(10)).to("a@example.com");
+
+        assert_eq!(message.to, "a@example.com");
+        assert_eq!(message.subject, "Your new device verification code");
+        assert!(message.text.contains("Enter 123456"));
+        assert!(message.html.unwrap().contains("<b>123456</b>"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Request and workload metrics, written to Workers Analytics Engine.
+//!
+//! Enabled by binding a dataset as `METRICS` in `wrangler.toml`; without the binding nothing is
+//! recorded. Every data point is indexed by its metric name, so each metric is sampled on its
+//! own, and carries:
+//!
+//! | metric       | blobs                        | doubles                                     |
+//! |--------------|------------------------------|---------------------------------------------|
+//! | `request`    | method, route, user          | status, duration (ms)                       |
+//! | `sync`       | user                         | response bytes, folders, collections, sends |
+//! | `import`     | user                         | ciphers inserted, batches, duration (ms)    |
+//! | `attachment` | user                         | bytes uploaded                              |
+//!
+//! `route` is the route's path template (`/api/ciphers/{id}`), or `unmatched` for the web vault
+//! and unknown paths. `user` is a short hash of the user id (empty for anonymous requests): it
+//! counts users without recording who they are. Sync ciphers are passed through as raw JSON, so
+//! their share is in the response size rather than counted; attachments streamed by `entry.js`
+//! aren't recorded.
+//!
+//! Error rate and latency per route over the last day, with the SQL API:
+//!
+//! ```sql
+//! SELECT blob2 AS route,
+//!        SUM(_sample_interval) AS requests,
+//!        SUM(IF(double1 >= 500, _sample_interval, 0)) AS errors,
+//!        quantileWeighted(0.95)(double2, _sample_interval) AS p95_ms
+//! FROM warden_metrics
+//! WHERE index1 = 'request' AND timestamp > NOW() - INTERVAL '1' DAY
+//! GROUP BY route
+//! ORDER BY requests DESC
+//! ```
+
+use crate::Env;
+use sha2::{Digest, Sha256};
+use worker::AnalyticsEngineDataPointBuilder;
+
+const BINDING: &str = "METRICS";
+/// `route` of requests that matched no API route: the web vault and unknown paths.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+/// Hex digits of the user hash: 65536 buckets, plenty to count users but not to single them out.
+const USER_HASH_LEN: usize = 4;
+
+/// One data point, before it is handed to the binding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataPoint {
+    pub metric: &'static str,
+    pub blobs: Vec<String>,
+    pub doubles: Vec<f64>,
+}
+
+pub fn request(
+    method: &str,
+    route: &str,
+    user_id: Option<&str>,
+    status: u16,
+    duration_ms: i64,
+) -> DataPoint {
+    DataPoint {
+        metric: "request",
+        blobs: vec![
+            method.to_string(),
+            route.to_string(),
+            user_id.map(user_hash).unwrap_or_default(),
+        ],
+        doubles: vec![f64::from(status), duration_ms as f64],
+    }
+}
+
+pub fn sync(
+    user_id: &str,
+    response_bytes: usize,
+    folders: usize,
+    collections: usize,
+    sends: usize,
+) -> DataPoint {
+    DataPoint {
+        metric: "sync",
+        blobs: vec![user_hash(user_id)],
+        doubles: vec![
+            response_bytes as f64,
+            folders as f64,
+            collections as f64,
+            sends as f64,
+        ],
+    }
+}
+
+pub fn import(user_id: &str, ciphers: usize, batches: usize, duration_ms: i64) -> DataPoint {
+    DataPoint {
+        metric: "import",
+        blobs: vec![user_hash(user_id)],
+        doubles: vec![ciphers as f64, batches as f64, duration_ms as f64],
+    }
+}
+
+pub fn attachment(user_id: &str, bytes: usize) -> DataPoint {
+    DataPoint {
+        metric: "attachment",
+        blobs: vec![user_hash(user_id)],
+        doubles: vec![bytes as f64],
+    }
+}
+
+fn user_hash(user_id: &str) -> String {
+    let mut hash = hex::encode(Sha256::digest(user_id.as_bytes()));
+    hash.truncate(USER_HASH_LEN);
+    hash
+}
+
+/// Writes `point` when the `METRICS` binding exists. Failures are logged, never returned:
+/// metrics must not fail a request.
+pub fn record(env: &Env, point: DataPoint) {
+    let Ok(dataset) = env.analytics_engine(BINDING) else {
+        return;
+    };
+    let result = AnalyticsEngineDataPointBuilder::new()
+        .indexes([point.metric])
+        .blobs(point.blobs)
+        .doubles(point.doubles)
+        .write_to(&dataset);
+    if let Err(err) = result {
+        log::warn!("Failed to record the {} metric: {err}", point.metric);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = "0b6e3f4c-9a5d-4c1e-8f3a-2d7b6c5e4f3a";
+
+    #[test]
+    fn request_points_carry_the_route_and_a_user_hash() {
+        let point = request("GET", "/api/ciphers/{id}", Some(USER), 404, 12);
+
+        assert_eq!(point.metric, "request");
+        assert_eq!(point.blobs[0], "GET");
+        assert_eq!(point.blobs[1], "/api/ciphers/{id}");
+        assert_eq!(point.blobs[2], user_hash(USER));
+        assert_eq!(point.doubles, vec![404.0, 12.0]);
+    }
+
+    #[test]
+    fn anonymous_requests_have_an_empty_user() {
+        let point = request("POST", UNMATCHED_ROUTE, None, 200, 3);
+
+        assert_eq!(point.blobs, vec!["POST", "unmatched", ""]);
+    }
+
+    #[test]
+    fn user_hashes_are_short_and_stable() {
+        let hash = user_hash(USER);
+
+        assert_eq!(hash.len(), USER_HASH_LEN);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(hash, user_hash(USER));
+        assert_ne!(hash, user_hash("another-user"));
+    }
+
+    #[test]
+    fn workload_points_never_carry_the_user_id() {
+        let points = [
+            sync(USER, 2048, 3, 2, 1),
+            import(USER, 120, 2, 350),
+            attachment(USER, 4096),
+        ];
+        for point in &points {
+            assert_eq!(point.blobs, vec![user_hash(USER)], "{}", point.metric);
+        }
+
+        assert_eq!(points[0].doubles, vec![2048.0, 3.0, 2.0, 1.0]);
+        assert_eq!(points[1].doubles, vec![120.0, 2.0, 350.0]);
+        assert_eq!(points[2].doubles, vec![4096.0]);
+    }
+}
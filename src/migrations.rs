@@ -0,0 +1,558 @@
+//! Schema migrations applied by the Worker itself.
+//!
+//! The SQL files under `migrations/` are embedded here in order, keyed by the number of their
+//! file. Applied versions are recorded in `schema_migrations`; each pending migration runs in a
+//! D1 batch that starts by recording its version, so a concurrent run of the same migration
+//! fails on the primary key and rolls back as a whole instead of applying it twice.
+//!
+//! An empty database gets `sql/schema.sql` (version `0`), which already includes every migration,
+//! and all of them are recorded as applied. Wrangler's own `d1_migrations` table is kept in step
+//! both ways: migrations it already applied are recorded without running them again, and the
+//! ones run here are added to it, so `wrangler d1 migrations apply` and the deploy workflow skip
+//! them.
+//!
+//! Databases whose columns were added by hand or by the old `ensure_schema` (as the deploy
+//! workflow allows) are brought up to date too: an `ALTER TABLE ... ADD COLUMN` for a column that
+//! already exists is left out of the batch, and the rest of its migration still runs.
+//!
+//! New migrations are added to `migrations/`, mirrored into `sql/schema.sql` and listed in
+//! [`MIGRATIONS`]. See [`statements`] for what their SQL may contain.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+use crate::config::Settings;
+use crate::db::{ConstraintViolation, Database, Db, Statement};
+use crate::error::{db_error, AppError};
+use crate::time;
+use crate::Env;
+
+/// The whole schema, for empty databases.
+const BASELINE: &str = include_str!("../sql/schema.sql");
+const BASELINE_NAME: &str = "schema.sql";
+/// Wrangler's migration table, as the deploy workflow creates it.
+const D1_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS d1_migrations (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT UNIQUE NOT NULL, applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL)";
+
+pub struct Migration {
+    pub version: u32,
+    /// File name under `migrations/`, as Wrangler records it.
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: concat!($name, ".sql"),
+            sql: include_str!(concat!("../migrations/", $name, ".sql")),
+        }
+    };
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_add_password_salt"),
+    migration!(2, "0002_add_argon2_fields"),
+    migration!(3, "0003_add_twofactor"),
+    migration!(4, "0004_add_avatar_color"),
+    migration!(5, "0005_add_attachments"),
+    migration!(6, "0006_add_pending_attachments"),
+    migration!(7, "0007_add_password_iterations"),
+    migration!(8, "0008_add_eq_domains"),
+    migration!(9, "0009_add_ciphers_folders_user_id_index"),
+    migration!(10, "0010_add_auth_requests"),
+    migration!(11, "0011_add_devices"),
+    migration!(12, "0012_add_device_sessions"),
+    migration!(13, "0013_add_device_push"),
+    migration!(14, "0014_add_device_web_push"),
+    migration!(15, "0015_add_sends"),
+    migration!(16, "0016_add_organizations"),
+    migration!(17, "0017_add_collections_users"),
+    migration!(18, "0018_add_ciphers_collections"),
+    migration!(19, "0019_add_organization_policies"),
+    migration!(20, "0020_add_events"),
+    migration!(21, "0021_add_groups"),
+    migration!(22, "0022_add_account_recovery"),
+    migration!(23, "0023_add_emergency_access"),
+    migration!(24, "0024_add_emergency_access_recovery"),
+    migration!(25, "0025_add_import_sessions"),
+    migration!(26, "0026_add_hot_query_indexes"),
+    migration!(27, "0027_add_user_object_counts"),
+    migration!(28, "0028_normalize_user_timestamps"),
+    migration!(29, "0029_add_invitations"),
+    migration!(30, "0030_add_organization_use_totp"),
+    migration!(31, "0031_add_import_locks"),
+    migration!(32, "0032_add_user_verify_devices"),
+    migration!(33, "0033_add_server_state"),
+    migration!(34, "0034_add_organization_use_groups"),
+];
+
+/// What one [`run`] did.
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationSummary {
+    /// Migrations run by this call.
+    pub applied: Vec<String>,
+    /// Migrations recorded without running them: covered by the baseline schema or already
+    /// applied by Wrangler.
+    pub recorded: Vec<String>,
+    /// Latest version recorded after the run.
+    pub version: u32,
+}
+
+thread_local! {
+    /// Whether this isolate already brought the schema up to date (AUTO_MIGRATE).
+    static MIGRATED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// With AUTO_MIGRATE, runs the pending migrations on the first request an isolate handles.
+/// Failures are logged and retried on the next request.
+pub async fn run_on_first_request(env: &Env) {
+    if MIGRATED.get() || !Settings::get(env).auto_migrate {
+        return;
+    }
+    let result = match env.d1("vault1") {
+        Ok(db) => run(&db).await,
+        Err(err) => Err(AppError::Worker(err)),
+    };
+    match result {
+        Ok(summary) => {
+            MIGRATED.set(true);
+            if !summary.applied.is_empty() {
+                log::info!("Applied migrations: {}", summary.applied.join(", "));
+            }
+        }
+        Err(err) => log::error!("Schema migration failed: {err:?}"),
+    }
+}
+
+/// Applies every pending migration in order.
+pub async fn run(db: &Db) -> Result<MigrationSummary, AppError> {
+    db.run(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY NOT NULL, name TEXT NOT NULL, applied_at TEXT NOT NULL)",
+        &[],
+    )
+    .await
+    .map_err(db_error!("schema_migrations"))?;
+
+    let mut summary = MigrationSummary::default();
+    let mut applied = applied_versions(db).await?;
+    if applied.is_empty() && !has_table(db, "users").await? {
+        apply_baseline(db, &mut summary).await?;
+        applied = applied_versions(db).await?;
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect();
+    if !pending.is_empty() {
+        let by_wrangler = wrangler_migrations(db).await?;
+        for migration in pending {
+            if by_wrangler
+                .as_ref()
+                .is_some_and(|names| names.contains(migration.name))
+            {
+                record(db, migration).await?;
+                summary.recorded.push(migration.name.to_string());
+            } else {
+                apply(db, migration, by_wrangler.is_some(), &mut summary).await?;
+            }
+        }
+    }
+
+    summary.version = applied_versions(db).await?.into_iter().max().unwrap_or(0);
+    Ok(summary)
+}
+
+/// Creates the schema of an empty database and records every migration it includes.
+async fn apply_baseline(db: &Db, summary: &mut MigrationSummary) -> Result<(), AppError> {
+    let now = time::now_bw();
+    let mut batch = vec![
+        record_statement(db, 0, BASELINE_NAME, &now)?,
+        Database::prepare(db, D1_MIGRATIONS_TABLE, &[]).map_err(db_error!())?,
+    ];
+    for migration in MIGRATIONS {
+        batch.push(record_statement(
+            db,
+            migration.version,
+            migration.name,
+            &now,
+        )?);
+        batch.push(wrangler_record_statement(db, migration.name)?);
+    }
+    for sql in statements(BASELINE) {
+        batch.push(Database::prepare(db, &sql, &[]).map_err(db_error!())?);
+    }
+
+    match Database::batch(db, batch).await {
+        Ok(_) => {
+            summary.applied.push(BASELINE_NAME.to_string());
+            summary.recorded.extend(
+                MIGRATIONS
+                    .iter()
+                    .map(|migration| migration.name.to_string()),
+            );
+            Ok(())
+        }
+        // Another isolate got there first
+        Err(err) if ConstraintViolation::of(&err) == Some(ConstraintViolation::Unique) => Ok(()),
+        Err(err) => Err(AppError::database(BASELINE_NAME, err)),
+    }
+}
+
+/// Runs `migration`, also recording it for Wrangler when `track_wrangler` is set.
+async fn apply(
+    db: &Db,
+    migration: &Migration,
+    track_wrangler: bool,
+    summary: &mut MigrationSummary,
+) -> Result<(), AppError> {
+    let mut batch = vec![record_statement(
+        db,
+        migration.version,
+        migration.name,
+        &time::now_bw(),
+    )?];
+    if track_wrangler {
+        batch.push(wrangler_record_statement(db, migration.name)?);
+    }
+    for sql in statements(migration.sql) {
+        if let Some((table, column)) = added_column(&sql) {
+            if has_column(db, &table, &column).await? {
+                log::warn!(
+                    "Migration {} adds {table}.{column}, which already exists; skipping it",
+                    migration.name
+                );
+                continue;
+            }
+        }
+        batch.push(Database::prepare(db, &sql, &[]).map_err(db_error!())?);
+    }
+
+    match Database::batch(db, batch).await {
+        Ok(_) => {
+            summary.applied.push(migration.name.to_string());
+            Ok(())
+        }
+        // Another isolate applied it concurrently
+        Err(err) if ConstraintViolation::of(&err) == Some(ConstraintViolation::Unique) => Ok(()),
+        Err(err) => Err(AppError::database(migration.name, err)),
+    }
+}
+
+/// Records `migration` as applied without running it.
+async fn record(db: &Db, migration: &Migration) -> Result<(), AppError> {
+    db.run(
+        "INSERT OR IGNORE INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+        &[
+            migration.version.into(),
+            migration.name.into(),
+            time::now_bw().into(),
+        ],
+    )
+    .await
+    .map_err(db_error!())?;
+    Ok(())
+}
+
+fn record_statement(db: &Db, version: u32, name: &str, now: &str) -> Result<Statement, AppError> {
+    Database::prepare(
+        db,
+        "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+        &[version.into(), name.into(), now.into()],
+    )
+    .map_err(db_error!())
+}
+
+fn wrangler_record_statement(db: &Db, name: &str) -> Result<Statement, AppError> {
+    Database::prepare(
+        db,
+        "INSERT OR IGNORE INTO d1_migrations (name) VALUES (?1)",
+        &[name.into()],
+    )
+    .map_err(db_error!())
+}
+
+async fn applied_versions(db: &Db) -> Result<HashSet<u32>, AppError> {
+    let rows: Vec<Value> = db
+        .all("SELECT version FROM schema_migrations", &[])
+        .await
+        .map_err(db_error!())?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| row.get("version").and_then(Value::as_u64))
+        .map(|version| version as u32)
+        .collect())
+}
+
+/// Names of the migrations `wrangler d1 migrations apply` recorded, or `None` when it was never
+/// used on this database.
+async fn wrangler_migrations(db: &Db) -> Result<Option<HashSet<String>>, AppError> {
+    if !has_table(db, "d1_migrations").await? {
+        return Ok(None);
+    }
+    let rows: Vec<Value> = db
+        .all("SELECT name FROM d1_migrations", &[])
+        .await
+        .map_err(db_error!())?;
+    Ok(Some(
+        rows.iter()
+            .filter_map(|row| row.get("name").and_then(Value::as_str))
+            .map(str::to_string)
+            .collect(),
+    ))
+}
+
+async fn has_table(db: &Db, name: &str) -> Result<bool, AppError> {
+    let found: Option<Value> = db
+        .first(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            &[name.into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(found.is_some())
+}
+
+async fn has_column(db: &Db, table: &str, column: &str) -> Result<bool, AppError> {
+    let found: Option<Value> = db
+        .first(
+            "SELECT name FROM pragma_table_info(?1) WHERE name = ?2",
+            &[table.into(), column.into()],
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(found.is_some())
+}
+
+/// The table and column of an `ALTER TABLE <table> ADD [COLUMN] <column> ...` statement.
+fn added_column(sql: &str) -> Option<(String, String)> {
+    let mut words = sql.split_whitespace();
+    let mut keyword = |expected: &str| {
+        words
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case(expected))
+    };
+    if !keyword("ALTER") || !keyword("TABLE") {
+        return None;
+    }
+    let table = words.next()?;
+    if !words.next()?.eq_ignore_ascii_case("ADD") {
+        return None;
+    }
+    let column = match words.next()? {
+        word if word.eq_ignore_ascii_case("COLUMN") => words.next()?,
+        word => word,
+    };
+    let unquote = |name: &str| name.trim_matches(['"', '`', '[', ']']).to_string();
+    Some((unquote(table), unquote(column)))
+}
+
+/// Splits a migration into its statements, dropping comments: a D1 batch takes one statement
+/// at a time.
+///
+/// Statements end at every `;` outside a quoted string or identifier. Nothing else is parsed, so
+/// a statement with `;` in its body, like `CREATE TRIGGER ... BEGIN ...; END`, would be cut in
+/// pieces: teach this function about `BEGIN`/`END` before a migration adds one.
+fn statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        current.push('\n');
+                        break;
+                    }
+                }
+            }
+            '\'' | '"' => {
+                current.push(c);
+                for inner in chars.by_ref() {
+                    current.push(inner);
+                    if inner == c {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                let statement = current.trim();
+                if !statement.is_empty() {
+                    statements.push(statement.to_string());
+                }
+                current.clear();
+            }
+            c => current.push(c),
+        }
+    }
+    let statement = current.trim();
+    if !statement.is_empty() {
+        statements.push(statement.to_string());
+    }
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::block_on;
+
+    fn migration(name: &str) -> &'static Migration {
+        MIGRATIONS
+            .iter()
+            .find(|migration| migration.name == name)
+            .unwrap()
+    }
+
+    fn columns(db: &Db, table: &str) -> Vec<String> {
+        block_on(db.texts("SELECT name FROM pragma_table_info(?1)", &[table.into()])).unwrap()
+    }
+
+    #[test]
+    fn splits_statements_on_semicolons_outside_quotes() {
+        let sql = "-- a comment; with a semicolon\n\
+                   CREATE TABLE t (a TEXT DEFAULT 'x;y', \"b;c\" TEXT);\n\
+                   \n\
+                   INSERT INTO t (a) VALUES ('--not a comment'); -- trailing\n\
+                   UPDATE t SET a = 'z'";
+        assert_eq!(
+            statements(sql),
+            vec![
+                "CREATE TABLE t (a TEXT DEFAULT 'x;y', \"b;c\" TEXT)",
+                "INSERT INTO t (a) VALUES ('--not a comment')",
+                "UPDATE t SET a = 'z'",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_migration_splits_into_statements() {
+        for migration in MIGRATIONS {
+            let statements = statements(migration.sql);
+            assert!(!statements.is_empty(), "{}", migration.name);
+            assert!(
+                statements.iter().all(|sql| !sql.starts_with("--")),
+                "{}",
+                migration.name
+            );
+        }
+    }
+
+    #[test]
+    fn finds_the_column_an_alter_table_adds() {
+        assert_eq!(
+            added_column("ALTER TABLE users ADD COLUMN avatar_color TEXT"),
+            Some(("users".to_string(), "avatar_color".to_string()))
+        );
+        assert_eq!(
+            added_column("alter table \"devices\"\n    add push_uuid TEXT"),
+            Some(("devices".to_string(), "push_uuid".to_string()))
+        );
+        assert_eq!(added_column("ALTER TABLE users RENAME TO people"), None);
+        assert_eq!(added_column("CREATE TABLE users (id TEXT)"), None);
+        assert_eq!(added_column("UPDATE users SET cipher_count = 0"), None);
+    }
+
+    #[test]
+    fn builds_an_empty_database_from_the_baseline() {
+        let db = Db::in_memory().unwrap();
+
+        let summary = block_on(run(&db)).unwrap();
+        assert_eq!(summary.applied, vec![BASELINE_NAME]);
+        assert_eq!(summary.recorded.len(), MIGRATIONS.len());
+        assert_eq!(summary.version, MIGRATIONS.last().unwrap().version);
+
+        let again = block_on(run(&db)).unwrap();
+        assert!(again.applied.is_empty() && again.recorded.is_empty());
+        assert_eq!(again.version, summary.version);
+    }
+
+    #[test]
+    fn emails_differing_only_in_case_are_one_account() {
+        let db = Db::in_memory().unwrap();
+        block_on(run(&db)).unwrap();
+        let insert = |id: &str, email: &str| {
+            block_on(db.run(
+                "INSERT INTO users (id, email, master_password_hash, key, private_key, public_key, created_at, updated_at)
+                 VALUES (?1, ?2, 'h', 'k', 'p', 'p', '2025-01-01T00:00:00.000Z', '2025-01-01T00:00:00.000Z')",
+                &[id.into(), email.into()],
+            ))
+        };
+
+        insert("alice", "alice@example.com").unwrap();
+        let err = insert("alice-again", "Alice@Example.com").unwrap_err();
+        assert!(
+            err.to_string().contains("UNIQUE constraint failed"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn skips_only_the_columns_that_already_exist() {
+        let db = Db::in_memory().unwrap();
+        block_on(db.exec(
+            "CREATE TABLE users (id TEXT PRIMARY KEY, equivalent_domains TEXT NOT NULL DEFAULT '[]');
+             CREATE TABLE schema_migrations (version INTEGER PRIMARY KEY NOT NULL, name TEXT NOT NULL, applied_at TEXT NOT NULL);",
+        ))
+        .unwrap();
+
+        let mut summary = MigrationSummary::default();
+        block_on(apply(
+            &db,
+            migration("0008_add_eq_domains.sql"),
+            false,
+            &mut summary,
+        ))
+        .unwrap();
+
+        assert_eq!(summary.applied, vec!["0008_add_eq_domains.sql"]);
+        assert_eq!(
+            columns(&db, "users"),
+            vec!["id", "equivalent_domains", "excluded_globals"]
+        );
+        assert!(block_on(has_table(&db, "global_equivalent_domains")).unwrap());
+        assert_eq!(block_on(applied_versions(&db)).unwrap(), HashSet::from([8]));
+    }
+
+    #[test]
+    fn backfills_counts_when_some_count_columns_exist() {
+        let db = Db::in_memory().unwrap();
+        block_on(db.exec(
+            "CREATE TABLE users (id TEXT PRIMARY KEY, cipher_count INTEGER NOT NULL DEFAULT 0);
+             CREATE TABLE ciphers (id TEXT PRIMARY KEY, user_id TEXT);
+             CREATE TABLE folders (id TEXT PRIMARY KEY, user_id TEXT);
+             CREATE TABLE sends (id TEXT PRIMARY KEY, user_id TEXT);
+             CREATE TABLE schema_migrations (version INTEGER PRIMARY KEY NOT NULL, name TEXT NOT NULL, applied_at TEXT NOT NULL);
+             INSERT INTO users (id) VALUES ('u1');
+             INSERT INTO ciphers (id, user_id) VALUES ('c1', 'u1'), ('c2', 'u1');
+             INSERT INTO folders (id, user_id) VALUES ('f1', 'u1');",
+        ))
+        .unwrap();
+
+        let mut summary = MigrationSummary::default();
+        block_on(apply(
+            &db,
+            migration("0027_add_user_object_counts.sql"),
+            false,
+            &mut summary,
+        ))
+        .unwrap();
+
+        let counts: Value = block_on(db.first(
+            "SELECT cipher_count, folder_count, send_count FROM users WHERE id = 'u1'",
+            &[],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            counts,
+            serde_json::json!({ "cipher_count": 2, "folder_count": 1, "send_count": 0 })
+        );
+    }
+}
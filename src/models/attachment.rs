@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AttachmentDB {
@@ -14,7 +15,7 @@ pub struct AttachmentDB {
     pub organization_id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AttachmentResponse {
     pub id: String,
@@ -46,7 +47,7 @@ impl AttachmentDB {
     }
 }
 
-fn display_size(bytes: i64) -> String {
+pub(crate) fn display_size(bytes: i64) -> String {
     if bytes < 0 {
         return "0 B".to_string();
     }
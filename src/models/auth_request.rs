@@ -0,0 +1,97 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::models::device::device_type_name;
+use crate::time;
+
+/// Auth requests ("login with device") expire this long after creation.
+pub const AUTH_REQUEST_TTL_MINUTES: i64 = 15;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthRequest {
+    pub id: String,
+    pub user_id: String,
+    pub request_device_identifier: String,
+    pub device_type: i32,
+    pub request_ip: String,
+    pub response_device_id: Option<String>,
+    pub access_code: String,
+    pub public_key: String,
+    pub enc_key: Option<String>,
+    pub master_password_hash: Option<String>,
+    /// NULL while pending, 1 when approved, 0 when denied.
+    pub approved: Option<i32>,
+    pub creation_date: String,
+    pub response_date: Option<String>,
+    pub authentication_date: Option<String>,
+}
+
+impl AuthRequest {
+    pub fn is_approved(&self) -> bool {
+        self.approved == Some(1)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.approved.is_none()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        time::parse_bw(&self.creation_date)
+            .map(|created| created + Duration::minutes(AUTH_REQUEST_TTL_MINUTES) < Utc::now())
+            .unwrap_or(true)
+    }
+
+    pub fn to_json(&self, origin: &str) -> Value {
+        json!({
+            "id": self.id,
+            "publicKey": self.public_key,
+            "requestDeviceType": device_type_name(self.device_type),
+            "requestDeviceTypeValue": self.device_type,
+            "requestDeviceIdentifier": self.request_device_identifier,
+            "requestIpAddress": self.request_ip,
+            "key": self.enc_key,
+            "masterPasswordHash": self.master_password_hash,
+            "creationDate": self.creation_date,
+            "responseDate": self.response_date,
+            "requestApproved": self.approved.map(|a| a == 1),
+            "origin": origin,
+            "object": "auth-request",
+        })
+    }
+}
+
+/// Cutoff timestamp: requests created before this are expired.
+pub fn expiry_cutoff() -> String {
+    time::format_bw(Utc::now() - Duration::minutes(AUTH_REQUEST_TTL_MINUTES))
+}
+
+// For POST /api/auth-requests request
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthRequestCreate {
+    pub email: String,
+    pub public_key: String,
+    pub device_identifier: String,
+    pub access_code: String,
+    /// 0 = AuthenticateAndUnlock, 1 = Unlock, 2 = AdminApproval
+    #[serde(rename = "type")]
+    #[allow(dead_code)] // Only AuthenticateAndUnlock is supported
+    pub request_type: Option<i32>,
+}
+
+// For PUT /api/auth-requests/{id} request
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthRequestUpdate {
+    pub device_identifier: String,
+    pub key: Option<String>,
+    pub master_password_hash: Option<String>,
+    pub request_approved: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthResponseQuery {
+    pub code: String,
+}
@@ -2,8 +2,13 @@ use std::collections::HashMap;
 
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{json, Map, Value};
+use utoipa::openapi::schema::{
+    ArrayBuilder, KnownFormat, ObjectBuilder, Ref, Schema, SchemaFormat, SchemaType, Type,
+};
+use utoipa::openapi::RefOr;
+use utoipa::{PartialSchema, ToSchema};
 
-use crate::models::attachment::AttachmentResponse;
+use crate::models::{attachment::AttachmentResponse, patch::Patch, serde_d1};
 
 // Cipher types:
 //   Login = 1,
@@ -15,26 +20,59 @@ use crate::models::attachment::AttachmentResponse;
 /// Common cipher type-specific fields shared across multiple cipher structures.
 /// These represent the encrypted content fields that vary based on cipher type.
 /// Used with `#[serde(flatten)]` to embed these fields into other structs.
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// Older clients send PascalCase keys; those of the layout clients define are stored camelCase.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CipherTypeFields {
     // Only one of these should exist, depending on cipher type
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "Login", default, deserialize_with = "deserialize_login")]
     pub login: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        alias = "Card",
+        default,
+        deserialize_with = "deserialize_camel_case_keys"
+    )]
     pub card: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        alias = "Identity",
+        default,
+        deserialize_with = "deserialize_camel_case_keys"
+    )]
     pub identity: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        alias = "SecureNote",
+        default,
+        deserialize_with = "deserialize_camel_case_keys"
+    )]
     pub secure_note: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        alias = "SshKey",
+        default,
+        deserialize_with = "deserialize_camel_case_keys"
+    )]
     pub ssh_key: Option<Value>,
     // Common fields
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        alias = "Fields",
+        default,
+        deserialize_with = "deserialize_camel_case_keys"
+    )]
     pub fields: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        alias = "PasswordHistory",
+        default,
+        deserialize_with = "deserialize_camel_case_keys"
+    )]
     pub password_history: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "Reprompt")]
     pub reprompt: Option<i32>,
 }
 
@@ -49,46 +87,75 @@ pub struct CipherData {
     pub type_fields: CipherTypeFields,
 }
 
-// Custom deserialization function for booleans
-fn deserialize_bool_from_int<'de, D>(deserializer: D) -> Result<bool, D::Error>
+// Type-specific objects from older clients have PascalCase keys (`Login.Uris[].Match`); the
+// clients only read camelCase ones back. Only the keys of the layout the clients define are
+// rewritten; anything nested deeper is stored as sent.
+fn deserialize_camel_case_keys<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    // A visitor is used to handle different data types
-    struct BoolOrIntVisitor;
-
-    impl<'de> de::Visitor<'de> for BoolOrIntVisitor {
-        type Value = bool;
+    Ok(Option::<Value>::deserialize(deserializer)?.map(camel_case_keys))
+}
 
-        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("a boolean or an integer 0 or 1")
+/// A login's own keys, and those of its URIs and passkeys.
+fn deserialize_login<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<Value>::deserialize(deserializer)?.map(|login| {
+        let mut login = camel_case_keys(login);
+        for key in ["uris", "fido2Credentials"] {
+            if let Some(value) = login.get_mut(key) {
+                *value = camel_case_keys(value.take());
+            }
         }
+        login
+    }))
+}
 
-        // Handles boolean values
-        fn visit_bool<E>(self, value: bool) -> Result<bool, E>
-        where
-            E: de::Error,
-        {
-            Ok(value)
-        }
+/// The keys of an object, or of each object in an array, with their first letter lowercased.
+fn camel_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (camel_case(key), value))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    Value::Object(_) => camel_case_keys(value),
+                    value => value,
+                })
+                .collect(),
+        ),
+        value => value,
+    }
+}
 
-        // Handles integer values (0 or 1)
-        fn visit_u64<E>(self, value: u64) -> Result<bool, E>
-        where
-            E: de::Error,
-        {
-            match value {
-                0 => Ok(false),
-                1 => Ok(true),
-                _ => Err(de::Error::invalid_value(
-                    de::Unexpected::Unsigned(value),
-                    &"0 or 1",
-                )),
-            }
+/// `key` in camelCase the way .NET writes it: the leading capitals are lowercased, except one
+/// starting the next word (`SSN` is `ssn`, `URLValue` is `urlValue`).
+fn camel_case(key: String) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    let mut leading = 0;
+    while leading < chars.len() && chars[leading].is_uppercase() {
+        let starts_word = chars
+            .get(leading + 1)
+            .is_some_and(|next| !next.is_uppercase());
+        if leading > 0 && starts_word {
+            break;
         }
+        leading += 1;
     }
-
-    deserializer.deserialize_any(BoolOrIntVisitor)
+    if leading == 0 {
+        return key;
+    }
+    chars[..leading]
+        .iter()
+        .flat_map(|c| c.to_lowercase())
+        .chain(chars[leading..].iter().copied())
+        .collect()
 }
 
 // Custom deserialization function for cipher types
@@ -119,7 +186,7 @@ pub struct Cipher {
     #[serde(rename = "type")]
     pub r#type: i32,
     pub data: Value,
-    #[serde(deserialize_with = "deserialize_bool_from_int")]
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
     pub favorite: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub folder_id: Option<String>,
@@ -132,13 +199,13 @@ pub struct Cipher {
     #[serde(default = "default_object")]
     pub object: String,
     #[serde(default)]
-    #[serde(deserialize_with = "deserialize_bool_from_int")]
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
     pub organization_use_totp: bool,
     #[serde(default = "default_true")]
-    #[serde(deserialize_with = "deserialize_bool_from_int")]
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
     pub edit: bool,
     #[serde(default = "default_true")]
-    #[serde(deserialize_with = "deserialize_bool_from_int")]
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
     pub view_password: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub collection_ids: Option<Vec<String>>,
@@ -149,14 +216,23 @@ pub struct Cipher {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CipherDBModel {
     pub id: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub user_id: String,
+    #[serde(default, deserialize_with = "serde_d1::deserialize_option_string")]
     pub organization_id: Option<String>,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
     pub r#type: i32,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub data: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
     pub favorite: i32,
+    #[serde(default, deserialize_with = "serde_d1::deserialize_option_string")]
     pub folder_id: Option<String>,
+    #[serde(default, deserialize_with = "serde_d1::deserialize_option_string")]
     pub deleted_at: Option<String>,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub created_at: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub updated_at: String,
 }
 
@@ -174,6 +250,7 @@ impl From<CipherDBModel> for Cipher {
             created_at: val.created_at,
             updated_at: val.updated_at,
             object: default_object(),
+            // Access and organization settings are filled in by the handlers
             organization_use_totp: false,
             edit: true,
             view_password: true,
@@ -246,7 +323,7 @@ impl Serialize for Cipher {
                 data_clone
                     .get("reprompt")
                     .cloned()
-                    .unwrap_or(Value::Number(serde_json::Number::from_f64(0.0).unwrap())),
+                    .unwrap_or(Value::Number(0.into())),
             );
 
             let mut login = Value::Null;
@@ -286,6 +363,92 @@ impl Serialize for Cipher {
     }
 }
 
+/// The JSON `Serialize` above writes, for the OpenAPI document; change the two together.
+impl PartialSchema for Cipher {
+    fn schema() -> RefOr<Schema> {
+        let string = || ObjectBuilder::new().schema_type(Type::String);
+        let nullable = |schema_type: Type| {
+            ObjectBuilder::new().schema_type(SchemaType::from_iter([schema_type, Type::Null]))
+        };
+        let boolean = || ObjectBuilder::new().schema_type(Type::Boolean);
+        let date = || string().format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime)));
+        let list = |items: RefOr<Schema>| {
+            ArrayBuilder::new()
+                .schema_type(SchemaType::from_iter([Type::Array, Type::Null]))
+                .items(items)
+        };
+
+        let mut schema = ObjectBuilder::new()
+            .property("object", string().enum_values(Some(["cipherDetails"])))
+            .property("id", string())
+            .property("userId", string())
+            .property("organizationId", nullable(Type::String))
+            .property("folderId", nullable(Type::String))
+            .property(
+                "type",
+                ObjectBuilder::new()
+                    .schema_type(Type::Integer)
+                    .description(Some("1 Login, 2 SecureNote, 3 Card, 4 Identity, 5 SshKey")),
+            )
+            .property("favorite", boolean())
+            .property("edit", boolean())
+            .property("viewPassword", boolean())
+            .property(
+                "permissions",
+                ObjectBuilder::new()
+                    .property("delete", boolean())
+                    .property("restore", boolean()),
+            )
+            .property("organizationUseTotp", boolean())
+            .property("collectionIds", list(string().into()))
+            .property("revisionDate", date())
+            .property("creationDate", date())
+            .property(
+                "deletedDate",
+                nullable(Type::String)
+                    .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime))),
+            )
+            .property(
+                "attachments",
+                list(Ref::from_schema_name(AttachmentResponse::name()).into()),
+            )
+            .property("name", string().description(Some("Encrypted")))
+            .property(
+                "notes",
+                nullable(Type::String).description(Some("Encrypted")),
+            )
+            .property("fields", list(ObjectBuilder::new().into()))
+            .property("passwordHistory", list(ObjectBuilder::new().into()))
+            .property("reprompt", ObjectBuilder::new().schema_type(Type::Integer));
+        // Only the object of the cipher's type is set, the others are null
+        for field in ["login", "secureNote", "card", "identity", "sshKey"] {
+            schema = schema.property(field, nullable(Type::Object));
+        }
+        for field in [
+            "object",
+            "id",
+            "type",
+            "name",
+            "favorite",
+            "edit",
+            "revisionDate",
+            "creationDate",
+        ] {
+            schema = schema.required(field);
+        }
+        schema.into()
+    }
+}
+
+impl ToSchema for Cipher {
+    fn schemas(schemas: &mut Vec<(String, RefOr<Schema>)>) {
+        schemas.push((
+            AttachmentResponse::name().into(),
+            AttachmentResponse::schema(),
+        ));
+    }
+}
+
 fn default_object() -> String {
     "cipherDetails".to_string()
 }
@@ -297,48 +460,72 @@ fn default_true() -> bool {
 /// Represents the "Cipher" object within incoming request payloads.
 /// Used for create, update, import, and key rotation scenarios.
 /// Aligned with vaultwarden's CipherData structure.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+///
+/// On update, the encrypted content (name, notes and the type-specific fields) is replaced as a
+/// whole, so notes left out are cleared just like `"notes": null`. The other fields only change
+/// when sent:
+/// - `folderId`: left out keeps the folder, `null` takes the cipher out of it.
+/// - `favorite`: left out keeps the flag, `null` unmarks the cipher.
+/// - `organizationId`: only read when creating, or to move a personal cipher into an
+///   organization; a cipher never leaves its organization through an update.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CipherRequestData {
     // Id is optional as it is included only in bulk share / key rotation
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "Id")]
     pub id: Option<String>,
     // Folder id is not included in import (determined by folder_relationships)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub folder_id: Option<String>,
-    #[serde(alias = "organizationID")]
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    #[serde(alias = "FolderId")]
+    #[schema(value_type = Option<String>)]
+    pub folder_id: Patch<String>,
+    #[serde(alias = "organizationID", alias = "OrganizationId")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub organization_id: Option<String>,
     #[serde(rename = "type")]
     #[serde(deserialize_with = "deserialize_cipher_type")]
+    #[serde(alias = "Type")]
     pub r#type: i32,
+    #[serde(alias = "Name")]
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "Notes")]
     pub notes: Option<String>,
-    #[serde(default)]
-    pub favorite: Option<bool>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    #[serde(alias = "Favorite")]
+    #[schema(value_type = Option<bool>)]
+    pub favorite: Patch<bool>,
     #[serde(flatten)]
     pub type_fields: CipherTypeFields,
     /// Used during key rotation to update attachment keys and encrypted filenames.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "Attachments2")]
     pub attachments2: Option<HashMap<String, Attachments2Data>>,
+    /// Id of the user whose key the cipher was encrypted with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "EncryptedFor")]
+    pub encrypted_for: Option<String>,
     // The revision datetime (in ISO 8601 format) of the client's local copy
     // Used to prevent updating a cipher when client doesn't have the latest version
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "LastKnownRevisionDate")]
     pub last_known_revision_date: Option<String>,
 }
 
 /// Attachment metadata sent by clients during key rotation.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Attachments2Data {
+    #[serde(alias = "FileName")]
     pub file_name: String,
+    #[serde(alias = "Key")]
     pub key: String,
 }
 
 /// Represents the full request payload for creating a cipher with collections.
 /// Supports both camelCase and PascalCase for compatibility with different clients.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateCipherRequest {
     #[serde(alias = "Cipher")]
@@ -359,10 +546,150 @@ pub struct CipherListResponse {
     pub continuation_token: Option<String>,
 }
 
-/// Request body for updating a cipher partially (PUT /api/ciphers/{id}/partial)
-#[derive(Debug, Deserialize)]
+/// Request body for updating a cipher partially (PUT /api/ciphers/{id}/partial). Fields left
+/// out are kept; `null` takes the cipher out of its folder or unmarks it as a favorite.
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PartialCipherData {
-    pub folder_id: Option<String>,
-    pub favorite: bool,
+    #[serde(default, alias = "FolderId")]
+    #[schema(value_type = Option<String>)]
+    pub folder_id: Patch<String>,
+    #[serde(default, alias = "Favorite")]
+    #[schema(value_type = Option<bool>)]
+    pub favorite: Patch<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A login as clients before the switch to camelCase sent it to `POST /api/ciphers/create`.
+    fn pascal_case_request() -> Value {
+        json!({
+            "Cipher": {
+                "Type": 1,
+                "Name": "2.bmFtZQ==|aXY=|bWFj",
+                "Notes": null,
+                "FolderId": "folder",
+                "Favorite": true,
+                "Login": {
+                    "Username": "2.dXNlcg==|aXY=|bWFj",
+                    "Password": "2.cGFzcw==|aXY=|bWFj",
+                    "Totp": null,
+                    "Uris": [{ "Uri": "2.dXJp|aXY=|bWFj", "Match": 3 }],
+                    "Fido2Credentials": [{ "CredentialId": "2.aWQ=|aXY=|bWFj", "Counter": "2.MA==|aXY=|bWFj" }],
+                },
+                "Fields": [{ "Name": "2.bg==|aXY=|bWFj", "Value": "2.dg==|aXY=|bWFj", "Type": 0, "LinkedId": null }],
+                "PasswordHistory": [{ "Password": "2.b2xk|aXY=|bWFj", "LastUsedDate": "2024-01-01T00:00:00.000Z" }],
+                "Reprompt": 1,
+            },
+            "CollectionIds": ["collection"],
+        })
+    }
+
+    #[test]
+    fn reads_pascal_case_requests_from_older_clients() {
+        let request: CreateCipherRequest = serde_json::from_value(pascal_case_request()).unwrap();
+        let cipher = request.cipher;
+
+        assert_eq!(request.collection_ids, vec!["collection"]);
+        assert_eq!(cipher.r#type, 1);
+        assert_eq!(cipher.name, "2.bmFtZQ==|aXY=|bWFj");
+        assert!(matches!(cipher.folder_id, Patch::Value(ref id) if id == "folder"));
+        assert!(matches!(cipher.favorite, Patch::Value(true)));
+        assert_eq!(cipher.type_fields.reprompt, Some(1));
+        assert_eq!(
+            cipher.type_fields.login,
+            Some(json!({
+                "username": "2.dXNlcg==|aXY=|bWFj",
+                "password": "2.cGFzcw==|aXY=|bWFj",
+                "totp": null,
+                "uris": [{ "uri": "2.dXJp|aXY=|bWFj", "match": 3 }],
+                "fido2Credentials": [{ "credentialId": "2.aWQ=|aXY=|bWFj", "counter": "2.MA==|aXY=|bWFj" }],
+            }))
+        );
+        assert_eq!(
+            cipher.type_fields.fields,
+            Some(
+                json!([{ "name": "2.bg==|aXY=|bWFj", "value": "2.dg==|aXY=|bWFj", "type": 0, "linkedId": null }])
+            )
+        );
+        assert_eq!(
+            cipher.type_fields.password_history,
+            Some(
+                json!([{ "password": "2.b2xk|aXY=|bWFj", "lastUsedDate": "2024-01-01T00:00:00.000Z" }])
+            )
+        );
+    }
+
+    #[test]
+    fn reads_pascal_case_card_and_identity_keys() {
+        let fields: CipherTypeFields = serde_json::from_value(json!({
+            "Card": { "CardholderName": "2.bmFtZQ==|aXY=|bWFj", "ExpMonth": "2.MQ==|aXY=|bWFj" },
+            "Identity": { "FirstName": "2.Zmlyc3Q=|aXY=|bWFj", "SSN": null },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            fields.card,
+            Some(
+                json!({ "cardholderName": "2.bmFtZQ==|aXY=|bWFj", "expMonth": "2.MQ==|aXY=|bWFj" })
+            )
+        );
+        assert_eq!(
+            fields.identity,
+            Some(json!({ "firstName": "2.Zmlyc3Q=|aXY=|bWFj", "ssn": null }))
+        );
+    }
+
+    #[test]
+    fn camel_cases_keys_like_dotnet() {
+        for (key, expected) in [
+            ("Uri", "uri"),
+            ("CardholderName", "cardholderName"),
+            ("Fido2Credentials", "fido2Credentials"),
+            ("SSN", "ssn"),
+            ("URLValue", "urlValue"),
+            ("uri", "uri"),
+        ] {
+            assert_eq!(camel_case(key.to_string()), expected);
+        }
+    }
+
+    #[test]
+    fn leaves_keys_below_the_layout_alone() {
+        let fields: CipherTypeFields = serde_json::from_value(json!({
+            "Login": {
+                "Uris": [{ "Uri": "2.dXJp|aXY=|bWFj", "Extra": { "KeepMe": 1 } }],
+                "Extra": { "KeepMe": [{ "AndMe": 2 }] },
+            },
+            "Fields": [{ "Name": "2.bg==|aXY=|bWFj", "Extra": { "KeepMe": 3 } }],
+        }))
+        .unwrap();
+
+        assert_eq!(
+            fields.login,
+            Some(json!({
+                "uris": [{ "uri": "2.dXJp|aXY=|bWFj", "extra": { "KeepMe": 1 } }],
+                "extra": { "KeepMe": [{ "AndMe": 2 }] },
+            }))
+        );
+        assert_eq!(
+            fields.fields,
+            Some(json!([{ "name": "2.bg==|aXY=|bWFj", "extra": { "KeepMe": 3 } }]))
+        );
+    }
+
+    #[test]
+    fn camel_case_requests_are_stored_as_sent() {
+        let login = json!({
+            "username": "2.dXNlcg==|aXY=|bWFj",
+            "uris": [{ "uri": "2.dXJp|aXY=|bWFj", "match": null }],
+        });
+        let fields: CipherTypeFields =
+            serde_json::from_value(json!({ "login": login.clone(), "reprompt": 0 })).unwrap();
+
+        assert_eq!(fields.login, Some(login));
+        assert_eq!(fields.reprompt, Some(0));
+    }
 }
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: String,
+    pub organization_id: String,
+    /// Encrypted with the organization key.
+    pub name: String,
+    pub external_id: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl Collection {
+    /// Collection as listed on the organization's pages (`collection`).
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "organizationId": self.organization_id,
+            "name": self.name,
+            "externalId": self.external_id,
+            "object": "collection",
+        })
+    }
+
+    /// Collection with the requesting member's access to it (`collectionDetails`), used by the
+    /// vault sidebar and `/api/sync`.
+    pub fn to_details_json(&self, access: &CollectionAccess) -> Value {
+        json!({
+            "id": self.id,
+            "organizationId": self.organization_id,
+            "name": self.name,
+            "externalId": self.external_id,
+            "readOnly": access.read_only,
+            "hidePasswords": access.hide_passwords,
+            "manage": access.manage,
+            "object": "collectionDetails",
+        })
+    }
+
+    /// Collection with every member's and group's access to it (`collectionAccessDetails`), for
+    /// the admin console. `assigned` tells whether the requesting member is assigned to it.
+    pub fn to_access_details_json(
+        &self,
+        access: &CollectionAccess,
+        assigned: bool,
+        groups: Vec<Value>,
+        users: Vec<Value>,
+    ) -> Value {
+        json!({
+            "id": self.id,
+            "organizationId": self.organization_id,
+            "name": self.name,
+            "externalId": self.external_id,
+            "groups": groups,
+            "users": users,
+            "assigned": assigned,
+            "readOnly": access.read_only,
+            "hidePasswords": access.hide_passwords,
+            "manage": access.manage,
+            "object": "collectionAccessDetails",
+        })
+    }
+}
+
+/// What a member may do with a collection's items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollectionAccess {
+    pub read_only: bool,
+    pub hide_passwords: bool,
+    pub manage: bool,
+}
+
+impl CollectionAccess {
+    /// Access of owners, admins and members with access to every collection.
+    pub const FULL: CollectionAccess = CollectionAccess {
+        read_only: false,
+        hide_passwords: false,
+        manage: true,
+    };
+}
+
+/// A row of `collections_users`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionUser {
+    pub collection_id: String,
+    pub membership_id: String,
+    pub read_only: i32,
+    pub hide_passwords: i32,
+    pub manage: i32,
+}
+
+impl CollectionUser {
+    pub fn access(&self) -> CollectionAccess {
+        CollectionAccess {
+            read_only: self.read_only != 0,
+            hide_passwords: self.hide_passwords != 0,
+            manage: self.manage != 0,
+        }
+    }
+}
+
+// For POST /api/organizations/{id}/collections and PUT /api/organizations/{id}/collections/{id}
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionRequest {
+    pub name: String,
+    pub external_id: Option<String>,
+    /// Member assignments; `None` leaves them untouched on update.
+    pub users: Option<Vec<CollectionAccessRequest>>,
+    /// Group access; `None` leaves it untouched on update. Ignored unless groups are enabled.
+    pub groups: Option<Vec<CollectionAccessRequest>>,
+}
+
+/// Access granted to a member or group on a collection.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionAccessRequest {
+    /// Membership or group id in collection payloads, collection id in member and group
+    /// payloads.
+    pub id: String,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub hide_passwords: bool,
+    #[serde(default)]
+    pub manage: bool,
+}
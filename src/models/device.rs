@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::models::serde_d1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub user_id: String,
+    /// Client-generated device identifier (stable per install).
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub identifier: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub name: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
+    pub atype: i32,
+    pub encrypted_user_key: Option<String>,
+    pub encrypted_public_key: Option<String>,
+    pub encrypted_private_key: Option<String>,
+    /// Id (`jti`) of the refresh token currently issued to this device.
+    pub refresh_token_id: Option<String>,
+    pub last_active_at: Option<String>,
+    pub push_uuid: Option<String>,
+    pub push_token: Option<String>,
+    /// Web Push subscription (browser clients).
+    pub web_push_endpoint: Option<String>,
+    pub web_push_p256dh: Option<String>,
+    pub web_push_auth: Option<String>,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub created_at: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub updated_at: String,
+}
+
+impl Device {
+    /// Whether trusted device encryption keys are stored for this device.
+    pub fn is_trusted(&self) -> bool {
+        self.encrypted_user_key.is_some()
+            && self.encrypted_public_key.is_some()
+            && self.encrypted_private_key.is_some()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "type": self.atype,
+            "identifier": self.identifier,
+            "creationDate": self.created_at,
+            "revisionDate": self.updated_at,
+            "isTrusted": self.is_trusted(),
+            "isActive": self.refresh_token_id.is_some(),
+            "lastActivityDate": self.last_active_at,
+            "encryptedUserKey": self.encrypted_user_key,
+            "encryptedPublicKey": self.encrypted_public_key,
+            "devicePendingAuthRequest": null,
+            "object": "device",
+        })
+    }
+
+    /// `ProtectedDeviceResponseModel`, returned when a trusted device retrieves its keys.
+    pub fn to_protected_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "type": self.atype,
+            "identifier": self.identifier,
+            "creationDate": self.created_at,
+            "encryptedUserKey": self.encrypted_user_key,
+            "encryptedPublicKey": self.encrypted_public_key,
+            "object": "protectedDevice",
+        })
+    }
+}
+
+/// Display name of a Bitwarden `DeviceType`.
+pub fn device_type_name(device_type: i32) -> &'static str {
+    match device_type {
+        0 => "Android",
+        1 => "iOS",
+        2 => "Chrome Extension",
+        3 => "Firefox Extension",
+        4 => "Opera Extension",
+        5 => "Edge Extension",
+        6 => "Windows",
+        7 => "macOS",
+        8 => "Linux",
+        9 => "Chrome",
+        10 => "Firefox",
+        11 => "Opera",
+        12 => "Edge",
+        13 => "Internet Explorer",
+        15 => "Android",
+        16 => "UWP",
+        17 => "Safari",
+        18 => "Vivaldi",
+        19 => "Vivaldi Extension",
+        20 => "Safari Extension",
+        21 => "SDK",
+        22 => "Server",
+        23 => "Windows CLI",
+        24 => "macOS CLI",
+        25 => "Linux CLI",
+        _ => "Unknown Browser",
+    }
+}
+
+// For PUT /api/devices/{identifier}/keys request
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceKeysRequest {
+    pub encrypted_user_key: String,
+    pub encrypted_public_key: String,
+    pub encrypted_private_key: String,
+}
+
+// For PUT /api/devices/identifier/{identifier}/web-push-auth and POST /api/web-push/register.
+// Accepts Bitwarden's flat model as well as a raw `PushSubscription.toJSON()`.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebPushAuthRequest {
+    pub endpoint: String,
+    pub p256dh: Option<String>,
+    pub auth: Option<String>,
+    pub keys: Option<WebPushKeys>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct WebPushKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+impl WebPushAuthRequest {
+    /// Returns `(endpoint, p256dh, auth)`, wherever the client put the keys.
+    pub fn into_parts(self) -> Option<(String, String, String)> {
+        match (self.p256dh, self.auth, self.keys) {
+            (Some(p256dh), Some(auth), _) => Some((self.endpoint, p256dh, auth)),
+            (_, _, Some(keys)) => Some((self.endpoint, keys.p256dh, keys.auth)),
+            _ => None,
+        }
+    }
+}
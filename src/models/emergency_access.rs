@@ -0,0 +1,157 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::time;
+
+/// What a grantee may do once access is granted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum EmergencyAccessType {
+    View = 0,
+    Takeover = 1,
+}
+
+impl EmergencyAccessType {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(EmergencyAccessType::View),
+            1 => Some(EmergencyAccessType::Takeover),
+            _ => None,
+        }
+    }
+}
+
+/// Emergency access lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum EmergencyAccessStatus {
+    Invited = 0,
+    Accepted = 1,
+    Confirmed = 2,
+    RecoveryInitiated = 3,
+    RecoveryApproved = 4,
+}
+
+/// A row of `emergency_access`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmergencyAccess {
+    pub id: String,
+    pub grantor_id: String,
+    pub grantee_id: Option<String>,
+    /// Invited address, lowercase.
+    pub email: String,
+    /// Grantor's user key encrypted with the grantee's public key, set once confirmed.
+    pub key_encrypted: Option<String>,
+    pub atype: i32,
+    pub status: i32,
+    pub wait_time_days: i32,
+    /// When the grantee requested access; set while a recovery is initiated or approved.
+    pub recovery_initiated_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// The user on the other side of an emergency access grant.
+#[derive(Debug, Deserialize)]
+pub struct EmergencyContact {
+    pub id: String,
+    pub name: Option<String>,
+    pub email: String,
+    pub avatar_color: Option<String>,
+}
+
+impl EmergencyAccess {
+    /// Whether an initiated recovery has waited out the grantor's wait time, so it counts as
+    /// approved even though the grantor never answered.
+    pub fn recovery_wait_elapsed(&self, now: DateTime<Utc>) -> bool {
+        self.status == EmergencyAccessStatus::RecoveryInitiated as i32
+            && self
+                .recovery_initiated_at
+                .as_deref()
+                .and_then(time::parse_bw)
+                .is_some_and(|initiated| {
+                    initiated + Duration::days(self.wait_time_days.into()) <= now
+                })
+    }
+
+    /// Grant as listed by the grantor (`emergencyAccessGranteeDetails`). `grantee` is `None`
+    /// until the invitation has been accepted.
+    pub fn to_grantee_details_json(&self, grantee: Option<&EmergencyContact>) -> Value {
+        json!({
+            "id": self.id,
+            "granteeId": self.grantee_id,
+            "name": grantee.and_then(|user| user.name.as_deref()),
+            "email": grantee.map_or(self.email.as_str(), |user| user.email.as_str()),
+            "avatarColor": grantee.and_then(|user| user.avatar_color.as_deref()),
+            "type": self.atype,
+            "status": self.status,
+            "waitTimeDays": self.wait_time_days,
+            "creationDate": self.created_at,
+            "object": "emergencyAccessGranteeDetails",
+        })
+    }
+
+    /// Grant as listed by the grantee (`emergencyAccessGrantorDetails`).
+    pub fn to_grantor_details_json(&self, grantor: Option<&EmergencyContact>) -> Value {
+        json!({
+            "id": self.id,
+            "grantorId": self.grantor_id,
+            "name": grantor.and_then(|user| user.name.as_deref()),
+            "email": grantor.map(|user| user.email.as_str()),
+            "avatarColor": grantor.and_then(|user| user.avatar_color.as_deref()),
+            "type": self.atype,
+            "status": self.status,
+            "waitTimeDays": self.wait_time_days,
+            "creationDate": self.created_at,
+            "object": "emergencyAccessGrantorDetails",
+        })
+    }
+}
+
+// For POST /api/emergency-access/invite requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessInviteRequest {
+    pub email: String,
+    #[serde(rename = "type")]
+    pub atype: i32,
+    pub wait_time_days: i32,
+}
+
+// For PUT /api/emergency-access/{id} requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessUpdateRequest {
+    #[serde(rename = "type")]
+    pub atype: i32,
+    pub wait_time_days: i32,
+    /// Re-encrypted user key, sent when the grantor rotates their key.
+    pub key_encrypted: Option<String>,
+}
+
+// For POST /api/emergency-access/{id}/accept requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessAcceptRequest {
+    /// Token from the invitation link.
+    pub token: String,
+}
+
+// For POST /api/emergency-access/{id}/confirm requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessConfirmRequest {
+    /// Grantor's user key encrypted with the grantee's public key.
+    pub key: String,
+}
+
+// For POST /api/emergency-access/{id}/password requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessPasswordRequest {
+    pub new_master_password_hash: String,
+    /// The grantor's user key encrypted with the new master key.
+    pub key: String,
+}
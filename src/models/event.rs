@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::time;
+
+/// Audit event types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Mirrors Bitwarden's EventType
+#[repr(i32)]
+pub enum EventType {
+    UserLoggedIn = 1000,
+    UserChangedPassword = 1001,
+    UserUpdated2fa = 1002,
+    UserDisabled2fa = 1003,
+    UserRecovered2fa = 1004,
+    UserFailedLogIn = 1005,
+    UserFailedLogIn2fa = 1006,
+    UserClientExportedVault = 1007,
+
+    CipherCreated = 1100,
+    CipherUpdated = 1101,
+    CipherDeleted = 1102,
+    CipherAttachmentCreated = 1103,
+    CipherAttachmentDeleted = 1104,
+    CipherShared = 1105,
+    CipherUpdatedCollections = 1106,
+    CipherSoftDeleted = 1115,
+    CipherRestored = 1116,
+
+    CollectionCreated = 1300,
+    CollectionUpdated = 1301,
+    CollectionDeleted = 1302,
+
+    GroupCreated = 1400,
+    GroupUpdated = 1401,
+    GroupDeleted = 1402,
+
+    OrganizationUserInvited = 1500,
+    OrganizationUserConfirmed = 1501,
+    OrganizationUserUpdated = 1502,
+    OrganizationUserRemoved = 1503,
+    OrganizationUserUpdatedGroups = 1504,
+    OrganizationUserResetPasswordEnroll = 1506,
+    OrganizationUserResetPasswordWithdraw = 1507,
+    OrganizationUserAdminResetPassword = 1508,
+    OrganizationUserRevoked = 1511,
+    OrganizationUserRestored = 1512,
+    OrganizationUserLeft = 1516,
+
+    OrganizationUpdated = 1600,
+    OrganizationPurgedVault = 1601,
+    OrganizationClientExportedVault = 1602,
+
+    PolicyUpdated = 1700,
+
+    // Not in Bitwarden, which has no operator API
+    /// An operator purged the user's vault through `POST /admin/users/{id}/purge-vault`.
+    AdminPurgedUserVault = 9000,
+    /// The user purged their own vault through `POST /api/ciphers/purge`.
+    UserPurgedVault = 9001,
+}
+
+/// A row of `events`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub atype: i32,
+    pub organization_id: Option<String>,
+    /// User the event is about.
+    pub user_id: Option<String>,
+    pub cipher_id: Option<String>,
+    pub collection_id: Option<String>,
+    pub group_id: Option<String>,
+    pub policy_id: Option<String>,
+    /// Membership the event is about.
+    pub member_id: Option<String>,
+    pub acting_user_id: Option<String>,
+    pub device_type: Option<i32>,
+    pub ip_address: Option<String>,
+    pub date: String,
+}
+
+impl Event {
+    /// An event happening now, caused by `acting_user_id`. Callers fill in what it's about.
+    pub fn new(atype: EventType, acting_user_id: &str) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            atype: atype as i32,
+            organization_id: None,
+            user_id: None,
+            cipher_id: None,
+            collection_id: None,
+            group_id: None,
+            policy_id: None,
+            member_id: None,
+            acting_user_id: Some(acting_user_id.to_string()),
+            device_type: None,
+            ip_address: None,
+            date: time::now_bw(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "type": self.atype,
+            "userId": self.user_id,
+            "organizationId": self.organization_id,
+            "providerId": null,
+            "cipherId": self.cipher_id,
+            "collectionId": self.collection_id,
+            "groupId": self.group_id,
+            "policyId": self.policy_id,
+            "organizationUserId": self.member_id,
+            "providerUserId": null,
+            "providerOrganizationId": null,
+            "actingUserId": self.acting_user_id,
+            "installationId": null,
+            "date": self.date,
+            "deviceType": self.device_type,
+            "ipAddress": self.ip_address,
+            "systemUser": null,
+            "domainName": null,
+            "secretId": null,
+            "serviceAccountId": null,
+            "object": "event",
+        })
+    }
+}
@@ -1,16 +1,23 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::serde_d1;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Folder {
     pub id: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub user_id: String,
     // The name is encrypted client-side
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub name: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub created_at: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub updated_at: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct FolderResponse {
     pub id: String,
@@ -35,7 +42,8 @@ impl From<Folder> for FolderResponse {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateFolderRequest {
+    #[serde(alias = "Name")]
     pub name: String,
 }
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::models::collection::CollectionAccessRequest;
+
+/// A row of `groups`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Group {
+    pub id: String,
+    pub organization_id: String,
+    pub name: String,
+    pub external_id: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl Group {
+    /// Group as listed on the organization's pages (`group`).
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "organizationId": self.organization_id,
+            "name": self.name,
+            "externalId": self.external_id,
+            "object": "group",
+        })
+    }
+
+    /// Group with its collection access (`groupDetails`), for the edit dialog.
+    pub fn to_details_json(&self, collections: Vec<Value>) -> Value {
+        json!({
+            "id": self.id,
+            "organizationId": self.organization_id,
+            "name": self.name,
+            "externalId": self.external_id,
+            "collections": collections,
+            "object": "groupDetails",
+        })
+    }
+}
+
+// For POST /api/organizations/{id}/groups and PUT /api/organizations/{id}/groups/{group_id}
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupRequest {
+    pub name: String,
+    pub external_id: Option<String>,
+    /// Replaces the group's collection access.
+    #[serde(default)]
+    pub collections: Vec<CollectionAccessRequest>,
+    /// Membership ids; `None` leaves the group's members untouched.
+    pub users: Option<Vec<String>>,
+}
+
+// For PUT /api/organizations/{id}/users/{member_id}/groups requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberGroupsRequest {
+    pub group_ids: Vec<String>,
+}
@@ -1,6 +1,88 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
 
-use crate::models::cipher::CipherRequestData;
+use serde::de::{self, Deserializer};
+
+use crate::models::cipher::CipherTypeFields;
+
+/// Cipher types this server stores: Login, SecureNote, Card, Identity, SshKey.
+pub const SUPPORTED_CIPHER_TYPES: std::ops::RangeInclusive<i32> = 1..=5;
+
+// Cipher type of an import: an integer, or a numeric string as some converters write it. Unknown
+// types are let through so the import can skip just those items.
+fn deserialize_import_cipher_type<'de, D>(deserializer: D) -> Result<i32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(i64),
+        String(String),
+    }
+
+    let value = match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(value) => value,
+        NumberOrString::String(value) => value.trim().parse().map_err(|_| {
+            de::Error::invalid_value(de::Unexpected::Str(&value), &"a numeric cipher type")
+        })?,
+    };
+    i32::try_from(value)
+        .map_err(|_| de::Error::invalid_value(de::Unexpected::Signed(value), &"a cipher type"))
+}
+
+/// A cipher of an import payload. Like `CipherRequestData`, but tolerant of what old exports and
+/// converters produce; see [`ImportCipher::normalize`].
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportCipher {
+    #[serde(alias = "organizationID", alias = "OrganizationId")]
+    pub organization_id: Option<String>,
+    #[serde(rename = "type")]
+    #[serde(deserialize_with = "deserialize_import_cipher_type")]
+    #[serde(alias = "Type")]
+    pub r#type: i32,
+    #[serde(alias = "Name")]
+    pub name: String,
+    #[serde(alias = "Notes")]
+    pub notes: Option<String>,
+    #[serde(default)]
+    #[serde(alias = "Favorite")]
+    pub favorite: Option<bool>,
+    #[serde(flatten)]
+    pub type_fields: CipherTypeFields,
+    /// Id of the user whose key the cipher was encrypted with.
+    #[serde(alias = "EncryptedFor")]
+    pub encrypted_for: Option<String>,
+}
+
+impl ImportCipher {
+    pub fn is_supported(&self) -> bool {
+        SUPPORTED_CIPHER_TYPES.contains(&self.r#type)
+    }
+
+    /// Fills in what very old exports leave out: secure notes without their `secureNote`
+    /// object, or without its `type` (0, the only kind there is).
+    pub fn normalize(&mut self) {
+        if self.r#type != 2 {
+            return;
+        }
+        let secure_note = self
+            .type_fields
+            .secure_note
+            .get_or_insert_with(|| json!({}));
+        if secure_note.is_null() {
+            *secure_note = json!({});
+        }
+        if let Some(secure_note) = secure_note.as_object_mut() {
+            let note_type = secure_note.entry("type").or_insert(Value::Null);
+            if note_type.is_null() {
+                *note_type = json!(0);
+            }
+        }
+    }
+}
 
 /// Folder data structure for import requests.
 /// Aligned with vaultwarden's FolderData.
@@ -8,7 +90,9 @@ use crate::models::cipher::CipherRequestData;
 #[serde(rename_all = "camelCase")]
 pub struct ImportFolder {
     /// Optional folder ID - if provided and exists, the existing folder is used
+    #[serde(alias = "Id")]
     pub id: Option<String>,
+    #[serde(alias = "Name")]
     pub name: String,
 }
 
@@ -17,8 +101,10 @@ pub struct ImportFolder {
 #[serde(rename_all = "camelCase")]
 pub struct FolderRelationship {
     /// Cipher index in the ciphers array
+    #[serde(alias = "Key")]
     pub key: usize,
     /// Folder index in the folders array
+    #[serde(alias = "Value")]
     pub value: usize,
 }
 
@@ -27,8 +113,257 @@ pub struct FolderRelationship {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ImportRequest {
-    pub ciphers: Vec<CipherRequestData>,
+    #[serde(alias = "Ciphers")]
+    pub ciphers: Vec<ImportCipher>,
+    #[serde(alias = "Folders")]
+    pub folders: Vec<ImportFolder>,
+    #[serde(default)]
+    #[serde(alias = "FolderRelationships")]
+    pub folder_relationships: Vec<FolderRelationship>,
+    /// Only present in exports of organization items
+    #[serde(default)]
+    #[serde(alias = "Collections")]
+    pub collections: Vec<ImportCollection>,
+    #[serde(default)]
+    #[serde(alias = "CollectionRelationships")]
+    pub collection_relationships: Vec<CollectionRelationship>,
+    /// Required to replace the vault (`?replace=true`), which deletes everything in it first.
+    #[serde(alias = "MasterPasswordHash")]
+    pub master_password_hash: Option<String>,
+}
+
+/// Query of POST /api/ciphers/import.
+#[derive(Deserialize, Debug, Default)]
+pub struct ImportQuery {
+    /// Replace the personal vault with the export instead of adding to it. An extension for
+    /// migrations; official clients never send it.
+    #[serde(default)]
+    pub replace: bool,
+}
+
+/// Collection data structure for organization import requests.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportCollection {
+    /// Optional collection ID - if provided and it belongs to the organization, it is reused
+    #[serde(alias = "Id")]
+    pub id: Option<String>,
+    #[serde(alias = "Name")]
+    pub name: String,
+    #[serde(alias = "ExternalId")]
+    pub external_id: Option<String>,
+}
+
+/// Relationship between cipher index and collection index in the import arrays.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionRelationship {
+    /// Cipher index in the ciphers array
+    #[serde(alias = "Key")]
+    pub key: usize,
+    /// Collection index in the collections array
+    #[serde(alias = "Value")]
+    pub value: usize,
+}
+
+/// Organization import request payload structure.
+/// Aligned with vaultwarden's ImportData used in POST /ciphers/import-organization.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationImportRequest {
+    #[serde(alias = "Ciphers")]
+    pub ciphers: Vec<ImportCipher>,
+    #[serde(default)]
+    #[serde(alias = "Collections")]
+    pub collections: Vec<ImportCollection>,
+    #[serde(default)]
+    #[serde(alias = "CollectionRelationships")]
+    pub collection_relationships: Vec<CollectionRelationship>,
+}
+
+/// What an import did, returned instead of an empty body. Official clients ignore it.
+#[derive(Serialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSummary {
+    /// Ciphers and folders removed because the import replaced the vault
+    pub ciphers_deleted: usize,
+    pub folders_deleted: usize,
+    pub folders_inserted: usize,
+    /// Folders that already existed, or whose id was taken and couldn't be inserted
+    pub folders_skipped: usize,
+    pub collections_inserted: usize,
+    /// Collections of the organization that were reused
+    pub collections_skipped: usize,
+    pub ciphers_inserted: usize,
+    /// Folder and collection relationships that ended up on an imported cipher
+    pub relationships_resolved: usize,
+    /// Items that were imported differently than the export described
+    pub warnings: Vec<String>,
+}
+
+/// How long a chunked import session accepts chunks and can be committed.
+pub const IMPORT_SESSION_TTL_MINUTES: i64 = 60;
+
+/// A row of `import_sessions`.
+#[derive(Deserialize, Debug)]
+pub struct ImportSession {
+    pub id: String,
+    pub user_id: String,
+    pub expected_folders: Option<i64>,
+    pub expected_ciphers: Option<i64>,
+    pub committed_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub expires_at: String,
+}
+
+/// Items staged for an import session so far.
+#[derive(Deserialize, Debug)]
+pub struct ImportSessionProgress {
+    pub folders: i64,
+    pub ciphers: i64,
+    pub relationships: i64,
+}
+
+impl ImportSession {
+    pub fn to_json(&self, progress: &ImportSessionProgress) -> Value {
+        json!({
+            "id": self.id,
+            "expectedFolders": self.expected_folders,
+            "expectedCiphers": self.expected_ciphers,
+            "foldersReceived": progress.folders,
+            "ciphersReceived": progress.ciphers,
+            "folderRelationshipsReceived": progress.relationships,
+            "committed": self.committed_at.is_some(),
+            "committedDate": self.committed_at,
+            "creationDate": self.created_at,
+            "revisionDate": self.updated_at,
+            "expirationDate": self.expires_at,
+            "object": "importSession",
+        })
+    }
+}
+
+/// POST /api/ciphers/import/start payload. The totals are optional; when given, the commit
+/// checks that every item arrived.
+#[derive(Deserialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSessionStartRequest {
+    #[serde(alias = "Folders")]
+    pub folders: Option<usize>,
+    #[serde(alias = "Ciphers")]
+    pub ciphers: Option<usize>,
+}
+
+/// POST /api/ciphers/import/chunk payload: a slice of an export.
+///
+/// `folderOffset` and `cipherOffset` are the positions of the chunk's first folder and cipher in
+/// the whole export, and relationships use export-wide indexes, so they may point into other
+/// chunks. Sending a chunk again replaces what it staged before.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportChunkRequest {
+    #[serde(alias = "SessionId")]
+    pub session_id: String,
+    #[serde(default)]
+    #[serde(alias = "FolderOffset")]
+    pub folder_offset: usize,
+    #[serde(default)]
+    #[serde(alias = "Folders")]
     pub folders: Vec<ImportFolder>,
     #[serde(default)]
+    #[serde(alias = "CipherOffset")]
+    pub cipher_offset: usize,
+    #[serde(default)]
+    #[serde(alias = "Ciphers")]
+    pub ciphers: Vec<ImportCipher>,
+    #[serde(default)]
+    #[serde(alias = "FolderRelationships")]
     pub folder_relationships: Vec<FolderRelationship>,
 }
+
+/// POST /api/ciphers/import/commit payload.
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportCommitRequest {
+    #[serde(alias = "SessionId")]
+    pub session_id: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_pascal_case_imports_from_older_clients() {
+        let request: ImportRequest = serde_json::from_value(json!({
+            "Ciphers": [{
+                "Type": 2,
+                "Name": "2.bm90ZQ==|aXY=|bWFj",
+                "Notes": "2.dGV4dA==|aXY=|bWFj",
+                "Favorite": false,
+                "SecureNote": { "Type": 0 },
+                "Fields": [{ "Name": "2.bg==|aXY=|bWFj", "Value": null, "Type": 1 }],
+            }],
+            "Folders": [{ "Name": "2.Zm9sZGVy|aXY=|bWFj" }],
+            "FolderRelationships": [{ "Key": 0, "Value": 0 }],
+        }))
+        .unwrap();
+
+        let cipher = &request.ciphers[0];
+        assert_eq!(cipher.r#type, 2);
+        assert_eq!(cipher.notes.as_deref(), Some("2.dGV4dA==|aXY=|bWFj"));
+        assert_eq!(cipher.type_fields.secure_note, Some(json!({ "type": 0 })));
+        assert_eq!(
+            cipher.type_fields.fields,
+            Some(json!([{ "name": "2.bg==|aXY=|bWFj", "value": null, "type": 1 }]))
+        );
+        assert_eq!(request.folders[0].name, "2.Zm9sZGVy|aXY=|bWFj");
+        assert_eq!(request.folder_relationships[0].key, 0);
+        assert_eq!(request.folder_relationships[0].value, 0);
+    }
+
+    fn cipher(value: Value) -> ImportCipher {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn cipher_types_may_be_numeric_strings() {
+        assert_eq!(cipher(json!({ "type": 1, "name": "n" })).r#type, 1);
+        assert_eq!(cipher(json!({ "type": "3", "name": "n" })).r#type, 3);
+        assert_eq!(cipher(json!({ "Type": " 4 ", "Name": "n" })).r#type, 4);
+        // Unknown types get through, to be skipped by the import
+        let unknown = cipher(json!({ "type": "99", "name": "n" }));
+        assert_eq!(unknown.r#type, 99);
+        assert!(!unknown.is_supported());
+
+        for bad in [json!("login"), json!(1.5), json!(i64::MAX)] {
+            let result: Result<ImportCipher, _> =
+                serde_json::from_value(json!({ "type": bad, "name": "n" }));
+            assert!(result.is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn secure_notes_get_their_type() {
+        for secure_note in [
+            None,
+            Some(Value::Null),
+            Some(json!({})),
+            Some(json!({ "type": null })),
+        ] {
+            let mut note = json!({ "type": "2", "name": "n" });
+            if let Some(secure_note) = secure_note {
+                note["secureNote"] = secure_note;
+            }
+            let mut note = cipher(note);
+            note.normalize();
+            assert_eq!(note.type_fields.secure_note, Some(json!({ "type": 0 })));
+        }
+
+        // Other types are left alone
+        let mut login = cipher(json!({ "type": 1, "name": "n", "login": {} }));
+        login.normalize();
+        assert_eq!(login.type_fields.secure_note, None);
+    }
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A row of `invitations`: an address the operator invited to register.
+#[derive(Debug, Deserialize)]
+pub struct Invitation {
+    pub email: String,
+    pub created_at: String,
+    pub expires_at: String,
+}
+
+/// As listed by `GET /admin/invites`.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationResponse {
+    pub email: String,
+    pub creation_date: String,
+    pub expiration_date: String,
+}
+
+impl From<Invitation> for InvitationResponse {
+    fn from(invitation: Invitation) -> Self {
+        Self {
+            email: invitation.email,
+            creation_date: invitation.created_at,
+            expiration_date: invitation.expires_at,
+        }
+    }
+}
+
+// For POST /admin/invite request
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteRequest {
+    pub email: String,
+}
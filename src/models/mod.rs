@@ -1,7 +1,21 @@
 pub mod attachment;
+pub mod auth_request;
 pub mod cipher;
+pub mod collection;
+pub mod device;
+pub mod emergency_access;
+pub mod event;
 pub mod folder;
+pub mod group;
 pub mod import;
+pub mod invitation;
+pub mod organization;
+pub mod patch;
+pub mod policy;
+pub mod response;
+pub mod send;
+pub mod serde_d1;
 pub mod sync;
 pub mod twofactor;
 pub mod user;
+pub mod vaultwarden;
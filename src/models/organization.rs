@@ -0,0 +1,374 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::models::{collection::CollectionAccessRequest, serde_d1};
+
+/// Organization member roles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Mirrors Bitwarden's OrganizationUserType
+#[repr(i32)]
+pub enum MembershipType {
+    Owner = 0,
+    Admin = 1,
+    User = 2,
+    Manager = 3,
+    Custom = 4,
+}
+
+impl MembershipType {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(MembershipType::Owner),
+            1 => Some(MembershipType::Admin),
+            2 => Some(MembershipType::User),
+            3 => Some(MembershipType::Manager),
+            4 => Some(MembershipType::Custom),
+            _ => None,
+        }
+    }
+}
+
+/// Organization membership lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Mirrors Bitwarden's OrganizationUserStatusType
+#[repr(i32)]
+pub enum MembershipStatus {
+    Revoked = -1,
+    Invited = 0,
+    Accepted = 1,
+    Confirmed = 2,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    pub billing_email: String,
+    pub public_key: Option<String>,
+    pub private_key: Option<String>,
+    /// Members see the TOTP codes of the organization's items.
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
+    pub use_totp: bool,
+    /// Collection access is also granted through the organization's groups.
+    #[serde(deserialize_with = "serde_d1::deserialize_bool")]
+    pub use_groups: bool,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Features advertised for every organization. Billing isn't implemented, so every organization
+/// is a self-hosted one with all features and no seat limit. Groups and TOTP codes are turned on
+/// or off per organization.
+fn feature_flags(use_groups: bool, use_totp: bool) -> Value {
+    json!({
+        "seats": null,
+        "maxCollections": null,
+        "maxStorageGb": i16::MAX,
+        "use2fa": true,
+        "useCustomPermissions": false,
+        "useDirectory": false,
+        "useEvents": true,
+        "useGroups": use_groups,
+        "useTotp": use_totp,
+        "usePolicies": true,
+        "useScim": false,
+        "useSso": false,
+        "useKeyConnector": false,
+        "usePasswordManager": true,
+        "useSecretsManager": false,
+        "useResetPassword": true,
+        "useApi": false,
+        "selfHost": true,
+        "usersGetPremium": true,
+        "planProductType": 3, // Enterprise
+        "productTierType": 3,
+    })
+}
+
+fn merge(mut base: Value, extra: Value) -> Value {
+    if let (Some(base), Value::Object(extra)) = (base.as_object_mut(), extra) {
+        base.extend(extra);
+    }
+    base
+}
+
+impl Organization {
+    pub fn has_keys(&self) -> bool {
+        self.public_key.is_some() && self.private_key.is_some()
+    }
+
+    /// The organization's key pair (`organizationKeys`). The private key is encrypted with the
+    /// organization key, so only members can use it.
+    pub fn to_keys_json(&self) -> Value {
+        json!({
+            "publicKey": self.public_key,
+            "privateKey": self.private_key,
+            "object": "organizationKeys",
+        })
+    }
+
+    /// Organization details (`organization`), as shown on the organization settings pages.
+    pub fn to_json(&self) -> Value {
+        merge(
+            feature_flags(self.use_groups, self.use_totp),
+            json!({
+                "id": self.id,
+                "identifier": null,
+                "name": self.name,
+                "billingEmail": self.billing_email,
+                "businessName": null,
+                "businessAddress1": null,
+                "businessAddress2": null,
+                "businessAddress3": null,
+                "businessCountry": null,
+                "businessTaxNumber": null,
+                "planType": 6, // EnterpriseAnnually
+                "hasPublicAndPrivateKeys": self.has_keys(),
+                "allowAdminAccessToAllCollectionItems": true,
+                "limitCollectionCreation": false,
+                "limitCollectionDeletion": false,
+                "maxAutoscaleSeats": null,
+                "object": "organization",
+            }),
+        )
+    }
+
+    /// The organization as seen by one of its members (`profileOrganization`), used in the
+    /// profile, `/api/sync` and `/api/organizations`.
+    pub fn to_profile_json(&self, membership: &Membership) -> Value {
+        merge(
+            feature_flags(self.use_groups, self.use_totp),
+            json!({
+                "id": self.id,
+                "identifier": null,
+                "name": self.name,
+                "organizationUserId": membership.id,
+                "userId": membership.user_id,
+                "key": membership.akey,
+                "status": membership.status,
+                "type": membership.atype,
+                "enabled": true,
+                "hasPublicAndPrivateKeys": self.has_keys(),
+                "resetPasswordEnrolled": membership.reset_password_key.is_some(),
+                "ssoBound": false,
+                "keyConnectorEnabled": false,
+                "keyConnectorUrl": null,
+                "providerId": null,
+                "providerName": null,
+                "providerType": null,
+                "familySponsorshipFriendlyName": null,
+                "familySponsorshipAvailable": false,
+                "familySponsorshipLastSyncDate": null,
+                "familySponsorshipValidUntil": null,
+                "familySponsorshipToDelete": null,
+                "accessSecretsManager": false,
+                "limitCollectionCreation": false,
+                "limitCollectionDeletion": false,
+                "allowAdminAccessToAllCollectionItems": true,
+                "userIsManagedByOrganization": false,
+                "userIsClaimedByOrganization": false,
+                "permissions": {
+                    "accessEventLogs": false,
+                    "accessImportExport": false,
+                    "accessReports": false,
+                    "createNewCollections": false,
+                    "editAnyCollection": false,
+                    "deleteAnyCollection": false,
+                    "manageGroups": false,
+                    "managePolicies": false,
+                    "manageSso": false,
+                    "manageUsers": false,
+                    "manageResetPassword": false,
+                    "manageScim": false,
+                },
+                "object": "profileOrganization",
+            }),
+        )
+    }
+}
+
+/// A row of `organization_users`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Membership {
+    pub id: String,
+    pub organization_id: String,
+    pub user_id: Option<String>,
+    pub email: String,
+    /// Organization key encrypted with the member's public key.
+    pub akey: Option<String>,
+    pub status: i32,
+    pub atype: i32,
+    pub access_all: i32,
+    /// The member's user key encrypted with the organization's public key, set while enrolled
+    /// in account recovery.
+    pub reset_password_key: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl Membership {
+    pub fn is_owner(&self) -> bool {
+        self.atype == MembershipType::Owner as i32
+    }
+
+    /// Owners and admins manage the organization.
+    pub fn is_admin(&self) -> bool {
+        self.atype == MembershipType::Owner as i32 || self.atype == MembershipType::Admin as i32
+    }
+
+    /// Owners and admins, plus members explicitly given every collection, see all collections.
+    pub fn has_full_access(&self) -> bool {
+        Self::full_access_for(self.atype, self.access_all)
+    }
+
+    /// Member as listed on the organization's members page (`organizationUserUserDetails`).
+    /// `name` is `None` until the invitation has been accepted.
+    pub fn to_user_details_json(
+        &self,
+        name: Option<&str>,
+        two_factor_enabled: bool,
+        collections: Vec<Value>,
+        groups: Vec<String>,
+    ) -> Value {
+        json!({
+            "id": self.id,
+            "userId": self.user_id,
+            "name": name,
+            "email": self.email,
+            "externalId": null,
+            "avatarColor": null,
+            "groups": groups,
+            "collections": collections,
+            "status": self.status,
+            "type": self.atype,
+            "accessAll": self.access_all != 0,
+            "twoFactorEnabled": two_factor_enabled,
+            "resetPasswordEnrolled": self.reset_password_key.is_some(),
+            "hasMasterPassword": self.user_id.is_some(),
+            "permissions": null,
+            "ssoBound": false,
+            "usesKeyConnector": false,
+            "accessSecretsManager": false,
+            "managedByOrganization": false,
+            "claimedByOrganization": false,
+            "object": "organizationUserUserDetails",
+        })
+    }
+
+    pub fn full_access_for(atype: i32, access_all: i32) -> bool {
+        atype == MembershipType::Owner as i32
+            || atype == MembershipType::Admin as i32
+            || access_all != 0
+    }
+
+    /// Owners, admins and managers can create collections and see the member list.
+    pub fn is_manager(&self) -> bool {
+        self.is_admin() || self.atype == MembershipType::Manager as i32
+    }
+}
+
+// For POST /api/organizations requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationCreateRequest {
+    pub name: String,
+    pub billing_email: String,
+    /// Organization key encrypted with the creator's public key.
+    pub key: String,
+    /// Name of the default collection, encrypted with the organization key.
+    pub collection_name: String,
+    pub keys: Option<OrganizationKeysRequest>,
+    // Plan and seat fields are ignored: billing isn't implemented, so every organization is
+    // treated as a free one without seat limits.
+}
+
+// Also for POST /api/organizations/{id}/keys requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationKeysRequest {
+    pub public_key: String,
+    pub encrypted_private_key: String,
+}
+
+// For PUT /api/organizations/{id} requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationUpdateRequest {
+    pub name: String,
+    pub billing_email: Option<String>,
+    pub use_totp: Option<bool>,
+    pub use_groups: Option<bool>,
+}
+
+// For POST /api/organizations/{id}/users/invite requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationInviteRequest {
+    pub emails: Vec<String>,
+    #[serde(rename = "type")]
+    pub atype: i32,
+    #[serde(default)]
+    pub access_all: bool,
+    /// Collections the new members get access to.
+    #[serde(default)]
+    pub collections: Vec<CollectionAccessRequest>,
+    /// Groups the new members join. Ignored unless groups are enabled.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // Custom permissions aren't supported yet and are ignored.
+}
+
+// For PUT /api/organizations/{id}/users/{member_id} requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationMemberUpdateRequest {
+    #[serde(rename = "type")]
+    pub atype: i32,
+    #[serde(default)]
+    pub access_all: bool,
+    /// Replaces the member's collection access.
+    #[serde(default)]
+    pub collections: Vec<CollectionAccessRequest>,
+    /// Replaces the groups the member belongs to. Ignored unless groups are enabled.
+    pub groups: Option<Vec<String>>,
+    // Custom permissions aren't supported yet and are ignored.
+}
+
+// For POST /api/organizations/{id}/users/{member_id}/accept requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationAcceptRequest {
+    /// Token from the invitation link.
+    pub token: String,
+    /// User key encrypted with the organization's public key, sent when the organization
+    /// enrolls its members in account recovery automatically.
+    pub reset_password_key: Option<String>,
+}
+
+// For POST /api/organizations/{id}/users/{member_id}/confirm requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationConfirmRequest {
+    /// Organization key encrypted with the member's public key.
+    pub key: String,
+}
+
+// For PUT /api/organizations/{id}/users/{user_id}/reset-password-enrollment requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPasswordEnrollmentRequest {
+    /// User key encrypted with the organization's public key; `None` withdraws.
+    pub reset_password_key: Option<String>,
+    /// Required to enroll.
+    pub master_password_hash: Option<String>,
+}
+
+// For PUT /api/organizations/{id}/users/{member_id}/admin-reset-password requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminResetPasswordRequest {
+    pub new_master_password_hash: String,
+    /// The member's user key encrypted with the new master key.
+    pub key: String,
+}
@@ -0,0 +1,115 @@
+//! Fields of update requests where leaving a value out and sending `null` mean different things.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A field of an update request: left out, to keep the stored value, `null`, to clear it, or a
+/// new value. Declare it with `#[serde(default)]` so that a field left out reads as
+/// [`Patch::Missing`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Patch<T> {
+    #[default]
+    Missing,
+    Null,
+    Value(T),
+}
+
+impl<T> Patch<T> {
+    pub fn is_missing(&self) -> bool {
+        matches!(self, Patch::Missing)
+    }
+
+    /// The new value, if one was sent.
+    pub fn value(&self) -> Option<&T> {
+        match self {
+            Patch::Value(value) => Some(value),
+            Patch::Missing | Patch::Null => None,
+        }
+    }
+
+    /// The value to store in place of `current`.
+    pub fn apply(self, current: Option<T>) -> Option<T> {
+        match self {
+            Patch::Missing => current,
+            Patch::Null => None,
+            Patch::Value(value) => Some(value),
+        }
+    }
+
+    /// The value sent, for records that have nothing to keep yet.
+    pub fn into_option(self) -> Option<T> {
+        self.apply(None)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Patch<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match Option::<T>::deserialize(deserializer)? {
+            Some(value) => Patch::Value(value),
+            None => Patch::Null,
+        })
+    }
+}
+
+impl<T: Serialize> Serialize for Patch<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.value().serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Update {
+        #[serde(default, skip_serializing_if = "Patch::is_missing")]
+        folder_id: Patch<String>,
+    }
+
+    fn update(body: serde_json::Value) -> Patch<String> {
+        serde_json::from_value::<Update>(body).unwrap().folder_id
+    }
+
+    #[test]
+    fn missing_null_and_values_are_told_apart() {
+        assert_eq!(update(json!({})), Patch::Missing);
+        assert_eq!(update(json!({ "folder_id": null })), Patch::Null);
+        assert_eq!(
+            update(json!({ "folder_id": "folder" })),
+            Patch::Value("folder".to_string())
+        );
+    }
+
+    #[test]
+    fn apply_keeps_clears_or_replaces() {
+        let current = || Some("current".to_string());
+        assert_eq!(Patch::Missing.apply(current()), current());
+        assert_eq!(Patch::<String>::Null.apply(current()), None);
+        assert_eq!(
+            Patch::Value("new".to_string()).apply(current()),
+            Some("new".to_string())
+        );
+        assert_eq!(Patch::<String>::Missing.into_option(), None);
+        assert_eq!(Patch::Value(true).value(), Some(&true));
+        assert_eq!(Patch::<bool>::Null.value(), None);
+    }
+
+    #[test]
+    fn serializes_as_it_was_sent() {
+        for body in [
+            json!({}),
+            json!({ "folder_id": null }),
+            json!({ "folder_id": "folder" }),
+        ] {
+            let update: Update = serde_json::from_value(body.clone()).unwrap();
+            assert_eq!(serde_json::to_value(update).unwrap(), body);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+/// Organization policy types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Mirrors Bitwarden's PolicyType
+#[repr(i32)]
+pub enum PolicyType {
+    TwoFactorAuthentication = 0,
+    MasterPassword = 1,
+    PasswordGenerator = 2,
+    SingleOrg = 3,
+    RequireSso = 4,
+    PersonalOwnership = 5,
+    DisableSend = 6,
+    SendOptions = 7,
+    ResetPassword = 8,
+    MaximumVaultTimeout = 9,
+    DisablePersonalVaultExport = 10,
+    ActivateAutofill = 11,
+    AutomaticAppLogIn = 12,
+    FreeFamiliesSponsorship = 13,
+    RemoveUnlockWithPin = 14,
+    RestrictedItemTypes = 15,
+}
+
+impl PolicyType {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(PolicyType::TwoFactorAuthentication),
+            1 => Some(PolicyType::MasterPassword),
+            2 => Some(PolicyType::PasswordGenerator),
+            3 => Some(PolicyType::SingleOrg),
+            4 => Some(PolicyType::RequireSso),
+            5 => Some(PolicyType::PersonalOwnership),
+            6 => Some(PolicyType::DisableSend),
+            7 => Some(PolicyType::SendOptions),
+            8 => Some(PolicyType::ResetPassword),
+            9 => Some(PolicyType::MaximumVaultTimeout),
+            10 => Some(PolicyType::DisablePersonalVaultExport),
+            11 => Some(PolicyType::ActivateAutofill),
+            12 => Some(PolicyType::AutomaticAppLogIn),
+            13 => Some(PolicyType::FreeFamiliesSponsorship),
+            14 => Some(PolicyType::RemoveUnlockWithPin),
+            15 => Some(PolicyType::RestrictedItemTypes),
+            _ => None,
+        }
+    }
+}
+
+/// A row of `organization_policies`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrganizationPolicy {
+    pub id: String,
+    pub organization_id: String,
+    pub atype: i32,
+    pub enabled: i32,
+    /// Policy options as JSON.
+    pub data: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl OrganizationPolicy {
+    pub fn data_json(&self) -> Value {
+        self.data
+            .as_deref()
+            .and_then(|data| serde_json::from_str(data).ok())
+            .unwrap_or(Value::Null)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "organizationId": self.organization_id,
+            "type": self.atype,
+            "data": self.data_json(),
+            "enabled": self.enabled != 0,
+            "object": "policy",
+        })
+    }
+
+    /// A policy that was never configured: disabled and without options.
+    pub fn default_json(organization_id: &str, atype: PolicyType) -> Value {
+        json!({
+            "id": null,
+            "organizationId": organization_id,
+            "type": atype as i32,
+            "data": null,
+            "enabled": false,
+            "object": "policy",
+        })
+    }
+}
+
+/// Options of the "Master password requirements" policy.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MasterPasswordPolicyData {
+    pub min_complexity: Option<i32>,
+    pub min_length: Option<i32>,
+    pub require_upper: bool,
+    pub require_lower: bool,
+    pub require_numbers: bool,
+    pub require_special: bool,
+    pub enforce_on_login: bool,
+}
+
+impl MasterPasswordPolicyData {
+    /// Combines the requirements of several organizations into the strictest set.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            min_complexity: self.min_complexity.max(other.min_complexity),
+            min_length: self.min_length.max(other.min_length),
+            require_upper: self.require_upper || other.require_upper,
+            require_lower: self.require_lower || other.require_lower,
+            require_numbers: self.require_numbers || other.require_numbers,
+            require_special: self.require_special || other.require_special,
+            enforce_on_login: self.enforce_on_login || other.enforce_on_login,
+        }
+    }
+
+    /// `MasterPasswordPolicy` of the token response.
+    pub fn to_token_json(&self) -> Value {
+        json!({
+            "MinComplexity": self.min_complexity,
+            "MinLength": self.min_length,
+            "RequireUpper": self.require_upper,
+            "RequireLower": self.require_lower,
+            "RequireNumbers": self.require_numbers,
+            "RequireSpecial": self.require_special,
+            "EnforceOnLogin": self.enforce_on_login,
+            "Object": "masterPasswordPolicy",
+        })
+    }
+}
+
+/// Options of the "Account recovery administration" policy.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ResetPasswordPolicyData {
+    /// New members must enroll when they accept their invitation, and can't withdraw.
+    pub auto_enroll_enabled: bool,
+}
+
+// For PUT /api/organizations/{id}/policies/{type} requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyUpdateRequest {
+    pub enabled: bool,
+    pub data: Option<Value>,
+}
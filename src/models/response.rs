@@ -0,0 +1,116 @@
+//! The envelope every list endpoint answers with.
+
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// `{"data": [...], "object": "list", "continuationToken": ...}`, the shape the clients expect
+/// of any list. The continuation token is only set when another page follows.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub data: Vec<T>,
+    object: &'static str,
+    pub continuation_token: Option<String>,
+}
+
+impl<T> ListResponse<T> {
+    /// The whole list, in a single page.
+    pub fn new(data: Vec<T>) -> Self {
+        ListResponse {
+            data,
+            object: "list",
+            continuation_token: None,
+        }
+    }
+
+    /// A page of at most `page_size` items, out of `items` queried with a limit of
+    /// `page_size + 1`: the extra item only tells that another page follows, and `token` makes
+    /// the continuation token out of the last item kept.
+    pub fn page(mut items: Vec<T>, page_size: usize, token: impl FnOnce(&T) -> String) -> Self {
+        let continuation_token = if items.len() > page_size {
+            items.truncate(page_size);
+            items.last().map(token)
+        } else {
+            None
+        };
+        ListResponse {
+            continuation_token,
+            ..ListResponse::new(items)
+        }
+    }
+
+    /// The same page, with every item converted.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> ListResponse<U> {
+        ListResponse {
+            data: self.data.into_iter().map(f).collect(),
+            object: self.object,
+            continuation_token: self.continuation_token,
+        }
+    }
+}
+
+/// Writes the start of the envelope of a list whose items are already JSON, such as the arrays
+/// SQLite builds, before the array is appended to `out`.
+pub fn open_raw_list(out: &mut String) {
+    out.push_str("{\"data\":");
+}
+
+/// Completes the envelope [`open_raw_list`] started, once the array is in `out`.
+pub fn close_raw_list(out: &mut String, continuation_token: Option<&str>) {
+    out.push_str(",\"object\":\"list\",\"continuationToken\":");
+    out.push_str(&Value::from(continuation_token).to_string());
+    out.push('}');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn empty_lists_keep_the_envelope() {
+        assert_eq!(
+            serde_json::to_value(ListResponse::<Value>::new(Vec::new())).unwrap(),
+            json!({ "data": [], "object": "list", "continuationToken": null })
+        );
+    }
+
+    #[test]
+    fn pages_carry_the_token_of_their_last_item_when_more_follow() {
+        let page = ListResponse::page(vec![1, 2, 3], 2, |last| format!("after-{last}"));
+        assert_eq!(
+            serde_json::to_value(&page).unwrap(),
+            json!({ "data": [1, 2], "object": "list", "continuationToken": "after-2" })
+        );
+
+        let last_page = ListResponse::page(vec![1, 2], 2, |_| unreachable!());
+        assert_eq!(last_page.data, [1, 2]);
+        assert_eq!(last_page.continuation_token, None);
+    }
+
+    #[test]
+    fn mapping_keeps_the_token() {
+        let page = ListResponse::page(vec![1, 2], 1, |last| last.to_string()).map(|n| n * 10);
+        assert_eq!(
+            serde_json::to_value(&page).unwrap(),
+            json!({ "data": [10], "object": "list", "continuationToken": "1" })
+        );
+    }
+
+    #[test]
+    fn raw_lists_match_the_serialized_envelope() {
+        for token in [None, Some("next")] {
+            let mut raw = String::new();
+            open_raw_list(&mut raw);
+            raw.push_str("[{\"id\":\"a\"}]");
+            close_raw_list(&mut raw, token);
+
+            let list = ListResponse {
+                continuation_token: token.map(str::to_string),
+                ..ListResponse::new(vec![json!({ "id": "a" })])
+            };
+            assert_eq!(raw, serde_json::to_string(&list).unwrap());
+        }
+    }
+}
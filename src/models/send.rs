@@ -0,0 +1,354 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{models::serde_d1, time};
+
+pub const SEND_TYPE_TEXT: i32 = 0;
+pub const SEND_TYPE_FILE: i32 = 1;
+
+/// Sends can't be scheduled for deletion further out than this.
+pub const SEND_MAX_DELETION_DAYS: i64 = 31;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Send {
+    pub id: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub user_id: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
+    pub atype: i32,
+    // Name, notes, data and key are encrypted client-side
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub name: String,
+    pub notes: Option<String>,
+    /// JSON text of the type-specific payload (`text` or `file` object).
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub data: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub akey: String,
+    pub password_hash: Option<String>,
+    pub password_salt: Option<String>,
+    #[serde(default, deserialize_with = "serde_d1::deserialize_option_i32")]
+    pub password_iter: Option<i32>,
+    #[serde(default, deserialize_with = "serde_d1::deserialize_option_i32")]
+    pub max_access_count: Option<i32>,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
+    pub access_count: i32,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub creation_date: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub revision_date: String,
+    pub expiration_date: Option<String>,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
+    pub deletion_date: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
+    pub disabled: i32,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
+    pub hide_email: i32,
+}
+
+impl Send {
+    pub fn access_id(&self) -> String {
+        access_id(&self.id)
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    /// Typed `text` payload, for text sends.
+    pub fn text(&self) -> Option<SendText> {
+        if self.atype != SEND_TYPE_TEXT {
+            return None;
+        }
+        serde_json::from_str(&self.data).ok()
+    }
+
+    /// Typed `file` payload, for file sends.
+    pub fn file(&self) -> Option<SendFile> {
+        if self.atype != SEND_TYPE_FILE {
+            return None;
+        }
+        serde_json::from_str(&self.data).ok()
+    }
+
+    /// Id of the uploaded file, for file sends.
+    pub fn file_id(&self) -> Option<String> {
+        self.file().and_then(|file| file.id)
+    }
+
+    /// Storage key of the uploaded file, for file sends.
+    pub fn storage_key(&self) -> Option<String> {
+        self.file_id()
+            .map(|file_id| send_file_key(&self.id, &file_id))
+    }
+
+    /// Size of the uploaded file in bytes, as recorded in the send data.
+    pub fn file_size(&self) -> Option<i64> {
+        self.file().and_then(|file| file.size.parse().ok())
+    }
+
+    pub fn to_response(&self) -> SendResponse {
+        SendResponse {
+            id: self.id.clone(),
+            access_id: self.access_id(),
+            atype: self.atype,
+            name: self.name.clone(),
+            notes: self.notes.clone(),
+            text: self.text(),
+            file: self.file(),
+            key: self.akey.clone(),
+            max_access_count: self.max_access_count,
+            access_count: self.access_count,
+            password: None,
+            has_password: self.has_password(),
+            disabled: self.disabled != 0,
+            hide_email: self.hide_email != 0,
+            revision_date: self.revision_date.clone(),
+            expiration_date: self.expiration_date.clone(),
+            deletion_date: self.deletion_date.clone(),
+            object: "send".to_string(),
+        }
+    }
+
+    /// Response for anonymous access (`send-access`). Only what recipients need is included.
+    pub fn to_access_json(&self, creator_identifier: Option<String>) -> Value {
+        json!({
+            "id": self.access_id(),
+            "type": self.atype,
+            "name": self.name,
+            "text": self.text(),
+            "file": self.file(),
+            "expirationDate": self.expiration_date,
+            "creatorIdentifier": creator_identifier,
+            "object": "send-access",
+        })
+    }
+}
+
+/// `text` payload of a text send. `text` is encrypted client-side.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SendText {
+    pub text: Option<String>,
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+/// `file` payload of a file send. `fileName` is encrypted client-side.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SendFile {
+    pub id: Option<String>,
+    pub file_name: Option<String>,
+    /// Size in bytes. Clients expect a string; older rows may hold a number.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub size: String,
+    pub size_name: Option<String>,
+}
+
+fn deserialize_size<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s,
+        _ => "0".to_string(),
+    })
+}
+
+/// Owner-side representation of a send, used by the CRUD endpoints and `/api/sync`.
+///
+/// Official clients parse every field here; the mobile apps crash on the Send tab when one is
+/// missing, so optional values are emitted as `null` rather than skipped.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SendResponse {
+    pub id: String,
+    pub access_id: String,
+    #[serde(rename = "type")]
+    pub atype: i32,
+    pub name: String,
+    pub notes: Option<String>,
+    pub text: Option<SendText>,
+    pub file: Option<SendFile>,
+    pub key: String,
+    pub max_access_count: Option<i32>,
+    pub access_count: i32,
+    /// Always `null`: the hash never leaves the server.
+    pub password: Option<String>,
+    pub has_password: bool,
+    pub disabled: bool,
+    pub hide_email: bool,
+    pub revision_date: String,
+    pub expiration_date: Option<String>,
+    pub deletion_date: String,
+    pub object: String,
+}
+
+/// Public id used in Send links: the URL-safe base64 of the send's UUID bytes.
+pub fn access_id(send_id: &str) -> String {
+    match Uuid::parse_str(send_id) {
+        Ok(uuid) => BASE64URL.encode(uuid.as_bytes()),
+        Err(_) => BASE64URL.encode(send_id.as_bytes()),
+    }
+}
+
+/// Resolves the id used in a Send link back to the send's UUID. Plain UUIDs are accepted as well.
+pub fn send_id_from_access_id(access_id: &str) -> Option<String> {
+    if let Ok(uuid) = Uuid::parse_str(access_id) {
+        return Some(uuid.to_string());
+    }
+    let bytes = BASE64URL.decode(access_id.trim_end_matches('=')).ok()?;
+    Uuid::from_slice(&bytes).ok().map(|uuid| uuid.to_string())
+}
+
+/// Storage key (R2 or KV) of a file send's contents.
+pub fn send_file_key(send_id: &str, file_id: &str) -> String {
+    format!("sends/{send_id}/{file_id}")
+}
+
+/// Normalizes a client-supplied ISO 8601 date to the format stored in D1.
+pub fn normalize_date(value: &str) -> Option<String> {
+    time::parse_bw(value).map(time::format_bw)
+}
+
+// For POST /api/sends, POST /api/sends/file/v2 and PUT /api/sends/{id} requests
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SendRequest {
+    #[serde(rename = "type")]
+    pub atype: i32,
+    pub key: String,
+    pub password: Option<String>,
+    pub max_access_count: Option<i32>,
+    pub expiration_date: Option<String>,
+    pub deletion_date: String,
+    #[serde(default)]
+    pub disabled: bool,
+    pub hide_email: Option<bool>,
+    pub name: String,
+    pub notes: Option<String>,
+    pub text: Option<Value>,
+    pub file: Option<Value>,
+    /// Declared size of the encrypted file, for file sends. Clients send a number or a string.
+    pub file_length: Option<Value>,
+}
+
+impl SendRequest {
+    pub fn file_length(&self) -> Option<i64> {
+        match self.file_length.as_ref()? {
+            Value::Number(n) => n.as_i64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+// For POST /api/sends/access/{access_id} and POST /api/sends/{id}/access/file/{file_id} requests
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SendAccessRequest {
+    pub password: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sends row as D1 returns it.
+    fn row(atype: i32, data: Value) -> Send {
+        serde_json::from_value(json!({
+            "id": "4b9d1c6e-2f3a-4e8b-9c7d-5a1f0e6b3d27",
+            "user_id": "alice",
+            "atype": atype,
+            "name": "2.bmFtZQ==|aXY=|bWFj",
+            "notes": null,
+            "data": data.to_string(),
+            "akey": "2.a2V5|aXY=|bWFj",
+            "password_hash": "aGFzaA==",
+            "password_salt": "c2FsdA==",
+            "password_iter": 1000,
+            "max_access_count": 5,
+            "access_count": 2,
+            "creation_date": "2024-05-01T12:00:00.000Z",
+            "revision_date": "2024-05-02T08:30:00.000Z",
+            "expiration_date": null,
+            "deletion_date": "2024-05-08T12:00:00.000Z",
+            "disabled": 0,
+            "hide_email": 1,
+        }))
+        .unwrap()
+    }
+
+    /// `SendResponseModel` as the official server returns it for these sends, plus `hasPassword`.
+    fn expected(atype: i32, text: Value, file: Value) -> Value {
+        json!({
+            "id": "4b9d1c6e-2f3a-4e8b-9c7d-5a1f0e6b3d27",
+            "accessId": "S50cbi86ToucfVofDms9Jw",
+            "type": atype,
+            "name": "2.bmFtZQ==|aXY=|bWFj",
+            "notes": null,
+            "text": text,
+            "file": file,
+            "key": "2.a2V5|aXY=|bWFj",
+            "maxAccessCount": 5,
+            "accessCount": 2,
+            "password": null,
+            "hasPassword": true,
+            "disabled": false,
+            "hideEmail": true,
+            "revisionDate": "2024-05-02T08:30:00.000Z",
+            "expirationDate": null,
+            "deletionDate": "2024-05-08T12:00:00.000Z",
+            "object": "send",
+        })
+    }
+
+    #[test]
+    fn text_send_response_has_every_field_clients_parse() {
+        let text = json!({ "text": "2.dGV4dA==|aXY=|bWFj", "hidden": true });
+        let send = row(SEND_TYPE_TEXT, text.clone());
+
+        assert_eq!(
+            serde_json::to_value(send.to_response()).unwrap(),
+            expected(SEND_TYPE_TEXT, text, Value::Null)
+        );
+    }
+
+    #[test]
+    fn file_send_response_has_every_field_clients_parse() {
+        let file = json!({
+            "id": "2f7a1c9e8b3d4e5f",
+            "fileName": "2.ZmlsZQ==|aXY=|bWFj",
+            "size": "3145728",
+            "sizeName": "3 MB",
+        });
+        let send = row(SEND_TYPE_FILE, file.clone());
+
+        assert_eq!(
+            serde_json::to_value(send.to_response()).unwrap(),
+            expected(SEND_TYPE_FILE, Value::Null, file)
+        );
+    }
+
+    #[test]
+    fn file_sizes_stored_as_numbers_are_sent_as_strings() {
+        let send = row(SEND_TYPE_FILE, json!({ "id": "f", "size": 3145728 }));
+
+        assert_eq!(send.to_response().file.unwrap().size, "3145728");
+        assert_eq!(send.file_size(), Some(3145728));
+    }
+
+    #[test]
+    fn access_ids_resolve_back_to_the_send() {
+        let id = "4b9d1c6e-2f3a-4e8b-9c7d-5a1f0e6b3d27";
+
+        assert_eq!(send_id_from_access_id(&access_id(id)).as_deref(), Some(id));
+        assert_eq!(send_id_from_access_id(id).as_deref(), Some(id));
+        assert_eq!(send_id_from_access_id("not-an-id"), None);
+    }
+}
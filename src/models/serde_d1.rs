@@ -0,0 +1,255 @@
+//! Tolerant deserializers for the columns of D1 rows.
+//!
+//! D1 doesn't always return a column in the type the schema suggests: booleans come back as
+//! integers, integers now and then as floats (`1.0`) or strings, and NULL turns up in columns
+//! the models treat as required (rows written by older versions, or columns added without a
+//! default). One such value fails the whole row, and with it the request, so the DB models read
+//! their columns through these, with `#[serde(deserialize_with = "...")]`.
+
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+
+fn invalid<E: de::Error>(value: &Value, expected: &str) -> E {
+    E::custom(format!("invalid value {value}, expected {expected}"))
+}
+
+fn as_i64(value: &Value) -> Option<i64> {
+    let integral = |float: f64| (float.fract() == 0.0).then_some(float as i64);
+    match value {
+        Value::Bool(value) => Some(*value as i64),
+        Value::Number(number) => number
+            .as_i64()
+            .or_else(|| number.as_f64().and_then(integral)),
+        Value::String(value) => {
+            let value = value.trim();
+            value
+                .parse()
+                .ok()
+                .or_else(|| value.parse().ok().and_then(integral))
+        }
+        _ => None,
+    }
+}
+
+fn as_i32<E: de::Error>(value: &Value) -> Result<i32, E> {
+    as_i64(value)
+        .and_then(|number| i32::try_from(number).ok())
+        .ok_or_else(|| invalid(value, "a 32-bit integer"))
+}
+
+/// A boolean stored as `0`/`1`, `true`/`false` or the strings of either. NULL is false.
+pub fn deserialize_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    match &value {
+        Value::Null => Ok(false),
+        Value::String(text) if text.trim().eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(text) if text.trim().eq_ignore_ascii_case("false") => Ok(false),
+        _ => as_i64(&value)
+            .map(|number| number != 0)
+            .ok_or_else(|| invalid(&value, "a boolean")),
+    }
+}
+
+/// An integer, from a number (integral floats included) or a numeric string. NULL is 0.
+pub fn deserialize_i32<'de, D>(deserializer: D) -> Result<i32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(0),
+        value => as_i32(&value),
+    }
+}
+
+/// Like [`deserialize_i32`], for a nullable column.
+pub fn deserialize_option_i32<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(None),
+        value => as_i32(&value).map(Some),
+    }
+}
+
+/// Text of any scalar value, numbers included. NULL is the empty string.
+pub fn deserialize_string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_option_string(deserializer).map(Option::unwrap_or_default)
+}
+
+/// Like [`deserialize_string`], for a nullable column.
+pub fn deserialize_option_string<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Null => None,
+        Value::String(text) => Some(text),
+        value => Some(value.to_string()),
+    })
+}
+
+/// `#[serde(with = "serde_d1::bool_as_int")]`: a boolean column written back as `0`/`1`.
+pub mod bool_as_int {
+    use serde::{Deserializer, Serializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<bool, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::deserialize_bool(deserializer)
+    }
+
+    pub fn serialize<S>(value: &bool, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_i64(*value as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::cipher::CipherDBModel;
+    use crate::models::folder::Folder;
+    use serde_json::json;
+
+    #[derive(Debug, Deserialize)]
+    struct Row {
+        #[serde(deserialize_with = "deserialize_bool")]
+        flag: bool,
+        #[serde(deserialize_with = "deserialize_i32")]
+        number: i32,
+        #[serde(default, deserialize_with = "deserialize_option_i32")]
+        maybe_number: Option<i32>,
+        #[serde(deserialize_with = "deserialize_string")]
+        text: String,
+        #[serde(default, deserialize_with = "deserialize_option_string")]
+        maybe_text: Option<String>,
+    }
+
+    fn row(value: Value) -> Result<Row, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn booleans_from_anything_d1_returns() {
+        for (flag, expected) in [
+            (json!(true), true),
+            (json!(1), true),
+            (json!(1.0), true),
+            (json!("1"), true),
+            (json!(" TRUE "), true),
+            (json!(false), false),
+            (json!(0), false),
+            (json!("false"), false),
+            (json!(null), false),
+        ] {
+            let row = row(json!({ "flag": flag, "number": 0, "text": "" })).unwrap();
+            assert_eq!(row.flag, expected, "{flag}");
+        }
+        assert!(row(json!({ "flag": "maybe", "number": 0, "text": "" })).is_err());
+        assert!(row(json!({ "flag": [1], "number": 0, "text": "" })).is_err());
+    }
+
+    #[test]
+    fn integers_from_numbers_and_numeric_strings() {
+        for (number, expected) in [
+            (json!(7), 7),
+            (json!(7.0), 7),
+            (json!("7"), 7),
+            (json!(" -7 "), -7),
+            (json!("7.0"), 7),
+            (json!(true), 1),
+            (json!(null), 0),
+        ] {
+            let row = row(json!({ "flag": 0, "number": number, "text": "" })).unwrap();
+            assert_eq!(row.number, expected, "{number}");
+        }
+        for number in [json!(7.5), json!("seven"), json!(i64::MAX), json!({})] {
+            let err = row(json!({ "flag": 0, "number": number, "text": "" })).unwrap_err();
+            assert!(
+                err.to_string().contains("expected a 32-bit integer"),
+                "{err}"
+            );
+        }
+
+        let row = |maybe_number| {
+            row(json!({ "flag": 0, "number": 0, "text": "", "maybe_number": maybe_number }))
+        };
+        assert_eq!(row(json!(null)).unwrap().maybe_number, None);
+        assert_eq!(row(json!(3.0)).unwrap().maybe_number, Some(3));
+        assert_eq!(row(json!("3")).unwrap().maybe_number, Some(3));
+        assert!(row(json!("three")).is_err());
+    }
+
+    #[test]
+    fn strings_from_any_scalar() {
+        let read = |text: Value| {
+            row(json!({ "flag": 0, "number": 0, "text": text.clone(), "maybe_text": text }))
+                .unwrap()
+        };
+        let row = read(json!("text"));
+        assert_eq!(
+            (row.text.as_str(), row.maybe_text.as_deref()),
+            ("text", Some("text"))
+        );
+        let row = read(json!(42));
+        assert_eq!(
+            (row.text.as_str(), row.maybe_text.as_deref()),
+            ("42", Some("42"))
+        );
+        let row = read(json!(null));
+        assert_eq!((row.text.as_str(), row.maybe_text), ("", None));
+
+        let missing = self::row(json!({ "flag": 0, "number": 0, "text": "" })).unwrap();
+        assert_eq!(missing.maybe_number, None);
+        assert_eq!(missing.maybe_text, None);
+    }
+
+    #[test]
+    fn bool_as_int_writes_integers_back() {
+        #[derive(Debug, Deserialize, serde::Serialize)]
+        struct Flag(#[serde(with = "bool_as_int")] bool);
+
+        assert_eq!(serde_json::to_value(Flag(true)).unwrap(), json!(1));
+        assert_eq!(serde_json::to_value(Flag(false)).unwrap(), json!(0));
+        assert!(serde_json::from_value::<Flag>(json!("1")).unwrap().0);
+    }
+
+    #[test]
+    fn db_models_read_odd_rows() {
+        let cipher: CipherDBModel = serde_json::from_value(json!({
+            "id": "cipher",
+            "user_id": "alice",
+            "organization_id": null,
+            "type": "1",
+            "data": "{}",
+            "favorite": 1.0,
+            "folder_id": null,
+            "created_at": "2025-01-01T00:00:00.000Z",
+            "updated_at": null,
+        }))
+        .unwrap();
+        assert_eq!((cipher.r#type, cipher.favorite), (1, 1));
+        assert_eq!(cipher.updated_at, "");
+        assert_eq!(cipher.deleted_at, None);
+
+        let folder: Folder = serde_json::from_value(json!({
+            "id": "folder",
+            "user_id": "alice",
+            "name": null,
+            "created_at": "2025-01-01T00:00:00.000Z",
+            "updated_at": "2025-01-01T00:00:00.000Z",
+        }))
+        .unwrap();
+        assert_eq!(folder.name, "");
+    }
+}
@@ -1,10 +1,12 @@
-use super::{folder::FolderResponse, user::User};
+use super::{folder::FolderResponse, send::SendResponse, user::User};
 use crate::error::AppError;
+use crate::time;
 use chrono::SecondsFormat;
 use serde::Serialize;
 use serde_json::Value;
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Profile {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -19,6 +21,7 @@ pub struct Profile {
     pub force_password_reset: bool,
     pub email_verified: bool,
     pub two_factor_enabled: bool,
+    pub verify_devices: bool,
     pub premium: bool,
     pub uses_key_connector: bool,
     pub creation_date: String,
@@ -36,8 +39,10 @@ pub struct Profile {
 
 impl Profile {
     pub fn from_user(user: User, two_factor_enabled: bool) -> Result<Self, AppError> {
-        let creation_date = chrono::DateTime::parse_from_rfc3339(&user.created_at)
-            .map_err(|_| AppError::Internal)?
+        let creation_date = time::parse_bw(&user.created_at)
+            .ok_or_else(|| {
+                AppError::Internal(Some(format!("Invalid created_at: {}", user.created_at)))
+            })?
             .to_rfc3339_opts(SecondsFormat::Micros, true);
 
         Ok(Self {
@@ -49,9 +54,10 @@ impl Profile {
             object: "profile".to_string(),
             premium_from_organization: false,
             culture: "en-US".to_string(),
-            force_password_reset: false,
+            force_password_reset: user.force_password_reset,
             email_verified: true,
             two_factor_enabled,
+            verify_devices: user.verify_devices,
             premium: true,
             uses_key_connector: false,
             creation_date,
@@ -80,6 +86,6 @@ pub struct SyncResponse {
     pub ciphers: Vec<Value>,
     pub domains: Value,
     #[serde(default)]
-    pub sends: Vec<Value>,
+    pub sends: Vec<SendResponse>,
     pub object: String,
 }
@@ -1,5 +1,8 @@
 use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::serde_d1;
 
 /// Remember token expiration in days
 const REMEMBER_TOKEN_EXPIRATION_DAYS: i64 = 30;
@@ -41,9 +44,11 @@ impl TwoFactorType {
 pub struct TwoFactor {
     pub uuid: String,
     pub user_uuid: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
     pub atype: i32,
-    #[serde(with = "bool_from_int")]
+    #[serde(with = "serde_d1::bool_as_int")]
     pub enabled: bool,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub data: String,
     pub last_used: i64,
 }
@@ -69,35 +74,8 @@ impl TwoFactor {
     }
 }
 
-mod bool_from_int {
-    use serde::{self, Deserialize, Deserializer, Serializer};
-
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<bool, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let value = i64::deserialize(deserializer)?;
-        match value {
-            0 => Ok(false),
-            1 => Ok(true),
-            _ => Err(serde::de::Error::custom("expected integer 0 or 1")),
-        }
-    }
-
-    pub fn serialize<S>(value: &bool, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        if *value {
-            serializer.serialize_i64(1)
-        } else {
-            serializer.serialize_i64(0)
-        }
-    }
-}
-
 /// POST /api/two-factor/authenticator - Enable TOTP
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EnableAuthenticatorData {
     pub key: String,
@@ -107,7 +85,7 @@ pub struct EnableAuthenticatorData {
 }
 
 /// POST /api/two-factor/disable - Disable a 2FA method
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DisableTwoFactorData {
     pub master_password_hash: Option<String>,
@@ -117,7 +95,7 @@ pub struct DisableTwoFactorData {
 }
 
 /// POST /api/two-factor/get-recover - Get recovery code
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RecoverTwoFactor {
     pub master_password_hash: String,
@@ -126,7 +104,7 @@ pub struct RecoverTwoFactor {
 }
 
 /// DELETE /api/two-factor/authenticator - Disable TOTP with key verification
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DisableAuthenticatorData {
     pub key: String,
@@ -1,30 +1,44 @@
 use constant_time_eq::constant_time_eq;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use crate::{crypto::verify_password, error::AppError};
+use crate::{crypto::verify_password, error::AppError, models::serde_d1};
 
 fn default_json_array_string() -> String {
     "[]".to_string()
 }
 
+fn default_true() -> bool {
+    true
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
     pub name: Option<String>,
     pub avatar_color: Option<String>,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub email: String,
-    #[serde(with = "bool_from_int")]
+    #[serde(with = "serde_d1::bool_as_int")]
     pub email_verified: bool,
     pub master_password_hash: String,
     pub master_password_hint: Option<String>,
     pub password_salt: Option<String>, // Salt for server-side PBKDF2 (NULL for legacy users)
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
     pub password_iterations: i32, // Server-side PBKDF2 iterations used for master_password_hash
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub key: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub private_key: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub public_key: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
     pub kdf_type: i32,
+    #[serde(deserialize_with = "serde_d1::deserialize_i32")]
     pub kdf_iterations: i32,
+    #[serde(default, deserialize_with = "serde_d1::deserialize_option_i32")]
     pub kdf_memory: Option<i32>, // Argon2 memory parameter (15-1024 MB)
+    #[serde(default, deserialize_with = "serde_d1::deserialize_option_i32")]
     pub kdf_parallelism: Option<i32>, // Argon2 parallelism parameter (1-16)
     pub security_stamp: String,
     /// JSON string of `Vec<Vec<String>>` storing user-defined equivalent domain groups.
@@ -34,7 +48,17 @@ pub struct User {
     #[serde(default = "default_json_array_string")]
     pub excluded_globals: String,
     pub totp_recover: Option<String>, // Recovery code for 2FA
+    /// Set when an organization admin reset the master password; cleared once the user picks
+    /// a new one.
+    #[serde(default, with = "serde_d1::bool_as_int")]
+    pub force_password_reset: bool,
+    /// Whether password logins from unknown devices need an emailed code, when
+    /// NEW_DEVICE_VERIFICATION is on.
+    #[serde(default = "default_true", with = "serde_d1::bool_as_int")]
+    pub verify_devices: bool,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub created_at: String,
+    #[serde(deserialize_with = "serde_d1::deserialize_string")]
     pub updated_at: String,
 }
 
@@ -91,35 +115,8 @@ impl User {
     }
 }
 
-mod bool_from_int {
-    use serde::{self, Deserialize, Deserializer, Serializer};
-
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<bool, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let value = i64::deserialize(deserializer)?;
-        match value {
-            0 => Ok(false),
-            1 => Ok(true),
-            _ => Err(serde::de::Error::custom("expected integer 0 or 1")),
-        }
-    }
-
-    pub fn serialize<S>(value: &bool, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        if *value {
-            serializer.serialize_i64(1)
-        } else {
-            serializer.serialize_i64(0)
-        }
-    }
-}
-
 // For /accounts/prelogin response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PreloginResponse {
     pub kdf: i32,
@@ -128,55 +125,96 @@ pub struct PreloginResponse {
     pub kdf_parallelism: Option<i32>,
 }
 
-// For /accounts/register request
-#[derive(Debug, Deserialize)]
+// For /identity/accounts/register(/finish) and /api/accounts/register requests
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RegisterRequest {
+    #[serde(alias = "Name")]
     pub name: Option<String>,
+    #[serde(alias = "Email")]
     pub email: String,
+    #[serde(alias = "MasterPasswordHash")]
     pub master_password_hash: String,
+    #[serde(alias = "MasterPasswordHint")]
     pub master_password_hint: Option<String>,
+    // Older clients send `key` and `keys`, as POST /api/accounts/register did
+    #[serde(alias = "UserSymmetricKey", alias = "key", alias = "Key")]
     pub user_symmetric_key: String,
+    #[serde(alias = "UserAsymmetricKeys", alias = "keys", alias = "Keys")]
     pub user_asymmetric_keys: KeyData,
+    #[serde(alias = "Kdf")]
     pub kdf: i32,
+    #[serde(alias = "KdfIterations")]
     pub kdf_iterations: i32,
+    #[serde(alias = "KdfMemory")]
     pub kdf_memory: Option<i32>, // Argon2 memory parameter (15-1024 MB)
+    #[serde(alias = "KdfParallelism")]
     pub kdf_parallelism: Option<i32>, // Argon2 parallelism parameter (1-16)
 }
 
 // For POST /accounts/password-hint request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PasswordHintRequest {
+    #[serde(alias = "Email")]
     pub email: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct KeyData {
+    #[serde(alias = "PublicKey")]
     pub public_key: String,
+    #[serde(alias = "EncryptedPrivateKey")]
     pub encrypted_private_key: String,
 }
 
 /// Request body for password-protected operations (delete account, purge vault, etc.)
 /// Supports both master password hash and OTP verification.
 /// Note: OTP verification is not implemented in this simplified version.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PasswordOrOtpData {
     #[serde(alias = "MasterPasswordHash")]
     pub master_password_hash: Option<String>,
     #[allow(dead_code)] // OTP verification is not implemented in this simplified version
+    #[serde(alias = "Otp")]
     pub otp: Option<String>,
 }
 
+// For PUT /accounts/verify-devices request
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyDevicesRequest {
+    #[serde(alias = "VerifyDevices")]
+    pub verify_devices: bool,
+    #[serde(alias = "MasterPasswordHash")]
+    pub master_password_hash: Option<String>,
+}
+
 // For POST /accounts/password request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ChangePasswordRequest {
+    #[serde(alias = "MasterPasswordHash")]
     pub master_password_hash: String,
+    #[serde(alias = "NewMasterPasswordHash")]
+    pub new_master_password_hash: String,
+    #[serde(alias = "MasterPasswordHint")]
+    pub master_password_hint: Option<String>,
+    #[serde(alias = "Key")]
+    pub key: String,
+}
+
+// For PUT /accounts/update-temp-password request - Replace a password set by an admin reset
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateTempPasswordRequest {
+    #[serde(alias = "NewMasterPasswordHash")]
     pub new_master_password_hash: String,
+    #[serde(alias = "MasterPasswordHint")]
     pub master_password_hint: Option<String>,
+    #[serde(alias = "Key")]
     pub key: String,
 }
 
@@ -209,48 +247,67 @@ pub struct ChangePasswordRequest {
 //   "newMasterPasswordHash": "..."
 // }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct KdfParams {
     #[serde(alias = "kdfType")]
+    #[serde(alias = "KdfType")]
     pub kdf_type: i32,
+    #[serde(alias = "Iterations")]
     pub iterations: i32,
+    #[serde(alias = "Memory")]
     pub memory: Option<i32>,
+    #[serde(alias = "Parallelism")]
     pub parallelism: Option<i32>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AuthenticationData {
+    #[serde(alias = "Salt")]
     pub salt: String,
+    #[serde(alias = "Kdf")]
     pub kdf: KdfParams,
+    #[serde(alias = "MasterPasswordAuthenticationHash")]
     pub master_password_authentication_hash: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct UnlockData {
+    #[serde(alias = "Salt")]
     pub salt: String,
+    #[serde(alias = "Kdf")]
     pub kdf: KdfParams,
+    #[serde(alias = "MasterKeyWrappedUserKey")]
     pub master_key_wrapped_user_key: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ChangeKdfRequest {
     // Common fields (both formats)
+    #[serde(alias = "Key")]
     pub key: String,
+    #[serde(alias = "MasterPasswordHash")]
     pub master_password_hash: String,
+    #[serde(alias = "NewMasterPasswordHash")]
     pub new_master_password_hash: String,
 
     // Simple format fields (optional)
+    #[serde(alias = "Kdf")]
     pub kdf: Option<i32>,
+    #[serde(alias = "KdfIterations")]
     pub kdf_iterations: Option<i32>,
+    #[serde(alias = "KdfMemory")]
     pub kdf_memory: Option<i32>,
+    #[serde(alias = "KdfParallelism")]
     pub kdf_parallelism: Option<i32>,
 
     // Complex format fields (optional)
+    #[serde(alias = "AuthenticationData")]
     pub authentication_data: Option<AuthenticationData>,
+    #[serde(alias = "UnlockData")]
     pub unlock_data: Option<UnlockData>,
 }
 
@@ -295,65 +352,85 @@ impl ChangeKdfRequest {
 }
 
 // For POST /accounts/key-management/rotate-user-account-keys request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RotateKeyRequest {
+    #[serde(alias = "AccountUnlockData")]
     pub account_unlock_data: RotateAccountUnlockData,
+    #[serde(alias = "AccountKeys")]
     pub account_keys: RotateAccountKeys,
+    #[serde(alias = "AccountData")]
     pub account_data: RotateAccountData,
+    #[serde(alias = "OldMasterKeyAuthenticationHash")]
     pub old_master_key_authentication_hash: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RotateAccountUnlockData {
+    #[serde(alias = "MasterPasswordUnlockData")]
     pub master_password_unlock_data: MasterPasswordUnlockData,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MasterPasswordUnlockData {
+    #[serde(alias = "KdfType")]
     pub kdf_type: i32,
+    #[serde(alias = "KdfIterations")]
     pub kdf_iterations: i32,
+    #[serde(alias = "KdfParallelism")]
     pub kdf_parallelism: Option<i32>,
+    #[serde(alias = "KdfMemory")]
     pub kdf_memory: Option<i32>,
+    #[serde(alias = "Email")]
     pub email: String,
+    #[serde(alias = "MasterKeyAuthenticationHash")]
     pub master_key_authentication_hash: String,
+    #[serde(alias = "MasterKeyEncryptedUserKey")]
     pub master_key_encrypted_user_key: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RotateAccountKeys {
+    #[serde(alias = "UserKeyEncryptedAccountPrivateKey")]
     pub user_key_encrypted_account_private_key: String,
+    #[serde(alias = "AccountPublicKey")]
     pub account_public_key: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RotateAccountData {
+    #[serde(alias = "Ciphers")]
     pub ciphers: Vec<crate::models::cipher::CipherRequestData>,
+    #[serde(alias = "Folders")]
     pub folders: Vec<RotateFolderData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RotateFolderData {
     // There is a bug in 2024.3.x which adds a `null` item.
     // To bypass this we allow an Option here, but skip it during the updates
     // See: https://github.com/bitwarden/clients/issues/8453
+    #[serde(alias = "Id")]
     pub id: Option<String>,
+    #[serde(alias = "Name")]
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ProfileData {
+    #[serde(alias = "Name")]
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AvatarData {
+    #[serde(alias = "AvatarColor")]
     pub avatar_color: Option<String>,
 }
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A dump of a Vaultwarden database, as produced by the extraction query documented in
+/// [`crate::handlers::vaultwarden`]. Rows keep Vaultwarden's column names.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VaultwardenDump {
+    #[serde(default)]
+    pub users: Vec<VaultwardenUser>,
+    #[serde(default)]
+    pub folders: Vec<VaultwardenFolder>,
+    #[serde(default)]
+    pub ciphers: Vec<VaultwardenCipher>,
+    #[serde(default)]
+    pub folders_ciphers: Vec<VaultwardenFolderCipher>,
+    #[serde(default)]
+    pub favorites: Vec<VaultwardenFavorite>,
+}
+
+/// A row of Vaultwarden's `users`, with the `password_hash` and `salt` blobs as hex.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VaultwardenUser {
+    pub uuid: String,
+    pub email: String,
+    pub name: Option<String>,
+    pub password_hash: String,
+    pub salt: String,
+    pub password_iterations: i32,
+    pub password_hint: Option<String>,
+    pub akey: String,
+    pub private_key: Option<String>,
+    pub public_key: Option<String>,
+    pub security_stamp: String,
+    pub equivalent_domains: Option<String>,
+    pub excluded_globals: Option<String>,
+    pub client_kdf_type: i32,
+    pub client_kdf_iter: i32,
+    pub client_kdf_memory: Option<i32>,
+    pub client_kdf_parallelism: Option<i32>,
+    pub avatar_color: Option<String>,
+    pub verified_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A row of Vaultwarden's `folders`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VaultwardenFolder {
+    pub uuid: String,
+    pub user_uuid: String,
+    pub name: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A row of Vaultwarden's `ciphers`. `data` holds only the type-specific object (the login,
+/// card, ...); name, notes, fields and password history have columns of their own.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VaultwardenCipher {
+    pub uuid: String,
+    pub user_uuid: Option<String>,
+    pub organization_uuid: Option<String>,
+    /// Per-item encryption key, from newer clients.
+    pub key: Option<String>,
+    pub atype: i32,
+    pub name: String,
+    pub notes: Option<String>,
+    pub fields: Option<String>,
+    pub data: String,
+    pub password_history: Option<String>,
+    pub reprompt: Option<i32>,
+    pub deleted_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A row of Vaultwarden's `folders_ciphers`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VaultwardenFolderCipher {
+    pub cipher_uuid: String,
+    pub folder_uuid: String,
+}
+
+/// A row of Vaultwarden's `favorites`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VaultwardenFavorite {
+    pub user_uuid: String,
+    pub cipher_uuid: String,
+}
+
+/// What a Vaultwarden migration did with the rows of one table.
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TableSummary {
+    pub inserted: usize,
+    /// Rows whose id was already in the database, from an earlier run.
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// A row that couldn't be migrated.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RowFailure {
+    pub table: &'static str,
+    pub id: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultwardenMigrationSummary {
+    pub users: TableSummary,
+    pub folders: TableSummary,
+    pub ciphers: TableSummary,
+    pub failures: Vec<RowFailure>,
+}
@@ -0,0 +1,189 @@
+//! Running the Worker's handlers outside Workers, on SQLite (`native-db`).
+//!
+//! With the feature on, [`Env`] takes the place of `worker::Env` throughout the crate: `d1` hands
+//! out one SQLite database, vars and secrets come from maps, and every other binding is missing,
+//! so handlers take the paths they take on a Worker without that binding. [`fetch`] and
+//! [`scheduled`] stand in for the Worker's handlers; the tests under `tests/` drive the crate
+//! through them.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::task::{Context, Poll, Waker};
+
+use axum::body::Body;
+use axum::http::{Request, Response};
+use axum::Extension;
+use jwt_compact::{Claims as JwtClaims, TimeOptions};
+use serde::Deserialize;
+use tower_service::Service;
+use worker::{
+    AnalyticsEngineDataset, Bucket, Error, Fetcher, KvStore, ObjectNamespace, RateLimiter,
+};
+
+use crate::auth::{keys::KeyRing, token_audience, token_issuer, Claims};
+use crate::config::Settings;
+use crate::db::Database;
+use crate::{handlers, logging, migrations, router, BaseUrl};
+
+pub use crate::db::native::{SqliteDatabase, SqliteStatement};
+
+#[cfg(test)]
+pub(crate) mod testing;
+
+/// The bindings of a Worker running natively.
+#[derive(Clone)]
+pub struct Env {
+    db: SqliteDatabase,
+    vars: HashMap<String, String>,
+    secrets: HashMap<String, String>,
+}
+
+impl Env {
+    /// An `Env` over `db`, with no vars or secrets set.
+    pub fn new(db: SqliteDatabase) -> Self {
+        Env {
+            db,
+            vars: HashMap::new(),
+            secrets: HashMap::new(),
+        }
+    }
+
+    pub fn with_var(mut self, name: &str, value: &str) -> Self {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_secret(mut self, name: &str, value: &str) -> Self {
+        self.secrets.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// The database, whatever the binding's name.
+    pub fn d1(&self, _binding: &str) -> Result<SqliteDatabase, Error> {
+        Ok(self.db.clone())
+    }
+
+    pub fn var(&self, name: &str) -> Result<String, Error> {
+        self.vars.get(name).cloned().ok_or_else(|| unbound(name))
+    }
+
+    pub fn secret(&self, name: &str) -> Result<String, Error> {
+        self.secrets.get(name).cloned().ok_or_else(|| unbound(name))
+    }
+
+    pub fn kv(&self, binding: &str) -> Result<KvStore, Error> {
+        Err(unbound(binding))
+    }
+
+    pub fn bucket(&self, binding: &str) -> Result<Bucket, Error> {
+        Err(unbound(binding))
+    }
+
+    pub fn durable_object(&self, binding: &str) -> Result<ObjectNamespace, Error> {
+        Err(unbound(binding))
+    }
+
+    pub fn rate_limiter(&self, binding: &str) -> Result<RateLimiter, Error> {
+        Err(unbound(binding))
+    }
+
+    pub fn analytics_engine(&self, binding: &str) -> Result<AnalyticsEngineDataset, Error> {
+        Err(unbound(binding))
+    }
+
+    pub fn assets(&self, binding: &str) -> Result<Fetcher, Error> {
+        Err(unbound(binding))
+    }
+}
+
+fn unbound(name: &str) -> Error {
+    Error::RustError(format!("{name} isn't bound when running natively"))
+}
+
+/// Brings the database up to date, as AUTO_MIGRATE does on a Worker.
+pub async fn migrate(env: &Env) -> Result<(), String> {
+    let db = env.d1("vault1").map_err(|err| err.to_string())?;
+    migrations::run(&db)
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+/// Handles `req` the way the Worker's `fetch` handler does. Its URI is absolute, with the origin
+/// the server is reached at.
+pub async fn fetch(env: &Env, req: Request<Body>) -> Response<Body> {
+    logging::apply_log_level(env);
+    let base_url = BaseUrl::new(req.uri(), &Settings::get(env));
+
+    migrations::run_on_first_request(env).await;
+
+    let mut app = router::with_cors(
+        router::api_router(env.clone()).layer(Extension(base_url)),
+        env,
+    );
+
+    match app.call(req).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    }
+}
+
+/// Runs the Worker's cron tasks, as its `scheduled` handler does.
+pub async fn scheduled(env: &Env) {
+    handlers::purge::run_maintenance(env).await;
+    handlers::backup::run_scheduled_backup(env).await;
+}
+
+#[derive(Deserialize)]
+struct TokenUser {
+    id: String,
+    name: Option<String>,
+    security_stamp: String,
+}
+
+/// An access token for the user registered as `email`, as the identity endpoint issues it after a
+/// password login.
+pub async fn access_token(env: &Env, email: &str) -> Result<String, String> {
+    let db = env.d1("vault1").map_err(|err| err.to_string())?;
+    let user: TokenUser = db
+        .first(
+            "SELECT id, name, security_stamp FROM users WHERE email = ?1",
+            &[email.to_lowercase().into()],
+        )
+        .await
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| format!("no user {email}"))?;
+
+    let settings = Settings::get(env);
+    let claims = JwtClaims::new(Claims {
+        sub: user.id,
+        sstamp: user.security_stamp,
+        premium: true,
+        name: user.name.unwrap_or_else(|| "User".to_string()),
+        email: email.to_lowercase(),
+        email_verified: true,
+        amr: vec!["Application".to_string()],
+        device: None,
+        iss: token_issuer(&settings),
+        aud: token_audience(&settings),
+    })
+    .set_duration_and_issuance(&TimeOptions::default(), chrono::Duration::hours(1))
+    .set_not_before(chrono::Utc::now());
+    KeyRing::access(env)
+        .and_then(|keys| keys.sign(&claims))
+        .map_err(|err| err.to_string())
+}
+
+/// Runs `future` to completion on the current thread.
+///
+/// Natively nothing handlers await waits on I/O (SQLite answers synchronously and the other
+/// bindings are missing), so polling until ready is enough of an executor.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
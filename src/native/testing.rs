@@ -0,0 +1,161 @@
+//! The fixture the crate's own tests share: a migrated env over an in-memory database, accounts
+//! in it, and requests through the router, made the way `tests/common` makes them.
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, Response, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+
+use super::{access_token, block_on, migrate, Env, SqliteDatabase};
+use crate::db::Database;
+use crate::models::organization::{MembershipStatus, MembershipType};
+use crate::time;
+
+pub const ORIGIN: &str = "https://vault.example.com";
+
+/// The client-side hash every registered test account logs in with.
+pub const PASSWORD_HASH: &str = "bWFzdGVyLXBhc3N3b3JkLWhhc2g=";
+
+/// An env without vars, secrets or tables, for reading settings.
+pub fn bare() -> Env {
+    Env::new(SqliteDatabase::in_memory().unwrap())
+}
+
+/// A migrated env with the JWT secrets set and registration open to `@example.com`. Vars added
+/// to it count until the first request reads the settings.
+pub fn env() -> Env {
+    let env = bare()
+        .with_secret("JWT_SECRET", "jwt-secret-for-tests")
+        .with_secret("JWT_REFRESH_SECRET", "jwt-refresh-secret-for-tests")
+        .with_secret("ALLOWED_EMAILS", "*@example.com");
+    block_on(migrate(&env)).unwrap();
+    env
+}
+
+/// Inserts the user `id` as `{id}@example.com` and returns an access token for them. The
+/// account has no usable password; [`register`] one to log in.
+pub fn user(env: &Env, id: &str) -> String {
+    let now = time::now_bw();
+    block_on(env.d1("vault1").unwrap().run(
+        "INSERT INTO users (id, email, master_password_hash, key, private_key, public_key, security_stamp, created_at, updated_at)
+         VALUES (?1, ?1 || '@example.com', 'hash', 'key', 'private', 'public', 'stamp', ?2, ?2)",
+        &[id.into(), now.as_str().into()],
+    ))
+    .unwrap();
+    token(env, &format!("{id}@example.com"))
+}
+
+/// Inserts the organization `id`, named `Org` and billed to alice. Each of `members`, the
+/// account `{name}@example.com`, joins it confirmed, with the given type, as `{name}-membership`.
+pub fn organization(env: &Env, id: &str, members: &[(&str, MembershipType)]) {
+    let db = env.d1("vault1").unwrap();
+    let now = time::now_bw();
+    block_on(db.run(
+        "INSERT INTO organizations (id, name, billing_email, created_at, updated_at)
+         VALUES (?1, 'Org', 'alice@example.com', ?2, ?2)",
+        &[id.into(), now.as_str().into()],
+    ))
+    .unwrap();
+    for (name, atype) in members {
+        block_on(db.run(
+            "INSERT INTO organization_users (id, organization_id, user_id, email, akey, status, atype, created_at, updated_at)
+             SELECT ?1 || '-membership', ?2, id, email, 'key', ?3, ?4, ?5, ?5 FROM users
+             WHERE email = ?1 || '@example.com'",
+            &[
+                (*name).into(),
+                id.into(),
+                (MembershipStatus::Confirmed as i32).into(),
+                (*atype as i32).into(),
+                now.as_str().into(),
+            ],
+        ))
+        .unwrap();
+    }
+}
+
+/// An access token for the account registered as `email`.
+pub fn token(env: &Env, email: &str) -> String {
+    block_on(access_token(env, email)).unwrap()
+}
+
+/// The body clients send to register `email` with [`PASSWORD_HASH`] and PBKDF2.
+pub fn registration(email: &str) -> Value {
+    json!({
+        "email": email,
+        "name": email.split('@').next(),
+        "masterPasswordHash": PASSWORD_HASH,
+        "masterPasswordHint": null,
+        "userSymmetricKey": "2.c3ltbWV0cmljLWtleQ==|aXY=|bWFj",
+        "userAsymmetricKeys": {
+            "publicKey": "cHVibGljLWtleQ==",
+            "encryptedPrivateKey": "2.cHJpdmF0ZS1rZXk=|aXY=|bWFj",
+        },
+        "kdf": 0,
+        "kdfIterations": 600000,
+    })
+}
+
+/// Registers `email` through the identity endpoint and returns an access token for it.
+pub fn register(env: &Env, email: &str) -> String {
+    let (status, body) = request(
+        env,
+        Method::POST,
+        "/identity/accounts/register",
+        None,
+        Some(registration(email)),
+    );
+    assert!(status.is_success(), "register {email}: {status} {body}");
+    token(env, email)
+}
+
+/// Makes a request to `path`, with `body` sent as JSON and `token` as the bearer token.
+pub fn request(
+    env: &Env,
+    method: Method,
+    path: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(format!("{ORIGIN}{path}"));
+    if let Some(token) = token {
+        builder = builder.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    let body = match body {
+        Some(body) => {
+            builder = builder.header(header::CONTENT_TYPE, "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    json_response(fetch(env, builder.body(body).unwrap()))
+}
+
+/// Posts `body`, already form-encoded, as clients do to the identity endpoints.
+pub fn post_form(env: &Env, path: &str, body: &str) -> (StatusCode, Value) {
+    let req = Request::post(format!("{ORIGIN}{path}"))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    json_response(fetch(env, req))
+}
+
+/// Handles `req`, whose URI is absolute, as the Worker would.
+pub fn fetch(env: &Env, req: Request<Body>) -> Response<Body> {
+    block_on(super::fetch(env, req))
+}
+
+/// The status of `response` and its body: JSON when it parses, a string when it doesn't and
+/// null when it's empty.
+pub fn json_response(response: Response<Body>) -> (StatusCode, Value) {
+    let status = response.status();
+    let bytes = block_on(response.into_body().collect()).unwrap().to_bytes();
+    let body = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into()))
+    };
+    (status, body)
+}
@@ -0,0 +1,153 @@
+//! Tells the user's other devices about a change, so they sync it right away.
+//!
+//! Handlers call these after a successful write. Each notification goes to every backend that is
+//! set up:
+//! - the notifications hub, for desktop and browser clients with a WebSocket open to
+//!   `/notifications/hub` (when `NOTIFICATIONS_HUB` is bound),
+//! - Web Push, for browsers that subscribed (when VAPID keys are configured),
+//! - the Bitwarden push relay, for the mobile apps (when `PUSH_INSTALLATION_ID` is set).
+//!
+//! The device that made the change (the `device` claim of its token) is left out everywhere: it
+//! already has the change. Notifications are fire-and-forget: failures are logged and never fail
+//! the request.
+
+use serde_json::{json, Value};
+use worker::{wasm_bindgen::JsValue, Method, Request, RequestInit, Stub};
+
+use crate::db::Db;
+use crate::Env;
+use crate::{durable::SEND_PATH, push, time};
+
+pub use crate::push::UpdateType;
+
+/// The user's notifications hub, when `NOTIFICATIONS_HUB` is bound.
+pub fn hub_stub(env: &Env, user_id: &str) -> Option<Stub> {
+    let namespace = env.durable_object("NOTIFICATIONS_HUB").ok()?;
+    match namespace.id_from_name(user_id).and_then(|id| id.get_stub()) {
+        Ok(stub) => Some(stub),
+        Err(e) => {
+            log::error!("Failed to reach the notifications hub: {:?}", e);
+            None
+        }
+    }
+}
+
+async fn send_hub_notification(
+    env: &Env,
+    update_type: UpdateType,
+    user_id: &str,
+    acting_device: Option<&str>,
+    payload: &Value,
+) {
+    let Some(stub) = hub_stub(env, user_id) else {
+        return;
+    };
+    // The hub skips the connection of the acting device, and clients ignore their own context
+    let notification = json!({
+        "ContextId": acting_device,
+        "Type": update_type as i32,
+        "Payload": payload,
+    });
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_body(Some(JsValue::from_str(&notification.to_string())));
+    let result =
+        match Request::new_with_init(&format!("https://notifications-hub{SEND_PATH}"), &init) {
+            Ok(request) => stub.fetch_with_request(request).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+    if let Err(e) = result {
+        log::error!("Failed to send {:?} hub notification: {:?}", update_type, e);
+    }
+}
+
+async fn send(
+    env: &Env,
+    db: &Db,
+    update_type: UpdateType,
+    user_id: &str,
+    acting_device: Option<&str>,
+    payload: Value,
+) {
+    send_hub_notification(env, update_type, user_id, acting_device, &payload).await;
+    push::send_web_push(env, db, update_type, user_id, acting_device, &payload).await;
+    push::send_relay_notification(env, db, update_type, user_id, acting_device, payload).await;
+}
+
+/// A cipher was created, changed or deleted.
+pub async fn notify_cipher_update(
+    env: &Env,
+    db: &Db,
+    update_type: UpdateType,
+    user_id: &str,
+    cipher_id: &str,
+    revision_date: &str,
+    acting_device: Option<&str>,
+) {
+    let payload = json!({
+        "id": cipher_id,
+        "userId": user_id,
+        "organizationId": null,
+        "collectionIds": null,
+        "revisionDate": revision_date,
+    });
+    send(env, db, update_type, user_id, acting_device, payload).await;
+}
+
+/// A folder was created, renamed or deleted.
+pub async fn notify_folder_update(
+    env: &Env,
+    db: &Db,
+    update_type: UpdateType,
+    user_id: &str,
+    folder_id: &str,
+    revision_date: &str,
+    acting_device: Option<&str>,
+) {
+    let payload = json!({
+        "id": folder_id,
+        "userId": user_id,
+        "revisionDate": revision_date,
+    });
+    send(env, db, update_type, user_id, acting_device, payload).await;
+}
+
+/// A Send was created, changed or deleted.
+pub async fn notify_send_update(
+    env: &Env,
+    db: &Db,
+    update_type: UpdateType,
+    user_id: &str,
+    send_id: &str,
+    revision_date: &str,
+    acting_device: Option<&str>,
+) {
+    let payload = json!({
+        "id": send_id,
+        "userId": user_id,
+        "revisionDate": revision_date,
+    });
+    send(env, db, update_type, user_id, acting_device, payload).await;
+}
+
+/// Too much changed to describe item by item (bulk changes, imports, purges): the devices do a
+/// full sync.
+pub async fn notify_vault_sync(env: &Env, db: &Db, user_id: &str, acting_device: Option<&str>) {
+    notify_user(env, db, UpdateType::SyncVault, user_id, acting_device).await;
+}
+
+/// A change to the account as a whole, such as new organization keys or a forced log out.
+pub async fn notify_user(
+    env: &Env,
+    db: &Db,
+    update_type: UpdateType,
+    user_id: &str,
+    acting_device: Option<&str>,
+) {
+    let payload = json!({
+        "userId": user_id,
+        "date": time::now_bw(),
+    });
+    send(env, db, update_type, user_id, acting_device, payload).await;
+}
@@ -0,0 +1,342 @@
+//! Bitwarden push relay integration (mobile sync notifications).
+//!
+//! The official iOS/Android apps only receive live sync updates through Bitwarden's push relay.
+//! Self-hosted servers authenticate to it with an installation id/key (request one at
+//! <https://bitwarden.com/host>), register each device's push token, and post notifications that
+//! the relay forwards to APNs/FCM.
+//!
+//! Configuration (all optional; push is disabled unless both id and key are set):
+//! - `PUSH_INSTALLATION_ID` / `PUSH_INSTALLATION_KEY` (secret): installation credentials.
+//! - `PUSH_RELAY_URI`: defaults to `https://push.bitwarden.com` (use `https://api.bitwarden.eu`
+//!   for EU installations).
+//! - `PUSH_IDENTITY_URI`: defaults to `https://identity.bitwarden.com`.
+//!
+//! Browser clients are notified directly through Web Push instead (see [`web_push`]). Handlers
+//! don't call these backends themselves but [`crate::notify`], which also covers the clients
+//! connected to the notifications hub.
+//!
+//! Every call is best effort: failures are logged and never fail the originating request.
+
+// Only the NotificationsHub frames messages, and it isn't built natively.
+#[cfg_attr(feature = "native-db", allow(dead_code))]
+pub mod signalr;
+mod web_push;
+
+use std::cell::RefCell;
+
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use worker::{wasm_bindgen::JsValue, Fetch, Headers, Method, Request, RequestInit};
+
+use crate::db::{Database, Db};
+use crate::Env;
+use crate::{
+    config::{PushRelaySettings, Settings},
+    error::{db_error, AppError},
+    models::device::Device,
+};
+
+use web_push::{Delivery, Subscription, VapidKeys};
+
+/// Refresh the cached relay token this many seconds before it expires.
+const TOKEN_EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// Notification types understood by the clients (Bitwarden `PushType`).
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)] // Not every type is sent yet
+pub enum UpdateType {
+    SyncCipherUpdate = 0,
+    SyncCipherCreate = 1,
+    SyncLoginDelete = 2,
+    SyncFolderDelete = 3,
+    SyncCiphers = 4,
+    SyncVault = 5,
+    SyncOrgKeys = 6,
+    SyncFolderCreate = 7,
+    SyncFolderUpdate = 8,
+    SyncCipherDelete = 9,
+    SyncSettings = 10,
+    LogOut = 11,
+    SyncSendCreate = 12,
+    SyncSendUpdate = 13,
+    SyncSendDelete = 14,
+    AuthRequest = 15,
+    AuthRequestResponse = 16,
+}
+
+thread_local! {
+    /// Relay bearer token and its expiry (unix seconds), cached per isolate.
+    static RELAY_TOKEN: RefCell<Option<(String, i64)>> = const { RefCell::new(None) };
+}
+
+#[derive(Deserialize)]
+struct RelayTokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+async fn relay_token(config: &PushRelaySettings) -> Result<String, AppError> {
+    let now = Utc::now().timestamp();
+    if let Some(token) = RELAY_TOKEN.with(|cached| {
+        cached
+            .borrow()
+            .as_ref()
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(token, _)| token.clone())
+    }) {
+        return Ok(token);
+    }
+
+    let body = form_urlencoded::Serializer::new(String::new())
+        .append_pair("grant_type", "client_credentials")
+        .append_pair("scope", "api.push")
+        .append_pair(
+            "client_id",
+            &format!("installation.{}", config.installation_id),
+        )
+        .append_pair("client_secret", &config.installation_key)
+        .finish();
+
+    let mut response = send(
+        &format!("{}/connect/token", config.identity_uri),
+        Method::Post,
+        "application/x-www-form-urlencoded",
+        None,
+        Some(body),
+    )
+    .await?;
+    if response.status_code() != 200 {
+        return Err(AppError::Internal(Some(format!(
+            "push relay token request returned HTTP {}",
+            response.status_code()
+        ))));
+    }
+    let token: RelayTokenResponse = response.json().await?;
+
+    let expires_at = now + token.expires_in - TOKEN_EXPIRY_MARGIN_SECS;
+    RELAY_TOKEN.with(|cached| {
+        *cached.borrow_mut() = Some((token.access_token.clone(), expires_at));
+    });
+    Ok(token.access_token)
+}
+
+async fn send(
+    url: &str,
+    method: Method,
+    content_type: &str,
+    bearer: Option<&str>,
+    body: Option<String>,
+) -> Result<worker::Response, AppError> {
+    let headers = Headers::new();
+    headers.set("Content-Type", content_type)?;
+    if let Some(bearer) = bearer {
+        headers.set("Authorization", &format!("Bearer {}", bearer))?;
+    }
+
+    let mut init = RequestInit::new();
+    init.with_method(method)
+        .with_headers(headers)
+        .with_body(body.map(|b| JsValue::from_str(&b)));
+
+    let request = Request::new_with_init(url, &init)?;
+    Ok(Fetch::Request(request).send().await?)
+}
+
+async fn relay_call(
+    config: &PushRelaySettings,
+    path: &str,
+    payload: Option<Value>,
+) -> Result<(), AppError> {
+    let token = relay_token(config).await?;
+    let response = send(
+        &format!("{}{}", config.relay_uri, path),
+        Method::Post,
+        "application/json",
+        Some(&token),
+        payload.map(|p| p.to_string()),
+    )
+    .await?;
+
+    match response.status_code() {
+        200..=299 => Ok(()),
+        status => Err(AppError::Internal(Some(format!(
+            "push relay {path} returned HTTP {status}"
+        )))),
+    }
+}
+
+/// Registers a device's push token with the relay.
+pub async fn register_push_device(env: &Env, device: &Device) {
+    let Some(config) = Settings::get(env).push_relay.clone() else {
+        return;
+    };
+    let (Some(push_uuid), Some(push_token)) = (&device.push_uuid, &device.push_token) else {
+        return;
+    };
+
+    let payload = json!({
+        "deviceId": push_uuid,
+        "pushToken": push_token,
+        "userId": device.user_id,
+        "type": device.atype,
+        "identifier": device.identifier,
+        "installationId": config.installation_id,
+    });
+    if let Err(e) = relay_call(&config, "/push/register", Some(payload)).await {
+        log::error!("Failed to register device {} for push: {:?}", device.id, e);
+    }
+}
+
+/// Removes a device registration from the relay.
+pub async fn unregister_push_device(env: &Env, push_uuid: &str) {
+    let Some(config) = Settings::get(env).push_relay.clone() else {
+        return;
+    };
+    if let Err(e) = relay_call(&config, &format!("/push/delete/{}", push_uuid), None).await {
+        log::error!("Failed to unregister push device {}: {:?}", push_uuid, e);
+    }
+}
+
+/// VAPID public key handed to browser clients, when Web Push is configured.
+pub fn web_push_public_key(env: &Env) -> Option<String> {
+    VapidKeys::from_env(env).map(|keys| keys.public_key)
+}
+
+async fn user_has_push_device(db: &Db, user_id: &str) -> Result<bool, AppError> {
+    let found: Option<String> = db
+        .first_column(
+            "SELECT id FROM devices WHERE user_id = ?1 AND push_token IS NOT NULL LIMIT 1",
+            &[user_id.into()],
+            "id",
+        )
+        .await
+        .map_err(db_error!())?;
+    Ok(found.is_some())
+}
+
+/// Sends a notification to the user's mobile apps through the push relay. The relay skips
+/// `acting_device`.
+pub(crate) async fn send_relay_notification(
+    env: &Env,
+    db: &Db,
+    update_type: UpdateType,
+    user_id: &str,
+    acting_device: Option<&str>,
+    payload: Value,
+) {
+    let Some(config) = Settings::get(env).push_relay.clone() else {
+        return;
+    };
+    match user_has_push_device(db, user_id).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            log::error!("Failed to look up push devices: {:?}", e);
+            return;
+        }
+    }
+
+    let body = json!({
+        "userId": user_id,
+        "organizationId": null,
+        "deviceId": null,
+        // The relay skips the device that made the change.
+        "identifier": acting_device,
+        "type": update_type as i32,
+        "payload": payload,
+        "clientType": null,
+        "installationId": null,
+    });
+    if let Err(e) = relay_call(&config, "/push/send", Some(body)).await {
+        log::error!(
+            "Failed to send {:?} push notification: {:?}",
+            update_type,
+            e
+        );
+    }
+}
+
+#[derive(Deserialize)]
+struct WebPushDevice {
+    id: String,
+    web_push_endpoint: String,
+    web_push_p256dh: String,
+    web_push_auth: String,
+}
+
+/// Sends a notification to the user's browsers subscribed to Web Push, except `acting_device`.
+pub(crate) async fn send_web_push(
+    env: &Env,
+    db: &Db,
+    update_type: UpdateType,
+    user_id: &str,
+    acting_device: Option<&str>,
+    payload: &Value,
+) {
+    let Some(keys) = VapidKeys::from_env(env) else {
+        return;
+    };
+    let devices: Vec<WebPushDevice> = match db
+        .all(
+            "SELECT id, web_push_endpoint, web_push_p256dh, web_push_auth FROM devices
+             WHERE user_id = ?1 AND identifier != ?2
+             AND web_push_endpoint IS NOT NULL AND web_push_p256dh IS NOT NULL AND web_push_auth IS NOT NULL",
+            &[user_id.into(), acting_device.unwrap_or("").into()],
+        )
+        .await
+    {
+        Ok(devices) => devices,
+        Err(e) => {
+            log::error!("Failed to look up Web Push devices: {:?}", e);
+            return;
+        }
+    };
+    if devices.is_empty() {
+        return;
+    }
+
+    // Same shape as the notification hub messages, wrapped the way the clients' service worker
+    // expects it.
+    let message = json!({
+        "data": {
+            "type": update_type as i32,
+            "contextId": acting_device,
+            "payload": payload,
+        }
+    })
+    .to_string();
+
+    for device in devices {
+        let subscription = Subscription {
+            endpoint: &device.web_push_endpoint,
+            p256dh: &device.web_push_p256dh,
+            auth: &device.web_push_auth,
+        };
+        match web_push::send(&keys, &subscription, message.as_bytes()).await {
+            Ok(Delivery::Sent) => {}
+            Ok(Delivery::Gone) => {
+                log::info!(
+                    "Dropping expired Web Push subscription of device {}",
+                    device.id
+                );
+                if let Err(e) = db
+                    .run(
+                        "UPDATE devices SET web_push_endpoint = NULL, web_push_p256dh = NULL, web_push_auth = NULL
+                         WHERE id = ?1",
+                        &[device.id.clone().into()],
+                    )
+                    .await
+                {
+                    log::error!("Failed to clear Web Push subscription: {:?}", e);
+                }
+            }
+            Err(e) => log::error!(
+                "Failed to send {:?} Web Push notification to device {}: {:?}",
+                update_type,
+                device.id,
+                e
+            ),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! The parts of the SignalR hub protocol the clients' notification connection uses: the
+//! handshake, keep-alive pings and `ReceiveMessage` invocations, in its JSON and MessagePack
+//! encodings.
+//!
+//! The official clients ask for MessagePack; JSON is kept for tools such as `wscat`. See
+//! <https://github.com/dotnet/aspnetcore/blob/main/src/SignalR/docs/specs/HubProtocol.md>.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Ends every JSON message, and the handshake of both protocols.
+const RECORD_SEPARATOR: char = '\u{1e}';
+
+/// Message types, the first field of every message.
+pub const INVOCATION: u64 = 1;
+pub const PING: u64 = 6;
+pub const CLOSE: u64 = 7;
+
+/// Method of the clients that receives notifications.
+pub const RECEIVE_MESSAGE: &str = "ReceiveMessage";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Json,
+    Messagepack,
+}
+
+/// A WebSocket frame to send.
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Deserialize)]
+struct HandshakeRequest {
+    protocol: String,
+    version: u32,
+}
+
+/// Reads the handshake request the client sends first. The error is for the handshake response.
+pub fn parse_handshake(message: &str) -> Result<Protocol, String> {
+    let record = message.trim_end_matches(RECORD_SEPARATOR);
+    let request: HandshakeRequest =
+        serde_json::from_str(record).map_err(|_| "Invalid handshake request".to_string())?;
+    if request.version != 1 {
+        return Err(format!("Unsupported protocol version {}", request.version));
+    }
+    match request.protocol.as_str() {
+        "json" => Ok(Protocol::Json),
+        "messagepack" => Ok(Protocol::Messagepack),
+        other => Err(format!("Unsupported protocol {other}")),
+    }
+}
+
+/// The handshake response, which is JSON whatever the protocol.
+pub fn handshake_response(error: Option<&str>) -> String {
+    let response = match error {
+        Some(error) => json!({ "error": error }),
+        None => json!({}),
+    };
+    format!("{response}{RECORD_SEPARATOR}")
+}
+
+/// The types of the messages in a frame from the client, which may hold several. Messages that
+/// can't be read are skipped.
+pub fn message_types(protocol: Protocol, frame: &[u8]) -> Vec<u64> {
+    match protocol {
+        Protocol::Json => String::from_utf8_lossy(frame)
+            .split(RECORD_SEPARATOR)
+            .filter_map(|record| serde_json::from_str::<Value>(record).ok())
+            .filter_map(|message| message["type"].as_u64())
+            .collect(),
+        Protocol::Messagepack => {
+            let mut types = Vec::new();
+            let mut rest = frame;
+            while let Some((length, header)) = read_varint(rest) {
+                let Some(end) = header.checked_add(length).filter(|end| *end <= rest.len()) else {
+                    break;
+                };
+                let message = &rest[header..end];
+                // Every message is an array whose first element is its type
+                if let Some(message_type) = message
+                    .split_first()
+                    .filter(|(marker, _)| matches!(marker, 0x91..=0x9f))
+                    .and_then(|(_, fields)| fields.first())
+                    .filter(|message_type| **message_type < 0x80)
+                {
+                    types.push(*message_type as u64);
+                }
+                rest = &rest[end..];
+            }
+            types
+        }
+    }
+}
+
+/// A ping, which the client expects at least every 30 seconds.
+pub fn ping(protocol: Protocol) -> Frame {
+    match protocol {
+        Protocol::Json => Frame::Text(format!("{}{RECORD_SEPARATOR}", json!({ "type": PING }))),
+        Protocol::Messagepack => Frame::Binary(framed(&[0x91, PING as u8])),
+    }
+}
+
+/// A call of `target` on the client, without waiting for a result.
+pub fn invocation(protocol: Protocol, target: &str, argument: &Value) -> Frame {
+    match protocol {
+        Protocol::Json => {
+            let message = json!({
+                "type": INVOCATION,
+                "target": target,
+                "arguments": [argument],
+            });
+            Frame::Text(format!("{message}{RECORD_SEPARATOR}"))
+        }
+        Protocol::Messagepack => {
+            // [type, headers, invocation id, target, arguments]
+            let mut message = vec![0x95, INVOCATION as u8, 0x80, 0xc0];
+            write_str(&mut message, target);
+            message.push(0x91);
+            write_value(&mut message, argument);
+            Frame::Binary(framed(&message))
+        }
+    }
+}
+
+/// A MessagePack message prefixed with its length.
+fn framed(message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    let mut length = message.len();
+    while length >= 0x80 {
+        frame.push((length & 0x7f) as u8 | 0x80);
+        length >>= 7;
+    }
+    frame.push(length as u8);
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// The length prefix of a MessagePack message, and how many bytes it took.
+fn read_varint(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut length = 0usize;
+    for (i, byte) in bytes.iter().take(5).enumerate() {
+        length |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((length, i + 1));
+        }
+    }
+    None
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    let len = value.len();
+    match len {
+        0..=31 => out.push(0xa0 | len as u8),
+        32..=0xff => out.extend_from_slice(&[0xd9, len as u8]),
+        0x100..=0xffff => {
+            out.push(0xda);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(0xdb);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// Array and map headers share their layout, only the markers differ.
+fn write_len(out: &mut Vec<u8>, len: usize, fix: u8, marker16: u8, marker32: u8) {
+    match len {
+        0..=15 => out.push(fix | len as u8),
+        16..=0xffff => {
+            out.push(marker16);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(marker32);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(value) => out.push(if *value { 0xc3 } else { 0xc2 }),
+        Value::Number(number) => {
+            if let Some(value) = number.as_i64() {
+                match value {
+                    0..=0x7f => out.push(value as u8),
+                    -32..=-1 => out.push(value as i8 as u8),
+                    _ => {
+                        out.push(0xd3);
+                        out.extend_from_slice(&value.to_be_bytes());
+                    }
+                }
+            } else if let Some(value) = number.as_u64() {
+                out.push(0xcf);
+                out.extend_from_slice(&value.to_be_bytes());
+            } else {
+                out.push(0xcb);
+                out.extend_from_slice(&number.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::String(value) => write_str(out, value),
+        Value::Array(values) => {
+            write_len(out, values.len(), 0x90, 0xdc, 0xdd);
+            for value in values {
+                write_value(out, value);
+            }
+        }
+        Value::Object(map) => {
+            write_len(out, map.len(), 0x80, 0xde, 0xdf);
+            for (key, value) in map {
+                write_str(out, key);
+                write_value(out, value);
+            }
+        }
+    }
+}
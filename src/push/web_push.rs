@@ -0,0 +1,348 @@
+//! Web Push delivery for browser clients (RFC 8030 / RFC 8291 / RFC 8292).
+//!
+//! Messages are encrypted with the subscription's ECDH key and auth secret (`aes128gcm` content
+//! encoding) and authorized with a VAPID JWT (ES256) signed by the server key. All primitives come
+//! from the runtime's WebCrypto implementation, so nothing extra is compiled into the Worker.
+//!
+//! Configuration (Web Push is disabled unless both keys are set):
+//! - `WEB_PUSH_VAPID_PUBLIC_KEY`: uncompressed P-256 public key, base64url (65 bytes).
+//! - `WEB_PUSH_VAPID_PRIVATE_KEY` (secret): P-256 private scalar, base64url (32 bytes).
+//! - `WEB_PUSH_SUBJECT`: optional `mailto:` or `https:` contact sent to push services (Safari
+//!   rejects VAPID tokens without it).
+//!
+//! A key pair can be generated with
+//! `npx web-push generate-vapid-keys` or OpenSSL.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL, Engine};
+use chrono::Utc;
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use serde_json::json;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::CryptoKey;
+use worker::{js_sys, Fetch, Headers, Method, Request, RequestInit};
+
+use crate::Env;
+use crate::{
+    config::Settings,
+    crypto::{random_bytes, subtle_crypto},
+    error::AppError,
+};
+
+/// Length of an uncompressed P-256 public key (`0x04 || X || Y`).
+const P256_PUBLIC_KEY_LEN: usize = 65;
+/// Record size advertised in the `aes128gcm` header. Payloads always fit in one record.
+const RECORD_SIZE: u32 = 4096;
+/// How long push services should keep undelivered messages.
+const MESSAGE_TTL_SECS: u32 = 24 * 60 * 60;
+/// VAPID tokens may be valid for at most 24 hours.
+const VAPID_TOKEN_TTL_SECS: i64 = 12 * 60 * 60;
+
+/// Server VAPID key pair.
+pub struct VapidKeys {
+    /// Base64url of the uncompressed public key, as handed to clients.
+    pub public_key: String,
+    public_bytes: Vec<u8>,
+    private_key: String,
+    subject: Option<String>,
+}
+
+impl VapidKeys {
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let settings = Settings::get(env).web_push.clone()?;
+        let public_key = settings.public_key.trim_end_matches('=').to_string();
+        let private_key = settings.private_key.trim_end_matches('=').to_string();
+
+        let public_bytes = BASE64URL.decode(&public_key).ok()?;
+        if public_bytes.len() != P256_PUBLIC_KEY_LEN || public_bytes[0] != 0x04 {
+            log::warn!("WEB_PUSH_VAPID_PUBLIC_KEY is not an uncompressed P-256 key");
+            return None;
+        }
+
+        Some(Self {
+            public_key,
+            public_bytes,
+            private_key,
+            subject: settings.subject,
+        })
+    }
+}
+
+/// A browser push subscription as stored on the device.
+pub struct Subscription<'a> {
+    pub endpoint: &'a str,
+    pub p256dh: &'a str,
+    pub auth: &'a str,
+}
+
+/// Outcome of a delivery attempt.
+pub enum Delivery {
+    Sent,
+    /// The push service no longer knows the subscription (HTTP 404/410); it should be dropped.
+    Gone,
+}
+
+/// Encrypts `payload` for `subscription` and posts it to the push service.
+pub async fn send(
+    keys: &VapidKeys,
+    subscription: &Subscription<'_>,
+    payload: &[u8],
+) -> Result<Delivery, AppError> {
+    let body = encrypt(subscription, payload).await?;
+    let token = vapid_token(keys, subscription.endpoint).await?;
+
+    let headers = Headers::new();
+    headers.set(
+        "Authorization",
+        &format!("vapid t={}, k={}", token, keys.public_key),
+    )?;
+    headers.set("Content-Encoding", "aes128gcm")?;
+    headers.set("Content-Type", "application/octet-stream")?;
+    headers.set("TTL", &MESSAGE_TTL_SECS.to_string())?;
+    headers.set("Urgency", "normal")?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_headers(headers)
+        .with_body(Some(Uint8Array::from(body.as_slice()).into()));
+
+    let request = Request::new_with_init(subscription.endpoint, &init)?;
+    let response = Fetch::Request(request).send().await?;
+    match response.status_code() {
+        200..=299 => Ok(Delivery::Sent),
+        404 | 410 => Ok(Delivery::Gone),
+        status => Err(AppError::Internal(Some(format!(
+            "Web Push service returned HTTP {status}"
+        )))),
+    }
+}
+
+/// RFC 8291 message encryption, producing a single `aes128gcm` record (RFC 8188).
+async fn encrypt(subscription: &Subscription<'_>, payload: &[u8]) -> Result<Vec<u8>, AppError> {
+    let ua_public = decode_b64url(subscription.p256dh, "p256dh")?;
+    let auth_secret = decode_b64url(subscription.auth, "auth")?;
+    if ua_public.len() != P256_PUBLIC_KEY_LEN {
+        return Err(AppError::Crypto("Invalid Web Push p256dh key".to_string()));
+    }
+
+    // Ephemeral application server key pair, used for this message only.
+    let ecdh = algorithm(&[
+        ("name", JsValue::from_str("ECDH")),
+        ("namedCurve", JsValue::from_str("P-256")),
+    ])?;
+    let key_pair = await_promise(
+        subtle_crypto()?.generate_key_with_object(&ecdh, true, &usages(&["deriveBits"])),
+        "generate ECDH key",
+    )
+    .await?;
+    let as_private = get_key(&key_pair, "privateKey")?;
+    let as_public = export_raw(&get_key(&key_pair, "publicKey")?).await?;
+
+    let ua_key = import_key(
+        "raw",
+        &Uint8Array::from(ua_public.as_slice()).into(),
+        &ecdh,
+        &[],
+    )
+    .await?;
+    let derive = algorithm(&[
+        ("name", JsValue::from_str("ECDH")),
+        ("public", ua_key.into()),
+    ])?;
+    let ecdh_secret = derive_bits(&derive, &as_private, 256).await?;
+
+    // IKM = HKDF(auth_secret, ecdh_secret, "WebPush: info" || 0x00 || ua_public || as_public)
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(&ua_public);
+    key_info.extend_from_slice(&as_public);
+    let ikm = hkdf(&auth_secret, &ecdh_secret, &key_info, 32).await?;
+
+    let salt = random_bytes(16)?;
+    let cek = hkdf(&salt, &ikm, b"Content-Encoding: aes128gcm\0", 16).await?;
+    let nonce = hkdf(&salt, &ikm, b"Content-Encoding: nonce\0", 12).await?;
+
+    // Single (last) record: payload followed by the 0x02 padding delimiter.
+    let mut plaintext = payload.to_vec();
+    plaintext.push(0x02);
+    let ciphertext = aes_gcm_encrypt(&cek, &nonce, &plaintext).await?;
+
+    let mut body = Vec::with_capacity(16 + 4 + 1 + as_public.len() + ciphertext.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.len() as u8);
+    body.extend_from_slice(&as_public);
+    body.extend_from_slice(&ciphertext);
+    Ok(body)
+}
+
+/// RFC 8292 VAPID token for the push service origin of `endpoint`.
+async fn vapid_token(keys: &VapidKeys, endpoint: &str) -> Result<String, AppError> {
+    let url = worker::Url::parse(endpoint)
+        .map_err(|_| AppError::BadRequest("Invalid Web Push endpoint".to_string()))?;
+    let audience = url.origin().ascii_serialization();
+
+    let header = json!({ "typ": "JWT", "alg": "ES256" });
+    let mut claims = json!({
+        "aud": audience,
+        "exp": Utc::now().timestamp() + VAPID_TOKEN_TTL_SECS,
+    });
+    if let Some(subject) = &keys.subject {
+        claims["sub"] = json!(subject);
+    }
+    let signing_input = format!(
+        "{}.{}",
+        BASE64URL.encode(header.to_string()),
+        BASE64URL.encode(claims.to_string())
+    );
+
+    let jwk = Object::new();
+    for (name, value) in [
+        ("kty", "EC".to_string()),
+        ("crv", "P-256".to_string()),
+        ("x", BASE64URL.encode(&keys.public_bytes[1..33])),
+        ("y", BASE64URL.encode(&keys.public_bytes[33..65])),
+        ("d", keys.private_key.clone()),
+    ] {
+        set(&jwk, name, &JsValue::from_str(&value))?;
+    }
+    let ecdsa = algorithm(&[
+        ("name", JsValue::from_str("ECDSA")),
+        ("namedCurve", JsValue::from_str("P-256")),
+    ])?;
+    let private_key = import_key("jwk", &jwk, &ecdsa, &["sign"]).await?;
+
+    // WebCrypto already emits the raw `r || s` form that JWS expects.
+    let params = algorithm(&[
+        ("name", JsValue::from_str("ECDSA")),
+        ("hash", JsValue::from_str("SHA-256")),
+    ])?;
+    let data = Uint8Array::from(signing_input.as_bytes());
+    let signature = await_promise(
+        subtle_crypto()?.sign_with_object_and_buffer_source(&params, &private_key, &data),
+        "sign VAPID token",
+    )
+    .await?;
+
+    Ok(format!(
+        "{}.{}",
+        signing_input,
+        BASE64URL.encode(Uint8Array::new(&signature).to_vec())
+    ))
+}
+
+async fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8], len: u32) -> Result<Vec<u8>, AppError> {
+    let base = import_key(
+        "raw",
+        &Uint8Array::from(ikm).into(),
+        &algorithm(&[("name", JsValue::from_str("HKDF"))])?,
+        &["deriveBits"],
+    )
+    .await?;
+    let params = algorithm(&[
+        ("name", JsValue::from_str("HKDF")),
+        ("hash", JsValue::from_str("SHA-256")),
+        ("salt", Uint8Array::from(salt).into()),
+        ("info", Uint8Array::from(info).into()),
+    ])?;
+    derive_bits(&params, &base, len * 8).await
+}
+
+async fn aes_gcm_encrypt(key: &[u8], nonce: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, AppError> {
+    let key = import_key(
+        "raw",
+        &Uint8Array::from(key).into(),
+        &algorithm(&[("name", JsValue::from_str("AES-GCM"))])?,
+        &["encrypt"],
+    )
+    .await?;
+    let params = algorithm(&[
+        ("name", JsValue::from_str("AES-GCM")),
+        ("iv", Uint8Array::from(nonce).into()),
+    ])?;
+    let data = Uint8Array::from(plaintext);
+    let ciphertext = await_promise(
+        subtle_crypto()?.encrypt_with_object_and_buffer_source(&params, &key, &data),
+        "AES-GCM encrypt",
+    )
+    .await?;
+    Ok(Uint8Array::new(&ciphertext).to_vec())
+}
+
+async fn derive_bits(params: &Object, key: &CryptoKey, bits: u32) -> Result<Vec<u8>, AppError> {
+    let derived = await_promise(
+        subtle_crypto()?.derive_bits_with_object(params, key, bits),
+        "deriveBits",
+    )
+    .await?;
+    Ok(Uint8Array::new(&derived).to_vec())
+}
+
+async fn import_key(
+    format: &str,
+    data: &Object,
+    algorithm: &Object,
+    key_usages: &[&str],
+) -> Result<CryptoKey, AppError> {
+    let key = await_promise(
+        subtle_crypto()?.import_key_with_object(
+            format,
+            data,
+            algorithm,
+            false,
+            &usages(key_usages),
+        ),
+        "import key",
+    )
+    .await?;
+    Ok(CryptoKey::from(key))
+}
+
+async fn export_raw(key: &CryptoKey) -> Result<Vec<u8>, AppError> {
+    let raw = await_promise(subtle_crypto()?.export_key("raw", key), "export key").await?;
+    Ok(Uint8Array::new(&raw).to_vec())
+}
+
+fn get_key(key_pair: &JsValue, name: &str) -> Result<CryptoKey, AppError> {
+    Reflect::get(key_pair, &JsValue::from_str(name))
+        .map(CryptoKey::from)
+        .map_err(|e| AppError::Crypto(format!("Missing {}: {:?}", name, e)))
+}
+
+async fn await_promise(
+    promise: Result<js_sys::Promise, JsValue>,
+    what: &str,
+) -> Result<JsValue, AppError> {
+    let promise = promise.map_err(|e| AppError::Crypto(format!("{} failed: {:?}", what, e)))?;
+    JsFuture::from(promise)
+        .await
+        .map_err(|e| AppError::Crypto(format!("{} failed: {:?}", what, e)))
+}
+
+fn algorithm(fields: &[(&str, JsValue)]) -> Result<Object, AppError> {
+    let object = Object::new();
+    for (name, value) in fields {
+        set(&object, name, value)?;
+    }
+    Ok(object)
+}
+
+fn set(object: &Object, name: &str, value: &JsValue) -> Result<(), AppError> {
+    Reflect::set(object, &JsValue::from_str(name), value)
+        .map(|_| ())
+        .map_err(|e| AppError::Crypto(format!("Failed to set {}: {:?}", name, e)))
+}
+
+fn usages(names: &[&str]) -> Array {
+    names.iter().map(|n| JsValue::from_str(n)).collect()
+}
+
+fn decode_b64url(value: &str, what: &str) -> Result<Vec<u8>, AppError> {
+    // Some clients hand out the subscription keys in standard base64.
+    let value = value
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_");
+    BASE64URL
+        .decode(value)
+        .map_err(|_| AppError::Crypto(format!("Invalid Web Push {} encoding", what)))
+}
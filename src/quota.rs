@@ -0,0 +1,263 @@
+//! Per-user object quotas (MAX_CIPHERS_PER_USER, MAX_FOLDERS_PER_USER, MAX_SENDS_PER_USER).
+//!
+//! A quota check reads counters kept on the user's row (`cipher_count`, `folder_count`,
+//! `send_count`) instead of counting rows. Every write that inserts or deletes a user's ciphers,
+//! folders or sends updates them in the same D1 batch, so they commit or roll back with the rows:
+//!
+//! - [`added`] and [`removed`] follow a statement that touched one user's rows, and apply its
+//!   `changes()`;
+//! - [`removing_ciphers`] precedes a delete of ciphers that may belong to several users;
+//! - [`recount`] recounts users from scratch, where a batch holds many writes.
+//!
+//! Ciphers count against the user who created them (`ciphers.user_id`), organization items
+//! included. The scheduled maintenance recounts every user with [`recount_all`], which corrects
+//! any drift.
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::config::{ObjectQuotas, Settings};
+use crate::db::{Database, Db, Param, Statement};
+use crate::error::{db_error, internal_error, AppError};
+use crate::Env;
+
+/// Users recounted per statement by [`recount_all`]
+const RECOUNT_BATCH_SIZE: u32 = 100;
+/// Statements [`recount_all`] runs per pass, so a large user table is covered over several runs
+const MAX_RECOUNT_BATCHES: u32 = 50;
+
+const RECOUNT_SQL: &str = "cipher_count = (SELECT COUNT(*) FROM ciphers WHERE user_id = users.id),
+     folder_count = (SELECT COUNT(*) FROM folders WHERE user_id = users.id),
+     send_count = (SELECT COUNT(*) FROM sends WHERE user_id = users.id)";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Object {
+    Cipher,
+    Folder,
+    Send,
+}
+
+impl Object {
+    fn column(self) -> &'static str {
+        match self {
+            Object::Cipher => "cipher_count",
+            Object::Folder => "folder_count",
+            Object::Send => "send_count",
+        }
+    }
+
+    fn noun(self) -> &'static str {
+        match self {
+            Object::Cipher => "items",
+            Object::Folder => "folders",
+            Object::Send => "Sends",
+        }
+    }
+
+    fn limit(self, quotas: &ObjectQuotas) -> Option<u64> {
+        match self {
+            Object::Cipher => quotas.ciphers,
+            Object::Folder => quotas.folders,
+            Object::Send => quotas.sends,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ObjectCounts {
+    cipher_count: u64,
+    folder_count: u64,
+    send_count: u64,
+}
+
+impl ObjectCounts {
+    fn get(&self, object: Object) -> u64 {
+        match object {
+            Object::Cipher => self.cipher_count,
+            Object::Folder => self.folder_count,
+            Object::Send => self.send_count,
+        }
+    }
+}
+
+/// Rejects a write that would take the user past a quota. `incoming` is how many of each object
+/// the write adds; the user's row is only read when one of them has a limit.
+pub async fn check(
+    db: &Db,
+    env: &Env,
+    user_id: &str,
+    incoming: &[(Object, usize)],
+) -> Result<(), AppError> {
+    enforce(
+        db,
+        env,
+        "SELECT cipher_count, folder_count, send_count FROM users WHERE id = ?1",
+        user_id,
+        incoming,
+    )
+    .await
+}
+
+/// Like [`check`], for an import replacing the personal vault: only the user's organization
+/// items and sends remain.
+pub async fn check_replacing(
+    db: &Db,
+    env: &Env,
+    user_id: &str,
+    incoming: &[(Object, usize)],
+) -> Result<(), AppError> {
+    enforce(
+        db,
+        env,
+        "SELECT (SELECT COUNT(*) FROM ciphers WHERE user_id = ?1 AND organization_id IS NOT NULL) AS cipher_count,
+                0 AS folder_count, send_count
+         FROM users WHERE id = ?1",
+        user_id,
+        incoming,
+    )
+    .await
+}
+
+/// Compares the counts `counts_sql` reads for the user with the limits of `incoming`.
+async fn enforce(
+    db: &Db,
+    env: &Env,
+    counts_sql: &str,
+    user_id: &str,
+    incoming: &[(Object, usize)],
+) -> Result<(), AppError> {
+    let settings = Settings::get(env);
+    let limited: Vec<(Object, u64, u64)> = incoming
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .filter_map(|&(object, count)| {
+            object
+                .limit(&settings.object_quotas)
+                .map(|limit| (object, count as u64, limit))
+        })
+        .collect();
+    if limited.is_empty() {
+        return Ok(());
+    }
+
+    let counts: ObjectCounts = db
+        .first(counts_sql, &[user_id.into()])
+        .await
+        .map_err(db_error!())?
+        .unwrap_or_default();
+
+    for (object, incoming, limit) in limited {
+        let existing = counts.get(object);
+        if existing.saturating_add(incoming) > limit {
+            let noun = object.noun();
+            return Err(AppError::BadRequest(format!(
+                "This account is limited to {limit} {noun}. It has {existing}, and this would add {incoming}; delete some {noun} first"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Adds the rows the previous statement of the batch inserted to the user's counter.
+pub fn added(db: &Db, user_id: &str, object: Object) -> Result<Statement, AppError> {
+    let column = object.column();
+    Database::prepare(
+        db,
+        &format!("UPDATE users SET {column} = {column} + changes() WHERE id = ?1"),
+        &[user_id.into()],
+    )
+    .map_err(db_error!())
+}
+
+/// Subtracts the rows the previous statement of the batch deleted from the user's counter.
+pub fn removed(db: &Db, user_id: &str, object: Object) -> Result<Statement, AppError> {
+    let column = object.column();
+    Database::prepare(
+        db,
+        &format!("UPDATE users SET {column} = MAX({column} - changes(), 0) WHERE id = ?1"),
+        &[user_id.into()],
+    )
+    .map_err(db_error!())
+}
+
+/// Subtracts the ciphers matching `condition` (on `ciphers` aliased `c`, with `params` bound)
+/// from their owners' counters. Goes right before the statement deleting them, in the same batch.
+pub fn removing_ciphers(db: &Db, condition: &str, params: &[Param]) -> Result<Statement, AppError> {
+    Database::prepare(
+        db,
+        &format!(
+            "UPDATE users SET cipher_count = MAX(cipher_count - (
+                 SELECT COUNT(*) FROM ciphers AS c WHERE c.user_id = users.id AND {condition}
+             ), 0)
+             WHERE id IN (SELECT c.user_id FROM ciphers AS c WHERE {condition})"
+        ),
+        params,
+    )
+    .map_err(db_error!())
+}
+
+/// Recounts every counter of the given users.
+pub fn recount(db: &Db, user_ids: &[&str]) -> Result<Statement, AppError> {
+    let user_ids = serde_json::to_string(user_ids).map_err(internal_error!())?;
+    Database::prepare(
+        db,
+        &format!("UPDATE users SET {RECOUNT_SQL} WHERE id IN (SELECT value FROM json_each(?1))"),
+        &[user_ids.into()],
+    )
+    .map_err(db_error!())
+}
+
+/// Recounts the counters of up to [`MAX_RECOUNT_BATCHES`] × [`RECOUNT_BATCH_SIZE`] users, in id
+/// order from a random starting point and wrapping around, and returns how many were off.
+pub async fn recount_all(env: &Env) -> Result<u32, worker::Error> {
+    let db: Db = env.d1("vault1")?;
+    let start = Uuid::new_v4().to_string();
+
+    let mut corrected = 0;
+    let mut batches = 0;
+    // Everything after the starting point, then everything up to it
+    for (after, until) in [(start.as_str(), None), ("", Some(start.as_str()))] {
+        let mut after = after.to_string();
+        while batches < MAX_RECOUNT_BATCHES {
+            batches += 1;
+            let ids: Vec<IdRow> = db
+                .all(
+                    "SELECT id FROM users WHERE id > ?1 AND (?2 IS NULL OR id <= ?2) ORDER BY id LIMIT ?3",
+                    &[
+                        after.as_str().into(),
+                        until.into(),
+                        RECOUNT_BATCH_SIZE.into(),
+                    ],
+                )
+                .await?;
+            let Some(last) = ids.last() else {
+                break;
+            };
+            after = last.id.clone();
+
+            let ids: Vec<&str> = ids.iter().map(|row| row.id.as_str()).collect();
+            corrected += db
+                .run(
+                    &format!(
+                        "UPDATE users SET {RECOUNT_SQL}
+                     WHERE id IN (SELECT value FROM json_each(?1))
+                     AND (cipher_count <> (SELECT COUNT(*) FROM ciphers WHERE user_id = users.id)
+                          OR folder_count <> (SELECT COUNT(*) FROM folders WHERE user_id = users.id)
+                          OR send_count <> (SELECT COUNT(*) FROM sends WHERE user_id = users.id))"
+                    ),
+                    &[serde_json::to_string(&ids)?.into()],
+                )
+                .await? as u32;
+
+            if (ids.len() as u32) < RECOUNT_BATCH_SIZE {
+                break;
+            }
+        }
+    }
+    Ok(corrected)
+}
+
+#[derive(Deserialize)]
+struct IdRow {
+    id: String,
+}
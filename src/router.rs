@@ -1,19 +1,42 @@
 use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, MatchedPath, Request, State},
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, CONTENT_LENGTH,
+        },
+        request::Parts,
+        HeaderValue,
+    },
+    middleware::{self, Next},
+    response::Response,
     routing::{delete, get, post, put},
-    Router,
+    Extension, Router,
 };
+use http_body_util::Limited;
 use std::sync::Arc;
-use worker::Env;
+use std::time::Duration;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::config::Settings;
+use crate::error::AppError;
+use crate::logging;
+use crate::Env;
 
 use crate::handlers::{
-    accounts, attachments, ciphers, config, devices, domains, emergency_access, folders, identity,
-    import, meta, sync, twofactor, webauth,
+    account_recovery, accounts, admin, attachments, auth_requests, backup, billing_stubs, ciphers,
+    collections, config, dev, devices, docs, domains, emergency_access, events, folders, groups,
+    icons, identity, import, invitations, meta, notifications, organizations, policies, sends,
+    stubs, sync, twofactor, vaultwarden, web_vault, webauth,
 };
 
 pub fn api_router(env: Env) -> Router {
+    let settings = Settings::get(&env);
+    let base_path = settings.base_path.clone();
     let app_state = Arc::new(env);
 
-    Router::new()
+    let router = Router::new()
         // Identity/Auth routes
         .route("/identity/accounts/prelogin", post(accounts::prelogin))
         .route("/identity/accounts/register", post(accounts::register))
@@ -26,6 +49,11 @@ pub fn api_router(env: Env) -> Router {
             "/identity/accounts/register/send-verification-email",
             post(accounts::send_verification_email),
         )
+        // Compatibility routes: paths older clients use for the same operations
+        .route("/api/accounts/prelogin", post(accounts::prelogin))
+        .route("/api/accounts/register", post(accounts::register))
+        .route("/api/ciphers/{id}", post(ciphers::update_cipher))
+        .route("/api/folders/{id}", post(folders::update_folder))
         // Main data sync route
         .route("/api/sync", get(sync::get_sync_data))
         // For on-demand sync checks
@@ -36,6 +64,11 @@ pub fn api_router(env: Env) -> Router {
         .route("/api/accounts/profile", post(accounts::post_profile))
         .route("/api/accounts/profile", put(accounts::put_profile))
         .route("/api/accounts/avatar", put(accounts::put_avatar))
+        // New device verification
+        .route(
+            "/api/accounts/verify-devices",
+            get(accounts::get_verify_devices).put(accounts::put_verify_devices),
+        )
         // Delete account
         .route("/api/accounts", delete(accounts::delete_account))
         .route("/api/accounts/delete", post(accounts::delete_account))
@@ -43,22 +76,64 @@ pub fn api_router(env: Env) -> Router {
         .route("/api/accounts/kdf", post(accounts::post_kdf))
         // Change password
         .route("/api/accounts/password", post(accounts::post_password))
+        .route(
+            "/api/accounts/update-temp-password",
+            put(accounts::put_update_temp_password),
+        )
         // Rotate encryption keys
         .route(
             "/api/accounts/key-management/rotate-user-account-keys",
             post(accounts::post_rotatekey),
         )
-        // Auth requests (login with device) - stub to prevent client 404s
-        .route("/api/auth-requests", get(accounts::get_auth_requests))
+        // Auth requests (login with device)
+        .route("/api/auth-requests", get(auth_requests::get_auth_requests))
+        .route(
+            "/api/auth-requests",
+            post(auth_requests::create_auth_request),
+        )
         .route(
             "/api/auth-requests/pending",
-            get(accounts::get_auth_requests_pending),
+            get(auth_requests::get_auth_requests_pending),
+        )
+        .route(
+            "/api/auth-requests/{id}",
+            get(auth_requests::get_auth_request),
+        )
+        .route(
+            "/api/auth-requests/{id}",
+            put(auth_requests::put_auth_request),
+        )
+        .route(
+            "/api/auth-requests/{id}/response",
+            get(auth_requests::get_auth_request_response),
         )
         // Ciphers CRUD
         .route("/api/ciphers", get(ciphers::list_ciphers))
         .route("/api/ciphers", post(ciphers::create_cipher_simple))
         .route("/api/ciphers/create", post(ciphers::create_cipher))
         .route("/api/ciphers/import", post(import::import_data))
+        .route(
+            "/api/ciphers/import/start",
+            post(import::start_import_session),
+        )
+        .route("/api/ciphers/import/chunk", post(import::post_import_chunk))
+        .route(
+            "/api/ciphers/import/commit",
+            post(import::commit_import_session),
+        )
+        .route("/api/ciphers/import/{id}", get(import::get_import_session))
+        .route(
+            "/api/ciphers/import-organization",
+            post(import::import_organization_data),
+        )
+        .route(
+            "/api/organizations/{id}/import",
+            post(import::import_organization),
+        )
+        .route(
+            "/api/ciphers/organization-details",
+            get(ciphers::list_organization_ciphers),
+        )
         .route("/api/ciphers/{id}", get(ciphers::get_cipher))
         .route(
             "/api/ciphers/{id}/details",
@@ -93,7 +168,6 @@ pub fn api_router(env: Env) -> Router {
             post(attachments::delete_attachment_post),
         )
         .route("/api/ciphers/{id}", put(ciphers::update_cipher))
-        .route("/api/ciphers/{id}", post(ciphers::update_cipher))
         // Cipher soft delete (PUT sets deleted_at timestamp)
         .route("/api/ciphers/{id}/delete", put(ciphers::soft_delete_cipher))
         // Cipher hard delete (DELETE/POST permanently removes cipher)
@@ -124,6 +198,30 @@ pub fn api_router(env: Env) -> Router {
         .route("/api/ciphers", delete(ciphers::hard_delete_ciphers_bulk))
         // Cipher restore (clears deleted_at)
         .route("/api/ciphers/{id}/restore", put(ciphers::restore_cipher))
+        // Organization vault (admin) variants
+        .route("/api/ciphers/admin", post(ciphers::create_cipher_admin))
+        .route("/api/ciphers/{id}/admin", get(ciphers::get_cipher_admin))
+        .route("/api/ciphers/{id}/admin", put(ciphers::update_cipher_admin))
+        .route(
+            "/api/ciphers/{id}/admin",
+            post(ciphers::update_cipher_admin),
+        )
+        .route(
+            "/api/ciphers/{id}/admin",
+            delete(ciphers::hard_delete_cipher_admin),
+        )
+        .route(
+            "/api/ciphers/{id}/delete-admin",
+            put(ciphers::soft_delete_cipher_admin),
+        )
+        .route(
+            "/api/ciphers/{id}/delete-admin",
+            post(ciphers::hard_delete_cipher_admin),
+        )
+        .route(
+            "/api/ciphers/{id}/restore-admin",
+            put(ciphers::restore_cipher_admin),
+        )
         // Cipher bulk restore
         .route("/api/ciphers/restore", put(ciphers::restore_ciphers_bulk))
         // Move ciphers to folder
@@ -138,17 +236,83 @@ pub fn api_router(env: Env) -> Router {
         .route("/api/folders/{id}", put(folders::update_folder))
         .route("/api/folders/{id}", delete(folders::delete_folder))
         .route("/api/folders/{id}/delete", post(folders::delete_folder))
+        // Sends
+        .route("/api/sends", get(sends::get_sends))
+        .route("/api/sends", post(sends::post_send))
+        .route("/api/sends/{id}", get(sends::get_send))
+        .route("/api/sends/{id}", put(sends::put_send))
+        .route("/api/sends/{id}", delete(sends::delete_send))
+        .route(
+            "/api/sends/{id}/remove-password",
+            put(sends::put_remove_password),
+        )
+        .route("/api/sends/file/v2", post(sends::post_send_file_v2))
+        .route(
+            "/api/sends/{id}/file/{file_id}",
+            post(sends::post_send_file),
+        )
+        // Note: the file download (GET /api/sends/{id}/{file_id}?token=...) is handled in
+        // entry.js for zero-copy streaming
+        .route("/api/sends/access/{id}", post(sends::post_access))
+        .route(
+            "/api/sends/{id}/access/file/{file_id}",
+            post(sends::post_access_file),
+        )
         .route("/api/config", get(config::config))
+        .route("/icons/{domain}/icon.png", get(icons::get_icon))
         // Meta endpoints (mirrors a subset of vaultwarden core/mod.rs)
+        .route("/alive", get(meta::alive))
+        .route("/now", get(meta::now))
         .route("/api/alive", get(meta::alive))
         .route("/api/now", get(meta::now))
         .route("/api/version", get(meta::version))
         .route("/api/hibp/breach", get(meta::hibp_breach))
+        // API documentation
+        .route("/api/docs/openapi.json", get(docs::get_openapi))
+        .route("/api/docs", get(docs::get_docs_ui))
+        // Billing (stubbed - every organization is self-hosted without a plan)
+        .route("/api/plans", get(billing_stubs::get_plans))
+        .route(
+            "/api/plans/sales-tax-rates",
+            get(billing_stubs::get_sales_tax_rates),
+        )
+        .route("/api/accounts/tax", get(billing_stubs::get_account_tax))
+        .route(
+            "/api/organizations/{id}/tax",
+            get(billing_stubs::get_organization_tax),
+        )
+        .route(
+            "/api/organizations/{id}/billing",
+            get(billing_stubs::get_billing),
+        )
+        .route(
+            "/api/organizations/{id}/billing/metadata",
+            get(billing_stubs::get_billing_metadata),
+        )
+        .route(
+            "/api/organizations/{id}/billing-status",
+            get(billing_stubs::get_billing_status),
+        )
+        .route(
+            "/api/organizations/{id}/subscription",
+            get(billing_stubs::get_subscription),
+        )
+        // Not implemented yet (stubbed - empty answers for endpoints clients poll, see stubs.rs)
+        .route(
+            "/api/accounts/sso-user-identifier",
+            get(stubs::get_sso_user_identifier),
+        )
+        .route("/api/notifications", get(stubs::get_notifications))
+        .route("/api/tasks", get(stubs::get_security_tasks))
+        .route(
+            "/api/organizations/{id}/auth-requests",
+            get(stubs::get_organization_auth_requests),
+        )
         // Settings (stubbed)
         .route("/api/settings/domains", get(domains::get_domains))
         .route("/api/settings/domains", post(domains::post_domains))
         .route("/api/settings/domains", put(domains::put_domains))
-        // Emergency access (stub - returns empty lists, feature not supported)
+        // Emergency access
         .route(
             "/api/emergency-access/trusted",
             get(emergency_access::get_trusted_contacts),
@@ -157,13 +321,90 @@ pub fn api_router(env: Env) -> Router {
             "/api/emergency-access/granted",
             get(emergency_access::get_granted_access),
         )
-        // Devices (stub - device tracking not implemented, JWT-based auth)
+        .route(
+            "/api/emergency-access/invite",
+            post(emergency_access::post_invite),
+        )
+        .route(
+            "/api/emergency-access/{id}",
+            get(emergency_access::get_emergency_access),
+        )
+        .route(
+            "/api/emergency-access/{id}",
+            put(emergency_access::put_emergency_access),
+        )
+        .route(
+            "/api/emergency-access/{id}",
+            post(emergency_access::put_emergency_access),
+        )
+        .route(
+            "/api/emergency-access/{id}",
+            delete(emergency_access::delete_emergency_access),
+        )
+        .route(
+            "/api/emergency-access/{id}/delete",
+            post(emergency_access::delete_emergency_access),
+        )
+        .route(
+            "/api/emergency-access/{id}/reinvite",
+            post(emergency_access::post_reinvite),
+        )
+        .route(
+            "/api/emergency-access/{id}/accept",
+            post(emergency_access::post_accept),
+        )
+        .route(
+            "/api/emergency-access/{id}/confirm",
+            post(emergency_access::post_confirm),
+        )
+        .route(
+            "/api/emergency-access/{id}/initiate",
+            post(emergency_access::post_initiate),
+        )
+        .route(
+            "/api/emergency-access/{id}/approve",
+            post(emergency_access::post_approve),
+        )
+        .route(
+            "/api/emergency-access/{id}/reject",
+            post(emergency_access::post_reject),
+        )
+        .route(
+            "/api/emergency-access/{id}/view",
+            post(emergency_access::post_view),
+        )
+        .route(
+            "/api/emergency-access/{id}/takeover",
+            post(emergency_access::post_takeover),
+        )
+        .route(
+            "/api/emergency-access/{id}/policies",
+            get(emergency_access::get_policies),
+        )
+        .route(
+            "/api/emergency-access/{id}/password",
+            post(emergency_access::post_password),
+        )
+        // Devices
         .route("/api/devices", get(devices::get_devices))
         .route("/api/devices/knowndevice", get(devices::get_known_device))
         .route(
             "/api/devices/identifier/{device_id}",
             get(devices::get_device),
         )
+        .route("/api/devices/{id}", delete(devices::delete_device))
+        .route("/api/devices/{id}/deactivate", post(devices::delete_device))
+        .route(
+            "/api/devices/{id}/sessions",
+            delete(devices::delete_device_sessions),
+        )
+        // Trusted device encryption keys
+        .route("/api/devices/{id}/keys", put(devices::put_device_keys))
+        .route("/api/devices/{id}/keys", post(devices::put_device_keys))
+        .route(
+            "/api/devices/{id}/retrieve-keys",
+            post(devices::post_retrieve_device_keys),
+        )
         .route(
             "/api/devices/identifier/{device_id}/token",
             post(devices::post_device_token),
@@ -180,6 +421,18 @@ pub fn api_router(env: Env) -> Router {
             "/api/devices/identifier/{device_id}/clear-token",
             post(devices::post_clear_device_token),
         )
+        .route(
+            "/api/devices/identifier/{device_id}/web-push-auth",
+            put(devices::put_device_web_push_auth),
+        )
+        .route(
+            "/api/devices/identifier/{device_id}/web-push-auth",
+            post(devices::put_device_web_push_auth),
+        )
+        .route(
+            "/api/web-push/register",
+            post(devices::post_web_push_register),
+        )
         // WebAuthn (stub - prevents 404 errors, passkeys not supported)
         .route("/api/webauthn", get(webauth::get_webauthn_credentials))
         // Two-factor authentication
@@ -210,5 +463,790 @@ pub fn api_router(env: Env) -> Router {
         )
         .route("/api/two-factor/get-recover", post(twofactor::get_recover))
         .route("/api/two-factor/recover", post(twofactor::recover))
-        .with_state(app_state)
+        // Organizations
+        .route("/api/organizations", get(organizations::get_organizations))
+        .route("/api/organizations", post(organizations::post_organization))
+        .route(
+            "/api/organizations/{id}",
+            get(organizations::get_organization),
+        )
+        .route(
+            "/api/organizations/{id}",
+            put(organizations::put_organization),
+        )
+        .route(
+            "/api/organizations/{id}",
+            post(organizations::put_organization),
+        )
+        .route(
+            "/api/organizations/{id}",
+            delete(organizations::delete_organization),
+        )
+        .route(
+            "/api/organizations/{id}/delete",
+            post(organizations::delete_organization),
+        )
+        .route(
+            "/api/organizations/{id}/leave",
+            post(organizations::post_leave),
+        )
+        .route(
+            "/api/organizations/{id}/keys",
+            get(organizations::get_organization_keys),
+        )
+        .route(
+            "/api/organizations/{id}/keys",
+            post(organizations::post_organization_keys),
+        )
+        .route(
+            "/api/organizations/{id}/users",
+            get(organizations::get_members),
+        )
+        .route(
+            "/api/organizations/{id}/users/invite",
+            post(organizations::post_invite),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}",
+            get(organizations::get_member),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}",
+            put(organizations::put_member),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}",
+            post(organizations::put_member),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}",
+            delete(organizations::delete_member),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/delete",
+            post(organizations::delete_member),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/accept",
+            post(organizations::post_accept_invite),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/confirm",
+            post(organizations::post_confirm_member),
+        )
+        .route(
+            "/api/organizations/{id}/public-key",
+            get(account_recovery::get_organization_public_key),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/reset-password-enrollment",
+            put(account_recovery::put_reset_password_enrollment),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/reset-password-details",
+            get(account_recovery::get_reset_password_details),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/admin-reset-password",
+            put(account_recovery::put_admin_reset_password),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/reset-password",
+            put(account_recovery::put_admin_reset_password),
+        )
+        .route(
+            "/api/users/{id}/public-key",
+            get(accounts::get_user_public_key),
+        )
+        // Policies
+        .route(
+            "/api/organizations/{id}/policies",
+            get(policies::get_policies),
+        )
+        .route(
+            "/api/organizations/{id}/policies/{policy_type}",
+            get(policies::get_policy),
+        )
+        .route(
+            "/api/organizations/{id}/policies/{policy_type}",
+            put(policies::put_policy),
+        )
+        // Groups
+        .route("/api/organizations/{id}/groups", get(groups::get_groups))
+        .route("/api/organizations/{id}/groups", post(groups::post_group))
+        .route(
+            "/api/organizations/{id}/groups/details",
+            get(groups::get_groups_details),
+        )
+        .route(
+            "/api/organizations/{id}/groups/{group_id}",
+            get(groups::get_group),
+        )
+        .route(
+            "/api/organizations/{id}/groups/{group_id}",
+            put(groups::put_group),
+        )
+        .route(
+            "/api/organizations/{id}/groups/{group_id}",
+            post(groups::put_group),
+        )
+        .route(
+            "/api/organizations/{id}/groups/{group_id}",
+            delete(groups::delete_group),
+        )
+        .route(
+            "/api/organizations/{id}/groups/{group_id}/delete",
+            post(groups::delete_group),
+        )
+        .route(
+            "/api/organizations/{id}/groups/{group_id}/details",
+            get(groups::get_group_details),
+        )
+        .route(
+            "/api/organizations/{id}/groups/{group_id}/users",
+            get(groups::get_group_users),
+        )
+        .route(
+            "/api/organizations/{id}/groups/{group_id}/users",
+            put(groups::put_group_users),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/groups",
+            get(groups::get_member_groups),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/groups",
+            put(groups::put_member_groups),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/groups",
+            post(groups::put_member_groups),
+        )
+        // Event logs
+        .route("/api/accounts/events", get(events::get_account_events))
+        .route(
+            "/api/organizations/{id}/events",
+            get(events::get_org_events),
+        )
+        .route(
+            "/api/organizations/{id}/users/{member_id}/events",
+            get(events::get_member_events),
+        )
+        // Collections
+        .route("/api/collections", get(collections::get_user_collections))
+        .route(
+            "/api/organizations/{id}/collections",
+            get(collections::get_org_collections),
+        )
+        .route(
+            "/api/organizations/{id}/collections",
+            post(collections::post_collection),
+        )
+        .route(
+            "/api/organizations/{id}/collections/details",
+            get(collections::get_org_collections_details),
+        )
+        .route(
+            "/api/organizations/{id}/collections/{collection_id}/details",
+            get(collections::get_collection_details),
+        )
+        .route(
+            "/api/organizations/{id}/collections/{collection_id}",
+            put(collections::put_collection),
+        )
+        .route(
+            "/api/organizations/{id}/collections/{collection_id}",
+            post(collections::put_collection),
+        )
+        .route(
+            "/api/organizations/{id}/collections/{collection_id}",
+            delete(collections::delete_collection),
+        )
+        .route(
+            "/api/organizations/{id}/collections/{collection_id}/delete",
+            post(collections::delete_collection),
+        )
+        // Notifications hub (WebSocket)
+        .route("/notifications/hub", get(notifications::get_hub))
+        .route(
+            "/notifications/hub/negotiate",
+            post(notifications::post_negotiate),
+        )
+        // Local development (DEV_SEED)
+        .route("/dev/seed", post(dev::post_seed))
+        // Admin
+        .route("/admin/backup", get(backup::get_backup))
+        .route("/admin/diagnostics", get(admin::get_diagnostics))
+        .route("/admin/invite", post(invitations::post_invite))
+        .route("/admin/invites", get(invitations::get_invites))
+        .route("/admin/invites/{email}", delete(invitations::delete_invite))
+        .route("/admin/mail/test", post(admin::post_mail_test))
+        .route("/admin/maintenance", post(admin::post_maintenance))
+        .route(
+            "/admin/migrate/vaultwarden",
+            post(vaultwarden::post_migrate_vaultwarden),
+        )
+        .route("/admin/migrations", post(admin::post_migrations))
+        .route("/admin/orphans", post(admin::post_orphans))
+        .route("/admin/restore", post(backup::post_restore))
+        .route(
+            "/admin/users/{id}/purge-vault",
+            post(admin::post_purge_user_vault),
+        )
+        .route_layer(middleware::from_fn_with_state(settings.clone(), limit_body))
+        .route_layer(middleware::from_fn(logging::record_route))
+        // Web vault (everything else)
+        .fallback(web_vault::serve)
+        .method_not_allowed_fallback(stubs::method_not_allowed)
+        .with_state(app_state.clone())
+        // Bodies are bounded by `limit_body` instead
+        .layer(DefaultBodyLimit::disable())
+        .layer(Extension(settings))
+        .layer(middleware::from_fn_with_state(
+            app_state,
+            logging::request_log,
+        ));
+
+    // BASE_PATH mounts everything, web vault included, under a prefix; other paths are 404s
+    if base_path.is_empty() {
+        router
+    } else {
+        Router::new()
+            .nest(&base_path, router)
+            .fallback(|| async { AppError::NotFound("Not found.".to_string()) })
+    }
+}
+
+/// Room for the multipart framing around an uploaded attachment.
+const MULTIPART_OVERHEAD: usize = 1024 * 1024;
+
+/// The largest request body `route` accepts.
+fn body_limit(route: &str, settings: &Settings) -> usize {
+    match route {
+        "/identity/accounts/prelogin"
+        | "/identity/accounts/register"
+        | "/identity/accounts/register/finish"
+        | "/identity/accounts/register/send-verification-email"
+        | "/api/accounts/prelogin"
+        | "/api/accounts/register"
+        | "/identity/connect/token"
+        | "/api/accounts/password-hint" => settings.auth_max_body_bytes,
+        "/api/ciphers/import"
+        | "/api/ciphers/import/chunk"
+        | "/api/ciphers/import-organization"
+        | "/api/organizations/{id}/import"
+        | "/admin/migrate/vaultwarden"
+        | "/admin/restore" => settings.import_max_body_bytes,
+        // Multipart uploads, up to ATTACHMENT_MAX_BYTES
+        "/api/ciphers/{id}/attachment" | "/api/ciphers/{id}/attachment/{attachment_id}" => {
+            match settings.attachment_max_bytes {
+                Ok(Some(max_bytes)) => usize::try_from(max_bytes)
+                    .unwrap_or(usize::MAX)
+                    .saturating_add(MULTIPART_OVERHEAD),
+                _ => settings.max_body_bytes,
+            }
+        }
+        "/api/sends/{id}/file/{file_id}" => sends::SEND_FILE_BODY_LIMIT,
+        _ => settings.max_body_bytes,
+    }
+}
+
+/// Refuses request bodies over the route's [`body_limit`] with a 413, from `Content-Length`
+/// before anything reads them, or while they are read when no length was sent.
+async fn limit_body(
+    State(settings): State<Arc<Settings>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_default();
+    // Under BASE_PATH, the matched path starts with it
+    let route = route
+        .strip_prefix(settings.base_path.as_str())
+        .unwrap_or(route);
+    let limit = body_limit(route, &settings);
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if length.is_some_and(|length| length > limit as u64) {
+        return Err(AppError::PayloadTooLarge(format!(
+            "The request body is larger than the {} KiB this endpoint accepts",
+            limit / 1024
+        )));
+    }
+    Ok(next
+        .run(req.map(|body| Body::new(Limited::new(body, limit))))
+        .await)
+}
+
+/// Origins of the official browser extensions, which call the API from their own pages.
+const EXTENSION_ORIGIN_PREFIXES: [&str; 3] = [
+    "chrome-extension://",
+    "moz-extension://",
+    "safari-web-extension://",
+];
+
+/// CORS for the web vault and browser extensions.
+///
+/// Requests from the worker's own origin and from browser extensions are always allowed;
+/// ALLOWED_ORIGINS adds comma-separated origins (e.g. a web vault hosted elsewhere), or `*` for
+/// any. Other origins get no CORS headers, so browsers block them. Preflight `OPTIONS` requests
+/// are answered here and never reach the handlers or their auth extractors.
+fn cors_layer(env: &Env) -> CorsLayer {
+    let configured = Settings::get(env).allowed_origins.clone();
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(
+            move |origin: &HeaderValue, parts: &Parts| origin_allowed(&configured, origin, parts),
+        ))
+        // Clients send Authorization, Content-Type and their Device-*/Bitwarden-* headers
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_methods(AllowMethods::mirror_request())
+        .max_age(Duration::from_secs(3600))
+}
+
+/// `router` behind [`cors_layer`]. tower-http mirrors the requested headers and methods on every
+/// preflight; they're dropped again when the origin isn't allowed, so it learns nothing.
+pub fn with_cors(router: Router, env: &Env) -> Router {
+    router
+        .layer(cors_layer(env))
+        .layer(middleware::map_response(hide_refused_cors))
+}
+
+async fn hide_refused_cors(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    if !headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+        for name in [
+            ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_MAX_AGE,
+        ] {
+            headers.remove(name);
+        }
+    }
+    response
+}
+
+/// Whether `origin` may read responses to the request of `parts`, given ALLOWED_ORIGINS.
+fn origin_allowed(configured: &[String], origin: &HeaderValue, parts: &Parts) -> bool {
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    let origin = origin.to_ascii_lowercase();
+    configured
+        .iter()
+        .any(|allowed| allowed == "*" || *allowed == origin)
+        || EXTENSION_ORIGIN_PREFIXES
+            .iter()
+            .any(|prefix| origin.starts_with(prefix))
+        || own_origin(parts).is_some_and(|own| own == origin)
+}
+
+/// `scheme://host[:port]` the request was sent to.
+fn own_origin(parts: &Parts) -> Option<String> {
+    let authority = parts.uri.authority()?;
+    let scheme = parts.uri.scheme_str().unwrap_or("https");
+    Some(format!("{scheme}://{authority}").to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing::{self, ORIGIN};
+    use crate::native::Env;
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, Response, StatusCode};
+    use serde_json::{json, Value};
+
+    fn parts(uri: &str) -> Parts {
+        Request::get(uri).body(()).unwrap().into_parts().0
+    }
+
+    fn allowed(configured: &[&str], origin: &str) -> bool {
+        let configured: Vec<String> = configured.iter().map(|origin| origin.to_string()).collect();
+        origin_allowed(
+            &configured,
+            &HeaderValue::from_str(origin).unwrap(),
+            &parts(&format!("{ORIGIN}/api/sync")),
+        )
+    }
+
+    #[test]
+    fn own_origin_and_extensions_are_always_allowed() {
+        assert!(allowed(&[], ORIGIN));
+        assert!(allowed(&[], "HTTPS://Vault.Example.com"));
+        assert!(allowed(
+            &[],
+            "chrome-extension://nngceckbapebfimnlniiiahkandclblb"
+        ));
+        assert!(allowed(&[], "moz-extension://0a1b2c3d-4e5f"));
+        assert!(allowed(&[], "safari-web-extension://0A1B2C3D"));
+
+        assert!(!allowed(&[], "http://vault.example.com"));
+        assert!(!allowed(&[], "https://vault.example.com:8443"));
+        assert!(!allowed(&[], "https://evil.example"));
+        assert!(!allowed(&[], "null"));
+    }
+
+    #[test]
+    fn allowed_origins_add_to_them() {
+        let configured = ["https://web.example.org", "http://localhost:8080"];
+        assert!(allowed(&configured, "https://web.example.org"));
+        assert!(allowed(&configured, "http://localhost:8080"));
+        assert!(!allowed(
+            &configured,
+            "https://web.example.org.evil.example"
+        ));
+        assert!(!allowed(&configured, "http://localhost:8081"));
+        assert!(allowed(&["*"], "https://anything.example"));
+    }
+
+    fn env() -> Env {
+        testing::env().with_var("ALLOWED_ORIGINS", "https://web.example.org/")
+    }
+
+    fn preflight(env: &Env, path: &str, origin: &str) -> Response<Body> {
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri(format!("{ORIGIN}{path}"))
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "authorization,content-type,device-type,bitwarden-client-name",
+            )
+            .body(Body::empty())
+            .unwrap();
+        testing::fetch(env, req)
+    }
+
+    fn header_of(response: &Response<Body>, name: header::HeaderName) -> Option<&str> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn preflights_from_allowed_origins_skip_authentication() {
+        let env = env();
+        for path in ["/api/ciphers", "/identity/connect/token"] {
+            let response = preflight(&env, path, "https://web.example.org");
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+            assert_eq!(
+                header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                Some("https://web.example.org")
+            );
+            assert_eq!(
+                header_of(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+                Some("authorization,content-type,device-type,bitwarden-client-name")
+            );
+            assert_eq!(
+                header_of(&response, header::ACCESS_CONTROL_ALLOW_METHODS),
+                Some("POST")
+            );
+        }
+    }
+
+    #[test]
+    fn disallowed_origins_get_no_cors_headers() {
+        let env = env();
+        let response = preflight(&env, "/api/ciphers", "https://evil.example");
+        assert_eq!(
+            header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            None
+        );
+        assert_eq!(
+            header_of(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+            None
+        );
+
+        let req = Request::builder()
+            .uri(format!("{ORIGIN}/api/config"))
+            .header(header::ORIGIN, "https://evil.example")
+            .body(Body::empty())
+            .unwrap();
+        let response = testing::fetch(&env, req);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            None
+        );
+    }
+
+    #[test]
+    fn requests_without_an_origin_are_served_as_usual() {
+        let env = env();
+        let req = Request::builder()
+            .uri(format!("{ORIGIN}/api/config"))
+            .body(Body::empty())
+            .unwrap();
+        let response = testing::fetch(&env, req);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            None
+        );
+
+        let req = Request::builder()
+            .uri(format!("{ORIGIN}/api/config"))
+            .header(header::ORIGIN, "https://web.example.org")
+            .body(Body::empty())
+            .unwrap();
+        let response = testing::fetch(&env, req);
+        assert_eq!(
+            header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("https://web.example.org")
+        );
+    }
+
+    fn limited_env() -> Env {
+        testing::env()
+            .with_var("AUTH_MAX_BODY_BYTES", "2048")
+            .with_var("MAX_BODY_BYTES", "4096")
+            .with_var("IMPORT_MAX_BODY_BYTES", "8192")
+            .with_var("ATTACHMENT_MAX_BYTES", "1024")
+    }
+
+    #[test]
+    fn body_limits_depend_on_the_route() {
+        let settings = Settings::get(&limited_env());
+        for (route, limit) in [
+            ("/identity/connect/token", 2048),
+            ("/identity/accounts/prelogin", 2048),
+            ("/api/ciphers/import", 8192),
+            ("/api/organizations/{id}/import", 8192),
+            (
+                "/api/ciphers/{id}/attachment/{attachment_id}",
+                1024 + MULTIPART_OVERHEAD,
+            ),
+            (
+                "/api/sends/{id}/file/{file_id}",
+                sends::SEND_FILE_BODY_LIMIT,
+            ),
+            ("/api/ciphers", 4096),
+            ("", 4096),
+        ] {
+            assert_eq!(body_limit(route, &settings), limit, "{route}");
+        }
+    }
+
+    #[test]
+    fn uploads_without_an_attachment_limit_get_the_default_one() {
+        let env = testing::bare().with_var("MAX_BODY_BYTES", "4096");
+        let settings = Settings::get(&env);
+        assert_eq!(body_limit("/api/ciphers/{id}/attachment", &settings), 4096);
+    }
+
+    /// Posts a JSON `body` of exactly `size` bytes, with or without a `Content-Length`.
+    fn post_sized(env: &Env, path: &str, size: usize, with_length: bool) -> Response<Body> {
+        let json = r#"{"email":"alice@example.com"}"#;
+        let body = format!("{json}{}", " ".repeat(size - json.len()));
+        let mut req = Request::post(format!("{ORIGIN}{path}"))
+            .header(header::CONTENT_TYPE, "application/json");
+        if with_length {
+            req = req.header(header::CONTENT_LENGTH, size);
+        }
+        testing::fetch(env, req.body(Body::from(body)).unwrap())
+    }
+
+    fn message(response: Response<Body>) -> String {
+        let (_, body) = testing::json_response(response);
+        body["message"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn bodies_over_the_limit_are_refused() {
+        let env = limited_env();
+        for with_length in [true, false] {
+            let response = post_sized(&env, "/identity/accounts/prelogin", 2048, with_length);
+            assert_eq!(response.status(), StatusCode::OK, "{with_length}");
+
+            let response = post_sized(&env, "/identity/accounts/prelogin", 2049, with_length);
+            assert_eq!(
+                response.status(),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "{with_length}"
+            );
+        }
+    }
+
+    #[test]
+    fn refusals_name_the_limit_of_the_route() {
+        let env = limited_env();
+        for (path, limit) in [
+            ("/identity/accounts/prelogin", "2 KiB"),
+            ("/api/ciphers", "4 KiB"),
+            ("/api/ciphers/import", "8 KiB"),
+            ("/api/ciphers/cipher-1/attachment/attachment-1", "1025 KiB"),
+        ] {
+            let response = post_sized(&env, path, 2 * 1024 * 1024, true);
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{path}");
+            assert_eq!(
+                message(response),
+                format!("The request body is larger than the {limit} this endpoint accepts")
+            );
+        }
+
+        // Read without a length, the body only turns out too large on the way
+        let response = post_sized(&env, "/identity/accounts/prelogin", 4096, false);
+        assert_eq!(message(response), "The request body is too large");
+    }
+
+    #[test]
+    fn compatibility_routes_reach_the_same_handlers() {
+        let env = env();
+        // The register body of older clients: `key` and `keys`, no verification token
+        let (status, body) = testing::request(
+            &env,
+            Method::POST,
+            "/api/accounts/register",
+            None,
+            Some(json!({
+                "email": "alice@example.com",
+                "name": "Alice",
+                "masterPasswordHash": "bWFzdGVyLXBhc3N3b3JkLWhhc2g=",
+                "masterPasswordHint": null,
+                "key": "2.c3ltbWV0cmljLWtleQ==|aXY=|bWFj",
+                "keys": {
+                    "publicKey": "cHVibGljLWtleQ==",
+                    "encryptedPrivateKey": "2.cHJpdmF0ZS1rZXk=|aXY=|bWFj",
+                },
+                "kdf": 0,
+                "kdfIterations": 650000,
+            })),
+        );
+        assert!(status.is_success(), "{status} {body}");
+
+        let email = json!({ "email": "alice@example.com" });
+        let (status, prelogin) = testing::request(
+            &env,
+            Method::POST,
+            "/api/accounts/prelogin",
+            None,
+            Some(email.clone()),
+        );
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(prelogin["kdfIterations"], 650000);
+        assert_eq!(
+            testing::request(
+                &env,
+                Method::POST,
+                "/identity/accounts/prelogin",
+                None,
+                Some(email)
+            )
+            .1,
+            prelogin
+        );
+
+        let token = testing::token(&env, "alice@example.com");
+        let token = Some(token.as_str());
+        let (_, folder) = testing::request(
+            &env,
+            Method::POST,
+            "/api/folders",
+            token,
+            Some(json!({ "name": "2.Zm9sZGVy|aXY=|bWFj" })),
+        );
+        let folder_path = format!("/api/folders/{}", folder["id"].as_str().unwrap());
+        let (status, folder) = testing::request(
+            &env,
+            Method::POST,
+            &folder_path,
+            token,
+            Some(json!({ "name": "2.cmVuYW1lZA==|aXY=|bWFj" })),
+        );
+        assert_eq!(status, StatusCode::OK, "{folder}");
+        assert_eq!(folder["name"], "2.cmVuYW1lZA==|aXY=|bWFj");
+
+        let cipher = |name: &str| {
+            json!({
+                "type": 2,
+                "name": name,
+                "secureNote": { "type": 0 },
+                "favorite": false,
+                "reprompt": 0,
+            })
+        };
+        let (_, created) = testing::request(
+            &env,
+            Method::POST,
+            "/api/ciphers",
+            token,
+            Some(cipher("2.bm90ZQ==|aXY=|bWFj")),
+        );
+        let cipher_path = format!("/api/ciphers/{}", created["id"].as_str().unwrap());
+        let (status, updated) = testing::request(
+            &env,
+            Method::POST,
+            &cipher_path,
+            token,
+            Some(cipher("2.cmVuYW1lZA==|aXY=|bWFj")),
+        );
+        assert_eq!(status, StatusCode::OK, "{updated}");
+        assert_eq!(updated["name"], "2.cmVuYW1lZA==|aXY=|bWFj");
+
+        let (status, _) = testing::request(
+            &env,
+            Method::POST,
+            &format!("{cipher_path}/delete"),
+            token,
+            None,
+        );
+        assert!(status.is_success(), "{status}");
+        let (status, _) = testing::request(&env, Method::GET, &cipher_path, token, None);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    fn config_api_url(env: &Env, path: &str) -> Value {
+        let (status, config) = testing::request(env, Method::GET, path, None, None);
+        assert_eq!(status, StatusCode::OK, "{path}");
+        config["environment"]["api"].clone()
+    }
+
+    #[test]
+    fn routes_are_at_the_root_without_a_base_path() {
+        let env = env();
+        assert_eq!(
+            config_api_url(&env, "/api/config"),
+            "https://vault.example.com/api"
+        );
+    }
+
+    #[test]
+    fn a_slash_base_path_is_the_root() {
+        let env = env().with_var("BASE_PATH", "/");
+        assert_eq!(
+            config_api_url(&env, "/api/config"),
+            "https://vault.example.com/api"
+        );
+    }
+
+    #[test]
+    fn base_path_mounts_every_route_under_it() {
+        let env = env()
+            .with_var("BASE_PATH", "vault/")
+            .with_var("AUTH_MAX_BODY_BYTES", "2048");
+        assert_eq!(
+            config_api_url(&env, "/vault/api/config"),
+            "https://vault.example.com/vault/api"
+        );
+
+        let (status, body) = testing::request(&env, Method::GET, "/api/config", None, None);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["message"], "Not found.");
+
+        // Routes keep their own body limits
+        let response = post_sized(&env, "/vault/identity/accounts/prelogin", 4096, true);
+        assert_eq!(
+            message(response),
+            "The request body is larger than the 2 KiB this endpoint accepts"
+        );
+    }
 }
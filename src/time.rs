@@ -0,0 +1,135 @@
+//! Timestamps, as stored in D1 and sent to clients.
+//!
+//! Everything is written in one format: UTC with millisecond precision and a `Z` suffix
+//! (`2024-05-01T12:34:56.789Z`), the one the Bitwarden server uses. Values in it sort as text, so
+//! SQL compares stored timestamps directly, and clients can compare revision dates returned by
+//! different endpoints as strings.
+
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+
+/// The current time, formatted with [`format_bw`].
+pub fn now_bw() -> String {
+    format_bw(Utc::now())
+}
+
+/// `2024-05-01T12:34:56.789Z`
+pub fn format_bw(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Reads a stored or client-supplied timestamp. Besides [`format_bw`]'s output, accepts RFC 3339
+/// with any precision and offset (users' timestamps used to be written by `to_rfc3339`, as
+/// `2024-05-01T12:34:56.789012345+00:00`) and SQLite's `CURRENT_TIMESTAMP` (`2024-05-01
+/// 12:34:56`, in UTC).
+pub fn parse_bw(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").map(|time| time.and_utc())
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, Db};
+    use crate::native::block_on;
+    use chrono::TimeZone;
+
+    fn time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 34, 56).unwrap() + chrono::Duration::milliseconds(789)
+    }
+
+    #[test]
+    fn formats_utc_with_milliseconds() {
+        assert_eq!(format_bw(time()), "2024-05-01T12:34:56.789Z");
+        let whole = Utc.with_ymd_and_hms(2024, 5, 1, 12, 34, 56).unwrap();
+        assert_eq!(format_bw(whole), "2024-05-01T12:34:56.000Z");
+    }
+
+    #[test]
+    fn parses_its_own_format() {
+        assert_eq!(parse_bw("2024-05-01T12:34:56.789Z"), Some(time()));
+        assert_eq!(parse_bw(&format_bw(time())), Some(time()));
+        assert_eq!(parse_bw(" 2024-05-01T12:34:56.789Z\n"), Some(time()));
+    }
+
+    #[test]
+    fn parses_legacy_formats() {
+        // chrono's to_rfc3339, as users' timestamps used to be written
+        let legacy = parse_bw("2024-05-01T12:34:56.789012345+00:00").unwrap();
+        assert_eq!(format_bw(legacy), "2024-05-01T12:34:56.789Z");
+        // Other offsets are converted to UTC
+        assert_eq!(parse_bw("2024-05-01T14:34:56.789+02:00"), Some(time()));
+        // SQLite's CURRENT_TIMESTAMP
+        assert_eq!(
+            parse_bw("2024-05-01 12:34:56"),
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 34, 56).single()
+        );
+        assert_eq!(parse_bw("2024-05-01 12:34:56.789"), Some(time()));
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_timestamp() {
+        assert_eq!(parse_bw(""), None);
+        assert_eq!(parse_bw("yesterday"), None);
+        assert_eq!(parse_bw("2024-05-01"), None);
+    }
+
+    #[test]
+    fn formatted_timestamps_sort_as_text() {
+        let earlier = format_bw(time());
+        let later = format_bw(time() + chrono::Duration::milliseconds(11));
+        assert!(earlier < later);
+    }
+
+    #[test]
+    fn the_normalizing_migration_agrees_with_parse_bw() {
+        let db = Db::in_memory().unwrap();
+        block_on(crate::migrations::run(&db)).unwrap();
+        let values = [
+            "2024-05-01T12:34:56.789012345+00:00",
+            "2024-05-01T14:34:56.789+02:00",
+            "2024-05-01 12:34:56",
+            "2024-05-01T12:34:56.789Z",
+            "not a timestamp",
+        ];
+        for (i, value) in values.iter().enumerate() {
+            block_on(db.run(
+                "INSERT INTO users (id, email, master_password_hash, key, private_key, public_key, created_at, updated_at)
+                 VALUES (?1, ?2, 'hash', 'key', 'private', 'public', ?3, ?3)",
+                &[
+                    i.to_string().into(),
+                    format!("{i}@example.com").into(),
+                    (*value).into(),
+                ],
+            ))
+            .unwrap();
+        }
+
+        // Run 0028 again, now that there are users for it to rewrite
+        block_on(db.run("DELETE FROM schema_migrations WHERE version = 28", &[])).unwrap();
+        block_on(db.run(
+            "DELETE FROM d1_migrations WHERE name = '0028_normalize_user_timestamps.sql'",
+            &[],
+        ))
+        .unwrap();
+        let summary = block_on(crate::migrations::run(&db)).unwrap();
+        assert_eq!(summary.applied, ["0028_normalize_user_timestamps.sql"]);
+
+        let rows =
+            block_on(db.all::<serde_json::Value>(
+                "SELECT created_at, updated_at FROM users ORDER BY id",
+                &[],
+            ))
+            .unwrap();
+        for (value, row) in values.iter().zip(&rows) {
+            let expected = parse_bw(value).map(format_bw);
+            let expected = expected.as_deref().unwrap_or(value);
+            assert_eq!(row["created_at"], *expected, "{value}");
+            assert_eq!(row["updated_at"], *expected, "{value}");
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Every per-user route, called by one user with another user's ids: each must refuse, and the
+//! owner's vault must come out unchanged.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{login_cipher, Response, Server, User};
+use serde_json::{json, Value};
+use warden_worker::native::block_on;
+
+struct Vaults {
+    server: Server,
+    alice: User,
+    bob: User,
+    folder_id: String,
+    cipher_id: String,
+}
+
+/// Alice with a folder and a cipher in it, and Bob with an empty vault.
+fn vaults() -> Vaults {
+    let server = Server::new();
+    let alice = server.user("alice@example.com");
+    let bob = server.user("bob@example.com");
+
+    let folder = server.post(
+        "/api/folders",
+        &alice,
+        json!({ "name": "2.YQ==|aXY=|bWFj" }),
+    );
+    let folder_id = folder.body["id"].as_str().expect("a folder id").to_string();
+    let cipher = server.post(
+        "/api/ciphers",
+        &alice,
+        login_cipher("2.Yw==|aXY=|bWFj", Some(&folder_id)),
+    );
+    let cipher_id = cipher.body["id"].as_str().expect("a cipher id").to_string();
+
+    Vaults {
+        server,
+        alice,
+        bob,
+        folder_id,
+        cipher_id,
+    }
+}
+
+impl Vaults {
+    /// Alice's vault, as she syncs it.
+    fn alice_vault(&self) -> Value {
+        let sync = self.server.get("/api/sync", &self.alice);
+        assert_eq!(sync.status, StatusCode::OK, "{}", sync.body);
+        sync.body
+    }
+
+    /// Makes Bob's request and checks it was refused with `status`, leaving Alice's vault as
+    /// it was.
+    fn refused(&self, method: Method, path: &str, body: Option<Value>, status: StatusCode) {
+        self.refused_by(&format!("{method} {path}"), status, || {
+            self.server
+                .request(method.clone(), path, Some(&self.bob.token), body)
+        });
+    }
+
+    /// Bob's upload of a file to `path`, refused with 404.
+    fn upload_refused(&self, path: &str) {
+        self.refused_by(&format!("upload to {path}"), StatusCode::NOT_FOUND, || {
+            self.server.post_file(path, &self.bob, b"bob's file")
+        });
+    }
+
+    fn refused_by(&self, request: &str, status: StatusCode, make: impl FnOnce() -> Response) {
+        let before = self.alice_vault();
+        let response = make();
+        assert_eq!(
+            response.status, status,
+            "{request} as another user: {}",
+            response.body
+        );
+        assert_eq!(
+            self.alice_vault(),
+            before,
+            "{request} changed the owner's vault"
+        );
+    }
+
+    /// Makes Bob's request, which is allowed because it only ever touches his own items, and
+    /// checks Alice's vault is unchanged by it.
+    fn ignored(&self, method: Method, path: &str, body: Value) {
+        let before = self.alice_vault();
+        let response = self
+            .server
+            .request(method.clone(), path, Some(&self.bob.token), Some(body));
+        assert!(
+            response.status.is_success(),
+            "{method} {path}: {} {}",
+            response.status,
+            response.body
+        );
+        assert_eq!(
+            self.alice_vault(),
+            before,
+            "{method} {path} changed the owner's vault"
+        );
+    }
+}
+
+#[test]
+fn another_users_cipher_is_not_found() {
+    let v = vaults();
+    let cipher = format!("/api/ciphers/{}", v.cipher_id);
+    let edit = || Some(login_cipher("2.Ym9i|aXY=|bWFj", None));
+
+    for (method, path, body) in [
+        (Method::GET, cipher.clone(), None),
+        (Method::GET, format!("{cipher}/details"), None),
+        (Method::PUT, cipher.clone(), edit()),
+        (Method::POST, cipher.clone(), edit()),
+        (Method::PUT, format!("{cipher}/delete"), None),
+        (Method::POST, format!("{cipher}/delete"), None),
+        (Method::DELETE, cipher.clone(), None),
+        (Method::PUT, format!("{cipher}/restore"), None),
+        (Method::GET, format!("{cipher}/admin"), None),
+        (Method::PUT, format!("{cipher}/admin"), edit()),
+        (Method::POST, format!("{cipher}/admin"), edit()),
+        (Method::DELETE, format!("{cipher}/admin"), None),
+        (Method::PUT, format!("{cipher}/delete-admin"), None),
+        (Method::POST, format!("{cipher}/delete-admin"), None),
+        (Method::PUT, format!("{cipher}/restore-admin"), None),
+    ] {
+        v.refused(method, &path, body, StatusCode::NOT_FOUND);
+    }
+}
+
+#[test]
+fn another_users_cipher_cannot_be_partially_updated() {
+    let v = vaults();
+    let partial = format!("/api/ciphers/{}/partial", v.cipher_id);
+    let body = || Some(json!({ "folderId": null, "favorite": true }));
+
+    v.refused(Method::PUT, &partial, body(), StatusCode::NOT_FOUND);
+    v.refused(Method::POST, &partial, body(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn bulk_cipher_routes_skip_another_users_ids() {
+    let v = vaults();
+    let ids = || json!({ "ids": [v.cipher_id] });
+
+    v.ignored(Method::PUT, "/api/ciphers/delete", ids());
+    v.ignored(Method::POST, "/api/ciphers/delete", ids());
+    v.ignored(Method::DELETE, "/api/ciphers", ids());
+    v.ignored(Method::PUT, "/api/ciphers/restore", ids());
+    v.ignored(
+        Method::PUT,
+        "/api/ciphers/move",
+        json!({ "ids": [v.cipher_id], "folderId": null }),
+    );
+    v.ignored(
+        Method::POST,
+        "/api/ciphers/move",
+        json!({ "ids": [v.cipher_id], "folderId": null }),
+    );
+}
+
+#[test]
+fn another_users_ciphers_are_not_listed() {
+    let v = vaults();
+
+    let listed = v.server.get("/api/ciphers", &v.bob);
+    assert_eq!(listed.status, StatusCode::OK, "{}", listed.body);
+    assert_eq!(listed.body["data"], json!([]));
+
+    let synced = v.server.get("/api/sync", &v.bob);
+    assert_eq!(synced.status, StatusCode::OK, "{}", synced.body);
+    assert_eq!(synced.body["ciphers"], json!([]));
+    assert_eq!(synced.body["folders"], json!([]));
+}
+
+#[test]
+fn another_users_folder_is_not_found() {
+    let v = vaults();
+    let folder = format!("/api/folders/{}", v.folder_id);
+    let rename = || Some(json!({ "name": "2.Ym9i|aXY=|bWFj" }));
+
+    v.refused(Method::GET, &folder, None, StatusCode::NOT_FOUND);
+    v.refused(Method::PUT, &folder, rename(), StatusCode::NOT_FOUND);
+    v.refused(Method::POST, &folder, rename(), StatusCode::NOT_FOUND);
+
+    // Deleting is idempotent, so a missing folder isn't an error, but it mustn't go either
+    v.ignored(Method::DELETE, &folder, json!({}));
+    v.ignored(Method::POST, &format!("{folder}/delete"), json!({}));
+
+    let listed = v.server.get("/api/folders", &v.bob);
+    assert_eq!(listed.body["data"], json!([]));
+}
+
+#[test]
+fn another_users_folder_cannot_hold_a_cipher() {
+    let v = vaults();
+    let in_alices_folder = || Some(login_cipher("2.Ym9i|aXY=|bWFj", Some(&v.folder_id)));
+
+    v.refused(
+        Method::POST,
+        "/api/ciphers",
+        in_alices_folder(),
+        StatusCode::BAD_REQUEST,
+    );
+
+    let own = v.server.post(
+        "/api/ciphers",
+        &v.bob,
+        login_cipher("2.Ym9i|aXY=|bWFj", None),
+    );
+    let own_id = own.body["id"].as_str().expect("a cipher id").to_string();
+    let own_path = format!("/api/ciphers/{own_id}");
+
+    v.refused(
+        Method::PUT,
+        &own_path,
+        in_alices_folder(),
+        StatusCode::BAD_REQUEST,
+    );
+    v.refused(
+        Method::PUT,
+        "/api/ciphers/move",
+        Some(json!({ "ids": [own_id], "folderId": v.folder_id })),
+        StatusCode::BAD_REQUEST,
+    );
+    v.refused(
+        Method::PUT,
+        &format!("{own_path}/partial"),
+        Some(json!({ "folderId": v.folder_id, "favorite": false })),
+        StatusCode::BAD_REQUEST,
+    );
+    assert!(v.server.get(&own_path, &v.bob).body["folderId"].is_null());
+}
+
+#[test]
+fn requests_without_a_valid_token_are_unauthorized() {
+    let v = vaults();
+
+    for token in [None, Some("not-a-token")] {
+        for path in ["/api/sync", "/api/ciphers", "/api/folders"] {
+            let response = v.server.request(Method::GET, path, token, None);
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{path}");
+        }
+    }
+}
+
+/// Alice's organization and the id of its default collection.
+fn organization(v: &Vaults) -> (String, String) {
+    let created = v.server.post(
+        "/api/organizations",
+        &v.alice,
+        json!({
+            "name": "Alice's",
+            "billingEmail": v.alice.email,
+            "key": "4.b3JnLWtleQ==",
+            "collectionName": "2.ZGVmYXVsdA==|aXY=|bWFj",
+        }),
+    );
+    assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+    let org_id = created.body["id"].as_str().expect("an organization id");
+    let collections = v.server.get(
+        &format!("/api/organizations/{org_id}/collections"),
+        &v.alice,
+    );
+    let collection_id = collections.body["data"][0]["id"]
+        .as_str()
+        .expect("a collection id");
+    (org_id.to_string(), collection_id.to_string())
+}
+
+#[test]
+fn another_users_attachment_is_not_found() {
+    let v = vaults();
+    let now = "2025-01-01T00:00:00.000Z";
+    block_on(
+        v.server.env.d1("vault1").unwrap().exec(&format!(
+            "INSERT INTO attachments (id, cipher_id, file_name, file_size, akey, created_at, updated_at)
+             VALUES ('attachment', '{}', '2.bmFtZQ==|aXY=|bWFj', 10, 'key', '{now}', '{now}')",
+            v.cipher_id
+        )),
+    )
+    .unwrap();
+    let cipher = format!("/api/ciphers/{}", v.cipher_id);
+    let attachment = format!("{cipher}/attachment/attachment");
+
+    v.refused(
+        Method::POST,
+        &format!("{cipher}/attachment/v2"),
+        Some(json!({ "key": "2.a2V5|aXY=|bWFj", "fileName": "2.Zg==|aXY=|bWFj", "fileSize": 10 })),
+        StatusCode::NOT_FOUND,
+    );
+    v.upload_refused(&format!("{cipher}/attachment"));
+    v.upload_refused(&attachment);
+    v.refused(Method::GET, &attachment, None, StatusCode::NOT_FOUND);
+    v.refused(Method::DELETE, &attachment, None, StatusCode::NOT_FOUND);
+    v.refused(
+        Method::POST,
+        &format!("{attachment}/delete"),
+        None,
+        StatusCode::NOT_FOUND,
+    );
+}
+
+#[test]
+fn another_users_collections_are_not_found() {
+    let v = vaults();
+    let (org_id, collection_id) = organization(&v);
+    let collections = format!("/api/organizations/{org_id}/collections");
+    let collection = format!("{collections}/{collection_id}");
+    let rename = || Some(json!({ "name": "2.Ym9i|aXY=|bWFj" }));
+
+    for (method, path, body) in [
+        (Method::GET, collections.clone(), None),
+        (Method::POST, collections.clone(), rename()),
+        (Method::GET, format!("{collections}/details"), None),
+        (Method::GET, format!("{collection}/details"), None),
+        (Method::PUT, collection.clone(), rename()),
+        (Method::POST, collection.clone(), rename()),
+        (Method::DELETE, collection.clone(), None),
+        (Method::POST, format!("{collection}/delete"), None),
+    ] {
+        v.refused(method, &path, body, StatusCode::NOT_FOUND);
+    }
+
+    let listed = v.server.get("/api/collections", &v.bob);
+    assert_eq!(listed.status, StatusCode::OK, "{}", listed.body);
+    assert_eq!(listed.body["data"], json!([]));
+}
+
+#[test]
+fn another_users_organization_cannot_be_shared_into() {
+    let v = vaults();
+    let (org_id, collection_id) = organization(&v);
+    let shared = || {
+        let mut cipher = login_cipher("2.Ym9i|aXY=|bWFj", None);
+        cipher["organizationId"] = json!(org_id);
+        cipher
+    };
+
+    v.refused(
+        Method::POST,
+        "/api/ciphers",
+        Some(shared()),
+        StatusCode::NOT_FOUND,
+    );
+    v.refused(
+        Method::POST,
+        "/api/ciphers/create",
+        Some(json!({ "cipher": shared(), "collectionIds": [collection_id] })),
+        StatusCode::NOT_FOUND,
+    );
+
+    let own = v.server.post(
+        "/api/ciphers",
+        &v.bob,
+        login_cipher("2.Ym9i|aXY=|bWFj", None),
+    );
+    let own_path = format!(
+        "/api/ciphers/{}",
+        own.body["id"].as_str().expect("a cipher id")
+    );
+    v.refused(
+        Method::PUT,
+        &own_path,
+        Some(shared()),
+        StatusCode::NOT_FOUND,
+    );
+    assert!(v.server.get(&own_path, &v.bob).body["organizationId"].is_null());
+}
+
+#[test]
+fn another_users_send_is_not_found() {
+    let v = vaults();
+    let deletion_date = (chrono::Utc::now() + chrono::Duration::days(7)).to_rfc3339();
+    let created = v.server.post(
+        "/api/sends",
+        &v.alice,
+        json!({
+            "type": 0,
+            "key": "2.a2V5|aXY=|bWFj",
+            "name": "2.c2VuZA==|aXY=|bWFj",
+            "deletionDate": deletion_date,
+            "text": { "text": "2.dGV4dA==|aXY=|bWFj", "hidden": false },
+        }),
+    );
+    assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+    let send = format!(
+        "/api/sends/{}",
+        created.body["id"].as_str().expect("a send id")
+    );
+    let edit = || {
+        Some(json!({
+            "type": 0,
+            "key": "2.a2V5|aXY=|bWFj",
+            "name": "2.Ym9i|aXY=|bWFj",
+            "deletionDate": deletion_date,
+            "text": { "text": "2.Ym9i|aXY=|bWFj", "hidden": false },
+        }))
+    };
+
+    v.refused(Method::GET, &send, None, StatusCode::NOT_FOUND);
+    v.refused(Method::PUT, &send, edit(), StatusCode::NOT_FOUND);
+    v.refused(Method::DELETE, &send, None, StatusCode::NOT_FOUND);
+    v.refused(
+        Method::PUT,
+        &format!("{send}/remove-password"),
+        None,
+        StatusCode::NOT_FOUND,
+    );
+    v.upload_refused(&format!("{send}/file/file"));
+
+    let listed = v.server.get("/api/sends", &v.bob);
+    assert_eq!(listed.status, StatusCode::OK, "{}", listed.body);
+    assert_eq!(listed.body["data"], json!([]));
+}
@@ -0,0 +1,198 @@
+//! Shared setup for the end-to-end tests: a server over a fresh in-memory database, requests
+//! through the Worker's router, and accounts to make them as.
+
+#![allow(dead_code)]
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use warden_worker::native::{self, block_on, Env, SqliteDatabase};
+
+pub const ORIGIN: &str = "https://vault.example.com";
+
+/// The client-side hash every test account logs in with.
+pub const MASTER_PASSWORD_HASH: &str = "bWFzdGVyLXBhc3N3b3JkLWhhc2g=";
+
+/// A warden-worker over its own in-memory database, migrated and ready for requests.
+pub struct Server {
+    pub env: Env,
+}
+
+/// A registered account and an access token for it.
+pub struct User {
+    pub email: String,
+    pub token: String,
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub body: Value,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        let db = SqliteDatabase::in_memory().expect("open an in-memory database");
+        let env = Env::new(db)
+            .with_secret("JWT_SECRET", "jwt-secret-for-tests")
+            .with_secret("JWT_REFRESH_SECRET", "jwt-refresh-secret-for-tests")
+            .with_secret("ALLOWED_EMAILS", "*@example.com");
+        block_on(native::migrate(&env)).expect("migrate the test database");
+        Server { env }
+    }
+
+    /// Makes a request to `path`, with `body` sent as JSON and `token` as the bearer token.
+    pub fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> Response {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(format!("{ORIGIN}{path}"));
+        if let Some(token) = token {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let body = match body {
+            Some(body) => {
+                builder = builder.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        self.send(builder.body(body).expect("build the request"))
+    }
+
+    /// Posts `fields` form-encoded, as clients do to the identity endpoints.
+    pub fn post_form(&self, path: &str, fields: &[(&str, &str)]) -> Response {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(fields)
+            .finish();
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{ORIGIN}{path}"))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .expect("build the request");
+        self.send(req)
+    }
+
+    /// Posts `file` as the `data` part of a multipart form, as clients upload attachments and
+    /// Send files.
+    pub fn post_file(&self, path: &str, user: &User, file: &[u8]) -> Response {
+        const BOUNDARY: &str = "warden-test-boundary";
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"key\"\r\n\r\n2.a2V5|aXY=|bWFj\r\n\
+             --{BOUNDARY}\r\nContent-Disposition: form-data; name=\"data\"; filename=\"file\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(file);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{ORIGIN}{path}"))
+            .header(header::AUTHORIZATION, format!("Bearer {}", user.token))
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .expect("build the request");
+        self.send(req)
+    }
+
+    fn send(&self, req: Request<Body>) -> Response {
+        block_on(async {
+            let response = native::fetch(&self.env, req).await;
+            let status = response.status();
+            let bytes = response
+                .into_body()
+                .collect()
+                .await
+                .expect("read the response body")
+                .to_bytes();
+            let body = if bytes.is_empty() {
+                Value::Null
+            } else {
+                serde_json::from_slice(&bytes)
+                    .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into()))
+            };
+            Response { status, body }
+        })
+    }
+
+    pub fn get(&self, path: &str, user: &User) -> Response {
+        self.request(Method::GET, path, Some(&user.token), None)
+    }
+
+    pub fn post(&self, path: &str, user: &User, body: Value) -> Response {
+        self.request(Method::POST, path, Some(&user.token), Some(body))
+    }
+
+    pub fn put(&self, path: &str, user: &User, body: Value) -> Response {
+        self.request(Method::PUT, path, Some(&user.token), Some(body))
+    }
+
+    pub fn delete(&self, path: &str, user: &User) -> Response {
+        self.request(Method::DELETE, path, Some(&user.token), None)
+    }
+
+    /// Registers `email` through the identity endpoint.
+    pub fn register(&self, email: &str) -> Response {
+        self.request(
+            Method::POST,
+            "/identity/accounts/register",
+            None,
+            Some(json!({
+                "email": email,
+                "name": email.split('@').next(),
+                "masterPasswordHash": MASTER_PASSWORD_HASH,
+                "masterPasswordHint": null,
+                "userSymmetricKey": "2.c3ltbWV0cmljLWtleQ==|aXY=|bWFj",
+                "userAsymmetricKeys": {
+                    "publicKey": "cHVibGljLWtleQ==",
+                    "encryptedPrivateKey": "2.cHJpdmF0ZS1rZXk=|aXY=|bWFj",
+                },
+                "kdf": 0,
+                "kdfIterations": 600000,
+            })),
+        )
+    }
+
+    /// Registers `email` and signs a token for it directly, skipping the password login.
+    pub fn user(&self, email: &str) -> User {
+        let registered = self.register(email);
+        assert!(
+            registered.status.is_success(),
+            "register {email}: {} {}",
+            registered.status,
+            registered.body
+        );
+        let token = block_on(native::access_token(&self.env, email)).expect("sign an access token");
+        User {
+            email: email.to_string(),
+            token,
+        }
+    }
+}
+
+/// A minimal login item, as clients send it to `POST /api/ciphers`.
+pub fn login_cipher(name: &str, folder_id: Option<&str>) -> Value {
+    json!({
+        "type": 1,
+        "name": name,
+        "notes": null,
+        "folderId": folder_id,
+        "favorite": false,
+        "login": {
+            "username": "2.dXNlcm5hbWU=|aXY=|bWFj",
+            "password": "2.cGFzc3dvcmQ=|aXY=|bWFj",
+            "uris": [],
+        },
+        "fields": [],
+        "reprompt": 0,
+    })
+}
@@ -0,0 +1,177 @@
+//! A client's first session against a fresh server: register, log in, fill the vault, sync.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{login_cipher, Server, User, MASTER_PASSWORD_HASH};
+use serde_json::json;
+
+#[test]
+fn register_log_in_and_sync() {
+    let server = Server::new();
+    let registered = server.register("alice@example.com");
+    assert_eq!(registered.status, StatusCode::OK, "{}", registered.body);
+
+    let prelogin = server.request(
+        axum::http::Method::POST,
+        "/identity/accounts/prelogin",
+        None,
+        Some(json!({ "email": "alice@example.com" })),
+    );
+    assert_eq!(prelogin.status, StatusCode::OK);
+    assert_eq!(prelogin.body["kdf"], 0);
+    assert_eq!(prelogin.body["kdfIterations"], 600000);
+
+    let login = server.post_form(
+        "/identity/connect/token",
+        &[
+            ("grant_type", "password"),
+            ("username", "alice@example.com"),
+            ("password", MASTER_PASSWORD_HASH),
+            ("scope", "api offline_access"),
+            ("client_id", "web"),
+            ("deviceType", "9"),
+            ("deviceIdentifier", "8f1c0e52-3f4d-4c35-9d7e-8f0b6f7a2c11"),
+            ("deviceName", "firefox"),
+        ],
+    );
+    assert_eq!(login.status, StatusCode::OK, "{}", login.body);
+    let alice = User {
+        email: "alice@example.com".to_string(),
+        token: login.body["access_token"]
+            .as_str()
+            .expect("an access token")
+            .to_string(),
+    };
+
+    let folder = server.post(
+        "/api/folders",
+        &alice,
+        json!({ "name": "2.d29yaw==|aXY=|bWFj" }),
+    );
+    assert_eq!(folder.status, StatusCode::OK, "{}", folder.body);
+    let folder_id = folder.body["id"].as_str().expect("a folder id").to_string();
+
+    let cipher = server.post(
+        "/api/ciphers",
+        &alice,
+        login_cipher("2.bWFpbA==|aXY=|bWFj", Some(&folder_id)),
+    );
+    assert_eq!(cipher.status, StatusCode::OK, "{}", cipher.body);
+    assert_eq!(cipher.body["folderId"], folder_id.as_str());
+    let cipher_id = cipher.body["id"].as_str().expect("a cipher id").to_string();
+
+    let sync = server.get("/api/sync", &alice);
+    assert_eq!(sync.status, StatusCode::OK, "{}", sync.body);
+    assert_eq!(sync.body["profile"]["email"], "alice@example.com");
+    assert_eq!(sync.body["folders"][0]["id"], folder_id.as_str());
+    assert_eq!(sync.body["ciphers"][0]["id"], cipher_id.as_str());
+    assert_eq!(sync.body["ciphers"][0]["folderId"], folder_id.as_str());
+}
+
+#[test]
+fn login_with_the_wrong_password_is_rejected() {
+    let server = Server::new();
+    server.user("alice@example.com");
+
+    let login = server.post_form(
+        "/identity/connect/token",
+        &[
+            ("grant_type", "password"),
+            ("username", "alice@example.com"),
+            ("password", "d3JvbmctaGFzaA=="),
+            ("scope", "api offline_access"),
+            ("client_id", "web"),
+        ],
+    );
+    assert_eq!(login.status, StatusCode::BAD_REQUEST, "{}", login.body);
+}
+
+#[test]
+fn edit_move_and_trash_a_cipher() {
+    let server = Server::new();
+    let alice = server.user("alice@example.com");
+
+    let cipher = server.post(
+        "/api/ciphers",
+        &alice,
+        login_cipher("2.YQ==|aXY=|bWFj", None),
+    );
+    let cipher_id = cipher.body["id"].as_str().unwrap().to_string();
+    let path = format!("/api/ciphers/{cipher_id}");
+
+    let mut edited = login_cipher("2.Yg==|aXY=|bWFj", None);
+    edited["notes"] = json!("2.bm90ZXM=|aXY=|bWFj");
+    let updated = server.put(&path, &alice, edited);
+    assert_eq!(updated.status, StatusCode::OK, "{}", updated.body);
+    assert_eq!(updated.body["name"], "2.Yg==|aXY=|bWFj");
+    assert_eq!(updated.body["notes"], "2.bm90ZXM=|aXY=|bWFj");
+
+    let folder = server.post(
+        "/api/folders",
+        &alice,
+        json!({ "name": "2.Zg==|aXY=|bWFj" }),
+    );
+    let folder_id = folder.body["id"].as_str().unwrap().to_string();
+    let moved = server.put(
+        "/api/ciphers/move",
+        &alice,
+        json!({ "ids": [cipher_id], "folderId": folder_id }),
+    );
+    assert!(moved.status.is_success(), "{} {}", moved.status, moved.body);
+    assert_eq!(
+        server.get(&path, &alice).body["folderId"],
+        folder_id.as_str()
+    );
+
+    let trashed = server.put(&format!("{path}/delete"), &alice, json!({}));
+    assert!(
+        trashed.status.is_success(),
+        "{} {}",
+        trashed.status,
+        trashed.body
+    );
+    assert!(server.get(&path, &alice).body["deletedDate"].is_string());
+
+    let restored = server.put(&format!("{path}/restore"), &alice, json!({}));
+    assert_eq!(restored.status, StatusCode::OK, "{}", restored.body);
+    assert!(restored.body["deletedDate"].is_null());
+
+    let deleted = server.delete(&path, &alice);
+    assert!(
+        deleted.status.is_success(),
+        "{} {}",
+        deleted.status,
+        deleted.body
+    );
+    assert_eq!(server.get(&path, &alice).status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn rename_and_delete_a_folder() {
+    let server = Server::new();
+    let alice = server.user("alice@example.com");
+
+    let folder = server.post(
+        "/api/folders",
+        &alice,
+        json!({ "name": "2.YQ==|aXY=|bWFj" }),
+    );
+    let path = format!("/api/folders/{}", folder.body["id"].as_str().unwrap());
+
+    let renamed = server.put(&path, &alice, json!({ "name": "2.Yg==|aXY=|bWFj" }));
+    assert_eq!(renamed.status, StatusCode::OK, "{}", renamed.body);
+    assert_eq!(server.get(&path, &alice).body["name"], "2.Yg==|aXY=|bWFj");
+
+    let listed = server.get("/api/folders", &alice);
+    assert_eq!(listed.body["data"].as_array().map(Vec::len), Some(1));
+
+    let deleted = server.delete(&path, &alice);
+    assert!(
+        deleted.status.is_success(),
+        "{} {}",
+        deleted.status,
+        deleted.body
+    );
+    assert_eq!(server.get(&path, &alice).status, StatusCode::NOT_FOUND);
+}
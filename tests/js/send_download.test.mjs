@@ -0,0 +1,221 @@
+// File send downloads through the JS fast path (`handleSendDownload` in src/attachments.js), over
+// an in-memory stand-in for D1 and R2. Run with `node --test tests/js/`.
+
+import assert from "node:assert/strict";
+import { createHash, createHmac } from "node:crypto";
+import { test } from "node:test";
+
+import { handleSendDownload } from "../../src/attachments.js";
+
+const SECRET = "jwt-secret-for-tests";
+const SEND_ID = "3c5e8a1f-6b2d-4f7e-9a0c-1d2e3f4a5b6c";
+const FILE_ID = "2f7a1c9e8b3d4e5f";
+const SIZE = 3 * 1024 * 1024;
+const CHUNK = 64 * 1024;
+
+const base64Url = (bytes) => Buffer.from(bytes).toString("base64url");
+const kid = (secret) => createHash("sha256").update(secret).digest("hex").slice(0, 16);
+
+// A token signed like `KeyRing::sign` does, with `claims` on top of a valid download's.
+function token({ secret = SECRET, keyId = kid(secret), ...claims } = {}) {
+  const header = base64Url(JSON.stringify({ alg: "HS256", typ: "JWT", kid: keyId }));
+  const payload = base64Url(
+    JSON.stringify({
+      exp: Math.floor(Date.now() / 1000) + 300,
+      purpose: "send_download",
+      send_id: SEND_ID,
+      file_id: FILE_ID,
+      ...claims,
+    })
+  );
+  const signature = createHmac("sha256", secret).update(`${header}.${payload}`).digest();
+  return `${header}.${payload}.${base64Url(signature)}`;
+}
+
+// The statements the download runs, against a table of sends and one of users.
+class FakeD1 {
+  constructor(sends) {
+    this.sends = new Map(sends.map((send) => [send.id, send]));
+    this.touched = [];
+  }
+
+  prepare(sql) {
+    const query = sql.replace(/\s+/g, " ").trim();
+    return {
+      bind: (...args) => ({
+        first: async () => this.first(query, args),
+        run: async () => this.run(query, args),
+      }),
+    };
+  }
+
+  first(query, [id]) {
+    assert.equal(query, "SELECT * FROM sends WHERE id = ?1");
+    const send = this.sends.get(id);
+    return send ? { ...send } : null;
+  }
+
+  run(query, args) {
+    if (query.startsWith("UPDATE sends SET access_count = access_count + 1")) {
+      const send = this.sends.get(args[0]);
+      const counted =
+        send &&
+        !send.disabled &&
+        (send.max_access_count === null || send.access_count < send.max_access_count);
+      if (counted) send.access_count += 1;
+      return { success: true, meta: { changes: counted ? 1 : 0 } };
+    }
+    assert.equal(query, "UPDATE users SET updated_at = ?1 WHERE id = ?2");
+    this.touched.push(args[1]);
+    return { success: true, meta: { changes: 1 } };
+  }
+}
+
+// An R2 object whose body is produced chunk by chunk as it is read.
+function streamedObject(size) {
+  const object = { size, pulls: 0 };
+  let sent = 0;
+  object.body = new ReadableStream(
+    {
+      pull(controller) {
+        object.pulls += 1;
+        const length = Math.min(CHUNK, size - sent);
+        controller.enqueue(new Uint8Array(length).fill(7));
+        sent += length;
+        if (sent === size) controller.close();
+      },
+    },
+    { highWaterMark: 0 }
+  );
+  return object;
+}
+
+function setup(fields = {}, vars = {}) {
+  const later = new Date(Date.now() + 7 * 24 * 3600 * 1000).toISOString();
+  const send = {
+    id: SEND_ID,
+    user_id: "alice",
+    atype: 1,
+    data: JSON.stringify({ id: FILE_ID, size: SIZE }),
+    disabled: 0,
+    deletion_date: later,
+    expiration_date: null,
+    max_access_count: null,
+    access_count: 0,
+    ...fields,
+  };
+  const db = new FakeD1([send]);
+  const objects = [];
+  const env = {
+    vault1: db,
+    JWT_SECRET: SECRET,
+    ATTACHMENTS_BUCKET: {
+      get: async (key) => {
+        assert.equal(key, `sends/${SEND_ID}/${FILE_ID}`);
+        const object = streamedObject(SIZE);
+        objects.push(object);
+        return object;
+      },
+    },
+    ...vars,
+  };
+  const download = (jwt = token()) =>
+    handleSendDownload(new Request("https://vault.example.com/"), env, SEND_ID, FILE_ID, jwt);
+  return { db, env, send, objects, download };
+}
+
+async function byteLength(response) {
+  let length = 0;
+  for await (const chunk of response.body) length += chunk.length;
+  return length;
+}
+
+test("a multi-MB file streams without being buffered and counts one access", async () => {
+  const { send, db, objects, download } = setup();
+
+  const response = await download();
+  assert.equal(response.status, 200);
+  assert.equal(response.headers.get("Content-Length"), String(SIZE));
+  // Nothing was read from storage before the response was handed back
+  assert.equal(objects[0].pulls, 0);
+
+  assert.equal(await byteLength(response), SIZE);
+  assert.equal(objects[0].pulls, SIZE / CHUNK);
+  assert.equal(send.access_count, 1);
+  assert.deepEqual(db.touched, ["alice"]);
+});
+
+test("an expired download token is rejected", async () => {
+  const { send, download } = setup();
+
+  const response = await download(token({ exp: Math.floor(Date.now() / 1000) - 3600 }));
+  assert.equal(response.status, 401);
+  assert.match((await response.json()).error, /expired/);
+  assert.equal(send.access_count, 0);
+});
+
+test("tokens are checked with the key their kid names", async () => {
+  const { download } = setup({}, { JWT_PREVIOUS_SECRETS: "older, rotated-out" });
+
+  // Signed before JWT_SECRET was rotated
+  assert.equal((await download(token({ secret: "rotated-out" }))).status, 200);
+  // Issued before key ids existed
+  assert.equal((await download(token({ keyId: undefined }))).status, 200);
+
+  for (const jwt of [
+    token({ secret: "elsewhere" }),
+    token({ secret: "elsewhere", keyId: kid(SECRET) }),
+    token({ secret: "dropped", keyId: undefined }),
+  ]) {
+    assert.equal((await download(jwt)).status, 401);
+  }
+});
+
+test("a token replayed after the send is deleted or disabled is rejected", async () => {
+  const { db, send, download } = setup();
+  const jwt = token();
+  assert.equal((await download(jwt)).status, 200);
+
+  send.disabled = 1;
+  assert.equal((await download(jwt)).status, 404);
+
+  send.disabled = 0;
+  db.sends.delete(SEND_ID);
+  assert.equal((await download(jwt)).status, 404);
+  assert.equal(send.access_count, 1);
+});
+
+test("downloads stop once access_count reaches max_access_count", async () => {
+  const { send, objects, download } = setup({ max_access_count: 2 });
+  const jwt = token();
+
+  assert.equal((await download(jwt)).status, 200);
+  assert.equal((await download(jwt)).status, 200);
+  assert.equal(send.access_count, 2);
+
+  assert.equal((await download(jwt)).status, 404);
+  assert.equal(send.access_count, 2);
+  assert.equal(objects.length, 2);
+});
+
+test("a download that loses the race for the last access is rejected", async () => {
+  const { env, send, download } = setup({ max_access_count: 1 });
+  // Both downloads found the send available before either counted its access
+  const get = env.ATTACHMENTS_BUCKET.get;
+  let arrived;
+  const both = new Promise((resolve) => {
+    let waiting = 2;
+    arrived = () => --waiting === 0 && resolve();
+  });
+  env.ATTACHMENTS_BUCKET.get = async (key) => {
+    arrived();
+    await both;
+    return get(key);
+  };
+
+  const jwt = token();
+  const [first, second] = await Promise.all([download(jwt), download(jwt)]);
+
+  assert.deepEqual([first.status, second.status].sort(), [200, 404]);
+  assert.equal(send.access_count, 1);
+});